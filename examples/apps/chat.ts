@@ -0,0 +1,133 @@
+/**
+ * Chat app
+ * A chat room server and a line-based terminal client
+ *
+ * Exercises the listener, split streams, a protocol codec and SharedWriter
+ * fan-out: every member gets one bounded send queue that all broadcasts go
+ * through, so a slow member can't hold up the room.
+ *
+ * Usage:
+ *   bun run examples/apps/chat.ts server [port]
+ *   bun run examples/apps/chat.ts client <name> [port]
+ */
+
+import { createInterface } from "readline";
+import { EncryptedListener } from "../../src/listener.js";
+import { EncryptedStream, type EncryptedReader } from "../../src/stream.js";
+import { SharedWriter } from "../../src/shared-writer.js";
+import { RawPacket, createProtocolCodec } from "../../src/protocol.js";
+import { writeString } from "../../src/bincode.js";
+import { DEMO_PSK, dial, portOf } from "./common.js";
+
+const Chat = createProtocolCodec(["Join", "Say", "Said", "Left"] as const);
+type ChatVariant = ReturnType<typeof Chat.variants>[number];
+
+function chatPacket(type: ChatVariant, ...strings: string[]): RawPacket {
+  const body: number[] = [];
+  for (const s of strings) writeString(body, s);
+  return new RawPacket(Chat.encode(type, new Uint8Array(body)));
+}
+
+/** Something that happened in the room */
+export type ChatEvent =
+  | { kind: "said"; from: string; text: string }
+  | { kind: "left"; name: string };
+
+export interface ChatServer {
+  port: number;
+  close(): Promise<void>;
+}
+
+/** Start a chat room on `port` (0 picks a free one) */
+export async function startChatServer(port = 0): Promise<ChatServer> {
+  const listener = await EncryptedListener.bind(port, "127.0.0.1", { streamOptions: { psk: DEMO_PSK } });
+  const members = new Map<SharedWriter, string>();
+  const broadcast = (packet: RawPacket) => {
+    for (const out of members.keys()) out.post(packet);
+  };
+
+  async function serve(reader: EncryptedReader, out: SharedWriter): Promise<void> {
+    let name: string | undefined;
+    try {
+      for (;;) {
+        const { type, reader: body } = Chat.decode(await reader.readPacketView());
+        if (type === "Join" && name === undefined) {
+          name = body.readString();
+          members.set(out, name);
+          broadcast(chatPacket("Said", "*", `${name} joined`));
+        } else if (type === "Say" && name !== undefined) {
+          broadcast(chatPacket("Said", name, body.readString()));
+        }
+      }
+    } catch {
+      // The member went away
+    } finally {
+      members.delete(out);
+      await out.release().catch(() => undefined);
+      if (name !== undefined) broadcast(chatPacket("Left", name));
+    }
+  }
+
+  void (async () => {
+    for await (const { stream, socket } of listener) {
+      const { reader, writer } = stream.split();
+      const out = SharedWriter.over(writer, { maxQueued: 256 });
+      void serve(reader, out).finally(() => socket.destroy());
+    }
+  })();
+
+  return { port: portOf(listener), close: () => listener.close() };
+}
+
+export interface ChatClient {
+  say(text: string): Promise<void>;
+  /** Wait for the next thing that happens in the room */
+  next(): Promise<ChatEvent>;
+  close(): Promise<void>;
+}
+
+/** Join the room on `port` as `name` */
+export async function joinChat(name: string, port: number, host = "127.0.0.1"): Promise<ChatClient> {
+  const stream = await EncryptedStream.new(await dial(port, host), { psk: DEMO_PSK });
+  await stream.writePacket(chatPacket("Join", name));
+  return {
+    say: async (text) => {
+      await stream.writePacket(chatPacket("Say", text));
+    },
+    next: async () => {
+      for (;;) {
+        const { type, reader } = Chat.decode(await stream.readPacketView());
+        if (type === "Said") return { kind: "said", from: reader.readString(), text: reader.readString() };
+        if (type === "Left") return { kind: "left", name: reader.readString() };
+      }
+    },
+    close: () => stream.close(),
+  };
+}
+
+if (import.meta.main) {
+  const [mode, ...args] = process.argv.slice(2);
+  if (mode === "server") {
+    const server = await startChatServer(Number(args[0] ?? 7300));
+    console.log(`Chat room on 127.0.0.1:${server.port}`);
+  } else if (mode === "client" && args[0]) {
+    const client = await joinChat(args[0], Number(args[1] ?? 7300));
+    const terminal = createInterface({ input: process.stdin, output: process.stdout, prompt: "> " });
+    terminal.on("line", (line) => {
+      if (line.trim()) void client.say(line.trim());
+      terminal.prompt();
+    });
+    terminal.on("close", () => void client.close().finally(() => process.exit(0)));
+    terminal.prompt();
+    for (;;) {
+      const event = await client.next().catch(() => undefined);
+      if (!event) break;
+      process.stdout.write(`\r${event.kind === "said" ? `${event.from}: ${event.text}` : `${event.name} left`}\n`);
+      terminal.prompt(true);
+    }
+    console.log("\rDisconnected");
+    process.exit(0);
+  } else {
+    console.log("Usage: chat.ts server [port] | chat.ts client <name> [port]");
+  }
+}
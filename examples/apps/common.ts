@@ -0,0 +1,27 @@
+/**
+ * Helpers shared by the example apps
+ */
+
+import { createConnection, type AddressInfo, type Socket } from "net";
+import type { EncryptedListener } from "../../src/listener.js";
+import { ClavisError, StreamError } from "../../src/error.js";
+
+/** Pre-shared key of the demos; set CLAVIS_PSK to use your own */
+export const DEMO_PSK = process.env.CLAVIS_PSK ?? "clavis-example-apps-demo-key";
+
+/** Open a TCP connection, rejecting with a ClavisError if it fails */
+export function dial(port: number, host = "127.0.0.1"): Promise<Socket> {
+  return new Promise((resolve, reject) => {
+    const socket = createConnection({ host, port }, () => {
+      socket.off("error", onError);
+      resolve(socket);
+    });
+    const onError = (error: Error) => reject(ClavisError.stream(StreamError.io(error)));
+    socket.once("error", onError);
+  });
+}
+
+/** Port a listener ended up on, for listeners bound to port 0 */
+export function portOf(listener: EncryptedListener): number {
+  return (listener.address() as AddressInfo).port;
+}
@@ -0,0 +1,90 @@
+/**
+ * File transfer app
+ * Serves the files of one directory; clients download them by name
+ *
+ * Exercises chunked payloads: a file of any size goes out as a run of
+ * packets no bigger than the max packet size, with backpressure from the
+ * connection, and a missing file reaches the client as an Abort.
+ *
+ * Usage:
+ *   bun run examples/apps/file-transfer.ts serve <directory> [port]
+ *   bun run examples/apps/file-transfer.ts get <name> <destination> [port]
+ */
+
+import { createReadStream, createWriteStream } from "fs";
+import { basename, join } from "path";
+import { Writable } from "stream";
+import { EncryptedListener } from "../../src/listener.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { readStream, writeStream } from "../../src/chunked.js";
+import { DEMO_PSK, dial, portOf } from "./common.js";
+
+export interface FileServer {
+  port: number;
+  close(): Promise<void>;
+}
+
+/**
+ * Serve the files in `root` on `port` (0 picks a free one). Each request
+ * packet is a file name; subdirectories aren't reachable.
+ */
+export async function startFileServer(root: string, port = 0): Promise<FileServer> {
+  const listener = await EncryptedListener.bind(port, "127.0.0.1", { streamOptions: { psk: DEMO_PSK } });
+
+  async function serve(stream: EncryptedStream): Promise<void> {
+    for (;;) {
+      const name = new TextDecoder().decode(await stream.readPacketView());
+      // A file that can't be read is sent as an Abort; the connection stays usable
+      await writeStream(stream, createReadStream(join(root, basename(name)))).catch(() => undefined);
+    }
+  }
+
+  void (async () => {
+    for await (const { stream, socket } of listener) {
+      void serve(stream).catch(() => socket.destroy());
+    }
+  })();
+
+  return { port: portOf(listener), close: () => listener.close() };
+}
+
+/**
+ * Download `name` from the server on `port` into `destination`. Resolves
+ * with the bytes written; rejects with the server's reason if it couldn't
+ * send the file.
+ */
+export async function downloadFile(name: string, destination: string, port: number, host = "127.0.0.1"): Promise<number> {
+  const stream = await EncryptedStream.new(await dial(port, host), { psk: DEMO_PSK });
+  try {
+    await stream.writePacket(new RawPacket(new TextEncoder().encode(name)));
+    let bytes = 0;
+    const counted = readStream(stream).pipeThrough(
+      new TransformStream<Uint8Array, Uint8Array>({
+        transform(chunk, controller) {
+          bytes += chunk.length;
+          controller.enqueue(chunk);
+        },
+      })
+    );
+    await counted.pipeTo(Writable.toWeb(createWriteStream(destination)));
+    return bytes;
+  } finally {
+    await stream.close();
+  }
+}
+
+if (import.meta.main) {
+  const [mode, ...args] = process.argv.slice(2);
+  if (mode === "serve" && args[0]) {
+    const server = await startFileServer(args[0], Number(args[1] ?? 7301));
+    console.log(`Serving ${args[0]} on 127.0.0.1:${server.port}`);
+  } else if (mode === "get" && args[0] && args[1]) {
+    const started = performance.now();
+    const bytes = await downloadFile(args[0], args[1], Number(args[2] ?? 7301));
+    const seconds = (performance.now() - started) / 1000;
+    console.log(`${bytes} bytes in ${seconds.toFixed(2)}s (${(bytes / seconds / 1e6).toFixed(1)} MB/s)`);
+  } else {
+    console.log("Usage: file-transfer.ts serve <directory> [port] | file-transfer.ts get <name> <destination> [port]");
+  }
+}
@@ -0,0 +1,150 @@
+/**
+ * RPC service app
+ * A key-value store served over RPC, with a one-shot command line client
+ *
+ * Exercises service definitions, the packet router and RpcConnection:
+ * plain calls, a server-streaming call and remote errors, which reject the
+ * client's call with the handler's message.
+ *
+ * Usage:
+ *   bun run examples/apps/rpc-service.ts serve [port]
+ *   bun run examples/apps/rpc-service.ts get <key> [port]
+ *   bun run examples/apps/rpc-service.ts put <key> <value> [port]
+ *   bun run examples/apps/rpc-service.ts keys [prefix] [port]
+ */
+
+import { EncryptedListener } from "../../src/listener.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { PacketRouter } from "../../src/router.js";
+import { RpcConnection } from "../../src/rpc.js";
+import { defineService, type ServiceClient, type ValueCodec } from "../../src/service.js";
+import { writeOptionString, writeString, writeU32 } from "../../src/bincode.js";
+import { DEMO_PSK, dial, portOf } from "./common.js";
+
+const variants = ["Get", "Value", "Put", "Stored", "Keys", "Key", "Error"] as const;
+const codec = createProtocolCodec(variants, { errorVariant: "Error" });
+
+const text: ValueCodec<string> = {
+  encode(value) {
+    const buffer: number[] = [];
+    writeString(buffer, value);
+    return new Uint8Array(buffer);
+  },
+  decode: (reader) => reader.readString(),
+};
+
+const optionalText: ValueCodec<string | undefined> = {
+  encode(value) {
+    const buffer: number[] = [];
+    writeOptionString(buffer, value);
+    return new Uint8Array(buffer);
+  },
+  decode: (reader) => reader.readOptionString(),
+};
+
+const entry: ValueCodec<[string, string]> = {
+  encode([key, value]) {
+    const buffer: number[] = [];
+    writeString(buffer, key);
+    writeString(buffer, value);
+    return new Uint8Array(buffer);
+  },
+  decode: (reader) => [reader.readString(), reader.readString()],
+};
+
+const count: ValueCodec<number> = {
+  encode(value) {
+    const buffer: number[] = [];
+    writeU32(buffer, value);
+    return new Uint8Array(buffer);
+  },
+  decode: (reader) => reader.readU32(),
+};
+
+/** Largest value the store accepts, in bytes */
+export const MAX_VALUE_LENGTH = 4096;
+
+export const KeyValueService = defineService(codec, {
+  get: { request: "Get", response: "Value", input: text, output: optionalText },
+  /** Resolves with the number of keys stored afterwards */
+  put: { request: "Put", response: "Stored", input: entry, output: count },
+  keys: { request: "Keys", response: "Key", input: text, output: text, streaming: true },
+});
+
+export type KeyValueClient = ServiceClient<(typeof variants)[number], typeof KeyValueService.methods>;
+
+export interface KeyValueServer {
+  port: number;
+  close(): Promise<void>;
+}
+
+/** Serve an in-memory store on `port` (0 picks a free one) */
+export async function startKeyValueServer(port = 0): Promise<KeyValueServer> {
+  const store = new Map<string, string>();
+  const router = KeyValueService.serve(new PacketRouter(codec), {
+    get: (key) => store.get(key),
+    put: ([key, value]) => {
+      if (value.length > MAX_VALUE_LENGTH) {
+        throw new Error(`Value for ${key} is longer than ${MAX_VALUE_LENGTH} bytes`);
+      }
+      store.set(key, value);
+      return store.size;
+    },
+    *keys(prefix) {
+      for (const key of [...store.keys()].sort()) {
+        if (key.startsWith(prefix)) yield key;
+      }
+    },
+  });
+
+  const listener = await EncryptedListener.bind(port, "127.0.0.1", { streamOptions: { psk: DEMO_PSK } });
+  void (async () => {
+    for await (const { stream, socket } of listener) {
+      const { reader, writer } = stream.split();
+      new RpcConnection(reader, writer, { codec, router }).once("close", () => socket.destroy()).start();
+    }
+  })();
+
+  return { port: portOf(listener), close: () => listener.close() };
+}
+
+/** Connect to the store on `port`; `close()` ends the connection */
+export async function connectKeyValue(
+  port: number,
+  host = "127.0.0.1"
+): Promise<{ client: KeyValueClient; close(): Promise<void> }> {
+  const stream = await EncryptedStream.new(await dial(port, host), { psk: DEMO_PSK });
+  const { reader, writer } = stream.split();
+  const rpc = new RpcConnection(reader, writer, { codec, defaultCallOptions: { timeoutMs: 5_000 } }).start();
+  return {
+    client: KeyValueService.client(rpc),
+    close: async () => {
+      rpc.close();
+      await writer.close();
+    },
+  };
+}
+
+if (import.meta.main) {
+  const [command, ...args] = process.argv.slice(2);
+  const portArg = (i: number) => Number(args[i] ?? 7302);
+  if (command === "serve") {
+    const server = await startKeyValueServer(portArg(0));
+    console.log(`Key-value store on 127.0.0.1:${server.port}`);
+  } else if (command === "get" && args[0]) {
+    const { client, close } = await connectKeyValue(portArg(1));
+    console.log((await client.get(args[0])) ?? "(not set)");
+    await close();
+  } else if (command === "put" && args[0] && args[1] !== undefined) {
+    const { client, close } = await connectKeyValue(portArg(2));
+    console.log(`${await client.put([args[0], args[1]])} keys stored`);
+    await close();
+  } else if (command === "keys") {
+    const { client, close } = await connectKeyValue(portArg(1));
+    for await (const key of client.keys(args[0] ?? "")) console.log(key);
+    await close();
+  } else {
+    console.log("Usage: rpc-service.ts serve [port] | get <key> [port] | put <key> <value> [port] | keys [prefix] [port]");
+  }
+}
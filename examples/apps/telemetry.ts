@@ -0,0 +1,120 @@
+/**
+ * UDP telemetry app
+ * A sensor reporting readings to a collector over encrypted datagrams
+ *
+ * Exercises EncryptedDatagram: a retransmitted handshake, then one sealed
+ * reading per datagram with nothing resent. Lost readings stay lost and
+ * replayed ones are dropped, which suits samples the next one replaces.
+ *
+ * Each side binds its own UDP port and names the other's, since a datagram
+ * endpoint talks to one remote address.
+ *
+ * Usage:
+ *   bun run examples/apps/telemetry.ts collect <port> <sensor port>
+ *   bun run examples/apps/telemetry.ts sense <port> <collector port> [name]
+ */
+
+import { createSocket, type Socket } from "dgram";
+import { EncryptedDatagram, udpTransport, type DatagramStats } from "../../src/datagram.js";
+import { BincodeReader, writeI64, writeString } from "../../src/bincode.js";
+import { RawPacket } from "../../src/protocol.js";
+import { DEMO_PSK } from "./common.js";
+
+/** One sample; values travel to three decimal places */
+export interface Reading {
+  sensor: string;
+  metric: string;
+  value: number;
+}
+
+function encodeReading(reading: Reading): RawPacket {
+  const buffer: number[] = [];
+  writeString(buffer, reading.sensor);
+  writeString(buffer, reading.metric);
+  writeI64(buffer, BigInt(Math.round(reading.value * 1000)));
+  return new RawPacket(new Uint8Array(buffer));
+}
+
+function decodeReading(packet: Uint8Array): Reading {
+  const reader = new BincodeReader(packet);
+  return { sensor: reader.readString(), metric: reader.readString(), value: Number(reader.readI64()) / 1000 };
+}
+
+/** Bind a UDP socket on loopback (port 0 picks a free one) */
+export function bindUdp(port = 0): Promise<Socket> {
+  return new Promise((resolve, reject) => {
+    const socket = createSocket("udp4");
+    socket.once("error", reject);
+    socket.bind(port, "127.0.0.1", () => {
+      socket.off("error", reject);
+      resolve(socket);
+    });
+  });
+}
+
+const psk = () => new TextEncoder().encode(DEMO_PSK);
+
+/** Port a bound UDP socket ended up on */
+export function udpPort(socket: Socket): number {
+  return socket.address().port;
+}
+
+export interface Collector {
+  /** Readings in arrival order, until `close()` */
+  readings(): AsyncIterable<Reading>;
+  stats(): DatagramStats;
+  close(): void;
+}
+
+/** Collect readings from the sensor on `sensorPort`, once it has shaken hands */
+export async function startCollector(socket: Socket, sensorPort: number): Promise<Collector> {
+  const endpoint = await EncryptedDatagram.new(udpTransport(socket, { address: "127.0.0.1", port: sensorPort }), {
+    psk: psk(),
+  });
+  return {
+    async *readings() {
+      for await (const packet of endpoint) yield decodeReading(packet);
+    },
+    stats: () => endpoint.stats(),
+    close: () => endpoint.close(),
+  };
+}
+
+export interface Sensor {
+  report(metric: string, value: number): Promise<void>;
+  close(): void;
+}
+
+/** Report readings as `name` to the collector on `collectorPort` */
+export async function startSensor(socket: Socket, collectorPort: number, name: string): Promise<Sensor> {
+  const endpoint = await EncryptedDatagram.new(udpTransport(socket, { address: "127.0.0.1", port: collectorPort }), {
+    psk: psk(),
+  });
+  return {
+    report: (metric, value) => endpoint.send(encodeReading({ sensor: name, metric, value })),
+    close: () => endpoint.close(),
+  };
+}
+
+if (import.meta.main) {
+  const [mode, port, peerPort, name] = process.argv.slice(2);
+  if (mode === "collect" && port && peerPort) {
+    const socket = await bindUdp(Number(port));
+    console.log(`Waiting for the sensor on port ${peerPort}`);
+    const collector = await startCollector(socket, Number(peerPort));
+    for await (const { sensor, metric, value } of collector.readings()) {
+      console.log(`${sensor} ${metric}=${value}`);
+    }
+  } else if (mode === "sense" && port && peerPort) {
+    const socket = await bindUdp(Number(port));
+    const sensor = await startSensor(socket, Number(peerPort), name ?? "sensor-1");
+    let load = 0.5;
+    setInterval(() => {
+      load = Math.min(1, Math.max(0, load + (Math.random() - 0.5) / 10));
+      void sensor.report("load", load);
+      void sensor.report("rss_mb", process.memoryUsage().rss / 2 ** 20);
+    }, 1000);
+  } else {
+    console.log("Usage: telemetry.ts collect <port> <sensor port> | telemetry.ts sense <port> <collector port> [name]");
+  }
+}
@@ -0,0 +1,28 @@
+/**
+ * Client module
+ * Prints a typed TypeScript module for a protocol reference
+ *
+ * The input is a module whose default export is a `ProtocolDoc` from
+ * `describeProtocol()`, or the same description as JSON:
+ *
+ *   bun run client-module ./src/my-protocol-doc.ts > src/my-protocol.ts
+ *   bun run client-module ./protocol.json --type=Command > src/commands.ts
+ */
+
+import { readFile } from "fs/promises";
+import { resolve } from "path";
+import { generateClientModule } from "../src/client-module.js";
+import type { ProtocolDoc } from "../src/protocol-doc.js";
+
+const args = process.argv.slice(2);
+const path = args.find((arg) => !arg.startsWith("--"));
+if (!path) {
+  console.error("Usage: bun run client-module <module|json> [--type=Message] [--import=clavis-js]");
+  process.exit(1);
+}
+const flag = (name: string) => args.find((arg) => arg.startsWith(`--${name}=`))?.slice(name.length + 3);
+
+const doc = path.endsWith(".json")
+  ? JSON.parse(await readFile(resolve(path), "utf8")) as ProtocolDoc
+  : (await import(resolve(path))).default as ProtocolDoc;
+process.stdout.write(generateClientModule(doc, { typeName: flag("type"), importFrom: flag("import") }));
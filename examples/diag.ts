@@ -0,0 +1,57 @@
+/**
+ * Diagnostics tool
+ * Runs either side of the built-in diagnostics protocol over TCP
+ *
+ *   bun examples/diag.ts serve <port> [psk]
+ *   bun examples/diag.ts <port> [host] [psk]
+ *
+ * The client prints round trip times, whether an echo came back intact and
+ * the throughput of 1000 padding packets of 16 KiB.
+ */
+
+import { createServer, connect, type Socket } from "net";
+import { EncryptedStream, type EncryptedStreamOptions } from "../src/stream.js";
+import { runDiag, serveDiag } from "../src/diag.js";
+
+const args = process.argv.slice(2);
+
+function options(psk: string | undefined): EncryptedStreamOptions {
+  return {
+    maxPacketSize: 65536,
+    ...(psk !== undefined ? { psk: new TextEncoder().encode(psk) } : {}),
+  };
+}
+
+if (args[0] === "serve") {
+  const port = Number(args[1] ?? 7272);
+  const streamOptions = options(args[2]);
+  const server = createServer(async (socket) => {
+    const peer = `${socket.remoteAddress}:${socket.remotePort}`;
+    try {
+      const stream = await EncryptedStream.new(socket, streamOptions);
+      const stats = await serveDiag(stream);
+      console.log(`${peer}: ${stats.packets} packets, ${stats.bytes} bytes`);
+    } catch (error) {
+      console.error(`${peer}:`, error);
+    } finally {
+      socket.destroy();
+    }
+  });
+  server.listen(port, () => console.log(`Diagnostics responder listening on port ${port}`));
+} else {
+  const port = Number(args[0] ?? 7272);
+  const host = args[1] ?? "127.0.0.1";
+  const socket = await new Promise<Socket>((resolve, reject) => {
+    const socket = connect(port, host, () => resolve(socket));
+    socket.once("error", reject);
+  });
+
+  const stream = await EncryptedStream.new(socket, options(args[2]));
+  const report = await runDiag(stream, { pings: 10, padPackets: 1000 });
+  socket.end();
+
+  const { min, avg, max } = report.rtt;
+  console.log(`rtt min/avg/max: ${min.toFixed(2)}/${avg.toFixed(2)}/${max.toFixed(2)} ms`);
+  console.log(`echo: ${report.echoOk ? "ok" : "MISMATCH"}`);
+  console.log(`throughput: ${((report.throughput ?? 0) / 1024 / 1024).toFixed(2)} MiB/s`);
+}
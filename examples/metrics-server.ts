@@ -0,0 +1,45 @@
+/**
+ * Prometheus metrics example
+ * An encrypted echo server that exposes its connection stats on /metrics
+ *
+ * Usage: bun run examples/metrics-server.ts [clavis-port] [metrics-port]
+ */
+
+import { createServer } from "http";
+import { EncryptedListener } from "../src/listener.js";
+import { PrometheusExporter } from "../src/prometheus.js";
+import { RawPacket } from "../src/protocol.js";
+
+const clavisPort = Number(process.argv[2] ?? 7272);
+const metricsPort = Number(process.argv[3] ?? 9464);
+
+const exporter = new PrometheusExporter();
+const listener = await EncryptedListener.bind(clavisPort, "127.0.0.1", {
+  streamOptions: { maxPacketSize: 65536, tracer: exporter },
+});
+
+const metrics = exporter.handler();
+createServer((request, response) => {
+  if (request.url !== "/metrics") {
+    response.writeHead(404).end();
+    return;
+  }
+  metrics(request, response);
+}).listen(metricsPort, "127.0.0.1", () => {
+  console.log(`Metrics on http://127.0.0.1:${metricsPort}/metrics`);
+});
+
+console.log(`Echo server listening on 127.0.0.1:${clavisPort}`);
+for await (const { stream, socket } of listener) {
+  void (async () => {
+    try {
+      for await (const packet of stream) {
+        await stream.writePacket(new RawPacket(packet));
+      }
+    } catch (error) {
+      console.error("Connection failed:", error);
+    } finally {
+      socket.destroy();
+    }
+  })();
+}
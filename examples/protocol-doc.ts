@@ -0,0 +1,22 @@
+/**
+ * Protocol reference
+ * Prints the reference for a protocol module as Markdown, or HTML with --html
+ *
+ * The module's default export must be a `ProtocolDoc` from `describeProtocol()`:
+ *
+ *   bun run protocol-doc ./src/my-protocol-doc.ts > PROTOCOL.md
+ *   bun run protocol-doc ./src/my-protocol-doc.ts --html > protocol.html
+ */
+
+import { resolve } from "path";
+import { formatProtocolDoc, formatProtocolDocHtml, type ProtocolDoc } from "../src/protocol-doc.js";
+
+const args = process.argv.slice(2);
+const path = args.find((arg) => !arg.startsWith("--"));
+if (!path) {
+  console.error("Usage: bun run protocol-doc <module> [--html]");
+  process.exit(1);
+}
+
+const doc = (await import(resolve(path))).default as ProtocolDoc;
+console.log(args.includes("--html") ? formatProtocolDocHtml(doc) : formatProtocolDoc(doc));
@@ -0,0 +1,40 @@
+/**
+ * Redis-backed replay cache
+ * Shares redeemed session tickets across a fleet, so a ticket resumes at most
+ * once no matter which server it reaches
+ *
+ * clavis has no Redis dependency; this example works with any client whose
+ * `set` accepts the standard `NX` / `PXAT` arguments (ioredis, node-redis's
+ * `sendCommand` wrapper, Bun's `RedisClient`). Copy it into your project and
+ * adapt the client type.
+ */
+
+import type { ReplayCache } from "../src/replay.js";
+import { TicketKeyring } from "../src/tickets.js";
+
+/**
+ * The one Redis command the cache needs
+ */
+export interface RedisSetClient {
+  set(key: string, value: string, nx: "NX", pxat: "PXAT", unixTimeMs: number): Promise<string | null>;
+}
+
+export class RedisReplayCache implements ReplayCache {
+  constructor(
+    private readonly redis: RedisSetClient,
+    private readonly prefix: string = "clavis:ticket:"
+  ) {}
+
+  async insert(key: string, expiresAt: number): Promise<boolean> {
+    // SET NX is atomic: exactly one server sees "OK" for a given ticket
+    const reply = await this.redis.set(this.prefix + key, "1", "NX", "PXAT", Math.ceil(expiresAt));
+    return reply === "OK";
+  }
+}
+
+/**
+ * Wire it into every server's keyring
+ */
+export function createFleetKeyring(redis: RedisSetClient): TicketKeyring {
+  return new TicketKeyring({ autoRotate: false, replayCache: new RedisReplayCache(redis) });
+}
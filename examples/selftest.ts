@@ -0,0 +1,29 @@
+/**
+ * Compatibility self-test
+ * Checks a clavis peer running the diagnostics responder and prints a report
+ *
+ *   bun examples/selftest.ts <host:port> [psk]
+ *
+ * Start a responder with `bun examples/diag.ts serve <port> [psk]` or the
+ * Rust crate's diag server. Exits with status 1 when a core check fails.
+ */
+
+import { connect, type Socket } from "net";
+import { formatSelfTestReport, runSelfTest } from "../src/selftest.js";
+
+const [target = "127.0.0.1:7272", psk] = process.argv.slice(2);
+const separator = target.lastIndexOf(":");
+const host = separator > 0 ? target.slice(0, separator).replace(/^\[(.*)\]$/, "$1") : target;
+const port = separator > 0 ? Number(target.slice(separator + 1)) : 7272;
+
+const report = await runSelfTest({
+  connect: () => new Promise<Socket>((resolve, reject) => {
+    const socket = connect(port, host, () => resolve(socket));
+    socket.once("error", reject);
+  }),
+  streamOptions: psk !== undefined ? { psk: new TextEncoder().encode(psk) } : {},
+});
+
+console.log(`clavis self-test against ${host}:${port}`);
+console.log(formatSelfTestReport(report));
+process.exit(report.ok ? 0 : 1);
@@ -0,0 +1,8 @@
+/**
+ * Wire format specification
+ * Prints the byte-level handshake and frame layout derived from the implementation
+ */
+
+import { generateWireSpec, formatWireSpec } from "../src/wire-spec.js";
+
+console.log(formatWireSpec(await generateWireSpec()));
@@ -0,0 +1,67 @@
+/**
+ * Audit mode
+ * Keeps key material and decrypted payloads out of debug output
+ *
+ * When enabled, keys, PSKs and the plaintext buffers returned by encrypted
+ * streams print as a placeholder under `util.inspect`/`console.log`,
+ * `JSON.stringify` and string conversion, so an accidental log line in an
+ * application can't leak them. The bytes themselves are untouched. Copies
+ * made by application code are ordinary buffers again.
+ *
+ * Off by default; enable it with `CLAVIS_AUDIT=1` in the environment or
+ * `setAuditMode(true)`. Only buffers created while it is on are redacted.
+ */
+
+const REDACTED = Symbol("clavis.redacted");
+
+/** `util.inspect.custom`, without loading `util`, so browsers can load this module */
+const INSPECT = Symbol.for("nodejs.util.inspect.custom");
+
+let auditMode = typeof process !== "undefined" && process.env.CLAVIS_AUDIT === "1";
+
+/**
+ * Turn audit mode on or off for buffers created from now on
+ */
+export function setAuditMode(enabled: boolean): void {
+  auditMode = enabled;
+}
+
+/**
+ * Whether audit mode is on
+ */
+export function isAuditMode(): boolean {
+  return auditMode;
+}
+
+/**
+ * In audit mode, make `bytes` print as a placeholder instead of its contents.
+ * Returns the same buffer.
+ */
+export function redact<B extends Uint8Array>(bytes: B, label: string): B {
+  if (!auditMode || isRedacted(bytes)) return bytes;
+  const placeholder = `<redacted ${label}: ${bytes.length} bytes>`;
+  const describe = { value: () => placeholder, configurable: true };
+  Object.defineProperties(bytes, {
+    [REDACTED]: { value: label },
+    [INSPECT]: describe,
+    toJSON: describe,
+    toString: describe,
+    toLocaleString: describe,
+  });
+  return bytes;
+}
+
+/**
+ * Whether `bytes` was redacted, so buffers derived from it can be too
+ */
+export function isRedacted(bytes: Uint8Array): boolean {
+  return REDACTED in bytes;
+}
+
+/**
+ * Redact `derived` if `source` was, keeping its label
+ */
+export function redactLike<B extends Uint8Array>(derived: B, source: Uint8Array): B {
+  const label = (source as unknown as Record<symbol, string | undefined>)[REDACTED];
+  return label === undefined ? derived : redact(derived, label);
+}
@@ -0,0 +1,153 @@
+/**
+ * Backoff
+ * Retry delays shared by RPC retries, pools and the reconnecting client
+ *
+ * Delays grow exponentially (or linearly) from `initialDelayMs` up to
+ * `maxDelayMs`. Jitter spreads retries out so that clients failing at the
+ * same moment don't all come back at the same moment:
+ * - "full": anywhere from 0 to the delay (AWS's "full jitter")
+ * - "equal": half the delay plus up to half again
+ * - "decorrelated": anywhere from `initialDelayMs` to three times the
+ *   previous delay, so each client's sequence drifts apart
+ * - a number: the delay plus or minus that fraction of it
+ */
+
+import { ClavisError } from "./error.js";
+import { systemClock, type Clock } from "./clock.js";
+
+/**
+ * How retry delays are randomized
+ */
+export type BackoffJitter = "none" | "full" | "equal" | "decorrelated" | number;
+
+/**
+ * When to retry and how long to wait in between
+ */
+export interface RetryPolicy {
+  /** Total number of attempts including the first one (default: 3) */
+  maxAttempts?: number | undefined;
+  /** Delay before the first retry in milliseconds (default: 100) */
+  initialDelayMs?: number | undefined;
+  /** Factor applied to the delay after each retry (default: 2) */
+  multiplier?: number | undefined;
+  /** Upper bound for the delay in milliseconds (default: 5000) */
+  maxDelayMs?: number | undefined;
+  /** Grow delays by `multiplier` or by `initialDelayMs` each retry (default: "exponential") */
+  backoff?: "exponential" | "linear" | undefined;
+  /** Randomization (default: "none") */
+  jitter?: BackoffJitter | undefined;
+  /**
+   * After `succeeded()`, start over from the first delay only once the
+   * success has lasted this long, in milliseconds (default: 0, at once).
+   * A connection that keeps dropping right after connecting then keeps
+   * backing off instead of retrying at full speed.
+   */
+  resetAfterMs?: number | undefined;
+}
+
+/**
+ * Delay before retry number `retry` (1-based) without jitter
+ */
+export function backoffDelay(policy: RetryPolicy, retry: number): number {
+  const initial = policy.initialDelayMs ?? 100;
+  const max = policy.maxDelayMs ?? 5000;
+  const delay = (policy.backoff ?? "exponential") === "linear"
+    ? initial * retry
+    : initial * Math.pow(policy.multiplier ?? 2, retry - 1);
+  return Math.min(delay, max);
+}
+
+/**
+ * Retry state for one operation or connection: hands out the delay before
+ * each retry and says when the attempts are used up
+ *
+ * @example
+ * ```typescript
+ * const backoff = new Backoff({ maxAttempts: 5, jitter: "full" });
+ * for (;;) {
+ *   try {
+ *     return await connect();
+ *   } catch (error) {
+ *     const delay = backoff.next();
+ *     if (delay === undefined) throw error;
+ *     await sleepOn(systemClock, delay);
+ *   }
+ * }
+ * ```
+ */
+export class Backoff {
+  private taken = 0;
+  private previous: number;
+  private succeededAt: number | undefined;
+
+  constructor(
+    private readonly policy: RetryPolicy = {},
+    private readonly clock: Clock = systemClock,
+    private readonly random: () => number = Math.random
+  ) {
+    if (policy.maxAttempts !== undefined && !(policy.maxAttempts >= 1)) {
+      throw ClavisError.config("maxAttempts must be at least 1");
+    }
+    if (typeof policy.jitter === "number" && !(policy.jitter >= 0 && policy.jitter <= 1)) {
+      throw ClavisError.config("A numeric jitter must be a fraction from 0 to 1");
+    }
+    this.previous = policy.initialDelayMs ?? 100;
+  }
+
+  /** Retries handed out since the last reset */
+  get retries(): number {
+    return this.taken;
+  }
+
+  /** Retries allowed in total */
+  get maxRetries(): number {
+    return (this.policy.maxAttempts ?? 3) - 1;
+  }
+
+  /**
+   * Delay before the next retry in milliseconds, or undefined once
+   * `maxAttempts` is used up
+   */
+  next(): number | undefined {
+    if (this.succeededAt !== undefined) {
+      if (this.clock.now() - this.succeededAt >= (this.policy.resetAfterMs ?? 0)) this.reset();
+      this.succeededAt = undefined;
+    }
+    if (this.taken >= this.maxRetries) return undefined;
+    this.taken++;
+
+    const base = backoffDelay(this.policy, this.taken);
+    const max = this.policy.maxDelayMs ?? 5000;
+    const jitter = this.policy.jitter ?? "none";
+    let delay: number;
+    if (jitter === "full") {
+      delay = this.random() * base;
+    } else if (jitter === "equal") {
+      delay = base / 2 + (this.random() * base) / 2;
+    } else if (jitter === "decorrelated") {
+      const initial = this.policy.initialDelayMs ?? 100;
+      delay = Math.min(max, initial + this.random() * (this.previous * 3 - initial));
+    } else if (typeof jitter === "number") {
+      delay = Math.min(max, base * (1 + jitter * (this.random() * 2 - 1)));
+    } else {
+      delay = base;
+    }
+    this.previous = delay;
+    return delay;
+  }
+
+  /**
+   * The operation succeeded; the next failure starts over from the first
+   * delay, or keeps backing off if it comes within `resetAfterMs`
+   */
+  succeeded(): void {
+    this.succeededAt = this.clock.now();
+  }
+
+  /** Start over from the first delay */
+  reset(): void {
+    this.taken = 0;
+    this.previous = this.policy.initialDelayMs ?? 100;
+    this.succeededAt = undefined;
+  }
+}
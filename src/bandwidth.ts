@@ -0,0 +1,98 @@
+/**
+ * Bandwidth estimation
+ * Tracks how much a connection has queued and how fast the queue drains,
+ * so senders can slow down before buffers pile up
+ *
+ * A write completes once the transport has taken the bytes off our hands.
+ * Writes drain in order, so each one occupied the link from when it was
+ * queued (or when the write before it finished, if later) until it
+ * completed. Bytes drained over that busy time, summed across a sliding
+ * window, give the rate the link sustained while it had data to send; idle
+ * gaps don't count against it.
+ */
+
+import type { Clock } from "./clock.js";
+
+/**
+ * Snapshot of a connection's send side
+ */
+export interface BandwidthEstimate {
+  /**
+   * Rate the link drained queued data at over the recent window, or
+   * undefined before anything has been written in it. Writes the transport
+   * takes at once (an empty socket buffer) measure memory speed, so the
+   * figure only means link capacity once the socket has backed up; it is
+   * Infinity when every write in the window completed immediately.
+   */
+  bytesPerSecond: number | undefined;
+  /** Bytes written but not yet taken by the transport */
+  queuedBytes: number;
+  /** Writes not yet taken by the transport */
+  queuedWrites: number;
+}
+
+interface DrainSample {
+  at: number;
+  bytes: number;
+  busyMs: number;
+}
+
+/** Window the rate is averaged over, in milliseconds */
+const DEFAULT_WINDOW_MS = 1_000;
+
+/**
+ * Records writes as they are queued and completed
+ */
+export class BandwidthEstimator {
+  private queuedBytes = 0;
+  private queuedWrites = 0;
+  private lastDrain = -Infinity;
+  private samples: DrainSample[] = [];
+
+  constructor(
+    private readonly clock: Clock,
+    private readonly windowMs: number = DEFAULT_WINDOW_MS
+  ) {}
+
+  /**
+   * Record a write of `bytes` being queued; call the returned function once
+   * it completes (or fails)
+   */
+  queued(bytes: number): () => void {
+    const start = this.clock.now();
+    this.queuedBytes += bytes;
+    this.queuedWrites++;
+    return () => {
+      const now = this.clock.now();
+      this.queuedBytes -= bytes;
+      this.queuedWrites--;
+      this.samples.push({ at: now, bytes, busyMs: Math.max(0, now - Math.max(start, this.lastDrain)) });
+      this.lastDrain = now;
+      this.prune(now);
+    };
+  }
+
+  /** Current queue depth and drain rate */
+  estimate(): BandwidthEstimate {
+    this.prune(this.clock.now());
+    let bytes = 0;
+    let busyMs = 0;
+    for (const sample of this.samples) {
+      bytes += sample.bytes;
+      busyMs += sample.busyMs;
+    }
+    return {
+      // Everything drained within the clock's resolution: the link kept up with any rate
+      bytesPerSecond: this.samples.length === 0 ? undefined : busyMs === 0 ? Infinity : (bytes * 1000) / busyMs,
+      queuedBytes: this.queuedBytes,
+      queuedWrites: this.queuedWrites,
+    };
+  }
+
+  private prune(now: number): void {
+    const cutoff = now - this.windowMs;
+    let stale = 0;
+    while (stale < this.samples.length && this.samples[stale]!.at < cutoff) stale++;
+    if (stale > 0) this.samples.splice(0, stale);
+  }
+}
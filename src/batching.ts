@@ -0,0 +1,268 @@
+/**
+ * Batching writer
+ * Gathers packets into `writePackets()` batches under a flush policy
+ *
+ * Every write to a socket costs a syscall, and every frame gets its own
+ * header, nonce and tag either way, so batching trades latency for fewer
+ * writes. The adaptive policy only pays that latency when it buys
+ * something: an idle connection flushes each packet at once, while a
+ * backed-up one lets packets accumulate until the transport catches up,
+ * so batches grow with congestion and shrink again when it clears.
+ */
+
+import { ClavisError } from "./error.js";
+import { serializedSize, type PacketTrait } from "./protocol.js";
+import type { BandwidthEstimate } from "./bandwidth.js";
+import { systemClock, type Clock, type TimerHandle } from "./clock.js";
+
+/**
+ * When queued packets are written
+ */
+export type FlushPolicy =
+  /** Write every packet as soon as it is queued */
+  | { kind: "immediate" }
+  /** Write once `maxPackets` are queued or the oldest has waited `maxDelayMs` */
+  | { kind: "batch"; maxPackets?: number | undefined; maxDelayMs?: number | undefined }
+  /**
+   * Write at once while the connection is idle; while a batch is in flight
+   * or the transport holds `congestedBytes` or more, hold packets until it
+   * drains, `maxPackets` are queued or the oldest has waited `maxDelayMs`
+   */
+  | {
+      kind: "adaptive";
+      maxPackets?: number | undefined;
+      maxDelayMs?: number | undefined;
+      congestedBytes?: number | undefined;
+    };
+
+/**
+ * Where batches go (`EncryptedWriter`, `EncryptedStream`)
+ */
+export interface BatchTarget {
+  writePackets(packets: Iterable<PacketTrait>): Promise<number>;
+  bandwidthEstimate(): BandwidthEstimate;
+}
+
+/**
+ * What is waiting in a `BatchingWriter` queue
+ */
+export interface SendQueueStats {
+  /** Packets queued and not yet handed to the target */
+  packets: number;
+  /** Their serialized size in bytes, before compression and framing */
+  bytes: number;
+  /** Milliseconds the oldest queued packet has waited; 0 when the queue is empty */
+  oldestAgeMs: number;
+}
+
+/**
+ * Options of one queued write
+ */
+export interface QueuedWriteOptions {
+  /**
+   * Packets sharing a supersede key replace each other while queued: a
+   * newer one drops the older if that hasn't gone to the target yet, as
+   * for repeated status updates (default: none)
+   */
+  supersede?: string | number | undefined;
+}
+
+/**
+ * A queued write that can still be taken back
+ */
+export interface CancellableWrite {
+  /**
+   * Resolves with true once the packet is written, or false if it was
+   * cancelled or superseded; rejects if the write fails
+   */
+  written: Promise<boolean>;
+  /**
+   * Take the packet out of the queue. Returns false when it was already
+   * handed to the target (or cancelled), and nothing changes.
+   */
+  cancel(): boolean;
+}
+
+interface QueuedPacket {
+  packet: PacketTrait;
+  queuedAt: number;
+  /** Serialized size, measured the first time stats ask for it */
+  size?: number | undefined;
+  key?: string | number | undefined;
+  /** Settle with whether the packet was written (false: dropped from the queue) */
+  resolve: (sent: boolean) => void;
+  reject: (error: unknown) => void;
+}
+
+const DEFAULT_MAX_PACKETS = 64;
+const DEFAULT_MAX_DELAY_MS = 5;
+const DEFAULT_CONGESTED_BYTES = 64 * 1024;
+
+/**
+ * Queues packets and writes them in batches according to a `FlushPolicy`
+ *
+ * @example
+ * ```typescript
+ * const batcher = new BatchingWriter(writer, { kind: "adaptive" });
+ * await batcher.write(Packet.Position({ x, y }));
+ * ```
+ */
+export class BatchingWriter {
+  private queue: QueuedPacket[] = [];
+  private readonly keyed = new Map<string | number, QueuedPacket>();
+  private timer: TimerHandle | undefined;
+  private inFlight: Promise<void> | undefined;
+  private readonly maxPackets: number;
+  private readonly maxDelayMs: number;
+  private readonly congestedBytes: number;
+  /** Batches written so far */
+  batches = 0;
+  /** Queued packets dropped for a newer one with the same supersede key */
+  superseded = 0;
+
+  constructor(
+    private readonly target: BatchTarget,
+    private readonly policy: FlushPolicy = { kind: "adaptive" },
+    private readonly clock: Clock = systemClock
+  ) {
+    const batching = policy.kind === "immediate" ? undefined : policy;
+    this.maxPackets = batching?.maxPackets ?? DEFAULT_MAX_PACKETS;
+    this.maxDelayMs = batching?.maxDelayMs ?? DEFAULT_MAX_DELAY_MS;
+    this.congestedBytes = (policy.kind === "adaptive" ? policy.congestedBytes : undefined) ?? DEFAULT_CONGESTED_BYTES;
+    if (!(this.maxPackets >= 1) || !(this.maxDelayMs >= 0)) {
+      throw ClavisError.config("maxPackets must be at least 1 and maxDelayMs not negative");
+    }
+  }
+
+  /** Packets queued and not yet handed to the target */
+  get pending(): number {
+    return this.queue.length;
+  }
+
+  /** Packets, bytes and the age of the oldest packet waiting in the queue */
+  queueStats(): SendQueueStats {
+    let bytes = 0;
+    for (const entry of this.queue) {
+      entry.size ??= serializedSize(entry.packet);
+      bytes += entry.size;
+    }
+    const oldest = this.queue[0];
+    return {
+      packets: this.queue.length,
+      bytes,
+      oldestAgeMs: oldest ? this.clock.now() - oldest.queuedAt : 0,
+    };
+  }
+
+  /**
+   * Queue a packet. Resolves once the batch holding it has been written,
+   * or once a newer packet with its supersede key replaced it; rejects if
+   * the write fails.
+   *
+   * @example
+   * ```typescript
+   * // however often the status changes, at most one update per room waits
+   * void batcher.write(Packet.RoomStatus(status), { supersede: `status:${room}` });
+   * ```
+   */
+  async write(packet: PacketTrait, options?: QueuedWriteOptions): Promise<void> {
+    await this.enqueue(packet, options?.supersede).written;
+  }
+
+  /**
+   * Queue a packet that may be taken back until its batch goes out, for
+   * updates that are worthless once a newer one exists.
+   *
+   * @example
+   * ```typescript
+   * latest?.cancel();
+   * latest = batcher.writeCancellable(Packet.Position({ x, y }));
+   * ```
+   */
+  writeCancellable(packet: PacketTrait, options?: QueuedWriteOptions): CancellableWrite {
+    const { entry, written } = this.enqueue(packet, options?.supersede);
+    return {
+      written,
+      cancel: () => {
+        if (!this.drop(entry)) return false;
+        if (this.queue.length === 0) {
+          this.timer?.cancel();
+          this.timer = undefined;
+        }
+        return true;
+      },
+    };
+  }
+
+  private enqueue(packet: PacketTrait, key: string | number | undefined): { entry: QueuedPacket; written: Promise<boolean> } {
+    let entry!: QueuedPacket;
+    const written = new Promise<boolean>((resolve, reject) => {
+      entry = { packet, queuedAt: this.clock.now(), key, resolve, reject };
+    });
+    if (key !== undefined) {
+      const older = this.keyed.get(key);
+      if (older && this.drop(older)) this.superseded++;
+      this.keyed.set(key, entry);
+    }
+    this.queue.push(entry);
+    if (this.shouldFlush()) {
+      void this.flush();
+    } else if (!this.timer) {
+      this.timer = this.clock.setTimer(() => void this.flush(), this.maxDelayMs);
+    }
+    return { entry, written };
+  }
+
+  /** Take a packet out of the queue unsent; false if it already left it */
+  private drop(entry: QueuedPacket): boolean {
+    const index = this.queue.indexOf(entry);
+    if (index === -1) return false;
+    this.queue.splice(index, 1);
+    if (entry.key !== undefined && this.keyed.get(entry.key) === entry) this.keyed.delete(entry.key);
+    entry.resolve(false);
+    return true;
+  }
+
+  /** Write everything queued now, whatever the policy */
+  async flush(): Promise<void> {
+    this.timer?.cancel();
+    this.timer = undefined;
+    // Batches go out in order: wait for the one in flight first
+    while (this.inFlight) await this.inFlight;
+    const batch = this.queue.splice(0);
+    this.keyed.clear();
+    if (batch.length === 0) return;
+
+    const write = this.writeBatch(batch);
+    this.inFlight = write;
+    try {
+      await write;
+    } finally {
+      this.inFlight = undefined;
+    }
+    // Packets held back while the batch was in flight go out now if the link allows it
+    if (this.queue.length > 0 && this.shouldFlush()) void this.flush();
+  }
+
+  private async writeBatch(batch: QueuedPacket[]): Promise<void> {
+    this.batches++;
+    try {
+      await this.target.writePackets(batch.map((entry) => entry.packet));
+      for (const entry of batch) entry.resolve(true);
+    } catch (error) {
+      for (const entry of batch) entry.reject(error);
+    }
+  }
+
+  private shouldFlush(): boolean {
+    if (this.queue.length >= this.maxPackets) return true;
+    switch (this.policy.kind) {
+      case "immediate":
+        return true;
+      case "batch":
+        return false;
+      case "adaptive":
+        return !this.inFlight && this.target.bandwidthEstimate().queuedBytes < this.congestedBytes;
+    }
+  }
+}
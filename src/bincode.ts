@@ -12,6 +12,7 @@
  */
 
 import { ClavisError } from "./error.js";
+import { systemClock, type Clock } from "./clock.js";
 
 // ============================================================================
 // WRITE FUNCTIONS (Serialization)
@@ -387,7 +388,11 @@ export function readDateTime(data: Uint8Array, offset: number): ReadResult<Date>
   const nsecsResult = readU32(data, offset + secsResult.bytesRead);
   
   const ms = Number(secsResult.value) * 1000 + Math.floor(nsecsResult.value / 1_000_000);
-  return { value: new Date(ms), bytesRead: secsResult.bytesRead + nsecsResult.bytesRead };
+  const date = new Date(ms);
+  if (isNaN(date.getTime())) {
+    throw ClavisError.deserializationFailed(`DateTime out of range: ${secsResult.value}s`);
+  }
+  return { value: date, bytesRead: secsResult.bytesRead + nsecsResult.bytesRead };
 }
 
 /**
@@ -406,6 +411,16 @@ export function readChronoString(data: Uint8Array, offset: number): ReadResult<D
  * Read raw bytes (for binary data like Uint8Array)
  */
 export function readBytes(data: Uint8Array, offset: number): ReadResult<Uint8Array> {
+  const result = readBytesView(data, offset);
+  return { value: result.value.slice(), bytesRead: result.bytesRead };
+}
+
+/**
+ * Read raw bytes as a view into `data` instead of a copy.
+ * Nothing is copied however large the field is, but the view keeps all of
+ * `data` alive and sees any later change to it.
+ */
+export function readBytesView(data: Uint8Array, offset: number): ReadResult<Uint8Array> {
   const lenResult = readU64(data, offset);
   const length = Number(lenResult.value);
   const start = offset + lenResult.bytesRead;
@@ -414,8 +429,7 @@ export function readBytes(data: Uint8Array, offset: number): ReadResult<Uint8Arr
     throw ClavisError.deserializationFailed(`Bytes length ${length} exceeds available data`);
   }
   
-  const bytes = data.slice(start, start + length);
-  return { value: bytes, bytesRead: lenResult.bytesRead + length };
+  return { value: data.subarray(start, start + length), bytesRead: lenResult.bytesRead + length };
 }
 
 // ============================================================================
@@ -530,6 +544,105 @@ export function getRemainingBytes(data: Uint8Array, offset: number): number {
   return data.length - offset;
 }
 
+// ============================================================================
+// DECODE LIMITS
+// ============================================================================
+
+/**
+ * Hardening limits for decoding untrusted payloads. Well-framed payloads
+ * within `maxPacketSize` can still declare huge collections or nest deeply
+ * enough to exhaust the stack; these bound what a single packet can make
+ * the decoder do. Lengths are checked before anything is allocated.
+ */
+export interface DecodeLimits {
+  /** Most items in one sequence or map (default: as many as the payload has room for) */
+  maxSequenceLength?: number | undefined;
+  /** Deepest nesting of sequences, maps and `nested()` reads (default: 64) */
+  maxDepth?: number | undefined;
+  /** Longest string in UTF-8 bytes (default: as long as the payload has room for) */
+  maxStringLength?: number | undefined;
+  /**
+   * Time budget for decoding one payload (default: none). A budget starts
+   * when it's created, so give each payload a fresh one.
+   */
+  budget?: DecodeBudget | undefined;
+}
+
+/** Calls to `DecodeBudget.check()` between clock readings */
+const BUDGET_CHECK_INTERVAL = 64;
+
+/**
+ * Time a single payload may spend being decoded. Limits bound how much a
+ * packet can make the decoder do, but a payload within them can still be
+ * slow to walk; decoders check the budget cooperatively at every sequence,
+ * map and nested item and give up once it's spent, so one pathological
+ * packet can't hold the event loop until it's done.
+ */
+export class DecodeBudget {
+  private readonly deadline: number;
+  private calls = 0;
+
+  constructor(readonly budgetMs: number, private readonly clock: Clock = systemClock) {
+    if (!(budgetMs > 0)) {
+      throw ClavisError.config("Decode budget must be positive");
+    }
+    this.deadline = clock.now() + budgetMs;
+  }
+
+  /** Throw once the budget is spent; reads the clock every 64th call, so it's cheap per item */
+  check(): void {
+    if (++this.calls % BUDGET_CHECK_INTERVAL === 0) this.checkNow();
+  }
+
+  /** Throw if the budget is spent, reading the clock now */
+  checkNow(): void {
+    if (this.clock.now() > this.deadline) {
+      throw ClavisError.deserializationFailed(`Decoding took longer than its ${this.budgetMs}ms budget`);
+    }
+  }
+}
+
+/** Nesting allowed when `maxDepth` isn't set */
+export const DEFAULT_MAX_DEPTH = 64;
+
+/**
+ * Throw a configuration error unless every limit that is set is a
+ * non-negative integer
+ */
+export function checkDecodeLimits(limits: DecodeLimits): void {
+  for (const [name, value] of Object.entries(limits)) {
+    if (name === "budget") continue;
+    if (value !== undefined && !(Number.isInteger(value) && value >= 0)) {
+      throw ClavisError.config(`${name} must be a non-negative integer`);
+    }
+  }
+}
+
+/** Refuse a sequence or map longer than `maxSequenceLength`, or one reached after the budget ran out */
+export function checkSequenceLength(length: number, limits: DecodeLimits | undefined): void {
+  limits?.budget?.check();
+  const max = limits?.maxSequenceLength;
+  if (max !== undefined && length > max) {
+    throw ClavisError.deserializationFailed(`Sequence of ${length} items exceeds maxSequenceLength ${max}`);
+  }
+}
+
+/** Refuse a string longer than `maxStringLength` */
+export function checkStringLength(length: number, limits: DecodeLimits | undefined): void {
+  const max = limits?.maxStringLength;
+  if (max !== undefined && length > max) {
+    throw ClavisError.deserializationFailed(`String of ${length} bytes exceeds maxStringLength ${max}`);
+  }
+}
+
+/** Refuse an item nested deeper than `maxDepth`, or one reached after the budget ran out */
+export function checkDepth(depth: number, limits: DecodeLimits | undefined): void {
+  limits?.budget?.check();
+  if (depth > (limits?.maxDepth ?? DEFAULT_MAX_DEPTH)) {
+    throw ClavisError.deserializationFailed("Payload nested too deeply");
+  }
+}
+
 // ============================================================================
 // BINCODE READER CLASS (Stateful Deserialization)
 // ============================================================================
@@ -550,8 +663,13 @@ export function getRemainingBytes(data: Uint8Array, offset: number): number {
 export class BincodeReader {
   private data: Uint8Array;
   private pos: number = 0;
+  private depth: number = 0;
 
-  constructor(data: Uint8Array) {
+  /**
+   * @param data - Bytes to read
+   * @param limits - Bounds on string lengths, sequence lengths and nesting (default: none beyond the data's length)
+   */
+  constructor(data: Uint8Array, readonly limits?: DecodeLimits) {
     this.data = data;
   }
 
@@ -639,6 +757,9 @@ export class BincodeReader {
 
   /** Read a string */
   readString(): string {
+    if (this.limits?.maxStringLength !== undefined) {
+      checkStringLength(Number(readU64(this.data, this.pos).value), this.limits);
+    }
     const result = readString(this.data, this.pos);
     this.pos += result.bytesRead;
     return result.value;
@@ -646,9 +767,7 @@ export class BincodeReader {
 
   /** Read an optional string */
   readOptionString(): string | undefined {
-    const result = readOptionString(this.data, this.pos);
-    this.pos += result.bytesRead;
-    return result.value;
+    return this.readU8() === 0 ? undefined : this.readString();
   }
 
   /** Read an Option<u32> */
@@ -667,16 +786,42 @@ export class BincodeReader {
 
   /** Read a Vec<String> */
   readStringVec(): string[] {
-    const result = readStringVec(this.data, this.pos);
-    this.pos += result.bytesRead;
-    return result.value;
+    const length = this.readSequenceLength(8);
+    return Array.from({ length }, () => this.readString());
   }
 
   /** Read a Vec<(String, String)> */
   readStringPairVec(): Array<[string, string]> {
-    const result = readStringPairVec(this.data, this.pos);
-    this.pos += result.bytesRead;
-    return result.value;
+    const length = this.readSequenceLength(16);
+    return Array.from({ length }, (): [string, string] => [this.readString(), this.readString()]);
+  }
+
+  /**
+   * Read the u64 item count of a sequence or map, refusing counts above
+   * `maxSequenceLength` and counts the remaining data can't hold at
+   * `minItemSize` bytes per item
+   */
+  readSequenceLength(minItemSize: number = 1): number {
+    const length = this.readU64();
+    if (length * BigInt(Math.max(minItemSize, 0)) > BigInt(this.remaining)) {
+      throw ClavisError.deserializationFailed(`Sequence length ${length} exceeds available data`);
+    }
+    checkSequenceLength(Number(length), this.limits);
+    return Number(length);
+  }
+
+  /**
+   * Run `read` one level deeper, refusing to go past `maxDepth`. Decoders
+   * of recursive types should read each nested value through it.
+   */
+  nested<V>(read: (reader: this) => V): V {
+    checkDepth(this.depth + 1, this.limits);
+    this.depth++;
+    try {
+      return read(this);
+    } finally {
+      this.depth--;
+    }
   }
 
   /** Read a DateTime (struct format) */
@@ -700,6 +845,13 @@ export class BincodeReader {
     return result.value;
   }
 
+  /** Read raw bytes as a view into the packet, without copying (see `readBytesView`) */
+  readBytesView(): Uint8Array {
+    const result = readBytesView(this.data, this.pos);
+    this.pos += result.bytesRead;
+    return result.value;
+  }
+
   /** Read a fixed number of raw bytes without length prefix */
   readRawBytes(length: number): Uint8Array {
     if (this.pos + length > this.data.length) {
@@ -710,6 +862,15 @@ export class BincodeReader {
     return bytes;
   }
 
+  /**
+   * Read a trailing field that was appended in a later protocol version.
+   * Returns undefined if the packet ends before the field, so packets from
+   * older senders still decode.
+   */
+  readTrailing<V>(read: (reader: BincodeReader) => V): V | undefined {
+    return this.hasMore ? read(this) : undefined;
+  }
+
   /** Peek at the next byte without consuming it */
   peekU8(): number {
     if (this.pos >= this.data.length) {
@@ -731,6 +892,6 @@ export class BincodeReader {
     }
     const subData = this.data.slice(this.pos, this.pos + length);
     this.pos += length;
-    return new BincodeReader(subData);
+    return new BincodeReader(subData, this.limits);
   }
 }
@@ -0,0 +1,214 @@
+/**
+ * Plaintext bridge
+ * A local sidecar through which programs without a clavis implementation
+ * (a C++ desktop client, a shell script) talk to clavis peers
+ *
+ * A JavaScript library can't export C functions, so instead of linking
+ * clavis in, the program connects to the bridge over loopback TCP and
+ * exchanges packets as `length (u32 little-endian) | bytes`: the framing of
+ * clavis without the nonce and tag. The bridge runs the handshake and
+ * encrypts and decrypts every packet, so the program needs no cryptography.
+ *
+ * - "connect" mode: every local connection gets its own clavis connection to
+ *   a server; the program plays the client.
+ * - "accept" mode: every clavis client that connects gets its own plaintext
+ *   connection to a local service; the program plays the server.
+ *
+ * Closing either side closes the other: a local end becomes a close frame,
+ * and a close frame or EOF from the peer ends the local connection.
+ * Packets cross the loopback interface unencrypted, so in "connect" mode
+ * the plaintext side listens on 127.0.0.1 unless told otherwise. In
+ * "accept" mode the bridge listens for the network and takes no default:
+ * the caller names the address to expose.
+ */
+
+import { createConnection, createServer, type AddressInfo, type Server, type Socket } from "net";
+import type { Duplex } from "stream";
+import { ClavisError, MessageError, StreamError } from "./error.js";
+import { EncryptedStream, type EncryptedStreamOptions } from "./stream.js";
+import { EncryptedListener, type EncryptedListenerOptions } from "./listener.js";
+import { RawPacket } from "./protocol.js";
+
+/** Bytes of the length prefix in front of every plaintext packet */
+export const BRIDGE_HEADER_LENGTH = 4;
+
+/**
+ * An address to listen on or connect to
+ */
+export interface BridgeEndpoint {
+  /** Host name or address (default for the plaintext side of "connect" mode: 127.0.0.1) */
+  host?: string | undefined;
+  port: number;
+}
+
+/**
+ * Options for `startBridge`
+ */
+export type BridgeOptions =
+  | {
+    mode: "connect";
+    /** Where local programs connect, in plaintext */
+    listen: BridgeEndpoint;
+    /** The clavis server each local connection is bridged to */
+    remote: BridgeEndpoint & { host: string };
+    /** Options of every outgoing encrypted stream */
+    streamOptions?: EncryptedStreamOptions | undefined;
+  }
+  | {
+    mode: "accept";
+    /** Where clavis clients connect; the host is required, e.g. "0.0.0.0" to accept from anywhere */
+    listen: BridgeEndpoint & { host: string };
+    /** The plaintext service each clavis connection is bridged to */
+    local: BridgeEndpoint & { host: string };
+    /** Options of the encrypted listener, its `streamOptions` included */
+    listenerOptions?: EncryptedListenerOptions | undefined;
+  };
+
+/**
+ * A running bridge
+ */
+export interface Bridge {
+  /** Port the bridge listens on, useful after binding port 0 */
+  readonly port: number;
+  /** Stop accepting connections; bridged connections keep running */
+  close(): Promise<void>;
+}
+
+/**
+ * Start a bridge in either mode
+ *
+ * @example
+ * ```typescript
+ * // A C++ client connects to 127.0.0.1:9000 and reaches the clavis server
+ * await startBridge({
+ *   mode: "connect",
+ *   listen: { port: 9000 },
+ *   remote: { host: "api.example.com", port: 7000 },
+ *   streamOptions: { psk },
+ * });
+ * ```
+ */
+export async function startBridge(options: BridgeOptions): Promise<Bridge> {
+  if (options.mode === "accept") {
+    const listener = await EncryptedListener.bind(options.listen.port, options.listen.host, options.listenerOptions);
+    const local = options.local;
+    void (async () => {
+      for await (const { stream, socket } of listener) {
+        connectSocket(local)
+          .then((plain) => bridgeStream(plain, stream))
+          .catch(() => undefined)
+          .finally(() => socket.destroy());
+      }
+    })();
+    return { port: (listener.address() as AddressInfo).port, close: () => listener.close() };
+  }
+
+  const remote = options.remote;
+  const streamOptions = options.streamOptions;
+  const server = createServer((plain) => {
+    plain.pause();
+    connectSocket(remote)
+      .then(async (socket) => {
+        try {
+          await bridgeStream(plain, await EncryptedStream.new(socket, streamOptions));
+        } finally {
+          socket.destroy();
+        }
+      })
+      .catch(() => plain.destroy());
+  });
+  await listen(server, options.listen.port, options.listen.host ?? "127.0.0.1");
+  return {
+    port: (server.address() as AddressInfo).port,
+    close: () => new Promise<void>((resolve) => server.close(() => resolve())),
+  };
+}
+
+/**
+ * Pass packets between a plaintext duplex, framed as `length | bytes`, and
+ * an encrypted stream until both directions have ended. Rejects with the
+ * first failure of either side after destroying `plain`; the caller
+ * destroys the encrypted stream's transport.
+ */
+export async function bridgeStream(plain: Duplex, stream: EncryptedStream): Promise<void> {
+  const outbound = (async () => {
+    // Chunks are only joined once they hold what the next step needs, so a
+    // large packet arriving in many chunks is copied once, not once per chunk
+    const chunks: Uint8Array[] = [];
+    let buffered = 0;
+    let length: number | undefined;
+    for await (const chunk of plain) {
+      chunks.push(chunk as Uint8Array);
+      buffered += (chunk as Uint8Array).length;
+      if (buffered < (length ?? BRIDGE_HEADER_LENGTH)) continue;
+      const bytes = chunks.length === 1 ? chunks[0]! : Buffer.concat(chunks, buffered);
+      let offset = 0;
+      for (;;) {
+        if (length === undefined) {
+          if (bytes.length - offset < BRIDGE_HEADER_LENGTH) break;
+          length = new DataView(bytes.buffer, bytes.byteOffset + offset, BRIDGE_HEADER_LENGTH).getUint32(0, true);
+          if (length > stream.maxPacketSize) {
+            throw ClavisError.message(MessageError.messageTooLarge(length, stream.maxPacketSize));
+          }
+          offset += BRIDGE_HEADER_LENGTH;
+        }
+        if (bytes.length - offset < length) break;
+        // Iteration pauses the socket while the packet is written
+        await stream.writePacket(new RawPacket(new Uint8Array(bytes.subarray(offset, offset + length))));
+        offset += length;
+        length = undefined;
+      }
+      chunks.length = 0;
+      buffered = bytes.length - offset;
+      if (buffered > 0) chunks.push(bytes.subarray(offset));
+    }
+    if (buffered > 0 || length !== undefined) {
+      throw ClavisError.stream(StreamError.unexpectedClose());
+    }
+    await stream.close();
+  })();
+
+  const inbound = (async () => {
+    for await (const packet of stream) {
+      const framed = new Uint8Array(BRIDGE_HEADER_LENGTH + packet.length);
+      new DataView(framed.buffer).setUint32(0, packet.length, true);
+      framed.set(packet, BRIDGE_HEADER_LENGTH);
+      if (!plain.write(framed)) {
+        await new Promise<void>((resolve) => plain.once("drain", resolve));
+      }
+    }
+    plain.end();
+  })();
+
+  // The direction that didn't fail ends once the caller tears the transport down
+  outbound.catch(() => undefined);
+  inbound.catch(() => undefined);
+  try {
+    await Promise.all([outbound, inbound]);
+  } catch (error) {
+    plain.destroy();
+    throw error;
+  }
+}
+
+function connectSocket(endpoint: BridgeEndpoint & { host: string }): Promise<Socket> {
+  return new Promise((resolve, reject) => {
+    const onError = (error: Error) => reject(ClavisError.stream(StreamError.connectionRefused(error)));
+    const socket = createConnection({ host: endpoint.host, port: endpoint.port }, () => {
+      socket.off("error", onError);
+      resolve(socket);
+    });
+    socket.once("error", onError);
+  });
+}
+
+function listen(server: Server, port: number, host: string): Promise<void> {
+  return new Promise((resolve, reject) => {
+    const onError = (error: Error) => reject(ClavisError.stream(StreamError.io(error)));
+    server.once("error", onError);
+    server.listen({ port, host }, () => {
+      server.off("error", onError);
+      resolve();
+    });
+  });
+}
@@ -0,0 +1,114 @@
+/**
+ * Broadcast
+ * Fans one packet out to many encrypted writers with bounded concurrency
+ * and per-connection results
+ */
+
+import { ClavisError, StreamError } from "./error.js";
+import { PreparedPacket, type PacketTrait } from "./protocol.js";
+import { systemClock, type Clock, type TimerHandle } from "./clock.js";
+
+/**
+ * Anything a packet can be broadcast to (`EncryptedWriter`, `EncryptedStream`)
+ */
+export interface BroadcastTarget {
+  writePacket(packet: PacketTrait): Promise<number>;
+}
+
+/**
+ * Options for a broadcast
+ */
+export interface BroadcastOptions {
+  /** Writes in flight at once (default: 64) */
+  concurrency?: number | undefined;
+  /** Give up on a connection whose write takes longer than this many milliseconds */
+  timeoutMs?: number | undefined;
+  /** Time source for write timeouts (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+/**
+ * Outcome of writing to one target
+ */
+export type BroadcastResult<W> =
+  | { target: W; ok: true; bytes: number }
+  | { target: W; ok: false; error: ClavisError };
+
+/**
+ * Outcome of a whole broadcast, with results in target order
+ */
+export interface BroadcastSummary<W> {
+  results: BroadcastResult<W>[];
+  delivered: number;
+  failed: number;
+  /** Total bytes written across all targets */
+  bytes: number;
+}
+
+const DEFAULT_CONCURRENCY = 64;
+
+function toClavisError(error: unknown): ClavisError {
+  if (error instanceof ClavisError) return error;
+  if (error instanceof StreamError) return ClavisError.stream(error);
+  return ClavisError.stream(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+}
+
+function withTimeout<R>(promise: Promise<R>, timeoutMs: number | undefined, clock: Clock): Promise<R> {
+  if (timeoutMs === undefined) return promise;
+  let timer: TimerHandle | undefined;
+  const timeout = new Promise<never>((_, reject) => {
+    timer = clock.setTimer(() => reject(ClavisError.stream(StreamError.timeout(timeoutMs))), timeoutMs);
+  });
+  return Promise.race([promise, timeout]).finally(() => timer?.cancel());
+}
+
+/**
+ * Send one packet to every target.
+ * The packet is serialized once; each target encrypts it with its own keys.
+ * A failing or slow target never affects the others, and the returned
+ * promise never rejects because of a single connection.
+ *
+ * @example
+ * ```typescript
+ * const summary = await broadcast(Packet.Status({ usersOnline }), writers, { timeoutMs: 1000 });
+ * for (const result of summary.results) {
+ *   if (!result.ok) dropClient(result.target);
+ * }
+ * ```
+ */
+export async function broadcast<W extends BroadcastTarget>(
+  packet: PacketTrait,
+  targets: Iterable<W>,
+  options: BroadcastOptions = {}
+): Promise<BroadcastSummary<W>> {
+  const prepared = packet instanceof PreparedPacket ? packet : PreparedPacket.new(packet);
+  const list = Array.from(targets);
+  const concurrency = Math.max(1, options.concurrency ?? DEFAULT_CONCURRENCY);
+  const clock = options.clock ?? systemClock;
+  const results: BroadcastResult<W>[] = new Array(list.length);
+
+  let next = 0;
+  const lane = async () => {
+    while (next < list.length) {
+      const index = next++;
+      const target = list[index]!;
+      try {
+        const bytes = await withTimeout(target.writePacket(prepared), options.timeoutMs, clock);
+        results[index] = { target, ok: true, bytes };
+      } catch (error) {
+        results[index] = { target, ok: false, error: toClavisError(error) };
+      }
+    }
+  };
+  await Promise.all(Array.from({ length: Math.min(concurrency, list.length) }, lane));
+
+  let delivered = 0;
+  let bytes = 0;
+  for (const result of results) {
+    if (result.ok) {
+      delivered++;
+      bytes += result.bytes;
+    }
+  }
+  return { results, delivered, failed: list.length - delivered, bytes };
+}
@@ -0,0 +1,218 @@
+/**
+ * Chunked payloads
+ * Sends byte streams larger than the max packet size as a run of packets
+ *
+ * Every packet of a transfer starts with a kind byte (see `ChunkKind`):
+ * - Data: the next slice of the payload
+ * - End: the payload is complete
+ * - Abort: the sender gave up; the rest is a UTF-8 reason
+ *
+ * A transfer occupies the connection until its End or Abort: packets are
+ * read in order, so anything else sent in between would be taken for a
+ * chunk. Run transfers alongside other traffic on their own mux channel,
+ * giving a `chunkSize` that leaves room for the channel envelope.
+ *
+ * Backpressure comes from the connection itself. The writer waits for each
+ * packet to be written before reading more of the source, and the reader
+ * only reads a packet when its consumer pulls, so a slow consumer fills the
+ * socket buffers and stalls the sender.
+ */
+
+import { ClavisError, MessageError } from "./error.js";
+import { RawPacket, type PacketTrait } from "./protocol.js";
+
+/**
+ * Packet kinds within a transfer
+ */
+export enum ChunkKind {
+  Data = 0,
+  End = 1,
+  Abort = 2,
+}
+
+/** Bytes each chunk packet spends on its kind */
+export const CHUNK_HEADER_SIZE = 1;
+
+/**
+ * Something to write chunks to: a stream, its writer half or a mux channel's
+ * writer. Writers without a `maxPacketSize` need an explicit `chunkSize`.
+ */
+export interface ChunkWriter {
+  writePacket(packet: PacketTrait): Promise<number>;
+  readonly maxPacketSize?: number | undefined;
+}
+
+/** Something to read chunks from: a stream, its reader half or a mux channel's reader */
+export interface ChunkReader {
+  readPacket(): Promise<unknown>;
+}
+
+/** Payload bytes, e.g. a Node.js `Readable`, a web `ReadableStream` or an array of buffers */
+export type ChunkSource = AsyncIterable<Uint8Array> | Iterable<Uint8Array> | ReadableStream<Uint8Array>;
+
+/**
+ * Options for `writeStream`
+ */
+export interface WriteStreamOptions {
+  /**
+   * Payload bytes per packet (default: as many as the max packet size
+   * allows). Smaller source reads are gathered until a chunk is full.
+   */
+  chunkSize?: number | undefined;
+}
+
+function encodeChunk(kind: ChunkKind, payload: Uint8Array): RawPacket {
+  const packet = new Uint8Array(CHUNK_HEADER_SIZE + payload.length);
+  packet[0] = kind;
+  packet.set(payload, CHUNK_HEADER_SIZE);
+  return new RawPacket(packet);
+}
+
+async function* sourceChunks(source: ChunkSource): AsyncGenerator<Uint8Array> {
+  if (source instanceof ReadableStream) {
+    const reader = source.getReader();
+    try {
+      for (;;) {
+        const { done, value } = await reader.read();
+        if (done) return;
+        yield value;
+      }
+    } finally {
+      reader.releaseLock();
+    }
+  }
+  yield* source;
+}
+
+/**
+ * Send everything `source` yields as one chunked payload, then an End.
+ * If the source fails the receiver gets an Abort and the error is rethrown.
+ * Resolves with the number of payload bytes sent.
+ *
+ * @example
+ * ```typescript
+ * await writeStream(writer, createReadStream("video.mp4"));
+ * ```
+ */
+export async function writeStream(
+  writer: ChunkWriter,
+  source: ChunkSource,
+  options: WriteStreamOptions = {}
+): Promise<number> {
+  const maxChunk = writer.maxPacketSize === undefined ? undefined : writer.maxPacketSize - CHUNK_HEADER_SIZE;
+  const chunkSize = options.chunkSize ?? maxChunk;
+  if (chunkSize === undefined) {
+    throw ClavisError.config("chunkSize is required for writers without a maxPacketSize");
+  }
+  if (!Number.isInteger(chunkSize) || chunkSize <= 0) {
+    throw ClavisError.config("chunkSize must be a positive integer");
+  }
+  if (maxChunk !== undefined && chunkSize > maxChunk) {
+    throw ClavisError.config(`chunkSize must be at most ${maxChunk} bytes for this writer`);
+  }
+
+  const buffer = new Uint8Array(chunkSize);
+  let filled = 0;
+  let sent = 0;
+  const chunks = sourceChunks(source);
+  try {
+    for (;;) {
+      let next: IteratorResult<Uint8Array>;
+      try {
+        next = await chunks.next();
+      } catch (error) {
+        // The source failed, not the connection; tell the receiver
+        const reason = error instanceof Error ? error.message : String(error);
+        await writer.writePacket(encodeChunk(ChunkKind.Abort, new TextEncoder().encode(reason))).catch(() => undefined);
+        throw error;
+      }
+      if (next.done) break;
+
+      const data = next.value;
+      let offset = 0;
+      while (offset < data.length) {
+        const take = Math.min(chunkSize - filled, data.length - offset);
+        buffer.set(data.subarray(offset, offset + take), filled);
+        filled += take;
+        offset += take;
+        if (filled === chunkSize) {
+          await writer.writePacket(encodeChunk(ChunkKind.Data, buffer));
+          sent += filled;
+          filled = 0;
+        }
+      }
+    }
+  } finally {
+    // Let the source clean up if a write failed partway
+    await chunks.return(undefined);
+  }
+  if (filled > 0) {
+    await writer.writePacket(encodeChunk(ChunkKind.Data, buffer.subarray(0, filled)));
+    sent += filled;
+  }
+  await writer.writePacket(encodeChunk(ChunkKind.End, new Uint8Array(0)));
+  return sent;
+}
+
+/**
+ * Read one chunked payload as a web `ReadableStream`.
+ * The stream closes at the sender's End and errors on an Abort or a read
+ * failure. Cancelling it reads and discards the rest of the transfer, so the
+ * connection can carry on afterwards.
+ *
+ * @example
+ * ```typescript
+ * await readStream(reader).pipeTo(Writable.toWeb(createWriteStream("video.mp4")));
+ * ```
+ */
+export function readStream(reader: ChunkReader): ReadableStream<Uint8Array> {
+  let finished = false;
+
+  const next = async (): Promise<Uint8Array | undefined> => {
+    const packet = (await reader.readPacket()) as unknown as Uint8Array;
+    const kind = packet[0];
+    switch (kind) {
+      case ChunkKind.Data:
+        return packet.subarray(CHUNK_HEADER_SIZE);
+      case ChunkKind.End:
+        finished = true;
+        return undefined;
+      case ChunkKind.Abort:
+        finished = true;
+        throw ClavisError.invalidOperation(
+          `Sender aborted the transfer: ${new TextDecoder().decode(packet.subarray(CHUNK_HEADER_SIZE))}`
+        );
+      default:
+        finished = true;
+        throw ClavisError.message(MessageError.invalidFormat(`Unknown chunk kind ${kind}`));
+    }
+  };
+
+  // A pull may still be reading when the stream is cancelled
+  let reading: Promise<Uint8Array | undefined> | undefined;
+
+  return new ReadableStream<Uint8Array>({
+    async pull(controller) {
+      // Skip empty chunks so every pull delivers bytes or ends the stream
+      for (;;) {
+        const chunk = await (reading = next());
+        if (chunk === undefined) {
+          controller.close();
+          return;
+        }
+        if (chunk.length > 0) {
+          controller.enqueue(chunk);
+          return;
+        }
+      }
+    },
+    async cancel() {
+      await reading?.catch(() => undefined);
+      while (!finished) {
+        await next().catch(() => {
+          finished = true;
+        });
+      }
+    },
+  });
+}
@@ -0,0 +1,247 @@
+/**
+ * Client module generator
+ * Turns a protocol reference into a TypeScript module with one typed
+ * interface per variant and helpers to encode, send, decode and receive them
+ *
+ * The input is the same `ProtocolDoc` `describeProtocol` builds for the
+ * published reference. It is plain data, so it can just as well be loaded
+ * from JSON exported next to the Rust enum. The generated module defines the
+ * codec and schemas itself and only imports clavis-js, so it can be checked
+ * in and regenerated whenever the protocol changes.
+ *
+ * Variants with a schema get an interface of their fields; variants without
+ * one carry their payload as raw bytes.
+ */
+
+import { ClavisError } from "./error.js";
+import { schemaHash, type FieldType, type SchemaField } from "./schema.js";
+import type { DocumentedVariant, ProtocolDoc } from "./protocol-doc.js";
+
+/**
+ * Options for `generateClientModule`
+ */
+export interface ClientModuleOptions {
+  /** Module the generated code imports clavis-js from (default: "clavis-js") */
+  importFrom?: string | undefined;
+  /**
+   * Name of the message union; also prefixes the codec and the decode and
+   * receive helpers (default: "Message")
+   */
+  typeName?: string | undefined;
+}
+
+const TS_TYPES: Record<FieldType, string> = {
+  u8: "number",
+  u16: "number",
+  u32: "number",
+  i32: "number",
+  u64: "bigint",
+  i64: "bigint",
+  bool: "boolean",
+  string: "string",
+  bytes: "Uint8Array",
+  datetime: "Date",
+};
+
+const IDENTIFIER = /^[A-Za-z_$][\w$]*$/;
+
+function isFieldType(type: string): type is FieldType {
+  return Object.hasOwn(TS_TYPES, type);
+}
+
+/** Read a documented `u32` or `Option<u32>` back into a schema field */
+function parseField(variant: string, field: { name: string; type: string }): SchemaField {
+  const option = /^Option<(.+)>$/.exec(field.type);
+  const type = option ? option[1]! : field.type;
+  if (!isFieldType(type)) {
+    throw ClavisError.config(`Field ${variant}.${field.name} has unsupported type ${field.type}`);
+  }
+  return option ? { name: field.name, type, optional: true } : { name: field.name, type };
+}
+
+/** `send_message` becomes `SendMessage` */
+function pascalCase(name: string): string {
+  const joined = name
+    .split(/[^A-Za-z0-9]+/)
+    .filter((part) => part.length > 0)
+    .map((part) => part[0]!.toUpperCase() + part.slice(1))
+    .join("");
+  return /^[0-9]/.test(joined) ? `V${joined}` : joined;
+}
+
+function propertyKey(name: string): string {
+  return IDENTIFIER.test(name) ? name : JSON.stringify(name);
+}
+
+function docComment(text: string | undefined): string[] {
+  if (!text) return [];
+  const lines = text.replace(/\*\//g, "*\\/").split("\n");
+  if (lines.length === 1) return [`/** ${lines[0]} */`];
+  return ["/**", ...lines.map((line) => ` * ${line}`.trimEnd()), " */"];
+}
+
+interface VariantPlan {
+  variant: DocumentedVariant;
+  ident: string;
+  fields: SchemaField[] | undefined;
+}
+
+function planVariants(doc: ProtocolDoc, reserved: Set<string>): VariantPlan[] {
+  const taken = new Map<string, string>();
+  return doc.variants.map((variant) => {
+    const ident = pascalCase(variant.name);
+    if (ident === "" || reserved.has(ident)) {
+      throw ClavisError.config(`Variant ${variant.name} has no usable TypeScript name; choose another typeName or rename it`);
+    }
+    const clash = taken.get(ident);
+    if (clash !== undefined) {
+      throw ClavisError.config(`Variants ${clash} and ${variant.name} would both be named ${ident}`);
+    }
+    taken.set(ident, variant.name);
+
+    const fields = variant.fields?.map((field) => parseField(variant.name, field));
+    if (fields && variant.schemaHash !== undefined && schemaHash(variant.name, fields) !== variant.schemaHash) {
+      throw ClavisError.config(`Fields of ${variant.name} don't match its schema hash`);
+    }
+    return { variant, ident, fields };
+  });
+}
+
+/**
+ * Generate the source of a TypeScript module for a protocol
+ *
+ * @example
+ * ```typescript
+ * const doc = describeProtocol(ChatCodec, { title: "Chat", variants: { Join: { schema: JoinSchema } } });
+ * writeFileSync("chat-client.ts", generateClientModule(doc));
+ *
+ * // chat-client.ts exports Join, MessageCodec, sendJoin(), receiveMessage(), ...
+ * ```
+ */
+export function generateClientModule(doc: ProtocolDoc, options: ClientModuleOptions = {}): string {
+  const importFrom = options.importFrom ?? "clavis-js";
+  const typeName = options.typeName ?? "Message";
+  if (!IDENTIFIER.test(typeName)) {
+    throw ClavisError.config(`typeName must be a TypeScript identifier, got ${typeName}`);
+  }
+  if (doc.variants.length === 0) {
+    throw ClavisError.config(`Protocol ${doc.title} has no variants`);
+  }
+
+  const codecName = `${typeName}Codec`;
+  const plans = planVariants(doc, new Set([typeName, codecName]));
+  const withSchema = plans.filter((plan) => plan.fields !== undefined);
+  const withFields = withSchema.filter((plan) => plan.fields!.length > 0);
+  const errorVariant = doc.variants.find((variant) => variant.error)?.name;
+
+  const imports = ["createProtocolCodec", "RawPacket"];
+  if (withSchema.length > 0) imports.push("defineSchema");
+  if (withFields.length > 0) imports.push("encodeStruct", "decodeStruct");
+  imports.push("type PhaseTransport");
+
+  const codecOptions: string[] = [];
+  if (doc.indexEncoding === "varint") codecOptions.push("useVarint: true");
+  if (errorVariant !== undefined) codecOptions.push(`errorVariant: ${JSON.stringify(errorVariant)}`);
+  // Only indices that don't follow from the one before need pinning
+  const ids: string[] = [];
+  let next = 0;
+  for (const variant of doc.variants) {
+    if (variant.index !== next) ids.push(`${JSON.stringify(variant.name)}: ${variant.index}`);
+    next = variant.index + 1;
+  }
+  if (ids.length > 0) codecOptions.push(`ids: { ${ids.join(", ")} }`);
+  const deprecated = doc.variants.filter((variant) => variant.deprecated).map((variant) => JSON.stringify(variant.name));
+  if (deprecated.length > 0) codecOptions.push(`deprecated: [${deprecated.join(", ")}]`);
+
+  const out: string[] = [
+    `// Generated by clavis-js from the ${JSON.stringify(doc.title)} protocol reference. Do not edit.`,
+    "",
+    `import { ${imports.join(", ")} } from ${JSON.stringify(importFrom)};`,
+    "",
+    ...docComment(doc.description),
+    `export const ${codecName} = createProtocolCodec(`,
+    `  ${JSON.stringify(doc.variants.map((variant) => variant.name))} as const${codecOptions.length > 0 ? "," : ""}`,
+  ];
+  if (codecOptions.length > 0) out.push(`  { ${codecOptions.join(", ")} }`);
+  out.push(");");
+
+  for (const { variant, ident, fields } of withFields) {
+    out.push("", ...docComment(variant.doc), `export interface ${ident} {`);
+    for (const field of fields!) {
+      const type = TS_TYPES[field.type];
+      out.push(field.optional ? `  ${propertyKey(field.name)}?: ${type} | undefined;` : `  ${propertyKey(field.name)}: ${type};`);
+    }
+    out.push("}");
+  }
+
+  if (withSchema.length > 0) out.push("");
+  for (const { variant, ident, fields } of withSchema) {
+    const list = fields!.map((field) =>
+      field.optional
+        ? `{ name: ${JSON.stringify(field.name)}, type: "${field.type}", optional: true }`
+        : `{ name: ${JSON.stringify(field.name)}, type: "${field.type}" }`
+    );
+    out.push(`export const ${ident}Schema = defineSchema(${JSON.stringify(variant.name)}, [${list.join(", ")}]);`);
+  }
+
+  out.push("", `/** Any packet of the protocol, by variant */`, `export type ${typeName} =`);
+  for (const { variant, ident, fields } of plans) {
+    const name = JSON.stringify(variant.name);
+    const shape = fields === undefined
+      ? `{ type: ${name}; data: Uint8Array }`
+      : fields.length === 0 ? `{ type: ${name} }` : `{ type: ${name}; value: ${ident} }`;
+    out.push(`  | ${shape}`);
+  }
+  out[out.length - 1] += ";";
+
+  for (const { variant, ident, fields } of plans) {
+    const name = JSON.stringify(variant.name);
+    const [params, args, encoded] = fields === undefined
+      ? ["data?: Uint8Array", "data", `${codecName}.encode(${name}, data)`]
+      : fields.length === 0
+        ? ["", "", `${codecName}.encode(${name})`]
+        : [`value: ${ident}`, "value", `${codecName}.encode(${name}, encodeStruct(${ident}Schema, value as unknown as Record<string, unknown>))`];
+    const note = variant.deprecated ? "\n@deprecated" : "";
+    out.push(
+      "",
+      ...docComment(`Serialize a ${variant.name} packet${note}`),
+      `export function encode${ident}(${params}): Uint8Array {`,
+      `  return ${encoded};`,
+      "}",
+      "",
+      ...docComment(`Send a ${variant.name} packet${note}`),
+      `export function send${ident}(transport: PhaseTransport${params ? `, ${params}` : ""}): Promise<number> {`,
+      `  return transport.writePacket(new RawPacket(encode${ident}(${args})));`,
+      "}"
+    );
+  }
+
+  // Only destructure what the cases use, so the module passes noUnusedLocals
+  const destructured = ["type"];
+  if (withFields.length > 0) destructured.push("reader");
+  if (plans.some((plan) => plan.fields === undefined)) destructured.push("data");
+  out.push(
+    "",
+    `/** Decode a serialized packet; throws on an unknown variant */`,
+    `export function decode${typeName}(packet: Uint8Array): ${typeName} {`,
+    `  const { ${destructured.join(", ")} } = ${codecName}.decode(packet);`,
+    "  switch (type) {"
+  );
+  for (const { variant, ident, fields } of plans) {
+    const result = fields === undefined
+      ? "{ type, data }"
+      : fields.length === 0 ? "{ type }" : `{ type, value: decodeStruct(${ident}Schema, reader) as unknown as ${ident} }`;
+    out.push(`    case ${JSON.stringify(variant.name)}: return ${result};`);
+  }
+  out.push(
+    "  }",
+    "}",
+    "",
+    `/** Read and decode the next packet */`,
+    `export async function receive${typeName}(transport: PhaseTransport): Promise<${typeName}> {`,
+    `  return decode${typeName}((await transport.readPacket()) as unknown as Uint8Array);`,
+    "}",
+    ""
+  );
+  return out.join("\n");
+}
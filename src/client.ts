@@ -4,10 +4,15 @@
  */
 
 import { EventEmitter } from "events";
-import { createConnection, Socket } from "net";
+import { createConnection } from "net";
+import type { Duplex } from "stream";
 import { EncryptedStream, EncryptedReader, EncryptedWriter } from "./stream.js";
-import { ClavisError, StreamError } from "./error.js";
+import { ClavisError, CryptoError, CryptoOperation, StreamError } from "./error.js";
 import type { PacketTrait } from "./protocol.js";
+import type { SessionTicket } from "./resumption.js";
+import { systemClock, type Clock, type TimerHandle } from "./clock.js";
+import type { HostResolver } from "./resolver.js";
+import { Backoff, type BackoffJitter } from "./backoff.js";
 
 /**
  * Reconnection configuration
@@ -25,6 +30,13 @@ export interface ReconnectOptions {
   backoff?: "linear" | "exponential";
   /** Backoff multiplier for exponential (default: 2) */
   multiplier?: number;
+  /** Randomization of the delays, see `BackoffJitter` (default: 0.1, ±10%) */
+  jitter?: BackoffJitter;
+  /**
+   * Only start over from `initialDelayMs` once a connection has stayed up
+   * this long, in milliseconds (default: 0)
+   */
+  resetAfterMs?: number;
 }
 
 /**
@@ -37,14 +49,45 @@ export interface ClavisClientOptions {
   port: number;
   /** Pre-shared key for authentication (optional) */
   psk?: string | Uint8Array;
+  /**
+   * Pre-shared keys to try in order instead of `psk`, new first, while the
+   * server rotates its key. A handshake that fails authentication is tried
+   * again on a fresh connection with the next key; `pskIndex` tells which
+   * one the connection uses.
+   */
+  psks?: readonly (string | Uint8Array)[];
   /** Maximum packet size in bytes (default: 65536) */
   maxPacketSize?: number;
   /** Connection timeout in milliseconds (default: 10000) */
   connectTimeoutMs?: number;
   /** Reconnection options */
   reconnect?: ReconnectOptions;
+  /** Time source for connect timeouts and reconnect delays (default: `systemClock`) */
+  clock?: Clock;
+  /**
+   * Keep the session ticket the server issues and present it when
+   * reconnecting (default: false). The server must set `tickets`.
+   */
+  resumeSessions?: boolean;
+  /**
+   * Resolve `host` through this resolver on every connection attempt
+   * (default: none, the system resolver picks an address). Expired records
+   * are looked up again and attempts rotate among the addresses, so
+   * reconnects fail over to other replicas.
+   */
+  resolver?: HostResolver;
+  /**
+   * Open connections with this instead of TCP (default: a TCP socket).
+   * Lets a client run over a simulated network, see `SimNetwork.connector()`.
+   */
+  connector?: Connector;
 }
 
+/**
+ * Opens a byte stream to `host:port`; rejects if the connection can't be made
+ */
+export type Connector = (host: string, port: number) => Promise<Duplex>;
+
 /**
  * Connection status
  */
@@ -89,8 +132,24 @@ const DEFAULT_RECONNECT: Required<ReconnectOptions> = {
   maxDelayMs: 30000,
   backoff: "exponential",
   multiplier: 2,
+  jitter: 0.1,
+  resetAfterMs: 0,
 };
 
+/** The stream error behind `error`, unwrapping ClavisErrors thrown by the stream */
+function toStreamError(error: unknown): StreamError {
+  if (error instanceof StreamError) return error;
+  if (error instanceof ClavisError && error.cause instanceof StreamError) return error.cause;
+  return StreamError.io(error instanceof Error ? error : new Error(String(error)));
+}
+
+/** Whether `error` is a handshake refused for its key, which another key might pass */
+function isAuthenticationFailure(error: unknown): boolean {
+  return error instanceof ClavisError &&
+    error.cause instanceof CryptoError &&
+    error.cause.operation === CryptoOperation.Authentication;
+}
+
 /**
  * High-level Clavis client with automatic connection management.
  * 
@@ -117,23 +176,34 @@ const DEFAULT_RECONNECT: Required<ReconnectOptions> = {
 export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
   private options: ClavisClientOptions;
   private reconnectOptions: Required<ReconnectOptions>;
-  private socket: Socket | null = null;
+  private socket: Duplex | null = null;
   private stream: EncryptedStream | null = null;
+  private sessionTicket: SessionTicket | undefined;
   private reader: EncryptedReader | null = null;
   private writer: EncryptedWriter | null = null;
   private _status: ConnectionStatus = "disconnected";
   private readingPackets = false;
-  private reconnectAttempt = 0;
-  private reconnectTimer: ReturnType<typeof setTimeout> | null = null;
+  private backoff: Backoff;
+  private reconnectTimer: TimerHandle | null = null;
   private manualDisconnect = false;
+  private _pskIndex: number | undefined;
+  private readonly clock: Clock;
 
   constructor(options: ClavisClientOptions) {
     super();
+    if (options.psks !== undefined && (options.psk !== undefined || options.psks.length === 0)) {
+      throw ClavisError.config("Set either psk or a non-empty list of psks");
+    }
     this.options = options;
+    this.clock = options.clock ?? systemClock;
     this.reconnectOptions = {
       ...DEFAULT_RECONNECT,
       ...options.reconnect,
     };
+    this.backoff = new Backoff(
+      { ...this.reconnectOptions, maxAttempts: this.reconnectOptions.maxRetries + 1 },
+      this.clock
+    );
   }
 
   /** Current connection status */
@@ -146,6 +216,11 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
     return this._status === "connected";
   }
 
+  /** Index in `psks` of the key the last successful connection used */
+  get pskIndex(): number | undefined {
+    return this._pskIndex;
+  }
+
   /**
    * Connect to the server
    */
@@ -165,22 +240,16 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
     this.setStatus("connecting");
 
     try {
-      // Create TCP socket
-      this.socket = await this.createSocket();
-
-      // Create encrypted stream
-      this.stream = await EncryptedStream.new(this.socket, {
-        psk: this.options.psk,
-        ...(this.options.maxPacketSize !== undefined && { maxPacketSize: this.options.maxPacketSize }),
-      });
+      this.stream = await this.openStream();
+      this.sessionTicket = this.stream.sessionTicket;
 
       // Split into reader/writer
       const { reader, writer } = this.stream.split();
       this.reader = reader;
       this.writer = writer;
 
-      // Reset reconnect counter on successful connection
-      this.reconnectAttempt = 0;
+      // Reset reconnect backoff on successful connection (after resetAfterMs)
+      this.backoff.succeeded();
 
       this.setStatus("connected");
       this.emit("connect");
@@ -198,28 +267,65 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
   }
 
   /**
-   * Create and connect a TCP socket
+   * Connect and run the handshake, moving on to the next of `psks` while the
+   * server refuses the key
+   */
+  private async openStream(): Promise<EncryptedStream> {
+    const keys = this.options.psks ?? [this.options.psk];
+    for (let index = 0; ; index++) {
+      // Create TCP socket
+      const socket = await this.createSocket();
+      this.socket = socket;
+
+      // Create encrypted stream
+      try {
+        const stream = await EncryptedStream.new(socket, {
+          psk: keys[index],
+          ...(this.options.maxPacketSize !== undefined && { maxPacketSize: this.options.maxPacketSize }),
+          ...(this.options.resumeSessions && { resumption: this.sessionTicket ?? true }),
+        });
+        if (this.options.psks) this._pskIndex = index;
+        return stream;
+      } catch (error) {
+        if (index + 1 >= keys.length || !isAuthenticationFailure(error)) throw error;
+        // The server may hold the next key; drop this connection without reporting a disconnect
+        socket.removeAllListeners("close");
+        socket.destroy();
+        this.socket = null;
+      }
+    }
+  }
+
+  /**
+   * Create and connect a TCP socket, or a connection from the configured connector
    */
-  private createSocket(): Promise<Socket> {
+  private async createSocket(): Promise<Duplex> {
+    const host = this.options.resolver
+      ? (await this.options.resolver.next(this.options.host)).address
+      : this.options.host;
+    if (this.options.connector) {
+      return this.connectWith(this.options.connector, host);
+    }
+
     return new Promise((resolve, reject) => {
       const socket = createConnection({
-        host: this.options.host,
+        host,
         port: this.options.port,
       });
 
       const timeout = this.options.connectTimeoutMs ?? 10000;
-      const timer = setTimeout(() => {
+      const timer = this.clock.setTimer(() => {
         socket.destroy();
         reject(StreamError.timeout(timeout));
       }, timeout);
 
       socket.on("connect", () => {
-        clearTimeout(timer);
+        timer.cancel();
         resolve(socket);
       });
 
       socket.on("error", (error) => {
-        clearTimeout(timer);
+        timer.cancel();
         reject(StreamError.io(error));
       });
 
@@ -230,6 +336,38 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
     });
   }
 
+  /**
+   * Open a connection through a custom connector, with the same timeout as TCP
+   */
+  private connectWith(connector: Connector, host: string): Promise<Duplex> {
+    return new Promise((resolve, reject) => {
+      const timeout = this.options.connectTimeoutMs ?? 10000;
+      let timedOut = false;
+      const timer = this.clock.setTimer(() => {
+        timedOut = true;
+        reject(StreamError.timeout(timeout));
+      }, timeout);
+
+      connector(host, this.options.port).then(
+        (socket) => {
+          timer.cancel();
+          if (timedOut) {
+            socket.destroy();
+            return;
+          }
+          socket.on("close", () => {
+            this.handleDisconnect("Socket closed");
+          });
+          resolve(socket);
+        },
+        (error: unknown) => {
+          timer.cancel();
+          reject(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+        }
+      );
+    });
+  }
+
   /**
    * Start reading packets in a loop
    */
@@ -239,15 +377,15 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
 
     while (this.readingPackets && this.reader && this._status === "connected") {
       try {
-        const packet = await this.reader.readPacket<PacketTrait>();
-        // Emit raw packet data (packet is Uint8Array at this point)
-        this.emit("packet", packet as unknown as Uint8Array);
+        const packets = await this.reader.readPackets<PacketTrait>();
+        // Emit raw packet data (packets are Uint8Arrays at this point)
+        for (const packet of packets) {
+          this.emit("packet", packet as unknown as Uint8Array);
+        }
       } catch (error) {
         if (!this.readingPackets) break;
 
-        const streamError = error instanceof StreamError ? error : StreamError.io(
-          error instanceof Error ? error : new Error(String(error))
-        );
+        const streamError = toStreamError(error);
 
         // Check if this is a connection error
         if (streamError.isConnectionClosed()) {
@@ -255,8 +393,8 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
           break;
         }
 
-        // Emit non-fatal errors
-        this.emit("error", ClavisError.stream(streamError));
+        // Emit non-fatal errors, keeping the stream's connection context
+        this.emit("error", error instanceof ClavisError ? error : ClavisError.stream(streamError));
       }
     }
 
@@ -283,20 +421,18 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
    * Schedule a reconnection attempt
    */
   private scheduleReconnect(): void {
-    if (this.reconnectAttempt >= this.reconnectOptions.maxRetries) {
+    const delay = this.backoff.next();
+    if (delay === undefined) {
       this.emit("error", ClavisError.stream(
         StreamError.connectionClosed("Max reconnection attempts reached")
       ));
       return;
     }
 
-    const delay = this.calculateReconnectDelay();
-    this.reconnectAttempt++;
-
     this.setStatus("reconnecting");
-    this.emit("reconnecting", this.reconnectAttempt, this.reconnectOptions.maxRetries);
+    this.emit("reconnecting", this.backoff.retries, this.reconnectOptions.maxRetries);
 
-    this.reconnectTimer = setTimeout(async () => {
+    this.reconnectTimer = this.clock.setTimer(async () => {
       try {
         await this._connect();
       } catch {
@@ -305,49 +441,28 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
           this.scheduleReconnect();
         }
       }
-    }, delay);
+    }, Math.round(delay));
   }
 
   /**
-   * Calculate reconnection delay with backoff
+   * Send a packet.
+   * Resolves with the number of bytes written, frame overhead included.
    */
-  private calculateReconnectDelay(): number {
-    const { initialDelayMs, maxDelayMs, backoff, multiplier } = this.reconnectOptions;
-
-    let delay: number;
-    if (backoff === "exponential") {
-      delay = initialDelayMs * Math.pow(multiplier, this.reconnectAttempt);
-    } else {
-      delay = initialDelayMs * (this.reconnectAttempt + 1);
-    }
-
-    // Add jitter (±10%)
-    const jitter = delay * 0.1 * (Math.random() * 2 - 1);
-    delay = Math.min(delay + jitter, maxDelayMs);
-
-    return Math.round(delay);
-  }
-
-  /**
-   * Send a packet
-   */
-  async send(packet: PacketTrait): Promise<void> {
+  async send(packet: PacketTrait): Promise<number> {
     if (!this.writer || this._status !== "connected") {
       throw ClavisError.stream(StreamError.invalidOperation("Not connected"));
     }
 
     try {
-      await this.writer.writePacket(packet);
+      return await this.writer.writePacket(packet);
     } catch (error) {
-      const streamError = error instanceof StreamError ? error : StreamError.io(
-        error instanceof Error ? error : new Error(String(error))
-      );
+      const streamError = toStreamError(error);
       
       if (streamError.isConnectionClosed()) {
         this.handleDisconnect(streamError.message);
       }
       
-      throw ClavisError.stream(streamError);
+      throw error instanceof ClavisError ? error : ClavisError.stream(streamError);
     }
   }
 
@@ -367,7 +482,7 @@ export class ClavisClient extends EventEmitter implements ClavisClientEmitter {
    */
   private cancelReconnect(): void {
     if (this.reconnectTimer) {
-      clearTimeout(this.reconnectTimer);
+      this.reconnectTimer.cancel();
       this.reconnectTimer = null;
     }
   }
@@ -0,0 +1,147 @@
+/**
+ * Time source
+ * Abstraction over timers so timeouts and backoff can be driven by a manual
+ * clock in tests instead of waiting in real time
+ */
+
+/**
+ * Handle to a scheduled timer
+ */
+export interface TimerHandle {
+  /** Cancel the timer; does nothing if it already fired */
+  cancel(): void;
+}
+
+/**
+ * Source of time and timers
+ */
+export interface Clock {
+  /** Current time in milliseconds */
+  now(): number;
+  /** Run `callback` once after `delayMs` milliseconds */
+  setTimer(callback: () => void, delayMs: number): TimerHandle;
+}
+
+/**
+ * Clock backed by the runtime's `setTimeout` and `performance.now()`
+ */
+export const systemClock: Clock = {
+  now: () => performance.now(),
+  setTimer(callback, delayMs) {
+    const timer = setTimeout(callback, delayMs);
+    return { cancel: () => clearTimeout(timer) };
+  },
+};
+
+/**
+ * Clock whose `now()` is milliseconds since the epoch, for timestamps that
+ * must agree across machines (ticket expiry, key schedules)
+ */
+export const wallClock: Clock = {
+  now: () => Date.now(),
+  setTimer: systemClock.setTimer,
+};
+
+/**
+ * Resolve after `delayMs` milliseconds on `clock`
+ */
+export function sleepOn(clock: Clock, delayMs: number): Promise<void> {
+  return new Promise((resolve) => {
+    clock.setTimer(resolve, delayMs);
+  });
+}
+
+interface ManualTimer {
+  id: number;
+  dueAt: number;
+  callback: () => void;
+}
+
+/**
+ * Clock that only moves when told to.
+ *
+ * @example
+ * ```typescript
+ * const clock = new ManualClock();
+ * const rpc = new RpcConnection(reader, writer, { codec, clock });
+ * const call = rpc.call("GetStatus", undefined, { timeoutMs: 30_000 });
+ * await clock.advance(30_000); // call rejects with a timeout immediately
+ * ```
+ */
+export class ManualClock implements Clock {
+  private current: number;
+  private timers: ManualTimer[] = [];
+  private nextId = 0;
+
+  constructor(start = 0) {
+    this.current = start;
+  }
+
+  now(): number {
+    return this.current;
+  }
+
+  setTimer(callback: () => void, delayMs: number): TimerHandle {
+    const timer: ManualTimer = {
+      id: this.nextId++,
+      dueAt: this.current + Math.max(0, delayMs),
+      callback,
+    };
+    this.timers.push(timer);
+    return {
+      cancel: () => {
+        this.timers = this.timers.filter((t) => t !== timer);
+      },
+    };
+  }
+
+  /** Number of timers that have not fired or been cancelled */
+  get pendingTimers(): number {
+    return this.timers.length;
+  }
+
+  /**
+   * Move time forward by `ms`, firing due timers in order.
+   * Pending promise callbacks run after each timer, so timers scheduled in
+   * reaction to an earlier one fire within the same advance if they are due.
+   */
+  async advance(ms: number): Promise<void> {
+    const target = this.current + ms;
+    for (;;) {
+      await settle();
+      const next = this.earliestDue(target);
+      if (!next) break;
+      this.timers = this.timers.filter((t) => t !== next);
+      this.current = next.dueAt;
+      next.callback();
+    }
+    this.current = target;
+    await settle();
+  }
+
+  /** Fire every pending timer, however far in the future */
+  async runAll(): Promise<void> {
+    for (;;) {
+      await settle();
+      const last = this.timers.reduce((max, t) => Math.max(max, t.dueAt), this.current);
+      if (this.timers.length === 0) break;
+      await this.advance(last - this.current);
+    }
+  }
+
+  private earliestDue(target: number): ManualTimer | undefined {
+    let earliest: ManualTimer | undefined;
+    for (const timer of this.timers) {
+      if (timer.dueAt > target) continue;
+      if (!earliest || timer.dueAt < earliest.dueAt || (timer.dueAt === earliest.dueAt && timer.id < earliest.id)) {
+        earliest = timer;
+      }
+    }
+    return earliest;
+  }
+}
+
+/** Let queued promise callbacks and I/O callbacks run */
+function settle(): Promise<void> {
+  return new Promise((resolve) => setImmediate(resolve));
+}
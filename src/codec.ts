@@ -0,0 +1,227 @@
+/**
+ * Record layer codec
+ * The record layer of an established connection as an encoder and a
+ * decoder, for applications that build transports out of stacked codecs
+ *
+ * `ClavisCodec` seals packets into frames and opens frames back into
+ * packets with the keys of a finished handshake, and does nothing else: no
+ * I/O, no handshake, no timers. `encoder()` and `decoder()` wrap it as web
+ * stream transforms, so it slots into a `pipeThrough()` chain next to
+ * length prefixes, compression or a serialization codec, the way a codec
+ * slots into a framed pipeline. `clavisFramed()` runs the handshake over a
+ * pair of web streams and returns the pipeline's two ends.
+ *
+ * The frames are those of a stream with default options, so the peer can be
+ * an `EncryptedStream`, a `ClavisConnection` or another codec. Like
+ * `ClavisConnection`, the codec leaves the stream's optional features to
+ * `EncryptedStream`. It skips pings rather than answer them, so the peer
+ * should leave `keepAliveMs` off.
+ */
+
+import { XChaCha20Poly1305Cipher, type FrameCipher } from "./crypto.js";
+import { ClavisError, MessageError, StreamError } from "./error.js";
+import { HandshakeMachine, type HandshakeResult, type TimedHandshakeResult } from "./handshake.js";
+import { FRAME_TAG_LENGTH, FrameDecoder, openFrame, sealFrame } from "./frame.js";
+import { ControlFrameKind, decodeControlFrame, encodeControlFrame } from "./control.js";
+import type { ClavisConnectionOptions } from "./connection.js";
+import type { PacketTrait } from "./protocol.js";
+import { systemClock } from "./clock.js";
+
+/** Default largest packet, as for streams */
+const DEFAULT_MAX_PACKET_SIZE = 65536;
+
+/**
+ * Options for `ClavisCodec`
+ */
+export interface ClavisCodecOptions {
+  /** Largest packet encoded or accepted, in bytes (default: 65536) */
+  maxPacketSize?: number | undefined;
+}
+
+/**
+ * Seals packets into frames and opens frames into packets, with the keys
+ * of a finished handshake
+ *
+ * @example
+ * ```typescript
+ * const codec = new ClavisCodec(handshakeResult);
+ * socket.readable.pipeThrough(codec.decoder()).pipeThrough(jsonLines()).pipeTo(handler);
+ * ```
+ */
+export class ClavisCodec {
+  private readonly cipher: FrameCipher;
+  private readonly decipher: FrameCipher;
+  private readonly frames: FrameDecoder;
+  private readonly maxPacketSize: number;
+  private writeClosed = false;
+  private peerClosed = false;
+
+  constructor(keys: Pick<HandshakeResult, "encKey" | "decKey">, options: ClavisCodecOptions = {}) {
+    const maxPacketSize = options.maxPacketSize ?? DEFAULT_MAX_PACKET_SIZE;
+    if (!Number.isInteger(maxPacketSize) || maxPacketSize < 1) {
+      throw ClavisError.config(`maxPacketSize must be a positive integer, got ${maxPacketSize}`);
+    }
+    this.maxPacketSize = maxPacketSize;
+    this.frames = new FrameDecoder(maxPacketSize + FRAME_TAG_LENGTH);
+    this.cipher = new XChaCha20Poly1305Cipher(keys.encKey);
+    this.decipher = new XChaCha20Poly1305Cipher(keys.decKey);
+  }
+
+  /** Whether the peer's close frame arrived; nothing more will be decoded */
+  get closed(): boolean {
+    return this.peerClosed;
+  }
+
+  /** Seal one packet, or already serialized bytes, into a frame */
+  encode(packet: PacketTrait | Uint8Array): Uint8Array {
+    if (this.writeClosed) {
+      throw ClavisError.invalidOperation("Cannot encode after the close frame");
+    }
+    const plaintext = packet instanceof Uint8Array ? packet : packet.serialize();
+    if (plaintext.length > this.maxPacketSize) {
+      const variant = packet instanceof Uint8Array ? undefined : packet.variantName;
+      throw ClavisError.message(MessageError.packetTooLarge(plaintext.length, this.maxPacketSize, variant));
+    }
+    return sealFrame(this.cipher, plaintext);
+  }
+
+  /** The close frame, telling the peer nothing follows; nothing can be encoded after it */
+  encodeClose(): Uint8Array {
+    const frame = sealFrame(this.cipher, encodeControlFrame(ControlFrameKind.Close, new Uint8Array(0)), true);
+    this.writeClosed = true;
+    return frame;
+  }
+
+  /** Append bytes received from the peer, in chunks of any size */
+  push(chunk: Uint8Array): void {
+    if (this.peerClosed && chunk.length > 0) {
+      throw ClavisError.message(MessageError.invalidFormat("Data after the peer's close frame"));
+    }
+    this.frames.push(chunk);
+  }
+
+  /**
+   * Next packet, or undefined until more bytes arrive. Throws if a frame
+   * doesn't authenticate or parse.
+   */
+  decode(): Uint8Array | undefined {
+    for (let frame = this.frames.next(); frame; frame = this.frames.next()) {
+      const plaintext = openFrame(this.decipher, frame);
+      if (!frame.control) return plaintext;
+      const control = decodeControlFrame(plaintext);
+      if (control.kind === ControlFrameKind.Close) {
+        this.peerClosed = true;
+        if (this.frames.buffered > 0) {
+          throw ClavisError.message(MessageError.invalidFormat("Data after the peer's close frame"));
+        }
+        return undefined;
+      }
+      if (control.kind !== ControlFrameKind.Ping && control.kind !== ControlFrameKind.Pong) {
+        throw ClavisError.message(
+          MessageError.invalidFormat(`Control frame ${ControlFrameKind[control.kind] ?? control.kind} is not supported by ClavisCodec`)
+        );
+      }
+    }
+    return undefined;
+  }
+
+  /** Packets in, frames out; closing it writes the close frame */
+  encoder(): TransformStream<PacketTrait | Uint8Array, Uint8Array> {
+    return new TransformStream({
+      transform: (packet, controller) => controller.enqueue(this.encode(packet)),
+      flush: (controller) => {
+        if (!this.writeClosed) controller.enqueue(this.encodeClose());
+      },
+    });
+  }
+
+  /** Bytes in, packets out; ends at the peer's close frame */
+  decoder(): TransformStream<Uint8Array, Uint8Array> {
+    const drain = (controller: TransformStreamDefaultController<Uint8Array>) => {
+      for (let packet = this.decode(); packet; packet = this.decode()) controller.enqueue(packet);
+      if (this.peerClosed) controller.terminate();
+    };
+    return new TransformStream({
+      // Bytes pushed before the transform existed, such as those that followed the handshake
+      start: drain,
+      transform: (chunk, controller) => {
+        this.push(chunk);
+        drain(controller);
+      },
+      flush: () => {
+        if (this.frames.buffered > 0) {
+          throw ClavisError.stream(StreamError.connectionClosed("Transport ended in the middle of a frame"));
+        }
+      },
+    });
+  }
+}
+
+/**
+ * The two ends of a pipeline over an established connection
+ */
+export interface ClavisFramed {
+  /** Packets from the peer, until its close frame */
+  readable: ReadableStream<Uint8Array>;
+  /** Packets for the peer; closing it sends the close frame */
+  writable: WritableStream<PacketTrait | Uint8Array>;
+  /** Keys, role and timings of the handshake */
+  handshake: TimedHandshakeResult;
+}
+
+/**
+ * Run the handshake over a pair of web streams, then carry packets over
+ * them with a `ClavisCodec`
+ *
+ * @example
+ * ```typescript
+ * const { readable, writable } = await clavisFramed(await transport.createBidirectionalStream(), { psk });
+ * for await (const message of readable.pipeThrough(new TextDecoderStream())) console.log(message);
+ * ```
+ */
+export async function clavisFramed(
+  transport: { readable: ReadableStream<Uint8Array>; writable: WritableStream<Uint8Array> },
+  options: ClavisConnectionOptions = {}
+): Promise<ClavisFramed> {
+  const machine = new HandshakeMachine(options.psk, options.clock ?? systemClock, options.keyExchange);
+  const reader = transport.readable.getReader();
+  const writer = transport.writable.getWriter();
+  let rest = new Uint8Array(0);
+  try {
+    await writer.write(machine.takeOutput());
+    while (!machine.done) {
+      const { value, done } = await reader.read();
+      if (done) {
+        throw ClavisError.stream(StreamError.connectionClosed("Transport ended during the handshake"));
+      }
+      let used = 0;
+      let failure: unknown;
+      try {
+        used = machine.feedBytes(value);
+      } catch (error) {
+        failure = error;
+      }
+      // The responder's MAC goes out even when the initiator's fails to verify
+      const output = machine.takeOutput();
+      if (output.length > 0) await writer.write(output);
+      if (failure !== undefined) throw failure;
+      if (machine.done) rest = value.subarray(used);
+    }
+  } catch (error) {
+    reader.cancel().catch(() => undefined);
+    writer.abort().catch(() => undefined);
+    throw error;
+  }
+  reader.releaseLock();
+  writer.releaseLock();
+
+  const codec = new ClavisCodec(machine.result(), options);
+  codec.push(rest);
+  const encoder = codec.encoder();
+  encoder.readable.pipeTo(transport.writable).catch(() => undefined);
+  return {
+    readable: transport.readable.pipeThrough(codec.decoder()),
+    writable: encoder.writable,
+    handshake: machine.result(),
+  };
+}
@@ -0,0 +1,361 @@
+/**
+ * Packet compression with shared dictionaries
+ * Small packets compress poorly on their own; a dictionary built offline from
+ * sample traffic gives the compressor common strings to refer back to.
+ *
+ * Compression is opt-in on both sides. Right after the key exchange each side
+ * sends an encrypted offer listing the dictionaries it has, identified by hash,
+ * and both pick the same one. An offer can also list general-purpose
+ * algorithms for when there's no shared dictionary; each side then sends with
+ * the first of its own algorithms that the peer offered too. Every packet
+ * carries a one-byte method prefix inside the ciphertext:
+ * - 0: stored as is
+ * - 1: raw DEFLATE with the negotiated dictionary
+ * - 2: zstd
+ * - 3: raw DEFLATE without a dictionary
+ *
+ * zstd is not available on every supported runtime, so dictionaries use
+ * DEFLATE's preset dictionary support, and zstd is only offered where the
+ * runtime's zlib has it.
+ */
+
+import * as zlib from "zlib";
+import { deflateRawSync, inflateRawSync } from "zlib";
+import { ClavisError, DecompressionLimit, MessageError, StreamError } from "./error.js";
+import { sha256Hash } from "./crypto.js";
+import { redactLike } from "./audit.js";
+import type { DecodeBudget } from "./bincode.js";
+
+/**
+ * A shared compression dictionary
+ */
+export interface CompressionDictionary {
+  /** Hex identifier derived from the dictionary's SHA-256 */
+  readonly id: string;
+  readonly bytes: Uint8Array;
+}
+
+/**
+ * General-purpose algorithm for packets without a shared dictionary
+ */
+export type CompressionAlgorithm = "zstd" | "deflate";
+
+/**
+ * Compression settings for an encrypted stream
+ */
+export interface CompressionOptions {
+  /** Dictionaries this side can use, in no particular order */
+  dictionaries?: readonly CompressionDictionary[] | undefined;
+  /**
+   * Algorithms to use when the peers share no dictionary, most preferred
+   * first (default: none, so such packets are sent stored). Ones the runtime
+   * lacks are left out of the offer; see `supportedCompressionAlgorithms()`.
+   */
+  algorithms?: readonly CompressionAlgorithm[] | undefined;
+  /** Packets smaller than this are sent stored (default: 32) */
+  minSize?: number | undefined;
+  /** DEFLATE level 1-9; zstd uses its own default (default: 6) */
+  level?: number | undefined;
+  /**
+   * Largest packet a compressed packet may inflate to, whatever
+   * `maxPacketSize` allows (default: 16 MiB)
+   */
+  maxDecompressedSize?: number | undefined;
+  /**
+   * Largest ratio of inflated to compressed size (default: 256). Packets
+   * inflating to 64 KiB or less are never refused for their ratio, so small
+   * packets that compress well against a dictionary still get through.
+   */
+  maxRatio?: number | undefined;
+}
+
+/** Bytes of the SHA-256 used as a dictionary identifier */
+const DICTIONARY_ID_LENGTH = 8;
+/** DEFLATE only looks back 32 KiB, so a longer dictionary is wasted */
+const MAX_DICTIONARY_SIZE = 32 * 1024;
+const OFFER_MAGIC = [0x43, 0x4c, 0x56, 0x5a]; // "CLVZ"
+const OFFER_VERSION = 1;
+/** Offers listing algorithms; sent only when there are some, so older peers keep working otherwise */
+const OFFER_VERSION_ALGORITHMS = 2;
+
+const METHOD_STORED = 0;
+const METHOD_DEFLATE = 1;
+const METHOD_ZSTD = 2;
+const METHOD_DEFLATE_PLAIN = 3;
+
+const DEFAULT_MAX_DECOMPRESSED_SIZE = 16 * 1024 * 1024;
+const DEFAULT_MAX_RATIO = 256;
+/** Inflated size below which `maxRatio` doesn't apply */
+const RATIO_FLOOR = 64 * 1024;
+
+const ALGORITHM_METHODS: Record<CompressionAlgorithm, number> = { zstd: METHOD_ZSTD, deflate: METHOD_DEFLATE_PLAIN };
+
+interface ZstdBindings {
+  zstdCompressSync(data: Uint8Array): Uint8Array;
+  zstdDecompressSync(data: Uint8Array, options: { maxOutputLength: number }): Uint8Array;
+}
+
+/** zlib's zstd functions, on runtimes that have them (Node 22.15+, recent Bun) */
+const zstd: ZstdBindings | undefined = typeof (zlib as unknown as Partial<ZstdBindings>).zstdCompressSync === "function"
+  ? (zlib as unknown as ZstdBindings)
+  : undefined;
+
+/**
+ * Algorithms this runtime can compress and decompress
+ */
+export function supportedCompressionAlgorithms(): CompressionAlgorithm[] {
+  return zstd ? ["zstd", "deflate"] : ["deflate"];
+}
+
+function toHex(bytes: Uint8Array): string {
+  return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+function fromHex(hex: string): Uint8Array {
+  const bytes = new Uint8Array(hex.length / 2);
+  for (let i = 0; i < bytes.length; i++) {
+    bytes[i] = parseInt(hex.slice(i * 2, i * 2 + 2), 16);
+  }
+  return bytes;
+}
+
+/**
+ * Wrap existing dictionary bytes
+ */
+export function createDictionary(bytes: Uint8Array): CompressionDictionary {
+  if (bytes.length === 0 || bytes.length > MAX_DICTIONARY_SIZE) {
+    throw ClavisError.config(`Compression dictionaries must be 1 to ${MAX_DICTIONARY_SIZE} bytes`);
+  }
+  return { id: toHex(sha256Hash(bytes).subarray(0, DICTIONARY_ID_LENGTH)), bytes };
+}
+
+/**
+ * Build a dictionary from sample packets.
+ * Duplicate samples are dropped and the most frequent ones are placed last,
+ * where DEFLATE matches them with the shortest distances.
+ */
+export function buildDictionary(samples: Iterable<Uint8Array>, maxSize: number = 16 * 1024): CompressionDictionary {
+  const counts = new Map<string, { sample: Uint8Array; count: number }>();
+  for (const sample of samples) {
+    const key = toHex(sample);
+    const entry = counts.get(key);
+    if (entry) {
+      entry.count++;
+    } else {
+      counts.set(key, { sample, count: 1 });
+    }
+  }
+
+  const ordered = [...counts.values()].sort((a, b) => a.count - b.count);
+  const limit = Math.min(maxSize, MAX_DICTIONARY_SIZE);
+  const picked: Uint8Array[] = [];
+  let size = 0;
+  for (let i = ordered.length - 1; i >= 0 && size < limit; i--) {
+    const sample = ordered[i]!.sample.subarray(0, limit - size);
+    picked.unshift(sample);
+    size += sample.length;
+  }
+
+  const bytes = new Uint8Array(size);
+  let offset = 0;
+  for (const sample of picked) {
+    bytes.set(sample, offset);
+    offset += sample.length;
+  }
+  return createDictionary(bytes);
+}
+
+/**
+ * Encode this side's compression offer
+ */
+export function encodeCompressionOffer(options: CompressionOptions): Uint8Array {
+  const dictionaries = options.dictionaries ?? [];
+  if (dictionaries.length > 255) {
+    throw ClavisError.config("At most 255 compression dictionaries can be offered");
+  }
+  const algorithms = usableAlgorithms(options);
+  const idsEnd = 6 + dictionaries.length * DICTIONARY_ID_LENGTH;
+  const offer = new Uint8Array(idsEnd + (algorithms.length > 0 ? 1 + algorithms.length : 0));
+  offer.set(OFFER_MAGIC, 0);
+  offer[4] = algorithms.length > 0 ? OFFER_VERSION_ALGORITHMS : OFFER_VERSION;
+  offer[5] = dictionaries.length;
+  dictionaries.forEach((dictionary, i) => {
+    offer.set(fromHex(dictionary.id), 6 + i * DICTIONARY_ID_LENGTH);
+  });
+  if (algorithms.length > 0) {
+    offer[idsEnd] = algorithms.length;
+    algorithms.forEach((algorithm, i) => {
+      offer[idsEnd + 1 + i] = ALGORITHM_METHODS[algorithm];
+    });
+  }
+  return offer;
+}
+
+/** This side's algorithms that the runtime has, without duplicates */
+function usableAlgorithms(options: CompressionOptions): CompressionAlgorithm[] {
+  const supported = supportedCompressionAlgorithms();
+  return [...new Set(options.algorithms ?? [])].filter((algorithm) => supported.includes(algorithm));
+}
+
+/**
+ * Decode a peer's compression offer into dictionary ids
+ */
+export function decodeCompressionOffer(offer: Uint8Array): string[] {
+  const magic = OFFER_MAGIC.every((b, i) => offer[i] === b);
+  if (!magic || (offer[4] !== OFFER_VERSION && offer[4] !== OFFER_VERSION_ALGORITHMS)) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Peer did not negotiate compression"));
+  }
+  const count = offer[5] ?? 0;
+  const idsEnd = 6 + count * DICTIONARY_ID_LENGTH;
+  const expected = offer[4] === OFFER_VERSION ? idsEnd : idsEnd + 1 + (offer[idsEnd] ?? 0);
+  if (offer.length !== expected) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Malformed compression offer"));
+  }
+  const ids: string[] = [];
+  for (let i = 0; i < count; i++) {
+    const start = 6 + i * DICTIONARY_ID_LENGTH;
+    ids.push(toHex(offer.subarray(start, start + DICTIONARY_ID_LENGTH)));
+  }
+  return ids;
+}
+
+/**
+ * Decode the algorithms listed in a peer's compression offer, skipping ones
+ * this side doesn't know
+ */
+export function decodeCompressionAlgorithms(offer: Uint8Array): CompressionAlgorithm[] {
+  decodeCompressionOffer(offer);
+  if (offer[4] === OFFER_VERSION) return [];
+  const start = 6 + (offer[5] ?? 0) * DICTIONARY_ID_LENGTH + 1;
+  const algorithms: CompressionAlgorithm[] = [];
+  for (const code of offer.subarray(start)) {
+    const algorithm = (Object.keys(ALGORITHM_METHODS) as CompressionAlgorithm[])
+      .find((name) => ALGORITHM_METHODS[name] === code);
+    if (algorithm) algorithms.push(algorithm);
+  }
+  return algorithms;
+}
+
+/**
+ * Per-connection packet compressor, created once both offers are known
+ */
+export class PacketCompressor {
+  /** Most bytes compression adds to a packet: the method byte of a stored one */
+  readonly overhead = 1;
+  private readonly minSize: number;
+  private readonly level: number;
+  private readonly maxDecompressedSize: number;
+  private readonly maxRatio: number;
+  /** Algorithms this side offered, and so accepts */
+  private readonly accepted: readonly CompressionAlgorithm[];
+
+  private constructor(
+    /** Negotiated dictionary, or undefined when the peers share none */
+    readonly dictionary: CompressionDictionary | undefined,
+    /** Algorithm this side sends with when there's no dictionary, if any */
+    readonly algorithm: CompressionAlgorithm | undefined,
+    options: CompressionOptions
+  ) {
+    this.minSize = options.minSize ?? 32;
+    this.level = options.level ?? 6;
+    this.maxDecompressedSize = options.maxDecompressedSize ?? DEFAULT_MAX_DECOMPRESSED_SIZE;
+    this.maxRatio = options.maxRatio ?? DEFAULT_MAX_RATIO;
+    if (!(this.maxDecompressedSize > 0) || !(this.maxRatio >= 1)) {
+      throw ClavisError.config("maxDecompressedSize must be positive and maxRatio at least 1");
+    }
+    this.accepted = usableAlgorithms(options);
+  }
+
+  /**
+   * Pick the dictionary both sides have. Both peers run this on the same two
+   * id lists, so the smallest shared id is a choice they agree on. Without
+   * one, send with our first algorithm the peer offered as well.
+   */
+  static negotiate(
+    options: CompressionOptions,
+    peerIds: readonly string[],
+    peerAlgorithms: readonly CompressionAlgorithm[] = []
+  ): PacketCompressor {
+    const peer = new Set(peerIds);
+    const shared = (options.dictionaries ?? [])
+      .filter((dictionary) => peer.has(dictionary.id))
+      .sort((a, b) => (a.id < b.id ? -1 : a.id > b.id ? 1 : 0));
+    const algorithm = usableAlgorithms(options).find((candidate) => peerAlgorithms.includes(candidate));
+    return new PacketCompressor(shared[0], algorithm, options);
+  }
+
+  /** Method prefix plus either the stored or compressed packet */
+  compress(plaintext: Uint8Array): Uint8Array {
+    if (plaintext.length >= this.minSize) {
+      let method = METHOD_STORED;
+      let compressed: Uint8Array | undefined;
+      if (this.dictionary) {
+        method = METHOD_DEFLATE;
+        compressed = deflateRawSync(plaintext, { dictionary: this.dictionary.bytes, level: this.level });
+      } else if (this.algorithm === "zstd" && zstd) {
+        method = METHOD_ZSTD;
+        compressed = zstd.zstdCompressSync(plaintext);
+      } else if (this.algorithm === "deflate") {
+        method = METHOD_DEFLATE_PLAIN;
+        compressed = deflateRawSync(plaintext, { level: this.level });
+      }
+      if (compressed && compressed.length < plaintext.length) {
+        return prefixed(method, compressed);
+      }
+    }
+    return prefixed(METHOD_STORED, plaintext);
+  }
+
+  /**
+   * Undo `compress`, refusing output longer than `maxLength`, than
+   * `maxDecompressedSize` or than `maxRatio` times the compressed body.
+   * Inflating stops at the limit, so a bomb never expands in memory.
+   * zlib inflates a packet in one call, which those limits keep short; a
+   * `budget` is checked once it returns, so a packet that spent its budget
+   * inflating is refused before anything decodes it.
+   */
+  decompress(data: Uint8Array, maxLength: number, budget?: DecodeBudget): Uint8Array {
+    const method = data[0];
+    const body = data.subarray(1);
+    if (method === METHOD_STORED) {
+      return redactLike(body, data);
+    }
+    const ratioLimit = Math.max(body.length * this.maxRatio, RATIO_FLOOR);
+    const limit = Math.min(maxLength, this.maxDecompressedSize, ratioLimit);
+    const inflate = (run: (maxOutputLength: number) => Uint8Array): Uint8Array => {
+      let inflated: Uint8Array;
+      try {
+        inflated = run(limit);
+      } catch (error) {
+        // zlib throws a RangeError once the output would pass maxOutputLength
+        const tooLarge = error instanceof RangeError || (error as { code?: string }).code === "ERR_BUFFER_TOO_LARGE";
+        if (tooLarge && limit < maxLength) {
+          const broken = limit === this.maxDecompressedSize ? DecompressionLimit.Size : DecompressionLimit.Ratio;
+          throw ClavisError.message(MessageError.decompressionLimitExceeded(broken, body.length, limit));
+        }
+        throw ClavisError.message(MessageError.invalidFormat("Compressed packet is corrupt or too large"));
+      }
+      budget?.checkNow();
+      return redactLike(new Uint8Array(inflated), data);
+    };
+    if (method === METHOD_DEFLATE && this.dictionary) {
+      const dictionary = this.dictionary.bytes;
+      return inflate((maxOutputLength) => inflateRawSync(body, { dictionary, maxOutputLength }));
+    }
+    if (method === METHOD_ZSTD && zstd && this.accepted.includes("zstd")) {
+      const bindings = zstd;
+      return inflate((maxOutputLength) => bindings.zstdDecompressSync(body, { maxOutputLength }));
+    }
+    if (method === METHOD_DEFLATE_PLAIN && this.accepted.includes("deflate")) {
+      return inflate((maxOutputLength) => inflateRawSync(body, { maxOutputLength }));
+    }
+    throw ClavisError.message(MessageError.invalidFormat(`Unknown compression method ${method}`));
+  }
+}
+
+function prefixed(method: number, body: Uint8Array): Uint8Array {
+  const out = new Uint8Array(1 + body.length);
+  out[0] = method;
+  out.set(body, 1);
+  return out;
+}
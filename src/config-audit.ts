@@ -0,0 +1,236 @@
+/**
+ * Configuration audit
+ * A structured report of the security posture a set of stream options
+ * produces, for servers to log at startup and compliance tooling to parse
+ *
+ * The report holds what the options mean rather than what they contain:
+ * which cipher and authentication are in effect, the limits with their
+ * defaults filled in, which features are on, and findings for settings
+ * that weaken the connection. No keys, callbacks or certificates end up in
+ * it, so it is safe to log and serializes to JSON as is. Limits that are
+ * off are `null` rather than missing, so every field is always present.
+ */
+
+import { checkStreamOptions, DEFAULT_MAX_PACKET_SIZE, type EncryptedStreamOptions } from "./stream.js";
+import { isAuditMode } from "./audit.js";
+import type { PostQuantumMode } from "./hybrid.js";
+import type { DecodeLimits } from "./bincode.js";
+
+/** How bad a finding is: `danger` breaks confidentiality or authentication, `warning` weakens it */
+export type AuditSeverity = "danger" | "warning";
+
+/**
+ * One setting, or combination of settings, that weakens the connection
+ */
+export interface AuditFinding {
+  severity: AuditSeverity;
+  /** Options involved; empty when the finding is about something left unset as a whole */
+  options: string[];
+  message: string;
+}
+
+/**
+ * The security posture of a set of stream options
+ */
+export interface ConfigAuditReport {
+  cipher: {
+    /** Suites this side accepts, most preferred first */
+    suites: string[];
+    /** Whether the suite is negotiated with the peer */
+    negotiated: boolean;
+    /** `dangerousNullCipher`: frames are neither encrypted nor authenticated */
+    nullCipher: boolean;
+    postQuantum: PostQuantumMode | "off";
+    /** Where the handshake's ephemeral keys live */
+    keyExchange: "in-memory" | "provider";
+  };
+  authentication: {
+    /** `none` leaves the handshake open to an active attacker unless identities are verified */
+    mode: "none" | "psk" | "identity" | "psk+identity";
+    psk: "none" | "single" | "keyring";
+    pskIdentity: string | null;
+    /** Credentials this side presents */
+    identity: "none" | "static-key" | "certificate" | "x509";
+    /** Whether the identity's private key is held by a signer outside the process */
+    identityKeyExternal: boolean;
+    /** How this side checks the peer's identity */
+    peerVerification: Array<"signers" | "roots" | "callback">;
+    revocationCheck: boolean;
+    /** Accepts session tickets, or resumes an earlier session */
+    resumption: boolean;
+    /** Issues session tickets to the peer */
+    issuesTickets: boolean;
+  };
+  limits: {
+    maxPacketSize: number;
+    maxNegotiablePacketSize: number;
+    maxPacketsPerSecond: number | null;
+    packetBurst: number | null;
+    handshakeTimeoutMs: number | null;
+    readTimeoutMs: number | null;
+    writeTimeoutMs: number | null;
+    idleTimeoutMs: number | null;
+    keepAliveMs: number | null;
+    decodeBudgetMs: number | null;
+    rekeyAfterBytes: number | null;
+    rekeyAfterMs: number | null;
+    decodeLimits: Omit<DecodeLimits, "budget"> | null;
+  };
+  /** Optional features, each on or off */
+  features: {
+    compression: boolean;
+    padding: boolean;
+    coverTraffic: boolean;
+    negotiatePacketSize: boolean;
+    sequenceNumbers: boolean;
+    protocolVersion: boolean;
+    format: boolean;
+    timeSync: boolean;
+    ultraLowLatency: boolean;
+    journal: boolean;
+    interceptors: boolean;
+    tracing: boolean;
+    tcpKeepAlive: boolean;
+    watchdog: boolean;
+  };
+  /** Options named `dangerous*` or only meant for debugging that are on */
+  dangerous: string[];
+  /** Whether audit mode keeps keys and payloads out of debug output in this process */
+  redaction: boolean;
+  findings: AuditFinding[];
+}
+
+/**
+ * Report the security posture of `options`. Throws the configuration error
+ * `EncryptedStream.new` would for options that can't be used, so a report
+ * always describes a configuration that runs.
+ *
+ * @example
+ * ```typescript
+ * const report = auditStreamOptions(config.stream);
+ * log.info("clavis configuration", report);
+ * if (report.findings.some((f) => f.severity === "danger")) process.exit(1);
+ * ```
+ */
+export function auditStreamOptions(options?: EncryptedStreamOptions): ConfigAuditReport {
+  checkStreamOptions(options);
+  const o: EncryptedStreamOptions = options ?? {};
+  const findings: AuditFinding[] = [];
+  const find = (severity: AuditSeverity, names: string[], message: string) =>
+    findings.push({ severity, options: names, message });
+
+  const identity = o.identity === undefined
+    ? "none"
+    : "chain" in o.identity
+      ? "x509"
+      : "certificate" in o.identity
+        ? "certificate"
+        : "static-key";
+  const identityKeyExternal = o.identity !== undefined && (
+    "chain" in o.identity
+      ? typeof o.identity.privateKey === "object" && "sign" in o.identity.privateKey
+      : "signer" in o.identity
+  );
+  const peerVerification: ConfigAuditReport["authentication"]["peerVerification"] = [];
+  if (o.trustedSigners !== undefined) peerVerification.push("signers");
+  if (o.trustedRoots !== undefined) peerVerification.push("roots");
+  if (o.verifyPeer !== undefined) peerVerification.push("callback");
+
+  const psk = o.psks !== undefined ? "keyring" : o.psk ? "single" : "none";
+  const verified = peerVerification.length > 0;
+  const mode = psk === "none" ? (verified ? "identity" : "none") : verified ? "psk+identity" : "psk";
+  const suites = o.cipherSuites !== undefined
+    ? o.cipherSuites.map((suite) => (typeof suite === "string" ? suite : suite.name))
+    : ["xchacha20-poly1305"];
+
+  const dangerous: string[] = [];
+  if (o.dangerousNullCipher) {
+    dangerous.push("dangerousNullCipher");
+    find("danger", ["dangerousNullCipher"], "Frames are sent in the clear and not authenticated");
+  }
+  if (o.keyLog !== undefined) {
+    dangerous.push("keyLog");
+    find("danger", ["keyLog"], "Traffic keys are written to a key log, so anyone with the log can decrypt captures");
+  }
+  if (o.dangerousSessionHandoff) {
+    dangerous.push("dangerousSessionHandoff");
+    find("danger", ["dangerousSessionHandoff"], "Session keys can be exported, so anyone holding an exported state can decrypt and forge the connection's traffic");
+  }
+  if (mode === "none") {
+    find("danger", [], "Peers are not authenticated: set psk, psks or a way to verify the peer's identity");
+  }
+  if (identity !== "none" && !verified) {
+    find("warning", ["identity"], "This side proves its identity but doesn't verify the peer's");
+  }
+  if (suites.includes("aes-256-gcm") && o.rekeyAfterBytes === undefined && o.rekeyAfterMs === undefined) {
+    find("warning", ["cipherSuites"], "AES-256-GCM uses random IVs; set rekeyAfterBytes or rekeyAfterMs for long-lived connections");
+  }
+  if (o.handshakeTimeoutMs === undefined) {
+    find("warning", ["handshakeTimeoutMs"], "A peer that stalls the handshake holds the connection open indefinitely");
+  }
+  if (o.maxNegotiablePacketSize !== undefined && o.maxNegotiablePacketSize > (o.maxPacketSize ?? DEFAULT_MAX_PACKET_SIZE)) {
+    find("warning", ["maxNegotiablePacketSize"], "The peer may raise the packet size limit above maxPacketSize");
+  }
+  if (!isAuditMode()) {
+    find("warning", [], "Audit mode is off, so keys and payloads print in full in debug output");
+  }
+
+  const maxPacketSize = o.maxPacketSize ?? DEFAULT_MAX_PACKET_SIZE;
+  const limits = o.decodeLimits;
+  return {
+    cipher: {
+      suites,
+      negotiated: o.cipherSuites !== undefined,
+      nullCipher: o.dangerousNullCipher === true,
+      postQuantum: o.postQuantum ?? "off",
+      keyExchange: o.keyExchange !== undefined ? "provider" : "in-memory",
+    },
+    authentication: {
+      mode,
+      psk,
+      pskIdentity: o.pskIdentity ?? null,
+      identity,
+      identityKeyExternal,
+      peerVerification,
+      revocationCheck: o.isRevoked !== undefined,
+      resumption: o.resumption !== undefined && o.resumption !== false,
+      issuesTickets: o.tickets !== undefined,
+    },
+    limits: {
+      maxPacketSize,
+      maxNegotiablePacketSize: o.maxNegotiablePacketSize ?? maxPacketSize,
+      maxPacketsPerSecond: o.maxPacketsPerSecond ?? null,
+      packetBurst: o.packetBurst ?? null,
+      handshakeTimeoutMs: o.handshakeTimeoutMs ?? null,
+      readTimeoutMs: o.readTimeoutMs ?? null,
+      writeTimeoutMs: o.writeTimeoutMs ?? null,
+      idleTimeoutMs: o.idleTimeoutMs ?? (o.keepAliveMs !== undefined ? o.keepAliveMs * 3 : null),
+      keepAliveMs: o.keepAliveMs ?? null,
+      decodeBudgetMs: o.decodeBudgetMs ?? null,
+      rekeyAfterBytes: o.rekeyAfterBytes ?? null,
+      rekeyAfterMs: o.rekeyAfterMs ?? null,
+      decodeLimits: limits !== undefined
+        ? { maxSequenceLength: limits.maxSequenceLength, maxDepth: limits.maxDepth, maxStringLength: limits.maxStringLength }
+        : null,
+    },
+    features: {
+      compression: o.compression !== undefined,
+      padding: o.padding !== undefined,
+      coverTraffic: o.padding?.coverIntervalMs !== undefined,
+      negotiatePacketSize: o.negotiatePacketSize === true,
+      sequenceNumbers: o.sequenceNumbers === true,
+      protocolVersion: o.protocolVersion !== undefined,
+      format: o.format !== undefined,
+      timeSync: o.timeSync === true,
+      ultraLowLatency: o.ultraLowLatency === true,
+      journal: o.journal !== undefined,
+      interceptors: (o.interceptors?.length ?? 0) > 0,
+      tracing: o.tracer !== undefined,
+      tcpKeepAlive: o.tcpKeepAliveMs !== undefined,
+      watchdog: o.watchdog !== undefined,
+    },
+    dangerous,
+    redaction: isAuditMode(),
+    findings,
+  };
+}
@@ -0,0 +1,240 @@
+/**
+ * Sans-IO connection
+ * The handshake and record layer of a clavis connection, without any I/O
+ *
+ * `ClavisConnection` never reads or writes a socket and never awaits: bytes
+ * from the peer go in through `feedBytes()`, bytes for the peer come out of
+ * `takeOutput()`, and decrypted packets come out of `receive()`. That lets
+ * clavis run on top of any event loop, a blocking server that owns its
+ * sockets, or a fuzzer feeding it chunks. The bytes are exactly what
+ * `EncryptedStream` exchanges, since both use `HandshakeMachine` and the
+ * frame codec, so either end of a connection may be either one.
+ *
+ * The connection covers the handshake, application packets, pings and
+ * close. The stream's optional features (client identities, post-quantum
+ * keys, resumption, compression, padding, rekeying, packet size changes,
+ * time sync and journal acknowledgments) need their own exchanges and stay on
+ * `EncryptedStream`; their control frames fail the connection here.
+ */
+
+import { XChaCha20Poly1305Cipher, type FrameCipher } from "./crypto.js";
+import { ClavisError, MessageError } from "./error.js";
+import { HandshakeMachine, type TimedHandshakeResult } from "./handshake.js";
+import { FRAME_TAG_LENGTH, FrameDecoder, openFrame, sealFrame } from "./frame.js";
+import {
+  ControlFrameKind,
+  decodeControlFrame,
+  decodeControlU32,
+  encodeControlFrame,
+  encodeControlU32,
+} from "./control.js";
+import type { PacketTrait } from "./protocol.js";
+import { systemClock, type Clock } from "./clock.js";
+import type { KeyExchangeProvider } from "./key-provider.js";
+
+/** Default largest packet, as for streams */
+const DEFAULT_MAX_PACKET_SIZE = 65536;
+
+/**
+ * Options for `ClavisConnection`
+ */
+export interface ClavisConnectionOptions {
+  /** Pre-shared key both sides must hold (at least 16 bytes) */
+  psk?: Uint8Array | undefined;
+  /** Largest packet sent or accepted, in bytes (default: 65536) */
+  maxPacketSize?: number | undefined;
+  /** Time source for the handshake timings (default: `systemClock`) */
+  clock?: Clock | undefined;
+  /** Where the handshake's ephemeral key lives (default: process memory); see `KeyExchangeProvider` */
+  keyExchange?: KeyExchangeProvider | undefined;
+}
+
+/**
+ * A clavis connection as a state machine
+ *
+ * @example
+ * ```typescript
+ * const connection = new ClavisConnection({ psk });
+ * socket.write(connection.takeOutput());
+ * socket.on("data", (chunk) => {
+ *   connection.feedBytes(chunk);
+ *   for (let packet = connection.receive(); packet; packet = connection.receive()) {
+ *     connection.send(new RawPacket(handle(packet)));
+ *   }
+ *   socket.write(connection.takeOutput());
+ * });
+ * ```
+ */
+export class ClavisConnection {
+  private readonly handshake: HandshakeMachine;
+  private readonly decoder: FrameDecoder;
+  private readonly maxPacketSize: number;
+  private cipher: FrameCipher | undefined;
+  private decipher: FrameCipher | undefined;
+  private output: Uint8Array[] = [];
+  private packets: Uint8Array[] = [];
+  private writeClosed = false;
+  private peerClosed = false;
+  private failure: unknown;
+
+  constructor(options: ClavisConnectionOptions = {}) {
+    const maxPacketSize = options.maxPacketSize ?? DEFAULT_MAX_PACKET_SIZE;
+    if (!Number.isInteger(maxPacketSize) || maxPacketSize < 1) {
+      throw ClavisError.config(`maxPacketSize must be a positive integer, got ${maxPacketSize}`);
+    }
+    this.maxPacketSize = maxPacketSize;
+    this.decoder = new FrameDecoder(maxPacketSize + FRAME_TAG_LENGTH);
+    this.handshake = new HandshakeMachine(options.psk, options.clock ?? systemClock, options.keyExchange);
+    this.output.push(this.handshake.takeOutput());
+  }
+
+  /** Whether the handshake has finished and packets can be sent */
+  get established(): boolean {
+    return this.cipher !== undefined;
+  }
+
+  /** Whether the peer closed the connection; nothing more will be received */
+  get closed(): boolean {
+    return this.peerClosed;
+  }
+
+  /** Keys, role and timings of the handshake, once established */
+  get handshakeResult(): TimedHandshakeResult {
+    return this.handshake.result();
+  }
+
+  /**
+   * Consume bytes received from the peer, in chunks of any size.
+   * Throws if the peer fails the handshake or sends a frame that doesn't
+   * authenticate or parse; the connection is unusable after that, and every
+   * later call throws the same error.
+   */
+  feedBytes(bytes: Uint8Array): void {
+    if (this.failure !== undefined) throw this.failure;
+    try {
+      let records = bytes;
+      if (!this.handshake.done) {
+        const used = this.feedHandshake(bytes);
+        if (!this.handshake.done) return;
+        const result = this.handshake.result();
+        this.cipher = new XChaCha20Poly1305Cipher(result.encKey);
+        this.decipher = new XChaCha20Poly1305Cipher(result.decKey);
+        records = bytes.subarray(used);
+      }
+      this.readFrames(records);
+    } catch (error) {
+      this.failure = error;
+      throw error;
+    }
+  }
+
+  /** Bytes to send to the peer, in order; empty when there is nothing to send */
+  takeOutput(): Uint8Array {
+    const output = this.output;
+    this.output = [];
+    if (output.length === 1) return output[0]!;
+    const joined = new Uint8Array(output.reduce((total, chunk) => total + chunk.length, 0));
+    let offset = 0;
+    for (const chunk of output) {
+      joined.set(chunk, offset);
+      offset += chunk.length;
+    }
+    return joined;
+  }
+
+  /** Next decrypted packet, or undefined until more bytes arrive */
+  receive(): Uint8Array | undefined {
+    return this.packets.shift();
+  }
+
+  /** Encrypt a packet and queue it for `takeOutput()` */
+  send(packet: PacketTrait): void {
+    const cipher = this.writableCipher();
+    const reported = packet.serializedSize?.();
+    if (reported !== undefined) this.checkSize(packet, reported);
+    const plaintext = packet.serialize();
+    this.checkSize(packet, plaintext.length);
+    this.output.push(sealFrame(cipher, plaintext));
+  }
+
+  /** Queue a ping; the peer answers with a pong carrying the same id */
+  ping(id: number): void {
+    this.sendControl(ControlFrameKind.Ping, encodeControlU32(id));
+  }
+
+  /** Queue a close frame. Nothing can be sent after it; packets already received stay readable. */
+  close(): void {
+    if (this.writeClosed) return;
+    this.sendControl(ControlFrameKind.Close, new Uint8Array(0));
+    this.writeClosed = true;
+  }
+
+  private feedHandshake(bytes: Uint8Array): number {
+    try {
+      return this.handshake.feedBytes(bytes);
+    } finally {
+      // The responder's MAC goes out even when the initiator's fails to verify
+      this.output.push(this.handshake.takeOutput());
+    }
+  }
+
+  private readFrames(bytes: Uint8Array): void {
+    if (bytes.length === 0) return;
+    if (this.peerClosed) {
+      throw ClavisError.message(MessageError.invalidFormat("Data after the peer's close frame"));
+    }
+    this.decoder.push(bytes);
+    for (let frame = this.decoder.next(); frame; frame = this.decoder.next()) {
+      const plaintext = openFrame(this.decipher!, frame);
+      if (!frame.control) {
+        this.packets.push(plaintext);
+        continue;
+      }
+      this.handleControl(plaintext);
+      if (this.peerClosed && this.decoder.buffered > 0) {
+        throw ClavisError.message(MessageError.invalidFormat("Data after the peer's close frame"));
+      }
+    }
+  }
+
+  private handleControl(data: Uint8Array): void {
+    const frame = decodeControlFrame(data);
+    switch (frame.kind) {
+      case ControlFrameKind.Close:
+        this.peerClosed = true;
+        return;
+      case ControlFrameKind.Ping:
+        // Answers are dropped once our close frame is out
+        if (!this.writeClosed) {
+          this.sendControl(ControlFrameKind.Pong, encodeControlU32(decodeControlU32(frame.payload)));
+        }
+        return;
+      case ControlFrameKind.Pong:
+        return;
+      default:
+        throw ClavisError.message(
+          MessageError.invalidFormat(`Control frame ${ControlFrameKind[frame.kind] ?? frame.kind} is not supported by ClavisConnection`)
+        );
+    }
+  }
+
+  private sendControl(kind: ControlFrameKind, payload: Uint8Array): void {
+    this.output.push(sealFrame(this.writableCipher(), encodeControlFrame(kind, payload), true));
+  }
+
+  private writableCipher(): FrameCipher {
+    if (!this.cipher) {
+      throw ClavisError.invalidOperation("The handshake has not finished");
+    }
+    if (this.writeClosed) {
+      throw ClavisError.invalidOperation("Cannot write to a closed connection");
+    }
+    return this.cipher;
+  }
+
+  private checkSize(packet: PacketTrait, size: number): void {
+    if (size > this.maxPacketSize) {
+      throw ClavisError.message(MessageError.packetTooLarge(size, this.maxPacketSize, packet.variantName));
+    }
+  }
+}
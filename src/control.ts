@@ -0,0 +1,106 @@
+/**
+ * Control frames
+ * Connection-level messages carried alongside application packets
+ *
+ * A control frame is a normal encrypted frame whose length field has the top
+ * bit set. Its ciphertext is sealed with `CONTROL_AAD` as associated data, so
+ * flipping the flag on the wire makes the frame fail authentication instead
+ * of being reinterpreted. Peers that don't know about control frames see an
+ * oversized length and drop the connection.
+ *
+ * Plaintext layout: kind (u8) followed by a kind-specific payload.
+ */
+
+import { ClavisError, MessageError } from "./error.js";
+
+/** Set in the frame length field to mark a control frame */
+export const CONTROL_FRAME_FLAG = 0x80000000;
+
+/** Associated data every control frame is sealed with */
+export const CONTROL_AAD = new TextEncoder().encode("clavis-control-v1");
+
+/**
+ * Control frame kinds
+ */
+export enum ControlFrameKind {
+  /** Ask the peer to switch both directions to a new max packet size (u32) */
+  ResizeRequest = 1,
+  /** The peer switched to the requested size (u32) */
+  ResizeAccept = 2,
+  /** The peer kept its current size (u32) */
+  ResizeReject = 3,
+  /** The peer journaled every application packet up to this count (u32) */
+  Ack = 4,
+  /** Ask for the peer's wall-clock time (probe id, u32) */
+  TimeProbe = 5,
+  /** The peer's receive and send times for a probe (see `encodeTimeReply`) */
+  TimeReply = 6,
+  /** Start a rekey (the sender's ephemeral X25519 public key) */
+  RekeyRequest = 7,
+  /** Answer a rekey (the answering side's ephemeral public key); the sender's last frame under the old key */
+  RekeyResponse = 8,
+  /** The rekey's starter switched keys; its last frame under the old key */
+  RekeyConfirm = 9,
+  /** Liveness check; the peer answers with a Pong carrying the same id (u32) */
+  Ping = 10,
+  /** Answer to a Ping (u32 id) */
+  Pong = 11,
+  /** The sender has finished writing and is closing the connection (no payload) */
+  Close = 12,
+  /** Ask the peer to move both directions to another protocol version (u32) */
+  UpgradeRequest = 13,
+  /** The peer's packets from here on use the requested version (u32) */
+  UpgradeAccept = 14,
+  /** The peer stays on its current version (u32, the version it writes) */
+  UpgradeReject = 15,
+  /** The requester's packets from here on use the new version (u32) */
+  UpgradeConfirm = 16,
+}
+
+/**
+ * Decoded control frame
+ */
+export interface ControlFrame {
+  kind: ControlFrameKind;
+  payload: Uint8Array;
+}
+
+/**
+ * How a stream's control exchanges send their frames
+ */
+export interface ControlChannel {
+  /** Send a frame that asks the peer something; failures carry the write's context */
+  request(kind: ControlFrameKind, payload: Uint8Array): Promise<void>;
+  /** Send a frame answering the peer; failures surface on the read that handled its frame */
+  answer(kind: ControlFrameKind, payload: Uint8Array): Promise<void>;
+}
+
+export function encodeControlFrame(kind: ControlFrameKind, payload: Uint8Array = new Uint8Array(0)): Uint8Array {
+  const out = new Uint8Array(1 + payload.length);
+  out[0] = kind;
+  out.set(payload, 1);
+  return out;
+}
+
+export function decodeControlFrame(data: Uint8Array): ControlFrame {
+  const kind = data[0];
+  if (kind === undefined || kind < ControlFrameKind.ResizeRequest || kind > ControlFrameKind.UpgradeConfirm) {
+    throw ClavisError.message(MessageError.invalidFormat(`Unknown control frame kind ${kind}`));
+  }
+  return { kind, payload: data.subarray(1) };
+}
+
+/** Encode a u32 control payload */
+export function encodeControlU32(value: number): Uint8Array {
+  const out = new Uint8Array(4);
+  new DataView(out.buffer).setUint32(0, value >>> 0, true);
+  return out;
+}
+
+/** Decode a u32 control payload */
+export function decodeControlU32(payload: Uint8Array): number {
+  if (payload.length !== 4) {
+    throw ClavisError.message(MessageError.invalidFormat("Control payload must be a u32"));
+  }
+  return new DataView(payload.buffer, payload.byteOffset, 4).getUint32(0, true);
+}
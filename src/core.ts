@@ -0,0 +1,24 @@
+/**
+ * Clavis core
+ * Minimal entry point: the handshake, encrypted frames and protocol definitions
+ *
+ * `import ... from "clavis-js/core"` loads only what an encrypted connection
+ * needs. RPC, compression, identities, resumption, storage and the other
+ * optional features are not pulled in; import them from their own subpaths
+ * (e.g. "clavis-js/rpc"). Streams load compression, identity and resumption
+ * code lazily, the first time one is given the option that needs it.
+ */
+
+export * from "./error.js";
+export * from "./crypto.js";
+export * from "./handshake.js";
+export * from "./handshake-messages.js";
+export * from "./frame.js";
+export * from "./connection.js";
+export * from "./stream.js";
+export * from "./protocol.js";
+export * from "./bincode.js";
+export * from "./bincode-helpers.js";
+export * from "./clock.js";
+export * from "./control.js";
+export * from "./bandwidth.js";
@@ -0,0 +1,257 @@
+/**
+ * Fuzz corpus capture
+ * Writes anonymized copies of decrypted traffic as cargo-fuzz corpus entries
+ *
+ * Attach a `CorpusCapture` to streams with the `corpusCapture` option and
+ * every application packet they read is sampled, anonymized and written to
+ * `<directory>/<target>/<sha1 of the contents>`, the layout cargo-fuzz and
+ * libFuzzer read seeds from. Identical inputs land in the same file, so
+ * long-running capture only grows the corpus with new traffic shapes.
+ *
+ * Two targets are written for each packet:
+ * - packet: the decrypted packet, for fuzzers of the serde layer
+ * - frame: the packet sealed into a wire frame under `FUZZ_FRAME_KEY` and an
+ *   all-zero nonce, for frame fuzzers that open frames with that key
+ *
+ * Anonymization happens before anything touches the disk. The default,
+ * `scrubStrings`, overwrites runs of printable text and keeps every other
+ * byte, so lengths, variant indices and numbers survive while names,
+ * messages and identifiers don't. Pass `anonymize` for protocol-aware
+ * scrubbing. Setup exchanges are never captured.
+ */
+
+import { createHash } from "crypto";
+import { mkdir, readdir, writeFile } from "fs/promises";
+import { join } from "path";
+import { ClavisError } from "./error.js";
+import { XChaCha20Poly1305Cipher } from "./crypto.js";
+import { encodeFrame, FRAME_NONCE_LENGTH, FRAME_OVERHEAD } from "./frame.js";
+
+/**
+ * Layer a corpus directory seeds
+ */
+export type CorpusTarget = "packet" | "frame";
+
+/**
+ * Options for a `CorpusCapture`
+ */
+export interface CorpusCaptureOptions {
+  /** Corpus root, e.g. `fuzz/corpus`; one subdirectory per target is created in it */
+  directory: string;
+  /**
+   * Directory name of each target's corpus, usually the fuzz target's name
+   * (default: both, named "packet" and "frame"). Targets left out aren't written.
+   */
+  targets?: Partial<Record<CorpusTarget, string>> | undefined;
+  /**
+   * Turn a packet into the bytes to store, or undefined to skip it
+   * (default: `scrubStrings`). Must not return the packet itself.
+   */
+  anonymize?: ((packet: Uint8Array) => Uint8Array | undefined) | undefined;
+  /** Fraction of packets to capture, 0 to 1 (default: 1) */
+  sampleRate?: number | undefined;
+  /** Random source for sampling (default: Math.random) */
+  random?: (() => number) | undefined;
+  /** Largest entry to write, in bytes (default: 4096, libFuzzer's default max_len) */
+  maxInputSize?: number | undefined;
+  /** Entries per target after which capture stops, counting existing ones (default: 10000) */
+  maxEntries?: number | undefined;
+}
+
+/**
+ * What a `CorpusCapture` did with the packets it saw
+ */
+export interface CorpusCaptureCounts {
+  /** New corpus entries written */
+  written: number;
+  /** Entries that were already in the corpus */
+  duplicates: number;
+  /** Packets or entries left out by sampling, the anonymizer or the size and entry limits */
+  skipped: number;
+  /** Writes that failed */
+  failed: number;
+}
+
+/**
+ * Key the frame corpus is sealed under; frame fuzz targets should open
+ * their input with it
+ */
+export const FUZZ_FRAME_KEY: Uint8Array = new Uint8Array(32);
+
+const ZERO_NONCE = new Uint8Array(FRAME_NONCE_LENGTH);
+const DEFAULT_TARGETS: Record<CorpusTarget, string> = { packet: "packet", frame: "frame" };
+const DEFAULT_MAX_INPUT_SIZE = 4096;
+const DEFAULT_MAX_ENTRIES = 10_000;
+/** Shortest run of printable bytes `scrubStrings` treats as text */
+const MIN_TEXT_RUN = 4;
+
+/**
+ * Copy `packet` with every run of four or more printable ASCII bytes
+ * replaced by "x", keeping the length and all other bytes
+ */
+export function scrubStrings(packet: Uint8Array): Uint8Array {
+  const out = packet.slice();
+  let start = 0;
+  for (let i = 0; i <= out.length; i++) {
+    const byte = out[i];
+    if (byte !== undefined && byte >= 0x20 && byte <= 0x7e) continue;
+    if (i - start >= MIN_TEXT_RUN) out.fill(0x78, start, i);
+    start = i + 1;
+  }
+  return out;
+}
+
+/**
+ * Name of a corpus entry: the hex SHA-1 of its contents, as libFuzzer names them
+ */
+export function corpusEntryName(input: Uint8Array): string {
+  return createHash("sha1").update(input).digest("hex");
+}
+
+/**
+ * Seal `plaintext` into a deterministic frame under `FUZZ_FRAME_KEY`
+ */
+export function fuzzFrame(plaintext: Uint8Array): Uint8Array {
+  const cipher = new XChaCha20Poly1305Cipher(FUZZ_FRAME_KEY);
+  return encodeFrame(ZERO_NONCE, cipher.encrypt(ZERO_NONCE, plaintext));
+}
+
+interface TargetState {
+  directory: string;
+  /** Entry names on disk or being written; filled from the directory first */
+  entries: Set<string>;
+  /** Whether the directory could be created and listed */
+  ready: Promise<boolean>;
+}
+
+/**
+ * Writes a sample of the traffic of the streams it's attached to into
+ * a fuzz corpus
+ *
+ * @example
+ * ```typescript
+ * const capture = new CorpusCapture({
+ *   directory: "fuzz/corpus",
+ *   targets: { packet: "decode_packet", frame: "open_frame" },
+ *   sampleRate: 0.01,
+ * });
+ * const listener = new EncryptedListener(server, { streamOptions: { corpusCapture: capture } });
+ * ```
+ */
+export class CorpusCapture {
+  private readonly targets: Array<[CorpusTarget, TargetState]> = [];
+  private readonly anonymize: (packet: Uint8Array) => Uint8Array | undefined;
+  private readonly sampleRate: number;
+  private readonly random: () => number;
+  private readonly maxInputSize: number;
+  private readonly maxEntries: number;
+  private readonly pending = new Set<Promise<void>>();
+  private readonly tally: CorpusCaptureCounts = { written: 0, duplicates: 0, skipped: 0, failed: 0 };
+
+  constructor(options: CorpusCaptureOptions) {
+    this.sampleRate = options.sampleRate ?? 1;
+    this.maxInputSize = options.maxInputSize ?? DEFAULT_MAX_INPUT_SIZE;
+    this.maxEntries = options.maxEntries ?? DEFAULT_MAX_ENTRIES;
+    if (!(this.sampleRate >= 0 && this.sampleRate <= 1)) {
+      throw ClavisError.config("sampleRate must be between 0 and 1");
+    }
+    if (!(this.maxInputSize > 0) || !(this.maxEntries > 0)) {
+      throw ClavisError.config("maxInputSize and maxEntries must be positive");
+    }
+    this.anonymize = options.anonymize ?? scrubStrings;
+    this.random = options.random ?? Math.random;
+
+    const names = options.targets ?? DEFAULT_TARGETS;
+    for (const target of ["packet", "frame"] as const) {
+      const name = names[target];
+      if (name === undefined) continue;
+      const directory = join(options.directory, name);
+      const entries = new Set<string>();
+      const ready = mkdir(directory, { recursive: true })
+        .then(() => readdir(directory))
+        .then(
+          (existing) => {
+            existing.forEach((entry) => entries.add(entry));
+            return true;
+          },
+          () => false
+        );
+      this.targets.push([target, { directory, entries, ready }]);
+    }
+  }
+
+  /**
+   * Sample and anonymize one decrypted packet and queue its corpus entries.
+   * Never throws; failures show up in `counts()`.
+   */
+  capture(packet: Uint8Array): void {
+    if (this.sampleRate < 1 && this.random() >= this.sampleRate) {
+      this.tally.skipped++;
+      return;
+    }
+    let anonymized: Uint8Array | undefined;
+    try {
+      anonymized = this.anonymize(packet);
+    } catch {
+      this.tally.failed++;
+      return;
+    }
+    if (anonymized === undefined) {
+      this.tally.skipped++;
+      return;
+    }
+    for (const [target, state] of this.targets) {
+      const size = target === "frame" ? anonymized.length + FRAME_OVERHEAD : anonymized.length;
+      if (size > this.maxInputSize) {
+        this.tally.skipped++;
+        continue;
+      }
+      this.track(this.store(state, target === "frame" ? fuzzFrame(anonymized) : anonymized));
+    }
+  }
+
+  /** Counts so far; writes still in flight aren't included */
+  counts(): CorpusCaptureCounts {
+    return { ...this.tally };
+  }
+
+  /** Wait for every queued write to finish */
+  async flush(): Promise<void> {
+    while (this.pending.size > 0) {
+      await Promise.all(this.pending);
+    }
+  }
+
+  private track(write: Promise<void>): void {
+    this.pending.add(write);
+    void write.finally(() => this.pending.delete(write));
+  }
+
+  private async store(state: TargetState, input: Uint8Array): Promise<void> {
+    if (!(await state.ready)) {
+      this.tally.failed++;
+      return;
+    }
+    const name = corpusEntryName(input);
+    if (state.entries.has(name)) {
+      this.tally.duplicates++;
+      return;
+    }
+    if (state.entries.size >= this.maxEntries) {
+      this.tally.skipped++;
+      return;
+    }
+    state.entries.add(name);
+    try {
+      await writeFile(join(state.directory, name), input, { flag: "wx" });
+      this.tally.written++;
+    } catch (error) {
+      if ((error as NodeJS.ErrnoException).code === "EEXIST") {
+        this.tally.duplicates++;
+      } else {
+        state.entries.delete(name);
+        this.tally.failed++;
+      }
+    }
+  }
+}
@@ -0,0 +1,185 @@
+/**
+ * Corruption monitoring
+ * Counts frames that failed authentication or broke the framing, and raises
+ * alarms when they come in faster than a threshold
+ *
+ * A frame that fails authentication is consumed, so a reader that carries on
+ * after the error picks up the frames behind it and a connection can report
+ * several failures. Framing has no resynchronisation point though: after a
+ * bad length prefix the rest of the byte stream is unreadable, so there are
+ * no resync events to count and such connections are best closed. A monitor
+ * is meant to be shared by many streams, such as all of a listener's, where a
+ * burst of failures points at tampering on the path or a broken peer build.
+ */
+
+import { ClavisError, CryptoError, CryptoOperation, MessageError, StreamError, StreamErrorCode } from "./error.js";
+import { systemClock, type Clock } from "./clock.js";
+
+/**
+ * What went wrong with a frame
+ *
+ * - `macFailure`: the frame did not authenticate under the session key
+ * - `framing`: the frame was malformed, oversized or broke the control protocol
+ * - `truncated`: the connection ended partway through a frame
+ */
+export type CorruptionKind = "macFailure" | "framing" | "truncated";
+
+/**
+ * One corrupt frame
+ */
+export interface CorruptionEvent {
+  kind: CorruptionKind;
+  connectionId: string;
+  /** Clock time the failure was observed at */
+  at: number;
+  error: ClavisError;
+}
+
+/**
+ * Corruption counts, by kind
+ */
+export interface CorruptionCounts {
+  macFailures: number;
+  framingErrors: number;
+  truncatedFrames: number;
+}
+
+/**
+ * Fires when more than `count` events arrive within `windowMs`
+ */
+export interface CorruptionAlarm {
+  /** Only count this kind of event (default: every kind) */
+  kind?: CorruptionKind | undefined;
+  count: number;
+  windowMs: number;
+  /**
+   * Called with the events in the window once the threshold is passed.
+   * The window starts over afterwards, so a steady stream of failures fires
+   * once per `count + 1` events rather than on every one.
+   */
+  onAlarm: (events: CorruptionEvent[]) => void;
+}
+
+/**
+ * Options for a `CorruptionMonitor`
+ */
+export interface CorruptionMonitorOptions {
+  alarms?: CorruptionAlarm[] | undefined;
+  /** Time source for alarm windows (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+const UNEXPECTED_CLOSE = StreamError.unexpectedClose().message;
+
+/**
+ * Work out whether a read error was a corrupt frame, and which kind
+ */
+export function corruptionKind(error: unknown): CorruptionKind | undefined {
+  const cause = error instanceof ClavisError ? error.cause : error;
+  if (cause instanceof CryptoError) {
+    return cause.operation === CryptoOperation.Decryption ? "macFailure" : undefined;
+  }
+  if (cause instanceof MessageError) return "framing";
+  if (cause instanceof StreamError && cause.code === StreamErrorCode.ConnectionClosed &&
+      cause.message === UNEXPECTED_CLOSE) {
+    return "truncated";
+  }
+  return undefined;
+}
+
+function emptyCounts(): CorruptionCounts {
+  return { macFailures: 0, framingErrors: 0, truncatedFrames: 0 };
+}
+
+function countEvent(counts: CorruptionCounts, kind: CorruptionKind): void {
+  switch (kind) {
+    case "macFailure":
+      counts.macFailures++;
+      break;
+    case "framing":
+      counts.framingErrors++;
+      break;
+    case "truncated":
+      counts.truncatedFrames++;
+      break;
+  }
+}
+
+/**
+ * Shared tally of corrupt frames across streams
+ *
+ * Pass it to each stream as the `corruptionMonitor` option; streams report
+ * every read failure to `observe()`, which keeps the ones that were
+ * corruption and ignores the rest.
+ */
+export class CorruptionMonitor {
+  private readonly clock: Clock;
+  private readonly alarms: { alarm: CorruptionAlarm; events: CorruptionEvent[] }[];
+  private readonly totals = emptyCounts();
+  private readonly perConnection = new Map<string, CorruptionCounts>();
+
+  constructor(options: CorruptionMonitorOptions = {}) {
+    this.clock = options.clock ?? systemClock;
+    for (const alarm of options.alarms ?? []) {
+      if (!(alarm.count >= 0) || !(alarm.windowMs > 0)) {
+        throw ClavisError.config("Corruption alarms need a count of at least 0 and a positive window");
+      }
+    }
+    this.alarms = (options.alarms ?? []).map((alarm) => ({ alarm, events: [] }));
+  }
+
+  /**
+   * Record a read failure on a connection if it was a corrupt frame.
+   * Returns the event, or undefined for failures that weren't corruption.
+   */
+  observe(error: ClavisError, connectionId: string): CorruptionEvent | undefined {
+    const kind = corruptionKind(error);
+    if (kind === undefined) return undefined;
+    const event: CorruptionEvent = { kind, connectionId, at: this.clock.now(), error };
+    this.record(event);
+    return event;
+  }
+
+  /** Record an event directly, e.g. one detected outside a stream */
+  record(event: CorruptionEvent): void {
+    countEvent(this.totals, event.kind);
+    let counts = this.perConnection.get(event.connectionId);
+    if (!counts) {
+      counts = emptyCounts();
+      this.perConnection.set(event.connectionId, counts);
+    }
+    countEvent(counts, event.kind);
+
+    for (const entry of this.alarms) {
+      const { alarm } = entry;
+      if (alarm.kind !== undefined && alarm.kind !== event.kind) continue;
+      entry.events.push(event);
+      const since = event.at - alarm.windowMs;
+      while (entry.events.length > 0 && entry.events[0]!.at <= since) entry.events.shift();
+      if (entry.events.length > alarm.count) {
+        alarm.onAlarm(entry.events.splice(0));
+      }
+    }
+  }
+
+  /** Counts across every connection */
+  counts(): CorruptionCounts {
+    return { ...this.totals };
+  }
+
+  /** Counts for one connection; zeros if it never reported anything */
+  countsFor(connectionId: string): CorruptionCounts {
+    const counts = this.perConnection.get(connectionId);
+    return counts ? { ...counts } : emptyCounts();
+  }
+
+  /** Connections that have reported at least one event */
+  connections(): string[] {
+    return [...this.perConnection.keys()];
+  }
+
+  /** Forget a connection's counts; totals and alarm windows are kept */
+  forget(connectionId: string): void {
+    this.perConnection.delete(connectionId);
+  }
+}
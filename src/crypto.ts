@@ -1,10 +1,11 @@
-import { x25519 } from "@noble/curves/ed25519.js";
+import { ed25519, x25519 } from "@noble/curves/ed25519.js";
 import { xchacha20poly1305 } from "@noble/ciphers/chacha.js";
 import { sha256 } from "@noble/hashes/sha2.js";
 import { hmac } from "@noble/hashes/hmac.js";
 import { hkdf } from "@noble/hashes/hkdf.js";
 import { randomBytes } from "@noble/hashes/utils.js";
 import { ClavisError, CryptoError, CryptoOperation } from "./error.js";
+import { redact } from "./audit.js";
 
 /**
  * X25519 key pair for key exchange
@@ -19,7 +20,7 @@ export interface X25519KeyPair {
  */
 export function generateX25519KeyPair(): X25519KeyPair {
   const { secretKey, publicKey } = x25519.keygen();
-  return { secret: secretKey, publicKey };
+  return { secret: redact(secretKey, "key"), publicKey };
 }
 
 /**
@@ -41,7 +42,7 @@ export function computeSharedSecret(
   }
 
   try {
-    return x25519.getSharedSecret(secretKey, peerPublicKey);
+    return redact(x25519.getSharedSecret(secretKey, peerPublicKey), "key");
   } catch (error) {
     throw ClavisError.cryptoFailure(
       CryptoOperation.KeyExchange,
@@ -50,10 +51,80 @@ export function computeSharedSecret(
   }
 }
 
+/**
+ * Ed25519 key pair for signing
+ */
+export interface Ed25519KeyPair {
+  secretKey: Uint8Array; // 32 bytes
+  publicKey: Uint8Array; // 32 bytes
+}
+
+/**
+ * Generate a new Ed25519 signing key pair
+ */
+export function generateEd25519KeyPair(): Ed25519KeyPair {
+  const { secretKey, publicKey } = ed25519.keygen();
+  return { secretKey: redact(secretKey, "key"), publicKey };
+}
+
+/**
+ * Public key matching an Ed25519 secret key
+ */
+export function ed25519PublicKey(secretKey: Uint8Array): Uint8Array {
+  if (secretKey.length !== 32) {
+    throw ClavisError.crypto(
+      CryptoError.invalidKeyMaterial("Signing key must be 32 bytes")
+    );
+  }
+  return ed25519.getPublicKey(secretKey);
+}
+
+/**
+ * Sign a message with an Ed25519 secret key (64-byte signature)
+ */
+export function ed25519Sign(secretKey: Uint8Array, message: Uint8Array): Uint8Array {
+  if (secretKey.length !== 32) {
+    throw ClavisError.crypto(
+      CryptoError.invalidKeyMaterial("Signing key must be 32 bytes")
+    );
+  }
+  return ed25519.sign(message, secretKey);
+}
+
+/**
+ * Verify an Ed25519 signature; malformed keys or signatures verify as false
+ */
+export function ed25519Verify(publicKey: Uint8Array, message: Uint8Array, signature: Uint8Array): boolean {
+  try {
+    return ed25519.verify(signature, message, publicKey);
+  } catch {
+    return false;
+  }
+}
+
+/**
+ * AEAD that seals and opens frames: 24-byte nonces, and a 16-byte tag at the
+ * end of every ciphertext
+ */
+export interface FrameCipher {
+  encrypt(nonce: Uint8Array, plaintext: Uint8Array, aad?: Uint8Array): Uint8Array;
+  decrypt(nonce: Uint8Array, ciphertext: Uint8Array, aad?: Uint8Array): Uint8Array;
+  /**
+   * Decrypt into `output`, exactly as long as the plaintext, and return it.
+   * Optional: ciphers without it are read with `decrypt()`, allocating.
+   */
+  decryptInto?(nonce: Uint8Array, ciphertext: Uint8Array, output: Uint8Array, aad?: Uint8Array): Uint8Array;
+  /**
+   * Encrypt into `output`, exactly as long as the ciphertext (plaintext and
+   * tag), and return it. Optional: ciphers without it seal with `encrypt()`.
+   */
+  encryptInto?(nonce: Uint8Array, plaintext: Uint8Array, output: Uint8Array, aad?: Uint8Array): Uint8Array;
+}
+
 /**
  * XChaCha20-Poly1305 cipher instance
  */
-export class XChaCha20Poly1305Cipher {
+export class XChaCha20Poly1305Cipher implements FrameCipher {
   private key: Uint8Array;
 
   constructor(key: Uint8Array) {
@@ -62,7 +133,7 @@ export class XChaCha20Poly1305Cipher {
         CryptoError.invalidKeyMaterial("Key must be 32 bytes for XChaCha20-Poly1305")
       );
     }
-    this.key = key;
+    this.key = redact(key, "key");
   }
 
   /**
@@ -73,9 +144,20 @@ export class XChaCha20Poly1305Cipher {
   }
 
   /**
-   * Encrypt plaintext with a nonce
+   * Encrypt plaintext with a nonce, optionally binding associated data
    */
-  encrypt(nonce: Uint8Array, plaintext: Uint8Array): Uint8Array {
+  encrypt(nonce: Uint8Array, plaintext: Uint8Array, aad?: Uint8Array): Uint8Array {
+    return this.seal(nonce, plaintext, aad, undefined);
+  }
+
+  /**
+   * Encrypt into `output`, which must be exactly the plaintext length plus the tag
+   */
+  encryptInto(nonce: Uint8Array, plaintext: Uint8Array, output: Uint8Array, aad?: Uint8Array): Uint8Array {
+    return this.seal(nonce, plaintext, aad, output);
+  }
+
+  private seal(nonce: Uint8Array, plaintext: Uint8Array, aad: Uint8Array | undefined, output: Uint8Array | undefined): Uint8Array {
     if (nonce.length !== 24) {
       throw ClavisError.cryptoFailure(
         CryptoOperation.Encryption,
@@ -84,8 +166,8 @@ export class XChaCha20Poly1305Cipher {
     }
 
     try {
-      const cipher = xchacha20poly1305(this.key, nonce);
-      return cipher.encrypt(plaintext);
+      const cipher = xchacha20poly1305(this.key, nonce, aad);
+      return cipher.encrypt(plaintext, output);
     } catch (error: unknown) {
       const message = error instanceof Error ? error.message : String(error);
       throw ClavisError.cryptoFailure(
@@ -96,9 +178,20 @@ export class XChaCha20Poly1305Cipher {
   }
 
   /**
-   * Decrypt ciphertext with a nonce
+   * Decrypt ciphertext with a nonce and the associated data it was sealed with
    */
-  decrypt(nonce: Uint8Array, ciphertext: Uint8Array): Uint8Array {
+  decrypt(nonce: Uint8Array, ciphertext: Uint8Array, aad?: Uint8Array): Uint8Array {
+    return this.open(nonce, ciphertext, aad, undefined);
+  }
+
+  /**
+   * Decrypt into `output`, which must be exactly the plaintext length
+   */
+  decryptInto(nonce: Uint8Array, ciphertext: Uint8Array, output: Uint8Array, aad?: Uint8Array): Uint8Array {
+    return this.open(nonce, ciphertext, aad, output);
+  }
+
+  private open(nonce: Uint8Array, ciphertext: Uint8Array, aad: Uint8Array | undefined, output: Uint8Array | undefined): Uint8Array {
     if (nonce.length !== 24) {
       throw ClavisError.cryptoFailure(
         CryptoOperation.Decryption,
@@ -107,8 +200,8 @@ export class XChaCha20Poly1305Cipher {
     }
 
     try {
-      const cipher = xchacha20poly1305(this.key, nonce);
-      return cipher.decrypt(ciphertext);
+      const cipher = xchacha20poly1305(this.key, nonce, aad);
+      return redact(cipher.decrypt(ciphertext, output), "payload");
     } catch (error: unknown) {
       const message = error instanceof Error ? error.message : String(error);
       throw ClavisError.cryptoFailure(
@@ -165,7 +258,7 @@ export function hkdfExpand(
 
   try {
     const derived = hkdf(sha256, sharedSecret, salt, new TextEncoder().encode(info), 32);
-    return derived;
+    return redact(derived, "key");
   } catch (error) {
     throw ClavisError.crypto(
       CryptoError.keyDerivationFailure(`HKDF expansion failed: ${error}`)
@@ -0,0 +1,478 @@
+/**
+ * Encrypted datagrams
+ * The key exchange of an encrypted stream, then one sealed packet per
+ * datagram, for transports that may drop, duplicate or reorder what they
+ * carry: UDP sockets, QUIC unreliable datagrams, unordered WebRTC channels.
+ *
+ * Every datagram starts with a kind byte:
+ * - 0, handshake: bytes of the peer's messages received so far (u16
+ *   little-endian), then every handshake message this side has sent. Each
+ *   resend repeats the whole exchange, so any one that arrives catches the
+ *   peer up, and the count tells a finished side whether to answer.
+ * - 1, data: sequence (u64 little-endian), then the ciphertext with its
+ *   Poly1305 tag. The nonce is the sequence zero-padded to 24 bytes and the
+ *   kind and sequence are associated data. Each direction has its own key,
+ *   so a nonce never repeats under a key.
+ *
+ * A sliding window remembers which recent sequences arrived: duplicates and
+ * datagrams older than the window are dropped, as are datagrams that fail
+ * authentication, since anyone can send one. Nothing is retransmitted or
+ * reordered after the handshake; that is up to the application.
+ */
+
+import type { RemoteInfo, Socket } from "dgram";
+import { XChaCha20Poly1305Cipher, type FrameCipher } from "./crypto.js";
+import { ClavisError, MessageError, StreamError } from "./error.js";
+import { performHandshake } from "./handshake.js";
+import { FRAME_NONCE_LENGTH, FRAME_TAG_LENGTH } from "./frame.js";
+import { systemClock, type Clock, type TimerHandle } from "./clock.js";
+import type { PacketTrait } from "./protocol.js";
+import type { KeyExchangeProvider } from "./key-provider.js";
+
+const KIND_HANDSHAKE = 0;
+const KIND_DATA = 1;
+
+/** Bytes ahead of a data datagram's ciphertext: kind (1) + sequence (8) */
+const DATA_HEADER_LENGTH = 9;
+
+/** Bytes a datagram adds to a serialized packet: kind, sequence and Poly1305 tag */
+export const DATAGRAM_OVERHEAD = DATA_HEADER_LENGTH + FRAME_TAG_LENGTH;
+
+/** Sequences remembered by default */
+export const DEFAULT_REPLAY_WINDOW = 1024;
+
+/** Default largest datagram, which fits the path MTU QUIC assumes */
+export const DEFAULT_MAX_DATAGRAM_SIZE = 1200;
+
+/** Data datagrams kept while the handshake finishes */
+const MAX_EARLY_DATAGRAMS = 64;
+
+/**
+ * A transport carrying whole datagrams, in no particular order and not
+ * necessarily all of them
+ */
+export interface DatagramTransport {
+  /** Send one datagram */
+  send(datagram: Uint8Array): void | Promise<void>;
+  /** Register the one handler for incoming datagrams */
+  onDatagram(handler: (datagram: Uint8Array) => void): void;
+  /** Stop delivering datagrams (optional) */
+  close?(): void;
+}
+
+/**
+ * Options for `EncryptedDatagram.new`
+ */
+export interface EncryptedDatagramOptions {
+  /** Pre-shared key authenticating the handshake, at least 16 bytes (default: none) */
+  psk?: Uint8Array | undefined;
+  /** Where the handshake's ephemeral key lives (default: process memory); see `KeyExchangeProvider` */
+  keyExchange?: KeyExchangeProvider | undefined;
+  /** Give up on a handshake that hasn't finished within this many milliseconds (default: 10000) */
+  handshakeTimeoutMs?: number | undefined;
+  /** Resend handshake messages the peer hasn't acknowledged this often, in milliseconds (default: 250) */
+  retransmitMs?: number | undefined;
+  /** Recent sequences remembered to drop duplicates; older datagrams are dropped too (default: 1024) */
+  replayWindow?: number | undefined;
+  /** Largest datagram sent, overhead included (default: 1200) */
+  maxDatagramSize?: number | undefined;
+  /** Datagrams kept for `receive()` before new ones are dropped (default: 256) */
+  maxQueued?: number | undefined;
+  /** Time source for retransmits and the handshake timeout (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+/**
+ * What an encrypted datagram endpoint has sent, received and dropped
+ */
+export interface DatagramStats {
+  /** Data datagrams sent */
+  sent: number;
+  /** Data datagrams accepted */
+  received: number;
+  /** Duplicates and datagrams older than the replay window */
+  replayed: number;
+  /** Datagrams that failed authentication or were malformed */
+  rejected: number;
+  /** Datagrams dropped because `receive()` fell behind */
+  overflowed: number;
+}
+
+/**
+ * Sliding window over received sequence numbers, as in IPsec and
+ * WireGuard. `check` before authenticating and `commit` after, so a forged
+ * datagram can't move the window.
+ */
+export class ReplayWindow {
+  private highest = -1;
+  private readonly seen: Uint8Array;
+
+  constructor(readonly size: number = DEFAULT_REPLAY_WINDOW) {
+    if (!(Number.isInteger(size) && size > 0)) {
+      throw ClavisError.config("Replay window must be a positive integer");
+    }
+    this.seen = new Uint8Array(size);
+  }
+
+  /** Whether `sequence` is new and recent enough to tell */
+  check(sequence: number): boolean {
+    if (sequence > this.highest) return true;
+    if (this.highest - sequence >= this.size) return false;
+    return this.seen[sequence % this.size] === 0;
+  }
+
+  /** Record `sequence` as received */
+  commit(sequence: number): void {
+    if (sequence > this.highest) {
+      const skipped = Math.min(sequence - this.highest, this.size);
+      for (let i = 1; i <= skipped; i++) {
+        this.seen[(this.highest + i) % this.size] = 0;
+      }
+      this.highest = sequence;
+    }
+    this.seen[sequence % this.size] = 1;
+  }
+}
+
+/**
+ * Both sides' handshake messages over a lossy transport. `performHandshake`
+ * reads and writes through it as if it were a stream. A finished side
+ * answers a peer still missing its messages at most once per
+ * `retransmitMs`, so spoofed handshake datagrams can't turn it into an
+ * amplifier.
+ */
+class HandshakeChannel {
+  private sent = new Uint8Array(0);
+  private received = new Uint8Array(0);
+  private consumed = 0;
+  private waiter: (() => void) | undefined;
+  private timer: TimerHandle | undefined;
+  private failure: unknown;
+  private answeredAt = -Infinity;
+  /** Set once this side has sent and read everything */
+  done = false;
+
+  constructor(
+    private readonly transport: DatagramTransport,
+    private readonly clock: Clock,
+    private readonly retransmitMs: number
+  ) {}
+
+  read = async (length: number): Promise<Uint8Array> => {
+    while (this.received.length < this.consumed + length) {
+      if (this.failure !== undefined) throw this.failure;
+      this.armRetransmit();
+      await new Promise<void>((resolve) => (this.waiter = resolve));
+    }
+    const bytes = this.received.slice(this.consumed, this.consumed + length);
+    this.consumed += length;
+    return bytes;
+  };
+
+  write = async (data: Uint8Array): Promise<void> => {
+    const sent = new Uint8Array(this.sent.length + data.length);
+    sent.set(this.sent);
+    sent.set(data, this.sent.length);
+    this.sent = sent;
+    await this.resend();
+  };
+
+  /**
+   * Take the peer's handshake datagram: keep any messages we hadn't seen,
+   * and answer a peer that is missing some of ours once we're done
+   */
+  deliver(datagram: Uint8Array): void {
+    if (datagram.length < 3) return;
+    const acknowledged = datagram[1]! | (datagram[2]! << 8);
+    const messages = datagram.subarray(3);
+    if (messages.length > this.received.length && startsWith(messages, this.received)) {
+      this.received = messages.slice();
+      this.waiter?.();
+      this.waiter = undefined;
+    }
+    const now = this.clock.now();
+    if (this.done && acknowledged < this.sent.length && now - this.answeredAt >= this.retransmitMs) {
+      this.answeredAt = now;
+      void Promise.resolve(this.resend()).catch(() => undefined);
+    }
+  }
+
+  /** Fail the pending read, ending the handshake */
+  fail(error: unknown): void {
+    this.failure = error;
+    this.waiter?.();
+    this.waiter = undefined;
+  }
+
+  stop(): void {
+    this.done = true;
+    this.timer?.cancel();
+    this.timer = undefined;
+  }
+
+  private armRetransmit(): void {
+    if (this.timer) return;
+    this.timer = this.clock.setTimer(() => {
+      this.timer = undefined;
+      if (this.done) return;
+      void Promise.resolve(this.resend()).catch(() => undefined);
+      this.armRetransmit();
+    }, this.retransmitMs);
+  }
+
+  private resend(): void | Promise<void> {
+    const datagram = new Uint8Array(3 + this.sent.length);
+    datagram[0] = KIND_HANDSHAKE;
+    datagram[1] = this.received.length & 0xff;
+    datagram[2] = this.received.length >> 8;
+    datagram.set(this.sent, 3);
+    return this.transport.send(datagram);
+  }
+}
+
+function startsWith(bytes: Uint8Array, prefix: Uint8Array): boolean {
+  return prefix.every((byte, i) => bytes[i] === byte);
+}
+
+/** Nonce of a data datagram: its kind and sequence, zero-padded */
+function datagramNonce(header: Uint8Array): Uint8Array {
+  const nonce = new Uint8Array(FRAME_NONCE_LENGTH);
+  nonce.set(header.subarray(1, DATA_HEADER_LENGTH));
+  return nonce;
+}
+
+/**
+ * An encrypted, authenticated datagram endpoint. Each `send()` is one
+ * datagram; `receive()` yields the authentic ones that arrive, at most
+ * once each and in whatever order the transport delivers them.
+ *
+ * @example
+ * ```typescript
+ * const socket = dgram.createSocket("udp4");
+ * socket.bind(4000);
+ * const channel = await EncryptedDatagram.new(udpTransport(socket, { address: "10.0.0.2", port: 4000 }), { psk });
+ * await channel.send(new RawPacket(position));
+ * const update = await channel.receive();
+ * ```
+ */
+export class EncryptedDatagram {
+  private sendSequence = 0;
+  private readonly queue: Uint8Array[] = [];
+  private readonly waiters: Array<{ resolve: (datagram: Uint8Array) => void; reject: (error: unknown) => void }> = [];
+  private readonly counters: DatagramStats = { sent: 0, received: 0, replayed: 0, rejected: 0, overflowed: 0 };
+  private closed = false;
+
+  private constructor(
+    private readonly transport: DatagramTransport,
+    private readonly cipher: FrameCipher,
+    private readonly decipher: FrameCipher,
+    private readonly channel: HandshakeChannel,
+    /** Which role this side took in the handshake */
+    readonly initiator: boolean,
+    /** Handshake transcript hash, identical on both sides */
+    readonly transcriptHash: Uint8Array,
+    private readonly window: ReplayWindow,
+    private readonly maxDatagramSize: number,
+    private readonly maxQueued: number
+  ) {}
+
+  /**
+   * Run the key exchange over `transport`. Handshake messages are resent
+   * every `retransmitMs` until the peer's answer arrives, so a lost
+   * datagram only delays it.
+   */
+  static async new(transport: DatagramTransport, options?: EncryptedDatagramOptions): Promise<EncryptedDatagram> {
+    const clock = options?.clock ?? systemClock;
+    const handshakeTimeoutMs = options?.handshakeTimeoutMs ?? 10_000;
+    const retransmitMs = options?.retransmitMs ?? 250;
+    const maxDatagramSize = options?.maxDatagramSize ?? DEFAULT_MAX_DATAGRAM_SIZE;
+    const maxQueued = options?.maxQueued ?? 256;
+    if (!(handshakeTimeoutMs > 0) || !(retransmitMs > 0)) {
+      throw ClavisError.config("handshakeTimeoutMs and retransmitMs must be positive");
+    }
+    if (!(maxDatagramSize > DATAGRAM_OVERHEAD) || !(maxQueued > 0)) {
+      throw ClavisError.config(`maxDatagramSize must exceed ${DATAGRAM_OVERHEAD} and maxQueued must be positive`);
+    }
+
+    const window = new ReplayWindow(options?.replayWindow ?? DEFAULT_REPLAY_WINDOW);
+    const channel = new HandshakeChannel(transport, clock, retransmitMs);
+    const early: Uint8Array[] = [];
+    let endpoint: EncryptedDatagram | undefined;
+    transport.onDatagram((datagram) => {
+      if (endpoint) {
+        endpoint.deliver(datagram);
+      } else if (datagram[0] === KIND_HANDSHAKE) {
+        channel.deliver(datagram);
+      } else if (early.length < MAX_EARLY_DATAGRAMS) {
+        // The peer finished first; its data may overtake our last handshake read
+        early.push(datagram);
+      }
+    });
+
+    const timer = clock.setTimer(
+      () => channel.fail(ClavisError.stream(StreamError.handshakeTimeout(handshakeTimeoutMs))),
+      handshakeTimeoutMs
+    );
+    let created: EncryptedDatagram;
+    try {
+      const keys = await performHandshake(channel, options?.psk, clock, options?.keyExchange);
+      created = new EncryptedDatagram(
+        transport,
+        new XChaCha20Poly1305Cipher(keys.encKey),
+        new XChaCha20Poly1305Cipher(keys.decKey),
+        channel,
+        keys.initiator,
+        keys.transcriptHash,
+        window,
+        maxDatagramSize,
+        maxQueued
+      );
+    } catch (error) {
+      transport.close?.();
+      throw error;
+    } finally {
+      timer.cancel();
+      channel.stop();
+    }
+    endpoint = created;
+    for (const datagram of early) created.deliver(datagram);
+    return created;
+  }
+
+  /** Largest serialized packet `send()` accepts */
+  get maxPayloadSize(): number {
+    return this.maxDatagramSize - DATAGRAM_OVERHEAD;
+  }
+
+  /**
+   * Seal a packet into one datagram and send it. Nothing confirms it
+   * arrived.
+   */
+  async send(packet: PacketTrait): Promise<void> {
+    if (this.closed) {
+      throw ClavisError.stream(StreamError.closed());
+    }
+    const reported = packet.serializedSize?.();
+    if (reported !== undefined && reported > this.maxPayloadSize) {
+      throw ClavisError.message(MessageError.packetTooLarge(reported, this.maxPayloadSize, packet.variantName));
+    }
+    const plaintext = packet.serialize();
+    if (plaintext.length > this.maxPayloadSize) {
+      throw ClavisError.message(MessageError.packetTooLarge(plaintext.length, this.maxPayloadSize, packet.variantName));
+    }
+    const sequence = this.sendSequence++;
+    const header = new Uint8Array(DATA_HEADER_LENGTH);
+    header[0] = KIND_DATA;
+    new DataView(header.buffer).setBigUint64(1, BigInt(sequence), true);
+    const ciphertext = this.cipher.encrypt(datagramNonce(header), plaintext, header);
+    const datagram = new Uint8Array(DATA_HEADER_LENGTH + ciphertext.length);
+    datagram.set(header);
+    datagram.set(ciphertext, DATA_HEADER_LENGTH);
+    this.counters.sent++;
+    await this.transport.send(datagram);
+  }
+
+  /**
+   * Next authentic datagram's packet bytes. Rejects once the endpoint is
+   * closed.
+   */
+  receive(): Promise<Uint8Array> {
+    const queued = this.queue.shift();
+    if (queued) return Promise.resolve(queued);
+    if (this.closed) {
+      return Promise.reject(ClavisError.stream(StreamError.closed()));
+    }
+    return new Promise((resolve, reject) => this.waiters.push({ resolve, reject }));
+  }
+
+  /** Receive packets until the endpoint is closed */
+  async *[Symbol.asyncIterator](): AsyncGenerator<Uint8Array> {
+    while (!this.closed || this.queue.length > 0) {
+      try {
+        yield await this.receive();
+      } catch (error) {
+        if (this.closed) return;
+        throw error;
+      }
+    }
+  }
+
+  /** Counters for this endpoint */
+  stats(): DatagramStats {
+    return { ...this.counters };
+  }
+
+  /** Stop receiving, fail pending `receive()` calls and close the transport */
+  close(): void {
+    if (this.closed) return;
+    this.closed = true;
+    this.transport.close?.();
+    const error = ClavisError.stream(StreamError.closed());
+    for (const waiter of this.waiters.splice(0)) waiter.reject(error);
+  }
+
+  private deliver(datagram: Uint8Array): void {
+    if (this.closed) return;
+    if (datagram[0] === KIND_HANDSHAKE) {
+      // The peer is still waiting for our last handshake message
+      this.channel.deliver(datagram);
+      return;
+    }
+    if (datagram[0] !== KIND_DATA || datagram.length < DATAGRAM_OVERHEAD) {
+      this.counters.rejected++;
+      return;
+    }
+    const header = datagram.subarray(0, DATA_HEADER_LENGTH);
+    const sequence = Number(new DataView(header.buffer, header.byteOffset, DATA_HEADER_LENGTH).getBigUint64(1, true));
+    if (!this.window.check(sequence)) {
+      this.counters.replayed++;
+      return;
+    }
+    let plaintext: Uint8Array;
+    try {
+      plaintext = this.decipher.decrypt(datagramNonce(header), datagram.subarray(DATA_HEADER_LENGTH), header);
+    } catch {
+      this.counters.rejected++;
+      return;
+    }
+    this.window.commit(sequence);
+    this.counters.received++;
+    const waiter = this.waiters.shift();
+    if (waiter) {
+      waiter.resolve(plaintext);
+    } else if (this.queue.length < this.maxQueued) {
+      this.queue.push(plaintext);
+    } else {
+      this.counters.overflowed++;
+    }
+  }
+}
+
+/**
+ * Datagram transport over a bound UDP socket, talking to one remote
+ * address. Datagrams from anywhere else are ignored; closing it leaves the
+ * socket open for other peers.
+ */
+export function udpTransport(socket: Socket, remote: { address: string; port: number }): DatagramTransport {
+  let listener: ((message: Buffer, info: RemoteInfo) => void) | undefined;
+  return {
+    send: (datagram) =>
+      new Promise<void>((resolve, reject) => {
+        socket.send(datagram, remote.port, remote.address, (error) =>
+          error ? reject(ClavisError.stream(StreamError.io(error))) : resolve()
+        );
+      }),
+    onDatagram(handler) {
+      listener = (message, info) => {
+        if (info.address === remote.address && info.port === remote.port) {
+          handler(new Uint8Array(message));
+        }
+      };
+      socket.on("message", listener);
+    },
+    close() {
+      if (listener) socket.off("message", listener);
+      listener = undefined;
+    },
+  };
+}
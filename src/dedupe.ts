@@ -0,0 +1,100 @@
+/**
+ * Duplicate filter
+ * Drops packets whose message id was seen recently, so handlers without
+ * idempotency logic don't run twice for a packet that was sent again
+ *
+ * At-least-once senders resend whatever the peer never acknowledged
+ * (see `waitForAcknowledgment()` and the packet journal), usually on a new
+ * connection after a drop, so some packets arrive twice. Clavis frames carry
+ * no metadata besides their length and nonce, and the wire format is shared
+ * with the Rust crate, so the message id travels inside the packet and
+ * `idOf` reads it. The filter remembers the last `windowSize` ids; give
+ * every connection from the same sender the same filter.
+ */
+
+import { ClavisError } from "./error.js";
+import type { PacketInterceptor } from "./stream.js";
+
+/** A message id read from a packet */
+export type MessageId = string | number | bigint;
+
+/**
+ * Options for a `DuplicateFilter`
+ */
+export interface DuplicateFilterOptions {
+  /**
+   * Message id of a packet, or undefined for packets that aren't
+   * deduplicated (such as heartbeats)
+   */
+  idOf: (packet: Uint8Array) => MessageId | undefined;
+  /** Ids remembered; the oldest is forgotten first (default: 4096) */
+  windowSize?: number | undefined;
+}
+
+const DEFAULT_WINDOW_SIZE = 4096;
+
+/**
+ * Remembers recent message ids and drops packets that repeat one
+ *
+ * @example
+ * ```typescript
+ * const dedupe = new DuplicateFilter({
+ *   idOf: (packet) => Codec.decode(packet).reader.readU64(), // every variant starts with its id
+ * });
+ * const stream = await EncryptedStream.new(socket, { psk, interceptors: [dedupe.interceptor] });
+ * ```
+ */
+export class DuplicateFilter {
+  /** Insertion order is age order, so the first entry is the oldest */
+  private readonly seen = new Set<MessageId>();
+  private readonly windowSize: number;
+  private readonly idOf: (packet: Uint8Array) => MessageId | undefined;
+  /** Packets dropped as duplicates so far */
+  duplicates = 0;
+
+  constructor(options: DuplicateFilterOptions) {
+    this.windowSize = options.windowSize ?? DEFAULT_WINDOW_SIZE;
+    if (!(Number.isInteger(this.windowSize) && this.windowSize >= 1)) {
+      throw ClavisError.config(`windowSize must be a positive integer, got ${this.windowSize}`);
+    }
+    this.idOf = options.idOf;
+  }
+
+  /** Ids currently remembered */
+  get size(): number {
+    return this.seen.size;
+  }
+
+  /**
+   * Record `id`. Returns false, and counts a duplicate, if it is already
+   * in the window.
+   */
+  admit(id: MessageId): boolean {
+    if (this.seen.has(id)) {
+      this.duplicates++;
+      return false;
+    }
+    this.seen.add(id);
+    if (this.seen.size > this.windowSize) {
+      this.seen.delete(this.seen.values().next().value!);
+    }
+    return true;
+  }
+
+  /** Whether `id` is in the window, without recording it */
+  has(id: MessageId): boolean {
+    return this.seen.has(id);
+  }
+
+  /**
+   * Read interceptor dropping duplicates, for `stream.intercept()` or the
+   * `interceptors` option. Dropped packets count in the stream's
+   * `droppedPackets` too.
+   */
+  readonly interceptor: PacketInterceptor = {
+    read: (packet) => {
+      const id = this.idOf(packet);
+      return id === undefined || this.admit(id);
+    },
+  };
+}
@@ -0,0 +1,256 @@
+/**
+ * Derived protocols
+ * Builds a protocol codec from an existing TypeScript enum, the counterpart
+ * of `#[derive(Protocol)]` on an ordinary Rust enum
+ *
+ * Variants take the enum's declaration order, as serde numbers them, so an
+ * enum shared with the rest of a codebase doesn't have to be restated as a
+ * variant list; `ids` pins the indices of the ones peers already know. String enum values name variants on the wire; otherwise
+ * `rename` and `renameAll` follow `#[serde(rename)]` and
+ * `#[serde(rename_all)]`. Wire names only matter to the self-describing
+ * payload formats, where `encodeValue()` writes serde's externally tagged
+ * representation; bincode only ever sends the variant index.
+ *
+ * Payload types are a type parameter, so a generic Rust enum maps to a
+ * function that derives the codec for each instantiation:
+ *
+ * ```typescript
+ * enum Kind { Data, Ack }
+ * const envelope = <T>() => deriveProtocol<typeof Kind, { Data: T; Ack: number }>(Kind, { format: "json" });
+ * ```
+ */
+
+import { ClavisError } from "./error.js";
+import { createProtocolCodec, type ProtocolCodec } from "./protocol.js";
+import { payloadFormat, type WireFormat } from "./formats.js";
+import type { VariantDoc } from "./protocol-doc.js";
+import { DecodeBudget, type DecodeLimits } from "./bincode.js";
+
+/**
+ * serde's `rename_all` rules
+ */
+export type RenameRule =
+  | "lowercase"
+  | "UPPERCASE"
+  | "PascalCase"
+  | "camelCase"
+  | "snake_case"
+  | "SCREAMING_SNAKE_CASE"
+  | "kebab-case"
+  | "SCREAMING-KEBAB-CASE";
+
+/**
+ * A TypeScript enum object, numeric or string
+ */
+export type EnumObject = Record<string, string | number>;
+
+/** Variant names of an enum */
+export type EnumVariant<E extends EnumObject> = keyof E & string;
+
+/** Payload type of variant `K`; unit variants carry none */
+export type PayloadOf<P, K extends string> = K extends keyof P ? P[K] : undefined;
+
+/**
+ * A decoded value: its variant and payload
+ */
+export type DerivedValue<T extends string, P> = { [K in T]: { type: K; value: PayloadOf<P, K> } }[T];
+
+/**
+ * Options for `deriveProtocol`, the counterparts of serde and clavis attributes
+ */
+export interface DeriveProtocolOptions<T extends string> {
+  /** Wire names of individual variants (`#[serde(rename = "...")]`) */
+  rename?: Partial<Record<T, string>> | undefined;
+  /** Rule for variants without their own name (`#[serde(rename_all = "...")]`; default: names as declared) */
+  renameAll?: RenameRule | undefined;
+  /** Doc comments of variants, or full docs with a schema; see `DerivedProtocol.docs` */
+  docs?: Partial<Record<T, string | VariantDoc>> | undefined;
+  /** Variant that carries handler failures (see `ProtocolCodec.errorVariant`) */
+  errorVariant?: T | undefined;
+  /** Protocol version (see `ProtocolCodec.version`) */
+  version?: number | undefined;
+  /** Payload format of `encodeValue()` and `decodeValue()` (default: bincode) */
+  format?: WireFormat | undefined;
+  /** Write variant indices as varints instead of u32 (default: false) */
+  useVarint?: boolean | undefined;
+  /** Limits `decode()` readers and `decodeValue()` enforce (default: none beyond the payload's length) */
+  limits?: DecodeLimits | undefined;
+  /**
+   * Milliseconds to spend decoding one message, for every variant or per
+   * variant (default: none). `decodeValue()` only knows the variant once
+   * it's decoded, so it applies a single budget and ignores per-variant ones.
+   */
+  decodeBudgetMs?: number | Partial<Record<T, number>> | undefined;
+  /** Wire indices of individual variants (`#[id = N]`; see `createProtocolCodec`) */
+  ids?: Partial<Record<T, number>> | undefined;
+  /** Variants new code shouldn't send (`#[deprecated]`) */
+  deprecated?: readonly T[] | undefined;
+}
+
+/**
+ * A protocol codec derived from an enum, with typed payloads
+ */
+export interface DerivedProtocol<T extends string, P> extends ProtocolCodec<T> {
+  /** Docs per variant, ready for `describeProtocol(codec, { variants: codec.docs })` */
+  readonly docs: Partial<Record<T, VariantDoc>>;
+  /** Name of a variant on the wire */
+  wireName(type: T): string;
+  /** Variant with the given wire name, if any */
+  fromWireName(name: string): T | undefined;
+  /**
+   * Serialize a variant and its payload in the codec's format: the variant
+   * index and bincode payload, or serde's externally tagged representation
+   * (`"Name"` for unit variants, `{ "Name": payload }` otherwise)
+   */
+  encodeValue<K extends T>(type: K, ...payload: PayloadOf<P, K> extends undefined ? [] : [PayloadOf<P, K>]): Uint8Array;
+  /**
+   * Decode what `encodeValue()` produced. Only for self-describing formats:
+   * bincode payloads need `decode()` and a schema.
+   */
+  decodeValue(bytes: Uint8Array): DerivedValue<T, P>;
+}
+
+/** Apply a serde `rename_all` rule to a PascalCase variant name */
+export function applyRenameRule(name: string, rule: RenameRule): string {
+  const snake = () => [...name].map((ch, i) => (i > 0 && ch !== ch.toLowerCase() ? "_" : "") + ch.toLowerCase()).join("");
+  switch (rule) {
+    case "lowercase":
+      return name.toLowerCase();
+    case "UPPERCASE":
+      return name.toUpperCase();
+    case "PascalCase":
+      return name;
+    case "camelCase":
+      return name.charAt(0).toLowerCase() + name.slice(1);
+    case "snake_case":
+      return snake();
+    case "SCREAMING_SNAKE_CASE":
+      return snake().toUpperCase();
+    case "kebab-case":
+      return snake().replace(/_/g, "-");
+    case "SCREAMING-KEBAB-CASE":
+      return snake().replace(/_/g, "-").toUpperCase();
+  }
+}
+
+/**
+ * Variant names of an enum object in declaration order. Numeric enums also
+ * map values back to names; those reverse entries are left out.
+ */
+function enumVariants(enumObject: EnumObject): string[] {
+  return Object.keys(enumObject).filter((key) => {
+    const value = enumObject[key];
+    return !(typeof value === "string" && enumObject[value] !== undefined && typeof enumObject[value] === "number");
+  });
+}
+
+/**
+ * Derive a protocol codec from an existing enum
+ *
+ * @example
+ * ```typescript
+ * // Declared elsewhere and shared with the rest of the app
+ * enum Command { Join, SendMessage, Leave }
+ *
+ * const Codec = deriveProtocol<typeof Command, { Join: { room: string }; SendMessage: string }>(Command, {
+ *   renameAll: "snake_case",
+ *   docs: { Leave: "Leave the current room" },
+ *   format: "json",
+ * });
+ * Codec.encodeValue("SendMessage", "hi"); // {"send_message":"hi"}
+ * Codec.encodeValue("Leave");             // "leave"
+ * ```
+ */
+export function deriveProtocol<E extends EnumObject, P = Partial<Record<EnumVariant<E>, unknown>>>(
+  enumObject: E,
+  options: DeriveProtocolOptions<EnumVariant<E>> = {}
+): DerivedProtocol<EnumVariant<E>, P> {
+  type T = EnumVariant<E>;
+  const variants = enumVariants(enumObject) as T[];
+  if (variants.length === 0) {
+    throw ClavisError.config("Cannot derive a protocol from an empty enum");
+  }
+  const codec = createProtocolCodec<T>(variants, {
+    ...(options.useVarint !== undefined ? { useVarint: options.useVarint } : {}),
+    ...(options.errorVariant !== undefined ? { errorVariant: options.errorVariant } : {}),
+    ...(options.version !== undefined ? { version: options.version } : {}),
+    ...(options.format !== undefined ? { format: options.format } : {}),
+    ...(options.limits !== undefined ? { limits: options.limits } : {}),
+    ...(options.decodeBudgetMs !== undefined ? { decodeBudgetMs: options.decodeBudgetMs } : {}),
+    ...(options.ids !== undefined ? { ids: options.ids } : {}),
+    ...(options.deprecated !== undefined ? { deprecated: options.deprecated } : {}),
+  });
+  const format = payloadFormat(options.format ?? "bincode");
+
+  const toWire = new Map<T, string>();
+  const fromWire = new Map<string, T>();
+  for (const variant of variants) {
+    const value = enumObject[variant];
+    const name = options.rename?.[variant]
+      ?? (typeof value === "string" ? value : options.renameAll ? applyRenameRule(variant, options.renameAll) : variant);
+    const clash = fromWire.get(name);
+    if (clash !== undefined) {
+      throw ClavisError.config(`Variants ${clash} and ${variant} are both named ${name} on the wire`);
+    }
+    toWire.set(variant, name);
+    fromWire.set(name, variant);
+  }
+
+  const docs: Partial<Record<T, VariantDoc>> = {};
+  for (const [variant, doc] of Object.entries(options.docs ?? {}) as Array<[T, string | VariantDoc | undefined]>) {
+    if (!codec.isValidType(variant)) {
+      throw ClavisError.config(`Documented variant ${variant} is not part of the protocol`);
+    }
+    if (doc !== undefined) docs[variant] = typeof doc === "string" ? { doc } : doc;
+  }
+
+  const wireName = (type: T): string => {
+    const name = toWire.get(type);
+    if (name === undefined) throw ClavisError.serializationFailed(`Unknown variant type: ${type}`);
+    return name;
+  };
+
+  return {
+    ...codec,
+    docs,
+    wireName,
+
+    fromWireName(name: string): T | undefined {
+      return fromWire.get(name);
+    },
+
+    encodeValue<K extends T>(type: K, ...payload: PayloadOf<P, K> extends undefined ? [] : [PayloadOf<P, K>]): Uint8Array {
+      const [value] = payload as unknown[];
+      if (!format.selfDescribing) {
+        return codec.encode(type, value === undefined ? undefined : format.encode(value));
+      }
+      return format.encode(value === undefined ? wireName(type) : new Map([[wireName(type), value]]));
+    },
+
+    decodeValue(bytes: Uint8Array): DerivedValue<T, P> {
+      if (!format.selfDescribing) {
+        throw ClavisError.invalidOperation("bincode payloads aren't self-describing; read them with decode() and a schema");
+      }
+      const budgetMs = options.decodeBudgetMs;
+      const decoded = format.decode(
+        bytes,
+        typeof budgetMs === "number" ? { ...options.limits, budget: new DecodeBudget(budgetMs) } : options.limits
+      );
+      let name: string | undefined;
+      let value: unknown;
+      if (typeof decoded === "string") {
+        name = decoded;
+      } else if (decoded !== null && typeof decoded === "object" && !Array.isArray(decoded)) {
+        const entries = Object.entries(decoded);
+        if (entries.length === 1) [name, value] = entries[0]!;
+      }
+      const type = name === undefined ? undefined : fromWire.get(name);
+      if (type === undefined) {
+        throw ClavisError.deserializationFailed(
+          name === undefined ? "Expected an externally tagged variant" : `Unknown variant ${name}`
+        );
+      }
+      return { type, value } as DerivedValue<T, P>;
+    },
+  };
+}
@@ -0,0 +1,245 @@
+/**
+ * Diagnostics protocol
+ * A small standard protocol for connectivity and throughput checks between
+ * any two endpoints, whatever protocol they normally speak
+ *
+ * The wire format is that of this Rust definition, so clavis and clavis-js
+ * binaries can diagnose each other:
+ *
+ * ```rust
+ * clavis::protocol! {
+ *     pub enum Diag {
+ *         Ping(u64),
+ *         Pong(u64),
+ *         Pad(Vec<u8>),
+ *         Echo(Vec<u8>),
+ *     }
+ * }
+ * ```
+ *
+ * A responder answers `Ping(n)` with `Pong(n)`, sends every `Echo` back
+ * unchanged and discards `Pad`, which only exists to fill the link.
+ */
+
+import { randomBytes } from "@noble/hashes/utils.js";
+import { ClavisError, MessageError, StreamError } from "./error.js";
+import { writeU64 } from "./bincode.js";
+import { systemClock, type Clock } from "./clock.js";
+import { RawPacket, createProtocolCodec } from "./protocol.js";
+import type { PhaseTransport } from "./phases.js";
+
+/**
+ * Variant codec of the diagnostics protocol
+ */
+export const DiagCodec = createProtocolCodec(["Ping", "Pong", "Pad", "Echo"] as const);
+
+/**
+ * A decoded diagnostics packet
+ */
+export type DiagMessage =
+  | { type: "Ping"; sequence: bigint }
+  | { type: "Pong"; sequence: bigint }
+  | { type: "Pad"; size: number }
+  | { type: "Echo"; payload: Uint8Array };
+
+function bytesPacket(type: "Pad" | "Echo", payload: Uint8Array): RawPacket {
+  const buffer: number[] = [];
+  writeU64(buffer, BigInt(payload.length));
+  const header = DiagCodec.encode(type, new Uint8Array(buffer));
+  const bytes = new Uint8Array(header.length + payload.length);
+  bytes.set(header);
+  bytes.set(payload, header.length);
+  return new RawPacket(bytes);
+}
+
+function sequencePacket(type: "Ping" | "Pong", sequence: bigint | number): RawPacket {
+  const buffer: number[] = [];
+  writeU64(buffer, BigInt(sequence));
+  return new RawPacket(DiagCodec.encode(type, new Uint8Array(buffer)));
+}
+
+/**
+ * Packet constructors for the diagnostics protocol
+ */
+export const Diag = {
+  Ping: (sequence: bigint | number) => sequencePacket("Ping", sequence),
+  Pong: (sequence: bigint | number) => sequencePacket("Pong", sequence),
+  /** A packet carrying `size` bytes of zeros */
+  Pad: (size: number) => bytesPacket("Pad", new Uint8Array(size)),
+  Echo: (payload: Uint8Array) => bytesPacket("Echo", payload),
+};
+
+/**
+ * Decode a diagnostics packet
+ */
+export function decodeDiag(data: Uint8Array): DiagMessage {
+  const message = DiagCodec.decode(data);
+  switch (message.type) {
+    case "Ping":
+    case "Pong":
+      return { type: message.type, sequence: message.reader.readU64() };
+    case "Pad":
+      return { type: "Pad", size: message.reader.readBytes().length };
+    case "Echo":
+      return { type: "Echo", payload: message.reader.readBytes() };
+  }
+}
+
+/**
+ * What a responder handled before its peer went away
+ */
+export interface DiagServeStats {
+  /** Packets received */
+  packets: number;
+  /** Packet bytes received */
+  bytes: number;
+}
+
+/**
+ * Answer diagnostics packets on `transport` until the peer closes it.
+ *
+ * @example
+ * ```typescript
+ * const stream = await EncryptedStream.new(socket, options);
+ * const stats = await serveDiag(stream);
+ * console.log(`${stats.packets} packets, ${stats.bytes} bytes`);
+ * ```
+ */
+export async function serveDiag(transport: PhaseTransport): Promise<DiagServeStats> {
+  const stats: DiagServeStats = { packets: 0, bytes: 0 };
+  for (;;) {
+    let data: Uint8Array;
+    try {
+      data = (await transport.readPacket()) as unknown as Uint8Array;
+    } catch (error) {
+      if (isClosed(error)) return stats;
+      throw error;
+    }
+    stats.packets++;
+    stats.bytes += data.length;
+
+    const message = decodeDiag(data);
+    switch (message.type) {
+      case "Ping":
+        await transport.writePacket(Diag.Pong(message.sequence));
+        break;
+      case "Echo":
+        await transport.writePacket(Diag.Echo(message.payload));
+        break;
+      case "Pad":
+        break;
+      case "Pong":
+        throw ClavisError.message(MessageError.invalidFormat("Diagnostics responder received a Pong"));
+    }
+  }
+}
+
+/**
+ * What to measure with `runDiag()`
+ */
+export interface DiagOptions {
+  /** Round trips to time (default: 5) */
+  pings?: number | undefined;
+  /** Bytes sent in an Echo that must come back unchanged, 0 to skip (default: 64) */
+  echoSize?: number | undefined;
+  /** Pad packets sent to measure throughput, 0 to skip (default: 0) */
+  padPackets?: number | undefined;
+  /** Payload bytes per Pad packet (default: 16384) */
+  padSize?: number | undefined;
+  /** Clock the measurements are taken with */
+  clock?: Clock | undefined;
+}
+
+/**
+ * Results of `runDiag()`
+ */
+export interface DiagReport {
+  /** Round trip times in milliseconds */
+  rtt: { min: number; avg: number; max: number; samples: number[] };
+  /** Whether the Echo came back unchanged (true when skipped) */
+  echoOk: boolean;
+  /** Pad payload bytes per second, when padding was sent */
+  throughput?: number | undefined;
+}
+
+/**
+ * Run connectivity and throughput checks against a diagnostics responder.
+ *
+ * Round trips are timed one ping at a time. Throughput is the Pad payload
+ * sent divided by the time until a ping queued behind it comes back, so it
+ * counts what the responder actually received.
+ *
+ * @example
+ * ```typescript
+ * const report = await runDiag(stream, { padPackets: 1000 });
+ * console.log(`rtt ${report.rtt.avg.toFixed(2)}ms, ${report.throughput} B/s`);
+ * ```
+ */
+export async function runDiag(transport: PhaseTransport, options: DiagOptions = {}): Promise<DiagReport> {
+  const clock = options.clock ?? systemClock;
+  const pings = options.pings ?? 5;
+  if (!(pings >= 1)) {
+    throw ClavisError.config("pings must be at least 1");
+  }
+  let sequence = 0n;
+
+  const roundTrip = async (): Promise<number> => {
+    const sent = ++sequence;
+    const start = clock.now();
+    await transport.writePacket(Diag.Ping(sent));
+    const reply = await expectDiag(transport, "Pong");
+    if (reply.sequence !== sent) {
+      throw ClavisError.message(MessageError.invalidFormat(`Expected Pong ${sent}, got Pong ${reply.sequence}`));
+    }
+    return clock.now() - start;
+  };
+
+  const samples: number[] = [];
+  for (let i = 0; i < pings; i++) samples.push(await roundTrip());
+  const report: DiagReport = {
+    rtt: {
+      min: Math.min(...samples),
+      avg: samples.reduce((sum, sample) => sum + sample, 0) / samples.length,
+      max: Math.max(...samples),
+      samples,
+    },
+    echoOk: true,
+  };
+
+  const echoSize = options.echoSize ?? 64;
+  if (echoSize > 0) {
+    const payload = randomBytes(echoSize);
+    await transport.writePacket(Diag.Echo(payload));
+    const reply = await expectDiag(transport, "Echo");
+    report.echoOk = reply.payload.length === payload.length && reply.payload.every((byte, i) => byte === payload[i]);
+  }
+
+  const padPackets = options.padPackets ?? 0;
+  if (padPackets > 0) {
+    const padSize = options.padSize ?? 16384;
+    const pad = Diag.Pad(padSize);
+    const start = clock.now();
+    for (let i = 0; i < padPackets; i++) await transport.writePacket(pad);
+    await roundTrip();
+    const elapsed = Math.max(clock.now() - start, 1);
+    report.throughput = (padPackets * padSize * 1000) / elapsed;
+  }
+
+  return report;
+}
+
+async function expectDiag<K extends DiagMessage["type"]>(
+  transport: PhaseTransport,
+  type: K
+): Promise<Extract<DiagMessage, { type: K }>> {
+  const message = decodeDiag((await transport.readPacket()) as unknown as Uint8Array);
+  if (message.type !== type) {
+    throw ClavisError.message(MessageError.invalidFormat(`Expected ${type}, got ${message.type}`));
+  }
+  return message as Extract<DiagMessage, { type: K }>;
+}
+
+function isClosed(error: unknown): boolean {
+  const cause = error instanceof ClavisError ? error.cause : error;
+  return cause instanceof StreamError && cause.isConnectionClosed();
+}
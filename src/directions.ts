@@ -0,0 +1,157 @@
+/**
+ * Variant directions
+ * Marks protocol variants as client-to-server, server-to-client or both, so
+ * each side can only send what it may send and only accept what it may receive
+ *
+ * Directions are declared once for the protocol and a connection is opened
+ * as the client or the server. `send()` then only type-checks with variants
+ * that flow away from that side, and the same rule is checked at runtime for
+ * callers that bypass the types. An incoming variant that only the other
+ * direction carries is rejected as a protocol violation. Variants without a
+ * direction travel both ways.
+ */
+
+import { ClavisError, MessageError } from "./error.js";
+import { RawPacket, type DecodedMessage, type ProtocolCodec, type UnknownVariant } from "./protocol.js";
+import type { PhaseTransport } from "./phases.js";
+
+/**
+ * Which way a variant travels
+ */
+export type VariantDirection = "clientToServer" | "serverToClient" | "both";
+
+/**
+ * Side of the connection a handle sends for
+ */
+export type ConnectionRole = "client" | "server";
+
+/**
+ * Map of variant names to their directions
+ */
+export type DirectionMap<T extends string> = Partial<Record<T, VariantDirection>>;
+
+type VariantsGoing<D, V> = { [K in keyof D]: D[K] extends V ? K : never }[keyof D];
+
+/** Variants side `R` may send */
+export type DirectedSend<T extends string, D, R extends ConnectionRole> =
+  Exclude<T, VariantsGoing<D, R extends "client" ? "serverToClient" : "clientToServer">>;
+
+/** Variants side `R` may receive */
+export type DirectedReceive<T extends string, D, R extends ConnectionRole> =
+  Exclude<T, VariantsGoing<D, R extends "client" ? "clientToServer" : "serverToClient">>;
+
+/**
+ * A protocol with variant directions, able to open directed connections
+ */
+export interface DirectedProtocol<T extends string, D extends DirectionMap<T>> {
+  readonly codec: ProtocolCodec<T>;
+  readonly directions: D;
+  /** Direction of `variant` ("both" when not declared) */
+  directionOf(variant: T): VariantDirection;
+  /** Whether side `role` may send `variant` */
+  canSend(role: ConnectionRole, variant: T): boolean;
+  /** Wrap `transport` as side `role` */
+  open<R extends ConnectionRole>(transport: PhaseTransport, role: R): DirectedConnection<T, D, R>;
+}
+
+/**
+ * Declare which way the variants of a protocol travel.
+ *
+ * @example
+ * ```typescript
+ * const Chat = defineDirections(codec, {
+ *   Login: "clientToServer",
+ *   Welcome: "serverToClient",
+ *   Message: "both",
+ * });
+ *
+ * const client = Chat.open(stream, "client");
+ * await client.send("Login", credentials);
+ * await client.send("Welcome"); // does not compile
+ * ```
+ */
+export function defineDirections<T extends string, const D extends DirectionMap<T>>(
+  codec: ProtocolCodec<T>,
+  directions: D
+): DirectedProtocol<T, D> {
+  for (const [variant, direction] of Object.entries(directions)) {
+    if (!codec.isValidType(variant)) {
+      throw ClavisError.config(`Direction given for unknown variant ${variant}`);
+    }
+    if (direction !== "clientToServer" && direction !== "serverToClient" && direction !== "both") {
+      throw ClavisError.config(`Unknown direction ${String(direction)} for ${variant}`);
+    }
+  }
+
+  const protocol: DirectedProtocol<T, D> = {
+    codec,
+    directions,
+    directionOf(variant) {
+      return (directions as DirectionMap<T>)[variant] ?? "both";
+    },
+    canSend(role, variant) {
+      const direction = protocol.directionOf(variant);
+      return direction === "both" || direction === (role === "client" ? "clientToServer" : "serverToClient");
+    },
+    open(transport, role) {
+      if (role !== "client" && role !== "server") {
+        throw ClavisError.config(`Unknown role ${String(role)}`);
+      }
+      return new DirectedConnection(protocol, transport, role);
+    },
+  };
+  return protocol;
+}
+
+/**
+ * One side of a directed connection. Obtain one from `DirectedProtocol.open()`.
+ */
+export class DirectedConnection<T extends string, D extends DirectionMap<T>, R extends ConnectionRole> {
+  constructor(
+    private readonly protocol: DirectedProtocol<T, D>,
+    private readonly transport: PhaseTransport,
+    readonly role: R
+  ) {}
+
+  /**
+   * Send a variant this side may send.
+   * Resolves with the number of bytes written.
+   */
+  async send(type: DirectedSend<T, D, R>, data?: Uint8Array): Promise<number> {
+    const variant = type as T;
+    if (!this.protocol.canSend(this.role, variant)) {
+      throw ClavisError.invalidOperation(`${variant} cannot be sent by the ${this.role}`);
+    }
+    return this.transport.writePacket(new RawPacket(this.protocol.codec.encode(variant, data)));
+  }
+
+  /**
+   * Receive the next packet. A variant only this side may send is a
+   * protocol violation and is rejected.
+   */
+  async receive(): Promise<DecodedMessage<DirectedReceive<T, D, R>>> {
+    const message = await this.receiveVariant();
+    if (message.type === undefined) {
+      throw ClavisError.deserializationFailed(`Unknown variant index: ${message.index}`);
+    }
+    return message;
+  }
+
+  /**
+   * Receive like `receive()`, but return a variant this side doesn't know
+   * as an `UnknownVariant` instead of throwing, so packets from a newer
+   * peer can be skipped
+   */
+  async receiveVariant(): Promise<DecodedMessage<DirectedReceive<T, D, R>> | UnknownVariant> {
+    const bytes = (await this.transport.readPacket()) as unknown as Uint8Array;
+    const message = this.protocol.codec.decodeVariant(bytes);
+    if (message.type === undefined) return message;
+    const peer = this.role === "client" ? "server" : "client";
+    if (!this.protocol.canSend(peer, message.type)) {
+      throw ClavisError.message(
+        MessageError.invalidFormat(`Peer sent ${message.type}, which only the ${this.role} may send`)
+      );
+    }
+    return message as DecodedMessage<DirectedReceive<T, D, R>>;
+  }
+}
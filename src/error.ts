@@ -9,10 +9,50 @@ export enum CryptoOperation {
   Handshake = "handshake",
 }
 
+/**
+ * Why a credential was refused for its validity period
+ */
+export enum ValidityFailure {
+  /** Its validity period has ended */
+  Expired = "expired",
+  /** Its validity period starts well in the future */
+  NotYetValid = "not-yet-valid",
+  /** Its validity period starts shortly; most likely a clock is wrong */
+  SkewSuspected = "skew-suspected",
+}
+
+/**
+ * Which decompression limit a compressed packet broke
+ */
+export enum DecompressionLimit {
+  /** It inflated past `maxDecompressedSize` */
+  Size = "size",
+  /** It inflated past `maxRatio` times its compressed size */
+  Ratio = "ratio",
+}
+
+/**
+ * An outgoing packet refused for its size, the counterpart of
+ * `Error::PacketTooLarge`
+ */
+export interface PacketTooLarge {
+  /** Serialized size of the packet */
+  size: number;
+  /** Largest packet the connection accepts */
+  limit: number;
+  /** Variant of the packet, when it names one */
+  variant: string | undefined;
+  /** Whether `limit` is the receive limit the peer advertised (see `negotiatePacketSize`) */
+  peerLimit?: boolean | undefined;
+}
+
 /**
  * Represents cryptographic errors
  */
 export class CryptoError extends Error {
+  /** Set when a credential was refused for its validity period */
+  public validity: ValidityFailure | undefined;
+
   constructor(
     message: string,
     public operation?: CryptoOperation,
@@ -34,6 +74,12 @@ export class CryptoError extends Error {
     return new CryptoError(`Authentication failed: ${message}`, CryptoOperation.Authentication);
   }
 
+  static invalidPeriod(validity: ValidityFailure, message: string): CryptoError {
+    const error = new CryptoError(`Authentication failed: ${message}`, CryptoOperation.Authentication);
+    error.validity = validity;
+    return error;
+  }
+
   static invalidKeyMaterial(message: string): CryptoError {
     return new CryptoError(`Invalid key material: ${message}`);
   }
@@ -47,6 +93,11 @@ export class CryptoError extends Error {
  * Represents message format and processing errors
  */
 export class MessageError extends Error {
+  /** Set when a compressed packet was refused as a compression bomb */
+  public decompressionLimit: DecompressionLimit | undefined;
+  /** Set when an outgoing packet was refused for its size */
+  public packetTooLarge: PacketTooLarge | undefined;
+
   constructor(message: string) {
     super(message);
     this.name = "MessageError";
@@ -58,6 +109,15 @@ export class MessageError extends Error {
     );
   }
 
+  static packetTooLarge(size: number, limit: number, variant?: string, peerLimit = false): MessageError {
+    const error = new MessageError(
+      `Packet ${variant !== undefined ? `${variant} ` : ""}of ${size} bytes exceeds ` +
+        (peerLimit ? `the peer's receive limit of ${limit}` : `the limit of ${limit}`)
+    );
+    error.packetTooLarge = peerLimit ? { size, limit, variant, peerLimit } : { size, limit, variant };
+    return error;
+  }
+
   static serializationFailed(message: string): MessageError {
     return new MessageError(`Message serialization failed: ${message}`);
   }
@@ -69,6 +129,14 @@ export class MessageError extends Error {
   static invalidFormat(message: string): MessageError {
     return new MessageError(`Invalid message format: ${message}`);
   }
+
+  static decompressionLimitExceeded(limit: DecompressionLimit, compressedSize: number, maxSize: number): MessageError {
+    const error = new MessageError(
+      `Compressed packet of ${compressedSize} bytes inflates past ${maxSize} bytes (${limit} limit)`
+    );
+    error.decompressionLimit = limit;
+    return error;
+  }
 }
 
 /**
@@ -94,6 +162,18 @@ export enum StreamErrorCode {
   InvalidOperation = "INVALID_OPERATION",
   /** Generic IO error */
   IOError = "IO_ERROR",
+  /** Server is shedding load (e.g. too many pending handshakes) */
+  Overloaded = "OVERLOADED",
+  /** Peer stopped responding without closing the connection (keepalive gave up) */
+  PeerUnresponsive = "PEER_UNRESPONSIVE",
+  /** Nothing arrived from the peer within the stream's idle timeout */
+  IdleTimeout = "IDLE_TIMEOUT",
+  /** The peer closed the connection with an authenticated close frame */
+  Closed = "CLOSED",
+  /** The peers speak protocol versions they don't accept from each other */
+  VersionMismatch = "VERSION_MISMATCH",
+  /** The handshake and connection setup didn't finish within the handshake timeout */
+  HandshakeTimeout = "HANDSHAKE_TIMEOUT",
 }
 
 /**
@@ -159,6 +239,14 @@ export class StreamError extends Error {
     );
   }
 
+  static handshakeTimeout(timeoutMs: number): StreamError {
+    return new StreamError(
+      `Handshake timed out after ${timeoutMs}ms`,
+      undefined,
+      StreamErrorCode.HandshakeTimeout
+    );
+  }
+
   static eof(): StreamError {
     return new StreamError(
       "End of stream",
@@ -183,6 +271,47 @@ export class StreamError extends Error {
     );
   }
 
+  static overloaded(message: string): StreamError {
+    return new StreamError(
+      `Overloaded: ${message}`,
+      undefined,
+      StreamErrorCode.Overloaded
+    );
+  }
+
+  static peerUnresponsive(message: string, cause?: Error): StreamError {
+    return new StreamError(
+      `Peer unresponsive: ${message}`,
+      cause,
+      StreamErrorCode.PeerUnresponsive
+    );
+  }
+
+  static closed(): StreamError {
+    return new StreamError(
+      "Peer closed the connection",
+      undefined,
+      StreamErrorCode.Closed
+    );
+  }
+
+  static idleTimeout(idleTimeoutMs: number): StreamError {
+    return new StreamError(
+      `Nothing received from the peer for ${idleTimeoutMs}ms`,
+      undefined,
+      StreamErrorCode.IdleTimeout
+    );
+  }
+
+  static versionMismatch(local: number, peer: number, rejectedBy: "local" | "peer"): StreamError {
+    return new StreamError(
+      `Protocol version mismatch: this side speaks ${local}, the peer speaks ${peer} ` +
+        `(${rejectedBy === "local" ? "not accepted here" : "rejected by the peer"})`,
+      undefined,
+      StreamErrorCode.VersionMismatch
+    );
+  }
+
   static io(error: Error): StreamError {
     // Try to detect specific error codes from the underlying error
     const ioError = error as { code?: string };
@@ -203,22 +332,103 @@ export class StreamError extends Error {
   isConnectionClosed(): boolean {
     return this.code === StreamErrorCode.ConnectionClosed ||
            this.code === StreamErrorCode.ConnectionReset ||
-           this.code === StreamErrorCode.EOF;
+           this.code === StreamErrorCode.EOF ||
+           this.code === StreamErrorCode.Closed ||
+           this.code === StreamErrorCode.PeerUnresponsive ||
+           this.code === StreamErrorCode.IdleTimeout;
   }
 
   /** Check if this error might be transient and worth retrying */
   isTransient(): boolean {
     return this.code === StreamErrorCode.Timeout ||
-           this.code === StreamErrorCode.ConnectionReset;
+           this.code === StreamErrorCode.HandshakeTimeout ||
+           this.code === StreamErrorCode.ConnectionReset ||
+           this.code === StreamErrorCode.Overloaded;
+  }
+}
+
+/** Side of a connection an error occurred on */
+export type ErrorDirection = "read" | "write";
+
+/**
+ * One problem with a set of options: the options involved and what's wrong
+ */
+export interface ConfigConflict {
+  /** Options involved, e.g. `["postQuantum", "dangerousNullCipher"]` */
+  options: readonly string[];
+  message: string;
+}
+
+/**
+ * Every problem found in a set of options, reported together so they can
+ * all be fixed in one go
+ */
+export class ConfigError extends Error {
+  constructor(readonly conflicts: readonly ConfigConflict[]) {
+    super(conflicts.map((conflict) => conflict.message).join("; "));
+    this.name = "ConfigError";
+  }
+}
+
+/**
+ * A record that authenticated but arrived out of sequence, with
+ * `sequenceNumbers`: a replayed record when `received` is below `expected`,
+ * records missing or reordered when it is above
+ */
+export class ReplayError extends Error {
+  constructor(
+    /** Sequence number of the record that should have come next */
+    readonly expected: number,
+    /** Sequence number the record carried */
+    readonly received: number
+  ) {
+    super(
+      received < expected
+        ? `Replayed record: sequence ${received} arrived after ${expected - 1}`
+        : `Out-of-order record: sequence ${received} arrived when ${expected} was expected`
+    );
+    this.name = "ReplayError";
+  }
+}
+
+/**
+ * A host presented a different key than the one remembered for it; see
+ * `KnownHosts`. Either the host's key was replaced or someone is
+ * impersonating it.
+ */
+export class PeerKeyChangedError extends Error {
+  constructor(
+    /** Host the key is remembered under */
+    readonly host: string,
+    /** Fingerprint remembered for the host */
+    readonly previous: string,
+    /** Fingerprint the host presented now */
+    readonly current: string
+  ) {
+    super(`Key of ${host} changed: remembered ${previous}, presented ${current}`);
+    this.name = "PeerKeyChangedError";
   }
 }
 
+/**
+ * Where on which connection a reader or writer error occurred
+ */
+export interface ErrorContext {
+  /** Id of the stream (see `EncryptedStreamOptions.connectionId`) */
+  connectionId: string;
+  direction: ErrorDirection;
+  /** Index of the packet being read or written in that direction, from 0 */
+  sequence: number;
+}
+
 /**
  * Main error type for the Clavis library
  */
 export class ClavisError extends Error {
   public override name = "ClavisError";
-  public override cause: CryptoError | MessageError | StreamError | Error | undefined;
+  public override cause: CryptoError | MessageError | StreamError | ReplayError | PeerKeyChangedError | Error | undefined;
+  /** Set on errors from stream reads and writes */
+  public context: ErrorContext | undefined;
   constructor(
     message: string,
     cause?: CryptoError | MessageError | StreamError | Error
@@ -243,6 +453,12 @@ export class ClavisError extends Error {
     return new ClavisError(`Configuration error: ${message}`);
   }
 
+  /** A configuration error listing every conflict found */
+  static configConflicts(conflicts: readonly ConfigConflict[]): ClavisError {
+    const error = new ConfigError(conflicts);
+    return new ClavisError(`Configuration error: ${error.message}`, error);
+  }
+
   static cryptoFailure(operation: CryptoOperation, details: string): ClavisError {
     return ClavisError.crypto(CryptoError.operationFailure(operation, details));
   }
@@ -259,10 +475,58 @@ export class ClavisError extends Error {
     return ClavisError.stream(StreamError.invalidOperation(details));
   }
 
+  /** A record out of sequence; see `ReplayError` */
+  static replay(expected: number, received: number): ClavisError {
+    const error = new ReplayError(expected, received);
+    return new ClavisError(error.message, error);
+  }
+
+  /** A known host presented another key; see `PeerKeyChangedError` */
+  static peerKeyChanged(host: string, previous: string, current: string): ClavisError {
+    const error = new PeerKeyChangedError(host, previous, current);
+    return new ClavisError(error.message, error);
+  }
+
+  /** Attach connection context, keeping any context already attached */
+  withContext(context: ErrorContext): this {
+    this.context ??= context;
+    return this;
+  }
+
   isCryptoError(): boolean {
     return this.cause instanceof CryptoError;
   }
 
+  /** Why a credential was refused for its validity period, if that was the failure */
+  get validityFailure(): ValidityFailure | undefined {
+    return this.cause instanceof CryptoError ? this.cause.validity : undefined;
+  }
+
+  /** Every problem with the options, if a configuration check failed */
+  get configConflicts(): readonly ConfigConflict[] | undefined {
+    return this.cause instanceof ConfigError ? this.cause.conflicts : undefined;
+  }
+
+  /** Decompression limit a compressed packet broke, if that was the failure */
+  get decompressionLimit(): DecompressionLimit | undefined {
+    return this.cause instanceof MessageError ? this.cause.decompressionLimit : undefined;
+  }
+
+  /** Size, limit and variant of an outgoing packet refused for its size, if that was the failure */
+  get packetTooLarge(): PacketTooLarge | undefined {
+    return this.cause instanceof MessageError ? this.cause.packetTooLarge : undefined;
+  }
+
+  /** The remembered and presented keys, if a known host presented another key */
+  get peerKeyChanged(): PeerKeyChangedError | undefined {
+    return this.cause instanceof PeerKeyChangedError ? this.cause : undefined;
+  }
+
+  /** Whether a record arrived out of sequence, replayed or reordered */
+  isReplayError(): boolean {
+    return this.cause instanceof ReplayError;
+  }
+
   isMessageError(): boolean {
     return this.cause instanceof MessageError;
   }
@@ -0,0 +1,313 @@
+/**
+ * Flow control primitives
+ * Credit gates and bounded async queues shared by the streaming layers
+ */
+
+/**
+ * Credit-based send gate.
+ * Each `take()` consumes one credit, waiting until the peer grants more.
+ */
+export class CreditGate {
+  private waiters: Array<{ resolve: () => void; reject: (error: Error) => void }> = [];
+  private closedWith: Error | undefined;
+
+  constructor(private credits: number = 0) {}
+
+  /** Credits currently available */
+  get available(): number {
+    return this.credits;
+  }
+
+  /** Whether the gate has been closed */
+  get isClosed(): boolean {
+    return this.closedWith !== undefined;
+  }
+
+  /**
+   * Grant additional credits, waking waiting senders
+   */
+  add(n: number): void {
+    if (this.closedWith || n <= 0) return;
+    this.credits += n;
+    while (this.credits > 0 && this.waiters.length > 0) {
+      this.credits--;
+      this.waiters.shift()!.resolve();
+    }
+  }
+
+  /**
+   * Consume one credit, waiting if none are available
+   */
+  take(): Promise<void> {
+    if (this.closedWith) {
+      return Promise.reject(this.closedWith);
+    }
+    if (this.credits > 0) {
+      this.credits--;
+      return Promise.resolve();
+    }
+    return new Promise((resolve, reject) => {
+      this.waiters.push({ resolve, reject });
+    });
+  }
+
+  /**
+   * Close the gate; pending and future `take()` calls reject with `error`
+   */
+  close(error: Error): void {
+    if (this.closedWith) return;
+    this.closedWith = error;
+    for (const waiter of this.waiters.splice(0)) {
+      waiter.reject(error);
+    }
+  }
+}
+
+/**
+ * Async queue with a single consumer.
+ * Producers `push()` items and finish with `end()`; the consumer iterates with
+ * `for await`. `onConsume` fires after each item is handed out, which lets the
+ * owner return credits to the producer.
+ */
+export class AsyncQueue<V> implements AsyncIterable<V> {
+  private items: V[] = [];
+  private waiter: { resolve: (result: IteratorResult<V>) => void; reject: (error: Error) => void } | undefined;
+  private ended = false;
+  private failure: Error | undefined;
+
+  constructor(private readonly onConsume?: () => void) {}
+
+  /** Number of items buffered and not yet consumed */
+  get size(): number {
+    return this.items.length;
+  }
+
+  /** Whether `end()` has been called */
+  get isEnded(): boolean {
+    return this.ended;
+  }
+
+  /**
+   * Add an item. Ignored once the queue has ended.
+   */
+  push(item: V): void {
+    if (this.ended) return;
+    const waiter = this.waiter;
+    if (waiter) {
+      this.waiter = undefined;
+      waiter.resolve({ value: item, done: false });
+      this.onConsume?.();
+    } else {
+      this.items.push(item);
+    }
+  }
+
+  /**
+   * Finish the queue. Buffered items are still delivered before the
+   * end (or `error`) is reported to the consumer.
+   */
+  end(error?: Error): void {
+    if (this.ended) return;
+    this.ended = true;
+    this.failure = error;
+
+    const waiter = this.waiter;
+    if (waiter && this.items.length === 0) {
+      this.waiter = undefined;
+      if (error) {
+        waiter.reject(error);
+      } else {
+        waiter.resolve({ value: undefined, done: true });
+      }
+    }
+  }
+
+  /**
+   * Take the next item, or `done` once the queue has ended
+   */
+  next(): Promise<IteratorResult<V>> {
+    if (this.items.length > 0) {
+      const value = this.items.shift()!;
+      this.onConsume?.();
+      return Promise.resolve({ value, done: false });
+    }
+    if (this.ended) {
+      return this.failure
+        ? Promise.reject(this.failure)
+        : Promise.resolve({ value: undefined, done: true });
+    }
+    return new Promise((resolve, reject) => {
+      this.waiter = { resolve, reject };
+    });
+  }
+
+  [Symbol.asyncIterator](): AsyncIterator<V> {
+    return {
+      next: () => this.next(),
+    };
+  }
+}
+
+/**
+ * Counters of one flow of a `FairQueue`
+ */
+export interface FlowStats {
+  weight: number;
+  /** Items waiting for a turn */
+  queuedItems: number;
+  queuedBytes: number;
+  /** Items handed out so far */
+  sentItems: number;
+  sentBytes: number;
+}
+
+interface Flow<V> {
+  items: Array<{ value: V; size: number }>;
+  weight: number;
+  /** Bytes the flow may still send before its turn ends */
+  deficit: number;
+  queuedBytes: number;
+  sentItems: number;
+  sentBytes: number;
+  /** Drop the flow once it has drained */
+  forgotten: boolean;
+}
+
+/**
+ * Deficit round robin over flows of sized items.
+ * Flows with items waiting take turns; each turn a flow may hand out up to
+ * `quantum × weight` bytes plus what it couldn't use last turn because its
+ * next item didn't fit. Over time every busy flow gets bytes in proportion
+ * to its weight, however large or small its items are.
+ */
+export class FairQueue<V> {
+  private flows = new Map<number, Flow<V>>();
+  /** Flows with items waiting, in turn order; the first has the current turn */
+  private active: number[] = [];
+  /** Whether the first active flow's quantum for this turn has been added */
+  private turnStarted = false;
+  private queued = 0;
+
+  constructor(
+    private readonly quantum: number,
+    private readonly defaultWeight: (flow: number) => number = () => 1
+  ) {}
+
+  /** Items waiting across all flows */
+  get size(): number {
+    return this.queued;
+  }
+
+  /**
+   * Queue `value`, of `size` bytes, on `flow`
+   */
+  push(flow: number, value: V, size: number): void {
+    const state = this.flow(flow);
+    state.forgotten = false;
+    if (state.items.length === 0) this.active.push(flow);
+    state.items.push({ value, size });
+    state.queuedBytes += size;
+    this.queued++;
+  }
+
+  /**
+   * Take the next item in fair order, or undefined when nothing is queued
+   */
+  shift(): V | undefined {
+    while (this.active.length > 0) {
+      const id = this.active[0]!;
+      const state = this.flows.get(id)!;
+      if (!this.turnStarted) {
+        state.deficit += this.quantum * state.weight;
+        this.turnStarted = true;
+      }
+      const head = state.items[0]!;
+      if (head.size > state.deficit) {
+        // Keep the deficit; the item goes out once enough turns add up
+        this.endTurn(true);
+        continue;
+      }
+      state.items.shift();
+      state.deficit -= head.size;
+      state.queuedBytes -= head.size;
+      state.sentItems++;
+      state.sentBytes += head.size;
+      this.queued--;
+      if (state.items.length === 0) {
+        // An idle flow doesn't bank credit for later
+        state.deficit = 0;
+        this.endTurn(false);
+        if (state.forgotten) this.flows.delete(id);
+      }
+      return head.value;
+    }
+    return undefined;
+  }
+
+  /** Set the weight of `flow`; it applies from the flow's next turn */
+  setWeight(flow: number, weight: number): void {
+    this.flow(flow).weight = weight;
+  }
+
+  /** Drop the state of `flow` once its queued items have been handed out */
+  forget(flow: number): void {
+    const state = this.flows.get(flow);
+    if (!state) return;
+    if (state.items.length === 0) {
+      this.flows.delete(flow);
+    } else {
+      state.forgotten = true;
+    }
+  }
+
+  /** Remove and return every queued item */
+  clear(): V[] {
+    const items: V[] = [];
+    for (const state of this.flows.values()) {
+      for (const item of state.items) items.push(item.value);
+    }
+    this.flows.clear();
+    this.active = [];
+    this.turnStarted = false;
+    this.queued = 0;
+    return items;
+  }
+
+  /** Counters of every flow the queue knows */
+  stats(): Map<number, FlowStats> {
+    const stats = new Map<number, FlowStats>();
+    for (const [id, state] of this.flows) {
+      stats.set(id, {
+        weight: state.weight,
+        queuedItems: state.items.length,
+        queuedBytes: state.queuedBytes,
+        sentItems: state.sentItems,
+        sentBytes: state.sentBytes,
+      });
+    }
+    return stats;
+  }
+
+  private flow(id: number): Flow<V> {
+    let state = this.flows.get(id);
+    if (!state) {
+      state = {
+        items: [],
+        weight: this.defaultWeight(id),
+        deficit: 0,
+        queuedBytes: 0,
+        sentItems: 0,
+        sentBytes: 0,
+        forgotten: false,
+      };
+      this.flows.set(id, state);
+    }
+    return state;
+  }
+
+  private endTurn(requeue: boolean): void {
+    const id = this.active.shift()!;
+    if (requeue) this.active.push(id);
+    this.turnStarted = false;
+  }
+}
@@ -0,0 +1,640 @@
+/**
+ * Payload formats
+ * Selectable serialization for packet payloads: bincode, MessagePack, CBOR or JSON
+ *
+ * Bincode matches what Rust peers send by default but isn't self-describing,
+ * so decoding it needs a codec or schema. The other three carry their own
+ * structure, which makes them easier to share with peers whose bincode
+ * support is patchy. A stream's `format` option either fixes the format
+ * (both peers must set the same one) or negotiates it from a list.
+ *
+ * Values map the way serde maps them: objects become maps, `Uint8Array`
+ * becomes bytes (an array of numbers in JSON), `Date` an RFC 3339 string
+ * and bigint a 64-bit integer. Decoded integers beyond 2^53 come back as
+ * bigint from MessagePack and CBOR; JSON loses their precision.
+ *
+ * Negotiation runs over the encrypted channel after the version exchange.
+ * Wire layout:
+ * - magic: "CLVF"
+ * - count: u8
+ * - formats: count u8 ids, most preferred first
+ *   (0 = bincode, 1 = MessagePack, 2 = CBOR, 3 = JSON)
+ * Both peers pick the first format on the handshake initiator's list that
+ * the responder also offered.
+ */
+
+import { ClavisError, StreamError } from "./error.js";
+import {
+  checkDepth,
+  checkSequenceLength,
+  checkStringLength,
+  DEFAULT_MAX_DEPTH,
+  serialize as serializeBincode,
+  type DecodeLimits,
+} from "./bincode.js";
+
+/**
+ * A payload serialization format
+ */
+export type WireFormat = "bincode" | "msgpack" | "cbor" | "json";
+
+/**
+ * Encoder and decoder for one format
+ */
+export interface PayloadFormat {
+  readonly name: WireFormat;
+  /** Whether `decode` recovers the value's structure; bincode decodes to the raw bytes */
+  readonly selfDescribing: boolean;
+  encode(value: unknown): Uint8Array;
+  /** Decode a payload, within `limits` when given */
+  decode(bytes: Uint8Array, limits?: DecodeLimits): unknown;
+}
+
+const FORMAT_IDS: Record<WireFormat, number> = { bincode: 0, msgpack: 1, cbor: 2, json: 3 };
+const OFFER_MAGIC = [0x43, 0x4c, 0x56, 0x46]; // "CLVF"
+
+// ============================================================================
+// MESSAGEPACK
+// ============================================================================
+
+/** Bytes of `value` as a UTF-8 string */
+function utf8(value: string): Uint8Array {
+  return new TextEncoder().encode(value);
+}
+
+function pushUint(out: number[], value: number | bigint, bytes: number): void {
+  let big = BigInt(value);
+  const start = out.length;
+  for (let i = 0; i < bytes; i++) out.push(0);
+  for (let i = bytes - 1; i >= 0; i--) {
+    out[start + i] = Number(big & 0xffn);
+    big >>= 8n;
+  }
+}
+
+function pushFloat64(out: number[], value: number): void {
+  const bytes = new Uint8Array(8);
+  new DataView(bytes.buffer).setFloat64(0, value);
+  out.push(...bytes);
+}
+
+/** A value whose structure both self-describing formats map the same way */
+type Shape =
+  | { kind: "nil" }
+  | { kind: "bool"; value: boolean }
+  | { kind: "int"; value: bigint }
+  | { kind: "float"; value: number }
+  | { kind: "string"; value: string }
+  | { kind: "bytes"; value: Uint8Array }
+  | { kind: "array"; value: readonly unknown[] }
+  | { kind: "map"; value: Array<[unknown, unknown]> };
+
+function shapeOf(value: unknown): Shape {
+  if (value === null || value === undefined) return { kind: "nil" };
+  if (typeof value === "boolean") return { kind: "bool", value };
+  if (typeof value === "bigint") {
+    if (value < -(2n ** 63n) || value >= 2n ** 64n) {
+      throw ClavisError.serializationFailed(`Integer out of 64-bit range: ${value}`);
+    }
+    return { kind: "int", value };
+  }
+  if (typeof value === "number") {
+    return Number.isSafeInteger(value) ? { kind: "int", value: BigInt(value) } : { kind: "float", value };
+  }
+  if (typeof value === "string") return { kind: "string", value };
+  if (value instanceof Date) return { kind: "string", value: value.toISOString() };
+  if (value instanceof Uint8Array) return { kind: "bytes", value };
+  if (Array.isArray(value)) return { kind: "array", value };
+  if (value instanceof Map) return { kind: "map", value: [...value] };
+  if (typeof value === "object") {
+    return { kind: "map", value: Object.entries(value).filter(([, field]) => field !== undefined) };
+  }
+  throw ClavisError.serializationFailed(`Unsupported type: ${typeof value}`);
+}
+
+function encodeMsgpack(value: unknown, out: number[]): void {
+  const shape = shapeOf(value);
+  switch (shape.kind) {
+    case "nil":
+      out.push(0xc0);
+      return;
+    case "bool":
+      out.push(shape.value ? 0xc3 : 0xc2);
+      return;
+    case "int": {
+      const n = shape.value;
+      if (n >= 0n) {
+        if (n < 0x80n) {
+          out.push(Number(n));
+        } else {
+          const bytes = n <= 0xffn ? 1 : n <= 0xffffn ? 2 : n <= 0xffffffffn ? 4 : 8;
+          out.push({ 1: 0xcc, 2: 0xcd, 4: 0xce, 8: 0xcf }[bytes]);
+          pushUint(out, n, bytes);
+        }
+      } else if (n >= -32n) {
+        out.push(Number(n) & 0xff);
+      } else {
+        const bytes = n >= -0x80n ? 1 : n >= -0x8000n ? 2 : n >= -0x80000000n ? 4 : 8;
+        out.push({ 1: 0xd0, 2: 0xd1, 4: 0xd2, 8: 0xd3 }[bytes]);
+        pushUint(out, BigInt.asUintN(bytes * 8, n), bytes);
+      }
+      return;
+    }
+    case "float":
+      out.push(0xcb);
+      pushFloat64(out, shape.value);
+      return;
+    case "string": {
+      const bytes = utf8(shape.value);
+      if (bytes.length < 32) out.push(0xa0 | bytes.length);
+      else msgpackLength(out, bytes.length, 0xd9, 0xda, 0xdb);
+      out.push(...bytes);
+      return;
+    }
+    case "bytes":
+      msgpackLength(out, shape.value.length, 0xc4, 0xc5, 0xc6);
+      out.push(...shape.value);
+      return;
+    case "array":
+      if (shape.value.length < 16) out.push(0x90 | shape.value.length);
+      else msgpackLength(out, shape.value.length, undefined, 0xdc, 0xdd);
+      for (const item of shape.value) encodeMsgpack(item, out);
+      return;
+    case "map":
+      if (shape.value.length < 16) out.push(0x80 | shape.value.length);
+      else msgpackLength(out, shape.value.length, undefined, 0xde, 0xdf);
+      for (const [key, item] of shape.value) {
+        encodeMsgpack(key, out);
+        encodeMsgpack(item, out);
+      }
+      return;
+  }
+}
+
+function msgpackLength(out: number[], length: number, u8: number | undefined, u16: number, u32: number): void {
+  if (u8 !== undefined && length <= 0xff) {
+    out.push(u8, length);
+  } else if (length <= 0xffff) {
+    out.push(u16);
+    pushUint(out, length, 2);
+  } else {
+    out.push(u32);
+    pushUint(out, length, 4);
+  }
+}
+
+/** Bounds-checked reads over an encoded payload */
+class ByteReader {
+  private offset = 0;
+  private readonly view: DataView;
+
+  constructor(private readonly bytes: Uint8Array, readonly limits: DecodeLimits | undefined) {
+    this.view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+  }
+
+  get done(): boolean {
+    return this.offset === this.bytes.length;
+  }
+
+  get remaining(): number {
+    return this.bytes.length - this.offset;
+  }
+
+  private need(length: number): number {
+    const at = this.offset;
+    if (at + length > this.bytes.length) {
+      throw ClavisError.deserializationFailed("Payload ended unexpectedly");
+    }
+    this.offset += length;
+    return at;
+  }
+
+  u8(): number {
+    return this.view.getUint8(this.need(1));
+  }
+
+  uint(bytes: 1 | 2 | 4 | 8): bigint {
+    const at = this.need(bytes);
+    switch (bytes) {
+      case 1:
+        return BigInt(this.view.getUint8(at));
+      case 2:
+        return BigInt(this.view.getUint16(at));
+      case 4:
+        return BigInt(this.view.getUint32(at));
+      case 8:
+        return this.view.getBigUint64(at);
+    }
+  }
+
+  length(bytes: 1 | 2 | 4 | 8): number {
+    const length = this.uint(bytes);
+    // Every item takes at least a byte, so a longer count can't be honest
+    if (length > BigInt(this.remaining)) {
+      throw ClavisError.deserializationFailed("Length exceeds the payload");
+    }
+    return Number(length);
+  }
+
+  float(bytes: 2 | 4 | 8): number {
+    const at = this.need(bytes);
+    if (bytes === 4) return this.view.getFloat32(at);
+    if (bytes === 8) return this.view.getFloat64(at);
+    const half = this.view.getUint16(at);
+    const exponent = (half >> 10) & 0x1f;
+    const fraction = half & 0x3ff;
+    const sign = half & 0x8000 ? -1 : 1;
+    if (exponent === 0) return sign * fraction * 2 ** -24;
+    if (exponent === 0x1f) return fraction === 0 ? sign * Infinity : NaN;
+    return sign * (1 + fraction / 1024) * 2 ** (exponent - 15);
+  }
+
+  take(length: number): Uint8Array {
+    const at = this.need(length);
+    return this.bytes.slice(at, at + length);
+  }
+
+  /** Check an array or map's item count against the limits */
+  sequence(length: number): number {
+    checkSequenceLength(length, this.limits);
+    return length;
+  }
+
+  text(length: number): string {
+    checkStringLength(length, this.limits);
+    const at = this.need(length);
+    return new TextDecoder("utf-8", { fatal: true }).decode(this.bytes.subarray(at, at + length));
+  }
+}
+
+/** Integers as numbers when they fit exactly, bigint otherwise */
+function integer(value: bigint): number | bigint {
+  return value >= BigInt(Number.MIN_SAFE_INTEGER) && value <= BigInt(Number.MAX_SAFE_INTEGER) ? Number(value) : value;
+}
+
+/** Decoded maps become objects, keyed by the string form of their keys */
+function mapEntries(entries: Array<[unknown, unknown]>): Record<string, unknown> {
+  const object: Record<string, unknown> = {};
+  for (const [key, value] of entries) {
+    Object.defineProperty(object, String(key), { value, enumerable: true, writable: true, configurable: true });
+  }
+  return object;
+}
+
+function decodeMsgpack(reader: ByteReader, depth: number): unknown {
+  checkDepth(depth, reader.limits);
+  const byte = reader.u8();
+  if (byte < 0x80) return byte;
+  if (byte >= 0xe0) return byte - 0x100;
+  if ((byte & 0xf0) === 0x80) return decodeMsgpackMap(reader, byte & 0x0f, depth);
+  if ((byte & 0xf0) === 0x90) return decodeMsgpackArray(reader, byte & 0x0f, depth);
+  if ((byte & 0xe0) === 0xa0) return reader.text(byte & 0x1f);
+  switch (byte) {
+    case 0xc0:
+      return null;
+    case 0xc2:
+      return false;
+    case 0xc3:
+      return true;
+    case 0xc4:
+      return reader.take(reader.length(1));
+    case 0xc5:
+      return reader.take(reader.length(2));
+    case 0xc6:
+      return reader.take(reader.length(4));
+    case 0xca:
+      return reader.float(4);
+    case 0xcb:
+      return reader.float(8);
+    case 0xcc:
+      return integer(reader.uint(1));
+    case 0xcd:
+      return integer(reader.uint(2));
+    case 0xce:
+      return integer(reader.uint(4));
+    case 0xcf:
+      return integer(reader.uint(8));
+    case 0xd0:
+      return integer(BigInt.asIntN(8, reader.uint(1)));
+    case 0xd1:
+      return integer(BigInt.asIntN(16, reader.uint(2)));
+    case 0xd2:
+      return integer(BigInt.asIntN(32, reader.uint(4)));
+    case 0xd3:
+      return integer(BigInt.asIntN(64, reader.uint(8)));
+    case 0xd9:
+      return reader.text(reader.length(1));
+    case 0xda:
+      return reader.text(reader.length(2));
+    case 0xdb:
+      return reader.text(reader.length(4));
+    case 0xdc:
+      return decodeMsgpackArray(reader, reader.length(2), depth);
+    case 0xdd:
+      return decodeMsgpackArray(reader, reader.length(4), depth);
+    case 0xde:
+      return decodeMsgpackMap(reader, reader.length(2), depth);
+    case 0xdf:
+      return decodeMsgpackMap(reader, reader.length(4), depth);
+  }
+  throw ClavisError.deserializationFailed(`Unsupported MessagePack type 0x${byte.toString(16)}`);
+}
+
+function decodeMsgpackArray(reader: ByteReader, length: number, depth: number): unknown[] {
+  return Array.from({ length: reader.sequence(length) }, () => decodeMsgpack(reader, depth + 1));
+}
+
+function decodeMsgpackMap(reader: ByteReader, length: number, depth: number): Record<string, unknown> {
+  return mapEntries(
+    Array.from({ length: reader.sequence(length) }, () => [decodeMsgpack(reader, depth + 1), decodeMsgpack(reader, depth + 1)])
+  );
+}
+
+// ============================================================================
+// CBOR
+// ============================================================================
+
+function cborHead(out: number[], major: number, argument: number | bigint): void {
+  const n = BigInt(argument);
+  const type = major << 5;
+  if (n < 24n) {
+    out.push(type | Number(n));
+    return;
+  }
+  const bytes = n <= 0xffn ? 1 : n <= 0xffffn ? 2 : n <= 0xffffffffn ? 4 : 8;
+  out.push(type | { 1: 24, 2: 25, 4: 26, 8: 27 }[bytes]);
+  pushUint(out, n, bytes);
+}
+
+function encodeCbor(value: unknown, out: number[]): void {
+  const shape = shapeOf(value);
+  switch (shape.kind) {
+    case "nil":
+      out.push(0xf6);
+      return;
+    case "bool":
+      out.push(shape.value ? 0xf5 : 0xf4);
+      return;
+    case "int":
+      if (shape.value >= 0n) cborHead(out, 0, shape.value);
+      else cborHead(out, 1, -1n - shape.value);
+      return;
+    case "float":
+      out.push(0xfb);
+      pushFloat64(out, shape.value);
+      return;
+    case "string": {
+      const bytes = utf8(shape.value);
+      cborHead(out, 3, bytes.length);
+      out.push(...bytes);
+      return;
+    }
+    case "bytes":
+      cborHead(out, 2, shape.value.length);
+      out.push(...shape.value);
+      return;
+    case "array":
+      cborHead(out, 4, shape.value.length);
+      for (const item of shape.value) encodeCbor(item, out);
+      return;
+    case "map":
+      cborHead(out, 5, shape.value.length);
+      for (const [key, item] of shape.value) {
+        encodeCbor(key, out);
+        encodeCbor(item, out);
+      }
+      return;
+  }
+}
+
+function decodeCbor(reader: ByteReader, depth: number): unknown {
+  checkDepth(depth, reader.limits);
+  const initial = reader.u8();
+  const major = initial >> 5;
+  const info = initial & 0x1f;
+  if (major === 7) {
+    switch (info) {
+      case 20:
+        return false;
+      case 21:
+        return true;
+      case 22:
+      case 23:
+        return null;
+      case 25:
+        return reader.float(2);
+      case 26:
+        return reader.float(4);
+      case 27:
+        return reader.float(8);
+    }
+    throw ClavisError.deserializationFailed(`Unsupported CBOR simple value ${info}`);
+  }
+  if (info > 27) {
+    throw ClavisError.deserializationFailed("Indefinite-length CBOR items are not supported");
+  }
+  const argument = info < 24 ? BigInt(info) : reader.uint(([1, 2, 4, 8] as const)[info - 24]!);
+  // Every item takes at least a byte, so a longer count can't be honest
+  const count = () => {
+    if (argument > BigInt(reader.remaining)) throw ClavisError.deserializationFailed("Length exceeds the payload");
+    return Number(argument);
+  };
+  switch (major) {
+    case 0:
+      return integer(argument);
+    case 1:
+      return integer(-1n - argument);
+    case 2:
+      return reader.take(count());
+    case 3:
+      return reader.text(count());
+    case 4:
+      return Array.from({ length: reader.sequence(count()) }, () => decodeCbor(reader, depth + 1));
+    case 5:
+      return mapEntries(
+        Array.from({ length: reader.sequence(count()) }, () => [decodeCbor(reader, depth + 1), decodeCbor(reader, depth + 1)])
+      );
+    default:
+      // Tags (major 6) annotate the item that follows; the item is what matters here
+      return decodeCbor(reader, depth + 1);
+  }
+}
+
+// ============================================================================
+// JSON
+// ============================================================================
+
+function toJson(value: unknown, depth: number): string {
+  if (depth > DEFAULT_MAX_DEPTH) throw ClavisError.serializationFailed("Value nested too deeply");
+  if (value === null || value === undefined) return "null";
+  if (typeof value === "bigint") return value.toString();
+  if (typeof value === "number") {
+    if (!Number.isFinite(value)) throw ClavisError.serializationFailed(`JSON can't carry ${value}`);
+    return JSON.stringify(value);
+  }
+  if (typeof value === "boolean" || typeof value === "string") return JSON.stringify(value);
+  if (value instanceof Date) return JSON.stringify(value.toISOString());
+  if (value instanceof Uint8Array) return `[${value.join(",")}]`;
+  if (Array.isArray(value)) return `[${value.map((item) => toJson(item, depth + 1)).join(",")}]`;
+  const entries = value instanceof Map ? [...value] : typeof value === "object" ? Object.entries(value) : undefined;
+  if (!entries) throw ClavisError.serializationFailed(`Unsupported type: ${typeof value}`);
+  const fields = entries
+    .filter(([, field]) => field !== undefined)
+    .map(([key, field]) => `${JSON.stringify(String(key))}:${toJson(field, depth + 1)}`);
+  return `{${fields.join(",")}}`;
+}
+
+/**
+ * Refuse JSON nested deeper than `maxDepth` before parsing it, so the
+ * parser's recursion stays bounded
+ */
+function checkJsonDepth(text: string, limits: DecodeLimits | undefined): void {
+  let depth = 0;
+  let inString = false;
+  for (let i = 0; i < text.length; i++) {
+    const ch = text[i];
+    if (inString) {
+      if (ch === "\\") i++;
+      else if (ch === '"') inString = false;
+    } else if (ch === '"') {
+      inString = true;
+    } else if (ch === "[" || ch === "{") {
+      checkDepth(++depth, limits);
+    } else if (ch === "]" || ch === "}") {
+      depth--;
+    }
+  }
+}
+
+/** JSON.parse has no length limits, so check the parsed value instead */
+function checkJsonLengths(value: unknown, limits: DecodeLimits): void {
+  if (typeof value === "string") {
+    checkStringLength(utf8(value).length, limits);
+  } else if (Array.isArray(value)) {
+    checkSequenceLength(value.length, limits);
+    for (const item of value) checkJsonLengths(item, limits);
+  } else if (value !== null && typeof value === "object") {
+    const entries = Object.entries(value);
+    checkSequenceLength(entries.length, limits);
+    for (const [key, item] of entries) {
+      checkStringLength(utf8(key).length, limits);
+      checkJsonLengths(item, limits);
+    }
+  }
+}
+
+// ============================================================================
+// FORMATS
+// ============================================================================
+
+const FORMATS: Record<WireFormat, PayloadFormat> = {
+  bincode: {
+    name: "bincode",
+    selfDescribing: false,
+    encode: (value) => serializeBincode(value),
+    decode: (bytes) => bytes,
+  },
+  msgpack: {
+    name: "msgpack",
+    selfDescribing: true,
+    encode(value) {
+      const out: number[] = [];
+      encodeMsgpack(value, out);
+      return new Uint8Array(out);
+    },
+    decode: (bytes, limits) => decodeWhole(bytes, limits, (reader) => decodeMsgpack(reader, 0)),
+  },
+  cbor: {
+    name: "cbor",
+    selfDescribing: true,
+    encode(value) {
+      const out: number[] = [];
+      encodeCbor(value, out);
+      return new Uint8Array(out);
+    },
+    decode: (bytes, limits) => decodeWhole(bytes, limits, (reader) => decodeCbor(reader, 0)),
+  },
+  json: {
+    name: "json",
+    selfDescribing: true,
+    encode: (value) => utf8(toJson(value, 0)),
+    decode(bytes, limits) {
+      let value: unknown;
+      try {
+        const text = new TextDecoder("utf-8", { fatal: true }).decode(bytes);
+        checkJsonDepth(text, limits);
+        value = JSON.parse(text) as unknown;
+      } catch (error) {
+        if (error instanceof ClavisError) throw error;
+        throw ClavisError.deserializationFailed(`Invalid JSON payload: ${error instanceof Error ? error.message : String(error)}`);
+      }
+      // JSON.parse runs in one go, so the budget can only be checked after it
+      limits?.budget?.checkNow();
+      if (limits?.maxSequenceLength !== undefined || limits?.maxStringLength !== undefined) {
+        checkJsonLengths(value, limits);
+      }
+      return value;
+    },
+  },
+};
+
+function decodeWhole(bytes: Uint8Array, limits: DecodeLimits | undefined, decode: (reader: ByteReader) => unknown): unknown {
+  const reader = new ByteReader(bytes, limits);
+  const value = decode(reader);
+  if (!reader.done) throw ClavisError.deserializationFailed("Trailing bytes after the payload");
+  return value;
+}
+
+/**
+ * The encoder and decoder for `format`
+ */
+export function payloadFormat(format: WireFormat): PayloadFormat {
+  const found = FORMATS[format] as PayloadFormat | undefined;
+  if (!found) throw ClavisError.config(`Unknown payload format ${String(format)}`);
+  return found;
+}
+
+/**
+ * Encode this side's format offer, most preferred first
+ */
+export function encodeFormatOffer(formats: readonly WireFormat[]): Uint8Array {
+  if (formats.length === 0 || formats.length > 255) {
+    throw ClavisError.config("Offer between 1 and 255 payload formats");
+  }
+  return new Uint8Array([...OFFER_MAGIC, formats.length, ...formats.map((format) => FORMAT_IDS[payloadFormat(format).name])]);
+}
+
+/**
+ * Decode the peer's format offer, skipping formats this side doesn't know
+ */
+export function decodeFormatOffer(offer: Uint8Array): WireFormat[] {
+  const magic = OFFER_MAGIC.every((b, i) => offer[i] === b);
+  if (!magic) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Peer did not negotiate a payload format"));
+  }
+  const count = offer[4];
+  if (count === undefined || offer.length !== 5 + count) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Malformed payload format offer"));
+  }
+  const byId = new Map(Object.entries(FORMAT_IDS).map(([name, id]) => [id, name as WireFormat]));
+  return [...offer.subarray(5)].flatMap((id) => byId.get(id) ?? []);
+}
+
+/**
+ * Pick the first format on the initiator's list that the responder offered.
+ * Both peers compute the same answer.
+ */
+export function chooseFormat(
+  ours: readonly WireFormat[],
+  theirs: readonly WireFormat[],
+  initiator: boolean
+): WireFormat {
+  const [preferred, other] = initiator ? [ours, theirs] : [theirs, ours];
+  const chosen = preferred.find((format) => other.includes(format));
+  if (chosen === undefined) {
+    throw ClavisError.stream(StreamError.handshakeFailed(
+      `No payload format in common: this side offers ${ours.join(", ")}, the peer ${theirs.join(", ") || "none this side knows"}`
+    ));
+  }
+  return chosen;
+}
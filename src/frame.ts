@@ -0,0 +1,218 @@
+/**
+ * Frame codec
+ * The unit clavis puts on the wire, independent of any transport
+ *
+ * Layout: length (u32 little-endian) | nonce (24) | ciphertext (length bytes,
+ * Poly1305 tag included). The top bit of the length marks a control frame,
+ * whose ciphertext is sealed with `CONTROL_AAD` as associated data;
+ * application frames have none. `EncryptedStream` is built from these
+ * functions, so a custom transport (a message queue, a datagram socket, a
+ * test harness) that uses them produces and accepts exactly the same bytes.
+ *
+ * Nonces are random, so frames need no sequence state: any frame sealed
+ * with a key can be opened with the matching key in any order. Replay and
+ * reordering protection is up to the transport, unless both peers turn on
+ * `sequenceNumbers`: each side then numbers its frames from 0, control
+ * frames included, and writes the number into the first 8 bytes of the
+ * nonce (u64 little-endian, the other 16 stay random). The nonce is
+ * authenticated, so a receiver that opened a frame knows its number is
+ * genuine and can refuse one that isn't next.
+ */
+
+import { XChaCha20Poly1305Cipher, type FrameCipher } from "./crypto.js";
+import { ClavisError, MessageError } from "./error.js";
+import { CONTROL_AAD, CONTROL_FRAME_FLAG } from "./control.js";
+
+/** Bytes of the length prefix */
+export const FRAME_HEADER_LENGTH = 4;
+
+/** Bytes of the XChaCha20 nonce after the length prefix */
+export const FRAME_NONCE_LENGTH = 24;
+
+/** Bytes of the nonce that carry the frame's sequence number, with `sequenceNumbers` */
+export const FRAME_SEQUENCE_LENGTH = 8;
+
+/** Bytes of the Poly1305 tag at the end of every ciphertext */
+export const FRAME_TAG_LENGTH = 16;
+
+/** Bytes a frame adds to a serialized packet: length (4) + nonce (24) + Poly1305 tag (16) */
+export const FRAME_OVERHEAD = FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH + FRAME_TAG_LENGTH;
+
+/** Largest ciphertext length that fits next to the control flag */
+export const MAX_CIPHERTEXT_LENGTH = CONTROL_FRAME_FLAG - 1;
+
+/**
+ * Decoded length prefix
+ */
+export interface FrameHeader {
+  /** Ciphertext length, tag included (excludes the nonce) */
+  length: number;
+  /** Whether the frame carries a control message */
+  control: boolean;
+}
+
+/**
+ * A frame split into its parts, still encrypted
+ */
+export interface RawFrame extends FrameHeader {
+  nonce: Uint8Array;
+  ciphertext: Uint8Array;
+}
+
+/**
+ * Assemble a wire frame: length (u32 little-endian) + nonce + ciphertext
+ */
+export function encodeFrame(nonce: Uint8Array, ciphertext: Uint8Array, control: boolean = false): Uint8Array {
+  if (nonce.length !== FRAME_NONCE_LENGTH) {
+    throw ClavisError.message(MessageError.invalidFormat(`Frame nonces are ${FRAME_NONCE_LENGTH} bytes`));
+  }
+  if (ciphertext.length > MAX_CIPHERTEXT_LENGTH) {
+    throw ClavisError.message(MessageError.messageTooLarge(ciphertext.length, MAX_CIPHERTEXT_LENGTH));
+  }
+  const frame = new Uint8Array(FRAME_HEADER_LENGTH + nonce.length + ciphertext.length);
+  const header = control ? ciphertext.length + CONTROL_FRAME_FLAG : ciphertext.length;
+  new DataView(frame.buffer).setUint32(0, header, true);
+  frame.set(nonce, FRAME_HEADER_LENGTH);
+  frame.set(ciphertext, FRAME_HEADER_LENGTH + nonce.length);
+  return frame;
+}
+
+/**
+ * Read the length prefix at the start of `bytes`
+ */
+export function decodeFrameHeader(bytes: Uint8Array): FrameHeader {
+  if (bytes.length < FRAME_HEADER_LENGTH) {
+    throw ClavisError.message(MessageError.invalidFormat("Frame header is 4 bytes"));
+  }
+  const header = new DataView(bytes.buffer, bytes.byteOffset, FRAME_HEADER_LENGTH).getUint32(0, true);
+  const control = header >= CONTROL_FRAME_FLAG;
+  return { length: control ? header - CONTROL_FRAME_FLAG : header, control };
+}
+
+/**
+ * Split one complete frame into its parts. `bytes` must hold exactly one frame.
+ */
+export function decodeFrame(bytes: Uint8Array): RawFrame {
+  const header = decodeFrameHeader(bytes);
+  if (bytes.length !== FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH + header.length) {
+    throw ClavisError.message(MessageError.invalidFormat("Frame length does not match its header"));
+  }
+  const body = FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH;
+  return {
+    ...header,
+    nonce: bytes.subarray(FRAME_HEADER_LENGTH, body),
+    ciphertext: bytes.subarray(body),
+  };
+}
+
+/**
+ * A random nonce carrying `sequence` in its first 8 bytes
+ */
+export function sequenceNonce(sequence: number): Uint8Array {
+  const nonce = XChaCha20Poly1305Cipher.generateNonce();
+  new DataView(nonce.buffer, nonce.byteOffset, FRAME_SEQUENCE_LENGTH).setBigUint64(0, BigInt(sequence), true);
+  return nonce;
+}
+
+/** The sequence number in a nonce from `sequenceNonce()` */
+export function nonceSequence(nonce: Uint8Array): number {
+  return Number(new DataView(nonce.buffer, nonce.byteOffset, FRAME_SEQUENCE_LENGTH).getBigUint64(0, true));
+}
+
+/**
+ * Encrypt `plaintext` under `nonce`, by default a fresh random one, and frame it
+ */
+export function sealFrame(
+  cipher: FrameCipher,
+  plaintext: Uint8Array,
+  control: boolean = false,
+  nonce: Uint8Array = XChaCha20Poly1305Cipher.generateNonce()
+): Uint8Array {
+  return encodeFrame(nonce, cipher.encrypt(nonce, plaintext, control ? CONTROL_AAD : undefined), control);
+}
+
+/**
+ * Seal like `sealFrame`, but into `output`, which must have room for the
+ * whole frame; returns the frame as a view of `output`. Ciphers without
+ * `encryptInto` seal with `encrypt()`, and the frame is allocated.
+ */
+export function sealFrameInto(cipher: FrameCipher, plaintext: Uint8Array, output: Uint8Array, nonce?: Uint8Array): Uint8Array {
+  const length = plaintext.length + FRAME_TAG_LENGTH;
+  const size = FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH + length;
+  if (!cipher.encryptInto) return sealFrame(cipher, plaintext, false, nonce);
+  if (length > MAX_CIPHERTEXT_LENGTH) {
+    throw ClavisError.message(MessageError.messageTooLarge(length, MAX_CIPHERTEXT_LENGTH));
+  }
+  if (output.length < size) {
+    throw ClavisError.invalidOperation(`Frame needs ${size} bytes, output has ${output.length}`);
+  }
+  const frame = output.subarray(0, size);
+  new DataView(frame.buffer, frame.byteOffset, FRAME_HEADER_LENGTH).setUint32(0, length, true);
+  const nonceSlot = frame.subarray(FRAME_HEADER_LENGTH, FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH);
+  if (nonce) nonceSlot.set(nonce);
+  else crypto.getRandomValues(nonceSlot);
+  cipher.encryptInto(nonceSlot, plaintext, frame.subarray(FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH));
+  return frame;
+}
+
+/**
+ * Decrypt a frame's ciphertext. Fails with an authentication error if it was
+ * tampered with, sealed with another key or had its control flag flipped.
+ */
+export function openFrame(decipher: FrameCipher, frame: RawFrame): Uint8Array {
+  return decipher.decrypt(frame.nonce, frame.ciphertext, frame.control ? CONTROL_AAD : undefined);
+}
+
+/**
+ * Incremental frame parser for transports that deliver bytes in arbitrary chunks
+ *
+ * @example
+ * ```typescript
+ * const decoder = new FrameDecoder(65536 + FRAME_TAG_LENGTH);
+ * socket.on("data", (chunk) => {
+ *   decoder.push(chunk);
+ *   for (let frame = decoder.next(); frame; frame = decoder.next()) {
+ *     handle(openFrame(decipher, frame));
+ *   }
+ * });
+ * ```
+ */
+export class FrameDecoder {
+  private buffer = new Uint8Array(0);
+
+  /**
+   * @param maxLength - Largest ciphertext accepted; a longer header fails
+   *   before its body is buffered
+   */
+  constructor(private readonly maxLength: number = MAX_CIPHERTEXT_LENGTH) {}
+
+  /** Append received bytes */
+  push(chunk: Uint8Array): void {
+    const joined = new Uint8Array(this.buffer.length + chunk.length);
+    joined.set(this.buffer, 0);
+    joined.set(chunk, this.buffer.length);
+    this.buffer = joined;
+  }
+
+  /** Next complete frame, or undefined until more bytes arrive */
+  next(): RawFrame | undefined {
+    if (this.buffer.length < FRAME_HEADER_LENGTH) return undefined;
+    const header = decodeFrameHeader(this.buffer);
+    if (header.length > this.maxLength) {
+      throw ClavisError.message(MessageError.messageTooLarge(header.length, this.maxLength));
+    }
+    if (header.length < FRAME_TAG_LENGTH) {
+      throw ClavisError.message(MessageError.invalidFormat("Frame is shorter than its tag"));
+    }
+    const end = FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH + header.length;
+    if (this.buffer.length < end) return undefined;
+    const frame = decodeFrame(this.buffer.slice(0, end));
+    this.buffer = this.buffer.subarray(end);
+    return frame;
+  }
+
+  /** Bytes received but not yet returned as frames */
+  get buffered(): number {
+    return this.buffer.length;
+  }
+}
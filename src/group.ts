@@ -0,0 +1,666 @@
+/**
+ * Group sessions
+ * End-to-end encrypted group messaging through a relay that can't read it
+ *
+ * Every member holds a pairwise clavis session with the relay, which only
+ * forwards. The members share a group key the relay never sees: each one
+ * announces an X25519 public key when joining, and the group's leader, the
+ * member that has been in the group longest, wraps a fresh key for every
+ * other member with the X25519 secret it shares with them. Group messages
+ * are sealed with that key, so the relay passes on ciphertext only.
+ *
+ * The leader replaces the key whenever someone joins or leaves, so a new
+ * member can't read what was sent before it joined and a departed one
+ * can't read what is sent after. Members hold their sends until the new
+ * key arrives, and keep the previous key to read messages that were
+ * already on their way. Each key names the members it was handed out to,
+ * so during churn a member waits for the key that matches its own view of
+ * the group rather than sending under one a newcomer never got.
+ *
+ * The leader also manages the group: it can rotate the key on demand,
+ * remove a member (the relay disconnects it before the replacement key
+ * goes out) and, on an invite-only relay, invite members, since only
+ * invited names may join a group that already exists.
+ *
+ * Relay envelopes (u8 kind, then bincode fields):
+ *   0 Join     group: string, member: string, public key: [u8; 32]
+ *   1 Members  count: u64, (member: string, public key: [u8; 32]) in join order
+ *   2 Joined   member: string, public key: [u8; 32]
+ *   3 Left     member: string
+ *   4 Send     to: string ("" for everyone else), payload: bytes
+ *   5 Deliver  from: string, payload: bytes
+ *   6 Invite   member: string (leader only)
+ *   7 Remove   member: string (leader only)
+ * Group payloads, inside Send and Deliver (u8 kind, u32 epoch, 24-byte nonce, ciphertext):
+ *   0 Key      the group key and the members it went to (count: u64, member: string...), sealed with
+ *              HKDF(X25519(leader, member), SHA-256(group), "clavis group key wrap") and bound to
+ *              group, epoch, leader and member
+ *   1 Message  sender: string, sequence: u64, payload, sealed with the group key and bound to group and epoch
+ *
+ * The relay still sees who is in which group and when they send. It also
+ * hands out the members' public keys, so a relay that substitutes its own
+ * could read along; `verifyMember` checks each key against something the
+ * relay doesn't control, such as fingerprints compared out of band. Any
+ * member can claim to be another inside the group, since they share a key.
+ */
+
+import type { EncryptedStream } from "./stream.js";
+import { BincodeReader, writeString, writeU32, writeU64 } from "./bincode.js";
+import {
+  computeSharedSecret,
+  generateRandomBytes,
+  generateX25519KeyPair,
+  hkdfExpand,
+  sha256Hash,
+  XChaCha20Poly1305Cipher,
+  type X25519KeyPair,
+} from "./crypto.js";
+import { ClavisError, StreamError } from "./error.js";
+import { keyFingerprint } from "./identity.js";
+import { RawPacket } from "./protocol.js";
+import { AsyncQueue } from "./flow-control.js";
+
+/**
+ * Relay envelope kinds
+ */
+export enum GroupEnvelopeKind {
+  Join = 0,
+  Members = 1,
+  Joined = 2,
+  Left = 3,
+  Send = 4,
+  Deliver = 5,
+  Invite = 6,
+  Remove = 7,
+}
+
+enum GroupPayloadKind {
+  Key = 0,
+  Message = 1,
+}
+
+const KEY_LENGTH = 32;
+const NONCE_LENGTH = 24;
+const KEY_WRAP_INFO = "clavis group key wrap";
+const MESSAGE_AAD = "clavis group message";
+
+const encoder = new TextEncoder();
+
+function bytesField(buffer: number[], bytes: Uint8Array): void {
+  writeU64(buffer, BigInt(bytes.length));
+  for (const byte of bytes) buffer.push(byte);
+}
+
+function envelope(kind: GroupEnvelopeKind, fields: (buffer: number[]) => void): RawPacket {
+  const buffer: number[] = [kind];
+  fields(buffer);
+  return new RawPacket(new Uint8Array(buffer));
+}
+
+function readKey(reader: BincodeReader): Uint8Array {
+  return reader.readRawBytes(KEY_LENGTH);
+}
+
+function associatedData(...parts: Array<string | number>): Uint8Array {
+  const buffer: number[] = [];
+  for (const part of parts) {
+    if (typeof part === "number") writeU32(buffer, part);
+    else writeString(buffer, part);
+  }
+  return new Uint8Array(buffer);
+}
+
+/**
+ * Something that happened in the group
+ */
+export type GroupEvent =
+  | { type: "message"; from: string; payload: Uint8Array }
+  | { type: "joined"; member: string }
+  | { type: "left"; member: string };
+
+/**
+ * A member joining or leaving, as passed to `onMembershipChange`
+ */
+export type GroupMembershipEvent = Exclude<GroupEvent, { type: "message" }>;
+
+/**
+ * A snapshot of the group as one member sees it, safe to log or show
+ */
+export interface GroupInfo {
+  group: string;
+  /** The member this snapshot is from */
+  member: string;
+  leader: string;
+  epoch: number;
+  /** Accepted members in join order, with the fingerprint of the key each announced */
+  members: Array<{ member: string; fingerprint: string }>;
+}
+
+/**
+ * Options for `GroupSession.join`
+ */
+export interface GroupSessionOptions {
+  /** Group to join; the relay creates it for its first member */
+  group: string;
+  /** This member's name, unique in the group */
+  member: string;
+  /**
+   * X25519 key pair announced to the other members (default: a fresh one).
+   * Keep the default unless `verifyMember` checks keys against a fixed list.
+   */
+  keyPair?: X25519KeyPair | undefined;
+  /**
+   * Accept another member's announced public key (default: accept all).
+   * Members refused here get no group key, and their messages are dropped.
+   */
+  verifyMember?: ((member: string, publicKey: Uint8Array) => boolean) | undefined;
+  /** Called after a member joins or leaves, with the group as it is now */
+  onMembershipChange?: ((event: GroupMembershipEvent, info: GroupInfo) => void) | undefined;
+  /** Called when a new group key is in use, with its epoch */
+  onKeyRotated?: ((epoch: number) => void) | undefined;
+}
+
+interface GroupKey {
+  epoch: number;
+  cipher: XChaCha20Poly1305Cipher;
+  /** Members the key was handed out to */
+  roster: string[];
+}
+
+/**
+ * A member's view of an end-to-end encrypted group, over its clavis session with a relay
+ *
+ * @example
+ * ```typescript
+ * const chat = await GroupSession.join(await connectToRelay(), { group: "ops", member: "ada" });
+ * await chat.send(new TextEncoder().encode("deploying now"));
+ * for await (const event of chat) {
+ *   if (event.type === "message") console.log(event.from, new TextDecoder().decode(event.payload));
+ * }
+ * ```
+ */
+export class GroupSession implements AsyncIterable<GroupEvent> {
+  /** Accepted members, this one included, in the order they joined */
+  private readonly members = new Map<string, Uint8Array>();
+  private readonly events = new AsyncQueue<GroupEvent>();
+  private readonly lastSequence = new Map<string, bigint>();
+  /** Members announced by the relay that `verifyMember` refused */
+  private readonly refused = new Set<string>();
+  private current: GroupKey | undefined;
+  private previous: GroupKey | undefined;
+  private keyReady!: Promise<void>;
+  private resolveKey: (() => void) | undefined;
+  private failure: ClavisError | undefined;
+  private sequence = 0n;
+
+  private constructor(
+    private readonly stream: EncryptedStream,
+    private readonly group: string,
+    private readonly member: string,
+    private readonly keyPair: X25519KeyPair,
+    private readonly options: GroupSessionOptions
+  ) {
+    this.awaitKey();
+  }
+
+  /**
+   * Join `group` through the relay at the other end of `stream`. Resolves
+   * once the relay listed the members; `send()` waits for the group key.
+   */
+  static async join(stream: EncryptedStream, options: GroupSessionOptions): Promise<GroupSession> {
+    const keyPair = options.keyPair ?? generateX25519KeyPair();
+    const session = new GroupSession(stream, options.group, options.member, keyPair, options);
+    await stream.writePacket(envelope(GroupEnvelopeKind.Join, (buffer) => {
+      writeString(buffer, options.group);
+      writeString(buffer, options.member);
+      for (const byte of keyPair.publicKey) buffer.push(byte);
+    }));
+    const reader = new BincodeReader(await stream.readPacketView());
+    if (reader.readU8() !== GroupEnvelopeKind.Members) {
+      throw ClavisError.stream(StreamError.handshakeFailed("Relay did not list the group's members"));
+    }
+    const count = Number(reader.readU64());
+    for (let i = 0; i < count; i++) {
+      const member = reader.readString();
+      session.admit(member, readKey(reader));
+    }
+    if (!session.members.has(options.member)) {
+      throw ClavisError.stream(StreamError.handshakeFailed("Relay left this member out of the group"));
+    }
+    session.rekeyOrWait();
+    void session.receive();
+    return session;
+  }
+
+  /** Members this side accepted, itself included, in the order they joined */
+  get memberNames(): string[] {
+    return [...this.members.keys()];
+  }
+
+  /** Member that hands out the group key: the one in the group longest */
+  get leader(): string {
+    return this.members.keys().next().value!;
+  }
+
+  /** Number of the group key in use, or 0 before the first one arrived */
+  get epoch(): number {
+    return this.current?.epoch ?? 0;
+  }
+
+  /**
+   * Encrypt `payload` with the group key and have the relay pass it to
+   * every other member. Waits while a new key is being handed out.
+   */
+  async send(payload: Uint8Array): Promise<void> {
+    await this.keyReady;
+    if (this.failure) throw this.failure;
+    const { epoch, cipher } = this.current!;
+    const plaintext: number[] = [];
+    writeString(plaintext, this.member);
+    writeU64(plaintext, this.sequence++);
+    for (const byte of payload) plaintext.push(byte);
+    await this.relay("", GroupPayloadKind.Message, epoch, cipher, new Uint8Array(plaintext), associatedData(MESSAGE_AAD, this.group, epoch));
+  }
+
+  /** Messages and membership changes, until the connection to the relay ends */
+  [Symbol.asyncIterator](): AsyncIterator<GroupEvent> {
+    return this.events[Symbol.asyncIterator]();
+  }
+
+  /** The group as this member sees it */
+  groupInfo(): GroupInfo {
+    return {
+      group: this.group,
+      member: this.member,
+      leader: this.leader,
+      epoch: this.epoch,
+      members: [...this.members].map(([member, publicKey]) => ({ member, fingerprint: keyFingerprint(publicKey) })),
+    };
+  }
+
+  /**
+   * Invite `member` into the group, for relays that only let invited names
+   * join. Leader only; the invitation is used up when it joins.
+   */
+  async addMember(member: string): Promise<void> {
+    this.requireLeader("invite members");
+    await this.stream.writePacket(envelope(GroupEnvelopeKind.Invite, (buffer) => writeString(buffer, member)));
+  }
+
+  /**
+   * Have the relay disconnect `member`, or withdraw its invitation, and hand
+   * the others a key it never had. Leader only.
+   */
+  async removeMember(member: string): Promise<void> {
+    this.requireLeader("remove members");
+    if (member === this.member) {
+      throw ClavisError.invalidOperation("The leader leaves with leave(), not removeMember()");
+    }
+    await this.stream.writePacket(envelope(GroupEnvelopeKind.Remove, (buffer) => writeString(buffer, member)));
+    if (this.members.delete(member)) {
+      this.lastSequence.delete(member);
+      this.membershipEvent({ type: "left", member });
+      this.rotate();
+    }
+  }
+
+  /**
+   * Hand out a new group key without a membership change, for example on
+   * a schedule. Leader only; returns the new epoch.
+   */
+  rotateKey(): number {
+    this.requireLeader("rotate the group key");
+    if (this.failure) throw this.failure;
+    return this.rotate();
+  }
+
+  /** Leave the group by closing the connection to the relay */
+  async leave(): Promise<void> {
+    await this.stream.close().catch(() => undefined);
+  }
+
+  private async receive(): Promise<void> {
+    try {
+      for (;;) {
+        const reader = new BincodeReader(await this.stream.readPacketView());
+        const kind = reader.readU8() as GroupEnvelopeKind;
+        if (kind === GroupEnvelopeKind.Joined) {
+          const member = reader.readString();
+          if (this.admit(member, readKey(reader))) {
+            this.membershipEvent({ type: "joined", member });
+            this.rekeyOrWait();
+          }
+        } else if (kind === GroupEnvelopeKind.Left) {
+          const member = reader.readString();
+          this.refused.delete(member);
+          if (member === this.member) {
+            // Removed by the leader; the relay closes its end next
+            this.fail(ClavisError.invalidOperation(`Removed from group ${this.group}`));
+            this.events.push({ type: "left", member });
+            void this.leave();
+          } else if (this.members.delete(member)) {
+            this.lastSequence.delete(member);
+            this.membershipEvent({ type: "left", member });
+            this.rekeyOrWait();
+          }
+        } else if (kind === GroupEnvelopeKind.Deliver) {
+          const from = reader.readString();
+          this.deliver(from, reader.readBytes());
+        }
+      }
+    } catch (error) {
+      const failure = error instanceof ClavisError ? error : ClavisError.stream(StreamError.io(error as Error));
+      this.fail(failure);
+      const cause = failure.cause;
+      this.events.end(cause instanceof StreamError && cause.isConnectionClosed() ? undefined : failure);
+    }
+  }
+
+  /** Record a member, unless `verifyMember` refuses its key */
+  private admit(member: string, publicKey: Uint8Array): boolean {
+    const { verifyMember } = this.options;
+    if (member !== this.member && verifyMember && !verifyMember(member, publicKey)) {
+      this.refused.add(member);
+      return false;
+    }
+    this.members.set(member, publicKey);
+    return true;
+  }
+
+  private membershipEvent(event: GroupMembershipEvent): void {
+    this.events.push(event);
+    this.options.onMembershipChange?.(event, this.groupInfo());
+  }
+
+  private requireLeader(action: string): void {
+    if (this.leader !== this.member) {
+      throw ClavisError.invalidOperation(`Only the group's leader (${this.leader}) can ${action}`);
+    }
+  }
+
+  /** Hand out a new key when leading; otherwise hold sends until a key for the current members is in */
+  private rekeyOrWait(): void {
+    if (this.leader === this.member) this.rotate();
+    else if (this.current && this.coversMembers(this.current.roster)) this.keyIsReady();
+    else this.awaitKey();
+  }
+
+  /** Wrap a fresh key for every other member and start using it */
+  private rotate(): number {
+    const epoch = this.epoch + 1;
+    const key = generateRandomBytes(KEY_LENGTH);
+    const roster = this.memberNames;
+    const plaintext: number[] = [...key];
+    writeU64(plaintext, BigInt(roster.length));
+    for (const member of roster) writeString(plaintext, member);
+    for (const [member, publicKey] of this.members) {
+      if (member === this.member) continue;
+      const wrap = new XChaCha20Poly1305Cipher(this.wrappingKey(publicKey));
+      void this.relay(member, GroupPayloadKind.Key, epoch, wrap, new Uint8Array(plaintext), associatedData(KEY_WRAP_INFO, this.group, epoch, this.member, member))
+        .catch(() => undefined);
+    }
+    this.install(epoch, key, roster);
+    return epoch;
+  }
+
+  /** Whether a key handed out to `roster` reached exactly the members this side accepted */
+  private coversMembers(roster: string[]): boolean {
+    const accepted = roster.filter((member) => !this.refused.has(member));
+    return accepted.length === this.members.size && accepted.every((member) => this.members.has(member));
+  }
+
+  private deliver(from: string, payload: Uint8Array): void {
+    const publicKey = this.members.get(from);
+    if (!publicKey || payload.length < 5 + NONCE_LENGTH) return;
+    const kind = payload[0] as GroupPayloadKind;
+    const epoch = new DataView(payload.buffer, payload.byteOffset).getUint32(1, true);
+    const nonce = payload.subarray(5, 5 + NONCE_LENGTH);
+    const sealed = payload.subarray(5 + NONCE_LENGTH);
+    try {
+      if (kind === GroupPayloadKind.Key) {
+        // Only the leader hands out keys, and only newer ones
+        if (from !== this.leader || epoch <= this.epoch) return;
+        const wrap = new XChaCha20Poly1305Cipher(this.wrappingKey(publicKey));
+        const reader = new BincodeReader(wrap.decrypt(nonce, sealed, associatedData(KEY_WRAP_INFO, this.group, epoch, from, this.member)));
+        const key = reader.readRawBytes(KEY_LENGTH);
+        const roster: string[] = [];
+        for (let count = Number(reader.readU64()); count > 0; count--) roster.push(reader.readString());
+        this.install(epoch, key, roster);
+      } else if (kind === GroupPayloadKind.Message) {
+        const key = [this.current, this.previous].find((k) => k?.epoch === epoch);
+        if (!key) return;
+        const reader = new BincodeReader(key.cipher.decrypt(nonce, sealed, associatedData(MESSAGE_AAD, this.group, epoch)));
+        const sender = reader.readString();
+        const sequence = reader.readU64();
+        const last = this.lastSequence.get(from);
+        if (sender !== from || (last !== undefined && sequence <= last)) return;
+        this.lastSequence.set(from, sequence);
+        this.events.push({ type: "message", from, payload: reader.readRawBytes(reader.remaining) });
+      }
+    } catch {
+      // Sealed for someone else or tampered with on the way: nothing to deliver
+    }
+  }
+
+  private install(epoch: number, key: Uint8Array, roster: string[]): void {
+    this.previous = this.current;
+    this.current = { epoch, cipher: new XChaCha20Poly1305Cipher(key), roster };
+    this.options.onKeyRotated?.(epoch);
+    if (this.coversMembers(roster)) this.keyIsReady();
+  }
+
+  private keyIsReady(): void {
+    this.resolveKey?.();
+    this.resolveKey = undefined;
+  }
+
+  private awaitKey(): void {
+    if (this.resolveKey) return;
+    this.keyReady = new Promise((resolve) => {
+      this.resolveKey = resolve;
+    });
+  }
+
+  private wrappingKey(publicKey: Uint8Array): Uint8Array {
+    const shared = computeSharedSecret(this.keyPair.secret, publicKey);
+    return hkdfExpand(shared, sha256Hash(encoder.encode(this.group)), KEY_WRAP_INFO);
+  }
+
+  private relay(
+    to: string,
+    kind: GroupPayloadKind,
+    epoch: number,
+    cipher: XChaCha20Poly1305Cipher,
+    plaintext: Uint8Array,
+    aad: Uint8Array
+  ): Promise<number> {
+    const nonce = XChaCha20Poly1305Cipher.generateNonce();
+    const sealed = cipher.encrypt(nonce, plaintext, aad);
+    const payload = new Uint8Array(5 + NONCE_LENGTH + sealed.length);
+    payload[0] = kind;
+    new DataView(payload.buffer).setUint32(1, epoch, true);
+    payload.set(nonce, 5);
+    payload.set(sealed, 5 + NONCE_LENGTH);
+    return this.stream.writePacket(envelope(GroupEnvelopeKind.Send, (buffer) => {
+      writeString(buffer, to);
+      bytesField(buffer, payload);
+    }));
+  }
+
+  private fail(error: ClavisError): void {
+    if (this.failure) return;
+    this.failure = error;
+    this.resolveKey?.();
+    this.resolveKey = undefined;
+  }
+}
+
+interface RelayMember {
+  publicKey: Uint8Array;
+  stream: EncryptedStream;
+  /** Bytes forwarded to the member that its transport hasn't taken yet */
+  pendingBytes: number;
+}
+
+const DEFAULT_MAX_PENDING_BYTES = 1024 * 1024;
+
+/**
+ * Options for `GroupRelay`
+ */
+export interface GroupRelayOptions {
+  /**
+   * Only let names the group's leader invited join a group that already
+   * exists (default: false). Whoever joins first creates the group.
+   */
+  inviteOnly?: boolean | undefined;
+  /**
+   * Bytes the relay holds for one member before it drops it (default:
+   * 1 MiB). A member that stops reading would otherwise make the relay
+   * buffer everything its group sends; once it falls this far behind its
+   * connection is destroyed and the others see it leave.
+   */
+  maxPendingBytes?: number | undefined;
+}
+
+/**
+ * Forwards group traffic between members' clavis sessions without being
+ * able to read it; one instance serves any number of groups
+ *
+ * @example
+ * ```typescript
+ * const relay = new GroupRelay();
+ * for await (const stream of listener) void relay.serve(stream).catch((error) => log.warn(error));
+ * ```
+ */
+export class GroupRelay {
+  private readonly groups = new Map<string, Map<string, RelayMember>>();
+  /** Names invited into each group that haven't joined yet */
+  private readonly invitations = new Map<string, Set<string>>();
+
+  constructor(private readonly options: GroupRelayOptions = {}) {}
+
+  /** Names of the members of `group`, in the order they joined */
+  members(group: string): string[] {
+    return [...(this.groups.get(group)?.keys() ?? [])];
+  }
+
+  /**
+   * Serve one member's connection: take its join, then forward what it
+   * sends until the connection ends, when the group learns it left
+   */
+  async serve(stream: EncryptedStream): Promise<void> {
+    const join = new BincodeReader(await stream.readPacketView());
+    if (join.readU8() !== GroupEnvelopeKind.Join) {
+      await stream.close().catch(() => undefined);
+      throw ClavisError.invalidOperation("Expected a group join");
+    }
+    const group = join.readString();
+    const name = join.readString();
+    const publicKey = readKey(join);
+    const members = this.groups.get(group) ?? new Map<string, RelayMember>();
+    if (members.has(name)) {
+      await stream.close().catch(() => undefined);
+      throw ClavisError.invalidOperation(`Member ${name} is already in group ${group}`);
+    }
+    if (this.options.inviteOnly && members.size > 0 && !this.invitations.get(group)?.delete(name)) {
+      await stream.close().catch(() => undefined);
+      throw ClavisError.invalidOperation(`Member ${name} was not invited into group ${group}`);
+    }
+    this.groups.set(group, members);
+    members.set(name, { publicKey, stream, pendingBytes: 0 });
+
+    // Written without awaiting, so every member sees events in the relay's order
+    this.forward(members, name, envelope(GroupEnvelopeKind.Members, (buffer) => {
+      writeU64(buffer, BigInt(members.size));
+      for (const [member, { publicKey }] of members) {
+        writeString(buffer, member);
+        for (const byte of publicKey) buffer.push(byte);
+      }
+    }));
+    this.toOthers(members, name, envelope(GroupEnvelopeKind.Joined, (buffer) => {
+      writeString(buffer, name);
+      for (const byte of publicKey) buffer.push(byte);
+    }));
+
+    try {
+      for (;;) {
+        const reader = new BincodeReader(await stream.readPacketView());
+        const kind = reader.readU8() as GroupEnvelopeKind;
+        if (kind === GroupEnvelopeKind.Invite || kind === GroupEnvelopeKind.Remove) {
+          // Membership is managed by the leader alone, the member in the group longest
+          if (members.keys().next().value === name) this.manage(group, members, kind, reader.readString());
+          continue;
+        }
+        if (kind !== GroupEnvelopeKind.Send) continue;
+        const to = reader.readString();
+        const deliver = envelope(GroupEnvelopeKind.Deliver, (buffer) => {
+          writeString(buffer, name);
+          bytesField(buffer, reader.readBytesView());
+        });
+        if (to === "") {
+          this.toOthers(members, name, deliver);
+        } else {
+          if (to !== name) this.forward(members, to, deliver);
+        }
+      }
+    } catch (error) {
+      const cause = (error as ClavisError).cause;
+      if (!(cause instanceof StreamError && cause.isConnectionClosed())) throw error;
+    } finally {
+      // A removed member already left
+      if (members.get(name)?.stream === stream) {
+        members.delete(name);
+        this.toOthers(members, name, envelope(GroupEnvelopeKind.Left, (buffer) => writeString(buffer, name)));
+      }
+      if (members.size === 0 && this.groups.get(group) === members) {
+        this.groups.delete(group);
+        this.invitations.delete(group);
+      }
+      await stream.close().catch(() => undefined);
+    }
+  }
+
+  private manage(group: string, members: Map<string, RelayMember>, kind: GroupEnvelopeKind, member: string): void {
+    const invited = this.invitations.get(group) ?? new Set<string>();
+    if (kind === GroupEnvelopeKind.Invite) {
+      if (!members.has(member)) this.invitations.set(group, invited.add(member));
+      return;
+    }
+    invited.delete(member);
+    const removed = members.get(member);
+    if (!removed) return;
+    const left = envelope(GroupEnvelopeKind.Left, (buffer) => writeString(buffer, member));
+    this.forward(members, member, left);
+    // Dropped instead if it had fallen behind, and the others were told then
+    if (!members.delete(member)) return;
+    this.toOthers(members, member, left);
+    removed.stream.close().catch(() => undefined);
+  }
+
+  private toOthers(members: Map<string, RelayMember>, except: string, packet: RawPacket): void {
+    for (const member of members.keys()) {
+      if (member !== except) this.forward(members, member, packet);
+    }
+  }
+
+  /**
+   * Write `packet` to `member` without waiting for it, or drop the member
+   * if that would put it more than `maxPendingBytes` behind
+   */
+  private forward(members: Map<string, RelayMember>, member: string, packet: RawPacket): void {
+    const target = members.get(member);
+    if (!target) return;
+    const size = packet.bytes.length;
+    if (target.pendingBytes + size > (this.options.maxPendingBytes ?? DEFAULT_MAX_PENDING_BYTES)) {
+      members.delete(member);
+      // Its serve() loop sees the connection closed and finds it gone already
+      target.stream.destroy();
+      this.toOthers(members, member, envelope(GroupEnvelopeKind.Left, (buffer) => writeString(buffer, member)));
+      return;
+    }
+    target.pendingBytes += size;
+    target.stream.writePacket(packet).then(() => {
+      target.pendingBytes -= size;
+    }, () => undefined);
+  }
+}
@@ -0,0 +1,240 @@
+/**
+ * Session handoff
+ * Carries an established connection over to another process, for
+ * zero-downtime binary upgrades
+ *
+ * `EncryptedStream.exportState()` detaches the stream from its socket and
+ * returns everything the record layer needs to carry on: the current
+ * traffic keys and rekey chain, the frame counters, the limits, versions
+ * and payload format agreed during setup, the verified peer, and the
+ * bytes received that no read took yet. The new process passes the socket
+ * and the state to `EncryptedStream.importState()` and reads and writes as
+ * if nothing happened; the peer never notices.
+ *
+ * The state holds live session keys in the clear: anyone who reads it can
+ * decrypt and forge the connection's traffic. Both sides of the handoff
+ * need the `dangerousSessionHandoff` option, and the state should only
+ * travel over the channel that carries the socket itself, such as the IPC
+ * channel of `child_process.fork()`.
+ */
+
+import {
+  BincodeReader,
+  writeBool,
+  writeOptionString,
+  writeOptionU32,
+  writeString,
+  writeU64,
+} from "./bincode.js";
+import { ClavisError } from "./error.js";
+import type { HandshakeTimings } from "./handshake.js";
+import type { PeerIdentity } from "./identity.js";
+import type { WireFormat } from "./formats.js";
+
+const MAGIC = [0x43, 0x4c, 0x56, 0x48]; // "CLVH"
+const VERSION = 1;
+const FORMATS: readonly WireFormat[] = ["bincode", "msgpack", "cbor", "json"];
+const KEY_LENGTH = 32;
+
+/**
+ * An established session, as exported for another process
+ */
+export interface SessionState {
+  connectionId: string;
+  /** Name of the cipher suite; a custom one must be listed in `cipherSuites` on import */
+  suite: string;
+  /** Traffic keys in use, after any rekeys */
+  sendingKey: Uint8Array;
+  receivingKey: Uint8Array;
+  /** Secret the next rekey chains to; undefined where rekeying isn't possible */
+  rekeyChain: Uint8Array | undefined;
+  rekeys: number;
+  exporterSecret: Uint8Array;
+  sessionId: string;
+  pskIdentity: string | undefined;
+  postQuantum: boolean;
+  resumed: boolean;
+  /** The verified peer, without the certificate it presented */
+  peer: PeerIdentity | undefined;
+  readLimit: number;
+  writeLimit: number;
+  /** Whether `writeLimit` is the receive limit the peer advertised */
+  writeLimitFromPeer: boolean;
+  sequenceNumbers: boolean;
+  /** Frames sealed and opened so far, control frames included */
+  recordsSent: number;
+  recordsReceived: number;
+  /** Packets written and read so far, and how many of them were setup exchanges */
+  packetsSent: number;
+  packetsReceived: number;
+  setupPacketsSent: number;
+  setupPacketsReceived: number;
+  readProtocolVersion: number | undefined;
+  writeProtocolVersion: number | undefined;
+  format: WireFormat | undefined;
+  timeSync: boolean;
+  timings: HandshakeTimings;
+  /** Bytes received from the peer that no read took yet */
+  unread: Uint8Array;
+}
+
+function writeBytes(buffer: number[], bytes: Uint8Array): void {
+  writeU64(buffer, BigInt(bytes.length));
+  for (const byte of bytes) buffer.push(byte);
+}
+
+function writeCount(buffer: number[], n: number): void {
+  writeU64(buffer, BigInt(n));
+}
+
+function writeF64(buffer: number[], n: number): void {
+  const bytes = new Uint8Array(8);
+  new DataView(bytes.buffer).setFloat64(0, n, true);
+  for (const byte of bytes) buffer.push(byte);
+}
+
+function readKey(reader: BincodeReader): Uint8Array {
+  return reader.readRawBytes(KEY_LENGTH);
+}
+
+function readCount(reader: BincodeReader): number {
+  return Number(reader.readU64());
+}
+
+function readF64(reader: BincodeReader): number {
+  return new DataView(reader.readRawBytes(8).buffer).getFloat64(0, true);
+}
+
+function readOneOf<T extends string>(reader: BincodeReader, values: readonly T[], what: string): T {
+  const value = reader.readString();
+  if (!values.includes(value as T)) {
+    throw ClavisError.deserializationFailed(`Unknown ${what} ${value} in session state`);
+  }
+  return value as T;
+}
+
+/**
+ * Encode `state` for `decodeSessionState()`. Layout after magic and
+ * version: bincode fields in the order of `SessionState`.
+ */
+export function encodeSessionState(state: SessionState): Uint8Array {
+  const buffer: number[] = [...MAGIC, VERSION];
+  writeString(buffer, state.connectionId);
+  writeString(buffer, state.suite);
+  buffer.push(...state.sendingKey, ...state.receivingKey);
+  writeBool(buffer, state.rekeyChain !== undefined);
+  if (state.rekeyChain) buffer.push(...state.rekeyChain);
+  writeCount(buffer, state.rekeys);
+  buffer.push(...state.exporterSecret);
+  writeString(buffer, state.sessionId);
+  writeOptionString(buffer, state.pskIdentity);
+  writeBool(buffer, state.postQuantum);
+  writeBool(buffer, state.resumed);
+  writeBool(buffer, state.peer !== undefined);
+  if (state.peer) {
+    writeString(buffer, state.peer.identity);
+    writeString(buffer, state.peer.credentialId);
+    writeF64(buffer, state.peer.expiresAt.getTime());
+    writeBool(buffer, state.peer.publicKey !== undefined);
+    if (state.peer.publicKey) buffer.push(...state.peer.publicKey);
+  }
+  writeCount(buffer, state.readLimit);
+  writeCount(buffer, state.writeLimit);
+  writeBool(buffer, state.writeLimitFromPeer);
+  writeBool(buffer, state.sequenceNumbers);
+  writeCount(buffer, state.recordsSent);
+  writeCount(buffer, state.recordsReceived);
+  writeCount(buffer, state.packetsSent);
+  writeCount(buffer, state.packetsReceived);
+  writeCount(buffer, state.setupPacketsSent);
+  writeCount(buffer, state.setupPacketsReceived);
+  writeOptionU32(buffer, state.readProtocolVersion);
+  writeOptionU32(buffer, state.writeProtocolVersion);
+  writeOptionString(buffer, state.format);
+  writeBool(buffer, state.timeSync);
+  const { firstFlightMs, keyExchangeMs, confirmationMs, cryptoMs, setupMs, totalMs } = state.timings;
+  for (const ms of [firstFlightMs, keyExchangeMs, confirmationMs, cryptoMs, setupMs, totalMs]) writeF64(buffer, ms);
+  writeBytes(buffer, state.unread);
+  return new Uint8Array(buffer);
+}
+
+/**
+ * Decode a state from `encodeSessionState()`; throws a deserialization
+ * error for anything else
+ */
+export function decodeSessionState(data: Uint8Array): SessionState {
+  if (!MAGIC.every((b, i) => data[i] === b) || data[MAGIC.length] !== VERSION) {
+    throw ClavisError.deserializationFailed("Not an exported session state");
+  }
+  const reader = new BincodeReader(data);
+  reader.skip(MAGIC.length + 1);
+  const connectionId = reader.readString();
+  const suite = reader.readString();
+  const sendingKey = readKey(reader);
+  const receivingKey = readKey(reader);
+  const rekeyChain = reader.readBool() ? readKey(reader) : undefined;
+  const rekeys = readCount(reader);
+  const exporterSecret = readKey(reader);
+  const sessionId = reader.readString();
+  const pskIdentity = reader.readOptionString();
+  const postQuantum = reader.readBool();
+  const resumed = reader.readBool();
+  let peer: PeerIdentity | undefined;
+  if (reader.readBool()) {
+    const identity = reader.readString();
+    const credentialId = reader.readString();
+    const expiresAt = new Date(readF64(reader));
+    const publicKey = reader.readBool() ? readKey(reader) : undefined;
+    peer = { identity, credentialId, expiresAt, publicKey };
+  }
+  const readLimit = readCount(reader);
+  const writeLimit = readCount(reader);
+  const writeLimitFromPeer = reader.readBool();
+  const sequenceNumbers = reader.readBool();
+  const recordsSent = readCount(reader);
+  const recordsReceived = readCount(reader);
+  const packetsSent = readCount(reader);
+  const packetsReceived = readCount(reader);
+  const setupPacketsSent = readCount(reader);
+  const setupPacketsReceived = readCount(reader);
+  const readProtocolVersion = reader.readOptionU32();
+  const writeProtocolVersion = reader.readOptionU32();
+  const format = reader.readBool() ? readOneOf(reader, FORMATS, "payload format") : undefined;
+  const timeSync = reader.readBool();
+  const [firstFlightMs, keyExchangeMs, confirmationMs, cryptoMs, setupMs, totalMs] =
+    Array.from({ length: 6 }, () => readF64(reader)) as [number, number, number, number, number, number];
+  const unread = reader.readBytes();
+  if (reader.hasMore) {
+    throw ClavisError.deserializationFailed("Trailing bytes after session state");
+  }
+  return {
+    connectionId,
+    suite,
+    sendingKey,
+    receivingKey,
+    rekeyChain,
+    rekeys,
+    exporterSecret,
+    sessionId,
+    pskIdentity,
+    postQuantum,
+    resumed,
+    peer,
+    readLimit,
+    writeLimit,
+    writeLimitFromPeer,
+    sequenceNumbers,
+    recordsSent,
+    recordsReceived,
+    packetsSent,
+    packetsReceived,
+    setupPacketsSent,
+    setupPacketsReceived,
+    readProtocolVersion,
+    writeProtocolVersion,
+    format,
+    timeSync,
+    timings: { firstFlightMs, keyExchangeMs, confirmationMs, cryptoMs, setupMs, totalMs },
+    unread,
+  };
+}
@@ -0,0 +1,184 @@
+/**
+ * Handshake messages
+ * The handshake's wire messages and the computations over them, shared by
+ * `performHandshake`, the wire spec and external tools
+ *
+ * Every message is a fixed-size byte string with no framing:
+ * 1. Both sides send a 32-byte random nonce; the greater one (compared
+ *    lexicographically) makes its sender the initiator. When PSK identities
+ *    are in use, each side follows it with a 64-byte identity: UTF-8,
+ *    zero-padded, all zeros for none.
+ * 2. The initiator sends its ephemeral X25519 public key, then the responder.
+ * 3. With a pre-shared key, the initiator sends HMAC-SHA256(psk, transcript),
+ *    then the responder.
+ *
+ * This module is part of the versioned wire contract: a change to anything
+ * here changes the bytes on the wire and ships only in a major release.
+ * Analyzers and alternative implementations can rely on it to match
+ * `EncryptedStream` exactly.
+ */
+
+import { hkdfExpand, hmacSha256, sha256Hash } from "./crypto.js";
+import { ClavisError, CryptoError } from "./error.js";
+
+/** Version of the handshake described here */
+export const HANDSHAKE_VERSION = 1;
+
+/** Handshake message kinds, in the order they are sent */
+export type HandshakeMessageKind = "nonce" | "psk_identity" | "public_key" | "mac";
+
+/** Bytes in each kind of handshake message */
+export const HANDSHAKE_MESSAGE_LENGTHS: Readonly<Record<HandshakeMessageKind, number>> = {
+  nonce: 32,
+  psk_identity: 64,
+  public_key: 32,
+  mac: 32,
+};
+
+/**
+ * One handshake message as sent on the wire
+ */
+export interface HandshakeMessage {
+  kind: HandshakeMessageKind;
+  bytes: Uint8Array;
+}
+
+/**
+ * One step of the handshake: who sends which message
+ */
+export interface HandshakeStepTemplate {
+  sender: "both" | "initiator" | "responder";
+  kind: HandshakeMessageKind;
+}
+
+/**
+ * Keys both sides derive once the handshake completes
+ */
+export interface HandshakeKeys {
+  encKey: Uint8Array;
+  decKey: Uint8Array;
+  transcriptHash: Uint8Array;
+  resumptionSecret: Uint8Array;
+}
+
+/**
+ * Check a message's length and return its bytes for writing
+ */
+export function encodeHandshakeMessage(message: HandshakeMessage): Uint8Array {
+  return decodeHandshakeMessage(message.kind, message.bytes).bytes;
+}
+
+/**
+ * Interpret bytes read from the wire as a message of `kind`
+ */
+export function decodeHandshakeMessage(kind: HandshakeMessageKind, bytes: Uint8Array): HandshakeMessage {
+  const length = HANDSHAKE_MESSAGE_LENGTHS[kind];
+  if (bytes.length !== length) {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial(`Handshake ${kind} must be ${length} bytes`));
+  }
+  return { kind, bytes };
+}
+
+/**
+ * Messages of a handshake in order, with or without a pre-shared key and
+ * PSK identities
+ */
+export function handshakeSteps(withPsk: boolean, withPskIdentity = false): HandshakeStepTemplate[] {
+  const steps: HandshakeStepTemplate[] = [{ sender: "both", kind: "nonce" }];
+  if (withPskIdentity) {
+    steps.push({ sender: "both", kind: "psk_identity" });
+  }
+  steps.push(
+    { sender: "initiator", kind: "public_key" },
+    { sender: "responder", kind: "public_key" },
+  );
+  if (withPsk) {
+    steps.push({ sender: "initiator", kind: "mac" }, { sender: "responder", kind: "mac" });
+  }
+  return steps;
+}
+
+/**
+ * PSK identity message announcing `identity`; the empty string announces none
+ */
+export function encodePskIdentity(identity: string): Uint8Array {
+  const bytes = new TextEncoder().encode(identity);
+  if (bytes.includes(0)) {
+    throw ClavisError.config("PSK identity must not contain NUL characters");
+  }
+  if (bytes.length > HANDSHAKE_MESSAGE_LENGTHS.psk_identity) {
+    throw ClavisError.config(`PSK identity must be at most ${HANDSHAKE_MESSAGE_LENGTHS.psk_identity} bytes of UTF-8, got ${bytes.length}`);
+  }
+  const message = new Uint8Array(HANDSHAKE_MESSAGE_LENGTHS.psk_identity);
+  message.set(bytes, 0);
+  return message;
+}
+
+/**
+ * Identity a PSK identity message announces
+ */
+export function decodePskIdentity(message: Uint8Array): string {
+  decodeHandshakeMessage("psk_identity", message);
+  let end = message.length;
+  while (end > 0 && message[end - 1] === 0) end--;
+  const bytes = message.subarray(0, end);
+  if (bytes.includes(0)) {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("PSK identity must be zero-padded UTF-8"));
+  }
+  try {
+    return new TextDecoder("utf-8", { fatal: true }).decode(bytes);
+  } catch {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("PSK identity must be zero-padded UTF-8"));
+  }
+}
+
+/**
+ * Whether the side that sent `localNonce` is the initiator.
+ * Compares lexicographically, like Rust's `Ord` for byte arrays; equal nonces
+ * make both sides responders, which fails the handshake.
+ */
+export function isHandshakeInitiator(localNonce: Uint8Array, peerNonce: Uint8Array): boolean {
+  decodeHandshakeMessage("nonce", localNonce);
+  decodeHandshakeMessage("nonce", peerNonce);
+  for (let i = 0; i < localNonce.length; i++) {
+    const local = localNonce[i]!;
+    const peer = peerNonce[i]!;
+    if (local !== peer) return local > peer;
+  }
+  return false;
+}
+
+/**
+ * Transcript both sides authenticate: initiator key first, then the responder's
+ */
+export function handshakeTranscript(initiatorKey: Uint8Array, responderKey: Uint8Array): Uint8Array {
+  decodeHandshakeMessage("public_key", initiatorKey);
+  decodeHandshakeMessage("public_key", responderKey);
+  const transcript = new Uint8Array(64);
+  transcript.set(initiatorKey, 0);
+  transcript.set(responderKey, 32);
+  return transcript;
+}
+
+/**
+ * MAC message proving knowledge of the pre-shared key; identical on both sides
+ */
+export function handshakeMac(psk: Uint8Array, transcript: Uint8Array): Uint8Array {
+  return hmacSha256(psk, transcript);
+}
+
+/**
+ * Derive session keys from the X25519 shared secret. The initiator encrypts
+ * with info "enc" and decrypts with info "dec", the responder the reverse.
+ */
+export function deriveHandshakeKeys(sharedSecret: Uint8Array, transcript: Uint8Array, initiator: boolean): HandshakeKeys {
+  const transcriptHash = sha256Hash(transcript);
+  const enc = hkdfExpand(sharedSecret, transcriptHash, "enc");
+  const dec = hkdfExpand(sharedSecret, transcriptHash, "dec");
+  return {
+    encKey: initiator ? enc : dec,
+    decKey: initiator ? dec : enc,
+    transcriptHash,
+    resumptionSecret: hkdfExpand(sharedSecret, transcriptHash, "resumption"),
+  };
+}
@@ -3,62 +3,306 @@
  * Matches Rust clavis handshake protocol exactly
  */
 
+import { generateRandomBytes } from "./crypto.js";
 import {
-  generateX25519KeyPair,
-  computeSharedSecret,
-  sha256Hash,
-  hmacSha256,
-  hkdfExpand,
-  generateRandomBytes,
-} from "./crypto.js";
+  inMemoryKeyExchange,
+  generateEphemeralKey,
+  agreeWith,
+  type KeyExchangeKey,
+  type KeyExchangeProvider,
+} from "./key-provider.js";
 import { ClavisError, CryptoError } from "./error.js";
+import { systemClock, type Clock } from "./clock.js";
+import {
+  HANDSHAKE_MESSAGE_LENGTHS,
+  decodeHandshakeMessage,
+  deriveHandshakeKeys,
+  encodeHandshakeMessage,
+  handshakeMac,
+  handshakeTranscript,
+  isHandshakeInitiator,
+  encodePskIdentity,
+  decodePskIdentity,
+  type HandshakeMessageKind,
+} from "./handshake-messages.js";
 
 export interface HandshakeResult {
   encKey: Uint8Array; // 32 bytes encryption key
   decKey: Uint8Array; // 32 bytes decryption key
+  transcriptHash: Uint8Array; // 32 bytes, identical on both sides
+  initiator: boolean; // which role this side took
+  resumptionSecret: Uint8Array; // 32 bytes, identical on both sides; keys session tickets
+  pskIdentity?: string | undefined; // identity the peer announced with PSK identities, if any
 }
 
 /**
- * Determine role (initiator/responder) by comparing nonces
- * Returns true if we are the initiator (our nonce > peer nonce)
- * Uses lexicographic comparison (same as Rust's byte array comparison)
+ * Pre-shared keys chosen by identity. Each side announces an identity right
+ * after its nonce and picks the key for the one the peer announced, so a
+ * server can accept an old and a new key while clients move over.
  */
-function compareNonces(localNonce: Uint8Array, peerNonce: Uint8Array): boolean {
-  if (localNonce.length !== 32 || peerNonce.length !== 32) {
-    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("Nonces must be 32 bytes"));
-  }
-  
-  // Lexicographic comparison (from index 0 to 31, like Rust's Ord for [u8])
-  for (let i = 0; i < 32; i++) {
-    const local = localNonce[i]!;
-    const peer = peerNonce[i]!;
-    if (local > peer) {
-      return true; // We are initiator
-    } else if (local < peer) {
-      return false; // We are responder
-    }
-  }
-  return false; // Equal (shouldn't happen with random nonces)
+export interface PskSelection {
+  /** Identity this side announces; empty for none */
+  identity: string;
+  /** Key for the identity the peer announced, or undefined to refuse it */
+  keyFor(peerIdentity: string): Uint8Array | undefined;
+}
+
+/**
+ * Time spent in each stage of setting up a connection, in milliseconds.
+ * The stages run back to back and, with `setupMs`, add up to `totalMs`;
+ * `cryptoMs` is the CPU part of the key exchange and confirmation, so the
+ * rest of those is network latency.
+ */
+export interface HandshakeTimings {
+  /** Sending our nonce and receiving the peer's: one round trip, nearly all network */
+  firstFlightMs: number;
+  /** Exchanging public keys, including key generation and X25519 (and key derivation without a PSK) */
+  keyExchangeMs: number;
+  /** Exchanging PSK confirmation MACs and deriving the keys (0 without a PSK) */
+  confirmationMs: number;
+  /** Key generation, X25519, MACs and key derivation within the stages above */
+  cryptoMs: number;
+  /**
+   * Exchanges after the handshake: post-quantum keys, identities, resumption
+   * and compression (0 for a bare `performHandshake()`)
+   */
+  setupMs: number;
+  /** From the first handshake message until the connection was ready */
+  totalMs: number;
 }
 
 /**
- * Construct transcript with initiator's key first, then responder's key
- * This ensures both sides compute the MAC over the same data regardless of role
+ * Handshake result with the time each stage took
  */
-function constructTranscript(
-  initiatorKey: Uint8Array,
-  responderKey: Uint8Array
-): Uint8Array {
-  const transcriptData = new Uint8Array(64);
-  transcriptData.set(initiatorKey, 0);
-  transcriptData.set(responderKey, 32);
-  return transcriptData;
+export interface TimedHandshakeResult extends HandshakeResult {
+  timings: HandshakeTimings;
+}
+
+/**
+ * The handshake as a state machine that does no I/O of its own
+ *
+ * Bytes from the peer go in through `feedBytes()` and bytes for the peer come
+ * out of `takeOutput()`, so the same handshake runs over a socket, a
+ * synchronous server loop, a message queue or a test harness. Input may be
+ * fed in chunks of any size; bytes past the end of the handshake are left
+ * unconsumed for the caller to pass on.
+ *
+ * @example
+ * ```typescript
+ * const machine = new HandshakeMachine(psk);
+ * socket.write(machine.takeOutput()); // our nonce
+ * socket.on("data", (chunk) => {
+ *   const used = machine.feedBytes(chunk);
+ *   socket.write(machine.takeOutput());
+ *   if (machine.done) start(machine.result(), chunk.subarray(used));
+ * });
+ * ```
+ */
+export class HandshakeMachine {
+  private stage: HandshakeMessageKind | "done" = "nonce";
+  private pending = new Uint8Array(0);
+  private output: Uint8Array[] = [];
+  private failure: unknown;
+
+  private readonly localNonce: Uint8Array;
+  private keyPair: KeyExchangeKey | undefined;
+  private initiator = false;
+  private sharedSecret: Uint8Array | undefined;
+  private transcript: Uint8Array | undefined;
+  private mac: Uint8Array | undefined;
+  private outcome: TimedHandshakeResult | undefined;
+  private psk: Uint8Array | undefined;
+  private readonly selection: PskSelection | undefined;
+  private peerPskIdentity: string | undefined;
+
+  private readonly start: number;
+  private firstFlightAt = 0;
+  private keyExchangeAt = 0;
+  private cryptoMs = 0;
+
+  /**
+   * @param psk - Optional pre-shared key, or keys chosen by identity
+   * @param clock - Time source for the stage timings (default: `systemClock`)
+   * @param keyExchange - Where the ephemeral key pair lives (default: process memory)
+   */
+  constructor(
+    psk?: Uint8Array | PskSelection,
+    private readonly clock: Clock = systemClock,
+    private readonly keyExchange: KeyExchangeProvider = inMemoryKeyExchange
+  ) {
+    if (psk instanceof Uint8Array) {
+      this.psk = checkPsk(psk);
+    } else {
+      this.selection = psk;
+    }
+    this.start = clock.now();
+    this.localNonce = generateRandomBytes(HANDSHAKE_MESSAGE_LENGTHS.nonce);
+    this.emit("nonce", this.localNonce);
+    if (this.selection) this.emit("psk_identity", encodePskIdentity(this.selection.identity));
+  }
+
+  /** Whether the handshake has finished; `result()` is ready */
+  get done(): boolean {
+    return this.stage === "done";
+  }
+
+  /** Bytes still missing from the message the machine is waiting for (0 once done) */
+  get bytesNeeded(): number {
+    return this.stage === "done" ? 0 : HANDSHAKE_MESSAGE_LENGTHS[this.stage] - this.pending.length;
+  }
+
+  /**
+   * Consume bytes received from the peer, queueing any answer for
+   * `takeOutput()`. Returns how many bytes were used: fewer than given once
+   * the handshake finishes partway through the chunk. Throws if the peer
+   * fails authentication, and again on every later call.
+   */
+  feedBytes(bytes: Uint8Array): number {
+    if (this.failure !== undefined) throw this.failure;
+    let used = 0;
+    while (this.stage !== "done" && used < bytes.length) {
+      const take = Math.min(this.bytesNeeded, bytes.length - used);
+      const joined = new Uint8Array(this.pending.length + take);
+      joined.set(this.pending, 0);
+      joined.set(bytes.subarray(used, used + take), this.pending.length);
+      this.pending = joined;
+      used += take;
+      if (this.bytesNeeded === 0) {
+        const message = decodeHandshakeMessage(this.stage, this.pending).bytes;
+        this.pending = new Uint8Array(0);
+        try {
+          this.step(message);
+        } catch (error) {
+          this.failure = error;
+          throw error;
+        }
+      }
+    }
+    return used;
+  }
+
+  /** Bytes to send to the peer, in order; empty when there is nothing to send */
+  takeOutput(): Uint8Array {
+    const output = concatBytes(this.output);
+    this.output = [];
+    return output;
+  }
+
+  /** Keys, role and timings of the finished handshake */
+  result(): TimedHandshakeResult {
+    if (!this.outcome) {
+      throw ClavisError.invalidOperation("The handshake has not finished");
+    }
+    return this.outcome;
+  }
+
+  private step(message: Uint8Array): void {
+    switch (this.stage) {
+      case "nonce":
+        // Step 1: Nonce exchange to determine role
+        this.initiator = isHandshakeInitiator(this.localNonce, message);
+        this.firstFlightAt = this.clock.now();
+        // Step 2: X25519 key exchange; the initiator's key goes first
+        this.keyPair = this.timed(() => generateEphemeralKey(this.keyExchange));
+        if (this.initiator) this.emit("public_key", this.keyPair.publicKey);
+        this.stage = this.selection ? "psk_identity" : "public_key";
+        return;
+
+      case "psk_identity": {
+        const identity = decodePskIdentity(message);
+        const psk = this.selection!.keyFor(identity);
+        if (!psk) {
+          throw ClavisError.crypto(
+            CryptoError.authenticationFailure(`No pre-shared key for identity "${identity}"`)
+          );
+        }
+        this.psk = checkPsk(psk);
+        this.peerPskIdentity = identity;
+        this.stage = "public_key";
+        return;
+      }
+
+      case "public_key": {
+        const keyPair = this.keyPair!;
+        if (!this.initiator) this.emit("public_key", keyPair.publicKey);
+        try {
+          this.sharedSecret = this.timed(() => agreeWith(keyPair, message));
+        } finally {
+          keyPair.destroy?.();
+        }
+        // Step 3: Transcript (initiator's key first, then responder's)
+        this.transcript = this.timed(() => this.initiator
+          ? handshakeTranscript(keyPair.publicKey, message)
+          : handshakeTranscript(message, keyPair.publicKey));
+        if (!this.psk) return this.finish();
+        // Step 4: MAC exchange, initiator first
+        const psk = this.psk;
+        const transcript = this.transcript;
+        this.mac = this.timed(() => handshakeMac(psk, transcript));
+        if (this.initiator) this.emit("mac", this.mac);
+        this.keyExchangeAt = this.clock.now();
+        this.stage = "mac";
+        return;
+      }
+
+      case "mac":
+        if (!this.initiator) this.emit("mac", this.mac!);
+        if (!constantTimeEquals(this.mac!, message)) {
+          throw ClavisError.crypto(
+            CryptoError.authenticationFailure("MAC verification failed")
+          );
+        }
+        return this.finish();
+
+      case "done":
+        return;
+    }
+  }
+
+  private finish(): void {
+    // Step 5: Key derivation (the responder uses the opposite keys)
+    const sharedSecret = this.sharedSecret!;
+    const transcript = this.transcript!;
+    const keys = this.timed(() => deriveHandshakeKeys(sharedSecret, transcript, this.initiator));
+    const end = this.clock.now();
+    // Without a PSK the key exchange is the last stage and takes the derivation with it
+    if (!this.psk) this.keyExchangeAt = end;
+    this.stage = "done";
+    this.outcome = {
+      ...keys,
+      initiator: this.initiator,
+      ...(this.peerPskIdentity ? { pskIdentity: this.peerPskIdentity } : {}),
+      timings: {
+        firstFlightMs: this.firstFlightAt - this.start,
+        keyExchangeMs: this.keyExchangeAt - this.firstFlightAt,
+        confirmationMs: end - this.keyExchangeAt,
+        cryptoMs: this.cryptoMs,
+        setupMs: 0,
+        totalMs: end - this.start,
+      },
+    };
+  }
+
+  private emit(kind: HandshakeMessageKind, bytes: Uint8Array): void {
+    this.output.push(encodeHandshakeMessage({ kind, bytes }));
+  }
+
+  private timed<R>(work: () => R): R {
+    const began = this.clock.now();
+    try {
+      return work();
+    } finally {
+      this.cryptoMs += this.clock.now() - began;
+    }
+  }
 }
 
 /**
  * Perform handshake to establish encrypted connection
  * @param stream - The stream to perform handshake on
- * @param psk - Optional pre-shared key for authentication
+ * @param psk - Optional pre-shared key, or keys chosen by identity
+ * @param clock - Time source for the stage timings (default: `systemClock`)
+ * @param keyExchange - Where the ephemeral key pair lives (default: process memory)
  * @returns Handshake result with encryption/decryption keys
  */
 export async function performHandshake(
@@ -66,95 +310,48 @@ export async function performHandshake(
     read: (length: number) => Promise<Uint8Array>;
     write: (data: Uint8Array) => Promise<void>;
   },
-  psk?: Uint8Array
-): Promise<HandshakeResult> {
-  // Validate PSK if provided
-  if (psk && psk.length < 16) {
+  psk?: Uint8Array | PskSelection,
+  clock: Clock = systemClock,
+  keyExchange?: KeyExchangeProvider
+): Promise<TimedHandshakeResult> {
+  const machine = new HandshakeMachine(psk, clock, keyExchange);
+  const flush = async () => {
+    const output = machine.takeOutput();
+    if (output.length > 0) await stream.write(output);
+  };
+
+  await flush();
+  while (!machine.done) {
+    const bytes = await stream.read(machine.bytesNeeded);
+    try {
+      machine.feedBytes(bytes);
+    } finally {
+      // The responder's MAC goes out even when the initiator's fails to verify
+      await flush();
+    }
+  }
+  return machine.result();
+}
+
+function checkPsk(psk: Uint8Array): Uint8Array {
+  if (psk.length < 16) {
     throw ClavisError.crypto(
       CryptoError.invalidKeyMaterial("Pre-shared key must be at least 16 bytes")
     );
   }
+  return psk;
+}
 
-  // Step 1: Nonce exchange to determine role
-  const localNonce = generateRandomBytes(32);
-  await stream.write(localNonce);
-  
-  const peerNonce = await stream.read(32);
-  const isInitiator = compareNonces(localNonce, peerNonce);
-
-  // Step 2: X25519 key exchange
-  const keyPair = generateX25519KeyPair();
-  
-  if (isInitiator) {
-    // Initiator sends public key first, then receives peer's public key
-    await stream.write(keyPair.publicKey);
-    const peerPublicKey = await stream.read(32);
-    
-    const sharedSecret = computeSharedSecret(keyPair.secret, peerPublicKey);
-    
-    // Step 3: Transcript hashing and MAC (initiator's key first, then responder's)
-    const transcriptData = constructTranscript(keyPair.publicKey, peerPublicKey);
-    const transcriptHash = sha256Hash(transcriptData);
-    
-    let mac: Uint8Array | undefined;
-    if (psk) {
-      mac = hmacSha256(psk, transcriptData);
-    }
-    
-    // Step 4: MAC exchange (if PSK provided)
-    if (mac) {
-      await stream.write(mac);
-      const peerMac = await stream.read(32);
-      
-      // Verify MACs match
-      if (!constantTimeEquals(mac, peerMac)) {
-        throw ClavisError.crypto(
-          CryptoError.authenticationFailure("MAC verification failed")
-        );
-      }
-    }
-    
-    // Step 5: Key derivation
-    const encKey = hkdfExpand(sharedSecret, transcriptHash, "enc");
-    const decKey = hkdfExpand(sharedSecret, transcriptHash, "dec");
-    
-    return { encKey, decKey };
-  } else {
-    // Responder receives public key first, then sends own public key
-    const peerPublicKey = await stream.read(32);
-    await stream.write(keyPair.publicKey);
-    
-    const sharedSecret = computeSharedSecret(keyPair.secret, peerPublicKey);
-    
-    // Step 3: Transcript hashing and MAC (initiator's key first, then responder's)
-    // peerPublicKey is from initiator, keyPair.publicKey is from responder
-    const transcriptData = constructTranscript(peerPublicKey, keyPair.publicKey);
-    const transcriptHash = sha256Hash(transcriptData);
-    
-    let mac: Uint8Array | undefined;
-    if (psk) {
-      mac = hmacSha256(psk, transcriptData);
-    }
-    
-    // Step 4: MAC exchange (if PSK provided)
-    if (mac) {
-      const peerMac = await stream.read(32);
-      await stream.write(mac);
-      
-      // Verify MACs match
-      if (!constantTimeEquals(mac, peerMac)) {
-        throw ClavisError.crypto(
-          CryptoError.authenticationFailure("MAC verification failed")
-        );
-      }
-    }
-    
-    // Step 5: Key derivation (responder uses opposite keys)
-    const encKey = hkdfExpand(sharedSecret, transcriptHash, "dec");
-    const decKey = hkdfExpand(sharedSecret, transcriptHash, "enc");
-    
-    return { encKey, decKey };
+/** Join byte chunks into one array */
+function concatBytes(chunks: Uint8Array[]): Uint8Array {
+  if (chunks.length === 1) return chunks[0]!;
+  const joined = new Uint8Array(chunks.reduce((total, chunk) => total + chunk.length, 0));
+  let offset = 0;
+  for (const chunk of chunks) {
+    joined.set(chunk, offset);
+    offset += chunk.length;
   }
+  return joined;
 }
 
 /**
@@ -0,0 +1,122 @@
+/**
+ * Hedged request deduplication
+ * Lets a server run each hedged request once, however many connections
+ * its copies arrive on
+ *
+ * `RpcPool` hedges an idempotent call by sending a copy on another
+ * connection when the first is slow. Every copy carries the same hedging
+ * group ID, a random 16-byte tag in the `HedgedRequest` envelope that also
+ * correlates the copies in logs and traces. A server that shares one
+ * `HedgeRegistry` between its connections runs the handler for the first
+ * copy only; later copies wait for that run and answer with its reply, so
+ * whichever connection is faster still delivers it. Once the caller has a
+ * reply it cancels the other copies, and their connections drop the reply
+ * instead of sending it. Copies reaching different servers can't share a
+ * run, but are still cancelled.
+ */
+
+import { systemClock, type Clock } from "./clock.js";
+
+/** Length of a hedging group ID */
+export const HEDGE_GROUP_LENGTH = 16;
+
+/** Default time a group's reply is kept for copies that arrive late */
+const DEFAULT_RETAIN_MS = 30_000;
+
+/**
+ * Options for `HedgeRegistry`
+ */
+export interface HedgeRegistryOptions {
+  /**
+   * How long a group is remembered after its first copy arrived, in
+   * milliseconds (default: 30000). A copy arriving later runs the handler
+   * again, so keep it above the callers' hedge delay plus call timeout.
+   */
+  retainMs?: number | undefined;
+  /** Time source for expiry (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+/**
+ * What a registry has done so far
+ */
+export interface HedgeStats {
+  /** Groups whose handler ran */
+  groups: number;
+  /** Copies answered with another copy's reply instead of running the handler */
+  deduplicated: number;
+  /** Replies dropped because the caller cancelled the copy */
+  suppressed: number;
+}
+
+interface HedgeGroup {
+  createdAt: number;
+  reply: Promise<unknown>;
+}
+
+/**
+ * Hedging groups seen by a server, shared by its connections through the
+ * `hedges` option of `RpcConnection`
+ *
+ * @example
+ * ```typescript
+ * const hedges = new HedgeRegistry();
+ * listener.on("connection", (stream) => {
+ *   const { reader, writer } = stream.split();
+ *   new RpcConnection(reader, writer, { codec, router, hedges }).start();
+ * });
+ * ```
+ */
+export class HedgeRegistry {
+  private readonly retainMs: number;
+  private readonly clock: Clock;
+  /** In arrival order, so expired groups are at the front */
+  private readonly groups = new Map<string, HedgeGroup>();
+  private counts: HedgeStats = { groups: 0, deduplicated: 0, suppressed: 0 };
+
+  constructor(options: HedgeRegistryOptions = {}) {
+    this.retainMs = options.retainMs ?? DEFAULT_RETAIN_MS;
+    this.clock = options.clock ?? systemClock;
+  }
+
+  /** Groups currently remembered */
+  get size(): number {
+    this.prune();
+    return this.groups.size;
+  }
+
+  /**
+   * Run `work` for the first copy of `group`; later copies get the same
+   * promise instead of running it again
+   */
+  run<R>(group: Uint8Array, work: () => Promise<R>): Promise<R> {
+    this.prune();
+    const key = Buffer.from(group).toString("hex");
+    const existing = this.groups.get(key);
+    if (existing) {
+      this.counts.deduplicated++;
+      return existing.reply as Promise<R>;
+    }
+    const reply = work();
+    this.groups.set(key, { createdAt: this.clock.now(), reply });
+    this.counts.groups++;
+    return reply;
+  }
+
+  /** Count a reply dropped because its caller cancelled it */
+  suppressed(): void {
+    this.counts.suppressed++;
+  }
+
+  stats(): HedgeStats {
+    return { ...this.counts };
+  }
+
+  private prune(): void {
+    const cutoff = this.clock.now() - this.retainMs;
+    for (const [key, group] of this.groups) {
+      if (group.createdAt > cutoff) break;
+      this.groups.delete(key);
+    }
+  }
+}
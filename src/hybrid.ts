@@ -0,0 +1,121 @@
+/**
+ * Hybrid post-quantum key exchange
+ * Mixes an ML-KEM-768 shared secret into the X25519 session keys
+ *
+ * Runs right after the handshake, before the other setup exchanges. Both
+ * peers send an offer: the level they want, and for the handshake's
+ * responder an ML-KEM-768 encapsulation key. When both can do it, the
+ * initiator encapsulates to that key and sends the ciphertext, and both
+ * derive new session keys from the X25519 secrets and the ML-KEM secret
+ * together. The keys stay safe as long as either exchange holds, so a
+ * recorded session can't be decrypted later by breaking X25519 alone.
+ *
+ * A peer whose build can't load ML-KEM offers nothing; "prefer" then keeps
+ * the X25519 keys, "require" refuses the connection. Peers that predate this
+ * exchange don't send an offer at all, so only enable it where both sides
+ * run a version that has it.
+ */
+
+import { hkdfExpand, sha256Hash } from "./crypto.js";
+import { ClavisError, MessageError } from "./error.js";
+import type { HandshakeResult } from "./handshake.js";
+
+/**
+ * How much a stream wants the hybrid exchange
+ */
+export type PostQuantumMode = "prefer" | "require";
+
+/** Bytes in an ML-KEM-768 encapsulation key */
+export const ML_KEM_768_PUBLIC_KEY_LENGTH = 1184;
+/** Bytes in an ML-KEM-768 ciphertext */
+export const ML_KEM_768_CIPHERTEXT_LENGTH = 1088;
+
+/**
+ * The ML-KEM-768 operations the exchange needs
+ */
+export interface MlKem {
+  keygen(): { publicKey: Uint8Array; secretKey: Uint8Array };
+  encapsulate(publicKey: Uint8Array): { cipherText: Uint8Array; sharedSecret: Uint8Array };
+  decapsulate(cipherText: Uint8Array, secretKey: Uint8Array): Uint8Array;
+}
+
+/**
+ * A peer's offer
+ */
+export interface HybridOffer {
+  /** undefined when the peer can't do the hybrid exchange */
+  mode: PostQuantumMode | undefined;
+  /** Present in the responder's offer */
+  encapsulationKey: Uint8Array | undefined;
+}
+
+const MODE_CODES: Record<PostQuantumMode, number> = { prefer: 1, require: 2 };
+
+/**
+ * Load ML-KEM-768 from `@noble/post-quantum`; undefined if it isn't installed
+ */
+export async function loadMlKem(): Promise<MlKem | undefined> {
+  try {
+    const { ml_kem768 } = await import("@noble/post-quantum/ml-kem.js");
+    return ml_kem768;
+  } catch {
+    return undefined;
+  }
+}
+
+/** Offer payload: mode (u8: 0 none, 1 prefer, 2 require), then the encapsulation key if any */
+export function encodeHybridOffer(mode: PostQuantumMode | undefined, encapsulationKey?: Uint8Array): Uint8Array {
+  const out = new Uint8Array(1 + (encapsulationKey?.length ?? 0));
+  out[0] = mode ? MODE_CODES[mode] : 0;
+  if (encapsulationKey) out.set(encapsulationKey, 1);
+  return out;
+}
+
+export function decodeHybridOffer(data: Uint8Array): HybridOffer {
+  const code = data[0];
+  const key = data.subarray(1);
+  if (code === undefined || code > 2 || (key.length !== 0 && key.length !== ML_KEM_768_PUBLIC_KEY_LENGTH)) {
+    throw ClavisError.message(MessageError.invalidFormat("Malformed post-quantum offer"));
+  }
+  return {
+    mode: code === 0 ? undefined : code === 1 ? "prefer" : "require",
+    encapsulationKey: key.length > 0 ? key.slice() : undefined,
+  };
+}
+
+/**
+ * Session keys after the hybrid exchange. The transcript hash also covers
+ * the encapsulation key and ciphertext, so identity proofs and session
+ * tickets made later are bound to the hybrid keys.
+ */
+export function deriveHybridKeys(
+  handshake: HandshakeResult,
+  pqSecret: Uint8Array,
+  encapsulationKey: Uint8Array,
+  cipherText: Uint8Array
+): HandshakeResult {
+  const transcriptHash = sha256Hash(concat(handshake.transcriptHash, encapsulationKey, cipherText));
+  // The handshake's resumption secret stands in for the X25519 secret it was derived from
+  const secret = sha256Hash(concat(handshake.resumptionSecret, pqSecret));
+  const enc = hkdfExpand(secret, transcriptHash, "hybrid-enc");
+  const dec = hkdfExpand(secret, transcriptHash, "hybrid-dec");
+  return {
+    encKey: handshake.initiator ? enc : dec,
+    decKey: handshake.initiator ? dec : enc,
+    transcriptHash,
+    initiator: handshake.initiator,
+    resumptionSecret: hkdfExpand(secret, transcriptHash, "hybrid-resumption"),
+  };
+}
+
+function concat(...parts: Uint8Array[]): Uint8Array {
+  let total = 0;
+  for (const part of parts) total += part.length;
+  const out = new Uint8Array(total);
+  let offset = 0;
+  for (const part of parts) {
+    out.set(part, offset);
+    offset += part.length;
+  }
+  return out;
+}
@@ -0,0 +1,418 @@
+/**
+ * Signed identity certificates
+ * PKI-lite client authentication: a deployment signer vouches for an identity
+ * and its public key until an expiry, and the peer proves it holds the key
+ *
+ * A certificate binds an identity string to an Ed25519 public key and is
+ * signed by a signer key the server trusts. During connection setup the
+ * presenting side sends its certificate together with a signature over the
+ * handshake transcript hash and its handshake role, made with the
+ * certificate's key. The transcript ties the proof to this connection, so a
+ * certificate captured elsewhere can't be replayed, and the role stops a peer
+ * from reflecting the proof back.
+ *
+ * Peers with an existing PKI can present an X.509 chain instead (see x509.ts);
+ * the proof is then made with the leaf's key. Peers without any PKI can
+ * present a bare long-term Ed25519 key, which the other side accepts or
+ * refuses itself, by fingerprint (Noise XX-style; pin the key beforehand for IK).
+ *
+ * Certificate layout (all integers little-endian):
+ * magic "CLVC" | version (u8) | identity length (u16) | identity (UTF-8) |
+ * public key (32) | expires at, ms since the epoch (u64) | signer key (32) |
+ * signature over everything before it (64)
+ */
+
+import { ed25519PublicKey, ed25519Sign, ed25519Verify, sha256Hash } from "./crypto.js";
+import { ClavisError, CryptoError, MessageError, StreamError } from "./error.js";
+import {
+  MAX_X509_CHAIN_LENGTH,
+  parseX509Certificate,
+  verifyX509Chain,
+  x509Identity,
+  x509Sign,
+  x509Verify,
+  type X509Credentials,
+} from "./x509.js";
+import type { X509Certificate } from "crypto";
+import { checkValidityPeriod } from "./validity.js";
+import type { IdentitySigner } from "./key-provider.js";
+
+/**
+ * An identity vouched for by a signer
+ */
+export interface IdentityCertificate {
+  /** Name the peer is known by, e.g. a user or device id */
+  readonly identity: string;
+  /** Ed25519 key the holder proves possession of */
+  readonly publicKey: Uint8Array;
+  readonly expiresAt: Date;
+  /** Public key of the signer that issued the certificate */
+  readonly signer: Uint8Array;
+  readonly signature: Uint8Array;
+}
+
+/**
+ * An Ed25519 secret key in process memory, or a signer that keeps it in a
+ * keystore (see `IdentitySigner`)
+ */
+export type IdentityKey = { secretKey: Uint8Array } | { signer: IdentitySigner };
+
+/**
+ * A certificate and the secret key matching its public key
+ */
+export type IdentityCredentials = { certificate: IdentityCertificate } & IdentityKey;
+
+/**
+ * A long-term Ed25519 key presented without a certificate
+ */
+export type StaticIdentityKey = IdentityKey;
+
+/**
+ * Fields to certify
+ */
+export interface CertificateRequest {
+  identity: string;
+  publicKey: Uint8Array;
+  expiresAt: Date;
+}
+
+const CERT_MAGIC = [0x43, 0x4c, 0x56, 0x43]; // "CLVC"
+const CERT_VERSION = 1;
+const PRESENTATION_MAGIC = [0x43, 0x4c, 0x56, 0x49]; // "CLVI"
+const PRESENTATION_VERSION = 1;
+const PRESENT_NONE = 0;
+const PRESENT_CERTIFICATE = 1;
+const PRESENT_X509 = 2;
+const PRESENT_KEY = 3;
+const PROOF_CONTEXT = new TextEncoder().encode("clavis-identity-proof-v1");
+const MAX_IDENTITY_LENGTH = 1024;
+
+function writeCertificateBody(request: CertificateRequest, signer: Uint8Array): Uint8Array {
+  const identity = new TextEncoder().encode(request.identity);
+  if (identity.length === 0 || identity.length > MAX_IDENTITY_LENGTH) {
+    throw ClavisError.config(`Identities must be 1 to ${MAX_IDENTITY_LENGTH} bytes`);
+  }
+  if (request.publicKey.length !== 32) {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("Identity public key must be 32 bytes"));
+  }
+  const expiresAt = request.expiresAt.getTime();
+  if (!Number.isSafeInteger(expiresAt) || expiresAt < 0) {
+    throw ClavisError.config("Certificate expiry must be a valid date after 1970");
+  }
+
+  const body = new Uint8Array(4 + 1 + 2 + identity.length + 32 + 8 + 32);
+  const view = new DataView(body.buffer);
+  body.set(CERT_MAGIC, 0);
+  body[4] = CERT_VERSION;
+  view.setUint16(5, identity.length, true);
+  let offset = 7;
+  body.set(identity, offset);
+  offset += identity.length;
+  body.set(request.publicKey, offset);
+  offset += 32;
+  view.setBigUint64(offset, BigInt(expiresAt), true);
+  offset += 8;
+  body.set(signer, offset);
+  return body;
+}
+
+/**
+ * Issue a certificate, signing it with the signer's Ed25519 secret key
+ */
+export function issueCertificate(signerSecretKey: Uint8Array, request: CertificateRequest): IdentityCertificate {
+  const signer = ed25519PublicKey(signerSecretKey);
+  const body = writeCertificateBody(request, signer);
+  return {
+    identity: request.identity,
+    publicKey: request.publicKey,
+    expiresAt: new Date(request.expiresAt.getTime()),
+    signer,
+    signature: ed25519Sign(signerSecretKey, body),
+  };
+}
+
+/**
+ * Encode a certificate for storage or transmission
+ */
+export function encodeCertificate(certificate: IdentityCertificate): Uint8Array {
+  const body = writeCertificateBody(certificate, certificate.signer);
+  const out = new Uint8Array(body.length + 64);
+  out.set(body, 0);
+  out.set(certificate.signature, body.length);
+  return out;
+}
+
+/**
+ * Decode a certificate. The signature is not checked; see `verifyCertificate`.
+ */
+export function decodeCertificate(data: Uint8Array): IdentityCertificate {
+  const magic = CERT_MAGIC.every((b, i) => data[i] === b);
+  if (!magic || data[4] !== CERT_VERSION || data.length < 7) {
+    throw ClavisError.message(MessageError.invalidFormat("Not an identity certificate"));
+  }
+  const view = new DataView(data.buffer, data.byteOffset, data.byteLength);
+  const identityLength = view.getUint16(5, true);
+  if (data.length !== 7 + identityLength + 32 + 8 + 32 + 64) {
+    throw ClavisError.message(MessageError.invalidFormat("Malformed identity certificate"));
+  }
+  let offset = 7;
+  let identity: string;
+  try {
+    identity = new TextDecoder("utf-8", { fatal: true }).decode(data.subarray(offset, offset + identityLength));
+  } catch {
+    throw ClavisError.message(MessageError.invalidFormat("Certificate identity is not valid UTF-8"));
+  }
+  offset += identityLength;
+  const publicKey = data.slice(offset, offset + 32);
+  offset += 32;
+  const expiresAt = Number(view.getBigUint64(offset, true));
+  offset += 8;
+  const signer = data.slice(offset, offset + 32);
+  offset += 32;
+  return { identity, publicKey, expiresAt: new Date(expiresAt), signer, signature: data.slice(offset) };
+}
+
+/**
+ * Check that a certificate was issued by one of `trustedSigners` and has not
+ * expired at `now` (milliseconds since the epoch), give or take `clockSkewMs`
+ */
+export function verifyCertificate(
+  certificate: IdentityCertificate,
+  trustedSigners: readonly Uint8Array[],
+  now: number = Date.now(),
+  clockSkewMs: number = 0
+): void {
+  const trusted = trustedSigners.some((signer) =>
+    signer.length === certificate.signer.length && signer.every((b, i) => b === certificate.signer[i])
+  );
+  if (!trusted) {
+    throw ClavisError.crypto(CryptoError.authenticationFailure("Certificate signer is not trusted"));
+  }
+  const body = writeCertificateBody(certificate, certificate.signer);
+  if (!ed25519Verify(certificate.signer, body, certificate.signature)) {
+    throw ClavisError.crypto(CryptoError.authenticationFailure("Certificate signature is invalid"));
+  }
+  checkValidityPeriod(`Certificate for ${certificate.identity}`, undefined, certificate.expiresAt.getTime(), now, clockSkewMs);
+}
+
+function proofMessage(transcriptHash: Uint8Array, initiator: boolean): Uint8Array {
+  const message = new Uint8Array(PROOF_CONTEXT.length + transcriptHash.length + 1);
+  message.set(PROOF_CONTEXT, 0);
+  message.set(transcriptHash, PROOF_CONTEXT.length);
+  message[message.length - 1] = initiator ? 1 : 0;
+  return message;
+}
+
+/**
+ * A peer identity that checked out against this side's trust settings
+ */
+export interface PeerIdentity {
+  readonly identity: string;
+  readonly expiresAt: Date;
+  /**
+   * Hex SHA-256 of the presented certificate (the leaf, for X.509), stable
+   * across connections; the key to list in a revocation store
+   */
+  readonly credentialId: string;
+  /** The signer-issued certificate, when the peer presented one */
+  readonly certificate?: IdentityCertificate | undefined;
+  /** The leaf certificate, when the peer presented an X.509 chain */
+  readonly x509?: X509Certificate | undefined;
+  /** The peer's Ed25519 key, when it presented a bare key (not kept in session tickets) */
+  readonly publicKey?: Uint8Array | undefined;
+}
+
+/**
+ * Issuers this side accepts peer identities from
+ */
+export interface IdentityTrust {
+  /** Signer public keys for clavis identity certificates */
+  signers?: readonly Uint8Array[] | undefined;
+  /** DER root certificates for X.509 chains */
+  x509Roots?: readonly Uint8Array[] | undefined;
+  /** Accept certificates this far outside their validity period (default: 0) */
+  clockSkewMs?: number | undefined;
+  /** Accept bare Ed25519 keys, leaving the decision to the caller (default: false) */
+  staticKeys?: boolean | undefined;
+}
+
+/**
+ * Hex SHA-256 of an Ed25519 public key: a bare key's identity and credential ID
+ */
+export function keyFingerprint(publicKey: Uint8Array): string {
+  return toHex(sha256Hash(publicKey));
+}
+
+/**
+ * Encode this side's identity message: a certificate or X.509 chain and a
+ * proof of possession over the handshake, or an empty presentation without
+ * credentials. Layout after magic and version: kind (u8), then for kind 1 the
+ * certificate and a 64-byte proof, for kind 2 a count (u8) of
+ * length-prefixed (u32) DER certificates followed by the proof, for kind 3 a
+ * 32-byte Ed25519 public key and the proof.
+ */
+export function encodePresentation(
+  credentials: IdentityCredentials | X509Credentials | StaticIdentityKey | undefined,
+  transcriptHash: Uint8Array,
+  initiator: boolean
+): Uint8Array {
+  const header = [...PRESENTATION_MAGIC, PRESENTATION_VERSION];
+  if (!credentials) {
+    return new Uint8Array([...header, PRESENT_NONE]);
+  }
+  const message = proofMessage(transcriptHash, initiator);
+  if ("chain" in credentials) {
+    if (credentials.chain.length === 0 || credentials.chain.length > MAX_X509_CHAIN_LENGTH) {
+      throw ClavisError.config(`X.509 chains must hold 1 to ${MAX_X509_CHAIN_LENGTH} certificates`);
+    }
+    const parts = [new Uint8Array([...header, PRESENT_X509, credentials.chain.length])];
+    for (const der of credentials.chain) {
+      const length = new Uint8Array(4);
+      new DataView(length.buffer).setUint32(0, der.length, true);
+      parts.push(length, der);
+    }
+    parts.push(x509Sign(credentials.privateKey, message));
+    return concat(parts);
+  }
+  if (!("certificate" in credentials)) {
+    return concat([
+      new Uint8Array([...header, PRESENT_KEY]),
+      identityPublicKey(credentials),
+      identitySign(credentials, message),
+    ]);
+  }
+  return concat([
+    new Uint8Array([...header, PRESENT_CERTIFICATE]),
+    encodeCertificate(credentials.certificate),
+    identitySign(credentials, message),
+  ]);
+}
+
+function identityPublicKey(key: IdentityKey): Uint8Array {
+  if ("secretKey" in key) return ed25519PublicKey(key.secretKey);
+  if (key.signer.publicKey?.length !== 32) {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("A static identity signer needs its 32-byte publicKey"));
+  }
+  return key.signer.publicKey;
+}
+
+function identitySign(key: IdentityKey, message: Uint8Array): Uint8Array {
+  if ("secretKey" in key) return ed25519Sign(key.secretKey, message);
+  const signature = key.signer.sign(message);
+  if (signature.length !== 64) {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("Identity signer must return a 64-byte Ed25519 signature"));
+  }
+  return signature;
+}
+
+/**
+ * Decode and check the peer's identity message. Returns the verified
+ * identity, or undefined if the peer's identity isn't checked against anything.
+ */
+export function verifyPresentation(
+  data: Uint8Array,
+  trust: IdentityTrust,
+  transcriptHash: Uint8Array,
+  peerInitiator: boolean,
+  now: number = Date.now()
+): PeerIdentity | undefined {
+  const magic = PRESENTATION_MAGIC.every((b, i) => data[i] === b);
+  if (!magic || data[4] !== PRESENTATION_VERSION) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Peer did not exchange identities"));
+  }
+  const required = trust.signers !== undefined || trust.x509Roots !== undefined || trust.staticKeys === true;
+  const message = proofMessage(transcriptHash, peerInitiator);
+  const kind = data[5];
+
+  if (kind === PRESENT_NONE && data.length === 6) {
+    if (required) {
+      throw ClavisError.crypto(CryptoError.authenticationFailure("Peer presented no identity"));
+    }
+    return undefined;
+  }
+
+  if (kind === PRESENT_CERTIFICATE && data.length >= 6 + 64) {
+    const certificate = decodeCertificate(data.subarray(6, data.length - 64));
+    if (!ed25519Verify(certificate.publicKey, message, data.subarray(data.length - 64))) {
+      throw ClavisError.crypto(CryptoError.authenticationFailure("Peer does not hold the certificate's key"));
+    }
+    if (!trust.signers) {
+      // Nothing to check the certificate against, so its claims mean nothing
+      return rejectUnchecked(required, "identity certificates");
+    }
+    verifyCertificate(certificate, trust.signers, now, trust.clockSkewMs);
+    return {
+      identity: certificate.identity,
+      expiresAt: certificate.expiresAt,
+      credentialId: toHex(sha256Hash(encodeCertificate(certificate))),
+      certificate,
+    };
+  }
+
+  if (kind === PRESENT_X509 && data.length >= 7) {
+    const view = new DataView(data.buffer, data.byteOffset, data.byteLength);
+    const chain: Uint8Array[] = [];
+    let offset = 7;
+    for (let i = 0; i < data[6]!; i++) {
+      if (offset + 4 > data.length) break;
+      const length = view.getUint32(offset, true);
+      if (offset + 4 + length > data.length) break;
+      chain.push(data.subarray(offset + 4, offset + 4 + length));
+      offset += 4 + length;
+    }
+    if (chain.length === 0 || chain.length !== data[6]) {
+      throw ClavisError.stream(StreamError.handshakeFailed("Malformed X.509 presentation"));
+    }
+    const leaf = parseX509Certificate(chain[0]!);
+    if (!x509Verify(leaf, message, data.subarray(offset))) {
+      throw ClavisError.crypto(CryptoError.authenticationFailure("Peer does not hold the certificate's key"));
+    }
+    if (!trust.x509Roots) {
+      return rejectUnchecked(required, "X.509 certificates");
+    }
+    verifyX509Chain(chain, trust.x509Roots, now, trust.clockSkewMs);
+    return {
+      identity: x509Identity(leaf),
+      expiresAt: new Date(leaf.validTo),
+      credentialId: toHex(sha256Hash(chain[0]!)),
+      x509: leaf,
+    };
+  }
+
+  if (kind === PRESENT_KEY && data.length === 6 + 32 + 64) {
+    const publicKey = data.slice(6, 38);
+    if (!ed25519Verify(publicKey, message, data.subarray(38))) {
+      throw ClavisError.crypto(CryptoError.authenticationFailure("Peer does not hold its identity key"));
+    }
+    if (!trust.staticKeys) {
+      return rejectUnchecked(required, "a bare identity key");
+    }
+    const fingerprint = keyFingerprint(publicKey);
+    // Bare keys don't expire; the maximum Date stands in for "never"
+    return { identity: fingerprint, expiresAt: new Date(8.64e15), credentialId: fingerprint, publicKey };
+  }
+
+  throw ClavisError.stream(StreamError.handshakeFailed("Malformed identity presentation"));
+}
+
+/** A presentation this side has no trust anchors for is refused if an identity is required, else ignored */
+function rejectUnchecked(required: boolean, what: string): undefined {
+  if (required) {
+    throw ClavisError.crypto(CryptoError.authenticationFailure(`Peer presented ${what}, which are not accepted`));
+  }
+  return undefined;
+}
+
+function toHex(bytes: Uint8Array): string {
+  return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+function concat(parts: readonly Uint8Array[]): Uint8Array {
+  const out = new Uint8Array(parts.reduce((total, part) => total + part.length, 0));
+  let offset = 0;
+  for (const part of parts) {
+    out.set(part, offset);
+    offset += part.length;
+  }
+  return out;
+}
@@ -9,11 +9,86 @@
 export * from "./error.js";
 export * from "./crypto.js";
 export * from "./handshake.js";
+export * from "./handshake-messages.js";
+export * from "./frame.js";
+export * from "./connection.js";
 export * from "./stream.js";
+export * from "./config-audit.js";
 export * from "./protocol.js";
 export * from "./bincode.js";
 export * from "./bincode-helpers.js";
+export * from "./backoff.js";
 export * from "./client.js";
+export * from "./reconnecting.js";
+export * from "./resolver.js";
+export * from "./listener.js";
+export * from "./shedding.js";
+export * from "./shards.js";
+export * from "./proxy-protocol.js";
+export * from "./router.js";
+export * from "./rpc.js";
+export * from "./pool.js";
+export * from "./hedging.js";
+export * from "./mux.js";
+export * from "./service.js";
+export * from "./phases.js";
+export * from "./directions.js";
+export * from "./diag.js";
+export * from "./corruption.js";
+export * from "./corpus.js";
+export * from "./chunked.js";
+export * from "./schema.js";
+export * from "./wire-spec.js";
+export * from "./protocol-doc.js";
+export * from "./client-module.js";
+export * from "./testing.js";
+export * from "./sim.js";
+export * from "./shared-memory.js";
+export * from "./clock.js";
+export * from "./mutex.js";
+export * from "./broadcast.js";
+export * from "./compression.js";
+export * from "./audit.js";
+export * from "./control.js";
+export * from "./identity.js";
+export * from "./x509.js";
+export * from "./tickets.js";
+export * from "./resumption.js";
+export * from "./replay.js";
+export * from "./storage.js";
+export * from "./known-hosts.js";
+export * from "./handoff.js";
+export * from "./journal.js";
+export * from "./validity.js";
+export * from "./time-sync.js";
+export * from "./rekey.js";
+export * from "./hybrid.js";
+export * from "./versioning.js";
+export * from "./formats.js";
+export * from "./derive.js";
+export * from "./bandwidth.js";
+export * from "./watchdog.js";
+export * from "./batching.js";
+export * from "./shared-writer.js";
+export * from "./prefetch.js";
+export * from "./dedupe.js";
+export * from "./null-cipher.js";
+export * from "./keylog.js";
+export * from "./key-provider.js";
+export * from "./datagram.js";
+export * from "./web.js";
+export * from "./codec.js";
+export * from "./quic.js";
+export * from "./group.js";
+export * from "./bridge.js";
+export * from "./logging.js";
+export * from "./padding.js";
+export * from "./suites.js";
+export * from "./prometheus.js";
+export * from "./protocol-stats.js";
+export * from "./keying-material.js";
+export * from "./selftest.js";
+export * from "./invariants.js";
 
 // ============================================================================
 // Re-exported types for convenience
@@ -25,71 +100,1025 @@ export type {
   CryptoError,
   MessageError,
   StreamError,
+  ConfigError,
+  ConfigConflict,
+  ReplayError,
+  PeerKeyChangedError,
+  ErrorContext,
+  ErrorDirection,
 } from "./error.js";
 
 export {
   CryptoOperation,
   StreamErrorCode,
+  ValidityFailure,
+  DecompressionLimit,
 } from "./error.js";
 
+export {
+  checkValidityPeriod,
+} from "./validity.js";
+
 // Stream types
 export type {
   EncryptedStreamOptions,
+  PskKeyring,
+  CloseLinger,
   SplitResult,
+  CpuTime,
+  ConnectionStats,
+  StreamReconfiguration,
+  StreamTracer,
+  TraceSpan,
+  TraceSpanName,
+  PacketInterceptor,
+  InterceptResult,
+  HealthCheckFailure,
+  HealthCheckResult,
 } from "./stream.js";
 
 export {
   EncryptedStream,
   EncryptedReader,
   EncryptedWriter,
+  wireSize,
+  healthCheckFailure,
+  checkStreamOptions,
+  combineTracers,
+  DEFAULT_MAX_PACKET_SIZE,
 } from "./stream.js";
 
+// Configuration audit types
+export type {
+  AuditSeverity,
+  AuditFinding,
+  ConfigAuditReport,
+} from "./config-audit.js";
+
+export {
+  auditStreamOptions,
+} from "./config-audit.js";
+
+// Frame codec
+export type {
+  FrameHeader,
+  RawFrame,
+} from "./frame.js";
+
+export {
+  FrameDecoder,
+  FRAME_OVERHEAD,
+  FRAME_HEADER_LENGTH,
+  FRAME_NONCE_LENGTH,
+  FRAME_SEQUENCE_LENGTH,
+  FRAME_TAG_LENGTH,
+  MAX_CIPHERTEXT_LENGTH,
+  encodeFrame,
+  decodeFrame,
+  decodeFrameHeader,
+  sealFrame,
+  openFrame,
+  sequenceNonce,
+  nonceSequence,
+} from "./frame.js";
+
 // Protocol types
 export type {
   PacketTrait,
   DecodedMessage,
+  UnknownVariant,
+  KnownVariantHandlers,
   ProtocolCodec,
 } from "./protocol.js";
 
 export {
   protocol,
   createProtocolCodec,
+  matchKnown,
+  RawPacket,
+  PreparedPacket,
+  serializedSize,
 } from "./protocol.js";
 
+// Router and RPC types
+export type {
+  RouterReply,
+  RouteHandler,
+  PacketRouterOptions,
+  StreamRouteHandler,
+  UploadRouteHandler,
+} from "./router.js";
+
+export {
+  PacketRouter,
+} from "./router.js";
+
+export type {
+  RpcConnectionOptions,
+  RpcMuxOptions,
+  RpcReader,
+  RpcWriter,
+  RpcConnectionEvents,
+  RpcEnvelope,
+  RpcCallOptions,
+  RpcRetryPolicy,
+  RpcStreamOptions,
+} from "./rpc.js";
+
+export {
+  RpcConnection,
+  RpcError,
+  RpcFrameKind,
+} from "./rpc.js";
+
+export type {
+  RpcPoolOptions,
+  RpcPoolCallOptions,
+  WarmUpOptions,
+} from "./pool.js";
+
+export {
+  RpcPool,
+} from "./pool.js";
+
+// Hedging types
+export type {
+  HedgeRegistryOptions,
+  HedgeStats,
+} from "./hedging.js";
+
+export {
+  HedgeRegistry,
+  HEDGE_GROUP_LENGTH,
+} from "./hedging.js";
+
+// Channel multiplexing types
+export type {
+  MuxEnvelope,
+  ChannelMuxOptions,
+  MuxSchedulerOptions,
+  ChannelQueueStats,
+  ChannelMuxEvents,
+  LogicalChannel,
+} from "./mux.js";
+
+export {
+  ChannelMux,
+  ChannelReader,
+  ChannelWriter,
+  MuxFrameKind,
+} from "./mux.js";
+
+// Service types
+export type {
+  ValueCodec,
+  ServiceMethod,
+  ServiceDefinition,
+  ServiceClient,
+  ServiceImplementation,
+  Service,
+} from "./service.js";
+
+export {
+  defineService,
+} from "./service.js";
+
+// Phase types
+export type {
+  PhaseTransport,
+  PhaseBindings,
+  PhaseDefinition,
+  PhaseMap,
+  PhasedProtocol,
+} from "./phases.js";
+
+export {
+  definePhases,
+  PhasedConnection,
+} from "./phases.js";
+
+// Direction types
+export type {
+  VariantDirection,
+  ConnectionRole,
+  DirectionMap,
+  DirectedProtocol,
+} from "./directions.js";
+
+export {
+  defineDirections,
+  DirectedConnection,
+} from "./directions.js";
+
+// Diagnostics types
+export type {
+  DiagMessage,
+  DiagServeStats,
+  DiagOptions,
+  DiagReport,
+} from "./diag.js";
+
+export {
+  DiagCodec,
+  Diag,
+  decodeDiag,
+  serveDiag,
+  runDiag,
+} from "./diag.js";
+
+// Corruption monitoring types
+export type {
+  CorruptionKind,
+  CorruptionEvent,
+  CorruptionCounts,
+  CorruptionAlarm,
+  CorruptionMonitorOptions,
+} from "./corruption.js";
+
+export {
+  CorruptionMonitor,
+  corruptionKind,
+} from "./corruption.js";
+
+// Fuzz corpus capture types
+export type {
+  CorpusTarget,
+  CorpusCaptureOptions,
+  CorpusCaptureCounts,
+} from "./corpus.js";
+
+export {
+  CorpusCapture,
+  FUZZ_FRAME_KEY,
+  scrubStrings,
+  corpusEntryName,
+  fuzzFrame,
+} from "./corpus.js";
+
+// Chunked payload types
+export type {
+  ChunkWriter,
+  ChunkReader,
+  ChunkSource,
+  WriteStreamOptions,
+} from "./chunked.js";
+
+export {
+  ChunkKind,
+  CHUNK_HEADER_SIZE,
+  writeStream,
+  readStream,
+} from "./chunked.js";
+
+// Wire specification types
+export type {
+  WireSpec,
+  WireField,
+  HandshakeStep,
+} from "./wire-spec.js";
+
+export {
+  generateWireSpec,
+  formatWireSpec,
+} from "./wire-spec.js";
+
+// Protocol reference types
+export type {
+  VariantDoc,
+  ProtocolDocOptions,
+  ProtocolDoc,
+  DocumentedVariant,
+  DocumentedField,
+} from "./protocol-doc.js";
+
+export {
+  describeProtocol,
+  formatProtocolDoc,
+  formatProtocolDocHtml,
+} from "./protocol-doc.js";
+
+// Client module types
+export type { ClientModuleOptions } from "./client-module.js";
+
+export { generateClientModule } from "./client-module.js";
+
+// Control frame types
+export type {
+  ControlFrame,
+} from "./control.js";
+
+export {
+  ControlFrameKind,
+  CONTROL_FRAME_FLAG,
+} from "./control.js";
+
+// Audit mode
+export {
+  setAuditMode,
+  isAuditMode,
+  redact,
+  isRedacted,
+} from "./audit.js";
+
+// Null cipher
+export {
+  DangerousNullCipher,
+  allowNullCipher,
+  isNullCipherAllowed,
+} from "./null-cipher.js";
+
+// Debug assertions
+export {
+  InvariantViolation,
+  setDebugAssertions,
+  areDebugAssertionsEnabled,
+} from "./invariants.js";
+
+// Key log types
+export type {
+  KeyLogSink,
+  KeyLogDirection,
+} from "./keylog.js";
+
+export {
+  KeyLogger,
+  allowKeyLog,
+  isKeyLogAllowed,
+  keyLogFile,
+  formatKeyLogLine,
+} from "./keylog.js";
+
+// Key provider types
+export type {
+  KeyExchangeKey,
+  KeyExchangeProvider,
+  IdentitySigner,
+} from "./key-provider.js";
+
+export {
+  inMemoryKeyExchange,
+} from "./key-provider.js";
+
+// Compression types
+export type {
+  CompressionDictionary,
+  CompressionOptions,
+  CompressionAlgorithm,
+} from "./compression.js";
+
+export {
+  createDictionary,
+  buildDictionary,
+  supportedCompressionAlgorithms,
+} from "./compression.js";
+
+// Identity types
+export type {
+  IdentityCertificate,
+  IdentityCredentials,
+  IdentityKey,
+  StaticIdentityKey,
+  CertificateRequest,
+  PeerIdentity,
+  IdentityTrust,
+} from "./identity.js";
+
+export {
+  issueCertificate,
+  encodeCertificate,
+  decodeCertificate,
+  verifyCertificate,
+  keyFingerprint,
+} from "./identity.js";
+
+export type {
+  X509Credentials,
+} from "./x509.js";
+
+export {
+  verifyX509Chain,
+  parseX509Certificate,
+  x509Identity,
+} from "./x509.js";
+
+// Ticket types
+export type {
+  TicketKey,
+  TicketKeyringOptions,
+} from "./tickets.js";
+
+export {
+  TicketKeyring,
+  generateTicketKey,
+  createTicketKeySchedule,
+  encodeTicketKeys,
+  decodeTicketKeys,
+} from "./tickets.js";
+
+// Resumption types
+export type {
+  SessionTicket,
+  ResumedSession,
+} from "./resumption.js";
+
+export type {
+  ReplayCache,
+  MemoryReplayCacheOptions,
+} from "./replay.js";
+
+export {
+  MemoryReplayCache,
+  StorageReplayCache,
+} from "./replay.js";
+
+// Storage types
+export type {
+  Storage,
+  StorageWriteOptions,
+  FileStorageOptions,
+} from "./storage.js";
+
+export {
+  MemoryStorage,
+  FileStorage,
+} from "./storage.js";
+
+// Known hosts types
+export type {
+  PeerKeyChange,
+  KnownHostsOptions,
+} from "./known-hosts.js";
+
+export {
+  KnownHosts,
+  peerKeyFingerprint,
+} from "./known-hosts.js";
+
+// Session handoff types
+export type {
+  SessionState,
+} from "./handoff.js";
+
+export {
+  encodeSessionState,
+  decodeSessionState,
+} from "./handoff.js";
+
+// Journal types
+export type {
+  JournalEntry,
+  PacketJournalOptions,
+} from "./journal.js";
+
+export {
+  PacketJournal,
+} from "./journal.js";
+
+// Broadcast types
+export type {
+  BroadcastTarget,
+  BroadcastOptions,
+  BroadcastResult,
+  BroadcastSummary,
+} from "./broadcast.js";
+
+export {
+  broadcast,
+} from "./broadcast.js";
+
+// Clock types
+export type {
+  Clock,
+  TimerHandle,
+} from "./clock.js";
+
+export type {
+  ClockSample,
+  ClockOffsetEstimate,
+} from "./time-sync.js";
+
+export {
+  ClockOffsetEstimator,
+} from "./time-sync.js";
+
+// Rekeying types
+export type {
+  RekeyKeys,
+} from "./rekey.js";
+
+export {
+  deriveRekeyKeys,
+} from "./rekey.js";
+
+// Hybrid key exchange types
+export type {
+  PostQuantumMode,
+  MlKem,
+  HybridOffer,
+} from "./hybrid.js";
+
+export {
+  deriveHybridKeys,
+  loadMlKem,
+} from "./hybrid.js";
+
+// Protocol version types
+export type {
+  ProtocolVersionOptions,
+  VersionAcceptance,
+} from "./versioning.js";
+
+export {
+  acceptsVersion,
+  encodeVersionOffer,
+  decodeVersionOffer,
+  encodeVersionVerdict,
+  decodeVersionVerdict,
+  normalizeVersionOptions,
+} from "./versioning.js";
+
+// Payload format types
+export type {
+  WireFormat,
+  PayloadFormat,
+} from "./formats.js";
+
+export {
+  payloadFormat,
+  encodeFormatOffer,
+  decodeFormatOffer,
+  chooseFormat,
+} from "./formats.js";
+
+// Derived protocol types
+export type {
+  RenameRule,
+  EnumObject,
+  EnumVariant,
+  PayloadOf,
+  DerivedValue,
+  DeriveProtocolOptions,
+  DerivedProtocol,
+} from "./derive.js";
+
+export {
+  deriveProtocol,
+  applyRenameRule,
+} from "./derive.js";
+
+// Bandwidth types
+export type {
+  BandwidthEstimate,
+} from "./bandwidth.js";
+
+export {
+  BandwidthEstimator,
+} from "./bandwidth.js";
+
+// Watchdog types
+export type {
+  StallDirection,
+  StallState,
+  StallReport,
+  WatchdogOptions,
+  StallProbe,
+} from "./watchdog.js";
+
+export {
+  StallWatchdog,
+} from "./watchdog.js";
+
+// Batching types
+export type {
+  FlushPolicy,
+  BatchTarget,
+  SendQueueStats,
+  QueuedWriteOptions,
+  CancellableWrite,
+} from "./batching.js";
+
+export {
+  BatchingWriter,
+} from "./batching.js";
+
+// Shared writer types
+export type {
+  SharedWriterOptions,
+} from "./shared-writer.js";
+
+export {
+  SharedWriter,
+} from "./shared-writer.js";
+
+// Prefetch types
+export type {
+  PrefetchSource,
+  PrefetchOptions,
+} from "./prefetch.js";
+
+export {
+  PrefetchReader,
+} from "./prefetch.js";
+
+// Duplicate filter types
+export type {
+  MessageId,
+  DuplicateFilterOptions,
+} from "./dedupe.js";
+
+export {
+  DuplicateFilter,
+} from "./dedupe.js";
+
+export {
+  systemClock,
+  wallClock,
+  ManualClock,
+  sleepOn,
+} from "./clock.js";
+
+// Concurrency
+export {
+  Mutex,
+} from "./mutex.js";
+
+// Testing types
+export type {
+  LinkProfile,
+  JitterDistribution,
+  ReadPathAttack,
+  ReadPathBatteryOptions,
+  ReadPathResult,
+  MemoryPairOptions,
+  EncryptedPair,
+} from "./testing.js";
+
+export {
+  LinkProfiles,
+  LinkShaper,
+  createChaosPair,
+  seededRandom,
+  runReadPathBattery,
+  MemoryDuplex,
+  createMemoryPair,
+  createEncryptedPair,
+} from "./testing.js";
+
+// Simulated network types
+export type {
+  SimNetworkOptions,
+} from "./sim.js";
+
+export {
+  SimNetwork,
+  SimServer,
+  SimSocket,
+} from "./sim.js";
+
+// Shared memory types
+export type {
+  SharedMemoryEndpoint,
+} from "./shared-memory.js";
+
+export {
+  SharedRing,
+  DEFAULT_RING_CAPACITY,
+  createSharedMemoryChannel,
+  openSharedMemoryStream,
+} from "./shared-memory.js";
+
+// Datagram types
+export type {
+  DatagramTransport,
+  EncryptedDatagramOptions,
+  DatagramStats,
+} from "./datagram.js";
+
+export {
+  EncryptedDatagram,
+  ReplayWindow,
+  DATAGRAM_OVERHEAD,
+  DEFAULT_REPLAY_WINDOW,
+  DEFAULT_MAX_DATAGRAM_SIZE,
+  udpTransport,
+} from "./datagram.js";
+
+// Web transport types
+export type {
+  ByteTransport,
+  WebSocketLike,
+  WebClientOptions,
+} from "./web.js";
+
+export {
+  WebClient,
+  webSocketTransport,
+  webStreamTransport,
+  transportStream,
+} from "./web.js";
+
+// QUIC transport types
+export type {
+  QuicBidirectionalStream,
+  QuicConnectionLike,
+  QuicSessionOptions,
+  QuicChannel,
+} from "./quic.js";
+
+export {
+  QuicSession,
+  quicStream,
+  QUIC_CHANNEL_LABEL,
+  QUIC_CHANNEL_HEADER_LENGTH,
+} from "./quic.js";
+
+// Group session types
+export type {
+  GroupEvent,
+  GroupMembershipEvent,
+  GroupInfo,
+  GroupSessionOptions,
+  GroupRelayOptions,
+} from "./group.js";
+
+export {
+  GroupSession,
+  GroupRelay,
+  GroupEnvelopeKind,
+} from "./group.js";
+
+// Bridge types
+export type {
+  BridgeEndpoint,
+  BridgeOptions,
+  Bridge,
+} from "./bridge.js";
+
+export {
+  BRIDGE_HEADER_LENGTH,
+  startBridge,
+  bridgeStream,
+} from "./bridge.js";
+
+// Logging types
+export type {
+  LogValue,
+  ConnectionLogContext,
+  ConnectionLogRecord,
+  ConnectionLoggerOptions,
+} from "./logging.js";
+
+export {
+  ConnectionLogger,
+} from "./logging.js";
+
+// Padding types
+export type {
+  PaddingPolicy,
+  PaddingOptions,
+} from "./padding.js";
+
+export {
+  PacketPadder,
+  padmeLength,
+  paddedLength,
+  unpadPacket,
+  PADDING_HEADER_LENGTH,
+} from "./padding.js";
+
+// Cipher suite types
+export type {
+  CipherSuite,
+  AeadSuite,
+} from "./suites.js";
+
+export {
+  Aes256GcmCipher,
+  CIPHER_SUITES,
+  XCHACHA20_POLY1305,
+  AES_256_GCM,
+  suiteCipher,
+  chooseSuite,
+} from "./suites.js";
+
+// Prometheus types
+export type {
+  PrometheusExporterOptions,
+} from "./prometheus.js";
+
+export {
+  PrometheusExporter,
+  DEFAULT_HANDSHAKE_BUCKETS,
+  PROMETHEUS_CONTENT_TYPE,
+} from "./prometheus.js";
+
+// Protocol statistics types
+export type {
+  PacketDirection,
+  ProtocolStatsOptions,
+  VariantCount,
+} from "./protocol-stats.js";
+
+export {
+  ProtocolStats,
+  ANONYMOUS_IDENTITY,
+  OTHER_IDENTITY,
+  UNKNOWN_VARIANT,
+} from "./protocol-stats.js";
+
+// Keying material exporter
+export {
+  MAX_EXPORTED_LENGTH,
+  deriveExporterSecret,
+  exportKeyingMaterial,
+} from "./keying-material.js";
+
+// Self-test types
+export type {
+  SelfTestOptions,
+  SelfTestCheck,
+  SelfTestReport,
+} from "./selftest.js";
+
+export {
+  runSelfTest,
+  formatSelfTestReport,
+} from "./selftest.js";
+
+// Schema types
+export type {
+  FieldType,
+  SchemaField,
+  SchemaEvolution,
+  SchemaOptions,
+  PacketSchema,
+} from "./schema.js";
+
+export {
+  defineSchema,
+  encodeStruct,
+  decodeStruct,
+  checkAppendOnly,
+  schemaHash,
+} from "./schema.js";
+
 // Bincode types
 export type {
   ReadResult,
+  DecodeLimits,
 } from "./bincode.js";
 
 export {
   BincodeReader,
+  DecodeBudget,
+  DEFAULT_MAX_DEPTH,
+  checkDecodeLimits,
+  checkSequenceLength,
+  checkStringLength,
+  checkDepth,
 } from "./bincode.js";
 
 // Handshake types
 export type {
   HandshakeResult,
+  HandshakeTimings,
+  TimedHandshakeResult,
 } from "./handshake.js";
 
+export { HandshakeMachine } from "./handshake.js";
+
+// Sans-IO connection types
+export type { ClavisConnectionOptions } from "./connection.js";
+export { ClavisConnection } from "./connection.js";
+
+// Record layer codec types
+export type { ClavisCodecOptions, ClavisFramed } from "./codec.js";
+export { ClavisCodec, clavisFramed } from "./codec.js";
+
+export type {
+  HandshakeKeys,
+  HandshakeMessage,
+  HandshakeMessageKind,
+  HandshakeStepTemplate,
+} from "./handshake-messages.js";
+
+export type { PskSelection } from "./handshake.js";
+
+export {
+  HANDSHAKE_VERSION,
+  HANDSHAKE_MESSAGE_LENGTHS,
+  encodeHandshakeMessage,
+  decodeHandshakeMessage,
+  handshakeSteps,
+  isHandshakeInitiator,
+  handshakeTranscript,
+  handshakeMac,
+  deriveHandshakeKeys,
+  encodePskIdentity,
+  decodePskIdentity,
+} from "./handshake-messages.js";
+
 // Client types
 export type {
   ClavisClientOptions,
   ClavisClientEvents,
   ConnectionStatus,
   ReconnectOptions,
+  Connector,
 } from "./client.js";
 
 export {
   ClavisClient,
 } from "./client.js";
 
+// Reconnecting stream types
+export type {
+  ReconnectingStreamOptions,
+  ReconnectingStreamEvents,
+  ReconnectInfo,
+} from "./reconnecting.js";
+
+export {
+  ReconnectingEncryptedStream,
+  isConnectionLost,
+} from "./reconnecting.js";
+
+// Backoff types
+export type {
+  BackoffJitter,
+  RetryPolicy,
+} from "./backoff.js";
+
+export {
+  Backoff,
+  backoffDelay,
+} from "./backoff.js";
+
+export type {
+  ResolvedAddress,
+  ResolvedRecord,
+  HostResolverOptions,
+} from "./resolver.js";
+
+export {
+  HostResolver,
+  resolveHost,
+} from "./resolver.js";
+
+// Listener types
+export type {
+  EncryptedListenerOptions,
+  EncryptedListenerEvents,
+  AcceptedStream,
+  PeerAddress,
+  AcceptedSplit,
+  ListenerSocket,
+  ConnectionServer,
+  HandshakeRateLimitOptions,
+} from "./listener.js";
+
+export {
+  EncryptedListener,
+  HandshakeLimiter,
+  HandshakeRateLimiter,
+  detectForeignProtocol,
+} from "./listener.js";
+
+// Load shedding types
+export type {
+  SheddingOrder,
+  LoadSheddingOptions,
+  TrackedConnection,
+} from "./shedding.js";
+
+export {
+  LoadShedder,
+} from "./shedding.js";
+
+// Shard types
+export type {
+  ShardOptions,
+  ShardContext,
+} from "./shards.js";
+
+export {
+  ShardSet,
+  spawnShards,
+  shardContext,
+  bindShard,
+} from "./shards.js";
+
 // Crypto types
 export type {
   X25519KeyPair,
+  Ed25519KeyPair,
+  FrameCipher,
 } from "./crypto.js";
 
 export {
   XChaCha20Poly1305Cipher,
   generateX25519KeyPair,
+  generateEd25519KeyPair,
   computeSharedSecret,
   sha256Hash,
   hmacSha256,
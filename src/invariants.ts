@@ -0,0 +1,112 @@
+/**
+ * Debug assertions
+ * Internal consistency checks on the frame layer, for development and tests
+ *
+ * A bug that hands out a sequence number twice, writes frames in another
+ * order than it sealed them or loses track of buffered bytes doesn't fail
+ * where it happens: the peer sees a replay error, a bad MAC or a stalled
+ * read some frames later, often only against another implementation. With
+ * debug assertions on, every stream checks that its nonces count up by one,
+ * that frames reach the transport whole and in the order they were sealed,
+ * and that its buffer counters match the bytes they count, and throws an
+ * `InvariantViolation` at the first mismatch.
+ *
+ * On under `bun test` (which sets `NODE_ENV=test`) and with
+ * `CLAVIS_DEBUG_ASSERTIONS=1`; `CLAVIS_DEBUG_ASSERTIONS=0` turns them off
+ * in tests. Streams check the switch once when they are created, so with
+ * assertions off, as in production, each check site costs a single
+ * `undefined` test.
+ */
+
+import { FRAME_HEADER_LENGTH, FRAME_NONCE_LENGTH, decodeFrameHeader, nonceSequence } from "./frame.js";
+
+const env = typeof process !== "undefined" ? process.env : undefined;
+
+let enabled = env?.CLAVIS_DEBUG_ASSERTIONS === "1"
+  || (env?.NODE_ENV === "test" && env.CLAVIS_DEBUG_ASSERTIONS !== "0");
+
+/**
+ * Turn debug assertions on or off for streams created from now on
+ */
+export function setDebugAssertions(on: boolean): void {
+  enabled = on;
+}
+
+/**
+ * Whether debug assertions are on
+ */
+export function areDebugAssertionsEnabled(): boolean {
+  return enabled;
+}
+
+/**
+ * A broken internal invariant: a bug in clavis, never something a peer can cause
+ */
+export class InvariantViolation extends Error {
+  constructor(message: string) {
+    super(`Frame layer invariant violated: ${message}`);
+    this.name = "InvariantViolation";
+  }
+}
+
+/** Throw an `InvariantViolation` unless `condition` holds */
+export function invariant(condition: boolean, message: string): asserts condition {
+  if (!condition) throw new InvariantViolation(message);
+}
+
+/** Check that `counted` is the total length of `chunks` */
+export function checkBuffered(counted: number, chunks: readonly Uint8Array[], what: string): void {
+  let total = 0;
+  for (const chunk of chunks) total += chunk.length;
+  invariant(counted === total, `${what} counted as ${counted} bytes, holds ${total}`);
+}
+
+/**
+ * Checks on one stream's outgoing frames: sequence numbers are handed out
+ * once each and in order, and frames reach the transport whole and, once
+ * nonces carry their numbers, in the order they were numbered
+ */
+export class FrameInvariants {
+  private sealed: number;
+  private written: number;
+
+  /**
+   * @param next - Sequence number of the next frame to seal
+   * @param numberedFrom - First frame whose nonce carries its number, if any was sealed yet
+   */
+  constructor(next: number, private numberedFrom: number | undefined = undefined) {
+    this.sealed = next;
+    this.written = next;
+  }
+
+  /** A frame is about to be sealed as `sequence`, under `nonce` when it is numbered */
+  sealing(sequence: number, nonce: Uint8Array | undefined): void {
+    invariant(sequence === this.sealed, `sealing frame ${sequence}, expected ${this.sealed}`);
+    this.sealed++;
+    if (nonce) {
+      const carried = nonceSequence(nonce);
+      invariant(carried === sequence, `frame ${sequence} carries ${carried} in its nonce`);
+      this.numberedFrom ??= sequence;
+    } else {
+      invariant(this.numberedFrom === undefined, `frame ${sequence} has a random nonce after numbered frames`);
+    }
+  }
+
+  /** `bytes` are about to be handed to the transport; they must be whole frames */
+  writing(bytes: Uint8Array): void {
+    let offset = 0;
+    while (offset < bytes.length) {
+      invariant(bytes.length - offset >= FRAME_HEADER_LENGTH, `${bytes.length - offset} bytes after the last frame`);
+      const { length } = decodeFrameHeader(bytes.subarray(offset));
+      const end = offset + FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH + length;
+      invariant(end <= bytes.length, `frame at byte ${offset} needs ${end - offset} bytes, has ${bytes.length - offset}`);
+      invariant(this.written < this.sealed, `writing frame ${this.written}, only ${this.sealed} sealed`);
+      if (this.numberedFrom !== undefined && this.written >= this.numberedFrom) {
+        const sequence = nonceSequence(bytes.subarray(offset + FRAME_HEADER_LENGTH));
+        invariant(sequence === this.written, `writing frame ${sequence}, expected ${this.written}`);
+      }
+      this.written++;
+      offset = end;
+    }
+  }
+}
@@ -0,0 +1,103 @@
+/**
+ * Packet journal
+ * Write-ahead log of received packets, so a server that crashes mid-handler
+ * can finish what it had accepted
+ *
+ * A stream with a `journal` appends every application packet to it before
+ * handing the packet out, and only then acknowledges it to the peer with an
+ * Ack control frame. With a durable `Storage` (such as `FileStorage`) an
+ * acknowledged packet has been fsynced, so the sender may forget it. The
+ * handler marks an entry complete once its effects are stored; on restart,
+ * `pending()` returns whatever was accepted but never completed.
+ *
+ * Processing is exactly-once only up to the handler: a crash after the
+ * handler's effects but before `complete()` replays the packet, so handlers
+ * should be idempotent for the entries they see in recovery.
+ */
+
+import { ClavisError } from "./error.js";
+import { Mutex } from "./mutex.js";
+import type { Storage } from "./storage.js";
+
+/**
+ * One journaled packet
+ */
+export interface JournalEntry {
+  /** Position in the journal, increasing across connections and restarts */
+  readonly sequence: number;
+  /** Packet plaintext as received */
+  readonly packet: Uint8Array;
+}
+
+/**
+ * Options for a packet journal
+ */
+export interface PacketJournalOptions {
+  /** Key prefix in the storage, so several journals can share one (default: "journal/") */
+  prefix?: string | undefined;
+}
+
+/** Sequence digits in a key; zero-padded so keys sort in journal order */
+const SEQUENCE_DIGITS = 16;
+
+/**
+ * Journal of received packets on top of a `Storage` backend
+ */
+export class PacketJournal {
+  private readonly prefix: string;
+  private readonly lock = new Mutex();
+  private nextSequence: number | undefined;
+
+  constructor(readonly storage: Storage, options?: PacketJournalOptions) {
+    this.prefix = options?.prefix ?? "journal/";
+  }
+
+  /**
+   * Store `packet` as the next entry; resolves once the storage has it
+   */
+  append(packet: Uint8Array): Promise<JournalEntry> {
+    return this.lock.runExclusive(async () => {
+      const sequence = this.nextSequence ?? (await this.lastSequence()) + 1;
+      await this.storage.set(this.key(sequence), packet);
+      this.nextSequence = sequence + 1;
+      return { sequence, packet };
+    });
+  }
+
+  /**
+   * Mark an entry as handled, removing it from the journal
+   */
+  async complete(sequence: number): Promise<void> {
+    await this.storage.delete(this.key(sequence));
+  }
+
+  /**
+   * Entries appended but not yet completed, oldest first.
+   * Call on startup, before accepting connections, to finish interrupted work.
+   */
+  async pending(): Promise<JournalEntry[]> {
+    const entries: JournalEntry[] = [];
+    for (const key of await this.storage.keys(this.prefix)) {
+      const packet = await this.storage.get(key);
+      if (packet) entries.push({ sequence: this.sequenceOf(key), packet });
+    }
+    return entries;
+  }
+
+  private async lastSequence(): Promise<number> {
+    const keys = await this.storage.keys(this.prefix);
+    const last = keys[keys.length - 1];
+    return last === undefined ? -1 : this.sequenceOf(last);
+  }
+
+  private key(sequence: number): string {
+    if (!Number.isSafeInteger(sequence) || sequence < 0) {
+      throw ClavisError.invalidOperation(`Invalid journal sequence ${sequence}`);
+    }
+    return this.prefix + String(sequence).padStart(SEQUENCE_DIGITS, "0");
+  }
+
+  private sequenceOf(key: string): number {
+    return Number(key.slice(this.prefix.length));
+  }
+}
@@ -0,0 +1,115 @@
+/**
+ * Key providers
+ * Private-key operations of the handshake and identity proofs behind
+ * interfaces, so the keys can live in an HSM, a TPM or an OS keystore
+ * instead of process memory
+ *
+ * A `KeyExchangeProvider` makes the handshake's ephemeral X25519 key pair
+ * and performs the agreement with the peer's public key; only the public
+ * key and the shared secret come back. An `IdentitySigner` stands in for the
+ * secret key of an identity certificate or a bare static key and signs the
+ * identity proof. For X.509 identities, pass a `KeyObject` for the leaf key,
+ * which Node.js can back with an OpenSSL engine or provider, or an
+ * `IdentitySigner` making signatures the way `crypto.sign()` would.
+ *
+ * The operations are synchronous, like the PKCS#11 bindings for Node.js,
+ * because the handshake runs as a state machine without I/O of its own.
+ * The traffic keys derived from the shared secret stay in process memory,
+ * as every frame is sealed with them, and so does the ML-KEM key of the
+ * `postQuantum` upgrade.
+ */
+
+import { generateX25519KeyPair, computeSharedSecret } from "./crypto.js";
+import { ClavisError, CryptoError, CryptoOperation } from "./error.js";
+
+/**
+ * An X25519 private key held by a provider
+ */
+export interface KeyExchangeKey {
+  /** The 32-byte public key sent to the peer */
+  readonly publicKey: Uint8Array;
+  /** X25519 of this key and the peer's 32-byte public key */
+  agree(peerPublicKey: Uint8Array): Uint8Array;
+  /**
+   * Called once the agreement is done, to destroy the key (optional). A
+   * handshake abandoned before the peer's public key arrived doesn't call
+   * it, so prefer session objects the keystore discards by itself.
+   */
+  destroy?(): void;
+}
+
+/**
+ * Source of the handshake's ephemeral key pairs
+ *
+ * @example
+ * ```typescript
+ * // `keystore` wraps the HSM's PKCS#11 session: C_GenerateKeyPair, then
+ * // C_DeriveKey with CKM_ECDH1_DERIVE, then C_DestroyObject
+ * const hsm: KeyExchangeProvider = {
+ *   generateEphemeral() {
+ *     const { publicKey, handle } = keystore.generateX25519();
+ *     return {
+ *       publicKey,
+ *       agree: (peer) => keystore.deriveX25519(handle, peer),
+ *       destroy: () => keystore.destroy(handle),
+ *     };
+ *   },
+ * };
+ * const stream = await EncryptedStream.new(socket, { psk, keyExchange: hsm });
+ * ```
+ */
+export interface KeyExchangeProvider {
+  /** A fresh key pair, used for one handshake */
+  generateEphemeral(): KeyExchangeKey;
+}
+
+/**
+ * Signs with an Ed25519 static key (or, for X.509, the leaf's key) that
+ * never leaves its keystore
+ */
+export interface IdentitySigner {
+  /** The 32-byte Ed25519 public key; unused for X.509, where the leaf certificate carries it */
+  readonly publicKey?: Uint8Array | undefined;
+  /** Signature over `message` */
+  sign(message: Uint8Array): Uint8Array;
+}
+
+/**
+ * The default provider: key pairs generated and used in process memory
+ */
+export const inMemoryKeyExchange: KeyExchangeProvider = {
+  generateEphemeral() {
+    const { secret, publicKey } = generateX25519KeyPair();
+    return {
+      publicKey,
+      agree: (peerPublicKey) => computeSharedSecret(secret, peerPublicKey),
+      destroy: () => secret.fill(0),
+    };
+  },
+};
+
+/**
+ * Run a provider's key generation, checking what it returns
+ */
+export function generateEphemeralKey(provider: KeyExchangeProvider): KeyExchangeKey {
+  const key = provider.generateEphemeral();
+  if (key.publicKey.length !== 32) {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("Key exchange public key must be 32 bytes"));
+  }
+  return key;
+}
+
+/**
+ * Run a provider's key agreement, checking what it returns. An all-zero
+ * secret means the peer sent a low-order point.
+ */
+export function agreeWith(key: KeyExchangeKey, peerPublicKey: Uint8Array): Uint8Array {
+  if (peerPublicKey.length !== 32) {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("Public key must be 32 bytes"));
+  }
+  const secret = key.agree(peerPublicKey);
+  if (secret.length !== 32 || secret.every((b) => b === 0)) {
+    throw ClavisError.cryptoFailure(CryptoOperation.KeyExchange, "Key exchange produced an invalid shared secret");
+  }
+  return secret;
+}
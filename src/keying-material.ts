@@ -0,0 +1,87 @@
+/**
+ * Keying material exporter
+ * Secrets derived from a connection's keys, for binding application-layer
+ * credentials to the channel they were sent over
+ *
+ * Modeled on the TLS exporter (RFC 5705 / RFC 8446 section 7.5): both peers
+ * derive the same bytes for the same label and context, and nobody without
+ * the session keys can. A login token MACed with exported bytes is useless
+ * on any other connection, so replaying it elsewhere fails.
+ *
+ * The exporter secret is fixed when setup completes, after any post-quantum
+ * exchange, and does not change on rekey:
+ *   exporter secret = HKDF-Expand(resumption secret, transcript hash, "clavis exporter")
+ *   output = HKDF-SHA256(exporter secret, no salt,
+ *     "clavis exporter" || u16 label length || label || u32 context length || context, length)
+ * An empty context and no context give the same output.
+ *
+ * The session id is not a secret: it is computed from the handshake
+ * transcript alone, so both peers get the same id without exchanging it,
+ * and it names a session in logs without revealing anything about its keys:
+ *   session id = hex(SHA-256("clavis session id" || transcript hash)[0..16])
+ */
+
+import { hkdf } from "@noble/hashes/hkdf.js";
+import { sha256 } from "@noble/hashes/sha2.js";
+import { hkdfExpand } from "./crypto.js";
+import { ClavisError } from "./error.js";
+import { redact } from "./audit.js";
+import type { HandshakeResult } from "./handshake.js";
+
+/** Most bytes one export can produce, the HKDF-SHA256 limit */
+export const MAX_EXPORTED_LENGTH = 255 * 32;
+
+const EXPORTER_INFO = "clavis exporter";
+const SESSION_ID_LABEL = "clavis session id";
+/** Bytes of the session id before hex encoding: 128 bits, so ids don't collide */
+const SESSION_ID_LENGTH = 16;
+
+/**
+ * Exporter secret of a session set up with `handshake`
+ */
+export function deriveExporterSecret(handshake: HandshakeResult): Uint8Array {
+  return hkdfExpand(handshake.resumptionSecret, handshake.transcriptHash, EXPORTER_INFO);
+}
+
+/**
+ * Session id of a session set up with `handshake`, as 32 hex digits
+ */
+export function deriveSessionId(handshake: HandshakeResult): string {
+  const label = new TextEncoder().encode(SESSION_ID_LABEL);
+  const input = new Uint8Array(label.length + handshake.transcriptHash.length);
+  input.set(label);
+  input.set(handshake.transcriptHash, label.length);
+  return Array.from(sha256(input).subarray(0, SESSION_ID_LENGTH), (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+/**
+ * `length` bytes bound to `secret`, `label` and `context`
+ */
+export function exportKeyingMaterial(
+  secret: Uint8Array,
+  label: string,
+  context: Uint8Array = new Uint8Array(0),
+  length = 32
+): Uint8Array {
+  const labelBytes = new TextEncoder().encode(label);
+  if (labelBytes.length === 0 || labelBytes.length > 0xffff) {
+    throw ClavisError.config("Exporter label must be 1 to 65535 bytes of UTF-8");
+  }
+  if (!Number.isInteger(length) || length < 1 || length > MAX_EXPORTED_LENGTH) {
+    throw ClavisError.config(`Exported length must be an integer from 1 to ${MAX_EXPORTED_LENGTH}, got ${length}`);
+  }
+  const prefix = new TextEncoder().encode(EXPORTER_INFO);
+  const info = new Uint8Array(prefix.length + 2 + labelBytes.length + 4 + context.length);
+  const view = new DataView(info.buffer);
+  let offset = 0;
+  info.set(prefix, offset);
+  offset += prefix.length;
+  view.setUint16(offset, labelBytes.length);
+  offset += 2;
+  info.set(labelBytes, offset);
+  offset += labelBytes.length;
+  view.setUint32(offset, context.length);
+  offset += 4;
+  info.set(context, offset);
+  return redact(hkdf(sha256, secret, undefined, info, length), "key");
+}
@@ -0,0 +1,102 @@
+/**
+ * Key log
+ * Traffic keys written out one line at a time, in the spirit of
+ * SSLKEYLOGFILE, so a dissector can decrypt captured sessions while
+ * debugging interop with the Rust crate
+ *
+ * DANGER: a key log reads and forges every session it covers. It is for
+ * test setups, never for production traffic.
+ *
+ * Off by default; a process enables it with `CLAVIS_ALLOW_KEYLOG=1` in the
+ * environment or `allowKeyLog(true)`, and then each stream with `keyLog`.
+ *
+ * Each line is `<label> <session id> <key>`, with the key in hex:
+ *
+ *   CLAVIS_CLIENT_TRAFFIC_KEY_0 3f9c...e1 9a41...07
+ *
+ * The label names the direction (the client is the side that initiated
+ * the handshake) and the key's epoch. Epoch 0 is the handshake's key; each
+ * switch after it, for a post-quantum upgrade, a cipher suite or a rekey,
+ * logs the next epoch. The session id is `stream.sessionId`, the same on
+ * both peers.
+ */
+
+import { appendFileSync } from "fs";
+import { ClavisError } from "./error.js";
+
+/** Receives each key log line, without a trailing newline */
+export type KeyLogSink = (line: string) => void;
+
+let allowed = process.env.CLAVIS_ALLOW_KEYLOG === "1";
+
+/**
+ * Allow or forbid the `keyLog` stream option in this process
+ */
+export function allowKeyLog(enabled: boolean): void {
+  allowed = enabled;
+}
+
+/**
+ * Whether the `keyLog` stream option may be used in this process
+ */
+export function isKeyLogAllowed(): boolean {
+  return allowed;
+}
+
+/**
+ * A sink appending lines to a file, created if missing, like the file
+ * named by SSLKEYLOGFILE
+ *
+ * @example
+ * ```typescript
+ * const stream = await EncryptedStream.new(socket, {
+ *   psk,
+ *   keyLog: keyLogFile(process.env.CLAVIS_KEYLOGFILE!),
+ * });
+ * ```
+ */
+export function keyLogFile(path: string): KeyLogSink {
+  return (line) => appendFileSync(path, `${line}\n`, { mode: 0o600 });
+}
+
+/** Direction a logged key protects */
+export type KeyLogDirection = "client" | "server";
+
+/**
+ * One key log line
+ */
+export function formatKeyLogLine(direction: KeyLogDirection, epoch: number, sessionId: string, key: Uint8Array): string {
+  const hex = Array.from(key, (b) => b.toString(16).padStart(2, "0")).join("");
+  return `CLAVIS_${direction.toUpperCase()}_TRAFFIC_KEY_${epoch} ${sessionId} ${hex}`;
+}
+
+/**
+ * Logs the keys of one stream, numbering the epochs of each direction
+ */
+export class KeyLogger {
+  private readonly epochs: Record<KeyLogDirection, number> = { client: 0, server: 0 };
+
+  constructor(
+    private readonly sink: KeyLogSink,
+    private readonly sessionId: string,
+    private readonly initiator: boolean
+  ) {
+    if (!allowed) {
+      throw ClavisError.config("Key logging is disabled; set CLAVIS_ALLOW_KEYLOG=1 or call allowKeyLog(true)");
+    }
+  }
+
+  /** Log the key this side now seals with */
+  sending(key: Uint8Array): void {
+    this.log(this.initiator ? "client" : "server", key);
+  }
+
+  /** Log the key this side now opens the peer's frames with */
+  receiving(key: Uint8Array): void {
+    this.log(this.initiator ? "server" : "client", key);
+  }
+
+  private log(direction: KeyLogDirection, key: Uint8Array): void {
+    this.sink(formatKeyLogLine(direction, this.epochs[direction]++, this.sessionId, key));
+  }
+}
@@ -0,0 +1,139 @@
+/**
+ * Known hosts
+ * Remembers the key each host presented and notices when it changes, the
+ * way SSH's known_hosts file does
+ *
+ * The first connection to a host trusts whatever key it presents and
+ * stores its fingerprint. Later connections compare: the same key passes,
+ * another one is either refused with a `PeerKeyChangedError` or accepted
+ * and reported, depending on `onChange`. The fingerprint is that of the
+ * key the peer proved it holds: the bare key, the certificate's key, or
+ * the X.509 leaf's key, so certificates renewed over the same key don't
+ * count as a change. Fingerprints live in a `Storage`, under
+ * `known-hosts/<host>`.
+ */
+
+import { ClavisError } from "./error.js";
+import { keyFingerprint, type PeerIdentity } from "./identity.js";
+import type { Storage } from "./storage.js";
+
+const KEY_PREFIX = "known-hosts/";
+
+/**
+ * A host that presented another key than the one remembered for it
+ */
+export interface PeerKeyChange {
+  host: string;
+  /** Fingerprint remembered for the host */
+  previous: string;
+  /** Fingerprint it presented now */
+  current: string;
+  /** Whether the connection went ahead with the new key */
+  accepted: boolean;
+}
+
+/**
+ * Options for `KnownHosts`
+ */
+export interface KnownHostsOptions {
+  /** Where fingerprints are kept; a `FileStorage` keeps them across restarts */
+  storage: Storage;
+  /**
+   * What to do when a host presents another key:
+   * - "refuse" (default): fail the connection with a `PeerKeyChangedError`
+   * - "warn": accept the new key, remember it and report the change
+   */
+  onChange?: "refuse" | "warn" | undefined;
+  /** Trust hosts seen for the first time and remember their key (default: true) */
+  trustOnFirstUse?: boolean | undefined;
+  /** Called for every changed key, refused or accepted */
+  onKeyChanged?: ((change: PeerKeyChange) => void) | undefined;
+}
+
+/**
+ * Fingerprint of the key a verified peer proved it holds, or undefined for
+ * identities that carry no key
+ */
+export function peerKeyFingerprint(peer: PeerIdentity): string | undefined {
+  if (peer.publicKey) return keyFingerprint(peer.publicKey);
+  if (peer.certificate) return keyFingerprint(peer.certificate.publicKey);
+  if (peer.x509) {
+    const spki = peer.x509.publicKey.export({ type: "spki", format: "der" });
+    return keyFingerprint(new Uint8Array(spki));
+  }
+  return undefined;
+}
+
+/**
+ * Host key fingerprints, checked on every connection through `verifier()`
+ *
+ * @example
+ * ```typescript
+ * const knownHosts = new KnownHosts({ storage: new FileStorage("./known-hosts") });
+ * const stream = await EncryptedStream.new(socket, {
+ *   verifyPeer: knownHosts.verifier("db.example.com:7000"),
+ * });
+ * ```
+ */
+export class KnownHosts {
+  private readonly storage: Storage;
+  private readonly onChange: "refuse" | "warn";
+  private readonly trustOnFirstUse: boolean;
+  private readonly onKeyChanged: ((change: PeerKeyChange) => void) | undefined;
+
+  constructor(options: KnownHostsOptions) {
+    this.storage = options.storage;
+    this.onChange = options.onChange ?? "refuse";
+    this.trustOnFirstUse = options.trustOnFirstUse ?? true;
+    this.onKeyChanged = options.onKeyChanged;
+  }
+
+  /**
+   * A `verifyPeer` callback for connections to `host`: true for the
+   * remembered key, or a first key under trust on first use. Throws a
+   * `PeerKeyChangedError` for a refused change, which fails the handshake
+   * with that error.
+   */
+  verifier(host: string): (peer: PeerIdentity) => Promise<boolean> {
+    return (peer) => this.check(host, peer);
+  }
+
+  /** Check `peer` against the key remembered for `host`; see `verifier()` */
+  async check(host: string, peer: PeerIdentity): Promise<boolean> {
+    const current = peerKeyFingerprint(peer);
+    if (current === undefined) return false;
+    const key = KEY_PREFIX + host;
+    const encoded = new TextEncoder().encode(current);
+    const stored = await this.storage.get(key);
+    if (stored === undefined) {
+      if (!this.trustOnFirstUse) return false;
+      // Another connection may have remembered a key in the meantime
+      if (await this.storage.setIfAbsent(key, encoded)) return true;
+      return this.check(host, peer);
+    }
+
+    const previous = new TextDecoder().decode(stored);
+    if (previous === current) return true;
+    const accepted = this.onChange === "warn";
+    if (accepted) await this.storage.set(key, encoded);
+    this.onKeyChanged?.({ host, previous, current, accepted });
+    if (!accepted) throw ClavisError.peerKeyChanged(host, previous, current);
+    return true;
+  }
+
+  /** Fingerprint remembered for `host`, if any */
+  async get(host: string): Promise<string | undefined> {
+    const stored = await this.storage.get(KEY_PREFIX + host);
+    return stored === undefined ? undefined : new TextDecoder().decode(stored);
+  }
+
+  /** Remember `fingerprint` for `host`, e.g. to pin a key before the first connection */
+  async trust(host: string, fingerprint: string): Promise<void> {
+    await this.storage.set(KEY_PREFIX + host, new TextEncoder().encode(fingerprint));
+  }
+
+  /** Forget `host`, so the next key it presents is trusted again; resolves whether it was known */
+  forget(host: string): Promise<boolean> {
+    return this.storage.delete(KEY_PREFIX + host);
+  }
+}
@@ -0,0 +1,756 @@
+/**
+ * Encrypted listener
+ * Accepts TCP connections and runs handshakes off the accept path,
+ * yielding ready-to-use encrypted streams
+ *
+ * Connections from the open internet are screened before they cost any
+ * crypto: the filter, the per-address rate limit and the first-bytes check
+ * all run before a socket takes a handshake slot, and the number of slots
+ * and the queue for them are bounded.
+ */
+
+import { EventEmitter } from "events";
+import { createServer, Socket, type AddressInfo } from "net";
+import type { Duplex } from "stream";
+import { EncryptedStream, type EncryptedStreamOptions, type SplitResult } from "./stream.js";
+import { ClavisError, StreamError, StreamErrorCode } from "./error.js";
+import { readProxyHeader, type ProxyHeader } from "./proxy-protocol.js";
+import { systemClock, type Clock } from "./clock.js";
+import { LoadShedder, type LoadSheddingOptions } from "./shedding.js";
+
+/**
+ * A connection as the listener sees it: a TCP socket, or a stand-in such as
+ * a `SimNetwork` connection
+ */
+export type ListenerSocket = Duplex & {
+  readonly remoteAddress?: string | undefined;
+  readonly remotePort?: number | undefined;
+};
+
+/**
+ * Where a listener gets its connections from: a `net.Server`, or anything
+ * emitting "connection" and "error" the same way (see `SimNetwork.bind()`)
+ */
+export interface ConnectionServer<S extends ListenerSocket = Socket> {
+  on(event: "connection", listener: (socket: S) => void): unknown;
+  on(event: "error", listener: (error: Error) => void): unknown;
+  close(callback?: (error?: Error) => void): unknown;
+  address(): AddressInfo | string | null;
+}
+
+/**
+ * Options for configuring an encrypted listener
+ */
+export interface EncryptedListenerOptions<S extends ListenerSocket = Socket> {
+  /** Options applied to every accepted stream */
+  streamOptions?: EncryptedStreamOptions | undefined;
+  /**
+   * Per-connection overrides of `streamOptions`, chosen from the peer's
+   * address or the name it asked for (`peer.serverName`). Called after
+   * `filter`; each returned field replaces the default one, and undefined
+   * keeps the defaults. A rejection drops the connection as a handshake error.
+   */
+  streamOptionsFor?: ((peer: PeerAddress) => EncryptedStreamOptions | undefined | Promise<EncryptedStreamOptions | undefined>) | undefined;
+  /**
+   * Time a handshake may take once it has a slot, in milliseconds
+   * (default: 10000). A `handshakeTimeoutMs` in the stream options wins.
+   * Bounds how long a slow or silent client can hold a slot.
+   */
+  handshakeTimeoutMs?: number | undefined;
+  /** Maximum number of handshakes running at the same time (default: 64) */
+  maxConcurrentHandshakes?: number | undefined;
+  /**
+   * Maximum number of sockets waiting for a handshake slot (default: 256).
+   * Sockets beyond this are rejected as overloaded. Set to 0 to reject
+   * as soon as all handshake slots are busy.
+   */
+  maxQueuedHandshakes?: number | undefined;
+  /**
+   * Called for every new socket before any handshake work is done.
+   * Return false to drop the connection immediately (e.g. IP blocklists).
+   */
+  filter?: ((peer: PeerAddress) => boolean | Promise<boolean>) | undefined;
+  /** Expect a PROXY protocol (v1 or v2) header ahead of the handshake (default: false) */
+  proxyProtocol?: boolean | undefined;
+  /** Time allowed for the PROXY header to arrive in milliseconds (default: 5000) */
+  proxyHeaderTimeoutMs?: number | undefined;
+  /**
+   * Limit how fast each address may start handshakes (default: no limit).
+   * Connections over the limit are dropped before any handshake work and
+   * reported as "rateLimited".
+   */
+  handshakeRateLimit?: HandshakeRateLimitOptions | undefined;
+  /**
+   * Wait for the peer's first bytes before giving it a handshake slot, and
+   * drop connections that open with another protocol, such as a TLS
+   * ClientHello or an HTTP request (default: false). Clavis clients send
+   * their nonce right away, so only scanners and silent sockets wait.
+   */
+  screenFirstBytes?: boolean | undefined;
+  /** Time allowed for the first bytes to arrive in milliseconds (default: 5000) */
+  firstBytesTimeoutMs?: number | undefined;
+  /** Time source for the PROXY header and first-bytes timeouts (default: `systemClock`) */
+  clock?: Clock | undefined;
+  /**
+   * Bind with SO_REUSEPORT so several listeners, typically one per worker
+   * thread, can share the port and the kernel spreads connections across
+   * them (default: false). See `spawnShards()`.
+   */
+  reusePort?: boolean | undefined;
+  /**
+   * Close accepted connections when the server is overloaded (default: off).
+   * Each new socket checks the overload signals and evicts one accepted
+   * connection while they trip; `shed()` evicts on demand.
+   */
+  loadShedding?: (LoadSheddingOptions<AcceptedStream<S>> & {
+    /** Priority of an accepted connection; lower is evicted first (default: 0) */
+    priority?: ((conn: AcceptedStream<S>) => number) | undefined;
+  }) | undefined;
+}
+
+/**
+ * Address information for an incoming connection
+ */
+export interface PeerAddress {
+  /** Address of the directly connected peer */
+  remoteAddress: string | undefined;
+  /** Port of the directly connected peer */
+  remotePort: number | undefined;
+  /** Original client address reported by a PROXY header, if any */
+  proxied?: ProxyHeader | undefined;
+  /**
+   * Host name the client connected to, if known. Clavis handshakes carry no
+   * name, so this comes from the PROXY v2 authority a TLS-terminating or
+   * SNI-routing proxy sends along (needs `proxyProtocol`).
+   */
+  serverName?: string | undefined;
+}
+
+/**
+ * A connection that completed its handshake
+ */
+export interface AcceptedStream<S extends ListenerSocket = Socket> {
+  stream: EncryptedStream;
+  socket: S;
+  peer: PeerAddress;
+  /**
+   * Change the connection's load shedding priority while it runs, replacing
+   * what `loadShedding.priority` returned (set only with `loadShedding`)
+   */
+  setPriority?: ((priority: number) => void) | undefined;
+}
+
+/**
+ * A connection that completed its handshake, already split into halves
+ * for separate read and write tasks
+ */
+export type AcceptedSplit<S extends ListenerSocket = Socket> = Omit<AcceptedStream<S>, "stream"> & SplitResult;
+
+/**
+ * Events emitted by EncryptedListener
+ */
+export interface EncryptedListenerEvents<S extends ListenerSocket = Socket> {
+  /** Emitted when a handshake fails (the socket is destroyed) */
+  handshakeError: [error: ClavisError, socket: S];
+  /** Emitted when a socket is rejected because the handshake queue is full */
+  overloaded: [socket: S];
+  /** Emitted when the filter callback rejects a connection */
+  rejected: [peer: PeerAddress, socket: S];
+  /** Emitted when a connection is dropped for exceeding `handshakeRateLimit` */
+  rateLimited: [peer: PeerAddress, socket: S];
+  /** Emitted when load shedding closes an accepted connection */
+  evicted: [conn: AcceptedStream<S>, reason: string];
+  /** Emitted when the underlying server fails */
+  error: [error: ClavisError];
+  /** Emitted once the listener has closed */
+  close: [];
+}
+
+/**
+ * Type-safe event emitter interface
+ */
+export interface EncryptedListenerEmitter<S extends ListenerSocket = Socket> {
+  on<K extends keyof EncryptedListenerEvents<S>>(event: K, listener: (...args: EncryptedListenerEvents<S>[K]) => void): this;
+  once<K extends keyof EncryptedListenerEvents<S>>(event: K, listener: (...args: EncryptedListenerEvents<S>[K]) => void): this;
+  off<K extends keyof EncryptedListenerEvents<S>>(event: K, listener: (...args: EncryptedListenerEvents<S>[K]) => void): this;
+  emit<K extends keyof EncryptedListenerEvents<S>>(event: K, ...args: EncryptedListenerEvents<S>[K]): boolean;
+}
+
+const DEFAULT_MAX_CONCURRENT_HANDSHAKES = 64;
+const DEFAULT_MAX_QUEUED_HANDSHAKES = 256;
+const DEFAULT_MAX_TRACKED_ADDRESSES = 10_000;
+const DEFAULT_FIRST_BYTES_TIMEOUT_MS = 5000;
+const DEFAULT_HANDSHAKE_TIMEOUT_MS = 10000;
+/** Bytes `detectForeignProtocol` looks at; every clavis nonce is longer */
+const SNIFF_LENGTH = 8;
+
+interface QueuedAcquire {
+  resolve: (release: () => void) => void;
+  reject: (error: Error) => void;
+}
+
+function toClavisError(error: unknown): ClavisError {
+  if (error instanceof ClavisError) return error;
+  return ClavisError.stream(StreamError.handshakeFailed(
+    error instanceof Error ? error.message : String(error),
+    error instanceof Error ? error : undefined
+  ));
+}
+
+/**
+ * Bounds the number of concurrently running handshakes.
+ * Callers beyond the limit wait in a bounded FIFO queue; once the queue is
+ * full, `acquire()` fails immediately with an Overloaded error.
+ */
+export class HandshakeLimiter {
+  private running = 0;
+  private queue: QueuedAcquire[] = [];
+
+  constructor(
+    private readonly maxConcurrent: number = DEFAULT_MAX_CONCURRENT_HANDSHAKES,
+    private readonly maxQueued: number = DEFAULT_MAX_QUEUED_HANDSHAKES
+  ) {
+    if (maxConcurrent < 1) {
+      throw ClavisError.config("maxConcurrentHandshakes must be at least 1");
+    }
+    if (maxQueued < 0) {
+      throw ClavisError.config("maxQueuedHandshakes must not be negative");
+    }
+  }
+
+  /** Number of handshakes currently running */
+  get active(): number {
+    return this.running;
+  }
+
+  /** Number of handshakes waiting for a slot */
+  get queued(): number {
+    return this.queue.length;
+  }
+
+  /**
+   * Wait for a handshake slot.
+   * Resolves with a release function that must be called exactly once.
+   *
+   * @param signal - Aborting removes the caller from the queue
+   */
+  acquire(signal?: AbortSignal): Promise<() => void> {
+    if (this.running < this.maxConcurrent) {
+      this.running++;
+      return Promise.resolve(this.releaser());
+    }
+
+    if (this.queue.length >= this.maxQueued) {
+      return Promise.reject(ClavisError.stream(
+        StreamError.overloaded(`${this.running} handshakes running, ${this.queue.length} queued`)
+      ));
+    }
+
+    return new Promise((resolve, reject) => {
+      const entry: QueuedAcquire = { resolve, reject };
+      this.queue.push(entry);
+
+      signal?.addEventListener("abort", () => {
+        const index = this.queue.indexOf(entry);
+        if (index !== -1) {
+          this.queue.splice(index, 1);
+          reject(ClavisError.stream(StreamError.connectionClosed("Handshake cancelled while queued")));
+        }
+      }, { once: true });
+    });
+  }
+
+  private releaser(): () => void {
+    let released = false;
+    return () => {
+      if (released) return;
+      released = true;
+
+      const next = this.queue.shift();
+      if (next) {
+        // Hand the slot straight to the next waiter
+        next.resolve(this.releaser());
+      } else {
+        this.running--;
+      }
+    };
+  }
+}
+
+/**
+ * Options for `HandshakeRateLimiter` and the listener's `handshakeRateLimit`
+ */
+export interface HandshakeRateLimitOptions {
+  /** Handshakes per second each address may start once its burst is spent */
+  ratePerSec: number;
+  /** Handshakes an address may start back to back (default: `ratePerSec`, at least 1) */
+  burst?: number | undefined;
+  /**
+   * Key connections are counted under (default: the PROXY source address,
+   * else the socket's). Return a prefix to count IPv6 networks rather than
+   * single addresses, or undefined to exempt the connection.
+   */
+  key?: ((peer: PeerAddress) => string | undefined) | undefined;
+  /**
+   * Most keys tracked at once (default: 10000). Beyond it the least
+   * recently seen are forgotten, which gives them a full burst again.
+   */
+  maxTracked?: number | undefined;
+  /** Time source for refilling (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+interface Bucket {
+  tokens: number;
+  refilledAt: number;
+}
+
+/**
+ * Token bucket per address, for limiting how fast each one starts handshakes
+ */
+export class HandshakeRateLimiter {
+  private readonly buckets = new Map<string, Bucket>();
+  private readonly ratePerSec: number;
+  private readonly burst: number;
+  private readonly maxTracked: number;
+  private readonly clock: Clock;
+
+  constructor(private readonly options: HandshakeRateLimitOptions) {
+    if (!(options.ratePerSec > 0)) {
+      throw ClavisError.config("handshakeRateLimit.ratePerSec must be positive");
+    }
+    this.ratePerSec = options.ratePerSec;
+    this.burst = options.burst ?? Math.max(1, options.ratePerSec);
+    if (!(this.burst >= 1)) {
+      throw ClavisError.config("handshakeRateLimit.burst must be at least 1");
+    }
+    this.maxTracked = options.maxTracked ?? DEFAULT_MAX_TRACKED_ADDRESSES;
+    if (!Number.isInteger(this.maxTracked) || this.maxTracked < 1) {
+      throw ClavisError.config("handshakeRateLimit.maxTracked must be a positive integer");
+    }
+    this.clock = options.clock ?? systemClock;
+  }
+
+  /** Number of keys currently tracked */
+  get tracked(): number {
+    return this.buckets.size;
+  }
+
+  /** Key a peer is counted under, or undefined if it is exempt */
+  keyFor(peer: PeerAddress): string | undefined {
+    if (this.options.key) return this.options.key(peer);
+    return peer.proxied?.sourceAddress ?? peer.remoteAddress;
+  }
+
+  /**
+   * Take one handshake from `key`'s bucket.
+   * Returns false, taking nothing, once the bucket is empty.
+   */
+  tryAcquire(key: string): boolean {
+    const now = this.clock.now();
+    const bucket = this.buckets.get(key) ?? { tokens: this.burst, refilledAt: now };
+    bucket.tokens = Math.min(this.burst, bucket.tokens + ((now - bucket.refilledAt) * this.ratePerSec) / 1000);
+    bucket.refilledAt = now;
+
+    // Re-insert so the map stays ordered from least to most recently seen
+    this.buckets.delete(key);
+    this.buckets.set(key, bucket);
+    if (this.buckets.size > this.maxTracked) {
+      this.buckets.delete(this.buckets.keys().next().value!);
+    }
+
+    if (bucket.tokens < 1) return false;
+    bucket.tokens -= 1;
+    return true;
+  }
+}
+
+/**
+ * Name the protocol a connection opened with, if it is one clavis servers
+ * commonly see from scanners: "TLS" (a ClientHello record), "HTTP", "HTTP/2",
+ * "SSH" or "PROXY". Undefined for anything else, including every real
+ * clavis nonce but roughly one in a billion.
+ *
+ * Looks at the first 8 bytes; fewer may give a false negative.
+ */
+export function detectForeignProtocol(bytes: Uint8Array): string | undefined {
+  const text = String.fromCharCode(...bytes.subarray(0, SNIFF_LENGTH));
+  // Handshake record, SSL 3.0 to TLS 1.3 record version, ClientHello
+  if (bytes.length >= 6 && bytes[0] === 0x16 && bytes[1] === 0x03 && bytes[2]! <= 0x04 && bytes[5] === 0x01) {
+    return "TLS";
+  }
+  if (text.startsWith("PRI * HT")) return "HTTP/2";
+  if (/^(GET|HEAD|POST|PUT|DELETE|OPTIONS|PATCH|CONNECT|TRACE) /.test(text)) return "HTTP";
+  if (text.startsWith("SSH-")) return "SSH";
+  if (text.startsWith("PROXY ") || text.startsWith("\r\n\r\n\0\r\nQ")) return "PROXY";
+  return undefined;
+}
+
+/**
+ * Wait for at least `length` bytes from `socket` and put them back, so the
+ * next reader sees them again
+ */
+function peekBytes(socket: Duplex, length: number, timeoutMs: number, clock: Clock): Promise<Uint8Array> {
+  return new Promise((resolve, reject) => {
+    const chunks: Buffer[] = [];
+    let buffered = 0;
+
+    const cleanup = () => {
+      timer.cancel();
+      socket.off("data", onData);
+      socket.off("end", onEnd);
+      socket.off("close", onEnd);
+      socket.pause();
+    };
+
+    const timer = clock.setTimer(() => {
+      cleanup();
+      reject(ClavisError.stream(StreamError.timeout(timeoutMs)));
+    }, timeoutMs);
+
+    const onData = (chunk: Buffer) => {
+      chunks.push(chunk);
+      buffered += chunk.length;
+      if (buffered < length) return;
+      cleanup();
+      const bytes = Buffer.concat(chunks);
+      socket.unshift(bytes);
+      resolve(new Uint8Array(bytes.buffer, bytes.byteOffset, bytes.length));
+    };
+
+    const onEnd = () => {
+      cleanup();
+      reject(ClavisError.stream(StreamError.unexpectedClose()));
+    };
+
+    socket.on("data", onData);
+    socket.once("end", onEnd);
+    socket.once("close", onEnd);
+  });
+}
+
+/**
+ * Split a `"host:port"` listen address; IPv6 hosts go in brackets
+ */
+function parseListenAddress(address: string): { host: string; port: number } {
+  const match = /^(?:\[([^\]]+)\]|([^:]*)):(\d+)$/.exec(address);
+  const port = Number(match?.[3]);
+  if (!match || !(port <= 65535)) {
+    throw ClavisError.config(`Listen address must look like "host:port", got "${address}"`);
+  }
+  return { host: match[1] ?? (match[2] || "127.0.0.1"), port };
+}
+
+/**
+ * Server-side accept helper.
+ * Handshakes run concurrently (bounded by `maxConcurrentHandshakes`) so a slow
+ * client never blocks the accept loop; completed streams are handed out by `accept()`.
+ *
+ * @example
+ * ```typescript
+ * const listener = await EncryptedListener.bind(7272, "0.0.0.0", {
+ *   streamOptions: { psk: process.env.CLAVIS_PSK },
+ * });
+ *
+ * for await (const { stream } of listener) {
+ *   const { reader, writer } = stream.split();
+ *   // ...
+ * }
+ * ```
+ */
+export class EncryptedListener<S extends ListenerSocket = Socket> extends EventEmitter implements EncryptedListenerEmitter<S> {
+  private readonly limiter: HandshakeLimiter;
+  private readonly rateLimiter: HandshakeRateLimiter | undefined;
+  private ready: AcceptedStream<S>[] = [];
+  private waiters: Array<{ resolve: (conn: AcceptedStream<S>) => void; reject: (error: Error) => void }> = [];
+  private pending = new Set<S>();
+  private closed = false;
+  private readonly shedder: LoadShedder<AcceptedStream<S>> | undefined;
+
+  constructor(
+    private readonly server: ConnectionServer<S>,
+    private readonly options: EncryptedListenerOptions<S> = {}
+  ) {
+    super();
+    this.limiter = new HandshakeLimiter(
+      options.maxConcurrentHandshakes ?? DEFAULT_MAX_CONCURRENT_HANDSHAKES,
+      options.maxQueuedHandshakes ?? DEFAULT_MAX_QUEUED_HANDSHAKES
+    );
+    if (options.handshakeRateLimit) {
+      this.rateLimiter = new HandshakeRateLimiter({ clock: options.clock, ...options.handshakeRateLimit });
+    }
+    if (options.firstBytesTimeoutMs !== undefined && !(options.firstBytesTimeoutMs > 0)) {
+      throw ClavisError.config("firstBytesTimeoutMs must be positive");
+    }
+    if (options.handshakeTimeoutMs !== undefined && !(options.handshakeTimeoutMs > 0)) {
+      throw ClavisError.config("handshakeTimeoutMs must be positive");
+    }
+
+    if (options.loadShedding) {
+      this.shedder = new LoadShedder(
+        options.loadShedding,
+        (conn, reason) => {
+          this.emit("evicted", conn, reason);
+          conn.socket.destroy();
+        },
+        () => this.limiter.queued
+      );
+    }
+
+    server.on("connection", (socket: S) => {
+      void this.handleSocket(socket);
+      void this.shedder?.shed(1);
+    });
+    server.on("error", (error: Error) => {
+      this.emit("error", ClavisError.stream(StreamError.io(error)));
+    });
+  }
+
+  /**
+   * Create a TCP server listening on the given port and wrap it. The
+   * address may also be given as one `"host:port"` string (`"[::1]:7000"`
+   * for IPv6). Either way a missing host means 127.0.0.1; accepting from
+   * other machines takes an explicit `"0.0.0.0"` or `"::"`.
+   */
+  static bind(address: string, options?: EncryptedListenerOptions): Promise<EncryptedListener>;
+  static bind(port: number, host?: string, options?: EncryptedListenerOptions): Promise<EncryptedListener>;
+  static bind(
+    portOrAddress: number | string,
+    hostOrOptions?: string | EncryptedListenerOptions,
+    maybeOptions?: EncryptedListenerOptions
+  ): Promise<EncryptedListener> {
+    let port: number;
+    let host: string;
+    let options: EncryptedListenerOptions | undefined;
+    try {
+      if (typeof portOrAddress === "string") {
+        ({ port, host } = parseListenAddress(portOrAddress));
+        options = hostOrOptions as EncryptedListenerOptions | undefined;
+      } else {
+        port = portOrAddress;
+        host = (hostOrOptions as string | undefined) ?? "127.0.0.1";
+        options = maybeOptions;
+      }
+    } catch (error) {
+      return Promise.reject(error);
+    }
+    const server = createServer();
+    const listener = new EncryptedListener(server, options);
+
+    return new Promise((resolve, reject) => {
+      const onError = (error: Error) => reject(ClavisError.stream(StreamError.io(error)));
+      server.once("error", onError);
+      const reusePort = options?.reusePort ?? false;
+      server.listen({ port, host, reusePort }, () => {
+        server.off("error", onError);
+        resolve(listener);
+      });
+    });
+  }
+
+  /** Address the underlying server is bound to */
+  address(): AddressInfo | string | null {
+    return this.server.address();
+  }
+
+  /** Number of handshakes currently running */
+  get activeHandshakes(): number {
+    return this.limiter.active;
+  }
+
+  /** Number of sockets waiting for a handshake slot */
+  get queuedHandshakes(): number {
+    return this.limiter.queued;
+  }
+
+  /**
+   * Evict accepted connections while the load shedding signals trip, at
+   * most `max` of them (needs `loadShedding`). Resolves with the evicted
+   * connections.
+   */
+  shed(max?: number): Promise<AcceptedStream<S>[]> {
+    if (!this.shedder) {
+      return Promise.reject(ClavisError.invalidOperation("Load shedding is not configured"));
+    }
+    return this.shedder.shed(max);
+  }
+
+  /**
+   * Wait for the next connection that completed its handshake
+   */
+  accept(): Promise<AcceptedStream<S>> {
+    const conn = this.ready.shift();
+    if (conn) {
+      return Promise.resolve(conn);
+    }
+    if (this.closed) {
+      return Promise.reject(ClavisError.stream(StreamError.connectionClosed("Listener closed")));
+    }
+    return new Promise((resolve, reject) => {
+      this.waiters.push({ resolve, reject });
+    });
+  }
+
+  /**
+   * Wait for the next connection that completed its handshake, as a
+   * reader and a writer instead of a whole stream
+   */
+  async acceptSplit(): Promise<AcceptedSplit<S>> {
+    const { stream, ...conn } = await this.accept();
+    return { ...conn, ...stream.split() };
+  }
+
+  /**
+   * Iterate over accepted connections until the listener is closed
+   */
+  async *[Symbol.asyncIterator](): AsyncGenerator<AcceptedStream<S>, void, undefined> {
+    while (true) {
+      try {
+        yield await this.accept();
+      } catch (error) {
+        if (this.closed) return;
+        throw error;
+      }
+    }
+  }
+
+  /**
+   * Stop accepting connections.
+   * Sockets still waiting for or running a handshake are destroyed;
+   * already accepted streams are left untouched.
+   */
+  async close(): Promise<void> {
+    if (this.closed) return;
+    this.closed = true;
+
+    for (const socket of this.pending) {
+      socket.destroy();
+    }
+    this.pending.clear();
+
+    for (const { socket } of this.ready) {
+      socket.destroy();
+    }
+    this.ready = [];
+
+    const error = ClavisError.stream(StreamError.connectionClosed("Listener closed"));
+    for (const waiter of this.waiters.splice(0)) {
+      waiter.reject(error);
+    }
+
+    await new Promise<void>((resolve) => {
+      this.server.close(() => resolve());
+    });
+    this.emit("close");
+  }
+
+  private async handleSocket(socket: S): Promise<void> {
+    if (this.closed) {
+      socket.destroy();
+      return;
+    }
+
+    this.pending.add(socket);
+    const abort = new AbortController();
+    const onClose = () => abort.abort();
+    socket.once("close", onClose);
+    // Errors on pending sockets surface through the handshake
+    socket.on("error", () => {});
+
+    const peer: PeerAddress = {
+      remoteAddress: socket.remoteAddress,
+      remotePort: socket.remotePort,
+    };
+    let streamOptions = this.options.streamOptions;
+
+    try {
+      if (this.options.proxyProtocol) {
+        peer.proxied = await readProxyHeader(socket, this.options.proxyHeaderTimeoutMs, this.options.clock);
+        peer.serverName = peer.proxied.authority;
+      }
+      if (this.options.filter && !(await this.options.filter(peer))) {
+        this.pending.delete(socket);
+        socket.off("close", onClose);
+        this.emit("rejected", peer, socket);
+        socket.destroy();
+        return;
+      }
+      const key = this.rateLimiter?.keyFor(peer);
+      if (key !== undefined && !this.rateLimiter!.tryAcquire(key)) {
+        this.pending.delete(socket);
+        socket.off("close", onClose);
+        this.emit("rateLimited", peer, socket);
+        socket.destroy();
+        return;
+      }
+      if (this.options.screenFirstBytes) {
+        const timeoutMs = this.options.firstBytesTimeoutMs ?? DEFAULT_FIRST_BYTES_TIMEOUT_MS;
+        const first = await peekBytes(socket, SNIFF_LENGTH, timeoutMs, this.options.clock ?? systemClock);
+        const foreign = detectForeignProtocol(first);
+        if (foreign !== undefined) {
+          throw ClavisError.stream(StreamError.handshakeFailed(`Peer opened with ${foreign}, not a clavis handshake`));
+        }
+      }
+      if (this.options.streamOptionsFor) {
+        const overrides = await this.options.streamOptionsFor(peer);
+        if (overrides) streamOptions = { ...streamOptions, ...overrides };
+      }
+    } catch (error) {
+      this.pending.delete(socket);
+      socket.off("close", onClose);
+      this.emit("handshakeError", toClavisError(error), socket);
+      socket.destroy();
+      return;
+    }
+
+    let release: () => void;
+    try {
+      release = await this.limiter.acquire(abort.signal);
+    } catch (error) {
+      this.pending.delete(socket);
+      socket.off("close", onClose);
+      if (error instanceof ClavisError && error.cause instanceof StreamError && error.cause.code === StreamErrorCode.Overloaded) {
+        this.emit("overloaded", socket);
+      }
+      socket.destroy();
+      return;
+    }
+
+    try {
+      // Applied after the spread, so an explicit undefined can't leave a slot held forever
+      const stream = await EncryptedStream.new(socket, {
+        ...streamOptions,
+        handshakeTimeoutMs: streamOptions?.handshakeTimeoutMs ?? this.options.handshakeTimeoutMs ?? DEFAULT_HANDSHAKE_TIMEOUT_MS,
+      });
+      this.pending.delete(socket);
+      socket.off("close", onClose);
+      this.deliver({ stream, socket, peer });
+    } catch (error) {
+      this.pending.delete(socket);
+      socket.off("close", onClose);
+      this.emit("handshakeError", toClavisError(error), socket);
+      socket.destroy();
+    } finally {
+      release();
+    }
+  }
+
+  private deliver(conn: AcceptedStream<S>): void {
+    if (this.closed) {
+      conn.socket.destroy();
+      return;
+    }
+    if (this.shedder) {
+      const tracked = this.shedder.track(conn, this.options.loadShedding?.priority?.(conn) ?? 0);
+      conn.socket.on("data", tracked.touch);
+      conn.socket.once("close", tracked.untrack);
+      conn.setPriority = tracked.setPriority;
+    }
+    const waiter = this.waiters.shift();
+    if (waiter) {
+      waiter.resolve(conn);
+    } else {
+      this.ready.push(conn);
+    }
+  }
+}
@@ -0,0 +1,159 @@
+/**
+ * Connection logging
+ * A `StreamTracer` that turns spans into structured log records, each
+ * tagged with what is known about its connection
+ *
+ * Until the handshake completes a record carries only the connection id.
+ * From then on it also carries the peer's identity and the negotiated
+ * parameters, so log pipelines can slice by peer or tenant without any
+ * span plumbing in the application. Pass one `ConnectionLogger` as the
+ * `tracer` of every stream; it forgets a connection once its transport closes.
+ */
+
+import type { EncryptedStream, StreamTracer, TraceSpan, TraceSpanName } from "./stream.js";
+import type { WireFormat } from "./formats.js";
+import type { CompressionAlgorithm } from "./compression.js";
+import { systemClock, type Clock } from "./clock.js";
+
+/** A value a log record field may hold */
+export type LogValue = string | number | boolean | undefined;
+
+/**
+ * What a `ConnectionLogger` knows about a connection
+ */
+export interface ConnectionLogContext {
+  connectionId: string;
+  /** Session id both peers derive from the handshake (see `EncryptedStream.sessionId`) */
+  sessionId?: string | undefined;
+  /** The peer's verified identity, when identities are configured */
+  peerIdentity?: string | undefined;
+  /** Hex SHA-256 of the peer's credential */
+  credentialId?: string | undefined;
+  /** Whether the session was resumed from a ticket */
+  resumed?: boolean | undefined;
+  /** Whether the keys include a post-quantum exchange */
+  postQuantum?: boolean | undefined;
+  /** AEAD sealing the frames */
+  cipherSuite?: string | undefined;
+  /** Protocol version the peer announced */
+  protocolVersion?: number | undefined;
+  /** Negotiated payload format */
+  payloadFormat?: WireFormat | undefined;
+  /** Negotiated compression */
+  compression?: CompressionAlgorithm | undefined;
+  /** Largest packet the stream sends */
+  maxPacketSize?: number | undefined;
+  /** Fields added by `ConnectionLoggerOptions.fields` */
+  [field: string]: LogValue;
+}
+
+/**
+ * One finished span, as handed to `ConnectionLoggerOptions.log`
+ */
+export interface ConnectionLogRecord extends ConnectionLogContext {
+  /** The operation that finished */
+  event: TraceSpanName;
+  /** Whether it succeeded */
+  ok: boolean;
+  /** Milliseconds it took */
+  durationMs: number;
+  /** Message of the error it failed with */
+  error?: string | undefined;
+}
+
+/**
+ * Options for `ConnectionLogger`
+ */
+export interface ConnectionLoggerOptions {
+  /** Receives every record; hand it to pino, winston or `console.log` */
+  log: (record: ConnectionLogRecord) => void;
+  /** Operations to log (default: all of them) */
+  events?: readonly TraceSpanName[] | undefined;
+  /**
+   * Extra fields for a connection, computed once its handshake completes,
+   * such as the tenant a peer identity belongs to
+   */
+  fields?: ((stream: EncryptedStream) => Record<string, LogValue>) | undefined;
+  /** Time source for `durationMs` (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+const NO_SPAN: TraceSpan = { end() {} };
+
+/**
+ * Structured logs for every connection a tracer is given to
+ *
+ * @example
+ * ```typescript
+ * const logger = new ConnectionLogger({
+ *   log: (record) => pino.info(record, record.event),
+ *   events: ["clavis.handshake"],
+ *   fields: (stream) => ({ tenant: tenants.of(stream.peerIdentity?.identity) }),
+ * });
+ * const listener = await EncryptedListener.bind(7000, "0.0.0.0", {
+ *   streamOptions: { trustedSigners, tracer: logger },
+ * });
+ * ```
+ */
+export class ConnectionLogger implements StreamTracer {
+  private readonly contexts = new Map<string, ConnectionLogContext>();
+  private readonly events: ReadonlySet<TraceSpanName> | undefined;
+  private readonly clock: Clock;
+
+  constructor(private readonly options: ConnectionLoggerOptions) {
+    this.events = options.events && new Set(options.events);
+    this.clock = options.clock ?? systemClock;
+  }
+
+  /** Number of open connections the logger holds context for */
+  get size(): number {
+    return this.contexts.size;
+  }
+
+  /**
+   * Fields of a connection, to tag the application's own log lines the
+   * same way; only the id until its handshake completes
+   */
+  context(connectionId: string): ConnectionLogContext {
+    return this.contexts.get(connectionId) ?? { connectionId };
+  }
+
+  startSpan(name: TraceSpanName, attributes: { connectionId: string }): TraceSpan {
+    if (this.events && !this.events.has(name)) return NO_SPAN;
+    const start = this.clock.now();
+    return {
+      end: (error) => {
+        const record: ConnectionLogRecord = {
+          ...this.context(attributes.connectionId),
+          event: name,
+          ok: error === undefined,
+          durationMs: this.clock.now() - start,
+        };
+        if (error !== undefined) record.error = error instanceof Error ? error.message : String(error);
+        this.options.log(record);
+      },
+    };
+  }
+
+  established(stream: EncryptedStream): void {
+    const identity = stream.peerIdentity;
+    this.contexts.set(stream.connectionId, {
+      ...this.options.fields?.(stream),
+      connectionId: stream.connectionId,
+      sessionId: stream.sessionId,
+      peerIdentity: identity?.identity,
+      credentialId: identity?.credentialId,
+      resumed: stream.resumed,
+      postQuantum: stream.postQuantum,
+      cipherSuite: stream.cipherSuite,
+      protocolVersion: stream.peerProtocolVersion,
+      payloadFormat: stream.payloadFormat,
+      compression: stream.compressionAlgorithm,
+      maxPacketSize: stream.maxPacketSize,
+    });
+  }
+
+  closed(connectionId: string): void {
+    this.contexts.delete(connectionId);
+  }
+}
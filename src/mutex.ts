@@ -0,0 +1,68 @@
+/**
+ * Async mutex
+ * Serializes async sections that share state across awaits
+ *
+ * JavaScript runs one task at a time, but an async function that awaits in
+ * the middle of updating shared state (such as a frame half read from a
+ * socket) can be interleaved with another call doing the same. Sections that
+ * must not interleave run under a `Mutex`; waiters are served in FIFO order.
+ */
+
+/**
+ * FIFO async mutex
+ */
+export class Mutex {
+  private held = false;
+  private waiters: Array<() => void> = [];
+
+  /** Whether a section currently holds the lock */
+  get locked(): boolean {
+    return this.held;
+  }
+
+  /** Number of callers waiting for the lock */
+  get waiting(): number {
+    return this.waiters.length;
+  }
+
+  /**
+   * Wait for the lock.
+   * Resolves with a release function; calling it more than once has no effect.
+   */
+  lock(): Promise<() => void> {
+    if (!this.held) {
+      this.held = true;
+      return Promise.resolve(this.releaser());
+    }
+    return new Promise((resolve) => {
+      this.waiters.push(() => resolve(this.releaser()));
+    });
+  }
+
+  /**
+   * Run `fn` while holding the lock, releasing it however `fn` ends
+   */
+  async runExclusive<R>(fn: () => R | Promise<R>): Promise<R> {
+    const release = await this.lock();
+    try {
+      return await fn();
+    } finally {
+      release();
+    }
+  }
+
+  private releaser(): () => void {
+    let released = false;
+    return () => {
+      if (released) return;
+      released = true;
+      const next = this.waiters.shift();
+      if (next) {
+        // Hand the lock straight to the next waiter so nobody can barge in
+        next();
+      } else {
+        this.held = false;
+      }
+    };
+  }
+}
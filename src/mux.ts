@@ -0,0 +1,690 @@
+/**
+ * Channel multiplexing
+ * Independent logical channels over one encrypted reader/writer pair
+ *
+ * Every packet carries a small envelope:
+ * - kind: u8 (see `MuxFrameKind`)
+ * - channel ID: u32 little-endian
+ * - payload: the channel's packet bytes
+ *
+ * Channels are flow controlled separately with packet credits, so a reader
+ * that stops consuming one channel only stalls that channel's writer; the
+ * others, and the connection, keep moving.
+ *
+ * Credits bound what a channel has in flight, not how much of the
+ * connection it takes: a bulk channel with a full window still queues its
+ * packets ahead of everyone else's. With the `scheduler` option, channel
+ * packets wait in per-channel queues instead and go out by deficit round
+ * robin, so each busy channel gets a share of the bytes sent in proportion
+ * to its weight.
+ *
+ * With `idleTimeoutMs`, channels that carry nothing for that long are
+ * closed in both directions and their IDs freed, so connections that open
+ * many short-lived channels don't leak the ones their users forget.
+ */
+
+import { EventEmitter } from "events";
+import type { EncryptedReader, EncryptedStream, EncryptedWriter } from "./stream.js";
+import { ClavisError, StreamError } from "./error.js";
+import { RawPacket, type PacketTrait } from "./protocol.js";
+import { readU32, writeU32 } from "./bincode.js";
+import { AsyncQueue, CreditGate, FairQueue, type FlowStats } from "./flow-control.js";
+import { systemClock, type Clock, type TimerHandle } from "./clock.js";
+
+/**
+ * Envelope kinds
+ */
+export enum MuxFrameKind {
+  /** Opens a channel, or answers an open; payload is the sender's receive window (u32) */
+  Open = 0,
+  /** One packet on the channel */
+  Data = 1,
+  /** Grants the peer more packets on the channel (u32) */
+  Credit = 2,
+  /** The sender will write nothing more on the channel */
+  Close = 3,
+}
+
+/** Size of the envelope header in bytes */
+export const MUX_HEADER_SIZE = 5;
+
+/**
+ * Decoded multiplexing envelope
+ */
+export interface MuxEnvelope {
+  kind: MuxFrameKind;
+  channel: number;
+  payload: Uint8Array;
+}
+
+/**
+ * Encode a multiplexing envelope
+ */
+export function encodeMuxEnvelope(kind: MuxFrameKind, channel: number, payload: Uint8Array): Uint8Array {
+  const header: number[] = [kind];
+  writeU32(header, channel);
+  const frame = new Uint8Array(MUX_HEADER_SIZE + payload.length);
+  frame.set(header, 0);
+  frame.set(payload, MUX_HEADER_SIZE);
+  return frame;
+}
+
+/**
+ * Decode a multiplexing envelope
+ */
+export function decodeMuxEnvelope(data: Uint8Array): MuxEnvelope {
+  if (data.length < MUX_HEADER_SIZE) {
+    throw ClavisError.deserializationFailed("Channel envelope too short");
+  }
+  const kind = data[0]!;
+  if (kind > MuxFrameKind.Close) {
+    throw ClavisError.deserializationFailed(`Unknown channel frame kind: ${kind}`);
+  }
+  return {
+    kind: kind as MuxFrameKind,
+    channel: readU32(data, 1).value,
+    payload: data.subarray(MUX_HEADER_SIZE),
+  };
+}
+
+/**
+ * Options for configuring a channel multiplexer
+ */
+export interface ChannelMuxOptions {
+  /**
+   * Packets each channel buffers before its peer must wait for credits
+   * (default: 64). A function sets it per channel ID, for the channels
+   * this side opens and those it accepts alike.
+   */
+  window?: number | ((channel: number) => number) | undefined;
+  /**
+   * Share the connection fairly between channels (default: off, packets
+   * go out in the order they are written)
+   */
+  scheduler?: MuxSchedulerOptions | undefined;
+  /**
+   * Close channels that send, receive and read nothing for this many
+   * milliseconds (default: never). A function sets it per channel ID and
+   * may return undefined to keep a channel, such as a long-lived control
+   * channel, open however quiet it gets. Closed channels are reported as
+   * "reaped".
+   */
+  idleTimeoutMs?: number | ((channel: number) => number | undefined) | undefined;
+  /** Time source for idle timeouts (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+/**
+ * Options for the fair scheduler of a channel multiplexer
+ */
+export interface MuxSchedulerOptions {
+  /** Bytes a channel of weight 1 may send per turn (default: 16384) */
+  quantum?: number | undefined;
+  /**
+   * Weight of a channel by ID, a positive number (default: 1 for every
+   * channel); `setChannelWeight()` changes it later
+   */
+  weight?: ((channel: number) => number) | undefined;
+}
+
+/**
+ * Send queue of one channel under the fair scheduler.
+ * Bytes count whole envelopes.
+ */
+export interface ChannelQueueStats extends FlowStats {
+  channel: number;
+}
+
+/**
+ * Events emitted by ChannelMux
+ */
+export interface ChannelMuxEvents {
+  /** Non-fatal error (e.g. a malformed envelope) */
+  error: [error: ClavisError];
+  /**
+   * A channel was closed for being idle: the peer was sent a close, and
+   * this side's reader and writer now fail
+   */
+  reaped: [channel: LogicalChannel];
+  /** The underlying reader stopped; every channel has been closed */
+  close: [reason: ClavisError];
+}
+
+/**
+ * Type-safe event emitter interface
+ */
+export interface ChannelMuxEmitter {
+  on<K extends keyof ChannelMuxEvents>(event: K, listener: (...args: ChannelMuxEvents[K]) => void): this;
+  once<K extends keyof ChannelMuxEvents>(event: K, listener: (...args: ChannelMuxEvents[K]) => void): this;
+  off<K extends keyof ChannelMuxEvents>(event: K, listener: (...args: ChannelMuxEvents[K]) => void): this;
+  emit<K extends keyof ChannelMuxEvents>(event: K, ...args: ChannelMuxEvents[K]): boolean;
+}
+
+const DEFAULT_CHANNEL_WINDOW = 64;
+const DEFAULT_QUANTUM = 16 * 1024;
+const MAX_CHANNEL_ID = 0xffffffff;
+
+function encodeCount(count: number): Uint8Array {
+  const buffer: number[] = [];
+  writeU32(buffer, count);
+  return new Uint8Array(buffer);
+}
+
+function checkWindow(window: number): number {
+  if (!Number.isInteger(window) || window < 1 || window > MAX_CHANNEL_ID) {
+    throw ClavisError.config(`Channel window must be a positive u32, got ${window}`);
+  }
+  return window;
+}
+
+function checkIdleTimeout(timeoutMs: number | undefined): number | undefined {
+  if (timeoutMs !== undefined && !(timeoutMs > 0)) {
+    throw ClavisError.config(`Channel idle timeout must be positive, got ${timeoutMs}`);
+  }
+  return timeoutMs;
+}
+
+function checkWeight(weight: number): number {
+  if (!(weight > 0) || !Number.isFinite(weight)) {
+    throw ClavisError.config(`Channel weight must be a positive number, got ${weight}`);
+  }
+  return weight;
+}
+
+function toClavisError(error: unknown): ClavisError {
+  if (error instanceof ClavisError) return error;
+  if (error instanceof StreamError) return ClavisError.stream(error);
+  return ClavisError.stream(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+}
+
+/**
+ * Read half of a logical channel
+ */
+export class ChannelReader implements AsyncIterable<Uint8Array> {
+  constructor(
+    private readonly queue: AsyncQueue<Uint8Array>,
+    /** Packets the peer may send before waiting for this reader */
+    readonly window: number
+  ) {}
+
+  /**
+   * Read the next packet's bytes (decode them with the protocol codec).
+   * Rejects once the peer closed its side and everything was read.
+   */
+  async readPacket(): Promise<Uint8Array> {
+    const next = await this.queue.next();
+    if (next.done) {
+      throw ClavisError.stream(StreamError.connectionClosed("Channel closed by peer"));
+    }
+    return next.value;
+  }
+
+  /**
+   * Read at least one packet, plus any further packets already received,
+   * up to `max`
+   */
+  async readPackets(max: number = 64): Promise<Uint8Array[]> {
+    const packets = [await this.readPacket()];
+    while (packets.length < max && this.queue.size > 0) {
+      const next = await this.queue.next();
+      if (next.done) break;
+      packets.push(next.value);
+    }
+    return packets;
+  }
+
+  /** Packets received and not yet read */
+  get buffered(): number {
+    return this.queue.size;
+  }
+
+  [Symbol.asyncIterator](): AsyncIterator<Uint8Array> {
+    return this.queue[Symbol.asyncIterator]();
+  }
+}
+
+/**
+ * Write half of a logical channel
+ */
+export class ChannelWriter {
+  private closed = false;
+
+  constructor(
+    private readonly channel: number,
+    private readonly gate: CreditGate,
+    private readonly send: (kind: MuxFrameKind, channel: number, payload: Uint8Array) => Promise<number>,
+    private readonly onClose: () => void
+  ) {}
+
+  /**
+   * Write a packet on the channel, waiting first if the peer's window is
+   * full. Resolves with the bytes written, frame overhead included.
+   */
+  async writePacket(packet: PacketTrait): Promise<number> {
+    if (this.closed) {
+      throw ClavisError.invalidOperation(`Channel ${this.channel} is closed for writing`);
+    }
+    await this.gate.take();
+    return this.send(MuxFrameKind.Data, this.channel, packet.serialize());
+  }
+
+  /** Packets that can be written before waiting for the peer */
+  get credits(): number {
+    return this.gate.available;
+  }
+
+  /** Tell the peer nothing more will be written; its reader ends once drained */
+  async close(): Promise<void> {
+    if (this.closed) return;
+    this.closed = true;
+    this.gate.close(ClavisError.invalidOperation(`Channel ${this.channel} is closed for writing`));
+    this.onClose();
+    await this.send(MuxFrameKind.Close, this.channel, new Uint8Array(0));
+  }
+}
+
+/**
+ * One logical channel: an independent reader/writer pair
+ */
+export interface LogicalChannel {
+  id: number;
+  reader: ChannelReader;
+  writer: ChannelWriter;
+}
+
+interface ChannelState {
+  channel: LogicalChannel;
+  /** Receive window advertised to the peer */
+  window: number;
+  queue: AsyncQueue<Uint8Array>;
+  gate: CreditGate;
+  /** The peer has answered or sent an open */
+  peerOpened: boolean;
+  /** Idle timeout of the channel, if it has one */
+  idleTimeoutMs: number | undefined;
+  /** Clock time data last moved on the channel */
+  lastActive: number;
+  readClosed: boolean;
+  writeClosed: boolean;
+}
+
+interface ScheduledFrame {
+  frame: Uint8Array;
+  resolve: (written: number) => void;
+  reject: (error: unknown) => void;
+}
+
+/**
+ * Multiplexes logical channels over one encrypted reader/writer pair, so
+ * many streams share a single handshake and connection.
+ *
+ * Either side opens a channel by ID; the other receives it from `accept()`.
+ * Both sides opening the same ID at once end up with the same channel.
+ *
+ * @example
+ * ```typescript
+ * const mux = ChannelMux.over(stream);
+ * const { reader, writer } = await mux.openChannel(1);
+ * await writer.writePacket(Packet.Hello({ name }));
+ *
+ * // On the other side
+ * for await (const channel of mux) {
+ *   void serve(channel.reader, channel.writer);
+ * }
+ * ```
+ */
+export class ChannelMux extends EventEmitter implements ChannelMuxEmitter, AsyncIterable<LogicalChannel> {
+  private readonly windowFor: (channel: number) => number;
+  private channels = new Map<number, ChannelState>();
+  private incoming = new AsyncQueue<LogicalChannel>();
+  private running = false;
+  private closedReason: ClavisError | undefined;
+  private readonly scheduler: FairQueue<ScheduledFrame> | undefined;
+  private pumping = false;
+  private readonly idleTimeoutFor: (channel: number) => number | undefined;
+  private readonly clock: Clock;
+  private sweepTimer: TimerHandle | undefined;
+
+  constructor(
+    private readonly reader: EncryptedReader,
+    private readonly writer: EncryptedWriter,
+    options: ChannelMuxOptions = {}
+  ) {
+    super();
+    const window = options.window ?? DEFAULT_CHANNEL_WINDOW;
+    this.windowFor = typeof window === "function"
+      ? (channel) => checkWindow(window(channel))
+      : () => Math.max(1, window);
+    if (options.scheduler) {
+      const quantum = options.scheduler.quantum ?? DEFAULT_QUANTUM;
+      if (!Number.isInteger(quantum) || quantum < 1) {
+        throw ClavisError.config(`Scheduler quantum must be a positive integer, got ${quantum}`);
+      }
+      const weight = options.scheduler.weight;
+      this.scheduler = new FairQueue(quantum, weight ? (channel) => checkWeight(weight(channel)) : undefined);
+    }
+    const idleTimeoutMs = options.idleTimeoutMs;
+    if (typeof idleTimeoutMs === "function") {
+      this.idleTimeoutFor = (channel) => checkIdleTimeout(idleTimeoutMs(channel));
+    } else {
+      checkIdleTimeout(idleTimeoutMs);
+      this.idleTimeoutFor = () => idleTimeoutMs;
+    }
+    this.clock = options.clock ?? systemClock;
+  }
+
+  /** Split `stream` and multiplex channels over it */
+  static over(stream: EncryptedStream, options?: ChannelMuxOptions): ChannelMux {
+    const { reader, writer } = stream.split();
+    return new ChannelMux(reader, writer, options);
+  }
+
+  /** Number of open channels */
+  get openChannels(): number {
+    return this.channels.size;
+  }
+
+  /** Whether channel `id` is open in either direction */
+  hasChannel(id: number): boolean {
+    return this.channels.has(id);
+  }
+
+  /**
+   * Change the weight of channel `id` under the fair scheduler, from its
+   * next turn on. Throws a config error without the `scheduler` option.
+   */
+  setChannelWeight(id: number, weight: number): void {
+    if (!this.scheduler) {
+      throw ClavisError.config("Channel weights need the scheduler option");
+    }
+    this.scheduler.setWeight(id, checkWeight(weight));
+  }
+
+  /**
+   * Send queues of the channels the fair scheduler knows, open ones and
+   * closed ones still draining; empty without the `scheduler` option
+   */
+  queueStats(): ChannelQueueStats[] {
+    if (!this.scheduler) return [];
+    return [...this.scheduler.stats()].map(([channel, stats]) => ({ channel, ...stats }));
+  }
+
+  /** Whether the multiplexer has stopped */
+  get isClosed(): boolean {
+    return this.closedReason !== undefined;
+  }
+
+  /**
+   * Start the background read loop that routes packets to channels
+   */
+  start(): this {
+    if (!this.running && !this.closedReason) {
+      this.running = true;
+      void this.readLoop();
+    }
+    return this;
+  }
+
+  /**
+   * Open channel `id` (0 to 2^32 - 1). Writes wait until the peer has
+   * answered with its window. `options.window` overrides this side's
+   * receive window for the channel.
+   */
+  async openChannel(id: number, options: { window?: number | undefined } = {}): Promise<LogicalChannel> {
+    if (this.closedReason) throw this.closedReason;
+    if (!Number.isInteger(id) || id < 0 || id > MAX_CHANNEL_ID) {
+      throw ClavisError.config(`Channel ID must be a u32, got ${id}`);
+    }
+    if (this.channels.has(id)) {
+      throw ClavisError.invalidOperation(`Channel ${id} is already open`);
+    }
+    const window = options.window === undefined ? this.windowFor(id) : checkWindow(options.window);
+    this.start();
+    const state = this.createChannel(id, window);
+    await this.send(MuxFrameKind.Open, id, encodeCount(window));
+    return state.channel;
+  }
+
+  /**
+   * Wait for the next channel opened by the peer
+   */
+  async accept(): Promise<LogicalChannel> {
+    this.start();
+    const next = await this.incoming.next();
+    if (next.done) {
+      throw this.closedReason ?? ClavisError.stream(StreamError.connectionClosed("Multiplexer closed"));
+    }
+    return next.value;
+  }
+
+  [Symbol.asyncIterator](): AsyncIterator<LogicalChannel> {
+    this.start();
+    return this.incoming[Symbol.asyncIterator]();
+  }
+
+  /**
+   * Stop multiplexing: every channel's reader and writer fail with `reason`.
+   * The underlying stream stays open.
+   */
+  close(reason?: ClavisError): void {
+    this.shutdown(reason ?? ClavisError.stream(StreamError.connectionClosed("Multiplexer closed")));
+  }
+
+  private createChannel(id: number, window: number): ChannelState {
+    let consumed = 0;
+    const queue = new AsyncQueue<Uint8Array>(() => {
+      state.lastActive = this.clock.now();
+      // Return credits in batches of half the window
+      if (++consumed >= Math.ceil(window / 2)) {
+        this.sendCredits(id, consumed);
+        consumed = 0;
+      }
+    });
+    const gate = new CreditGate();
+    const state: ChannelState = {
+      channel: {
+        id,
+        reader: new ChannelReader(queue, window),
+        writer: new ChannelWriter(id, gate, (kind, channel, payload) => this.sendOnChannel(kind, channel, payload), () => {
+          state.writeClosed = true;
+          this.release(id, state);
+        }),
+      },
+      window,
+      queue,
+      gate,
+      peerOpened: false,
+      idleTimeoutMs: this.idleTimeoutFor(id),
+      lastActive: this.clock.now(),
+      readClosed: false,
+      writeClosed: false,
+    };
+    this.channels.set(id, state);
+    this.scheduleSweep();
+    return state;
+  }
+
+  /** Forget a channel once both directions are closed, freeing its ID */
+  private release(id: number, state: ChannelState): void {
+    if (state.readClosed && state.writeClosed && this.channels.get(id) === state) {
+      this.channels.delete(id);
+      this.scheduler?.forget(id);
+    }
+  }
+
+  /** Send a channel's data or close, through the fair scheduler when there is one */
+  private sendOnChannel(kind: MuxFrameKind, channel: number, payload: Uint8Array): Promise<number> {
+    const state = this.channels.get(channel);
+    if (state && kind === MuxFrameKind.Data) state.lastActive = this.clock.now();
+    const scheduler = this.scheduler;
+    if (!scheduler) return this.send(kind, channel, payload);
+    if (this.closedReason) return Promise.reject(this.closedReason);
+    const frame = encodeMuxEnvelope(kind, channel, payload);
+    return new Promise((resolve, reject) => {
+      scheduler.push(channel, { frame, resolve, reject }, frame.length);
+      void this.pump(scheduler);
+    });
+  }
+
+  /**
+   * Write queued frames one at a time, so whatever is queued meanwhile
+   * waits in the scheduler rather than in the transport
+   */
+  private async pump(scheduler: FairQueue<ScheduledFrame>): Promise<void> {
+    if (this.pumping) return;
+    this.pumping = true;
+    try {
+      for (let next = scheduler.shift(); next; next = scheduler.shift()) {
+        try {
+          next.resolve(await this.writer.writePacket(new RawPacket(next.frame)));
+        } catch (error) {
+          next.reject(error);
+        }
+      }
+    } finally {
+      this.pumping = false;
+    }
+  }
+
+  private async send(kind: MuxFrameKind, channel: number, payload: Uint8Array): Promise<number> {
+    if (this.closedReason) throw this.closedReason;
+    return this.writer.writePacket(new RawPacket(encodeMuxEnvelope(kind, channel, payload)));
+  }
+
+  private sendCredits(channel: number, credits: number): void {
+    if (this.closedReason) return;
+    this.send(MuxFrameKind.Credit, channel, encodeCount(credits)).catch((error) => this.reportError(error));
+  }
+
+  private async readLoop(): Promise<void> {
+    while (!this.closedReason) {
+      let batch: Uint8Array[];
+      try {
+        batch = (await this.reader.readPackets()) as unknown as Uint8Array[];
+      } catch (error) {
+        this.shutdown(toClavisError(error));
+        return;
+      }
+
+      for (const data of batch) {
+        try {
+          this.handleEnvelope(decodeMuxEnvelope(data));
+        } catch (error) {
+          this.reportError(error);
+        }
+      }
+    }
+  }
+
+  private handleEnvelope(envelope: MuxEnvelope): void {
+    const state = this.channels.get(envelope.channel);
+    switch (envelope.kind) {
+      case MuxFrameKind.Open: {
+        const window = readU32(envelope.payload, 0).value;
+        if (state) {
+          // Our open was answered, or both sides opened the channel at once
+          if (!state.peerOpened) {
+            state.peerOpened = true;
+            state.gate.add(window);
+          }
+          return;
+        }
+        const opened = this.createChannel(envelope.channel, this.windowFor(envelope.channel));
+        opened.peerOpened = true;
+        opened.gate.add(window);
+        this.sendOpenAnswer(envelope.channel, opened.window);
+        this.incoming.push(opened.channel);
+        return;
+      }
+      case MuxFrameKind.Data:
+        // Late packets for a channel we already released are dropped
+        if (!state || state.readClosed) return;
+        if (state.queue.size >= state.window) {
+          state.readClosed = true;
+          state.queue.end(ClavisError.invalidOperation(
+            `Peer exceeded the channel window of ${state.window} packets`
+          ));
+          this.release(envelope.channel, state);
+          return;
+        }
+        state.lastActive = this.clock.now();
+        state.queue.push(envelope.payload);
+        return;
+      case MuxFrameKind.Credit:
+        state?.gate.add(readU32(envelope.payload, 0).value);
+        return;
+      case MuxFrameKind.Close:
+        if (!state || state.readClosed) return;
+        state.readClosed = true;
+        state.queue.end();
+        this.release(envelope.channel, state);
+        return;
+    }
+  }
+
+  /** Arm the timer for the earliest idle deadline, if any channel has one */
+  private scheduleSweep(): void {
+    if (this.sweepTimer || this.closedReason) return;
+    let deadline = Infinity;
+    for (const state of this.channels.values()) {
+      if (state.idleTimeoutMs !== undefined) {
+        deadline = Math.min(deadline, state.lastActive + state.idleTimeoutMs);
+      }
+    }
+    if (deadline === Infinity) return;
+    this.sweepTimer = this.clock.setTimer(() => {
+      this.sweepTimer = undefined;
+      this.reapIdle();
+    }, Math.max(0, deadline - this.clock.now()));
+  }
+
+  private reapIdle(): void {
+    if (this.closedReason) return;
+    const now = this.clock.now();
+    for (const [id, state] of [...this.channels]) {
+      if (state.idleTimeoutMs !== undefined && now - state.lastActive >= state.idleTimeoutMs) {
+        this.reap(id, state, state.idleTimeoutMs);
+      }
+    }
+    this.scheduleSweep();
+  }
+
+  private reap(id: number, state: ChannelState, idleTimeoutMs: number): void {
+    if (!state.readClosed) {
+      state.readClosed = true;
+      state.queue.end(ClavisError.stream(StreamError.connectionClosed(`Channel ${id} closed after ${idleTimeoutMs}ms idle`)));
+    }
+    // Sends the close and, with both directions closed, frees the ID
+    state.channel.writer.close().catch((error) => this.reportError(error));
+    this.release(id, state);
+    this.emit("reaped", state.channel);
+  }
+
+  private sendOpenAnswer(channel: number, window: number): void {
+    this.send(MuxFrameKind.Open, channel, encodeCount(window)).catch((error) => this.reportError(error));
+  }
+
+  private reportError(error: unknown): void {
+    // An unhandled "error" event would throw out of the read loop
+    if (this.listenerCount("error") > 0) {
+      this.emit("error", toClavisError(error));
+    }
+  }
+
+  private shutdown(reason: ClavisError): void {
+    if (this.closedReason) return;
+    this.closedReason = reason;
+    this.running = false;
+    this.sweepTimer?.cancel();
+    this.sweepTimer = undefined;
+
+    for (const state of this.channels.values()) {
+      state.queue.end(reason);
+      state.gate.close(reason);
+    }
+    this.channels.clear();
+    for (const frame of this.scheduler?.clear() ?? []) frame.reject(reason);
+    this.incoming.end();
+    this.emit("close", reason);
+  }
+}
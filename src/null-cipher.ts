@@ -0,0 +1,83 @@
+/**
+ * Null cipher
+ * Frames with their usual layout whose payloads travel in the clear
+ *
+ * DANGER: nothing sent through this cipher is confidential or authenticated.
+ * It exists to profile serialization without the cost of encryption, and for
+ * links already protected by IPsec or WireGuard. The handshake still runs,
+ * so a PSK still checks who connected, but anyone on the path can read and
+ * change every packet afterwards.
+ *
+ * Off by default; a process enables it with `CLAVIS_ALLOW_NULL_CIPHER=1` in
+ * the environment or `allowNullCipher(true)`, and then each stream with
+ * `dangerousNullCipher: true` on both peers.
+ */
+
+import { sha256 } from "@noble/hashes/sha2.js";
+import { ClavisError, CryptoOperation } from "./error.js";
+import { redact } from "./audit.js";
+import type { FrameCipher } from "./crypto.js";
+
+const TAG_LENGTH = 16;
+
+/** Tag of frames without associated data */
+const EMPTY_TAG = new Uint8Array(TAG_LENGTH);
+
+let allowed = process.env.CLAVIS_ALLOW_NULL_CIPHER === "1";
+
+/**
+ * Allow or forbid `DangerousNullCipher` in this process
+ */
+export function allowNullCipher(enabled: boolean): void {
+  allowed = enabled;
+}
+
+/**
+ * Whether `DangerousNullCipher` may be used in this process
+ */
+export function isNullCipherAllowed(): boolean {
+  return allowed;
+}
+
+/**
+ * Tag standing in for Poly1305: fixed for application frames and derived
+ * from the associated data otherwise, so a flipped control flag or a peer
+ * that is really encrypting is still caught. It protects against mistakes,
+ * not attackers.
+ */
+function nullTag(aad: Uint8Array | undefined): Uint8Array {
+  return aad === undefined ? EMPTY_TAG : sha256(aad).subarray(0, TAG_LENGTH);
+}
+
+/**
+ * Cipher that copies plaintext through unchanged and appends a fixed tag,
+ * keeping frame sizes identical to XChaCha20-Poly1305
+ */
+export class DangerousNullCipher implements FrameCipher {
+  constructor() {
+    if (!allowed) {
+      throw ClavisError.config(
+        "The null cipher is disabled; set CLAVIS_ALLOW_NULL_CIPHER=1 or call allowNullCipher(true)"
+      );
+    }
+  }
+
+  encrypt(_nonce: Uint8Array, plaintext: Uint8Array, aad?: Uint8Array): Uint8Array {
+    const sealed = new Uint8Array(plaintext.length + TAG_LENGTH);
+    sealed.set(plaintext, 0);
+    sealed.set(nullTag(aad), plaintext.length);
+    return sealed;
+  }
+
+  decrypt(_nonce: Uint8Array, ciphertext: Uint8Array, aad?: Uint8Array): Uint8Array {
+    const end = ciphertext.length - TAG_LENGTH;
+    const tag = nullTag(aad);
+    if (end < 0 || !tag.every((byte, i) => ciphertext[end + i] === byte)) {
+      throw ClavisError.cryptoFailure(
+        CryptoOperation.Decryption,
+        "Null cipher tag mismatch; is the peer encrypting?"
+      );
+    }
+    return redact(ciphertext.slice(0, end), "payload");
+  }
+}
@@ -0,0 +1,187 @@
+/**
+ * Packet padding and cover traffic
+ * Hides which packet is on the wire by its length
+ *
+ * Encryption hides a packet's bytes but not its size, so a 9-byte Heartbeat
+ * and a 300-byte Message are easy to tell apart. With padding on, each
+ * packet is padded inside the ciphertext to a size its policy picks:
+ * - "padme": the Padmé scheme, rounding to at most 12% more, so sizes
+ *   reveal only about the order of magnitude
+ * - `{ buckets }`: the smallest listed size that fits, and multiples of the
+ *   largest beyond it
+ *
+ * Padded plaintext: length (u32 little-endian) | packet | zero bytes. The
+ * padding is applied after compression and removed before decompression.
+ * A length of 0xffffffff marks a cover frame, which carries no packet and is
+ * dropped by the receiver; cover frames are sent at random intervals and
+ * look like any other application frame. Control frames are not padded.
+ *
+ * Padding is opt-in on both sides. Right after the other setup exchanges
+ * each side sends an encrypted offer; each side then pads what it sends by
+ * its own policy, since removing padding needs no policy.
+ */
+
+import { ClavisError, MessageError, StreamError } from "./error.js";
+
+/**
+ * How far to pad a packet
+ */
+export type PaddingPolicy = "padme" | { buckets: readonly number[] };
+
+/**
+ * Padding settings for an encrypted stream
+ */
+export interface PaddingOptions {
+  /** Sizes to pad to, counting the 4-byte length header (default: "padme") */
+  policy?: PaddingPolicy | undefined;
+  /**
+   * Send a cover frame on average this often, at exponentially distributed
+   * intervals so their timing doesn't stand out (default: no cover traffic)
+   */
+  coverIntervalMs?: number | undefined;
+  /** Largest made-up packet a cover frame carries, before padding (default: 256) */
+  coverMaxSize?: number | undefined;
+}
+
+/** Bytes of the length header in front of every padded packet */
+export const PADDING_HEADER_LENGTH = 4;
+
+const COVER_LENGTH = 0xffffffff;
+const DEFAULT_COVER_MAX_SIZE = 256;
+const OFFER_MAGIC = [0x43, 0x4c, 0x56, 0x50]; // "CLVP"
+const OFFER_VERSION = 1;
+
+/**
+ * Padmé length for `length` bytes: the low bits below the top
+ * log2(log2(length)) + 1 are rounded up
+ */
+export function padmeLength(length: number): number {
+  if (length < 2) return length;
+  const exponent = Math.floor(Math.log2(length));
+  const significant = Math.floor(Math.log2(exponent)) + 1;
+  const step = 2 ** (exponent - significant);
+  return Math.ceil(length / step) * step;
+}
+
+/**
+ * Size a policy pads `length` bytes to
+ */
+export function paddedLength(length: number, policy: PaddingPolicy): number {
+  if (policy === "padme") return padmeLength(length);
+  const { buckets } = policy;
+  for (const bucket of buckets) {
+    if (bucket >= length) return bucket;
+  }
+  const largest = buckets[buckets.length - 1]!;
+  return Math.ceil(length / largest) * largest;
+}
+
+function checkPolicy(policy: PaddingPolicy): void {
+  if (policy === "padme") return;
+  const { buckets } = policy;
+  if (buckets.length === 0) {
+    throw ClavisError.config("Padding buckets must not be empty");
+  }
+  buckets.forEach((bucket, i) => {
+    if (!Number.isInteger(bucket) || bucket < 1) {
+      throw ClavisError.config(`Padding buckets must be positive integers, got ${bucket}`);
+    }
+    if (i > 0 && bucket <= buckets[i - 1]!) {
+      throw ClavisError.config("Padding buckets must be in increasing order");
+    }
+  });
+}
+
+function randomUnit(): number {
+  return crypto.getRandomValues(new Uint32Array(1))[0]! / 2 ** 32;
+}
+
+/**
+ * Remove the padding of a received packet; undefined for a cover frame
+ */
+export function unpadPacket(data: Uint8Array): Uint8Array | undefined {
+  if (data.length < PADDING_HEADER_LENGTH) {
+    throw ClavisError.message(MessageError.invalidFormat("Padded packet is shorter than its header"));
+  }
+  const length = new DataView(data.buffer, data.byteOffset, PADDING_HEADER_LENGTH).getUint32(0, true);
+  if (length === COVER_LENGTH) return undefined;
+  if (length > data.length - PADDING_HEADER_LENGTH) {
+    throw ClavisError.message(MessageError.invalidFormat(`Padded packet claims ${length} bytes but holds ${data.length - PADDING_HEADER_LENGTH}`));
+  }
+  return data.subarray(PADDING_HEADER_LENGTH, PADDING_HEADER_LENGTH + length);
+}
+
+/**
+ * Pads outgoing packets and makes cover frames under one set of options
+ */
+export class PacketPadder {
+  readonly policy: PaddingPolicy;
+  /** Bytes padding adds to a packet beyond the padded size: the length header */
+  readonly overhead = PADDING_HEADER_LENGTH;
+  private readonly coverIntervalMs: number | undefined;
+  private readonly coverMaxSize: number;
+
+  constructor(options: PaddingOptions = {}) {
+    this.policy = options.policy ?? "padme";
+    checkPolicy(this.policy);
+    const { coverIntervalMs, coverMaxSize = DEFAULT_COVER_MAX_SIZE } = options;
+    if (coverIntervalMs !== undefined && !(coverIntervalMs > 0)) {
+      throw ClavisError.config(`coverIntervalMs must be positive, got ${coverIntervalMs}`);
+    }
+    if (!Number.isInteger(coverMaxSize) || coverMaxSize < 0) {
+      throw ClavisError.config(`coverMaxSize must be a non-negative integer, got ${coverMaxSize}`);
+    }
+    this.coverIntervalMs = coverIntervalMs;
+    this.coverMaxSize = coverMaxSize;
+  }
+
+  /**
+   * Prefix `packet` with its length and pad it, to no more than `limit`
+   * bytes unless the packet itself needs more
+   */
+  pad(packet: Uint8Array, limit: number): Uint8Array {
+    return this.padded(packet.length, packet, limit);
+  }
+
+  /** Remove the padding of a received packet; undefined for a cover frame */
+  unpad(data: Uint8Array): Uint8Array | undefined {
+    return unpadPacket(data);
+  }
+
+  /** A cover frame's plaintext, padded like a packet of random size */
+  cover(limit: number): Uint8Array {
+    const size = Math.floor(randomUnit() * (this.coverMaxSize + 1));
+    return this.padded(COVER_LENGTH, new Uint8Array(size), limit);
+  }
+
+  /** Milliseconds until the next cover frame, or undefined without cover traffic */
+  nextCoverDelay(): number | undefined {
+    if (this.coverIntervalMs === undefined) return undefined;
+    return -Math.log(1 - randomUnit()) * this.coverIntervalMs;
+  }
+
+  private padded(length: number, body: Uint8Array, limit: number): Uint8Array {
+    const needed = PADDING_HEADER_LENGTH + body.length;
+    const size = Math.max(needed, Math.min(paddedLength(needed, this.policy), limit));
+    const out = new Uint8Array(size);
+    new DataView(out.buffer).setUint32(0, length, true);
+    out.set(body, PADDING_HEADER_LENGTH);
+    return out;
+  }
+}
+
+/** This side's padding offer */
+export function encodePaddingOffer(): Uint8Array {
+  return new Uint8Array([...OFFER_MAGIC, OFFER_VERSION]);
+}
+
+/** Check the peer's padding offer */
+export function decodePaddingOffer(offer: Uint8Array): void {
+  const magic = OFFER_MAGIC.every((b, i) => offer[i] === b);
+  if (!magic || offer.length !== OFFER_MAGIC.length + 1) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Peer did not enable padding"));
+  }
+  if (offer[OFFER_MAGIC.length] !== OFFER_VERSION) {
+    throw ClavisError.stream(StreamError.handshakeFailed(`Unsupported padding version ${offer[OFFER_MAGIC.length]}`));
+  }
+}
@@ -0,0 +1,216 @@
+/**
+ * Connection phases
+ * Typestate wrapper that lets each phase of a connection send and receive only
+ * the variants that are legal in it
+ *
+ * A protocol such as Unauthenticated → Authenticated → Active is declared once;
+ * `send()` then only type-checks with the current phase's outgoing variants and
+ * `advance()` only with the phases it may move on to. The handle of a phase
+ * that has advanced stops working, so keeping an old handle around fails
+ * loudly instead of sending out of order. The same rules are checked at
+ * runtime for callers that bypass the types, and incoming variants that the
+ * phase doesn't allow are rejected as a protocol violation.
+ *
+ * A phased connection can also drive a reader's accept filter and a router's
+ * allowed handlers, so both follow the current phase's `receive` list and
+ * can't drift apart from it.
+ */
+
+import { ClavisError, MessageError } from "./error.js";
+import { RawPacket, type DecodedMessage, type PacketTrait, type ProtocolCodec, type UnknownVariant } from "./protocol.js";
+import type { PacketRouter } from "./router.js";
+
+/**
+ * Anything packets can be read from and written to, e.g. an `EncryptedStream`
+ */
+export interface PhaseTransport {
+  readPacket<P extends PacketTrait>(): Promise<P>;
+  writePacket(packet: PacketTrait): Promise<number>;
+}
+
+/**
+ * Components kept in step with the current phase
+ */
+export interface PhaseBindings<T extends string> {
+  /** Reader whose accept filter is set to the phase's `receive` list, e.g. an `EncryptedReader` */
+  reader?: { setAcceptFilter(codec: ProtocolCodec<T>, types: Iterable<T> | undefined): void } | undefined;
+  /** Router that only calls handlers for the phase's `receive` list */
+  router?: PacketRouter<T> | undefined;
+}
+
+/**
+ * Variants legal in one phase
+ */
+export interface PhaseDefinition<T extends string> {
+  /** Variants this side may send */
+  send: readonly T[];
+  /** Variants this side accepts from the peer */
+  receive: readonly T[];
+  /** Phases this one may advance to (default: any) */
+  next?: readonly string[] | undefined;
+}
+
+/**
+ * Map of phase names to their definitions
+ */
+export type PhaseMap<T extends string> = Record<string, PhaseDefinition<T>>;
+
+/** Variants that may be sent in phase `K` */
+export type PhaseSend<P, K extends keyof P> = P[K] extends { send: readonly (infer S)[] } ? S : never;
+
+/** Variants that may be received in phase `K` */
+export type PhaseReceive<P, K extends keyof P> = P[K] extends { receive: readonly (infer R)[] } ? R : never;
+
+/** Phases reachable from phase `K` */
+export type PhaseNext<P, K extends keyof P> = P[K] extends { next: readonly (infer N)[] }
+  ? N & keyof P & string
+  : keyof P & string;
+
+/**
+ * A defined set of phases, able to open phased connections
+ */
+export interface PhasedProtocol<T extends string, P extends PhaseMap<T>> {
+  readonly codec: ProtocolCodec<T>;
+  readonly phases: P;
+  /** Wrap `transport`, starting in `phase`, and keep `bindings` in step with the phase */
+  open<K extends keyof P & string>(
+    transport: PhaseTransport,
+    phase: K,
+    bindings?: PhaseBindings<T>
+  ): PhasedConnection<T, P, K>;
+}
+
+/**
+ * Define the phases of a protocol.
+ *
+ * @example
+ * ```typescript
+ * const Phases = definePhases(codec, {
+ *   Unauthenticated: { send: ["Login"], receive: ["LoginOk", "LoginFailed"], next: ["Active"] },
+ *   Active: { send: ["Message", "Leave"], receive: ["Message", "Status"] },
+ * });
+ *
+ * const login = Phases.open(stream, "Unauthenticated");
+ * await login.send("Login", credentials);
+ * const reply = await login.receive();
+ * const active = login.advance("Active");
+ * await active.send("Message", body); // login.send("Message", ...) does not compile
+ * ```
+ */
+export function definePhases<T extends string, const P extends PhaseMap<T>>(
+  codec: ProtocolCodec<T>,
+  phases: P
+): PhasedProtocol<T, P> {
+  for (const [name, phase] of Object.entries(phases)) {
+    for (const variant of [...phase.send, ...phase.receive]) {
+      if (!codec.isValidType(variant)) {
+        throw ClavisError.config(`Phase ${name} uses unknown variant ${variant}`);
+      }
+    }
+    for (const next of phase.next ?? []) {
+      if (!(next in phases)) {
+        throw ClavisError.config(`Phase ${name} advances to unknown phase ${next}`);
+      }
+    }
+  }
+
+  const protocol: PhasedProtocol<T, P> = {
+    codec,
+    phases,
+    open(transport, phase, bindings = {}) {
+      if (!(phase in phases)) {
+        throw ClavisError.config(`Unknown phase ${phase}`);
+      }
+      return new PhasedConnection(protocol, transport, phase, bindings);
+    },
+  };
+  return protocol;
+}
+
+/**
+ * A connection in phase `K`. Obtain one from `PhasedProtocol.open()` and move
+ * on with `advance()`.
+ */
+export class PhasedConnection<T extends string, P extends PhaseMap<T>, K extends keyof P & string> {
+  private advancedTo: string | undefined;
+
+  constructor(
+    private readonly protocol: PhasedProtocol<T, P>,
+    private readonly transport: PhaseTransport,
+    readonly phase: K,
+    private readonly bindings: PhaseBindings<T> = {}
+  ) {
+    const receive = this.definition.receive;
+    bindings.reader?.setAcceptFilter(protocol.codec, receive);
+    bindings.router?.allowOnly(receive);
+  }
+
+  /**
+   * Send a variant that is legal in this phase.
+   * Resolves with the number of bytes written.
+   */
+  async send(type: PhaseSend<P, K>, data?: Uint8Array): Promise<number> {
+    this.checkCurrent();
+    const variant = type as T;
+    if (!this.definition.send.includes(variant)) {
+      throw ClavisError.invalidOperation(`${variant} cannot be sent in phase ${this.phase}`);
+    }
+    return this.transport.writePacket(new RawPacket(this.protocol.codec.encode(variant, data)));
+  }
+
+  /**
+   * Receive the next packet. A variant this phase doesn't accept is a
+   * protocol violation and is rejected.
+   */
+  async receive(): Promise<DecodedMessage<PhaseReceive<P, K>>> {
+    const message = await this.receiveVariant();
+    if (message.type === undefined) {
+      throw ClavisError.deserializationFailed(`Unknown variant index: ${message.index}`);
+    }
+    return message;
+  }
+
+  /**
+   * Receive like `receive()`, but return a variant this side doesn't know
+   * as an `UnknownVariant` instead of throwing, so packets from a newer
+   * peer can be skipped. Known variants the phase doesn't accept are still
+   * rejected.
+   */
+  async receiveVariant(): Promise<DecodedMessage<PhaseReceive<P, K>> | UnknownVariant> {
+    this.checkCurrent();
+    const bytes = (await this.transport.readPacket()) as unknown as Uint8Array;
+    const message = this.protocol.codec.decodeVariant(bytes);
+    if (message.type === undefined) return message;
+    if (!this.definition.receive.includes(message.type)) {
+      throw ClavisError.message(
+        MessageError.invalidFormat(`Peer sent ${message.type} in phase ${this.phase}`)
+      );
+    }
+    return message as DecodedMessage<PhaseReceive<P, K>>;
+  }
+
+  /**
+   * Move to the next phase. This handle stops working; use the returned one.
+   */
+  advance<N extends PhaseNext<P, K>>(next: N): PhasedConnection<T, P, N> {
+    this.checkCurrent();
+    const allowed = this.definition.next;
+    if (!(next in this.protocol.phases) || (allowed && !allowed.includes(next))) {
+      throw ClavisError.invalidOperation(`Phase ${this.phase} cannot advance to ${next}`);
+    }
+    this.advancedTo = next;
+    return new PhasedConnection(this.protocol, this.transport, next, this.bindings);
+  }
+
+  private get definition(): PhaseDefinition<T> {
+    return this.protocol.phases[this.phase]!;
+  }
+
+  private checkCurrent(): void {
+    if (this.advancedTo !== undefined) {
+      throw ClavisError.invalidOperation(
+        `Phase ${this.phase} has already advanced to ${this.advancedTo}`
+      );
+    }
+  }
+}
@@ -0,0 +1,245 @@
+/**
+ * RPC connection pool
+ * Spreads calls over several connections, with retries and hedged requests
+ *
+ * The copies of a hedged call share a random hedging group ID, so a server
+ * with a `HedgeRegistry` runs the call once. As soon as one copy is
+ * answered the others are cancelled, and their connections drop the reply.
+ */
+
+import { ClavisError, StreamError } from "./error.js";
+import type { DecodedMessage, ProtocolCodec } from "./protocol.js";
+import { callWithRetry, type RpcCallOptions, type RpcConnection } from "./rpc.js";
+import { systemClock, type Clock, type TimerHandle } from "./clock.js";
+import { generateRandomBytes } from "./crypto.js";
+import { HEDGE_GROUP_LENGTH } from "./hedging.js";
+
+/**
+ * Options for configuring an RPC pool
+ */
+export interface RpcPoolOptions<T extends string> {
+  /** Codec used to encode requests (must match the pooled connections) */
+  codec: ProtocolCodec<T>;
+  /** Open a new, started RPC connection */
+  connect: () => Promise<RpcConnection<T>>;
+  /** Number of connections to keep open (default: 2) */
+  size?: number | undefined;
+  /** Options applied to every call unless overridden */
+  defaultCallOptions?: RpcPoolCallOptions | undefined;
+  /** Time source for hedging delays and retry backoff (default: `systemClock`) */
+  clock?: Clock | undefined;
+  /**
+   * Ping connections that sat idle before handing them out (default: off).
+   * One that doesn't answer is closed and replaced, so callers never get a
+   * connection that died while idle.
+   */
+  warmUp?: WarmUpOptions | undefined;
+}
+
+/**
+ * When and how pooled connections are checked before use
+ */
+export interface WarmUpOptions {
+  /** Ping a connection that has been idle at least this long, in milliseconds */
+  afterIdleMs: number;
+  /** How long to wait for the answer (default: 1000) */
+  timeoutMs?: number | undefined;
+}
+
+/**
+ * Per-call options for pooled calls
+ */
+export interface RpcPoolCallOptions extends RpcCallOptions {
+  /**
+   * Send a duplicate request on another connection if no reply arrived
+   * within this many milliseconds. Only applies to idempotent calls.
+   */
+  hedgeAfterMs?: number | undefined;
+  /** Maximum number of extra hedged requests (default: 1) */
+  maxHedges?: number | undefined;
+}
+
+const DEFAULT_WARM_UP_TIMEOUT_MS = 1_000;
+
+/**
+ * Pool of RPC connections.
+ * Calls are distributed round-robin; failed attempts are retried on the next
+ * connection, and idempotent calls can be hedged across connections.
+ *
+ * @example
+ * ```typescript
+ * const pool = new RpcPool({
+ *   codec,
+ *   size: 4,
+ *   connect: async () => {
+ *     const { reader, writer } = (await connectEncrypted()).split();
+ *     return new RpcConnection(reader, writer, { codec }).start();
+ *   },
+ * });
+ *
+ * const status = await pool.call("GetStatus", undefined, {
+ *   idempotent: true,
+ *   timeoutMs: 500,
+ *   hedgeAfterMs: 50,
+ * });
+ * ```
+ */
+export class RpcPool<T extends string> {
+  private readonly size: number;
+  private readonly defaultCallOptions: RpcPoolCallOptions;
+  private readonly clock: Clock;
+  private slots: Array<Promise<RpcConnection<T>> | undefined>;
+  private cursor = 0;
+  private closed = false;
+  /** Connections closed because they failed a warm-up ping */
+  discarded = 0;
+
+  constructor(private readonly options: RpcPoolOptions<T>) {
+    this.size = options.size ?? 2;
+    if (this.size < 1) {
+      throw ClavisError.config("RpcPool size must be at least 1");
+    }
+    this.defaultCallOptions = options.defaultCallOptions ?? {};
+    this.clock = options.clock ?? systemClock;
+    this.slots = new Array(this.size).fill(undefined);
+  }
+
+  /**
+   * Send a request on a pooled connection and wait for the reply
+   */
+  async call(type: T, data?: Uint8Array, options?: RpcPoolCallOptions): Promise<DecodedMessage<T>> {
+    const merged: RpcPoolCallOptions = { ...this.defaultCallOptions, ...options };
+    const payload = this.options.codec.encode(type, data);
+
+    return callWithRetry(() => {
+      if (merged.idempotent && merged.hedgeAfterMs !== undefined && this.size > 1) {
+        return this.hedged(payload, merged, merged.hedgeAfterMs);
+      }
+      return this.attemptOnNext(payload, merged);
+    }, merged, this.clock);
+  }
+
+  /**
+   * Close all pooled connections
+   */
+  async close(): Promise<void> {
+    this.closed = true;
+    const slots = this.slots;
+    this.slots = new Array(this.size).fill(undefined);
+    for (const slot of slots) {
+      if (!slot) continue;
+      try {
+        (await slot).close();
+      } catch {
+        // Connection never opened
+      }
+    }
+  }
+
+  private async attemptOnNext(payload: Uint8Array, options: RpcCallOptions): Promise<DecodedMessage<T>> {
+    const connection = await this.next();
+    return connection.attempt(payload, options);
+  }
+
+  /**
+   * Race the primary attempt against delayed duplicates on other connections.
+   * The first reply wins and the others are cancelled; if every attempt
+   * fails, the last error is thrown.
+   */
+  private hedged(payload: Uint8Array, options: RpcPoolCallOptions, delayMs: number): Promise<DecodedMessage<T>> {
+    const maxAttempts = 1 + Math.min(options.maxHedges ?? 1, this.size - 1);
+    const hedgeGroup = generateRandomBytes(HEDGE_GROUP_LENGTH);
+
+    return new Promise((resolve, reject) => {
+      let started = 0;
+      let failed = 0;
+      let settled = false;
+      let timer: TimerHandle | undefined;
+      const attempts: AbortController[] = [];
+      const abortAll = () => {
+        for (const attempt of attempts) attempt.abort();
+      };
+      options.signal?.addEventListener("abort", abortAll, { once: true });
+
+      const launch = () => {
+        started++;
+        const attempt = new AbortController();
+        attempts.push(attempt);
+        if (options.signal?.aborted) attempt.abort();
+        this.attemptOnNext(payload, { ...options, hedgeGroup, signal: attempt.signal }).then((reply) => {
+          if (settled) return;
+          settled = true;
+          timer?.cancel();
+          options.signal?.removeEventListener("abort", abortAll);
+          // The losers' connections drop their replies
+          for (const other of attempts) if (other !== attempt) other.abort();
+          resolve(reply);
+        }, (error) => {
+          failed++;
+          if (settled) return;
+          if (failed === started && started >= maxAttempts) {
+            settled = true;
+            options.signal?.removeEventListener("abort", abortAll);
+            reject(error);
+          } else if (failed === started) {
+            // Everything in flight failed; hedge immediately
+            timer?.cancel();
+            launch();
+          }
+        });
+
+        if (started < maxAttempts) {
+          timer = this.clock.setTimer(launch, delayMs);
+        }
+      };
+
+      launch();
+    });
+  }
+
+  private next(): Promise<RpcConnection<T>> {
+    if (this.closed) {
+      return Promise.reject(ClavisError.stream(StreamError.connectionClosed("RpcPool closed")));
+    }
+    const index = this.cursor;
+    this.cursor = (this.cursor + 1) % this.size;
+
+    const existing = this.slots[index];
+    if (existing) {
+      return existing.then((connection) => {
+        if (connection.isClosed) return this.open(index);
+        const warmUp = this.options.warmUp;
+        if (warmUp && connection.idleMs >= warmUp.afterIdleMs) return this.warmUp(index, connection, warmUp);
+        return connection;
+      }, () => this.open(index));
+    }
+    return this.open(index);
+  }
+
+  /**
+   * Ping an idle connection before handing it out. Calls picking the same
+   * slot meanwhile wait for the same check.
+   */
+  private warmUp(index: number, connection: RpcConnection<T>, options: WarmUpOptions): Promise<RpcConnection<T>> {
+    const checked = connection.healthCheck(options.timeoutMs ?? DEFAULT_WARM_UP_TIMEOUT_MS).then((health) => {
+      if (health.healthy) return connection;
+      this.discarded++;
+      connection.close(health.error);
+      return this.open(index);
+    });
+    this.slots[index] = checked;
+    return checked;
+  }
+
+  private open(index: number): Promise<RpcConnection<T>> {
+    const connection = this.options.connect();
+    this.slots[index] = connection;
+    // Forget failed connection attempts so the next call dials again
+    connection.catch(() => {
+      if (this.slots[index] === connection) {
+        this.slots[index] = undefined;
+      }
+    });
+    return connection;
+  }
+}
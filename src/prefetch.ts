@@ -0,0 +1,211 @@
+/**
+ * Read-ahead prefetch
+ * Decrypts and decodes packets while the consumer is still busy with the last one
+ *
+ * A handler that awaits a database or another service leaves the socket
+ * idle, and the next packet is only decrypted and decoded once it asks for
+ * it. A `PrefetchReader` keeps reading in the meantime, up to `depth`
+ * decoded values ahead, so that work overlaps with the handler's. Values and
+ * errors come out in the order their packets arrived.
+ *
+ * Unlike `readPacket()`, `next()` can be cancelled: a value only leaves the
+ * buffer for a caller that is still waiting, so a `next()` aborted through
+ * its signal never loses a packet.
+ */
+
+import { ClavisError, StreamError, StreamErrorCode } from "./error.js";
+import type { PacketTrait } from "./protocol.js";
+
+/**
+ * Where packets are read from (`EncryptedStream`, `EncryptedReader`)
+ */
+export interface PrefetchSource {
+  readPacket<P extends PacketTrait>(): Promise<P>;
+}
+
+/**
+ * Options of a `PrefetchReader`
+ */
+export interface PrefetchOptions<T> {
+  /** Decoded values held ahead of the consumer, a read in progress included (default: 16) */
+  depth?: number | undefined;
+  /**
+   * Turns a packet into the value `next()` returns, e.g. `codec.decode`
+   * (default: the packet's bytes). A value that fails to decode is thrown
+   * by the `next()` that would have returned it; reading goes on.
+   */
+  decode?: ((packet: Uint8Array) => T) | undefined;
+}
+
+type Entry<T> = { value: T } | { error: unknown };
+
+interface Waiter<T> {
+  resolve: (value: T) => void;
+  reject: (error: unknown) => void;
+}
+
+const DEFAULT_DEPTH = 16;
+
+/**
+ * Reads and decodes packets ahead of the consumer into a bounded buffer
+ *
+ * @example
+ * ```typescript
+ * const messages = new PrefetchReader(reader, { depth: 8, decode: (bytes) => codec.decode(bytes) });
+ * for await (const message of messages) await handle(message);
+ * ```
+ */
+export class PrefetchReader<T = Uint8Array> {
+  private readonly buffer: Entry<T>[] = [];
+  private readonly waiters: Waiter<T>[] = [];
+  private readonly depth: number;
+  private readonly decode: (packet: Uint8Array) => T;
+  private reading = false;
+  private stopped = false;
+  /** The read error that ended prefetching, thrown again once the buffer is empty */
+  private failure: unknown;
+
+  constructor(private readonly source: PrefetchSource, options: PrefetchOptions<T> = {}) {
+    this.depth = options.depth ?? DEFAULT_DEPTH;
+    if (!Number.isInteger(this.depth) || this.depth < 1) {
+      throw ClavisError.config("Prefetch depth must be a whole number of at least 1");
+    }
+    this.decode = options.decode ?? ((packet) => packet as unknown as T);
+    this.pump();
+  }
+
+  /** Values and errors decoded and not yet taken by `next()` */
+  get buffered(): number {
+    return this.buffer.length;
+  }
+
+  /** Whether a read is in progress on the source */
+  get readingAhead(): boolean {
+    return this.reading;
+  }
+
+  /**
+   * The next value, in arrival order. Throws the error of a packet that
+   * failed to decode in its place, and the source's read error (such as
+   * the end of the stream) once everything before it was taken.
+   *
+   * @param signal - Aborting gives up waiting; a packet arriving later stays buffered for the next call
+   */
+  next(signal?: AbortSignal): Promise<T> {
+    const entry = this.buffer.shift();
+    if (entry) {
+      this.pump();
+      return "value" in entry ? Promise.resolve(entry.value) : Promise.reject(entry.error);
+    }
+    if (this.failure !== undefined) return Promise.reject(this.failure);
+    if (this.stopped && !this.reading) {
+      return Promise.reject(ClavisError.invalidOperation("Prefetch was stopped"));
+    }
+    if (signal?.aborted) return Promise.reject(ClavisError.invalidOperation("Read aborted"));
+
+    return new Promise((resolve, reject) => {
+      const onAbort = () => {
+        const index = this.waiters.indexOf(waiter);
+        if (index !== -1) {
+          this.waiters.splice(index, 1);
+          reject(ClavisError.invalidOperation("Read aborted"));
+        }
+      };
+      const waiter: Waiter<T> = {
+        resolve: (value) => {
+          signal?.removeEventListener("abort", onAbort);
+          resolve(value);
+        },
+        reject: (error) => {
+          signal?.removeEventListener("abort", onAbort);
+          reject(error);
+        },
+      };
+      this.waiters.push(waiter);
+      signal?.addEventListener("abort", onAbort, { once: true });
+    });
+  }
+
+  /** The next value if one is already buffered, without waiting; throws as `next()` does */
+  tryNext(): T | undefined {
+    const entry = this.buffer.shift();
+    if (!entry) {
+      if (this.failure !== undefined) throw this.failure;
+      return undefined;
+    }
+    this.pump();
+    if ("value" in entry) return entry.value;
+    throw entry.error;
+  }
+
+  /**
+   * Stop reading ahead. A read already in progress still completes and its
+   * packet is buffered; what is buffered can still be taken, after which
+   * `next()` rejects. The source is left as it is, ready for other readers.
+   */
+  stop(): void {
+    this.stopped = true;
+    if (!this.reading) this.endWaiters();
+  }
+
+  async *[Symbol.asyncIterator](): AsyncGenerator<T> {
+    for (;;) {
+      try {
+        yield await this.next();
+      } catch (error) {
+        if (isEnd(error)) return;
+        throw error;
+      }
+    }
+  }
+
+  private pump(): void {
+    if (this.reading || this.stopped || this.failure !== undefined || this.buffer.length >= this.depth) return;
+    this.reading = true;
+    this.source.readPacket().then(
+      (packet) => {
+        let entry: Entry<T>;
+        try {
+          entry = { value: this.decode(packet as unknown as Uint8Array) };
+        } catch (error) {
+          entry = { error };
+        }
+        this.reading = false;
+        this.deliver(entry);
+        if (this.stopped) this.endWaiters();
+        this.pump();
+      },
+      (error: unknown) => {
+        this.reading = false;
+        // Callers only wait on an empty buffer, so nothing is skipped; later calls get the error too
+        this.failure = error;
+        for (const waiter of this.waiters.splice(0)) waiter.reject(error);
+      }
+    );
+  }
+
+  /** Hand an entry to the oldest waiter, or buffer it */
+  private deliver(entry: Entry<T>): void {
+    const waiter = this.waiters.shift();
+    if (!waiter) {
+      this.buffer.push(entry);
+    } else if ("value" in entry) {
+      waiter.resolve(entry.value);
+    } else {
+      waiter.reject(entry.error);
+    }
+  }
+
+  /** Reject the callers still waiting once nothing more will arrive */
+  private endWaiters(): void {
+    for (const waiter of this.waiters.splice(0)) {
+      waiter.reject(ClavisError.invalidOperation("Prefetch was stopped"));
+    }
+  }
+}
+
+/** Whether `error` is the peer closing cleanly, which ends iteration */
+function isEnd(error: unknown): boolean {
+  const cause = error instanceof ClavisError ? error.cause : error;
+  return cause instanceof StreamError && (cause.code === StreamErrorCode.EOF || cause.code === StreamErrorCode.Closed);
+}
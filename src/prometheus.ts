@@ -0,0 +1,191 @@
+/**
+ * Prometheus exporter
+ * Connection counters in the Prometheus text format, for a /metrics endpoint
+ *
+ * `PrometheusExporter` is a `StreamTracer`: give it as the `tracer` of the
+ * streams to watch (with `combineTracers()` next to another tracer) and it
+ * tracks each connection from its handshake until its transport closes.
+ * Counters add up `stats()` over open connections and the final stats of
+ * closed ones, so they only ever grow. Nothing is polled in the background;
+ * the numbers are gathered when `render()` is called.
+ *
+ * The output can be served on its own or appended to another registry's,
+ * e.g. `await register.metrics() + exporter.render()` with prom-client.
+ */
+
+import type { IncomingMessage, ServerResponse } from "http";
+import type { ConnectionStats, EncryptedStream, StreamTracer, TraceSpan, TraceSpanName } from "./stream.js";
+import type { ProtocolStats } from "./protocol-stats.js";
+import { systemClock, type Clock } from "./clock.js";
+
+/**
+ * Options for `PrometheusExporter`
+ */
+export interface PrometheusExporterOptions {
+  /** Prefix of every metric name (default: "clavis") */
+  prefix?: string | undefined;
+  /** Labels added to every sample, such as the server instance (default: none) */
+  labels?: Readonly<Record<string, string>> | undefined;
+  /** Upper bounds of the handshake duration histogram, in seconds */
+  handshakeBuckets?: readonly number[] | undefined;
+  /** Time source for handshake durations (default: `systemClock`) */
+  clock?: Clock | undefined;
+  /**
+   * Per-variant counts to render as well, labeled by identity, direction
+   * and variant (default: none)
+   */
+  protocolStats?: ProtocolStats | undefined;
+}
+
+/** Default handshake duration buckets, in seconds */
+export const DEFAULT_HANDSHAKE_BUCKETS: readonly number[] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1, 2.5, 5, 10];
+
+/** Content type of `render()`'s output */
+export const PROMETHEUS_CONTENT_TYPE = "text/plain; version=0.0.4; charset=utf-8";
+
+type Counter = Exclude<keyof ConnectionStats, "handshakeMs">;
+
+const COUNTERS: ReadonlyArray<[Counter, string, string]> = [
+  ["packetsSent", "packets_sent_total", "Application packets written"],
+  ["packetsReceived", "packets_received_total", "Application packets read"],
+  ["bytesSent", "bytes_sent_total", "Bytes handed to the transport, handshake and control frames included"],
+  ["bytesReceived", "bytes_received_total", "Bytes received from the transport"],
+  ["rekeys", "rekeys_total", "Completed rekeys"],
+  ["decryptionFailures", "decryption_failures_total", "Frames that failed authentication"],
+  ["keepAlivesSent", "keepalives_sent_total", "Keepalive pings sent"],
+  ["keepAlivesSuppressed", "keepalives_suppressed_total", "Keepalive pings skipped for outgoing traffic or a backed-up transport"],
+];
+
+const NO_SPAN: TraceSpan = { end() {} };
+
+function escapeLabel(value: string): string {
+  return value.replace(/\\/g, "\\\\").replace(/\n/g, "\\n").replace(/"/g, '\\"');
+}
+
+/**
+ * Connection metrics of every stream it traces
+ *
+ * @example
+ * ```typescript
+ * const exporter = new PrometheusExporter({ labels: { instance: "eu-1" } });
+ * const listener = await EncryptedListener.bind(7000, "0.0.0.0", { streamOptions: { tracer: exporter } });
+ * createServer(exporter.handler()).listen(9464);
+ * ```
+ */
+export class PrometheusExporter implements StreamTracer {
+  private readonly open = new Map<string, EncryptedStream>();
+  private readonly retired: Record<Counter, number> = {
+    packetsSent: 0,
+    packetsReceived: 0,
+    bytesSent: 0,
+    bytesReceived: 0,
+    rekeys: 0,
+    decryptionFailures: 0,
+    keepAlivesSent: 0,
+    keepAlivesSuppressed: 0,
+  };
+  private connections = 0;
+  private failedHandshakes = 0;
+  private readonly prefix: string;
+  private readonly labels: string;
+  private readonly buckets: readonly number[];
+  private readonly bucketCounts: number[];
+  private handshakeCount = 0;
+  private handshakeSum = 0;
+  private readonly clock: Clock;
+  private readonly protocolStats: ProtocolStats | undefined;
+
+  constructor(options: PrometheusExporterOptions = {}) {
+    this.prefix = options.prefix ?? "clavis";
+    this.labels = Object.entries(options.labels ?? {})
+      .map(([name, value]) => `${name}="${escapeLabel(value)}"`)
+      .join(",");
+    this.buckets = [...(options.handshakeBuckets ?? DEFAULT_HANDSHAKE_BUCKETS)].sort((a, b) => a - b);
+    this.bucketCounts = this.buckets.map(() => 0);
+    this.clock = options.clock ?? systemClock;
+    this.protocolStats = options.protocolStats;
+  }
+
+  startSpan(name: TraceSpanName): TraceSpan {
+    if (name !== "clavis.handshake") return NO_SPAN;
+    const start = this.clock.now();
+    return {
+      end: (error) => {
+        if (error !== undefined) {
+          this.failedHandshakes++;
+          return;
+        }
+        const seconds = (this.clock.now() - start) / 1000;
+        this.handshakeCount++;
+        this.handshakeSum += seconds;
+        this.buckets.forEach((bound, i) => {
+          if (seconds <= bound) this.bucketCounts[i]!++;
+        });
+      },
+    };
+  }
+
+  established(stream: EncryptedStream): void {
+    this.connections++;
+    this.open.set(stream.connectionId, stream);
+  }
+
+  closed(connectionId: string): void {
+    const stream = this.open.get(connectionId);
+    if (!stream) return;
+    this.open.delete(connectionId);
+    const stats = stream.stats();
+    for (const [counter] of COUNTERS) this.retired[counter] += stats[counter];
+  }
+
+  /** Every metric in the Prometheus text exposition format */
+  render(): string {
+    const totals = { ...this.retired };
+    for (const stream of this.open.values()) {
+      const stats = stream.stats();
+      for (const [counter] of COUNTERS) totals[counter] += stats[counter];
+    }
+
+    const lines: string[] = [];
+    const metric = (name: string, type: string, help: string, samples: Array<[string, string, number]>) => {
+      const full = `${this.prefix}_${name}`;
+      lines.push(`# HELP ${full} ${help}`, `# TYPE ${full} ${type}`);
+      for (const [suffix, labels, value] of samples) {
+        const all = [this.labels, labels].filter((part) => part !== "").join(",");
+        lines.push(`${full}${suffix}${all ? `{${all}}` : ""} ${value}`);
+      }
+    };
+
+    metric("connections_open", "gauge", "Connections currently open", [["", "", this.open.size]]);
+    metric("connections_total", "counter", "Connections that completed their handshake", [["", "", this.connections]]);
+    metric("handshake_failures_total", "counter", "Handshakes that failed", [["", "", this.failedHandshakes]]);
+    for (const [counter, name, help] of COUNTERS) {
+      metric(name, "counter", help, [["", "", totals[counter]]]);
+    }
+    metric("handshake_duration_seconds", "histogram", "Time to set up a connection, setup exchanges included", [
+      ...this.buckets.map((bound, i): [string, string, number] => ["_bucket", `le="${bound}"`, this.bucketCounts[i]!]),
+      ["_bucket", 'le="+Inf"', this.handshakeCount],
+      ["_sum", "", this.handshakeSum],
+      ["_count", "", this.handshakeCount],
+    ]);
+    if (this.protocolStats) {
+      const entries = this.protocolStats.entries().map((entry) => ({
+        ...entry,
+        labels: `identity="${escapeLabel(entry.identity)}",direction="${entry.direction}",variant="${escapeLabel(entry.variant)}"`,
+      }));
+      metric("variant_packets_total", "counter", "Application packets by peer identity, direction and variant",
+        entries.map((entry): [string, string, number] => ["", entry.labels, entry.packets]));
+      metric("variant_bytes_total", "counter", "Serialized bytes by peer identity, direction and variant",
+        entries.map((entry): [string, string, number] => ["", entry.labels, entry.bytes]));
+    }
+    return lines.join("\n") + "\n";
+  }
+
+  /** A `node:http` request handler serving `render()` */
+  handler(): (request: IncomingMessage, response: ServerResponse) => void {
+    return (_request, response) => {
+      response.writeHead(200, { "Content-Type": PROMETHEUS_CONTENT_TYPE });
+      response.end(this.render());
+    };
+  }
+}
@@ -0,0 +1,271 @@
+/**
+ * Protocol reference generator
+ * Turns a protocol codec, its variant schemas and their doc comments into a
+ * Markdown or HTML reference that can be published to integrators
+ *
+ * Variant indices and encodings come from the codec itself, and field
+ * layouts from the same schemas used to encode packets, so the reference
+ * describes what is actually sent.
+ */
+
+import { ClavisError } from "./error.js";
+import type { ProtocolCodec } from "./protocol.js";
+import type { FieldType, PacketSchema, SchemaField } from "./schema.js";
+
+/**
+ * Documentation attached to one variant
+ */
+export interface VariantDoc {
+  /** What the variant means and when it is sent */
+  doc?: string | undefined;
+  /** Fields following the variant index (default: undocumented payload) */
+  schema?: PacketSchema | undefined;
+  /** Largest serialized packet of this variant, when tighter than the protocol's */
+  maxSize?: number | undefined;
+}
+
+/**
+ * Options for `describeProtocol`
+ */
+export interface ProtocolDocOptions<T extends string> {
+  title: string;
+  /** Introductory text placed under the title */
+  description?: string | undefined;
+  /** Per-variant documentation; variants left out are listed without it */
+  variants?: Partial<Record<T, VariantDoc>> | undefined;
+  /** Largest serialized packet the server accepts (`maxPacketSize`) */
+  maxPacketSize?: number | undefined;
+}
+
+/**
+ * One documented field
+ */
+export interface DocumentedField {
+  name: string;
+  /** Rust-style type, e.g. `Option<string>` */
+  type: string;
+  /** Bytes on the wire, or undefined when it depends on the value */
+  size: number | undefined;
+}
+
+/**
+ * One documented variant
+ */
+export interface DocumentedVariant {
+  name: string;
+  index: number;
+  doc: string | undefined;
+  /** Whether this is the protocol's error variant */
+  error: boolean;
+  /** Whether new code shouldn't send it (see `ProtocolCodec.isDeprecated`) */
+  deprecated: boolean;
+  /** Undefined when no schema was given */
+  fields: DocumentedField[] | undefined;
+  /** Schema hash, for checking both sides agree */
+  schemaHash: string | undefined;
+  /** Smallest serialized size, variant index included */
+  minSize: number | undefined;
+  /** Largest serialized size, when known */
+  maxSize: number | undefined;
+}
+
+/**
+ * A protocol reference, ready to format
+ */
+export interface ProtocolDoc {
+  title: string;
+  description: string | undefined;
+  /** How variant indices are encoded */
+  indexEncoding: "u32" | "varint";
+  maxPacketSize: number | undefined;
+  variants: DocumentedVariant[];
+}
+
+const FIXED_SIZES: Record<FieldType, number | undefined> = {
+  u8: 1,
+  u16: 2,
+  u32: 4,
+  i32: 4,
+  u64: 8,
+  i64: 8,
+  bool: 1,
+  datetime: 12,
+  string: undefined,
+  bytes: undefined,
+};
+
+/** Length prefix of strings and byte arrays */
+const LENGTH_PREFIX = 8;
+
+function documentField(field: SchemaField): DocumentedField {
+  const size = FIXED_SIZES[field.type];
+  return {
+    name: field.name,
+    type: field.optional ? `Option<${field.type}>` : field.type,
+    size: field.optional ? undefined : size,
+  };
+}
+
+/** Smallest encoding of a field: None, or an empty string or byte array */
+function minFieldSize(field: SchemaField): number {
+  if (field.optional) return 1;
+  return FIXED_SIZES[field.type] ?? LENGTH_PREFIX;
+}
+
+/**
+ * Collect everything the reference shows about a protocol.
+ * Throws a config error if `options.variants` names a variant the codec lacks.
+ */
+export function describeProtocol<T extends string>(
+  codec: ProtocolCodec<T>,
+  options: ProtocolDocOptions<T>
+): ProtocolDoc {
+  const docs = (options.variants ?? {}) as Partial<Record<string, VariantDoc>>;
+  for (const name of Object.keys(docs)) {
+    if (!codec.isValidType(name)) {
+      throw ClavisError.config(`Documented variant ${name} is not part of the protocol`);
+    }
+  }
+
+  const variants = codec.variants();
+  // The codec doesn't say how it writes indices, but index 0 takes 4 bytes as a u32 and 1 as a varint
+  const indexLength = variants.length > 0 ? codec.encode(variants[0]!).length : 4;
+
+  return {
+    title: options.title,
+    description: options.description,
+    indexEncoding: indexLength === 4 ? "u32" : "varint",
+    maxPacketSize: options.maxPacketSize,
+    variants: variants.map((name) => {
+      const index = codec.variantIndex(name);
+      const variant = docs[name];
+      const schema = variant?.schema;
+      const fields = schema?.fields.map(documentField);
+      const fixed = fields?.every((field) => field.size !== undefined) ?? false;
+      const minSize = schema
+        ? codec.encode(name).length + schema.fields.reduce((sum, field) => sum + minFieldSize(field), 0)
+        : undefined;
+      return {
+        name,
+        index,
+        doc: variant?.doc,
+        error: codec.isError(name),
+        deprecated: codec.isDeprecated(name),
+        fields,
+        schemaHash: schema?.hash,
+        minSize,
+        maxSize: fixed ? minSize : variant?.maxSize ?? options.maxPacketSize,
+      };
+    }),
+  };
+}
+
+function sizeRange(variant: DocumentedVariant): string {
+  if (variant.minSize === undefined) {
+    return variant.maxSize === undefined ? "-" : `≤ ${variant.maxSize}`;
+  }
+  if (variant.minSize === variant.maxSize) return String(variant.minSize);
+  return variant.maxSize === undefined ? `≥ ${variant.minSize}` : `${variant.minSize}–${variant.maxSize}`;
+}
+
+/** Variant name with its role, e.g. `Failure (error)` */
+function label(variant: DocumentedVariant): string {
+  const notes = [variant.error && "error", variant.deprecated && "deprecated"].filter(Boolean);
+  return notes.length > 0 ? `${variant.name} (${notes.join(", ")})` : variant.name;
+}
+
+/** Keep doc text from breaking out of a Markdown table cell */
+function cell(text: string): string {
+  return text.replace(/\|/g, "\\|").replace(/\n+/g, " ");
+}
+
+/**
+ * Render a protocol reference as Markdown
+ */
+export function formatProtocolDoc(doc: ProtocolDoc): string {
+  const lines: string[] = [`# ${doc.title}`, ""];
+  if (doc.description) lines.push(doc.description, "");
+  lines.push(
+    `Variant indices are encoded as ${doc.indexEncoding === "u32" ? "u32 little-endian" : "bincode varints"}.`
+  );
+  if (doc.maxPacketSize !== undefined) {
+    lines.push(`Packets are at most ${doc.maxPacketSize} bytes once serialized.`);
+  }
+  lines.push(
+    "",
+    "| Index | Variant | Size | Description |",
+    "|-------|---------|------|-------------|"
+  );
+  for (const variant of doc.variants) {
+    const name = label(variant);
+    lines.push(`| ${variant.index} | ${name} | ${sizeRange(variant)} | ${cell(variant.doc ?? "")} |`);
+  }
+
+  for (const variant of doc.variants) {
+    lines.push("", `## ${variant.name}`, "");
+    if (variant.doc) lines.push(variant.doc, "");
+    lines.push(`- Index: ${variant.index}`, `- Size: ${sizeRange(variant)} bytes`);
+    if (variant.schemaHash) lines.push(`- Schema hash: \`${variant.schemaHash}\``);
+    if (!variant.fields) continue;
+    if (variant.fields.length === 0) {
+      lines.push("", "No fields.");
+      continue;
+    }
+    lines.push("", "| Field | Type | Size |", "|-------|------|------|");
+    for (const field of variant.fields) {
+      lines.push(`| ${field.name} | ${field.type} | ${field.size ?? "variable"} |`);
+    }
+  }
+  lines.push("");
+  return lines.join("\n");
+}
+
+function escapeHtml(text: string): string {
+  return text
+    .replace(/&/g, "&amp;")
+    .replace(/</g, "&lt;")
+    .replace(/>/g, "&gt;")
+    .replace(/"/g, "&quot;");
+}
+
+/**
+ * Render a protocol reference as a standalone HTML page
+ */
+export function formatProtocolDocHtml(doc: ProtocolDoc): string {
+  const out: string[] = [
+    "<!DOCTYPE html>",
+    `<html><head><meta charset="utf-8"><title>${escapeHtml(doc.title)}</title></head><body>`,
+    `<h1>${escapeHtml(doc.title)}</h1>`,
+  ];
+  if (doc.description) out.push(`<p>${escapeHtml(doc.description)}</p>`);
+  out.push(`<p>Variant indices are encoded as ${doc.indexEncoding === "u32" ? "u32 little-endian" : "bincode varints"}.</p>`);
+  if (doc.maxPacketSize !== undefined) {
+    out.push(`<p>Packets are at most ${doc.maxPacketSize} bytes once serialized.</p>`);
+  }
+  out.push("<table><tr><th>Index</th><th>Variant</th><th>Size</th><th>Description</th></tr>");
+  for (const variant of doc.variants) {
+    const name = label(variant);
+    out.push(
+      `<tr><td>${variant.index}</td><td><a href="#${escapeHtml(variant.name)}">${escapeHtml(name)}</a></td>` +
+      `<td>${escapeHtml(sizeRange(variant))}</td><td>${escapeHtml(variant.doc ?? "")}</td></tr>`
+    );
+  }
+  out.push("</table>");
+
+  for (const variant of doc.variants) {
+    out.push(`<h2 id="${escapeHtml(variant.name)}">${escapeHtml(variant.name)}</h2>`);
+    if (variant.doc) out.push(`<p>${escapeHtml(variant.doc)}</p>`);
+    out.push(`<p>Index ${variant.index}, ${escapeHtml(sizeRange(variant))} bytes</p>`);
+    if (variant.schemaHash) out.push(`<p>Schema hash: <code>${variant.schemaHash}</code></p>`);
+    if (!variant.fields || variant.fields.length === 0) continue;
+    out.push("<table><tr><th>Field</th><th>Type</th><th>Size</th></tr>");
+    for (const field of variant.fields) {
+      out.push(
+        `<tr><td>${escapeHtml(field.name)}</td><td>${escapeHtml(field.type)}</td><td>${field.size ?? "variable"}</td></tr>`
+      );
+    }
+    out.push("</table>");
+  }
+  out.push("</body></html>", "");
+  return out.join("\n");
+}
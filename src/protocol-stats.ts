@@ -0,0 +1,211 @@
+/**
+ * Protocol statistics
+ * Packet and byte counts per variant, per direction and per peer identity
+ *
+ * Attach one `ProtocolStats` to many streams with the `protocolStats` option
+ * and it counts every application packet they read and write by the variant
+ * index at its start, under the identity of the peer that sent or received
+ * it. That answers questions like "which client is sending all these Join
+ * packets" from metrics alone.
+ *
+ * Identities come from the peer's verified certificate or, failing that,
+ * its PSK identity. Only the `maxIdentities` most recently active ones are
+ * kept apart; when a new one arrives past the limit, the least recently
+ * active is folded into the "(other)" bucket, so totals stay exact and
+ * cardinality stays bounded. Setup exchanges are never counted.
+ */
+
+import type { EncryptedStream } from "./stream.js";
+import type { ProtocolCodec } from "./protocol.js";
+import { ClavisError } from "./error.js";
+
+/** Identity of connections whose peer has none */
+export const ANONYMOUS_IDENTITY = "(anonymous)";
+
+/** Identity the counts of evicted identities are folded into */
+export const OTHER_IDENTITY = "(other)";
+
+/** Variant of packets too short to hold a variant index */
+export const UNKNOWN_VARIANT = "(unknown)";
+
+const DEFAULT_MAX_IDENTITIES = 256;
+const VARIANT_INDEX_LENGTH = 4;
+
+/**
+ * Which way a counted packet went
+ */
+export type PacketDirection = "read" | "write";
+
+/**
+ * Options for `ProtocolStats`
+ */
+export interface ProtocolStatsOptions {
+  /** Names variants by their index; without it, variants are counted by index */
+  codec?: Pick<ProtocolCodec<string>, "variantName"> | undefined;
+  /** Identities counted separately before the least recently active is evicted (default: 256) */
+  maxIdentities?: number | undefined;
+  /**
+   * Identity to count a connection under, computed once its setup is done
+   * (default: the peer's verified identity, then its PSK identity)
+   */
+  identityOf?: ((stream: EncryptedStream) => string | undefined) | undefined;
+}
+
+/**
+ * Packets and bytes of one variant in one direction for one identity
+ */
+export interface VariantCount {
+  identity: string;
+  direction: PacketDirection;
+  variant: string;
+  packets: number;
+  bytes: number;
+}
+
+interface Tally {
+  packets: number;
+  bytes: number;
+}
+
+interface IdentityCounts {
+  lastSeen: number;
+  read: Map<string, Tally>;
+  write: Map<string, Tally>;
+}
+
+function emptyCounts(): IdentityCounts {
+  return { lastSeen: 0, read: new Map(), write: new Map() };
+}
+
+function add(into: Map<string, Tally>, variant: string, packets: number, bytes: number): void {
+  const tally = into.get(variant);
+  if (tally) {
+    tally.packets += packets;
+    tally.bytes += bytes;
+  } else {
+    into.set(variant, { packets, bytes });
+  }
+}
+
+/**
+ * Per-variant packet counts segmented by peer identity
+ *
+ * @example
+ * ```typescript
+ * const protocolStats = new ProtocolStats({ codec: GameProtocol });
+ * const listener = await EncryptedListener.bind(7000, "0.0.0.0", {
+ *   streamOptions: { trustedSigners, protocolStats },
+ * });
+ * protocolStats.top(5, { variant: "Join", direction: "read" });
+ * ```
+ */
+export class ProtocolStats {
+  private readonly identities = new Map<string, IdentityCounts>();
+  private readonly other = emptyCounts();
+  private readonly maxIdentities: number;
+  private readonly identityFor: (stream: EncryptedStream) => string | undefined;
+  private tick = 0;
+
+  constructor(private readonly options: ProtocolStatsOptions = {}) {
+    const maxIdentities = options.maxIdentities ?? DEFAULT_MAX_IDENTITIES;
+    if (!Number.isInteger(maxIdentities) || maxIdentities < 1) {
+      throw ClavisError.config(`maxIdentities must be a positive integer, got ${maxIdentities}`);
+    }
+    this.maxIdentities = maxIdentities;
+    this.identityFor = options.identityOf ?? ((stream) => stream.peerIdentity?.identity ?? stream.pskIdentity);
+  }
+
+  /** Number of identities counted separately right now */
+  get size(): number {
+    return this.identities.size;
+  }
+
+  /** Identity the packets of `stream` are counted under */
+  identityOf(stream: EncryptedStream): string {
+    return this.identityFor(stream) ?? ANONYMOUS_IDENTITY;
+  }
+
+  /** Count one serialized packet that went `direction` for `identity` */
+  observe(identity: string, direction: PacketDirection, packet: Uint8Array): void {
+    add(this.countsFor(identity)[direction], this.variantOf(packet), 1, packet.length);
+  }
+
+  /** Every count, the "(other)" bucket included */
+  entries(): VariantCount[] {
+    const out: VariantCount[] = [];
+    const push = (identity: string, counts: IdentityCounts) => {
+      for (const direction of ["read", "write"] as const) {
+        for (const [variant, { packets, bytes }] of counts[direction]) {
+          out.push({ identity, direction, variant, packets, bytes });
+        }
+      }
+    };
+    for (const [identity, counts] of this.identities) push(identity, counts);
+    push(OTHER_IDENTITY, this.other);
+    return out;
+  }
+
+  /**
+   * The `n` identities that sent or received the most packets matching
+   * `filter`, busiest first, each with its total over the matching counts
+   */
+  top(n: number, filter: { variant?: string | undefined; direction?: PacketDirection | undefined } = {}): VariantCount[] {
+    const totals = new Map<string, VariantCount>();
+    for (const entry of this.entries()) {
+      if (filter.variant !== undefined && entry.variant !== filter.variant) continue;
+      if (filter.direction !== undefined && entry.direction !== filter.direction) continue;
+      const total = totals.get(entry.identity);
+      if (total) {
+        total.packets += entry.packets;
+        total.bytes += entry.bytes;
+      } else {
+        totals.set(entry.identity, { ...entry, variant: filter.variant ?? "*" });
+      }
+    }
+    return [...totals.values()].sort((a, b) => b.packets - a.packets).slice(0, n);
+  }
+
+  /** Forget every count */
+  reset(): void {
+    this.identities.clear();
+    this.other.read.clear();
+    this.other.write.clear();
+  }
+
+  private variantOf(packet: Uint8Array): string {
+    if (packet.length < VARIANT_INDEX_LENGTH) return UNKNOWN_VARIANT;
+    const index = new DataView(packet.buffer, packet.byteOffset, VARIANT_INDEX_LENGTH).getUint32(0, true);
+    return this.options.codec?.variantName(index) ?? String(index);
+  }
+
+  private countsFor(identity: string): IdentityCounts {
+    if (identity === OTHER_IDENTITY) return this.other;
+    let counts = this.identities.get(identity);
+    if (!counts) {
+      if (this.identities.size >= this.maxIdentities) this.evictLeastRecent();
+      counts = emptyCounts();
+      this.identities.set(identity, counts);
+    }
+    counts.lastSeen = ++this.tick;
+    return counts;
+  }
+
+  private evictLeastRecent(): void {
+    let oldest: string | undefined;
+    let oldestSeen = Infinity;
+    for (const [identity, counts] of this.identities) {
+      if (counts.lastSeen < oldestSeen) {
+        oldest = identity;
+        oldestSeen = counts.lastSeen;
+      }
+    }
+    if (oldest === undefined) return;
+    const evicted = this.identities.get(oldest)!;
+    this.identities.delete(oldest);
+    for (const direction of ["read", "write"] as const) {
+      for (const [variant, { packets, bytes }] of evicted[direction]) {
+        add(this.other[direction], variant, packets, bytes);
+      }
+    }
+  }
+}
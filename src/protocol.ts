@@ -34,6 +34,8 @@
  */
 
 import { ClavisError } from "./error.js";
+import { redactLike } from "./audit.js";
+import type { WireFormat } from "./formats.js";
 import { 
   writeVarintU32, 
   writeString, 
@@ -43,6 +45,9 @@ import {
   readVarintU32,
   readU32,
   BincodeReader,
+  checkDecodeLimits,
+  DecodeBudget,
+  type DecodeLimits,
 } from "./bincode.js";
 
 /**
@@ -51,6 +56,62 @@ import {
 export interface PacketTrait {
   serialize(): Uint8Array;
   deserialize(data: Uint8Array): this;
+  /** Length of `serialize()` output, for packets that know it without serializing */
+  serializedSize?(): number;
+  /** Variant the packet is, named in errors about it */
+  readonly variantName?: string;
+}
+
+/**
+ * Serialized length of a packet, excluding frame overhead.
+ * Falls back to serializing when the packet can't report its size; to avoid
+ * paying for serialization twice, serialize once and send a `RawPacket`.
+ */
+export function serializedSize(packet: PacketTrait): number {
+  return packet.serializedSize?.() ?? packet.serialize().length;
+}
+
+/**
+ * Packet wrapping bytes that are already serialized.
+ * Useful for forwarding raw payloads or bytes produced by a `ProtocolCodec`.
+ */
+export class RawPacket implements PacketTrait {
+  constructor(public readonly bytes: Uint8Array) {}
+
+  serialize(): Uint8Array {
+    return this.bytes;
+  }
+
+  deserialize(data: Uint8Array): this {
+    return new RawPacket(data) as this;
+  }
+
+  serializedSize(): number {
+    return this.bytes.length;
+  }
+}
+
+/**
+ * A packet serialized once up front, for sending the same message to many
+ * connections. Each connection still encrypts it with its own keys.
+ *
+ * @example
+ * ```typescript
+ * const status = PreparedPacket.new(Packet.Status({ usersOnline: 42 }));
+ * for (const writer of writers) {
+ *   await writer.writePacket(status);
+ * }
+ * ```
+ */
+export class PreparedPacket extends RawPacket {
+  /** Serialize `packet` once; later changes to it are not reflected */
+  static new(packet: PacketTrait): PreparedPacket {
+    return new PreparedPacket(packet.serialize().slice());
+  }
+
+  override deserialize(data: Uint8Array): this {
+    return new PreparedPacket(data) as this;
+  }
 }
 
 /**
@@ -181,12 +242,26 @@ export interface DecodedMessage<T extends string> {
   type: T;
   /** The variant index */
   index: number;
-  /** Raw data after the variant index (for custom deserialization) */
+  /**
+   * Raw data after the variant index (for custom deserialization).
+   * A view into the decoded packet, not a copy.
+   */
   data: Uint8Array;
   /** BincodeReader positioned after the variant index */
   reader: BincodeReader;
 }
 
+/**
+ * Message with a variant index this side doesn't know, e.g. from a peer
+ * running a newer protocol version
+ */
+export interface UnknownVariant {
+  type: undefined;
+  index: number;
+  data: Uint8Array;
+  reader: BincodeReader;
+}
+
 /**
  * Protocol codec for encoding/decoding variant indices.
  * This is a lightweight alternative to the full `protocol()` DSL
@@ -244,6 +319,19 @@ export interface ProtocolCodec<T extends string> {
    * @returns Decoded message with type, index, remaining data, and a BincodeReader
    */
   decode(data: Uint8Array): DecodedMessage<T>;
+
+  /**
+   * Decode like `decode()`, but return a variant index this side doesn't
+   * know as an `UnknownVariant` instead of throwing. Pair it with
+   * `matchKnown()` to handle the known variants exhaustively.
+   */
+  decodeVariant(data: Uint8Array): DecodedMessage<T> | UnknownVariant;
+
+  /**
+   * Read just the variant index of encoded bytes, without copying the rest.
+   * Returns undefined when the bytes are too short or malformed.
+   */
+  peekIndex(data: Uint8Array): number | undefined;
   
   /**
    * Check if a variant index is valid
@@ -254,12 +342,45 @@ export interface ProtocolCodec<T extends string> {
    * Check if a type name is valid
    */
   isValidType(type: string): type is T;
+
+  /**
+   * Variant that carries handler failures, if the protocol follows the
+   * error packet convention (the TypeScript equivalent of `#[packet(error)]`).
+   * The RPC and router layers map thrown errors to this variant and back.
+   */
+  readonly errorVariant: T | undefined;
+
+  /**
+   * Check if a type name is the protocol's error variant
+   */
+  isError(type: T): boolean;
+
+  /**
+   * Check if a variant is deprecated: kept so older peers still decode,
+   * but not meant to be sent by new code (the TypeScript equivalent of
+   * `#[deprecated]` on a variant)
+   */
+  isDeprecated(type: T): boolean;
+
+  /**
+   * Protocol version, if one was declared (the TypeScript equivalent of
+   * `#[version = N]`). Pass the codec as a stream's `protocolVersion` to
+   * exchange and check it during connection setup.
+   */
+  readonly version: number | undefined;
+
+  /**
+   * Payload format the protocol's variant data is serialized with, if one
+   * was declared. Pass the codec as a stream's `format` to fix it.
+   */
+  readonly format: WireFormat | undefined;
 }
 
 /**
  * Create a protocol codec for encoding/decoding variant indices.
  * 
- * @param variants - Array of variant names in order (must match Rust enum definition order)
+ * @param variants - Array of variant names in order (must match Rust enum definition order,
+ *   unless `ids` pins their indices)
  * @param options - Codec options
  * @returns ProtocolCodec instance
  * 
@@ -292,19 +413,121 @@ export function createProtocolCodec<T extends string>(
   options?: {
     /** Use Varint encoding instead of u32 (default: false for clavis::protocol! compatibility) */
     useVarint?: boolean;
+    /** Variant used to carry handler failures (see `ProtocolCodec.errorVariant`) */
+    errorVariant?: T;
+    /** Protocol version exchanged during connection setup (see `ProtocolCodec.version`) */
+    version?: number;
+    /** Payload format of the variant data (see `ProtocolCodec.format`) */
+    format?: WireFormat;
+    /** Limits the readers of decoded messages enforce (default: none beyond the packet's length) */
+    limits?: DecodeLimits;
+    /**
+     * Milliseconds the reader of a decoded message may spend on it, for every
+     * variant or per variant (default: none). Each message's budget starts at
+     * `decode()`; readers check it at sequences and `nested()` reads.
+     */
+    decodeBudgetMs?: number | Partial<Record<T, number>>;
+    /**
+     * Wire index of individual variants (the TypeScript equivalent of
+     * `#[id = N]`). A variant without one takes the previous variant's index
+     * plus one, as Rust numbers discriminants, so pinning the variants
+     * peers already know lets new ones go anywhere in the list.
+     */
+    ids?: Partial<Record<T, number>>;
+    /** Variants new code shouldn't send (see `ProtocolCodec.isDeprecated`) */
+    deprecated?: readonly T[];
   }
 ): ProtocolCodec<T> {
   const useVarint = options?.useVarint ?? false;
+  const errorVariant = options?.errorVariant;
+  const version = options?.version;
+  const limits = options?.limits;
+  if (limits) checkDecodeLimits(limits);
+  const decodeBudgetMs = options?.decodeBudgetMs;
+  const budgets: Array<number | undefined> = typeof decodeBudgetMs === "object"
+    ? variants.map((variant) => decodeBudgetMs[variant])
+    : variants.map(() => decodeBudgetMs);
+  if (budgets.some((budget) => budget !== undefined && !(budget > 0))) {
+    throw ClavisError.config("decodeBudgetMs must be positive");
+  }
+  if (version !== undefined && !(Number.isInteger(version) && version >= 0 && version <= 0xffffffff)) {
+    throw ClavisError.config("Protocol version must be a u32");
+  }
   const nameToIndex = new Map<T, number>();
   const indexToName = new Map<number, T>();
+  const ids: Partial<Record<T, number>> = options?.ids ?? {};
   
-  for (let i = 0; i < variants.length; i++) {
-    const name = variants[i]!;
-    nameToIndex.set(name, i);
-    indexToName.set(i, name);
+  let next = 0;
+  for (const name of variants) {
+    const index = ids[name] ?? next;
+    if (!(Number.isInteger(index) && index >= 0 && index <= 0xffffffff)) {
+      throw ClavisError.config(`Variant ${name} needs an index that is a u32, got ${index}`);
+    }
+    const clash = indexToName.get(index);
+    if (clash !== undefined) {
+      throw ClavisError.config(`Variants ${clash} and ${name} both have index ${index}`);
+    }
+    nameToIndex.set(name, index);
+    indexToName.set(index, name);
+    next = index + 1;
+  }
+  for (const name of Object.keys(ids)) {
+    if (!nameToIndex.has(name as T)) {
+      throw ClavisError.config(`Variant ${name} has an id but is not part of the protocol`);
+    }
+  }
+  const budgetByIndex = new Map(variants.map((name, i) => [nameToIndex.get(name)!, budgets[i]]));
+  const deprecated = new Set<T>(options?.deprecated);
+  for (const name of deprecated) {
+    if (!nameToIndex.has(name)) {
+      throw ClavisError.config(`Deprecated variant ${name} is not part of the protocol`);
+    }
+  }
+
+  if (errorVariant !== undefined && !nameToIndex.has(errorVariant)) {
+    throw ClavisError.config(`Error variant ${errorVariant} is not part of the protocol`);
+  }
+
+  function decodeVariant(data: Uint8Array): DecodedMessage<T> | UnknownVariant {
+    if (data.length < 4) {
+      throw ClavisError.deserializationFailed("Data too short to contain variant index");
+    }
+
+    let index: number;
+    let bytesRead: number;
+
+    if (useVarint) {
+      const result = readVarintU32(data, 0);
+      index = result.value;
+      bytesRead = result.bytesRead;
+    } else {
+      const result = readU32(data, 0);
+      index = result.value;
+      bytesRead = result.bytesRead;
+    }
+
+    // Packets from a stream are fresh buffers owned by the caller, so a view is safe
+    const remainingData = redactLike(data.subarray(bytesRead), data);
+    const budgetMs = budgetByIndex.get(index);
+    const readerLimits = budgetMs === undefined ? limits : { ...limits, budget: new DecodeBudget(budgetMs) };
+    const rest = { index, data: remainingData, reader: new BincodeReader(remainingData, readerLimits) };
+    const type = indexToName.get(index);
+    return type === undefined ? { type, ...rest } : { type, ...rest };
   }
   
   return {
+    errorVariant,
+    version,
+    format: options?.format,
+
+    isError(type: T): boolean {
+      return errorVariant !== undefined && type === errorVariant;
+    },
+
+    isDeprecated(type: T): boolean {
+      return deprecated.has(type);
+    },
+
     variantIndex(type: T): number {
       const index = nameToIndex.get(type);
       if (index === undefined) {
@@ -343,39 +566,23 @@ export function createProtocolCodec<T extends string>(
     },
     
     decode(data: Uint8Array): DecodedMessage<T> {
-      if (data.length < 4) {
-        throw ClavisError.deserializationFailed("Data too short to contain variant index");
+      const message = decodeVariant(data);
+      if (message.type === undefined) {
+        throw ClavisError.deserializationFailed(`Unknown variant index: ${message.index}`);
       }
-      
-      let index: number;
-      let bytesRead: number;
-      
-      if (useVarint) {
-        const result = readVarintU32(data, 0);
-        index = result.value;
-        bytesRead = result.bytesRead;
-      } else {
-        const result = readU32(data, 0);
-        index = result.value;
-        bytesRead = result.bytesRead;
-      }
-      
-      const type = indexToName.get(index);
-      if (type === undefined) {
-        throw ClavisError.deserializationFailed(`Unknown variant index: ${index}`);
+      return message;
+    },
+
+    decodeVariant,
+
+    peekIndex(data: Uint8Array): number | undefined {
+      try {
+        return (useVarint ? readVarintU32(data, 0) : readU32(data, 0)).value;
+      } catch {
+        return undefined;
       }
-      
-      const remainingData = data.slice(bytesRead);
-      const reader = new BincodeReader(remainingData);
-      
-      return {
-        type,
-        index,
-        data: remainingData,
-        reader,
-      };
     },
-    
+
     isValidIndex(index: number): boolean {
       return indexToName.has(index);
     },
@@ -385,3 +592,35 @@ export function createProtocolCodec<T extends string>(
     },
   };
 }
+
+/**
+ * Handlers for every variant of a protocol. Leaving one out is a compile
+ * error, so a new variant can't fall through a catch-all unnoticed.
+ */
+export type KnownVariantHandlers<T extends string, R> = {
+  [K in T]: (message: DecodedMessage<K>) => R;
+};
+
+/**
+ * Dispatch a message from `decodeVariant()` to the handler for its variant,
+ * or to `onUnknown` for variants this side doesn't know.
+ *
+ * @example
+ * ```typescript
+ * const reply = matchKnown(codec.decodeVariant(bytes), {
+ *   Heartbeat: () => undefined,
+ *   TaskOffer: ({ reader }) => acceptTask(reader),
+ * }, ({ index }) => log.debug(`ignoring variant ${index} from a newer peer`));
+ * ```
+ */
+export function matchKnown<T extends string, R>(
+  message: DecodedMessage<T> | UnknownVariant,
+  handlers: KnownVariantHandlers<NoInfer<T>, R>,
+  onUnknown: (message: UnknownVariant) => R
+): R {
+  if (message.type === undefined) {
+    return onUnknown(message);
+  }
+  const handler = handlers[message.type] as (message: DecodedMessage<T>) => R;
+  return handler(message);
+}
@@ -0,0 +1,269 @@
+/**
+ * PROXY protocol (v1 and v2) header parsing
+ * Recovers the original client address when running behind a load balancer
+ */
+
+import { isIP } from "net";
+import type { Duplex } from "stream";
+import { ClavisError, MessageError, StreamError } from "./error.js";
+import { systemClock, type Clock } from "./clock.js";
+
+/**
+ * Source/destination addresses carried by a PROXY header
+ */
+export interface ProxyHeader {
+  /** Protocol version of the header (1 or 2) */
+  version: 1 | 2;
+  /** Address family, or "UNKNOWN" for LOCAL/UNKNOWN headers */
+  family: "TCP4" | "TCP6" | "UNKNOWN";
+  sourceAddress?: string | undefined;
+  sourcePort?: number | undefined;
+  destinationAddress?: string | undefined;
+  destinationPort?: number | undefined;
+  /** Host name the client asked the proxy for (v2 `PP2_TYPE_AUTHORITY`, usually the TLS SNI) */
+  authority?: string | undefined;
+}
+
+/** Result of parsing a PROXY header from the start of a buffer */
+export interface ProxyParseResult {
+  header: ProxyHeader;
+  /** Number of bytes the header occupied */
+  bytesRead: number;
+}
+
+const V2_SIGNATURE = new Uint8Array([0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a]);
+const V1_PREFIX = "PROXY ";
+const V1_MAX_LENGTH = 107;
+const DEFAULT_HEADER_TIMEOUT_MS = 5000;
+const PP2_TYPE_AUTHORITY = 0x02;
+
+function invalid(message: string): ClavisError {
+  return ClavisError.message(MessageError.invalidFormat(`PROXY header: ${message}`));
+}
+
+function startsWith(data: Uint8Array, prefix: Uint8Array): boolean {
+  const n = Math.min(data.length, prefix.length);
+  for (let i = 0; i < n; i++) {
+    if (data[i] !== prefix[i]) return false;
+  }
+  return true;
+}
+
+function parsePort(value: string): number {
+  const port = Number(value);
+  if (!/^\d+$/.test(value) || port > 0xffff) {
+    throw invalid(`invalid port "${value}"`);
+  }
+  return port;
+}
+
+function formatIpv6(data: Uint8Array, offset: number): string {
+  const groups: string[] = [];
+  for (let i = 0; i < 16; i += 2) {
+    groups.push(((data[offset + i]! << 8) | data[offset + i + 1]!).toString(16));
+  }
+  return groups.join(":");
+}
+
+/** Find the authority among the TLVs following the v2 addresses */
+function readAuthority(tlvs: Uint8Array): string | undefined {
+  let offset = 0;
+  while (offset < tlvs.length) {
+    if (offset + 3 > tlvs.length) throw invalid("truncated v2 TLV");
+    const type = tlvs[offset]!;
+    const length = (tlvs[offset + 1]! << 8) | tlvs[offset + 2]!;
+    const end = offset + 3 + length;
+    if (end > tlvs.length) throw invalid("truncated v2 TLV");
+    if (type === PP2_TYPE_AUTHORITY) {
+      return new TextDecoder().decode(tlvs.subarray(offset + 3, end));
+    }
+    offset = end;
+  }
+  return undefined;
+}
+
+function parseV1(data: Uint8Array): ProxyParseResult | undefined {
+  // Look for the terminating CRLF within the maximum header length
+  let end = -1;
+  const limit = Math.min(data.length, V1_MAX_LENGTH);
+  for (let i = 1; i < limit; i++) {
+    if (data[i - 1] === 0x0d && data[i] === 0x0a) {
+      end = i + 1;
+      break;
+    }
+  }
+  if (end === -1) {
+    if (data.length >= V1_MAX_LENGTH) {
+      throw invalid("v1 header exceeds 107 bytes");
+    }
+    return undefined;
+  }
+
+  const line = new TextDecoder().decode(data.subarray(0, end - 2));
+  const parts = line.split(" ");
+  const family = parts[1];
+
+  if (family === "UNKNOWN") {
+    return { header: { version: 1, family: "UNKNOWN" }, bytesRead: end };
+  }
+  if ((family !== "TCP4" && family !== "TCP6") || parts.length !== 6) {
+    throw invalid(`malformed v1 header "${line}"`);
+  }
+  // The source address becomes the peer's rate-limit key, so it has to be an address
+  const ipVersion = family === "TCP4" ? 4 : 6;
+  if (isIP(parts[2]!) !== ipVersion || isIP(parts[3]!) !== ipVersion) {
+    throw invalid(`v1 header addresses are not ${family} addresses: "${line}"`);
+  }
+
+  return {
+    header: {
+      version: 1,
+      family,
+      sourceAddress: parts[2],
+      destinationAddress: parts[3],
+      sourcePort: parsePort(parts[4]!),
+      destinationPort: parsePort(parts[5]!),
+    },
+    bytesRead: end,
+  };
+}
+
+function parseV2(data: Uint8Array): ProxyParseResult | undefined {
+  if (data.length < 16) return undefined;
+
+  const versionCommand = data[12]!;
+  if (versionCommand >> 4 !== 2) {
+    throw invalid(`unsupported v2 version ${versionCommand >> 4}`);
+  }
+  const command = versionCommand & 0x0f;
+  const familyProtocol = data[13]!;
+  const length = (data[14]! << 8) | data[15]!;
+  const total = 16 + length;
+  if (data.length < total) return undefined;
+
+  // LOCAL command (health checks from the proxy itself) or unsupported family
+  if (command === 0x0) {
+    return { header: { version: 2, family: "UNKNOWN" }, bytesRead: total };
+  }
+  if (command !== 0x1) {
+    throw invalid(`unsupported v2 command ${command}`);
+  }
+
+  if (familyProtocol === 0x11) {
+    if (length < 12) throw invalid("truncated v2 IPv4 addresses");
+    const body = data.subarray(16, total);
+    const header: ProxyHeader = {
+      version: 2,
+      family: "TCP4",
+      sourceAddress: `${body[0]}.${body[1]}.${body[2]}.${body[3]}`,
+      destinationAddress: `${body[4]}.${body[5]}.${body[6]}.${body[7]}`,
+      sourcePort: (body[8]! << 8) | body[9]!,
+      destinationPort: (body[10]! << 8) | body[11]!,
+    };
+    const authority = readAuthority(body.subarray(12));
+    if (authority !== undefined) header.authority = authority;
+    return { header, bytesRead: total };
+  }
+
+  if (familyProtocol === 0x21) {
+    if (length < 36) throw invalid("truncated v2 IPv6 addresses");
+    const header: ProxyHeader = {
+      version: 2,
+      family: "TCP6",
+      sourceAddress: formatIpv6(data, 16),
+      destinationAddress: formatIpv6(data, 32),
+      sourcePort: (data[48]! << 8) | data[49]!,
+      destinationPort: (data[50]! << 8) | data[51]!,
+    };
+    const authority = readAuthority(data.subarray(52, total));
+    if (authority !== undefined) header.authority = authority;
+    return { header, bytesRead: total };
+  }
+
+  return { header: { version: 2, family: "UNKNOWN" }, bytesRead: total };
+}
+
+/**
+ * Parse a PROXY header (v1 or v2) from the start of `data`.
+ * Returns undefined if more bytes are needed.
+ * Throws if the data is not a valid PROXY header.
+ */
+export function parseProxyHeader(data: Uint8Array): ProxyParseResult | undefined {
+  if (data.length === 0) return undefined;
+
+  if (startsWith(data, V2_SIGNATURE)) {
+    return data.length < V2_SIGNATURE.length ? undefined : parseV2(data);
+  }
+
+  const prefix = new TextEncoder().encode(V1_PREFIX);
+  if (startsWith(data, prefix)) {
+    return data.length < prefix.length ? undefined : parseV1(data);
+  }
+
+  throw invalid("missing PROXY signature");
+}
+
+/**
+ * Read a PROXY header from a freshly accepted socket.
+ * Bytes following the header are pushed back onto the socket, which is
+ * left paused so no application data is lost before the handshake attaches.
+ */
+export function readProxyHeader(
+  socket: Duplex,
+  timeoutMs: number = DEFAULT_HEADER_TIMEOUT_MS,
+  clock: Clock = systemClock
+): Promise<ProxyHeader> {
+  return new Promise((resolve, reject) => {
+    let buffered = new Uint8Array(0);
+
+    const cleanup = () => {
+      timer.cancel();
+      socket.off("data", onData);
+      socket.off("end", onEnd);
+      socket.off("error", onError);
+      socket.pause();
+    };
+
+    const timer = clock.setTimer(() => {
+      cleanup();
+      reject(ClavisError.stream(StreamError.timeout(timeoutMs)));
+    }, timeoutMs);
+
+    const onData = (chunk: Buffer) => {
+      const next = new Uint8Array(buffered.length + chunk.length);
+      next.set(buffered, 0);
+      next.set(chunk, buffered.length);
+      buffered = next;
+
+      let result: ProxyParseResult | undefined;
+      try {
+        result = parseProxyHeader(buffered);
+      } catch (error) {
+        cleanup();
+        reject(error);
+        return;
+      }
+      if (!result) return;
+
+      cleanup();
+      if (result.bytesRead < buffered.length) {
+        socket.unshift(Buffer.from(buffered.subarray(result.bytesRead)));
+      }
+      resolve(result.header);
+    };
+
+    const onEnd = () => {
+      cleanup();
+      reject(ClavisError.stream(StreamError.unexpectedClose()));
+    };
+
+    const onError = (error: Error) => {
+      cleanup();
+      reject(ClavisError.stream(StreamError.io(error)));
+    };
+
+    socket.on("data", onData);
+    socket.once("end", onEnd);
+    socket.once("error", onError);
+  });
+}
@@ -0,0 +1,240 @@
+/**
+ * QUIC transport
+ * Encrypted streams over the bidirectional streams of a QUIC connection,
+ * one logical clavis channel per QUIC stream
+ *
+ * QUIC already encrypts its streams, but with the TLS identity of the
+ * endpoint; clavis on top keeps the handshake, the identities and the
+ * packet types an application used over TCP, so a service can change
+ * transports without changing what it authenticates or what it sends.
+ *
+ * A `QuicSession` runs one full handshake, with the caller's options, on a
+ * control stream the connecting side opens first. Every other QUIC stream
+ * is a channel: its opener writes the channel ID (u32 little-endian) in the
+ * clear, then both sides run a handshake keyed with a PSK exported from the
+ * control stream for that ID:
+ *   channel psk = control.exportKeyingMaterial("clavis quic channel", u32 ID)
+ * so only the peer that completed the control handshake can open channels,
+ * and each channel inherits its identity. Streams are independent in QUIC,
+ * so a lost packet on one channel doesn't hold up the others.
+ *
+ * Nothing here binds to a particular QUIC stack: any connection that opens
+ * and accepts bidirectional streams as web streams fits, which is the shape
+ * of a WebTransport session and easy to adapt Node's `node:quic` or a
+ * native binding to.
+ */
+
+import { EncryptedStream, type EncryptedStreamOptions } from "./stream.js";
+import { ClavisError, StreamError } from "./error.js";
+import { transportStream, webStreamTransport } from "./web.js";
+import type { PeerIdentity } from "./identity.js";
+
+/** Label of the exporter that keys each channel */
+export const QUIC_CHANNEL_LABEL = "clavis quic channel";
+
+/** Length of the channel ID written ahead of each channel's handshake */
+export const QUIC_CHANNEL_HEADER_LENGTH = 4;
+
+/**
+ * One bidirectional QUIC stream, as a pair of web streams
+ */
+export interface QuicBidirectionalStream {
+  readable: ReadableStream<Uint8Array>;
+  writable: WritableStream<Uint8Array>;
+}
+
+/**
+ * The parts of a QUIC connection a session uses; a WebTransport session fits
+ */
+export interface QuicConnectionLike {
+  /** Open a new bidirectional stream to the peer */
+  createBidirectionalStream(): Promise<QuicBidirectionalStream>;
+  /** Bidirectional streams the peer opens, in the order they arrive */
+  readonly incomingBidirectionalStreams: ReadableStream<QuicBidirectionalStream>;
+  /** Close the connection (optional) */
+  close?(): void;
+}
+
+/**
+ * Options for `QuicSession.connect` and `QuicSession.accept`
+ */
+export interface QuicSessionOptions extends EncryptedStreamOptions {
+  /**
+   * Options for channel streams (default: none). Their key is exported from
+   * the control stream, so the PSK options don't apply.
+   */
+  channelOptions?: Omit<EncryptedStreamOptions, "psk" | "psks" | "pskIdentity"> | undefined;
+}
+
+/**
+ * A logical channel: its ID and the encrypted stream on its QUIC stream
+ */
+export interface QuicChannel {
+  id: number;
+  stream: EncryptedStream;
+}
+
+/**
+ * Run an encrypted stream over one bidirectional QUIC stream, with a
+ * handshake of its own
+ *
+ * @example
+ * ```typescript
+ * const stream = await quicStream(await transport.createBidirectionalStream(), { psk });
+ * ```
+ */
+export async function quicStream(
+  bidi: QuicBidirectionalStream,
+  options?: EncryptedStreamOptions
+): Promise<EncryptedStream> {
+  return EncryptedStream.new(await transportStream(webStreamTransport(bidi)), options);
+}
+
+function checkChannelId(id: number): void {
+  if (!Number.isInteger(id) || id < 1 || id > 0xffffffff) {
+    throw ClavisError.invalidOperation(`Channel ID must be a u32 other than 0, got ${id}`);
+  }
+}
+
+function encodeChannelId(id: number): Uint8Array {
+  const header = new Uint8Array(QUIC_CHANNEL_HEADER_LENGTH);
+  new DataView(header.buffer).setUint32(0, id, true);
+  return header;
+}
+
+/**
+ * Read a channel's header, returning its ID and the stream with whatever
+ * followed the header still in front
+ */
+async function readChannelHeader(bidi: QuicBidirectionalStream): Promise<[number, QuicBidirectionalStream]> {
+  const reader = bidi.readable.getReader();
+  let buffered = new Uint8Array(0);
+  while (buffered.length < QUIC_CHANNEL_HEADER_LENGTH) {
+    const { value, done } = await reader.read();
+    if (done) {
+      throw ClavisError.stream(StreamError.handshakeFailed("QUIC stream ended before its channel header"));
+    }
+    const joined = new Uint8Array(buffered.length + value.length);
+    joined.set(buffered, 0);
+    joined.set(value, buffered.length);
+    buffered = joined;
+  }
+  const id = new DataView(buffered.buffer).getUint32(0, true);
+  const rest = buffered.subarray(QUIC_CHANNEL_HEADER_LENGTH);
+  const readable = new ReadableStream<Uint8Array>({
+    start(controller) {
+      if (rest.length > 0) controller.enqueue(rest);
+    },
+    async pull(controller) {
+      const { value, done } = await reader.read();
+      if (done) controller.close();
+      else controller.enqueue(value);
+    },
+    cancel: (reason) => reader.cancel(reason),
+  });
+  return [id, { readable, writable: bidi.writable }];
+}
+
+/**
+ * Logical clavis channels over one QUIC connection, sharing the identity
+ * established on its control stream
+ *
+ * @example
+ * ```typescript
+ * // Client
+ * const session = await QuicSession.connect(new WebTransport(url), { psk, identity });
+ * const { stream } = await session.openChannel(2);
+ * await stream.writePacket(Packet.Upload({ name }));
+ *
+ * // Server
+ * const session = await QuicSession.accept(connection, { psk, verifyPeer });
+ * for await (const { id, stream } of session) serve(id, stream);
+ * ```
+ */
+export class QuicSession implements AsyncIterable<QuicChannel> {
+  private constructor(
+    private readonly connection: QuicConnectionLike,
+    /** The stream the session's handshake ran on, usable for packets of its own */
+    readonly control: EncryptedStream,
+    private readonly channelOptions: QuicSessionOptions["channelOptions"],
+    private readonly incoming: ReadableStreamDefaultReader<QuicBidirectionalStream>
+  ) {}
+
+  /**
+   * Open the control stream on `connection` and run the session's handshake
+   */
+  static async connect(connection: QuicConnectionLike, options: QuicSessionOptions = {}): Promise<QuicSession> {
+    const { channelOptions, ...streamOptions } = options;
+    const control = await quicStream(await connection.createBidirectionalStream(), streamOptions);
+    return new QuicSession(connection, control, channelOptions, connection.incomingBidirectionalStreams.getReader());
+  }
+
+  /**
+   * Wait for the peer's control stream on `connection` and run the session's handshake
+   */
+  static async accept(connection: QuicConnectionLike, options: QuicSessionOptions = {}): Promise<QuicSession> {
+    const { channelOptions, ...streamOptions } = options;
+    const incoming = connection.incomingBidirectionalStreams.getReader();
+    const { value, done } = await incoming.read();
+    if (done) {
+      throw ClavisError.stream(StreamError.connectionClosed("QUIC connection closed before the control stream"));
+    }
+    const control = await quicStream(value, streamOptions);
+    return new QuicSession(connection, control, channelOptions, incoming);
+  }
+
+  /** Identity the peer proved on the control stream, if identities are in use */
+  get peerIdentity(): PeerIdentity | undefined {
+    return this.control.peerIdentity;
+  }
+
+  /**
+   * Open channel `id` (a u32 other than 0) on a new QUIC stream. IDs are
+   * labels for the application, so the two sides should split them, for
+   * example even IDs for the client and odd ones for the server.
+   */
+  async openChannel(id: number): Promise<QuicChannel> {
+    checkChannelId(id);
+    const bidi = await this.connection.createBidirectionalStream();
+    const writer = bidi.writable.getWriter();
+    await writer.write(encodeChannelId(id));
+    writer.releaseLock();
+    return { id, stream: await quicStream(bidi, this.optionsFor(id)) };
+  }
+
+  /**
+   * Next channel the peer opens, or undefined once the connection accepts
+   * no more streams. Rejects if a channel's handshake fails; later channels
+   * can still be accepted.
+   */
+  async acceptChannel(): Promise<QuicChannel | undefined> {
+    const { value, done } = await this.incoming.read();
+    if (done) return undefined;
+    const [id, bidi] = await readChannelHeader(value);
+    if (id === 0) {
+      throw ClavisError.stream(StreamError.handshakeFailed("Peer opened a second control stream"));
+    }
+    return { id, stream: await quicStream(bidi, this.optionsFor(id)) };
+  }
+
+  /** Channels the peer opens, until the connection accepts no more streams */
+  async *[Symbol.asyncIterator](): AsyncIterableIterator<QuicChannel> {
+    for (let channel = await this.acceptChannel(); channel; channel = await this.acceptChannel()) {
+      yield channel;
+    }
+  }
+
+  /** Close the control stream, then the connection */
+  async close(): Promise<void> {
+    await this.control.close().catch(() => undefined);
+    this.incoming.cancel().catch(() => undefined);
+    this.connection.close?.();
+  }
+
+  private optionsFor(id: number): EncryptedStreamOptions {
+    return {
+      ...this.channelOptions,
+      psk: this.control.exportKeyingMaterial(QUIC_CHANNEL_LABEL, encodeChannelId(id)),
+    };
+  }
+}
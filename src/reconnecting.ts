@@ -0,0 +1,361 @@
+/**
+ * Reconnecting stream
+ * An encrypted stream that survives its connection: when the transport
+ * fails, it dials again through a connect factory, runs a new handshake and
+ * carries on reading and writing on the new connection
+ *
+ * Packets in flight when a connection drops are lost: a write that fails
+ * rejects and is not sent again, and whatever the peer had sent but this
+ * side had not read is gone. Writes made while reconnecting can wait in a
+ * small buffer and go out first on the new connection. Protocols that
+ * can't lose packets need acknowledgements of their own.
+ */
+
+import { EventEmitter } from "events";
+import type { Duplex } from "stream";
+import { EncryptedStream, type EncryptedStreamOptions } from "./stream.js";
+import { ClavisError, StreamError, StreamErrorCode } from "./error.js";
+import type { PacketTrait } from "./protocol.js";
+import type { SessionTicket } from "./resumption.js";
+import { Backoff, type RetryPolicy } from "./backoff.js";
+import { sleepOn, systemClock, type Clock } from "./clock.js";
+import { corruptionKind } from "./corruption.js";
+
+/**
+ * Options for `ReconnectingEncryptedStream.connect()`
+ */
+export interface ReconnectingStreamOptions {
+  /** Open a fresh transport to the peer; called for the first connection and every redial */
+  connect: () => Promise<Duplex>;
+  /** Options of every connection (default: none) */
+  streamOptions?: EncryptedStreamOptions | undefined;
+  /**
+   * Redial delays and how many redials to try after each drop before giving
+   * up (default: 5 retries from 1 second, doubling up to 30 seconds, ±10%)
+   */
+  retry?: RetryPolicy | undefined;
+  /**
+   * Writes that may wait while reconnecting; further writes reject until
+   * the connection is back (default: 0, every write made while
+   * reconnecting rejects)
+   */
+  bufferPackets?: number | undefined;
+  /**
+   * Present the last connection's session ticket when redialing
+   * (default: false). The server must issue tickets.
+   */
+  resumeSessions?: boolean | undefined;
+  /**
+   * Whether an error means the connection is gone (default:
+   * `isConnectionLost`, plus corrupt frames on the read side)
+   */
+  reconnectOn?: ((error: unknown) => boolean) | undefined;
+  /** Time source for redial delays (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+/**
+ * How a dropped connection came back, for tracking session continuity
+ */
+export interface ReconnectInfo {
+  /** The new connection */
+  stream: EncryptedStream;
+  /**
+   * Whether the session was resumed from the last connection's ticket,
+   * rather than set up with a full handshake
+   */
+  resumed: boolean;
+  /** Milliseconds from noticing the drop to the new connection being up */
+  downtimeMs: number;
+  /** Dials it took, the successful one included */
+  attempts: number;
+  /** Writes buffered while reconnecting and sent first on the new connection */
+  flushedPackets: number;
+}
+
+/**
+ * Events emitted by ReconnectingEncryptedStream
+ */
+export interface ReconnectingStreamEvents {
+  /** A new connection is up, after a drop */
+  reconnect: [info: ReconnectInfo];
+  /** The connection dropped; redialing starts */
+  disconnect: [error: unknown];
+  /** Waiting `delayMs` before redial number `attempt` */
+  reconnecting: [attempt: number, delayMs: number];
+  /** The stream stopped for good: closed, or out of redials */
+  close: [reason: ClavisError];
+}
+
+/**
+ * Type-safe event emitter interface
+ */
+export interface ReconnectingStreamEmitter {
+  on<K extends keyof ReconnectingStreamEvents>(event: K, listener: (...args: ReconnectingStreamEvents[K]) => void): this;
+  once<K extends keyof ReconnectingStreamEvents>(event: K, listener: (...args: ReconnectingStreamEvents[K]) => void): this;
+  off<K extends keyof ReconnectingStreamEvents>(event: K, listener: (...args: ReconnectingStreamEvents[K]) => void): this;
+  emit<K extends keyof ReconnectingStreamEvents>(event: K, ...args: ReconnectingStreamEvents[K]): boolean;
+}
+
+const DEFAULT_RETRY: RetryPolicy = {
+  maxAttempts: 6,
+  initialDelayMs: 1000,
+  maxDelayMs: 30000,
+  jitter: 0.1,
+};
+
+/**
+ * Default `reconnectOn`: the transport closed, reset or failed, the peer
+ * closed the session or stopped answering, or a frame failed to decrypt.
+ * Timeouts of single reads and errors about the packet itself don't count.
+ */
+export function isConnectionLost(error: unknown): boolean {
+  const cause = error instanceof ClavisError ? error.cause : error;
+  if (!(cause instanceof StreamError)) return false;
+  return cause.isConnectionClosed()
+    || cause.code === StreamErrorCode.IOError
+    || cause.code === StreamErrorCode.DecryptionFailed;
+}
+
+interface Connection {
+  stream: EncryptedStream;
+  transport: Duplex;
+}
+
+interface BufferedWrite {
+  packet: PacketTrait;
+  resolve: (written: number) => void;
+  reject: (error: unknown) => void;
+}
+
+/**
+ * An encrypted stream that redials and re-handshakes when its connection
+ * drops, for clients that would otherwise write this loop themselves
+ *
+ * Reads that hit a lost connection wait for the next one and read from it;
+ * they reject only once the redials are used up or the stream is closed.
+ *
+ * @example
+ * ```typescript
+ * const stream = await ReconnectingEncryptedStream.connect({
+ *   connect: () => connectTcp("api.example.com", 7272),
+ *   streamOptions: { psk },
+ *   retry: { maxAttempts: Infinity, initialDelayMs: 500, jitter: "full" },
+ *   bufferPackets: 32,
+ * });
+ * stream.on("reconnect", ({ resumed, downtimeMs }) => metrics.reconnect(resumed, downtimeMs));
+ *
+ * await stream.writePacket(Message.Subscribe(topic));
+ * for (;;) handle(await stream.readPacket());
+ * ```
+ */
+export class ReconnectingEncryptedStream extends EventEmitter implements ReconnectingStreamEmitter {
+  private current: Connection | undefined;
+  private reconnecting: Promise<Connection> | undefined;
+  private readonly buffer: BufferedWrite[] = [];
+  private readonly backoff: Backoff;
+  private readonly bufferPackets: number;
+  private readonly reconnectOn: (error: unknown) => boolean;
+  private readonly clock: Clock;
+  private sessionTicket: SessionTicket | undefined;
+  private closedReason: ClavisError | undefined;
+  private redials = 0;
+  private resumedRedials = 0;
+
+  private constructor(private readonly options: ReconnectingStreamOptions) {
+    super();
+    this.bufferPackets = options.bufferPackets ?? 0;
+    if (!Number.isInteger(this.bufferPackets) || this.bufferPackets < 0) {
+      throw ClavisError.config(`bufferPackets must be a non-negative integer, got ${this.bufferPackets}`);
+    }
+    this.clock = options.clock ?? systemClock;
+    this.backoff = new Backoff(options.retry ?? DEFAULT_RETRY, this.clock);
+    this.reconnectOn = options.reconnectOn ?? isConnectionLost;
+  }
+
+  /**
+   * Make the first connection. Rejects if it fails; redialing only covers
+   * connections that were up.
+   */
+  static async connect(options: ReconnectingStreamOptions): Promise<ReconnectingEncryptedStream> {
+    const stream = new ReconnectingEncryptedStream(options);
+    stream.current = await stream.dial();
+    return stream;
+  }
+
+  /** The current connection's stream; undefined while reconnecting */
+  get stream(): EncryptedStream | undefined {
+    return this.current?.stream;
+  }
+
+  /** Whether a connection is up */
+  get isConnected(): boolean {
+    return this.current !== undefined;
+  }
+
+  /** Whether the stream has stopped for good */
+  get isClosed(): boolean {
+    return this.closedReason !== undefined;
+  }
+
+  /** Successful redials so far */
+  get reconnects(): number {
+    return this.redials;
+  }
+
+  /** Successful redials so far that resumed the session from a ticket */
+  get resumedReconnects(): number {
+    return this.resumedRedials;
+  }
+
+  /** Writes waiting for the connection to come back */
+  get bufferedPackets(): number {
+    return this.buffer.length;
+  }
+
+  /**
+   * Write a packet on the current connection. While reconnecting it waits
+   * in the buffer if there is room, and rejects otherwise. A write that
+   * fails because the connection dropped rejects; it is not sent again.
+   */
+  async writePacket(packet: PacketTrait): Promise<number> {
+    if (this.closedReason) throw this.closedReason;
+    const connection = this.current;
+    if (!connection) return this.enqueue(packet);
+    try {
+      return await connection.stream.writePacket(packet);
+    } catch (error) {
+      if (this.reconnectOn(error)) this.lost(connection, error).catch(() => {});
+      throw error;
+    }
+  }
+
+  /**
+   * Read the next packet, from the next connection if this one drops.
+   * Rejects once the stream is closed or out of redials.
+   */
+  async readPacket<P extends PacketTrait>(): Promise<P> {
+    for (;;) {
+      if (this.closedReason) throw this.closedReason;
+      const connection = this.current ?? (await this.reconnecting!);
+      try {
+        return await connection.stream.readPacket<P>();
+      } catch (error) {
+        if (!this.reconnectOn(error) && corruptionKind(error) === undefined) throw error;
+        await this.lost(connection, error);
+      }
+    }
+  }
+
+  /**
+   * Stop for good: close the current connection, stop redialing and
+   * reject buffered writes
+   */
+  async close(): Promise<void> {
+    if (this.closedReason) return;
+    const connection = this.current;
+    this.fail(ClavisError.stream(StreamError.connectionClosed("Reconnecting stream closed")));
+    if (connection) {
+      await connection.stream.close().catch(() => {});
+      connection.transport.destroy();
+    }
+  }
+
+  private async dial(): Promise<Connection> {
+    const transport = await this.options.connect();
+    try {
+      const stream = await EncryptedStream.new(transport, {
+        ...this.options.streamOptions,
+        ...(this.options.resumeSessions && { resumption: this.sessionTicket ?? true }),
+      });
+      this.sessionTicket = stream.sessionTicket ?? this.sessionTicket;
+      return { stream, transport };
+    } catch (error) {
+      transport.destroy();
+      throw error;
+    }
+  }
+
+  /** Start redialing after `connection` dropped, unless that is already underway */
+  private lost(connection: Connection, error: unknown): Promise<Connection> {
+    if (this.closedReason) return Promise.reject(this.closedReason);
+    if (connection !== this.current) {
+      return this.reconnecting ?? Promise.resolve(this.current!);
+    }
+    this.current = undefined;
+    connection.transport.destroy();
+    this.emit("disconnect", error);
+    this.reconnecting = this.redial(error).finally(() => {
+      this.reconnecting = undefined;
+    });
+    return this.reconnecting;
+  }
+
+  private async redial(cause: unknown): Promise<Connection> {
+    const droppedAt = this.clock.now();
+    let attempts = 0;
+    for (;;) {
+      const delay = this.backoff.next();
+      if (delay === undefined) {
+        const reason = cause instanceof ClavisError
+          ? cause
+          : ClavisError.stream(StreamError.connectionClosed(`Gave up reconnecting: ${String(cause)}`));
+        this.fail(reason);
+        throw reason;
+      }
+      this.emit("reconnecting", this.backoff.retries, delay);
+      await sleepOn(this.clock, delay);
+      if (this.closedReason) throw this.closedReason;
+
+      let connection: Connection;
+      attempts++;
+      try {
+        connection = await this.dial();
+      } catch (error) {
+        cause = error;
+        continue;
+      }
+      if (this.closedReason) {
+        connection.transport.destroy();
+        throw this.closedReason;
+      }
+      this.backoff.succeeded();
+      this.redials++;
+      const { stream } = connection;
+      if (stream.resumed) this.resumedRedials++;
+      // Buffered writes go first: they are queued on the stream before anyone else can write
+      const flushed = this.buffer.splice(0);
+      for (const write of flushed) {
+        stream.writePacket(write.packet).then(write.resolve, write.reject);
+      }
+      this.current = connection;
+      this.emit("reconnect", {
+        stream,
+        resumed: stream.resumed,
+        downtimeMs: this.clock.now() - droppedAt,
+        attempts,
+        flushedPackets: flushed.length,
+      });
+      return connection;
+    }
+  }
+
+  private enqueue(packet: PacketTrait): Promise<number> {
+    if (this.buffer.length >= this.bufferPackets) {
+      return Promise.reject(ClavisError.stream(StreamError.connectionClosed(
+        `Reconnecting, and ${this.buffer.length} writes are already waiting`
+      )));
+    }
+    return new Promise((resolve, reject) => {
+      this.buffer.push({ packet, resolve, reject });
+    });
+  }
+
+  private fail(reason: ClavisError): void {
+    if (this.closedReason) return;
+    this.closedReason = reason;
+    this.current = undefined;
+    for (const write of this.buffer.splice(0)) write.reject(reason);
+    this.emit("close", reason);
+  }
+}
@@ -0,0 +1,267 @@
+/**
+ * Session rekeying
+ * Key derivation, payloads and the exchange that rolls a connection's keys in band
+ *
+ * A rekey is a fresh X25519 exchange over control frames. The side that
+ * starts it sends its ephemeral public key (RekeyRequest); the peer answers
+ * with its own (RekeyResponse) and switches its sending key right after the
+ * answer. The starter switches its reading key on the answer, sends
+ * RekeyConfirm as its last frame under the old key and switches its sending
+ * key; the peer switches its reading key on the confirmation. Every frame is
+ * therefore opened with the key it was sealed with and nothing is dropped.
+ *
+ * New keys come from the exchange and a chain secret carried over from the
+ * previous keys (initially the handshake's resumption secret), so a rekey
+ * both adds forward secrecy and stays bound to the authenticated session.
+ */
+
+import { computeSharedSecret, generateX25519KeyPair, hkdfExpand, sha256Hash, type X25519KeyPair } from "./crypto.js";
+import { ClavisError, MessageError } from "./error.js";
+import { ControlFrameKind, type ControlChannel } from "./control.js";
+import type { Clock } from "./clock.js";
+
+/** Length of a rekey payload: one X25519 public key */
+export const REKEY_KEY_LENGTH = 32;
+
+/**
+ * Keys for both directions after a rekey, plus the chain secret for the next one
+ */
+export interface RekeyKeys {
+  /** Key the side that started the rekey sends with */
+  initiatorKey: Uint8Array;
+  /** Key the answering side sends with */
+  responderKey: Uint8Array;
+  chain: Uint8Array;
+}
+
+/**
+ * Derive the next keys from the chain secret and the ephemeral exchange
+ */
+export function deriveRekeyKeys(
+  chain: Uint8Array,
+  sharedSecret: Uint8Array,
+  initiatorPublic: Uint8Array,
+  responderPublic: Uint8Array
+): RekeyKeys {
+  const salt = new Uint8Array(chain.length + initiatorPublic.length + responderPublic.length);
+  salt.set(chain, 0);
+  salt.set(initiatorPublic, chain.length);
+  salt.set(responderPublic, chain.length + initiatorPublic.length);
+  const digest = sha256Hash(salt);
+  return {
+    initiatorKey: hkdfExpand(sharedSecret, digest, "clavis-rekey-initiator"),
+    responderKey: hkdfExpand(sharedSecret, digest, "clavis-rekey-responder"),
+    chain: hkdfExpand(sharedSecret, digest, "clavis-rekey-chain"),
+  };
+}
+
+/** Public key carried by a RekeyRequest or RekeyResponse */
+export function decodeRekeyKey(payload: Uint8Array): Uint8Array {
+  if (payload.length !== REKEY_KEY_LENGTH) {
+    throw ClavisError.message(MessageError.invalidFormat("Rekey payload must be a 32-byte public key"));
+  }
+  return payload.slice();
+}
+
+/**
+ * When both peers start a rekey at once, the request with the lower public
+ * key goes ahead and the other side answers it instead
+ */
+export function winsRekeyTie(ours: Uint8Array, theirs: Uint8Array): boolean {
+  for (let i = 0; i < Math.min(ours.length, theirs.length); i++) {
+    if (ours[i]! !== theirs[i]!) return ours[i]! < theirs[i]!;
+  }
+  return ours.length < theirs.length;
+}
+
+/**
+ * What a rekey needs from its stream besides sending control frames
+ */
+export interface RekeyHost extends ControlChannel {
+  /** Seal every frame from here on with `key` */
+  useSendingKey(key: Uint8Array): void;
+  /** Open every frame from here on with `key` */
+  useReceivingKey(key: Uint8Array): void;
+  /** Called with the keys of a rekey as soon as they are derived */
+  derived(sendingKey: Uint8Array, receivingKey: Uint8Array): void;
+}
+
+/** Thresholds that start a rekey on their own; read on every count, so they can change */
+export interface RekeyPolicy {
+  readonly rekeyAfterBytes: number | undefined;
+  readonly rekeyAfterMs: number | undefined;
+}
+
+interface PendingRekey {
+  keyPair: X25519KeyPair;
+  done: Promise<void>;
+  resolve: () => void;
+  reject: (error: unknown) => void;
+  /** The peer started a rekey at the same time and its request went ahead */
+  lost: boolean;
+}
+
+/**
+ * Rekey state of one connection: the exchange under way, if any, and the
+ * bytes and time under the current keys that schedule the next one
+ */
+export class RekeyMachine {
+  private pending: PendingRekey | undefined;
+  /** Reading key the peer switches to once it confirms the rekey we answered */
+  private nextReceivingKey: Uint8Array | undefined;
+  /** Rekeys only start on their own once `arm()` was called */
+  private armed = false;
+  private keyedBytes = 0;
+  private keyedAt: number;
+
+  constructor(
+    /** Secret the next rekey's keys are chained to */
+    private chain: Uint8Array,
+    /** Completed rekeys, whichever side started them */
+    public count: number,
+    private readonly policy: RekeyPolicy,
+    private readonly clock: Clock,
+    private readonly host: RekeyHost
+  ) {
+    this.keyedAt = clock.now();
+  }
+
+  /** Secret the next rekey's keys are chained to */
+  get chainSecret(): Uint8Array {
+    return this.chain;
+  }
+
+  /** Whether an exchange is under way */
+  get busy(): boolean {
+    return this.pending !== undefined || this.nextReceivingKey !== undefined;
+  }
+
+  /** Let the policy start rekeys from here on */
+  arm(): void {
+    this.armed = true;
+  }
+
+  /**
+   * Send a rekey request, or join the rekey under way. Resolves once both
+   * directions use the new keys.
+   */
+  async start(): Promise<void> {
+    if (this.pending) return this.pending.done;
+
+    const keyPair = generateX25519KeyPair();
+    let resolve!: () => void;
+    let reject!: (error: unknown) => void;
+    const done = new Promise<void>((res, rej) => {
+      resolve = res;
+      reject = rej;
+    });
+    this.pending = { keyPair, done, resolve, reject, lost: false };
+
+    try {
+      await this.host.request(ControlFrameKind.RekeyRequest, keyPair.publicKey);
+    } catch (error) {
+      this.pending = undefined;
+      throw error;
+    }
+    return done;
+  }
+
+  /** Count bytes under the current keys and start a rekey once the policy says so */
+  countKeyed(bytes: number): void {
+    this.keyedBytes += bytes;
+    if (!this.armed || this.busy) return;
+    const { rekeyAfterBytes, rekeyAfterMs } = this.policy;
+    const due =
+      (rekeyAfterBytes !== undefined && this.keyedBytes >= rekeyAfterBytes) ||
+      (rekeyAfterMs !== undefined && this.clock.now() - this.keyedAt >= rekeyAfterMs);
+    if (due) {
+      // A failed rekey means the connection failed; its reads and writes report that
+      this.start().catch(() => undefined);
+    }
+  }
+
+  /**
+   * Handle a rekey control frame. Key switches happen right after the frame
+   * that announces them is handed to the transport, before anything else
+   * can be sealed.
+   */
+  async handle(kind: ControlFrameKind, payload: Uint8Array): Promise<void> {
+    const pending = this.pending;
+
+    switch (kind) {
+      case ControlFrameKind.RekeyRequest: {
+        const peerKey = decodeRekeyKey(payload);
+        if (pending && !pending.lost) {
+          // Both sides started one: answer the peer's only if its request goes ahead
+          if (winsRekeyTie(pending.keyPair.publicKey, peerKey)) return;
+          pending.lost = true;
+        }
+        if (this.nextReceivingKey) {
+          throw ClavisError.message(MessageError.invalidFormat("Rekey request before the last one was confirmed"));
+        }
+        const keyPair = generateX25519KeyPair();
+        const keys = deriveRekeyKeys(this.chain, computeSharedSecret(keyPair.secret, peerKey), peerKey, keyPair.publicKey);
+        this.chain = keys.chain;
+        this.host.derived(keys.responderKey, keys.initiatorKey);
+        this.nextReceivingKey = keys.initiatorKey;
+        const sent = this.host.answer(ControlFrameKind.RekeyResponse, keyPair.publicKey);
+        this.host.useSendingKey(keys.responderKey);
+        await sent;
+        return;
+      }
+
+      case ControlFrameKind.RekeyResponse: {
+        if (!pending || pending.lost) {
+          throw ClavisError.message(MessageError.invalidFormat("Rekey answer to a request never sent"));
+        }
+        const peerKey = decodeRekeyKey(payload);
+        const keys = deriveRekeyKeys(
+          this.chain,
+          computeSharedSecret(pending.keyPair.secret, peerKey),
+          pending.keyPair.publicKey,
+          peerKey
+        );
+        this.chain = keys.chain;
+        this.host.derived(keys.initiatorKey, keys.responderKey);
+        // The peer switched right after its answer
+        this.host.useReceivingKey(keys.responderKey);
+        const sent = this.host.answer(ControlFrameKind.RekeyConfirm, new Uint8Array(0));
+        this.host.useSendingKey(keys.initiatorKey);
+        this.pending = undefined;
+        this.rekeyed();
+        await sent.then(pending.resolve, (error: unknown) => {
+          pending.reject(error);
+          throw error;
+        });
+        return;
+      }
+
+      case ControlFrameKind.RekeyConfirm: {
+        const next = this.nextReceivingKey;
+        if (!next) {
+          throw ClavisError.message(MessageError.invalidFormat("Rekey confirmation without a rekey"));
+        }
+        this.host.useReceivingKey(next);
+        this.nextReceivingKey = undefined;
+        this.rekeyed();
+        if (pending?.lost) {
+          this.pending = undefined;
+          pending.resolve();
+        }
+        return;
+      }
+    }
+  }
+
+  /** Fail the rekey we started, once nothing more can arrive */
+  fail(error: unknown): void {
+    this.pending?.reject(error);
+    this.pending = undefined;
+  }
+
+  private rekeyed(): void {
+    this.count++;
+    this.keyedBytes = 0;
+    this.keyedAt = this.clock.now();
+  }
+}
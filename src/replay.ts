@@ -0,0 +1,96 @@
+/**
+ * Replay cache
+ * Remembers which session tickets have been redeemed, so each resumes at most once
+ *
+ * A server acting on resumed state (and any future early-data path) must not
+ * accept the same ticket twice. With a single server the in-memory cache is
+ * enough; a fleet sharing ticket keys needs a cache every instance sees:
+ * `StorageReplayCache` over a shared `Storage`, or the Redis-backed one in
+ * examples/redis-replay-cache.ts.
+ */
+
+import { ClavisError } from "./error.js";
+import { wallClock, type Clock } from "./clock.js";
+import type { Storage } from "./storage.js";
+
+/**
+ * Store of redeemed ticket ids
+ */
+export interface ReplayCache {
+  /**
+   * Record `key` until `expiresAt` (ms since the epoch). Resolves true if it
+   * was new, false if it had been recorded and not yet expired. Must be
+   * atomic across everything sharing the cache.
+   */
+  insert(key: string, expiresAt: number): boolean | Promise<boolean>;
+}
+
+/**
+ * Replay cache on top of any `Storage` backend, e.g. `FileStorage` to keep
+ * redeemed tickets across restarts or a shared database for a fleet
+ */
+export class StorageReplayCache implements ReplayCache {
+  constructor(
+    private readonly storage: Storage,
+    private readonly prefix: string = "replay/"
+  ) {}
+
+  insert(key: string, expiresAt: number): Promise<boolean> {
+    return this.storage.setIfAbsent(this.prefix + key, new Uint8Array(0), { expiresAt });
+  }
+}
+
+/**
+ * Options for the in-memory replay cache
+ */
+export interface MemoryReplayCacheOptions {
+  /**
+   * Entries kept at most (default: 100000). When full, new keys are refused
+   * as if replayed, so a flood degrades to full handshakes rather than replays.
+   */
+  maxEntries?: number | undefined;
+  /** Wall-clock time source (default: `wallClock`) */
+  clock?: Clock | undefined;
+}
+
+/**
+ * Replay cache for a single process
+ */
+export class MemoryReplayCache implements ReplayCache {
+  private readonly entries = new Map<string, number>();
+  private readonly maxEntries: number;
+  private readonly clock: Clock;
+
+  constructor(options?: MemoryReplayCacheOptions) {
+    this.maxEntries = options?.maxEntries ?? 100_000;
+    this.clock = options?.clock ?? wallClock;
+    if (!(this.maxEntries >= 1)) {
+      throw ClavisError.config("maxEntries must be at least 1");
+    }
+  }
+
+  insert(key: string, expiresAt: number): boolean {
+    const now = this.clock.now();
+    const existing = this.entries.get(key);
+    if (existing !== undefined && existing > now) {
+      return false;
+    }
+    if (this.entries.size >= this.maxEntries) {
+      this.prune(now);
+      if (this.entries.size >= this.maxEntries) return false;
+    }
+    this.entries.set(key, expiresAt);
+    return true;
+  }
+
+  /** Entries currently held */
+  get size(): number {
+    return this.entries.size;
+  }
+
+  private prune(now: number): void {
+    for (const [key, expiresAt] of this.entries) {
+      if (expiresAt <= now) this.entries.delete(key);
+    }
+  }
+}
@@ -0,0 +1,116 @@
+/**
+ * Max packet size changes
+ * The packet size limits of a connection and the control exchange that
+ * moves both peers to a new one
+ *
+ * The side that wants a new limit sends ResizeRequest and the peer answers
+ * with ResizeAccept or ResizeReject. Both directions switch together, so
+ * the peers never disagree on what fits in a packet.
+ */
+
+import { ClavisError, MessageError } from "./error.js";
+import { ControlFrameKind, decodeControlU32, encodeControlU32, type ControlChannel } from "./control.js";
+import { checkPacketSize } from "./stream-options.js";
+
+interface PendingResize {
+  size: number;
+  previousWriteLimit: number;
+  resolve: (size: number) => void;
+  reject: (error: ClavisError) => void;
+}
+
+/**
+ * Packet size limits of one connection and the resize under way, if any
+ */
+export class PacketLimits {
+  /** Largest ciphertext accepted from the peer */
+  read: number;
+  /** Largest serialized packet sent to the peer */
+  write: number;
+  /** Set when `write` is the receive limit the peer advertised */
+  writeFromPeer = false;
+  private pending: PendingResize | undefined;
+
+  constructor(
+    size: number,
+    /** Largest size the peer may ask for */
+    private readonly maxNegotiable: number,
+    private readonly channel: ControlChannel
+  ) {
+    this.read = size;
+    this.write = size;
+  }
+
+  /** Whether our request is waiting for its answer */
+  get busy(): boolean {
+    return this.pending !== undefined;
+  }
+
+  /**
+   * Ask the peer to switch both directions to `size`. Resolves once the
+   * peer agreed; rejects if it refused.
+   */
+  async request(size: number): Promise<number> {
+    checkPacketSize("Max packet size", size);
+    if (this.pending) {
+      throw ClavisError.invalidOperation("A max packet size change is already pending");
+    }
+
+    const previousWriteLimit = this.write;
+    // A lower limit applies to our own packets at once; a higher one waits for the peer
+    this.write = Math.min(this.write, size);
+    const result = new Promise<number>((resolve, reject) => {
+      this.pending = { size, previousWriteLimit, resolve, reject };
+    });
+
+    try {
+      await this.channel.request(ControlFrameKind.ResizeRequest, encodeControlU32(size));
+    } catch (error) {
+      this.pending = undefined;
+      this.write = previousWriteLimit;
+      throw error;
+    }
+    return result;
+  }
+
+  /** Answer the peer's request, or settle ours */
+  async handle(kind: ControlFrameKind, payload: Uint8Array): Promise<void> {
+    const size = decodeControlU32(payload);
+    const pending = this.pending;
+
+    switch (kind) {
+      case ControlFrameKind.ResizeRequest:
+        // Crossing requests could leave the peers on different limits, so
+        // refuse the peer's while ours is outstanding
+        if (pending || size < 1 || size > this.maxNegotiable) {
+          await this.channel.answer(ControlFrameKind.ResizeReject, encodeControlU32(this.write));
+          return;
+        }
+        // Switch before the acceptance is queued: everything we sent earlier
+        // was within the old limit, everything after is within the new one
+        this.read = size;
+        this.write = size;
+        await this.channel.answer(ControlFrameKind.ResizeAccept, encodeControlU32(size));
+        return;
+
+      case ControlFrameKind.ResizeAccept:
+        if (!pending || pending.size !== size) {
+          throw ClavisError.message(MessageError.invalidFormat("Unexpected max packet size acceptance"));
+        }
+        this.pending = undefined;
+        this.read = size;
+        this.write = size;
+        pending.resolve(size);
+        return;
+
+      case ControlFrameKind.ResizeReject:
+        if (!pending) {
+          throw ClavisError.message(MessageError.invalidFormat("Unexpected max packet size rejection"));
+        }
+        this.pending = undefined;
+        this.write = pending.previousWriteLimit;
+        pending.reject(ClavisError.invalidOperation(`Peer refused max packet size ${pending.size}`));
+        return;
+    }
+  }
+}
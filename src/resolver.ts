@@ -0,0 +1,152 @@
+/**
+ * Host resolution
+ * Re-resolves hostnames as their records expire and rotates among the
+ * addresses returned, so reconnects fail over to healthy replicas
+ *
+ * Records come from DNS with their TTLs where the name is in DNS, and from
+ * the system resolver (hosts file, mDNS) otherwise, which reports no TTL.
+ * If a lookup fails, the addresses from the last one are used until a
+ * later lookup succeeds.
+ */
+
+import { promises as dns } from "dns";
+import { isIP } from "net";
+import { ClavisError, StreamError } from "./error.js";
+import { systemClock, type Clock } from "./clock.js";
+
+/**
+ * Address to connect to
+ */
+export interface ResolvedAddress {
+  address: string;
+  family: 4 | 6;
+}
+
+/**
+ * Address record from a lookup, with its time to live when known
+ */
+export interface ResolvedRecord extends ResolvedAddress {
+  ttlMs?: number | undefined;
+}
+
+/**
+ * Options for configuring a host resolver
+ */
+export interface HostResolverOptions {
+  /** Look up a hostname (default: `resolveHost`) */
+  resolve?: ((host: string) => Promise<ResolvedRecord[]>) | undefined;
+  /** How long to keep records that carry no TTL, in milliseconds (default: 30000) */
+  defaultTtlMs?: number | undefined;
+  /** Lower bound on how long records are kept, so TTL 0 doesn't mean a lookup per attempt (default: 1000) */
+  minTtlMs?: number | undefined;
+  /** Time source for record expiry (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+interface CachedHost {
+  addresses: ResolvedAddress[];
+  expiresAt: number;
+  next: number;
+}
+
+const DEFAULT_TTL_MS = 30_000;
+const DEFAULT_MIN_TTL_MS = 1_000;
+
+/**
+ * Look up every A and AAAA record of `host` with its TTL, falling back to
+ * the system resolver for names DNS doesn't know
+ */
+export async function resolveHost(host: string): Promise<ResolvedRecord[]> {
+  const [v4, v6] = await Promise.allSettled([
+    dns.resolve4(host, { ttl: true }),
+    dns.resolve6(host, { ttl: true }),
+  ]);
+  const records: ResolvedRecord[] = [];
+  if (v4.status === "fulfilled") {
+    for (const record of v4.value) records.push({ address: record.address, family: 4, ttlMs: record.ttl * 1000 });
+  }
+  if (v6.status === "fulfilled") {
+    for (const record of v6.value) records.push({ address: record.address, family: 6, ttlMs: record.ttl * 1000 });
+  }
+  if (records.length > 0) return records;
+
+  const found = await dns.lookup(host, { all: true });
+  return found.map((record) => ({ address: record.address, family: record.family === 6 ? 6 : 4 }));
+}
+
+/**
+ * Resolves hostnames with a TTL-respecting cache and hands out their
+ * addresses in turn
+ *
+ * @example
+ * ```typescript
+ * const client = new ClavisClient({
+ *   host: "game.example.com",
+ *   port: 7000,
+ *   resolver: new HostResolver(),
+ * });
+ * ```
+ */
+export class HostResolver {
+  private readonly resolve: (host: string) => Promise<ResolvedRecord[]>;
+  private readonly defaultTtlMs: number;
+  private readonly minTtlMs: number;
+  private readonly clock: Clock;
+  private cache = new Map<string, CachedHost>();
+
+  constructor(options: HostResolverOptions = {}) {
+    this.resolve = options.resolve ?? resolveHost;
+    this.defaultTtlMs = options.defaultTtlMs ?? DEFAULT_TTL_MS;
+    this.minTtlMs = options.minTtlMs ?? DEFAULT_MIN_TTL_MS;
+    this.clock = options.clock ?? systemClock;
+    if (!(this.defaultTtlMs >= 0) || !(this.minTtlMs >= 0)) {
+      throw ClavisError.config("defaultTtlMs and minTtlMs must not be negative");
+    }
+  }
+
+  /**
+   * Address for the next connection attempt to `host`: looked up again once
+   * the cached records expired, and a different one each call when the name
+   * has several. IP literals are returned as they are.
+   */
+  async next(host: string): Promise<ResolvedAddress> {
+    const family = isIP(host);
+    if (family !== 0) return { address: host, family: family === 6 ? 6 : 4 };
+
+    let cached = this.cache.get(host);
+    const now = this.clock.now();
+    if (!cached || now >= cached.expiresAt) {
+      try {
+        const records = await this.resolve(host);
+        if (records.length === 0) {
+          throw new Error(`No addresses for ${host}`);
+        }
+        const ttlMs = Math.max(this.minTtlMs, Math.min(...records.map((record) => record.ttlMs ?? this.defaultTtlMs)));
+        cached = {
+          addresses: records.map(({ address, family }) => ({ address, family })),
+          expiresAt: now + ttlMs,
+          // Keep rotating from where we were, not from the first record again
+          next: cached?.next ?? 0,
+        };
+        this.cache.set(host, cached);
+      } catch (error) {
+        if (!cached) {
+          throw ClavisError.stream(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+        }
+      }
+    }
+
+    const address = cached.addresses[cached.next % cached.addresses.length]!;
+    cached.next = (cached.next + 1) % cached.addresses.length;
+    return address;
+  }
+
+  /** Forget the cached records for `host` (or every host), so the next call looks it up again */
+  invalidate(host?: string): void {
+    if (host === undefined) {
+      this.cache.clear();
+    } else {
+      this.cache.delete(host);
+    }
+  }
+}
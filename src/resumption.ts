@@ -0,0 +1,363 @@
+/**
+ * Stateless session resumption
+ * Lets a peer pick up an earlier session on any server that shares the ticket keys
+ *
+ * Everything a server needs to resume a session lives inside the ticket,
+ * sealed by a `TicketKeyring`: the session's resumption secret and the peer
+ * identity that was verified for it. No server keeps a session store, so a
+ * reconnect that a load balancer sends to a different instance resumes just
+ * as well. Both peers must opt in:
+ * 1. Each side puts a hello in front of its handshake nonce, so it travels
+ *    in the first flight: a fresh 32-byte nonce and the ticket it holds, if
+ *    any. The peer opens and redeems the ticket while the X25519 handshake
+ *    runs, keeping forward secrecy.
+ * 2. Right after the handshake each side sends a grant: whether it accepted
+ *    the peer's ticket, a fresh ticket for the next connection if it has a
+ *    keyring, and for its own ticket an HMAC keyed with the ticket's secret
+ *    over the transcript hash and its role. The proof shows the presenter
+ *    knows the secret, not just the ticket.
+ * 3. Once a ticket is accepted, both sides move to keys derived from the
+ *    handshake's, the ticket's secret and both hellos, so the session
+ *    depends on the ticket as well as on the fresh nonces and ephemeral keys.
+ *
+ * A resumed peer's identity comes from its ticket, so the grant goes out
+ * right behind the identity presentation and resuming costs no more than a
+ * connection that only exchanges identities. A new session waits to verify
+ * the peer's certificate before sealing it into a ticket, half a round trip
+ * more. The handshake messages are unchanged and still match the Rust
+ * implementation's; the hello in front of them is what needs both peers.
+ *
+ * Tickets are single-use: the keyring's replay cache records every redeemed
+ * one. A ticket that is unknown, expired or already used is not an error:
+ * the connection simply carries on as a new session. A ticket without the
+ * proof of its secret fails the connection, as it was spent on redeeming.
+ *
+ * Layouts (integers little-endian):
+ * hello: "CLVR" | version (u8) | ticket length (u16) | nonce (32) | ticket
+ * grant: "CLVR" | version (u8) | accepted (u8) | ticket length (u16) | ticket |
+ * proof (32, when the sender's hello had a ticket)
+ */
+
+import { generateRandomBytes, hkdfExpand, hmacSha256, sha256Hash } from "./crypto.js";
+import { ClavisError, CryptoError, MessageError, StreamError } from "./error.js";
+import type { HandshakeResult } from "./handshake.js";
+import type { PeerIdentity } from "./identity.js";
+import type { TicketKeyring } from "./tickets.js";
+
+/**
+ * What a client keeps to resume a session later
+ */
+export interface SessionTicket {
+  /** Opaque ticket from the server */
+  readonly ticket: Uint8Array;
+  /** Resumption secret of the session the ticket belongs to */
+  readonly secret: Uint8Array;
+}
+
+/**
+ * Session state recovered from an accepted ticket
+ */
+export interface ResumedSession {
+  /** Identity verified when the session was first established, if any */
+  identity: PeerIdentity | undefined;
+  /** Resumption secret of the earlier session */
+  secret: Uint8Array;
+}
+
+/**
+ * What the grants settled
+ */
+export interface GrantOutcome {
+  /** Whether either side resumed the other's session */
+  resumed: boolean;
+  /** Ticket the peer issued for the next connection, if it has a keyring */
+  ticket: SessionTicket | undefined;
+  /** The handshake with the secrets of accepted tickets mixed in */
+  handshake: HandshakeResult;
+  /** Session keys to switch to, when a ticket was accepted and there were keys to mix */
+  keys: { sendingKey: Uint8Array; receivingKey: Uint8Array } | undefined;
+}
+
+const MAGIC = [0x43, 0x4c, 0x56, 0x52]; // "CLVR"
+const VERSION = 2;
+const STATE_VERSION = 1;
+const NONCE_LENGTH = 32;
+const HELLO_PREFIX_LENGTH = MAGIC.length + 1 + 2;
+const PROOF_LENGTH = 32;
+const PROOF_CONTEXT = new TextEncoder().encode("clavis-resume-v2");
+
+function hasHeader(data: Uint8Array): boolean {
+  return MAGIC.every((b, i) => data[i] === b) && data[4] === VERSION;
+}
+
+function notNegotiated(): ClavisError {
+  return ClavisError.stream(StreamError.handshakeFailed("Peer did not negotiate resumption"));
+}
+
+function malformed(): ClavisError {
+  return ClavisError.stream(StreamError.handshakeFailed("Malformed resumption message"));
+}
+
+function concat(...parts: Uint8Array[]): Uint8Array {
+  let total = 0;
+  for (const part of parts) total += part.length;
+  const out = new Uint8Array(total);
+  let offset = 0;
+  for (const part of parts) {
+    out.set(part, offset);
+    offset += part.length;
+  }
+  return out;
+}
+
+function proof(secret: Uint8Array, transcriptHash: Uint8Array, initiator: boolean): Uint8Array {
+  return hmacSha256(secret, concat(PROOF_CONTEXT, transcriptHash, new Uint8Array([initiator ? 1 : 0])));
+}
+
+function withTicket(prefix: number[], ticket: Uint8Array, suffix: Uint8Array = new Uint8Array(0)): Uint8Array {
+  if (ticket.length > 0xffff) {
+    throw ClavisError.invalidOperation("Session tickets are limited to 65535 bytes");
+  }
+  const out = new Uint8Array(prefix.length + 2 + ticket.length + suffix.length);
+  out.set(prefix, 0);
+  new DataView(out.buffer).setUint16(prefix.length, ticket.length, true);
+  out.set(ticket, prefix.length + 2);
+  out.set(suffix, prefix.length + 2 + ticket.length);
+  return out;
+}
+
+function readTicket(data: Uint8Array, offset: number): [Uint8Array, number] {
+  if (offset + 2 > data.length) throw malformed();
+  const length = new DataView(data.buffer, data.byteOffset, data.byteLength).getUint16(offset, true);
+  const end = offset + 2 + length;
+  if (end > data.length) throw malformed();
+  return [data.subarray(offset + 2, end), end];
+}
+
+function encodeState(secret: Uint8Array, identity: PeerIdentity | undefined): Uint8Array {
+  const name = new TextEncoder().encode(identity?.identity ?? "");
+  const credential = new TextEncoder().encode(identity?.credentialId ?? "");
+  const out = new Uint8Array(1 + 32 + 1 + 2 + name.length + 8 + 2 + credential.length);
+  const view = new DataView(out.buffer);
+  out[0] = STATE_VERSION;
+  out.set(secret, 1);
+  out[33] = identity ? 1 : 0;
+  let offset = 34;
+  view.setUint16(offset, name.length, true);
+  out.set(name, offset + 2);
+  offset += 2 + name.length;
+  view.setBigUint64(offset, BigInt(identity?.expiresAt.getTime() ?? 0), true);
+  offset += 8;
+  view.setUint16(offset, credential.length, true);
+  out.set(credential, offset + 2);
+  return out;
+}
+function decodeState(state: Uint8Array): { secret: Uint8Array; identity: PeerIdentity | undefined } {
+  if (state[0] !== STATE_VERSION || state.length < 34 + 2 + 8 + 2) throw malformed();
+  const view = new DataView(state.buffer, state.byteOffset, state.byteLength);
+  const decoder = new TextDecoder();
+  const secret = state.subarray(1, 33);
+  let offset = 34;
+  const nameLength = view.getUint16(offset, true);
+  const name = decoder.decode(state.subarray(offset + 2, offset + 2 + nameLength));
+  offset += 2 + nameLength;
+  const expiresAt = Number(view.getBigUint64(offset, true));
+  offset += 8;
+  const credentialLength = view.getUint16(offset, true);
+  const credentialId = decoder.decode(state.subarray(offset + 2, offset + 2 + credentialLength));
+  const identity = state[33] === 1
+    ? { identity: name, expiresAt: new Date(expiresAt), credentialId }
+    : undefined;
+  return { secret, identity };
+}
+
+/**
+ * One side's part in resuming a session, from the hello in the first flight
+ * to the grants after the handshake
+ */
+export class ResumptionExchange {
+  /** Our hello, sent in front of the handshake's first message */
+  readonly hello: Uint8Array;
+  private peerHello = new Uint8Array(0);
+  private acceptance: Promise<ResumedSession | undefined> = Promise.resolve(undefined);
+  private resumed: ResumedSession | undefined;
+
+  constructor(
+    /** Ticket we present, if any */
+    private readonly ticket: SessionTicket | undefined,
+    /** Keyring that opens the peer's ticket and seals its next one, if any */
+    private readonly keyring: TicketKeyring | undefined
+  ) {
+    const presented = ticket?.ticket ?? new Uint8Array(0);
+    if (presented.length > 0xffff) {
+      throw ClavisError.invalidOperation("Session tickets are limited to 65535 bytes");
+    }
+    this.hello = new Uint8Array(HELLO_PREFIX_LENGTH + NONCE_LENGTH + presented.length);
+    this.hello.set([...MAGIC, VERSION], 0);
+    new DataView(this.hello.buffer).setUint16(MAGIC.length + 1, presented.length, true);
+    this.hello.set(generateRandomBytes(NONCE_LENGTH), HELLO_PREFIX_LENGTH);
+    this.hello.set(presented, HELLO_PREFIX_LENGTH + NONCE_LENGTH);
+  }
+
+  /**
+   * Wrap the handshake's transport so that our hello goes out with its first
+   * message and the peer's is read ahead of the peer's first
+   */
+  wrap(stream: {
+    read: (length: number) => Promise<Uint8Array>;
+    write: (data: Uint8Array) => Promise<void>;
+  }): { read: (length: number) => Promise<Uint8Array>; write: (data: Uint8Array) => Promise<void> } {
+    let sent = false;
+    let received = false;
+    return {
+      write: (data) => {
+        if (sent) return stream.write(data);
+        sent = true;
+        return stream.write(concat(this.hello, data));
+      },
+      read: async (length) => {
+        if (!received) {
+          received = true;
+          await this.readHello((n) => stream.read(n));
+        }
+        return stream.read(length);
+      },
+    };
+  }
+
+  /**
+   * Read the peer's hello and start opening its ticket, so the keyring's
+   * replay cache is consulted while the handshake goes on
+   */
+  private async readHello(read: (length: number) => Promise<Uint8Array>): Promise<void> {
+    // The prefix is shorter than a handshake nonce, so a peer that didn't opt in fails here instead of stalling
+    const prefix = (await read(HELLO_PREFIX_LENGTH)).slice();
+    if (!hasHeader(prefix)) throw notNegotiated();
+    const ticketLength = new DataView(prefix.buffer).getUint16(MAGIC.length + 1, true);
+    this.peerHello = concat(prefix, await read(NONCE_LENGTH + ticketLength));
+    this.acceptance = this.accept(this.peerHello.subarray(HELLO_PREFIX_LENGTH + NONCE_LENGTH));
+  }
+
+  /** The peer's session, once its ticket is opened and redeemed; undefined when it starts a new one */
+  async accepted(): Promise<ResumedSession | undefined> {
+    this.resumed = await this.acceptance;
+    return this.resumed;
+  }
+
+  /**
+   * Our grant: whether we resumed the peer's session, a ticket for its next
+   * connection sealed for `peerIdentity` if we have a keyring, and the proof
+   * for our own ticket. Call after `accepted()`.
+   */
+  encodeGrant(handshake: HandshakeResult, peerIdentity: PeerIdentity | undefined): Uint8Array {
+    const next = this.keyring
+      ? this.keyring.seal(encodeState(this.nextSecret(handshake, this.resumed?.secret), peerIdentity))
+      : new Uint8Array(0);
+    const ours = this.ticket ? proof(this.ticket.secret, handshake.transcriptHash, handshake.initiator) : new Uint8Array(0);
+    return withTicket([...MAGIC, VERSION, this.resumed ? 1 : 0], next, ours);
+  }
+
+  /**
+   * Read the peer's grant, check its proof if we resumed its session, and
+   * mix the secret of every accepted ticket into `handshake` and the
+   * session keys
+   */
+  decodeGrant(
+    data: Uint8Array,
+    handshake: HandshakeResult,
+    keys: { sendingKey: Uint8Array | undefined; receivingKey: Uint8Array | undefined }
+  ): GrantOutcome {
+    if (!hasHeader(data)) throw notNegotiated();
+    const flag = data[5];
+    if (flag !== 0 && flag !== 1) throw malformed();
+    const [next, end] = readTicket(data, 6);
+    const peerOffered = this.peerHello.length > HELLO_PREFIX_LENGTH + NONCE_LENGTH;
+    if (data.length !== end + (peerOffered ? PROOF_LENGTH : 0)) {
+      throw ClavisError.message(MessageError.invalidFormat("Trailing bytes after session ticket"));
+    }
+    const accepted = flag === 1;
+    if (accepted && !this.ticket) throw malformed();
+
+    if (this.resumed) {
+      const expected = proof(this.resumed.secret, handshake.transcriptHash, !handshake.initiator);
+      const presented = data.subarray(end);
+      let diff = 0;
+      for (let i = 0; i < expected.length; i++) diff |= expected[i]! ^ presented[i]!;
+      if (diff !== 0) {
+        throw ClavisError.crypto(CryptoError.authenticationFailure("Peer presented a session ticket without its secret"));
+      }
+    }
+
+    const ours = accepted ? this.ticket!.secret : undefined;
+    const theirs = this.resumed?.secret;
+    const ticket = next.length > 0 ? { ticket: next.slice(), secret: this.nextSecret(handshake, ours) } : undefined;
+    if (!ours && !theirs) {
+      return { resumed: false, ticket, handshake, keys: undefined };
+    }
+
+    // The initiator's ticket first, so both sides mix in the same order
+    const [first, second] = handshake.initiator ? [ours, theirs] : [theirs, ours];
+    const secret = concat(first ?? new Uint8Array(0), second ?? new Uint8Array(0));
+    const transcriptHash = this.transcriptHash(handshake);
+    const mix = (key: Uint8Array, info: string) => hkdfExpand(sha256Hash(concat(key, secret)), transcriptHash, info);
+    const { sendingKey, receivingKey } = keys;
+    return {
+      resumed: true,
+      ticket,
+      handshake: {
+        // The same label both ways: the two directions already start from different keys
+        encKey: mix(handshake.encKey, "resumed-key"),
+        decKey: mix(handshake.decKey, "resumed-key"),
+        transcriptHash,
+        initiator: handshake.initiator,
+        resumptionSecret: mix(handshake.resumptionSecret, "resumed-resumption"),
+        pskIdentity: handshake.pskIdentity,
+      },
+      keys: sendingKey && receivingKey
+        ? { sendingKey: mix(sendingKey, "resumed-key"), receivingKey: mix(receivingKey, "resumed-key") }
+        : undefined,
+    };
+  }
+
+  /** The handshake's transcript hash extended with both hellos, initiator's first */
+  private transcriptHash(handshake: HandshakeResult): Uint8Array {
+    const [first, second] = handshake.initiator ? [this.hello, this.peerHello] : [this.peerHello, this.hello];
+    return sha256Hash(concat(handshake.transcriptHash, first, second));
+  }
+
+  /**
+   * Secret of the ticket granted on this connection. A ticket granted for a
+   * resumed session needs the old secret too, so a stolen ticket that got
+   * accepted without its secret is no good for the next connection either.
+   */
+  private nextSecret(handshake: HandshakeResult, resumedSecret: Uint8Array | undefined): Uint8Array {
+    if (!resumedSecret) return handshake.resumptionSecret;
+    return hkdfExpand(sha256Hash(concat(handshake.resumptionSecret, resumedSecret)), this.transcriptHash(handshake), "resumed-ticket");
+  }
+
+  /** Open and redeem the peer's ticket; never throws, since a bad ticket only means a new session */
+  private async accept(ticket: Uint8Array): Promise<ResumedSession | undefined> {
+    const keyring = this.keyring;
+    if (ticket.length === 0 || !keyring) return undefined;
+    let state: ReturnType<typeof decodeState>;
+    try {
+      state = decodeState(keyring.open(ticket));
+    } catch {
+      return undefined;
+    }
+    // Each ticket resumes once; a second presentation gets a new session
+    let fresh: boolean;
+    try {
+      fresh = await keyring.redeem(ticket);
+    } catch {
+      // An unreachable cache can't vouch for the ticket; a new session is always safe
+      fresh = false;
+    }
+    if (!fresh) return undefined;
+    if (state.identity && state.identity.expiresAt.getTime() <= keyring.clock.now() - keyring.clockSkewMs) {
+      // The credential behind the session has lapsed; make the peer present a fresh one
+      return { identity: undefined, secret: state.secret };
+    }
+    return { identity: state.identity, secret: state.secret };
+  }
+}
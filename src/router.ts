@@ -0,0 +1,244 @@
+/**
+ * Packet router
+ * Dispatches decoded packets to handlers by variant name
+ */
+
+import { ClavisError } from "./error.js";
+import { writeString } from "./bincode.js";
+import type { DecodedMessage, ProtocolCodec, UnknownVariant } from "./protocol.js";
+
+/**
+ * Reply produced by a handler
+ */
+export interface RouterReply<T extends string> {
+  /** Variant of the reply */
+  type: T;
+  /** Serialized variant data (without the variant index) */
+  data?: Uint8Array | undefined;
+}
+
+/**
+ * Handler for a single variant.
+ * Returning a reply sends it back to the peer (for requests).
+ */
+export type RouteHandler<T extends string> = (
+  message: DecodedMessage<T>
+) => RouterReply<T> | void | Promise<RouterReply<T> | void>;
+
+/**
+ * Handler for a server-streaming request.
+ * Each yielded reply is sent to the caller as one stream item; the stream ends
+ * when the iterable completes. `signal` aborts when the caller cancels.
+ */
+export type StreamRouteHandler<T extends string> = (
+  message: DecodedMessage<T>,
+  signal: AbortSignal
+) => AsyncIterable<RouterReply<T>> | Iterable<RouterReply<T>>;
+
+/**
+ * Handler for a client-streaming request.
+ * `items` yields the packets uploaded by the caller; the returned reply is
+ * sent once the handler finishes.
+ */
+export type UploadRouteHandler<T extends string> = (
+  message: DecodedMessage<T>,
+  items: AsyncIterable<DecodedMessage<T>>
+) => RouterReply<T> | Promise<RouterReply<T>>;
+
+/**
+ * Options for configuring a packet router
+ */
+export interface PacketRouterOptions<T extends string> {
+  /**
+   * Variant that handler failures are mapped to.
+   * Defaults to the codec's `errorVariant`.
+   */
+  errorVariant?: T | undefined;
+  /**
+   * Serialize a thrown error into the error variant's data.
+   * Defaults to the error message as a bincode string.
+   */
+  encodeError?: ((error: unknown) => Uint8Array) | undefined;
+  /** Called for packets that have no registered handler */
+  onUnhandled?: ((message: DecodedMessage<T>) => RouterReply<T> | void | Promise<RouterReply<T> | void>) | undefined;
+  /**
+   * Called by `dispatchBytes()` for variants this side doesn't know, e.g.
+   * from a newer peer, so they can be skipped or logged. Without it they
+   * fail to decode.
+   */
+  onUnknown?: ((message: UnknownVariant) => RouterReply<T> | void | Promise<RouterReply<T> | void>) | undefined;
+}
+
+/**
+ * Default error body encoding: the error message as a bincode string
+ */
+export function encodeErrorMessage(error: unknown): Uint8Array {
+  const buffer: number[] = [];
+  writeString(buffer, error instanceof Error ? error.message : String(error));
+  return new Uint8Array(buffer);
+}
+
+/**
+ * Routes decoded packets to per-variant handlers.
+ *
+ * When the protocol has an error variant, a handler that throws produces
+ * that variant as its reply instead of propagating the exception.
+ *
+ * @example
+ * ```typescript
+ * const codec = createProtocolCodec(["GetStatus", "Status", "Error"] as const, {
+ *   errorVariant: "Error",
+ * });
+ *
+ * const router = new PacketRouter(codec)
+ *   .on("GetStatus", () => ({ type: "Status", data: encodeStatus(current) }));
+ * ```
+ */
+export class PacketRouter<T extends string> {
+  private handlers = new Map<T, RouteHandler<T>>();
+  private streamHandlers = new Map<T, StreamRouteHandler<T>>();
+  private uploadHandlers = new Map<T, UploadRouteHandler<T>>();
+  private allowed: Set<T> | undefined;
+  private readonly errorVariant: T | undefined;
+  private readonly encodeError: (error: unknown) => Uint8Array;
+
+  constructor(
+    public readonly codec: ProtocolCodec<T>,
+    private readonly options: PacketRouterOptions<T> = {}
+  ) {
+    this.errorVariant = options.errorVariant ?? codec.errorVariant;
+    this.encodeError = options.encodeError ?? encodeErrorMessage;
+
+    if (this.errorVariant !== undefined && !codec.isValidType(this.errorVariant)) {
+      throw ClavisError.config(`Error variant ${this.errorVariant} is not part of the protocol`);
+    }
+  }
+
+  /**
+   * Register a handler for a variant, replacing any existing one
+   */
+  on(type: T, handler: RouteHandler<T>): this {
+    this.handlers.set(type, handler);
+    return this;
+  }
+
+  /**
+   * Register a server-streaming handler for a variant
+   */
+  onStream(type: T, handler: StreamRouteHandler<T>): this {
+    this.streamHandlers.set(type, handler);
+    return this;
+  }
+
+  /**
+   * Register a client-streaming handler for a variant
+   */
+  onUpload(type: T, handler: UploadRouteHandler<T>): this {
+    this.uploadHandlers.set(type, handler);
+    return this;
+  }
+
+  /**
+   * Remove all handlers for a variant
+   */
+  off(type: T): this {
+    this.handlers.delete(type);
+    this.streamHandlers.delete(type);
+    this.uploadHandlers.delete(type);
+    return this;
+  }
+
+  /**
+   * Only call handlers for `types`; other variants fail as if they had no
+   * handler. Connection phases use this to keep dispatch in step with what
+   * the phase accepts. Pass undefined to allow every variant again.
+   */
+  allowOnly(types: Iterable<T> | undefined): this {
+    this.allowed = types === undefined ? undefined : new Set(types);
+    return this;
+  }
+
+  /** Whether handlers for a variant may currently be called */
+  isAllowed(type: T): boolean {
+    return this.allowed === undefined || this.allowed.has(type);
+  }
+
+  /**
+   * Check if a handler is registered for a variant
+   */
+  has(type: T): boolean {
+    return this.handlers.has(type);
+  }
+
+  /** Server-streaming handler for a variant, if any and allowed */
+  streamHandler(type: T): StreamRouteHandler<T> | undefined {
+    return this.isAllowed(type) ? this.streamHandlers.get(type) : undefined;
+  }
+
+  /** Client-streaming handler for a variant, if any and allowed */
+  uploadHandler(type: T): UploadRouteHandler<T> | undefined {
+    return this.isAllowed(type) ? this.uploadHandlers.get(type) : undefined;
+  }
+
+  /** Variants that currently have a handler that may be called */
+  routes(): T[] {
+    return [...this.handlers.keys()].filter((type) => this.isAllowed(type));
+  }
+
+  /**
+   * Run the handler for a decoded message.
+   * Handler failures are mapped to the error variant when one is configured;
+   * otherwise they are rethrown.
+   */
+  async dispatch(message: DecodedMessage<T>): Promise<RouterReply<T> | undefined> {
+    if (!this.isAllowed(message.type)) {
+      return this.mapFailure(ClavisError.invalidOperation(`${message.type} is not allowed now`));
+    }
+    const handler = this.handlers.get(message.type) ?? this.options.onUnhandled;
+    if (!handler) {
+      return this.mapFailure(ClavisError.invalidOperation(`No handler registered for ${message.type}`));
+    }
+
+    try {
+      const reply = await handler(message);
+      return reply as RouterReply<T> | undefined;
+    } catch (error) {
+      return this.mapFailure(error);
+    }
+  }
+
+  /**
+   * Decode raw packet bytes and dispatch them.
+   * Returns the encoded reply, if any.
+   */
+  async dispatchBytes(data: Uint8Array): Promise<Uint8Array | undefined> {
+    const onUnknown = this.options.onUnknown;
+    const message = onUnknown ? this.codec.decodeVariant(data) : this.codec.decode(data);
+    const reply = message.type === undefined
+      ? await this.dispatchUnknown(message, onUnknown!)
+      : await this.dispatch(message);
+    return reply ? this.codec.encode(reply.type, reply.data) : undefined;
+  }
+
+  private async dispatchUnknown(
+    message: UnknownVariant,
+    handler: NonNullable<PacketRouterOptions<T>["onUnknown"]>
+  ): Promise<RouterReply<T> | undefined> {
+    try {
+      return (await handler(message)) as RouterReply<T> | undefined;
+    } catch (error) {
+      return this.mapFailure(error);
+    }
+  }
+
+  /**
+   * Map a handler failure to the error variant, or rethrow it when the
+   * protocol has none
+   */
+  mapFailure(error: unknown): RouterReply<T> {
+    if (this.errorVariant === undefined) {
+      throw error;
+    }
+    return { type: this.errorVariant, data: this.encodeError(error) };
+  }
+}
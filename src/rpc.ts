@@ -0,0 +1,1238 @@
+/**
+ * RPC layer
+ * Correlates requests with replies over an encrypted reader/writer pair
+ *
+ * Every packet is wrapped in a small envelope:
+ * - kind: u8 (see `RpcFrameKind`)
+ * - correlation ID: u64 little-endian (0 for plain messages)
+ * - payload: the packet as encoded by the protocol codec
+ *
+ * Streaming calls reuse the correlation ID for every frame of the call and
+ * are flow controlled with credits: a sender may only have as many
+ * unconsumed items in flight as the receiver has granted.
+ *
+ * Hedged copies of a request (see `RpcPool`) are sent as `HedgedRequest`,
+ * whose payload starts with the 16-byte hedging group ID they share. A
+ * `Cancel` for a request that is being served drops its reply.
+ *
+ * Over a `ChannelMux` (`RpcConnection.overMux()`), envelopes travel on
+ * channel 0, and with `channelPerCall` each call gets a channel of its own
+ * instead, opened for the call and closed after it. A long streaming reply
+ * then holds up only its own channel; smaller calls alongside it keep
+ * moving, as with streams in QUIC.
+ */
+
+import { EventEmitter } from "events";
+import { healthCheckFailure, type HealthCheckResult } from "./stream.js";
+import { ClavisError, StreamError } from "./error.js";
+import { Backoff, backoffDelay, type RetryPolicy } from "./backoff.js";
+import { RawPacket, type DecodedMessage, type PacketTrait, type ProtocolCodec } from "./protocol.js";
+import {
+  PacketRouter,
+  encodeErrorMessage,
+  type RouterReply,
+  type StreamRouteHandler,
+  type UploadRouteHandler,
+} from "./router.js";
+import { BincodeReader, readU32, readU64, writeU32, writeU64 } from "./bincode.js";
+import { AsyncQueue, CreditGate } from "./flow-control.js";
+import { sleepOn, systemClock, type Clock, type TimerHandle } from "./clock.js";
+import type { ChannelMux, LogicalChannel } from "./mux.js";
+import { HEDGE_GROUP_LENGTH, type HedgeRegistry } from "./hedging.js";
+
+/**
+ * Envelope kinds
+ */
+export enum RpcFrameKind {
+  /** One-way packet that expects no reply */
+  Message = 0,
+  /** Request expecting a Response or Failure with the same correlation ID */
+  Request = 1,
+  /** Reply to a request */
+  Response = 2,
+  /** Handler failed and the protocol has no error variant; payload is the message string */
+  Failure = 3,
+  /** Opens a server-streaming call; payload is the initial credit window (u32) followed by the packet */
+  StreamRequest = 4,
+  /** One item of a server-streaming reply */
+  StreamItem = 5,
+  /** The server-streaming reply is complete */
+  StreamEnd = 6,
+  /** Grants the streaming server more items (u32) */
+  StreamCredit = 7,
+  /** Opens a client-streaming call; payload is the packet */
+  UploadRequest = 8,
+  /** One item uploaded by the caller */
+  UploadItem = 9,
+  /** The caller finished uploading */
+  UploadEnd = 10,
+  /** Grants the uploading caller more items (u32) */
+  UploadCredit = 11,
+  /** The caller abandoned a call; for a plain request, its reply is dropped */
+  Cancel = 12,
+  /** Request that is one of several hedged copies; payload is the hedging group ID followed by the packet */
+  HedgedRequest = 13,
+}
+
+/** Size of the envelope header in bytes */
+export const RPC_HEADER_SIZE = 9;
+
+/**
+ * Decoded RPC envelope
+ */
+export interface RpcEnvelope {
+  kind: RpcFrameKind;
+  id: number;
+  payload: Uint8Array;
+}
+
+/**
+ * Encode an RPC envelope
+ */
+export function encodeRpcEnvelope(kind: RpcFrameKind, id: number, payload: Uint8Array): Uint8Array {
+  const header: number[] = [kind];
+  writeU64(header, BigInt(id));
+  const frame = new Uint8Array(RPC_HEADER_SIZE + payload.length);
+  frame.set(header, 0);
+  frame.set(payload, RPC_HEADER_SIZE);
+  return frame;
+}
+
+/**
+ * Decode an RPC envelope
+ */
+export function decodeRpcEnvelope(data: Uint8Array): RpcEnvelope {
+  if (data.length < RPC_HEADER_SIZE) {
+    throw ClavisError.deserializationFailed("RPC envelope too short");
+  }
+  const kind = data[0]!;
+  if (kind > RpcFrameKind.HedgedRequest) {
+    throw ClavisError.deserializationFailed(`Unknown RPC frame kind: ${kind}`);
+  }
+  return {
+    kind: kind as RpcFrameKind,
+    id: Number(readU64(data, 1).value),
+    payload: data.subarray(RPC_HEADER_SIZE),
+  };
+}
+
+/**
+ * Error returned to the caller when the remote handler failed.
+ * `reply` holds the decoded error variant when the protocol has one.
+ */
+export class RpcError extends Error {
+  public override name = "RpcError";
+
+  constructor(
+    message: string,
+    public readonly reply?: DecodedMessage<string>
+  ) {
+    super(message);
+  }
+}
+
+/**
+ * Default error variant decoding: reads the message string written by `encodeErrorMessage`
+ */
+export function decodeErrorMessage<T extends string>(reply: DecodedMessage<T>): Error {
+  try {
+    return new RpcError(reply.reader.readString(), reply);
+  } catch {
+    return new RpcError(`Remote handler failed (${reply.type})`, reply);
+  }
+}
+
+/**
+ * Options for configuring an RPC connection
+ */
+export interface RpcConnectionOptions<T extends string> {
+  /** Codec used to encode/decode request and reply packets */
+  codec: ProtocolCodec<T>;
+  /** Router serving incoming requests and messages */
+  router?: PacketRouter<T> | undefined;
+  /**
+   * Turn a received error variant into the error thrown by `call()`.
+   * Defaults to an `RpcError` carrying the decoded message string.
+   */
+  decodeError?: ((reply: DecodedMessage<T>) => Error) | undefined;
+  /** Options applied to every `call()` unless overridden per call */
+  defaultCallOptions?: RpcCallOptions | undefined;
+  /** Items a caller may upload before waiting for this side to consume them (default: 16) */
+  uploadWindow?: number | undefined;
+  /** Time source for call timeouts and retry backoff (default: `systemClock`) */
+  clock?: Clock | undefined;
+  /**
+   * Run hedged copies of a request once across every connection sharing
+   * this registry (default: none, each copy runs the handler)
+   */
+  hedges?: HedgeRegistry | undefined;
+}
+
+/**
+ * Options for `RpcConnection.overMux()`
+ */
+export interface RpcMuxOptions<T extends string> extends RpcConnectionOptions<T> {
+  /**
+   * Which end of the connection this is. Clients open calls on even
+   * channel IDs and servers on odd ones, so the two never pick the same.
+   */
+  role: "client" | "server";
+  /**
+   * Run each call, streaming or not, on its own short-lived channel
+   * (default: false, every call shares channel 0)
+   */
+  channelPerCall?: boolean | undefined;
+}
+
+/**
+ * Where an RpcConnection reads envelopes from: an `EncryptedReader`, or
+ * the reader of a multiplexed channel
+ */
+export interface RpcReader {
+  readPackets(): Promise<unknown[]>;
+}
+
+/**
+ * Where an RpcConnection writes envelopes to. `ping()` is optional;
+ * without it `RpcConnection.ping()` fails.
+ */
+export interface RpcWriter {
+  writePacket(packet: PacketTrait): Promise<number>;
+  ping?(): Promise<number>;
+}
+
+/**
+ * Options for streaming calls
+ */
+export interface RpcStreamOptions {
+  /**
+   * Items the peer may send ahead of the consumer (default: 16).
+   * Only used by `callStream()`; upload windows are set by the receiver.
+   */
+  window?: number | undefined;
+  /** Abort the call; the peer is told to stop */
+  signal?: AbortSignal | undefined;
+}
+
+/**
+ * Retry behaviour for RPC calls
+ */
+export interface RpcRetryPolicy extends RetryPolicy {
+  /**
+   * Decide whether a failed attempt is worth retrying.
+   * Defaults to `isRetryableRpcError` (timeouts and transient stream errors).
+   */
+  retryOn?: ((error: unknown) => boolean) | undefined;
+}
+
+/**
+ * Per-call options
+ */
+export interface RpcCallOptions {
+  /** Reject the attempt if no reply arrives within this many milliseconds */
+  timeoutMs?: number | undefined;
+  /**
+   * Mark the request as safe to execute more than once.
+   * Only idempotent calls are retried after the request was sent
+   * (or hedged); requests that never left are always retryable.
+   */
+  idempotent?: boolean | undefined;
+  /** Retry policy; calls are attempted once when omitted */
+  retry?: RpcRetryPolicy | undefined;
+  /** Abort the call (pending attempts are rejected and not retried); the peer drops its reply */
+  signal?: AbortSignal | undefined;
+  /**
+   * Send the request as a copy in this hedging group (16 bytes). Set by
+   * `RpcPool` for hedged calls.
+   */
+  hedgeGroup?: Uint8Array | undefined;
+}
+
+const unsentErrors = new WeakSet<object>();
+
+function markUnsent(error: ClavisError): ClavisError {
+  unsentErrors.add(error);
+  return error;
+}
+
+/**
+ * Check whether an attempt failed before the request reached the wire
+ */
+export function isUnsentRpcError(error: unknown): boolean {
+  return typeof error === "object" && error !== null && unsentErrors.has(error);
+}
+
+/**
+ * Default retry predicate: timeouts and transient stream errors.
+ * Application errors (`RpcError`) are never retried.
+ */
+export function isRetryableRpcError(error: unknown): boolean {
+  if (error instanceof RpcError) return false;
+  if (error instanceof ClavisError && error.cause instanceof StreamError) {
+    return error.cause.isTransient() || isUnsentRpcError(error);
+  }
+  return false;
+}
+
+/**
+ * Compute the delay before retry number `retry` (1-based), without jitter
+ */
+export function rpcRetryDelay(policy: RpcRetryPolicy, retry: number): number {
+  return backoffDelay(policy, retry);
+}
+
+/**
+ * Run `attempt` under the retry policy in `options`.
+ * Sent, non-idempotent requests are never retried.
+ */
+export async function callWithRetry<R>(
+  attempt: (attemptNumber: number) => Promise<R>,
+  options: RpcCallOptions,
+  clock: Clock = systemClock
+): Promise<R> {
+  const policy = options.retry;
+  const backoff = policy ? new Backoff({ ...policy, maxAttempts: Math.max(1, policy.maxAttempts ?? 3) }, clock) : undefined;
+  const retryOn = policy?.retryOn ?? isRetryableRpcError;
+
+  for (let attemptNumber = 1; ; attemptNumber++) {
+    try {
+      return await attempt(attemptNumber);
+    } catch (error) {
+      const safe = options.idempotent === true || isUnsentRpcError(error);
+      const delay = safe && !options.signal?.aborted && retryOn(error) ? backoff?.next() : undefined;
+      if (delay === undefined) throw error;
+      await sleepOn(clock, delay);
+    }
+  }
+}
+
+/**
+ * Events emitted by RpcConnection
+ */
+export interface RpcConnectionEvents<T extends string> {
+  /** One-way packet with no registered route */
+  message: [message: DecodedMessage<T>];
+  /** Non-fatal error (e.g. a malformed envelope or failing message handler) */
+  error: [error: ClavisError];
+  /** The underlying reader stopped; pending calls have been rejected */
+  close: [reason: ClavisError];
+}
+
+/**
+ * Type-safe event emitter interface
+ */
+export interface RpcConnectionEmitter<T extends string> {
+  on<K extends keyof RpcConnectionEvents<T>>(event: K, listener: (...args: RpcConnectionEvents<T>[K]) => void): this;
+  once<K extends keyof RpcConnectionEvents<T>>(event: K, listener: (...args: RpcConnectionEvents<T>[K]) => void): this;
+  off<K extends keyof RpcConnectionEvents<T>>(event: K, listener: (...args: RpcConnectionEvents<T>[K]) => void): this;
+  emit<K extends keyof RpcConnectionEvents<T>>(event: K, ...args: RpcConnectionEvents<T>[K]): boolean;
+}
+
+interface PendingCall<T extends string> {
+  resolve: (reply: DecodedMessage<T>) => void;
+  reject: (error: Error) => void;
+}
+
+interface IncomingStream<T extends string> {
+  queue: AsyncQueue<DecodedMessage<T>>;
+  window: number;
+  /** The peer ended the stream (normally or with an error) */
+  finished: boolean;
+}
+
+interface ServingStream {
+  gate: CreditGate;
+  abort: AbortController;
+}
+
+interface ServingRequest {
+  /** The caller cancelled it, so the reply is dropped */
+  cancelled: boolean;
+}
+
+const DEFAULT_STREAM_WINDOW = 16;
+const EMPTY = new Uint8Array(0);
+const MAX_CHANNEL_ID = 0xffffffff;
+
+/** Channels this side opens for its calls */
+interface CallChannels {
+  mux: ChannelMux;
+  perCall: boolean;
+  /** 2 for clients, 1 for servers; IDs advance by two from here */
+  first: number;
+  nextId: number;
+}
+
+function encodeCredits(credits: number): Uint8Array {
+  const buffer: number[] = [];
+  writeU32(buffer, credits);
+  return new Uint8Array(buffer);
+}
+
+function windowOverrun(window: number): ClavisError {
+  return ClavisError.invalidOperation(`Peer exceeded the stream window of ${window} items`);
+}
+
+function concatBytes(head: Uint8Array, tail: Uint8Array): Uint8Array {
+  const out = new Uint8Array(head.length + tail.length);
+  out.set(head, 0);
+  out.set(tail, head.length);
+  return out;
+}
+
+function toClavisError(error: unknown): ClavisError {
+  if (error instanceof ClavisError) return error;
+  if (error instanceof StreamError) return ClavisError.stream(error);
+  return ClavisError.stream(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+}
+
+/**
+ * Request/response layer over an encrypted reader/writer pair.
+ *
+ * Both peers can issue calls and serve requests at the same time. When the
+ * protocol follows the error packet convention, handler failures on the
+ * remote side reject `call()` with the decoded error.
+ *
+ * @example
+ * ```typescript
+ * const { reader, writer } = stream.split();
+ * const rpc = new RpcConnection(reader, writer, { codec, router });
+ * rpc.start();
+ *
+ * const reply = await rpc.call("GetStatus");
+ * const usersOnline = reply.reader.readU32();
+ *
+ * for await (const event of rpc.callStream("Subscribe", topic)) {
+ *   handleEvent(event);
+ * }
+ *
+ * // One channel per call, so big replies don't delay small ones
+ * const client = await RpcConnection.overMux(ChannelMux.over(stream), { codec, role: "client", channelPerCall: true });
+ * ```
+ */
+export class RpcConnection<T extends string> extends EventEmitter implements RpcConnectionEmitter<T> {
+  private readonly codec: ProtocolCodec<T>;
+  private readonly router: PacketRouter<T> | undefined;
+  private readonly decodeError: (reply: DecodedMessage<T>) => Error;
+  private readonly defaultCallOptions: RpcCallOptions;
+  private readonly uploadWindow: number;
+  private readonly clock: Clock;
+  private readonly hedges: HedgeRegistry | undefined;
+  private pending = new Map<number, PendingCall<T>>();
+  private streams = new Map<number, IncomingStream<T>>();
+  private uploads = new Map<number, CreditGate>();
+  private servingStreams = new Map<number, ServingStream>();
+  private servingUploads = new Map<number, AsyncQueue<DecodedMessage<T>>>();
+  private servingRequests = new Map<number, ServingRequest>();
+  private messages: AsyncQueue<DecodedMessage<T>> | undefined;
+  private nextId = 1;
+  private running = false;
+  private closedReason: ClavisError | undefined;
+  private lastActivity: number;
+  private channels: CallChannels | undefined;
+  /** Connections running calls on their own channels, ours and the peer's */
+  private children = new Set<RpcConnection<T>>();
+
+  constructor(
+    private readonly reader: RpcReader,
+    private readonly writer: RpcWriter,
+    options: RpcConnectionOptions<T>
+  ) {
+    super();
+    this.codec = options.codec;
+    this.router = options.router;
+    this.decodeError = options.decodeError ?? decodeErrorMessage;
+    this.defaultCallOptions = options.defaultCallOptions ?? {};
+    this.uploadWindow = Math.max(1, options.uploadWindow ?? DEFAULT_STREAM_WINDOW);
+    this.clock = options.clock ?? systemClock;
+    this.hedges = options.hedges;
+    this.lastActivity = this.clock.now();
+  }
+
+  /**
+   * Run RPC over a multiplexer: plain envelopes on channel 0, which both
+   * sides open, and every channel the peer opens served as one call. Pass
+   * a mux that hasn't started yet, so the peer's open of channel 0 can't
+   * arrive first. Transport pings aren't available over channels.
+   */
+  static async overMux<T extends string>(mux: ChannelMux, options: RpcMuxOptions<T>): Promise<RpcConnection<T>> {
+    const { reader, writer } = await mux.openChannel(0);
+    const rpc = new RpcConnection(reader, writer, options);
+    const first = options.role === "client" ? 2 : 1;
+    rpc.channels = { mux, perCall: options.channelPerCall ?? false, first, nextId: first };
+    void (async () => {
+      try {
+        for await (const channel of mux) rpc.serveChannel(channel);
+      } catch (error) {
+        rpc.reportError(error);
+      }
+    })();
+    return rpc.start();
+  }
+
+  /** Number of calls waiting for a reply */
+  get pendingCalls(): number {
+    return this.pending.size + [...this.children].reduce((sum, child) => sum + child.pendingCalls, 0);
+  }
+
+  /** Whether the connection has stopped */
+  get isClosed(): boolean {
+    return this.closedReason !== undefined;
+  }
+
+  /** Milliseconds since a packet was last sent or received */
+  get idleMs(): number {
+    return this.clock.now() - this.lastActivity;
+  }
+
+  /**
+   * Check the connection with a transport-level ping; resolves with the
+   * round trip in milliseconds, or rejects if no answer arrives within
+   * `timeoutMs`
+   */
+  async ping(timeoutMs?: number): Promise<number> {
+    if (this.closedReason) throw this.closedReason;
+    if (!this.writer.ping) {
+      throw ClavisError.invalidOperation("The transport of this connection can't ping");
+    }
+    this.start();
+    const pong = this.writer.ping();
+    let timer: TimerHandle | undefined;
+    try {
+      const rttMs = await (timeoutMs === undefined ? pong : Promise.race([
+        pong,
+        new Promise<never>((_, reject) => {
+          timer = this.clock.setTimer(() => reject(ClavisError.stream(StreamError.timeout(timeoutMs))), timeoutMs);
+        }),
+      ]));
+      this.lastActivity = this.clock.now();
+      return rttMs;
+    } finally {
+      timer?.cancel();
+    }
+  }
+
+  /**
+   * One-way packets with no registered route, in arrival order, as an async
+   * iterable. Replies never show up here; they resolve their `call()`.
+   * Packets are buffered from the first call of `inbox()` on and iteration
+   * ends when the connection closes (with the reason, unless the peer or
+   * `close()` ended it normally). The `message` event still fires as well.
+   *
+   * @example
+   * ```typescript
+   * const rpc = new RpcConnection(reader, writer, { codec }).start();
+   * void (async () => {
+   *   for await (const notice of rpc.inbox()) handleNotice(notice);
+   * })();
+   * const reply = await rpc.call("GetStatus");
+   * ```
+   */
+  inbox(): AsyncIterable<DecodedMessage<T>> {
+    if (!this.messages) {
+      this.messages = new AsyncQueue<DecodedMessage<T>>();
+      if (this.closedReason) this.endInbox(this.closedReason);
+    }
+    return this.messages;
+  }
+
+  /**
+   * Ping with a deadline like `ping()`, but resolve with why the check
+   * failed instead of rejecting; see `EncryptedStream.healthCheck()`
+   */
+  async healthCheck(timeoutMs: number): Promise<HealthCheckResult> {
+    try {
+      return { healthy: true, rttMs: await this.ping(timeoutMs) };
+    } catch (error) {
+      return healthCheckFailure(error);
+    }
+  }
+
+  /**
+   * Start the background read loop that demultiplexes replies and serves requests
+   */
+  start(): this {
+    if (!this.running && !this.closedReason) {
+      this.running = true;
+      void this.readLoop();
+    }
+    return this;
+  }
+
+  /**
+   * Send a request and wait for the matching reply.
+   * Rejects with the decoded error if the peer replies with the error variant.
+   *
+   * @param options - Per-call timeout and retry behaviour, merged over `defaultCallOptions`
+   */
+  call(type: T, data?: Uint8Array, options?: RpcCallOptions): Promise<DecodedMessage<T>> {
+    const merged: RpcCallOptions = { ...this.defaultCallOptions, ...options };
+    const payload = this.codec.encode(type, data);
+    return callWithRetry(() => this.attempt(payload, merged), merged, this.clock);
+  }
+
+  /**
+   * Send an already encoded request once, without retries.
+   * Used by `call()` and by `RpcPool` to spread attempts over connections.
+   */
+  attempt(payload: Uint8Array, options: RpcCallOptions = {}): Promise<DecodedMessage<T>> {
+    if (this.closedReason) {
+      return Promise.reject(markUnsent(this.closedReason));
+    }
+    if (options.signal?.aborted) {
+      return Promise.reject(markUnsent(ClavisError.invalidOperation("Call aborted")));
+    }
+    const group = options.hedgeGroup;
+    if (group !== undefined && group.length !== HEDGE_GROUP_LENGTH) {
+      return Promise.reject(ClavisError.invalidOperation(`Hedging group IDs are ${HEDGE_GROUP_LENGTH} bytes, got ${group.length}`));
+    }
+    if (this.channels?.perCall) {
+      return this.onOwnChannel((child) => child.attempt(payload, options));
+    }
+    this.start();
+
+    const id = this.allocateId();
+
+    return new Promise((resolve, reject) => {
+      let timer: TimerHandle | undefined;
+      const onAbort = () => {
+        if (this.pending.delete(id)) {
+          finish();
+          reject(ClavisError.invalidOperation("Call aborted"));
+          // Spare the peer sending a reply nobody reads
+          if (!this.closedReason) {
+            this.send(RpcFrameKind.Cancel, id, EMPTY).catch((error) => this.reportError(error));
+          }
+        }
+      };
+      const finish = () => {
+        timer?.cancel();
+        options.signal?.removeEventListener("abort", onAbort);
+      };
+
+      this.pending.set(id, {
+        resolve: (reply) => {
+          finish();
+          resolve(reply);
+        },
+        reject: (error) => {
+          finish();
+          reject(error);
+        },
+      });
+
+      if (options.timeoutMs !== undefined) {
+        const timeoutMs = options.timeoutMs;
+        timer = this.clock.setTimer(() => {
+          if (this.pending.delete(id)) {
+            finish();
+            reject(ClavisError.stream(StreamError.timeout(timeoutMs)));
+          }
+        }, timeoutMs);
+      }
+      options.signal?.addEventListener("abort", onAbort, { once: true });
+
+      const request = group === undefined
+        ? this.send(RpcFrameKind.Request, id, payload)
+        : this.send(RpcFrameKind.HedgedRequest, id, concatBytes(group, payload));
+      request.catch((error) => {
+        if (this.pending.delete(id)) {
+          finish();
+          // The request never left, so retrying is safe even if not idempotent
+          reject(markUnsent(toClavisError(error)));
+        }
+      });
+    });
+  }
+
+  /**
+   * Open a server-streaming call.
+   * The request is sent when iteration starts; leaving the loop early (or
+   * aborting via `options.signal`) cancels the call on the peer. The peer
+   * never has more than `options.window` unconsumed items in flight.
+   */
+  async *callStream(
+    type: T,
+    data?: Uint8Array,
+    options: RpcStreamOptions = {}
+  ): AsyncGenerator<DecodedMessage<T>, void, undefined> {
+    if (this.closedReason) {
+      throw this.closedReason;
+    }
+    if (this.channels?.perCall) {
+      const { child, release } = await this.openCallChannel();
+      try {
+        yield* child.callStream(type, data, options);
+      } finally {
+        release();
+      }
+      return;
+    }
+    this.start();
+
+    const id = this.allocateId();
+    const window = Math.max(1, options.window ?? DEFAULT_STREAM_WINDOW);
+    let consumed = 0;
+    const stream: IncomingStream<T> = {
+      window,
+      finished: false,
+      queue: new AsyncQueue<DecodedMessage<T>>(() => {
+        // Return credits in batches of half the window
+        if (++consumed >= Math.ceil(window / 2)) {
+          this.sendCredits(RpcFrameKind.StreamCredit, id, consumed);
+          consumed = 0;
+        }
+      }),
+    };
+    this.streams.set(id, stream);
+
+    const onAbort = () => stream.queue.end(ClavisError.invalidOperation("Call aborted"));
+    options.signal?.addEventListener("abort", onAbort, { once: true });
+
+    try {
+      if (options.signal?.aborted) {
+        throw ClavisError.invalidOperation("Call aborted");
+      }
+      const request = this.codec.encode(type, data);
+      const payload = new Uint8Array(4 + request.length);
+      payload.set(encodeCredits(window), 0);
+      payload.set(request, 4);
+      await this.send(RpcFrameKind.StreamRequest, id, payload);
+
+      for await (const item of stream.queue) {
+        yield item;
+      }
+    } finally {
+      options.signal?.removeEventListener("abort", onAbort);
+      this.streams.delete(id);
+      if (!stream.finished && !this.closedReason) {
+        this.send(RpcFrameKind.Cancel, id, EMPTY).catch((error) => this.reportError(error));
+      }
+    }
+  }
+
+  /**
+   * Open a client-streaming call: send `type`/`data`, upload every packet
+   * from `items`, then wait for the single reply.
+   * Uploading pauses whenever the peer's credit window is exhausted; if the
+   * peer replies early, the remaining items are not sent.
+   */
+  async callUpload(
+    type: T,
+    data: Uint8Array | undefined,
+    items: AsyncIterable<RouterReply<T>> | Iterable<RouterReply<T>>,
+    options: RpcStreamOptions = {}
+  ): Promise<DecodedMessage<T>> {
+    if (this.closedReason) {
+      throw this.closedReason;
+    }
+    if (options.signal?.aborted) {
+      throw ClavisError.invalidOperation("Call aborted");
+    }
+    if (this.channels?.perCall) {
+      return this.onOwnChannel((child) => child.callUpload(type, data, items, options));
+    }
+    this.start();
+
+    const id = this.allocateId();
+    const gate = new CreditGate();
+    this.uploads.set(id, gate);
+
+    const result = new Promise<DecodedMessage<T>>((resolve, reject) => {
+      this.pending.set(id, { resolve, reject });
+    });
+    // Observed below; avoids an unhandled rejection while still uploading
+    result.catch(() => {});
+
+    const fail = (error: ClavisError) => {
+      const call = this.pending.get(id);
+      this.pending.delete(id);
+      gate.close(error);
+      call?.reject(error);
+    };
+    const onAbort = () => {
+      fail(ClavisError.invalidOperation("Call aborted"));
+      this.send(RpcFrameKind.Cancel, id, EMPTY).catch((error) => this.reportError(error));
+    };
+    options.signal?.addEventListener("abort", onAbort, { once: true });
+
+    try {
+      await this.send(RpcFrameKind.UploadRequest, id, this.codec.encode(type, data));
+      try {
+        for await (const item of items) {
+          await gate.take();
+          await this.send(RpcFrameKind.UploadItem, id, this.codec.encode(item.type, item.data));
+        }
+        await this.send(RpcFrameKind.UploadEnd, id, EMPTY);
+      } catch (error) {
+        // A closed gate means the call already settled; otherwise the source failed
+        if (!gate.isClosed) {
+          fail(toClavisError(error));
+          this.send(RpcFrameKind.Cancel, id, EMPTY).catch((sendError) => this.reportError(sendError));
+        }
+      }
+      return await result;
+    } finally {
+      options.signal?.removeEventListener("abort", onAbort);
+      this.pending.delete(id);
+      this.uploads.delete(id);
+    }
+  }
+
+  /**
+   * Send a one-way packet
+   */
+  async notify(type: T, data?: Uint8Array): Promise<void> {
+    if (this.closedReason) {
+      throw this.closedReason;
+    }
+    await this.send(RpcFrameKind.Message, 0, this.codec.encode(type, data));
+  }
+
+  /**
+   * Stop the connection and reject all pending calls.
+   * The underlying stream is not closed.
+   */
+  close(reason?: ClavisError): void {
+    this.shutdown(reason ?? ClavisError.stream(StreamError.connectionClosed("RPC connection closed")));
+  }
+
+  private allocateId(): number {
+    const id = this.nextId;
+    // Stay within the safe integer range; 0 is reserved for plain messages
+    this.nextId = id >= Number.MAX_SAFE_INTEGER ? 1 : id + 1;
+    return id;
+  }
+
+  /**
+   * Open the next free channel of ours and a connection over it for one
+   * call. `release()` closes both once the call is over.
+   */
+  private async openCallChannel(): Promise<{ child: RpcConnection<T>; release: () => void }> {
+    const channels = this.channels!;
+    let id = channels.nextId;
+    while (channels.mux.hasChannel(id)) id = this.advanceChannelId(id);
+    channels.nextId = this.advanceChannelId(id);
+
+    let channel: LogicalChannel;
+    try {
+      channel = await channels.mux.openChannel(id);
+    } catch (error) {
+      throw markUnsent(toClavisError(error));
+    }
+    this.lastActivity = this.clock.now();
+    const child = this.childOver(channel, undefined);
+    return {
+      child,
+      release: () => {
+        child.close();
+        channel.writer.close().catch((error) => this.reportError(error));
+      },
+    };
+  }
+
+  private advanceChannelId(id: number): number {
+    const first = this.channels!.first;
+    return id + 2 > MAX_CHANNEL_ID ? first : id + 2;
+  }
+
+  private async onOwnChannel<R>(call: (child: RpcConnection<T>) => Promise<R>): Promise<R> {
+    const { child, release } = await this.openCallChannel();
+    try {
+      return await call(child);
+    } finally {
+      release();
+    }
+  }
+
+  /** Serve the call on a channel the peer opened, then close our side of it */
+  private serveChannel(channel: LogicalChannel): void {
+    if (this.closedReason) {
+      channel.writer.close().catch((error) => this.reportError(error));
+      return;
+    }
+    const child = this.childOver(channel, this.router);
+    child.once("close", () => {
+      channel.writer.close().catch((error) => this.reportError(error));
+    });
+    child.start();
+  }
+
+  private childOver(channel: LogicalChannel, router: PacketRouter<T> | undefined): RpcConnection<T> {
+    const child = new RpcConnection<T>(channel.reader, channel.writer, {
+      codec: this.codec,
+      router,
+      decodeError: this.decodeError,
+      uploadWindow: this.uploadWindow,
+      clock: this.clock,
+      hedges: this.hedges,
+    });
+    this.children.add(child);
+    child.on("error", (error) => this.reportError(error));
+    child.once("close", () => this.children.delete(child));
+    return child;
+  }
+
+  private async send(kind: RpcFrameKind, id: number, payload: Uint8Array): Promise<void> {
+    this.lastActivity = this.clock.now();
+    await this.writer.writePacket(new RawPacket(encodeRpcEnvelope(kind, id, payload)));
+  }
+
+  private sendCredits(kind: RpcFrameKind, id: number, credits: number): void {
+    if (this.closedReason) return;
+    this.send(kind, id, encodeCredits(credits)).catch((error) => this.reportError(error));
+  }
+
+  private async readLoop(): Promise<void> {
+    while (!this.closedReason) {
+      let batch: Uint8Array[];
+      try {
+        batch = (await this.reader.readPackets()) as unknown as Uint8Array[];
+      } catch (error) {
+        this.shutdown(toClavisError(error));
+        return;
+      }
+
+      this.lastActivity = this.clock.now();
+      for (const data of batch) {
+        try {
+          this.handleEnvelope(decodeRpcEnvelope(data));
+        } catch (error) {
+          this.reportError(error);
+        }
+      }
+    }
+  }
+
+  private handleEnvelope(envelope: RpcEnvelope): void {
+    switch (envelope.kind) {
+      case RpcFrameKind.Request:
+        void this.serveRequest(envelope.id, envelope.payload, undefined);
+        return;
+      case RpcFrameKind.HedgedRequest:
+        if (envelope.payload.length < HEDGE_GROUP_LENGTH) {
+          throw ClavisError.deserializationFailed("Hedged request too short for its group ID");
+        }
+        void this.serveRequest(
+          envelope.id,
+          envelope.payload.subarray(HEDGE_GROUP_LENGTH),
+          envelope.payload.subarray(0, HEDGE_GROUP_LENGTH)
+        );
+        return;
+      case RpcFrameKind.Message:
+        void this.serveMessage(envelope.payload);
+        return;
+      case RpcFrameKind.Response:
+      case RpcFrameKind.Failure:
+        this.settle(envelope);
+        return;
+      case RpcFrameKind.StreamRequest:
+        void this.serveStream(envelope.id, envelope.payload);
+        return;
+      case RpcFrameKind.StreamItem:
+        this.receiveStreamItem(envelope);
+        return;
+      case RpcFrameKind.StreamEnd: {
+        const stream = this.streams.get(envelope.id);
+        if (stream) {
+          stream.finished = true;
+          stream.queue.end();
+        }
+        return;
+      }
+      case RpcFrameKind.StreamCredit:
+        this.servingStreams.get(envelope.id)?.gate.add(readU32(envelope.payload, 0).value);
+        return;
+      case RpcFrameKind.UploadRequest:
+        void this.serveUpload(envelope.id, envelope.payload);
+        return;
+      case RpcFrameKind.UploadItem:
+        this.receiveUploadItem(envelope);
+        return;
+      case RpcFrameKind.UploadEnd:
+        this.servingUploads.get(envelope.id)?.end();
+        return;
+      case RpcFrameKind.UploadCredit:
+        this.uploads.get(envelope.id)?.add(readU32(envelope.payload, 0).value);
+        return;
+      case RpcFrameKind.Cancel:
+        this.cancelServing(envelope.id);
+        return;
+    }
+  }
+
+  private settle(envelope: RpcEnvelope): void {
+    const outcome = this.decodeReply(envelope);
+    const call = this.pending.get(envelope.id);
+    if (call) {
+      this.pending.delete(envelope.id);
+      if (outcome instanceof Error) {
+        call.reject(outcome);
+      } else {
+        call.resolve(outcome);
+      }
+    }
+
+    // Replies to streaming calls end the upload or the item stream
+    const error = outcome instanceof Error ? outcome : ClavisError.invalidOperation("Streaming call already answered");
+    this.uploads.get(envelope.id)?.close(error);
+    const stream = this.streams.get(envelope.id);
+    if (stream) {
+      stream.finished = true;
+      stream.queue.end(error);
+    }
+    // Anything else is a late reply for a call that was already settled
+  }
+
+  private decodeReply(envelope: RpcEnvelope): DecodedMessage<T> | Error {
+    if (envelope.kind === RpcFrameKind.Failure) {
+      let message = "Remote handler failed";
+      try {
+        message = new BincodeReader(envelope.payload).readString();
+      } catch {
+        // Keep the generic message
+      }
+      return new RpcError(message);
+    }
+
+    let reply: DecodedMessage<T>;
+    try {
+      reply = this.codec.decode(envelope.payload);
+    } catch (error) {
+      return toClavisError(error);
+    }
+    return this.codec.isError(reply.type) ? this.decodeError(reply) : reply;
+  }
+
+  private receiveStreamItem(envelope: RpcEnvelope): void {
+    const stream = this.streams.get(envelope.id);
+    if (!stream) return;
+
+    const item = this.decodeReply(envelope);
+    if (item instanceof Error) {
+      stream.finished = true;
+      stream.queue.end(item);
+    } else if (stream.queue.size >= stream.window) {
+      stream.queue.end(windowOverrun(stream.window));
+    } else {
+      stream.queue.push(item);
+    }
+  }
+
+  private receiveUploadItem(envelope: RpcEnvelope): void {
+    const queue = this.servingUploads.get(envelope.id);
+    if (!queue) return;
+
+    try {
+      if (queue.size >= this.uploadWindow) {
+        throw windowOverrun(this.uploadWindow);
+      }
+      queue.push(this.codec.decode(envelope.payload));
+    } catch (error) {
+      queue.end(toClavisError(error));
+    }
+  }
+
+  private cancelServing(id: number): void {
+    const request = this.servingRequests.get(id);
+    if (request) request.cancelled = true;
+    const cancelled = ClavisError.invalidOperation("Call cancelled by peer");
+    const serving = this.servingStreams.get(id);
+    if (serving) {
+      serving.abort.abort(cancelled);
+      serving.gate.close(cancelled);
+    }
+    this.servingUploads.get(id)?.end(cancelled);
+  }
+
+  private async serveRequest(id: number, payload: Uint8Array, group: Uint8Array | undefined): Promise<void> {
+    const serving: ServingRequest = { cancelled: false };
+    this.servingRequests.set(id, serving);
+    let reply: { kind: RpcFrameKind; response: Uint8Array };
+    try {
+      // Copies of a hedged request share the run of the first to arrive
+      reply = group !== undefined && this.hedges
+        ? await this.hedges.run(group, () => this.handleRequest(payload))
+        : await this.handleRequest(payload);
+    } finally {
+      this.servingRequests.delete(id);
+    }
+
+    if (serving.cancelled) {
+      this.hedges?.suppressed();
+      return;
+    }
+    try {
+      await this.send(reply.kind, id, reply.response);
+    } catch (error) {
+      this.reportError(error);
+    }
+  }
+
+  /** Run a request's handler; resolves with the envelope to answer with, never rejects */
+  private async handleRequest(payload: Uint8Array): Promise<{ kind: RpcFrameKind; response: Uint8Array }> {
+    try {
+      if (!this.router) {
+        throw ClavisError.invalidOperation("No router configured for incoming requests");
+      }
+      const reply = await this.router.dispatch(this.codec.decode(payload));
+      if (!reply) {
+        throw ClavisError.invalidOperation("Handler produced no reply");
+      }
+      return { kind: RpcFrameKind.Response, response: this.codec.encode(reply.type, reply.data) };
+    } catch (error) {
+      return { kind: RpcFrameKind.Failure, response: encodeErrorMessage(error) };
+    }
+  }
+
+  private async serveStream(id: number, payload: Uint8Array): Promise<void> {
+    let message: DecodedMessage<T>;
+    let handler: StreamRouteHandler<T> | undefined;
+    let window: number;
+    try {
+      window = readU32(payload, 0).value;
+      message = this.codec.decode(payload.subarray(4));
+      handler = this.router?.streamHandler(message.type);
+      if (!handler) {
+        throw ClavisError.invalidOperation(`No stream handler registered for ${message.type}`);
+      }
+    } catch (error) {
+      await this.sendFailure(id, error);
+      return;
+    }
+
+    const serving: ServingStream = { gate: new CreditGate(window), abort: new AbortController() };
+    this.servingStreams.set(id, serving);
+
+    try {
+      for await (const reply of handler(message, serving.abort.signal)) {
+        await serving.gate.take();
+        await this.send(RpcFrameKind.StreamItem, id, this.codec.encode(reply.type, reply.data));
+      }
+      await this.send(RpcFrameKind.StreamEnd, id, EMPTY);
+    } catch (error) {
+      // A cancelled caller is no longer listening
+      if (!serving.abort.signal.aborted) {
+        await this.sendFailure(id, error);
+      }
+    } finally {
+      this.servingStreams.delete(id);
+    }
+  }
+
+  private async serveUpload(id: number, payload: Uint8Array): Promise<void> {
+    let message: DecodedMessage<T>;
+    let handler: UploadRouteHandler<T> | undefined;
+    try {
+      message = this.codec.decode(payload);
+      handler = this.router?.uploadHandler(message.type);
+      if (!handler) {
+        throw ClavisError.invalidOperation(`No upload handler registered for ${message.type}`);
+      }
+    } catch (error) {
+      await this.sendFailure(id, error);
+      return;
+    }
+
+    const window = this.uploadWindow;
+    let consumed = 0;
+    const queue = new AsyncQueue<DecodedMessage<T>>(() => {
+      if (++consumed >= Math.ceil(window / 2)) {
+        this.sendCredits(RpcFrameKind.UploadCredit, id, consumed);
+        consumed = 0;
+      }
+    });
+    this.servingUploads.set(id, queue);
+
+    try {
+      await this.send(RpcFrameKind.UploadCredit, id, encodeCredits(window));
+      const reply = await handler(message, queue);
+      await this.send(RpcFrameKind.Response, id, this.codec.encode(reply.type, reply.data));
+    } catch (error) {
+      await this.sendFailure(id, error);
+    } finally {
+      this.servingUploads.delete(id);
+      queue.end();
+    }
+  }
+
+  /**
+   * Report a handler failure to the caller: as the error variant when the
+   * router has one, otherwise as a Failure frame
+   */
+  private async sendFailure(id: number, error: unknown): Promise<void> {
+    let kind = RpcFrameKind.Failure;
+    let payload: Uint8Array;
+    try {
+      if (!this.router) {
+        throw error;
+      }
+      const reply = this.router.mapFailure(error);
+      payload = this.codec.encode(reply.type, reply.data);
+      kind = RpcFrameKind.Response;
+    } catch (unmapped) {
+      payload = encodeErrorMessage(unmapped);
+    }
+
+    try {
+      await this.send(kind, id, payload);
+    } catch (sendError) {
+      this.reportError(sendError);
+    }
+  }
+
+  private async serveMessage(payload: Uint8Array): Promise<void> {
+    try {
+      const message = this.codec.decode(payload);
+      if (this.router?.has(message.type)) {
+        await this.router.dispatch(message);
+      } else {
+        this.messages?.push(message);
+        this.emit("message", message);
+      }
+    } catch (error) {
+      this.reportError(error);
+    }
+  }
+
+  private reportError(error: unknown): void {
+    // An unhandled "error" event would throw out of the read loop
+    if (this.listenerCount("error") > 0) {
+      this.emit("error", toClavisError(error));
+    }
+  }
+
+  private shutdown(reason: ClavisError): void {
+    if (this.closedReason) return;
+    this.closedReason = reason;
+    this.running = false;
+
+    for (const call of this.pending.values()) {
+      call.reject(reason);
+    }
+    this.pending.clear();
+    for (const stream of this.streams.values()) {
+      stream.queue.end(reason);
+    }
+    for (const gate of this.uploads.values()) {
+      gate.close(reason);
+    }
+    for (const serving of this.servingStreams.values()) {
+      serving.abort.abort(reason);
+      serving.gate.close(reason);
+    }
+    for (const queue of this.servingUploads.values()) {
+      queue.end(reason);
+    }
+    for (const child of this.children) {
+      child.shutdown(reason);
+    }
+    this.endInbox(reason);
+    this.emit("close", reason);
+  }
+
+  private endInbox(reason: ClavisError): void {
+    const cause = reason.cause;
+    this.messages?.end(cause instanceof StreamError && cause.isConnectionClosed() ? undefined : reason);
+  }
+}
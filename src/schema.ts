@@ -0,0 +1,255 @@
+/**
+ * Packet schemas
+ * Field-level struct descriptions with schema hashing and append-only evolution checks
+ *
+ * Bincode is not self-describing: a struct is just its fields in order. A
+ * field can therefore only be added without breaking older peers if it is
+ * appended at the end and optional, so that its absence decodes as None.
+ */
+
+import { ClavisError } from "./error.js";
+import { sha256Hash } from "./crypto.js";
+import {
+  BincodeReader,
+  writeBool,
+  writeDateTime,
+  writeI32,
+  writeI64,
+  writeString,
+  writeU16,
+  writeU32,
+  writeU64,
+  writeU8,
+} from "./bincode.js";
+
+/**
+ * Wire type of a schema field
+ */
+export type FieldType = "u8" | "u16" | "u32" | "i32" | "u64" | "i64" | "bool" | "string" | "bytes" | "datetime";
+
+/**
+ * A single struct field
+ */
+export interface SchemaField {
+  name: string;
+  type: FieldType;
+  /** Encoded as Option<T> (default: false) */
+  optional?: boolean | undefined;
+  /**
+   * For bytes fields: decode as a view into the packet instead of a copy
+   * (default: false). Saves copying large blobs; the view keeps the whole
+   * packet in memory for as long as it is referenced. Does not affect the
+   * wire format or the schema hash.
+   */
+  view?: boolean | undefined;
+}
+
+/**
+ * Evolution rule for a schema.
+ * `"append-only"` requires every version to keep the previous fields
+ * unchanged and only add optional fields at the end.
+ */
+export type SchemaEvolution = "none" | "append-only";
+
+/**
+ * Options for defining a schema
+ */
+export interface SchemaOptions {
+  /** Evolution rule (default: "none") */
+  evolution?: SchemaEvolution | undefined;
+  /** Previous version of this schema; checked against the evolution rule */
+  previous?: PacketSchema | undefined;
+}
+
+/**
+ * A defined packet schema
+ */
+export interface PacketSchema {
+  readonly name: string;
+  readonly fields: readonly SchemaField[];
+  readonly evolution: SchemaEvolution;
+  /** Hex SHA-256 of the canonical schema description */
+  readonly hash: string;
+  /** Hashes of this and every earlier version this schema can decode */
+  readonly compatibleHashes: readonly string[];
+  /** Number of fields every accepted version carries */
+  readonly requiredFieldCount: number;
+}
+
+function toHex(bytes: Uint8Array): string {
+  return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+function describeField(field: SchemaField): string {
+  return `${field.name}:${field.optional ? `Option<${field.type}>` : field.type}`;
+}
+
+/**
+ * Canonical text description of a schema, as hashed by `defineSchema`
+ */
+export function describeSchema(name: string, fields: readonly SchemaField[]): string {
+  return `${name}{${fields.map(describeField).join(",")}}`;
+}
+
+/**
+ * Compute the schema hash for a struct description
+ */
+export function schemaHash(name: string, fields: readonly SchemaField[]): string {
+  return toHex(sha256Hash(new TextEncoder().encode(describeSchema(name, fields))));
+}
+
+/**
+ * Check that `next` only appends optional fields to `previous`.
+ * Throws a config error describing the first violation.
+ */
+export function checkAppendOnly(previous: PacketSchema, next: { name: string; fields: readonly SchemaField[] }): void {
+  if (previous.name !== next.name) {
+    throw ClavisError.config(`Schema ${next.name} cannot evolve from ${previous.name}`);
+  }
+  if (next.fields.length < previous.fields.length) {
+    throw ClavisError.config(`Schema ${next.name} removes fields from the previous version`);
+  }
+
+  previous.fields.forEach((field, i) => {
+    const current = next.fields[i]!;
+    if (describeField(field) !== describeField(current)) {
+      throw ClavisError.config(
+        `Schema ${next.name} changes field ${i} from ${describeField(field)} to ${describeField(current)}`
+      );
+    }
+  });
+
+  for (const field of next.fields.slice(previous.fields.length)) {
+    if (!field.optional) {
+      throw ClavisError.config(`Schema ${next.name} appends required field ${field.name}; appended fields must be optional`);
+    }
+  }
+}
+
+/**
+ * Define a packet schema.
+ *
+ * @example
+ * ```typescript
+ * const StatusV1 = defineSchema("Status", [
+ *   { name: "users_online", type: "u32" },
+ * ], { evolution: "append-only" });
+ *
+ * const StatusV2 = defineSchema("Status", [
+ *   { name: "users_online", type: "u32" },
+ *   { name: "region", type: "string", optional: true },
+ * ], { evolution: "append-only", previous: StatusV1 });
+ *
+ * // Packets from V1 senders decode with region = undefined
+ * const status = decodeStruct(StatusV2, reader);
+ * ```
+ */
+export function defineSchema(
+  name: string,
+  fields: readonly SchemaField[],
+  options: SchemaOptions = {}
+): PacketSchema {
+  const evolution = options.evolution ?? "none";
+  const seen = new Set<string>();
+  for (const field of fields) {
+    if (seen.has(field.name)) {
+      throw ClavisError.config(`Schema ${name} has duplicate field ${field.name}`);
+    }
+    seen.add(field.name);
+  }
+
+  const previous = options.previous;
+  if (previous) {
+    if (evolution !== "append-only" || previous.evolution !== "append-only") {
+      throw ClavisError.config(`Schema ${name} must use append-only evolution to declare a previous version`);
+    }
+    checkAppendOnly(previous, { name, fields });
+  }
+
+  const hash = schemaHash(name, fields);
+  return {
+    name,
+    fields,
+    evolution,
+    hash,
+    compatibleHashes: previous ? [...previous.compatibleHashes, hash] : [hash],
+    requiredFieldCount: previous ? previous.requiredFieldCount : fields.length,
+  };
+}
+
+function writeField(buffer: number[], field: SchemaField, value: unknown): void {
+  if (field.optional) {
+    if (value === undefined || value === null) {
+      writeU8(buffer, 0);
+      return;
+    }
+    writeU8(buffer, 1);
+  } else if (value === undefined || value === null) {
+    throw ClavisError.serializationFailed(`Missing required field ${field.name}`);
+  }
+
+  switch (field.type) {
+    case "u8": writeU8(buffer, value as number); break;
+    case "u16": writeU16(buffer, value as number); break;
+    case "u32": writeU32(buffer, value as number); break;
+    case "i32": writeI32(buffer, value as number); break;
+    case "u64": writeU64(buffer, BigInt(value as number | bigint)); break;
+    case "i64": writeI64(buffer, BigInt(value as number | bigint)); break;
+    case "bool": writeBool(buffer, value as boolean); break;
+    case "string": writeString(buffer, value as string); break;
+    case "datetime": writeDateTime(buffer, value as Date); break;
+    case "bytes": {
+      const bytes = value as Uint8Array;
+      writeU64(buffer, BigInt(bytes.length));
+      for (const b of bytes) buffer.push(b);
+      break;
+    }
+  }
+}
+
+function readField(reader: BincodeReader, field: SchemaField): unknown {
+  if (field.optional && reader.readU8() === 0) {
+    return undefined;
+  }
+
+  switch (field.type) {
+    case "u8": return reader.readU8();
+    case "u16": return reader.readU16();
+    case "u32": return reader.readU32();
+    case "i32": return reader.readI32();
+    case "u64": return reader.readU64();
+    case "i64": return reader.readI64();
+    case "bool": return reader.readBool();
+    case "string": return reader.readString();
+    case "datetime": return reader.readDateTime();
+    case "bytes": return field.view ? reader.readBytesView() : reader.readBytes();
+  }
+}
+
+/**
+ * Serialize a struct according to its schema
+ */
+export function encodeStruct(schema: PacketSchema, value: Record<string, unknown>): Uint8Array {
+  const buffer: number[] = [];
+  for (const field of schema.fields) {
+    writeField(buffer, field, value[field.name]);
+  }
+  return new Uint8Array(buffer);
+}
+
+/**
+ * Deserialize a struct according to its schema.
+ * With append-only evolution, fields added after the first version may be
+ * missing from the end of the data and decode as undefined. This only holds
+ * when the struct is the last thing in the packet.
+ */
+export function decodeStruct(schema: PacketSchema, reader: BincodeReader): Record<string, unknown> {
+  const value: Record<string, unknown> = {};
+  schema.fields.forEach((field, i) => {
+    value[field.name] =
+      schema.evolution === "append-only" && i >= schema.requiredFieldCount
+        ? reader.readTrailing((r) => readField(r, field))
+        : readField(reader, field);
+  });
+  return value;
+}
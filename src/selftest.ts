@@ -0,0 +1,216 @@
+/**
+ * Compatibility self-test
+ * Checks what a peer running the diagnostics responder accepts, from the
+ * handshake through packet size boundaries to each optional extension
+ *
+ * Every check opens its own connection, so one failure never hides the
+ * next. The core checks must pass for the peer to be usable at all:
+ * - handshake: the connection sets up with the given options
+ * - ping: a diagnostics round trip
+ * - echo: packets of the boundary sizes, a 0- and 1-byte payload and
+ *   packets of `maxPacketSize - 1` and `maxPacketSize` bytes, come back
+ *   intact
+ *
+ * Extensions are probed one at a time on top of the given options. A probe
+ * failing means the peer doesn't run with that extension enabled; since
+ * extensions need both peers to opt in, a peer that requires one fails the
+ * core checks instead.
+ */
+
+import type { Readable, Writable } from "stream";
+import { randomBytes } from "@noble/hashes/utils.js";
+import { EncryptedStream, type EncryptedStreamOptions } from "./stream.js";
+import { Diag, decodeDiag, runDiag } from "./diag.js";
+import { supportedCompressionAlgorithms } from "./compression.js";
+
+/** Bytes an Echo packet adds to its payload: variant index (4) and length (8) */
+const ECHO_HEADER_LENGTH = 12;
+const DEFAULT_TIMEOUT_MS = 5000;
+
+/**
+ * Options for `runSelfTest`
+ */
+export interface SelfTestOptions {
+  /** Open a fresh transport to the peer; called once per check */
+  connect: () => Promise<Readable & Writable>;
+  /** Options of every connection, such as `psk` (default: none) */
+  streamOptions?: EncryptedStreamOptions | undefined;
+  /** Milliseconds each connection may spend setting up and on each read (default: 5000) */
+  timeoutMs?: number | undefined;
+}
+
+/**
+ * Outcome of one check
+ */
+export interface SelfTestCheck {
+  name: string;
+  ok: boolean;
+  /** What was measured or negotiated, or why the check failed */
+  detail: string;
+}
+
+/**
+ * Results of `runSelfTest`
+ */
+export interface SelfTestReport {
+  /** Whether every core check passed */
+  ok: boolean;
+  /** Handshake, round trip and packet size checks */
+  checks: SelfTestCheck[];
+  /** Optional extensions, each probed on its own connection */
+  extensions: SelfTestCheck[];
+}
+
+interface Probe {
+  name: string;
+  options: EncryptedStreamOptions;
+  describe: (stream: EncryptedStream) => string;
+}
+
+const PROBES: readonly Probe[] = [
+  {
+    name: "post-quantum",
+    options: { postQuantum: "require" },
+    describe: () => "ML-KEM-768 mixed into the keys",
+  },
+  {
+    name: "cipher suites",
+    options: { cipherSuites: ["aes-256-gcm", "xchacha20-poly1305"] },
+    describe: (stream) => `chose ${stream.cipherSuite}`,
+  },
+  {
+    name: "payload formats",
+    options: { format: ["bincode", "msgpack", "cbor", "json"] },
+    describe: (stream) => `chose ${stream.payloadFormat}`,
+  },
+  {
+    name: "compression",
+    options: { compression: { algorithms: supportedCompressionAlgorithms() } },
+    describe: (stream) => `chose ${stream.compressionAlgorithm ?? "no algorithm"}`,
+  },
+  {
+    name: "padding",
+    options: { padding: {} },
+    describe: () => "padme",
+  },
+  {
+    name: "session resumption",
+    options: { resumption: true },
+    describe: (stream) => (stream.sessionTicket ? "ticket granted" : "accepted, but the peer issues no tickets"),
+  },
+];
+
+function failure(name: string, error: unknown): SelfTestCheck {
+  return { name, ok: false, detail: error instanceof Error ? error.message : String(error) };
+}
+
+/**
+ * Run every check against a diagnostics responder (`serveDiag`)
+ *
+ * @example
+ * ```typescript
+ * const report = await runSelfTest({
+ *   connect: () => connectTcp("api.example.com", 7272),
+ *   streamOptions: { psk },
+ * });
+ * console.log(formatSelfTestReport(report));
+ * ```
+ */
+export async function runSelfTest(options: SelfTestOptions): Promise<SelfTestReport> {
+  const timeoutMs = options.timeoutMs ?? DEFAULT_TIMEOUT_MS;
+  const base: EncryptedStreamOptions = {
+    ...options.streamOptions,
+    handshakeTimeoutMs: timeoutMs,
+    readTimeoutMs: timeoutMs,
+  };
+  const withStream = async <R>(extra: EncryptedStreamOptions, use: (stream: EncryptedStream) => Promise<R>): Promise<R> => {
+    const transport = await options.connect();
+    try {
+      const stream = await EncryptedStream.new(transport, { ...base, ...extra });
+      const result = await use(stream);
+      await stream.close().catch(() => undefined);
+      return result;
+    } finally {
+      transport.destroy();
+    }
+  };
+
+  const checks: SelfTestCheck[] = [];
+  let maxPacketSize = 0;
+  try {
+    checks.push(await withStream({}, async (stream) => {
+      maxPacketSize = stream.maxPacketSize;
+      const timings = stream.handshakeTimings;
+      return {
+        name: "handshake",
+        ok: true,
+        detail: `${timings.totalMs.toFixed(1)} ms, ${stream.cipherSuite}, max packet ${maxPacketSize} B`,
+      };
+    }));
+  } catch (error) {
+    checks.push(failure("handshake", error));
+    return { ok: false, checks, extensions: [] };
+  }
+
+  try {
+    checks.push(await withStream({}, async (stream) => {
+      const report = await runDiag(stream, { pings: 3, echoSize: 0 });
+      return { name: "ping", ok: true, detail: `rtt ${report.rtt.avg.toFixed(2)} ms` };
+    }));
+  } catch (error) {
+    checks.push(failure("ping", error));
+  }
+
+  const sizes: Array<[string, number]> = [
+    ["echo 0 B payload", 0],
+    ["echo 1 B payload", 1],
+    [`echo ${maxPacketSize - 1} B packet`, maxPacketSize - 1 - ECHO_HEADER_LENGTH],
+    [`echo ${maxPacketSize} B packet`, maxPacketSize - ECHO_HEADER_LENGTH],
+  ];
+  for (const [name, payloadSize] of sizes) {
+    try {
+      checks.push(await withStream({}, async (stream) => {
+        const payload = randomBytes(Math.max(payloadSize, 0));
+        await stream.writePacket(Diag.Echo(payload));
+        const reply = decodeDiag((await stream.readPacket()) as unknown as Uint8Array);
+        const intact = reply.type === "Echo"
+          && reply.payload.length === payload.length
+          && reply.payload.every((byte, i) => byte === payload[i]);
+        return { name, ok: intact, detail: intact ? "intact" : `came back as a different ${reply.type}` };
+      }));
+    } catch (error) {
+      checks.push(failure(name, error));
+    }
+  }
+
+  const extensions: SelfTestCheck[] = [];
+  for (const probe of PROBES) {
+    try {
+      extensions.push(await withStream(probe.options, async (stream) => {
+        await runDiag(stream, { pings: 1, echoSize: 16 });
+        return { name: probe.name, ok: true, detail: probe.describe(stream) };
+      }));
+    } catch (error) {
+      extensions.push(failure(probe.name, error));
+    }
+  }
+
+  return { ok: checks.every((check) => check.ok), checks, extensions };
+}
+
+/**
+ * A self-test report as aligned plain text, one check per line
+ */
+export function formatSelfTestReport(report: SelfTestReport): string {
+  const width = Math.max(...[...report.checks, ...report.extensions].map((check) => check.name.length));
+  const line = (status: string, check: SelfTestCheck) => `  ${status.padEnd(5)} ${check.name.padEnd(width)}  ${check.detail}`;
+  const lines = [
+    "checks",
+    ...report.checks.map((check) => line(check.ok ? "ok" : "FAIL", check)),
+  ];
+  if (report.extensions.length > 0) {
+    lines.push("extensions", ...report.extensions.map((check) => line(check.ok ? "yes" : "no", check)));
+  }
+  lines.push(report.ok ? "compatible" : "NOT compatible");
+  return lines.join("\n");
+}
@@ -0,0 +1,194 @@
+/**
+ * Service definitions
+ * Typed client stubs and server bindings generated from request/response variant pairs
+ */
+
+import { ClavisError } from "./error.js";
+import type { BincodeReader } from "./bincode.js";
+import type { DecodedMessage, ProtocolCodec } from "./protocol.js";
+import type { PacketRouter } from "./router.js";
+import type { RpcCallOptions, RpcConnection, RpcStreamOptions } from "./rpc.js";
+
+/**
+ * Encodes and decodes a method's request or response body
+ */
+export interface ValueCodec<V> {
+  encode(value: V): Uint8Array;
+  decode(reader: BincodeReader): V;
+}
+
+/**
+ * A single service method: the request variant and the variant it is answered with
+ */
+export interface ServiceMethod<T extends string> {
+  /** Variant sent by the client */
+  request: T;
+  /** Variant the server replies with */
+  response: T;
+  /** Codec for the request body; without one the body is passed through as raw bytes */
+  input?: ValueCodec<any> | undefined;
+  /** Codec for the response body; without one the decoded message is returned as is */
+  output?: ValueCodec<any> | undefined;
+  /** Server-streaming method: the response variant is sent once per item (default: false) */
+  streaming?: boolean | undefined;
+}
+
+/**
+ * Map of method names to their definitions
+ */
+export type ServiceDefinition<T extends string> = Record<string, ServiceMethod<T>>;
+
+/** Value accepted by a method (raw bytes without an input codec) */
+export type MethodInput<M> = M extends { input: ValueCodec<infer I> } ? I : Uint8Array | undefined;
+
+/** Value returned to the client (the decoded message without an output codec) */
+export type MethodOutput<T extends string, M> = M extends { output: ValueCodec<infer O> } ? O : DecodedMessage<T>;
+
+/** Value produced by the server implementation (raw bytes without an output codec) */
+export type MethodResult<M> = M extends { output: ValueCodec<infer O> } ? O : Uint8Array | undefined;
+
+type ClientArgs<M, O> = undefined extends MethodInput<M>
+  ? [input?: MethodInput<M>, options?: O]
+  : [input: MethodInput<M>, options?: O];
+
+/**
+ * Client stub: one method per service method
+ */
+export type ServiceClient<T extends string, D extends ServiceDefinition<T>> = {
+  [K in keyof D]: D[K] extends { streaming: true }
+    ? (...args: ClientArgs<D[K], RpcStreamOptions>) => AsyncGenerator<MethodOutput<T, D[K]>, void, undefined>
+    : (...args: ClientArgs<D[K], RpcCallOptions>) => Promise<MethodOutput<T, D[K]>>;
+};
+
+/**
+ * Server implementation: one handler per service method
+ */
+export type ServiceImplementation<T extends string, D extends ServiceDefinition<T>> = {
+  [K in keyof D]: D[K] extends { streaming: true }
+    ? (
+        input: MethodInput<D[K]>,
+        context: { message: DecodedMessage<T>; signal: AbortSignal }
+      ) => AsyncIterable<MethodResult<D[K]>> | Iterable<MethodResult<D[K]>>
+    : (
+        input: MethodInput<D[K]>,
+        context: { message: DecodedMessage<T> }
+      ) => MethodResult<D[K]> | Promise<MethodResult<D[K]>>;
+};
+
+/**
+ * A defined service, able to build client stubs and register server handlers
+ */
+export interface Service<T extends string, D extends ServiceDefinition<T>> {
+  readonly codec: ProtocolCodec<T>;
+  readonly methods: D;
+  /** Build a client stub that issues calls over `rpc` */
+  client(rpc: RpcConnection<T>): ServiceClient<T, D>;
+  /** Register an implementation's handlers on `router` */
+  serve(router: PacketRouter<T>, implementation: ServiceImplementation<T, D>): PacketRouter<T>;
+}
+
+/**
+ * Define a service from request/response variant pairs.
+ *
+ * The client stub hides correlation IDs and reply checking; the server side
+ * registers one router handler per method, so an implementation only deals
+ * in request and response values.
+ *
+ * @example
+ * ```typescript
+ * const StatusService = defineService(codec, {
+ *   getStatus: { request: "GetStatus", response: "Status", output: statusCodec },
+ *   subscribe: { request: "Subscribe", response: "Event", output: eventCodec, streaming: true },
+ * });
+ *
+ * StatusService.serve(router, {
+ *   getStatus: () => currentStatus(),
+ *   subscribe: (_input, { signal }) => events(signal),
+ * });
+ *
+ * const client = StatusService.client(rpc);
+ * const status = await client.getStatus();
+ * ```
+ */
+export function defineService<T extends string, const D extends ServiceDefinition<T>>(
+  codec: ProtocolCodec<T>,
+  methods: D
+): Service<T, D> {
+  for (const [name, method] of Object.entries(methods)) {
+    for (const variant of [method.request, method.response]) {
+      if (!codec.isValidType(variant)) {
+        throw ClavisError.config(`Service method ${name} uses unknown variant ${variant}`);
+      }
+    }
+  }
+
+  const encodeInput = (method: ServiceMethod<T>, input: unknown): Uint8Array | undefined =>
+    method.input ? method.input.encode(input) : (input as Uint8Array | undefined);
+
+  const decodeInput = (method: ServiceMethod<T>, message: DecodedMessage<T>): unknown =>
+    method.input ? method.input.decode(message.reader) : message.data;
+
+  const decodeOutput = (name: string, method: ServiceMethod<T>, reply: DecodedMessage<T>): unknown => {
+    if (reply.type !== method.response) {
+      throw ClavisError.deserializationFailed(
+        `Service method ${name} expected ${method.response} but received ${reply.type}`
+      );
+    }
+    return method.output ? method.output.decode(reply.reader) : reply;
+  };
+
+  const encodeOutput = (method: ServiceMethod<T>, result: unknown) => ({
+    type: method.response,
+    data: method.output ? method.output.encode(result) : (result as Uint8Array | undefined),
+  });
+
+  return {
+    codec,
+    methods,
+
+    client(rpc) {
+      const stub: Record<string, unknown> = {};
+      for (const [name, method] of Object.entries(methods)) {
+        if (method.streaming) {
+          stub[name] = async function* (input?: unknown, options?: RpcStreamOptions) {
+            for await (const reply of rpc.callStream(method.request, encodeInput(method, input), options)) {
+              yield decodeOutput(name, method, reply);
+            }
+          };
+        } else {
+          stub[name] = async (input?: unknown, options?: RpcCallOptions) => {
+            const reply = await rpc.call(method.request, encodeInput(method, input), options);
+            return decodeOutput(name, method, reply);
+          };
+        }
+      }
+      return stub as ServiceClient<T, D>;
+    },
+
+    serve(router, implementation) {
+      const handlers = implementation as Record<string, (input: unknown, context: any) => any>;
+      for (const [name, method] of Object.entries(methods)) {
+        const handler = handlers[name];
+        if (!handler) {
+          throw ClavisError.config(`Service implementation is missing method ${name}`);
+        }
+
+        if (method.streaming) {
+          router.onStream(method.request, async function* (message, signal) {
+            const results: AsyncIterable<unknown> | Iterable<unknown> =
+              handler(decodeInput(method, message), { message, signal });
+            for await (const result of results) {
+              yield encodeOutput(method, result);
+            }
+          });
+        } else {
+          router.on(method.request, async (message) => {
+            const result = await handler(decodeInput(method, message), { message });
+            return encodeOutput(method, result);
+          });
+        }
+      }
+      return router;
+    },
+  };
+}
@@ -0,0 +1,145 @@
+/**
+ * Sharded listeners
+ * Spreads accept and handshake work over worker threads sharing one port
+ *
+ * Each shard is a worker thread running the given module. The module calls
+ * `bindShard()`, which binds an `EncryptedListener` to the shared port with
+ * SO_REUSEPORT, and then runs its own accept loop. The kernel balances new
+ * connections across the shards, and each shard keeps its own connections,
+ * so nothing is shared between threads. Requires a runtime with `reusePort`
+ * support in `net.Server.listen()` (Bun, Node.js 22.12+) on Linux.
+ *
+ * @example
+ * ```typescript
+ * // main.ts
+ * const shards = await spawnShards(new URL("./shard.ts", import.meta.url), { port: 7272 });
+ *
+ * // shard.ts
+ * const listener = await bindShard({ streamOptions: { psk } });
+ * for await (const { stream } of listener) { ... }
+ * ```
+ */
+
+import { availableParallelism } from "os";
+import { Worker, isMainThread, parentPort, workerData } from "worker_threads";
+import { ClavisError, StreamError } from "./error.js";
+import { EncryptedListener, type EncryptedListenerOptions } from "./listener.js";
+
+/**
+ * Options for spawning listener shards
+ */
+export interface ShardOptions {
+  /** Port every shard binds; must not be 0 */
+  port: number;
+  /** Host every shard binds (default: "127.0.0.1") */
+  host?: string | undefined;
+  /** Number of worker threads (default: available parallelism) */
+  shards?: number | undefined;
+  /** Structured-cloneable value handed to every shard as `data` */
+  data?: unknown;
+}
+
+/**
+ * What a shard knows about itself
+ */
+export interface ShardContext {
+  /** Index of this shard, from 0 */
+  shardId: number;
+  /** Total number of shards */
+  shards: number;
+  port: number;
+  host: string;
+  data: unknown;
+}
+
+const SHARD_READY = "clavis-shard-ready";
+const SHARD_MARKER = "__clavisShard";
+
+interface ShardWorkerData extends ShardContext {
+  [SHARD_MARKER]: true;
+}
+
+/**
+ * Running shards
+ */
+export class ShardSet {
+  constructor(readonly workers: readonly Worker[]) {}
+
+  /** Stop every shard */
+  async close(): Promise<void> {
+    await Promise.all(this.workers.map((worker) => worker.terminate()));
+  }
+}
+
+/**
+ * Start `shards` worker threads running `module`, and wait until each has
+ * bound its listener with `bindShard()`.
+ * If any shard fails to start, the others are stopped and the error rethrown.
+ */
+export async function spawnShards(module: URL | string, options: ShardOptions): Promise<ShardSet> {
+  const shards = options.shards ?? availableParallelism();
+  if (!Number.isInteger(shards) || shards < 1) {
+    throw ClavisError.config("shards must be a positive integer");
+  }
+  if (options.port === 0) {
+    throw ClavisError.config("Sharded listeners need a fixed port; 0 would give each shard its own");
+  }
+
+  const workers: Worker[] = [];
+  const ready: Promise<void>[] = [];
+  for (let shardId = 0; shardId < shards; shardId++) {
+    const data: ShardWorkerData = {
+      [SHARD_MARKER]: true,
+      shardId,
+      shards,
+      port: options.port,
+      host: options.host ?? "127.0.0.1",
+      data: options.data,
+    };
+    const worker = new Worker(module, { workerData: data });
+    workers.push(worker);
+    ready.push(new Promise((resolve, reject) => {
+      worker.on("message", (message: unknown) => {
+        if (message === SHARD_READY) resolve();
+      });
+      worker.once("error", (error) => reject(ClavisError.stream(StreamError.io(error))));
+      worker.once("exit", (code) => reject(ClavisError.stream(
+        StreamError.connectionClosed(`Shard ${shardId} exited with code ${code} before binding`)
+      )));
+    }));
+  }
+
+  const set = new ShardSet(workers);
+  try {
+    await Promise.all(ready);
+  } catch (error) {
+    await set.close();
+    throw error;
+  }
+  return set;
+}
+
+/**
+ * This thread's shard context, or undefined outside a shard
+ */
+export function shardContext(): ShardContext | undefined {
+  if (isMainThread) return undefined;
+  const data = workerData as Partial<ShardWorkerData> | undefined;
+  if (!data?.[SHARD_MARKER]) return undefined;
+  const { shardId, shards, port, host } = data as ShardWorkerData;
+  return { shardId, shards, port, host, data: data.data };
+}
+
+/**
+ * Bind this shard's listener to the shared port and tell the main thread
+ * it is accepting. Only valid inside a module started by `spawnShards()`.
+ */
+export async function bindShard(options?: EncryptedListenerOptions): Promise<EncryptedListener> {
+  const context = shardContext();
+  if (!context) {
+    throw ClavisError.invalidOperation("bindShard() must run in a worker started by spawnShards()");
+  }
+  const listener = await EncryptedListener.bind(context.port, context.host, { ...options, reusePort: true });
+  parentPort?.postMessage(SHARD_READY);
+  return listener;
+}
@@ -0,0 +1,247 @@
+/**
+ * Shared-memory transport
+ * Duplex streams over SharedArrayBuffer ring buffers, for worker threads of
+ * one process that exchange more data than a socket pair comfortably carries
+ *
+ * Separate processes on the same host connect over a Unix domain socket
+ * instead (`net.connect({ path })`), which EncryptedStream accepts as is.
+ */
+
+import { Duplex } from "stream";
+import { ClavisError } from "./error.js";
+
+/** Default bytes buffered per direction */
+export const DEFAULT_RING_CAPACITY = 1 << 20;
+
+/** Int32 slots ahead of the data: write position, read position, closed flag */
+const WRITE_POS = 0;
+const READ_POS = 1;
+const CLOSED = 2;
+const HEADER_BYTES = 16;
+
+/** Upper bound on a single wait, so a stream destroyed mid-wait stops promptly */
+const WAIT_SLICE_MS = 50;
+
+type WaitAsync = (
+  array: Int32Array,
+  index: number,
+  value: number,
+  timeout?: number
+) => { async: boolean; value: Promise<unknown> | string };
+
+/**
+ * Resolve once `state[index]` may no longer be `value`.
+ * Falls back to a short poll on runtimes without `Atomics.waitAsync`.
+ */
+function waitForChange(state: Int32Array, index: number, value: number): Promise<void> {
+  const waitAsync = (Atomics as unknown as { waitAsync?: WaitAsync }).waitAsync;
+  if (!waitAsync) {
+    return new Promise((resolve) => setTimeout(resolve, 1));
+  }
+  const result = waitAsync(state, index, value, WAIT_SLICE_MS);
+  return result.async ? (result.value as Promise<unknown>).then(() => undefined) : Promise.resolve();
+}
+
+/**
+ * One direction of a shared-memory link: a single-producer,
+ * single-consumer byte ring in a SharedArrayBuffer.
+ * Positions are free-running u32 counters, so the capacity must be a power of two.
+ */
+export class SharedRing {
+  private readonly state: Int32Array;
+  private readonly data: Uint8Array;
+  private readonly mask: number;
+
+  constructor(readonly buffer: SharedArrayBuffer) {
+    const capacity = buffer.byteLength - HEADER_BYTES;
+    if (capacity < 1 || (capacity & (capacity - 1)) !== 0 || capacity > 1 << 30) {
+      throw ClavisError.config("Shared ring capacity must be a power of two up to 1 GiB");
+    }
+    this.state = new Int32Array(buffer, 0, HEADER_BYTES / 4);
+    this.data = new Uint8Array(buffer, HEADER_BYTES);
+    this.mask = capacity - 1;
+  }
+
+  /** Allocate a ring holding up to `capacity` unread bytes */
+  static create(capacity: number = DEFAULT_RING_CAPACITY): SharedRing {
+    if (!Number.isInteger(capacity)) {
+      throw ClavisError.config("Shared ring capacity must be an integer");
+    }
+    return new SharedRing(new SharedArrayBuffer(HEADER_BYTES + capacity));
+  }
+
+  /** Bytes the ring holds when full */
+  get capacity(): number {
+    return this.mask + 1;
+  }
+
+  /** Bytes written and not yet read */
+  get buffered(): number {
+    return (Atomics.load(this.state, WRITE_POS) - Atomics.load(this.state, READ_POS)) >>> 0;
+  }
+
+  /** Whether the writing side has closed the ring */
+  get closed(): boolean {
+    return Atomics.load(this.state, CLOSED) !== 0;
+  }
+
+  /**
+   * Copy as much of `bytes` as fits; returns the number of bytes written.
+   * Only the writing side may call this.
+   */
+  write(bytes: Uint8Array): number {
+    const writePos = Atomics.load(this.state, WRITE_POS) >>> 0;
+    const free = this.capacity - this.buffered;
+    const count = Math.min(free, bytes.length);
+    if (count === 0) return 0;
+
+    const start = writePos & this.mask;
+    const first = Math.min(count, this.capacity - start);
+    this.data.set(bytes.subarray(0, first), start);
+    this.data.set(bytes.subarray(first, count), 0);
+
+    Atomics.store(this.state, WRITE_POS, (writePos + count) | 0);
+    Atomics.notify(this.state, WRITE_POS);
+    return count;
+  }
+
+  /**
+   * Take up to `max` buffered bytes; empty when nothing is buffered.
+   * Only the reading side may call this.
+   */
+  read(max: number = this.capacity): Uint8Array {
+    const readPos = Atomics.load(this.state, READ_POS) >>> 0;
+    const count = Math.min(max, this.buffered);
+    const result = new Uint8Array(count);
+    if (count === 0) return result;
+
+    const start = readPos & this.mask;
+    const first = Math.min(count, this.capacity - start);
+    result.set(this.data.subarray(start, start + first));
+    result.set(this.data.subarray(0, count - first), first);
+
+    Atomics.store(this.state, READ_POS, (readPos + count) | 0);
+    Atomics.notify(this.state, READ_POS);
+    return result;
+  }
+
+  /** Mark the ring closed: the reader sees the end once it has drained it */
+  close(): void {
+    Atomics.store(this.state, CLOSED, 1);
+    Atomics.notify(this.state, WRITE_POS);
+    Atomics.notify(this.state, READ_POS);
+  }
+
+  /** Resolve once the reader may have freed space (or the ring closed) */
+  waitForSpace(): Promise<void> {
+    return waitForChange(this.state, READ_POS, Atomics.load(this.state, READ_POS));
+  }
+
+  /** Resolve once the writer may have added data (or the ring closed) */
+  waitForData(): Promise<void> {
+    return waitForChange(this.state, WRITE_POS, Atomics.load(this.state, WRITE_POS));
+  }
+}
+
+/**
+ * One side of a shared-memory link. Plain data, so it can be handed to a
+ * worker through `workerData` or `postMessage`; the buffers stay shared.
+ */
+export interface SharedMemoryEndpoint {
+  /** Ring this side writes to */
+  send: SharedArrayBuffer;
+  /** Ring this side reads from */
+  receive: SharedArrayBuffer;
+}
+
+/**
+ * Allocate the two rings of a link and return both of its ends.
+ * Keep one and pass the other to the worker, which opens it with
+ * `openSharedMemoryStream`.
+ *
+ * @example
+ * ```typescript
+ * const [local, remote] = createSharedMemoryChannel();
+ * const worker = new Worker("./worker.ts", { workerData: remote });
+ * const stream = await EncryptedStream.new(openSharedMemoryStream(local));
+ * ```
+ */
+export function createSharedMemoryChannel(
+  capacity: number = DEFAULT_RING_CAPACITY
+): [SharedMemoryEndpoint, SharedMemoryEndpoint] {
+  const aToB = SharedRing.create(capacity).buffer;
+  const bToA = SharedRing.create(capacity).buffer;
+  return [
+    { send: aToB, receive: bToA },
+    { send: bToA, receive: aToB },
+  ];
+}
+
+/**
+ * Open one end of a shared-memory link as a duplex stream.
+ * Ending the stream closes its sending ring, so the peer reads to the end
+ * of the data and then sees a clean EOF; destroying it closes both rings.
+ */
+export function openSharedMemoryStream(endpoint: SharedMemoryEndpoint): Duplex {
+  const outgoing = new SharedRing(endpoint.send);
+  const incoming = new SharedRing(endpoint.receive);
+  let pumping = false;
+
+  const stream: Duplex = new Duplex({
+    read() {
+      if (pumping) return;
+      pumping = true;
+      void pump();
+    },
+    write(chunk: Buffer, _encoding, callback) {
+      send(new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.byteLength)).then(
+        () => callback(),
+        (error: Error) => callback(error)
+      );
+    },
+    final(callback) {
+      outgoing.close();
+      callback();
+    },
+    destroy(error, callback) {
+      outgoing.close();
+      incoming.close();
+      callback(error);
+    },
+  });
+
+  async function send(bytes: Uint8Array): Promise<void> {
+    let offset = 0;
+    while (offset < bytes.length) {
+      if (stream.destroyed || outgoing.closed) {
+        throw new Error("Shared memory stream closed");
+      }
+      const written = outgoing.write(bytes.subarray(offset));
+      offset += written;
+      if (written === 0) await outgoing.waitForSpace();
+    }
+  }
+
+  // Move data from the ring into the stream until it stops asking for more
+  async function pump(): Promise<void> {
+    while (!stream.destroyed) {
+      // Check for close before draining: data written before the close is never lost
+      const closed = incoming.closed;
+      const chunk = incoming.read();
+      if (chunk.length > 0) {
+        if (!stream.push(chunk)) {
+          pumping = false;
+          return;
+        }
+        continue;
+      }
+      if (closed) {
+        stream.push(null);
+        return;
+      }
+      await incoming.waitForData();
+    }
+  }
+
+  return stream;
+}
@@ -0,0 +1,163 @@
+/**
+ * Shared writer
+ * One ordered send queue per connection, with cheap handles for every task
+ * that writes to it
+ *
+ * Frames never interleave on a stream, but a chat server with many tasks
+ * pushing to the same client still wants more than that: a bound on how
+ * much may pile up for a slow client, one place where a dead connection is
+ * noticed, and batched flushes instead of a write per packet. A
+ * `SharedWriter` puts a `BatchingWriter` behind a queue limit and hands
+ * out clones of itself; every clone writes to the same queue, in the order
+ * packets were sent. The first failed write fails the whole writer, so
+ * every task learns the connection is gone on its next send.
+ */
+
+import { ClavisError, StreamError } from "./error.js";
+import type { PacketTrait } from "./protocol.js";
+import { BatchingWriter, type BatchTarget, type FlushPolicy, type QueuedWriteOptions } from "./batching.js";
+import { systemClock, type Clock } from "./clock.js";
+
+/**
+ * Options for `SharedWriter.over()`
+ */
+export interface SharedWriterOptions {
+  /**
+   * Packets that may wait to be written; sends beyond that reject with an
+   * Overloaded error (default: 1024)
+   */
+  maxQueued?: number | undefined;
+  /** When queued packets are written (default: adaptive, see `BatchingWriter`) */
+  policy?: FlushPolicy | undefined;
+  /** Time source for batch delays (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+/** State every clone of a shared writer points to */
+interface SharedState {
+  batcher: BatchingWriter;
+  maxQueued: number;
+  queued: number;
+  handles: number;
+  failure: ClavisError | undefined;
+}
+
+const DEFAULT_MAX_QUEUED = 1024;
+
+function toClavisError(error: unknown): ClavisError {
+  if (error instanceof ClavisError) return error;
+  if (error instanceof StreamError) return ClavisError.stream(error);
+  return ClavisError.stream(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+}
+
+/**
+ * A handle on a connection's send queue; `clone()` makes another
+ *
+ * @example
+ * ```typescript
+ * const out = SharedWriter.over(writer, { maxQueued: 256 });
+ * rooms.join("lobby", out.clone());
+ * presence.watch(out.clone());
+ *
+ * // in any task
+ * if (!out.post(Packet.Chat({ from, text }))) kick(client); // queue full or connection gone
+ * ```
+ */
+export class SharedWriter {
+  private released = false;
+
+  private constructor(private readonly state: SharedState) {
+    state.handles++;
+  }
+
+  /** A shared writer over one connection's writer or stream */
+  static over(target: BatchTarget, options: SharedWriterOptions = {}): SharedWriter {
+    const maxQueued = options.maxQueued ?? DEFAULT_MAX_QUEUED;
+    if (!(Number.isInteger(maxQueued) && maxQueued >= 1)) {
+      throw ClavisError.config(`maxQueued must be a positive integer, got ${maxQueued}`);
+    }
+    const batcher = new BatchingWriter(target, options.policy, options.clock ?? systemClock);
+    return new SharedWriter({ batcher, maxQueued, queued: 0, handles: 0, failure: undefined });
+  }
+
+  /** Another handle on the same queue */
+  clone(): SharedWriter {
+    if (this.released) {
+      throw ClavisError.invalidOperation("Cannot clone a released SharedWriter");
+    }
+    return new SharedWriter(this.state);
+  }
+
+  /** Handles on the queue that haven't been released */
+  get handles(): number {
+    return this.state.handles;
+  }
+
+  /** Packets sent and not yet written */
+  get queued(): number {
+    return this.state.queued;
+  }
+
+  /** Error of the write that failed the writer, if one has */
+  get failure(): ClavisError | undefined {
+    return this.state.failure;
+  }
+
+  /**
+   * Queue a packet behind everything sent before it, from any handle.
+   * Resolves once it is written or superseded (see `QueuedWriteOptions`);
+   * rejects if the queue is full, the writer has failed or this handle was
+   * released.
+   */
+  send(packet: PacketTrait, options?: QueuedWriteOptions): Promise<void> {
+    const state = this.state;
+    const refused = this.refusal();
+    if (refused) return Promise.reject(refused);
+    state.queued++;
+    return state.batcher.write(packet, options).then(
+      () => {
+        state.queued--;
+      },
+      (error: unknown) => {
+        state.queued--;
+        state.failure ??= toClavisError(error);
+        throw state.failure;
+      }
+    );
+  }
+
+  /**
+   * Queue a packet without waiting for it. Returns false when it wasn't
+   * queued; a failed write shows up as `failure` and refuses later sends.
+   */
+  post(packet: PacketTrait, options?: QueuedWriteOptions): boolean {
+    if (this.refusal()) return false;
+    this.send(packet, options).catch(() => undefined);
+    return true;
+  }
+
+  /** Write everything queued now, whatever the flush policy */
+  flush(): Promise<void> {
+    return this.state.batcher.flush();
+  }
+
+  /**
+   * Give up this handle. Releasing the last one flushes what is queued;
+   * the target itself is left open.
+   */
+  async release(): Promise<void> {
+    if (this.released) return;
+    this.released = true;
+    if (--this.state.handles === 0) await this.flush();
+  }
+
+  private refusal(): ClavisError | undefined {
+    const state = this.state;
+    if (this.released) return ClavisError.invalidOperation("SharedWriter handle was released");
+    if (state.failure) return state.failure;
+    if (state.queued >= state.maxQueued) {
+      return ClavisError.stream(StreamError.overloaded(`${state.queued} packets already queued for this connection`));
+    }
+    return undefined;
+  }
+}
@@ -0,0 +1,162 @@
+/**
+ * Load shedding
+ * Closes the least valuable connections first when a server is overloaded
+ *
+ * Overload is judged from configurable signals: how many handshakes are
+ * waiting for a slot, how much memory the process uses, or a custom check.
+ * While any of them trips, connections are evicted one at a time, lowest
+ * priority first (ties broken by least recent activity) or least recently
+ * active first, until the signals clear.
+ */
+
+import { ClavisError } from "./error.js";
+import { systemClock, type Clock } from "./clock.js";
+
+/**
+ * Which connections go first
+ */
+export type SheddingOrder = "lowest-priority" | "least-recently-active";
+
+/**
+ * Options for a `LoadShedder`
+ */
+export interface LoadSheddingOptions<C> {
+  /** Overloaded while more handshakes than this are waiting for a slot (default: no limit) */
+  maxQueuedHandshakes?: number | undefined;
+  /** Overloaded while `memoryUsage()` exceeds this many bytes (default: no limit) */
+  memoryBudgetBytes?: number | undefined;
+  /** Memory measured against the budget (default: the process's resident set size) */
+  memoryUsage?: (() => number) | undefined;
+  /** Extra overload signal; return a reason to shed, or undefined */
+  isOverloaded?: (() => string | undefined) | undefined;
+  /** Eviction order (default: "lowest-priority") */
+  order?: SheddingOrder | undefined;
+  /**
+   * Called before each eviction with the connection and the overload reason.
+   * Returning false spares the connection and moves on to the next one.
+   */
+  beforeEvict?: ((connection: C, reason: string) => boolean | void | Promise<boolean | void>) | undefined;
+  /** Time source for activity tracking (default: `systemClock`) */
+  clock?: Clock | undefined;
+}
+
+/**
+ * Handle to a connection registered with a `LoadShedder`
+ */
+export interface TrackedConnection {
+  /** Record activity, so least-recently-active shedding spares it for now */
+  touch(): void;
+  /** Change the connection's priority; higher survives longer */
+  setPriority(priority: number): void;
+  /** Stop tracking, e.g. once the connection closed */
+  untrack(): void;
+}
+
+interface Entry<C> {
+  connection: C;
+  priority: number;
+  lastActive: number;
+}
+
+/**
+ * Tracks connections and evicts them in order while the server is overloaded
+ */
+export class LoadShedder<C> {
+  private readonly entries = new Set<Entry<C>>();
+  private readonly clock: Clock;
+  private shedding = false;
+
+  /**
+   * @param evict - Closes a connection chosen for eviction, given the overload reason
+   * @param queuedHandshakes - Current handshake queue depth, for `maxQueuedHandshakes`
+   */
+  constructor(
+    private readonly options: LoadSheddingOptions<C>,
+    private readonly evict: (connection: C, reason: string) => void,
+    private readonly queuedHandshakes: () => number = () => 0
+  ) {
+    if (options.memoryBudgetBytes !== undefined && !(options.memoryBudgetBytes > 0)) {
+      throw ClavisError.config("memoryBudgetBytes must be positive");
+    }
+    if (options.maxQueuedHandshakes !== undefined && !(options.maxQueuedHandshakes >= 0)) {
+      throw ClavisError.config("maxQueuedHandshakes must not be negative");
+    }
+    this.clock = options.clock ?? systemClock;
+  }
+
+  /** Number of tracked connections */
+  get size(): number {
+    return this.entries.size;
+  }
+
+  /** Register a connection that may be shed */
+  track(connection: C, priority: number = 0): TrackedConnection {
+    const entry: Entry<C> = { connection, priority, lastActive: this.clock.now() };
+    this.entries.add(entry);
+    return {
+      touch: () => {
+        entry.lastActive = this.clock.now();
+      },
+      setPriority: (next) => {
+        entry.priority = next;
+      },
+      untrack: () => {
+        this.entries.delete(entry);
+      },
+    };
+  }
+
+  /** Why the server counts as overloaded right now, or undefined if it isn't */
+  overloadReason(): string | undefined {
+    const { maxQueuedHandshakes, memoryBudgetBytes } = this.options;
+    if (maxQueuedHandshakes !== undefined) {
+      const queued = this.queuedHandshakes();
+      if (queued > maxQueuedHandshakes) {
+        return `${queued} handshakes queued, limit ${maxQueuedHandshakes}`;
+      }
+    }
+    if (memoryBudgetBytes !== undefined) {
+      const used = (this.options.memoryUsage ?? (() => process.memoryUsage().rss))();
+      if (used > memoryBudgetBytes) {
+        return `${used} bytes in use, budget ${memoryBudgetBytes}`;
+      }
+    }
+    return this.options.isOverloaded?.();
+  }
+
+  /**
+   * Evict connections in order while the server is overloaded, at most
+   * `max` of them. Resolves with the evicted connections. A call made while
+   * another is still running returns nothing.
+   */
+  async shed(max: number = Infinity): Promise<C[]> {
+    if (this.shedding) return [];
+    this.shedding = true;
+    const evicted: C[] = [];
+    try {
+      const candidates = this.candidates();
+      for (const entry of candidates) {
+        if (evicted.length >= max) break;
+        const reason = this.overloadReason();
+        if (reason === undefined) break;
+        if (!this.entries.has(entry)) continue;
+        if ((await this.options.beforeEvict?.(entry.connection, reason)) === false) continue;
+        this.entries.delete(entry);
+        this.evict(entry.connection, reason);
+        evicted.push(entry.connection);
+      }
+    } finally {
+      this.shedding = false;
+    }
+    return evicted;
+  }
+
+  private candidates(): Entry<C>[] {
+    const byActivity = (a: Entry<C>, b: Entry<C>) => a.lastActive - b.lastActive;
+    const entries = [...this.entries];
+    if ((this.options.order ?? "lowest-priority") === "least-recently-active") {
+      return entries.sort(byActivity);
+    }
+    return entries.sort((a, b) => a.priority - b.priority || byActivity(a, b));
+  }
+}
@@ -0,0 +1,380 @@
+/**
+ * Simulated network
+ * Named hosts, listeners and connections over in-memory links driven by a
+ * `ManualClock`, for deterministic tests of whole clusters
+ *
+ * Links are shaped like `createChaosPair` (latency, seeded jitter,
+ * bandwidth) and can be partitioned and repaired while connections are
+ * open. Nothing happens until the clock is advanced, so a test simulating
+ * minutes of traffic, timeouts and reconnects runs in milliseconds and
+ * plays out the same way every time.
+ *
+ * `bind()` returns a server an `EncryptedListener` accepts from and
+ * `connector()` plugs into `ClavisClient`; streams, clients and listeners
+ * should be given the network's clock so their timers run on simulated time.
+ * Only timing is simulated: key material is still random.
+ */
+
+import { EventEmitter } from "events";
+import { Duplex } from "stream";
+import type { AddressInfo } from "net";
+import { ManualClock } from "./clock.js";
+import { ClavisError } from "./error.js";
+import { LinkShaper, type LinkProfile } from "./testing.js";
+import type { ConnectionServer } from "./listener.js";
+import type { Connector } from "./client.js";
+
+/**
+ * Options for a `SimNetwork`
+ */
+export interface SimNetworkOptions {
+  /** Clock driving every link (default: a new `ManualClock` starting at 0) */
+  clock?: ManualClock | undefined;
+  /** Profile of every link without its own, in both directions (default: no delay) */
+  link?: LinkProfile | undefined;
+  /** Seed for links whose profile has none; each connection derives its own from it (default: 1) */
+  seed?: number | undefined;
+}
+
+/** First port handed out to outgoing connections */
+const FIRST_EPHEMERAL_PORT = 49152;
+
+function networkError(message: string, code: string): Error {
+  return Object.assign(new Error(message), { code });
+}
+
+/**
+ * One end of a simulated connection
+ */
+export class SimSocket extends Duplex {
+  constructor(
+    readonly localAddress: string,
+    readonly localPort: number,
+    readonly remoteAddress: string,
+    readonly remotePort: number,
+    private readonly send: (chunk: Buffer | null, callback: (error?: Error | null) => void) => void,
+    private readonly onDestroy: () => void
+  ) {
+    super();
+  }
+
+  override _read(): void {}
+
+  override _write(chunk: Buffer, _encoding: BufferEncoding, callback: (error?: Error | null) => void): void {
+    this.send(chunk, callback);
+  }
+
+  override _final(callback: (error?: Error | null) => void): void {
+    this.send(null, callback);
+  }
+
+  override _destroy(error: Error | null, callback: (error?: Error | null) => void): void {
+    this.onDestroy();
+    callback(error);
+  }
+}
+
+/**
+ * A simulated listening socket; pass it to `new EncryptedListener()`
+ */
+export class SimServer extends EventEmitter implements ConnectionServer<SimSocket> {
+  constructor(
+    readonly host: string,
+    readonly port: number,
+    private readonly onClose: () => void
+  ) {
+    super();
+  }
+
+  address(): AddressInfo {
+    return { address: this.host, family: "sim", port: this.port };
+  }
+
+  /** Stop accepting; connections already made stay open */
+  close(callback?: (error?: Error) => void): this {
+    this.onClose();
+    queueMicrotask(() => {
+      callback?.();
+      this.emit("close");
+    });
+    return this;
+  }
+}
+
+/** Sending side of one connection direction */
+interface SimLink {
+  from: string;
+  to: string;
+  peer: SimSocket;
+  shaper: LinkShaper;
+  started: number;
+  lastArrival: number;
+  /** Data sent while the hosts were partitioned; null is the end of the stream */
+  held: Array<Buffer | null>;
+  finished: boolean;
+}
+
+/**
+ * In-memory network of named hosts
+ *
+ * @example
+ * ```typescript
+ * const network = new SimNetwork({ link: { latencyMs: 20 } });
+ * const listener = new EncryptedListener(network.bind("server", 7000));
+ * const client = new ClavisClient({
+ *   host: "server", port: 7000,
+ *   connector: network.connector("client-1"),
+ *   clock: network.clock,
+ * });
+ *
+ * const connecting = client.connect();
+ * await network.clock.advance(1000);
+ * await connecting;
+ *
+ * network.partition("client-1", "server");
+ * await network.clock.advance(60_000); // the client sees nothing for a minute
+ * network.repair("client-1", "server");
+ * ```
+ */
+export class SimNetwork {
+  readonly clock: ManualClock;
+  private readonly defaultLink: LinkProfile;
+  private readonly seed: number;
+  private readonly profiles = new Map<string, LinkProfile>();
+  private readonly servers = new Map<string, SimServer>();
+  private readonly links = new Map<SimSocket, SimLink>();
+  private readonly partitions = new Set<string>();
+  private readonly nextPorts = new Map<string, number>();
+  /** Connection attempts waiting for a partition to heal */
+  private stalled: Array<() => void> = [];
+  private connections = 0;
+  /** Host being crashed; its sockets go away without telling their peers */
+  private crashing: string | undefined;
+
+  constructor(options: SimNetworkOptions = {}) {
+    this.clock = options.clock ?? new ManualClock();
+    this.defaultLink = options.link ?? {};
+    this.seed = options.seed ?? 1;
+  }
+
+  /**
+   * Listen on `host:port`. Throws InvalidOperation if it is already bound.
+   */
+  bind(host: string, port: number): SimServer {
+    const key = `${host}:${port}`;
+    if (this.servers.has(key)) {
+      throw ClavisError.invalidOperation(`${key} is already bound`);
+    }
+    const server = new SimServer(host, port, () => {
+      if (this.servers.get(key) === server) this.servers.delete(key);
+    });
+    this.servers.set(key, server);
+    return server;
+  }
+
+  /**
+   * Open a connection from host `from` to `host:port`. Resolves after a
+   * round trip; rejects with ECONNREFUSED if nothing listens there. While
+   * the hosts are partitioned the attempt waits for `repair()`.
+   */
+  connect(from: string, host: string, port: number): Promise<SimSocket> {
+    return new Promise((resolve, reject) => {
+      const attempt = () => {
+        if (this.isPartitioned(from, host)) {
+          this.stalled.push(attempt);
+          return;
+        }
+        const back = this.profile(host, from).latencyMs ?? 0;
+        this.clock.setTimer(() => {
+          const server = this.servers.get(`${host}:${port}`);
+          if (!server) {
+            const refused = networkError(`connect ECONNREFUSED ${host}:${port}`, "ECONNREFUSED");
+            this.clock.setTimer(() => reject(refused), back);
+            return;
+          }
+          const [client, accepted] = this.open(from, host, port);
+          server.emit("connection", accepted);
+          this.clock.setTimer(() => resolve(client), back);
+        }, this.profile(from, host).latencyMs ?? 0);
+      };
+      attempt();
+    });
+  }
+
+  /** A `ClavisClient` connector opening connections from host `from` */
+  connector(from: string): Connector {
+    return (host, port) => this.connect(from, host, port);
+  }
+
+  /**
+   * Shape traffic from `from` to `to`, including on open connections.
+   * Pass undefined to go back to the network's default link.
+   */
+  setLink(from: string, to: string, profile: LinkProfile | undefined): void {
+    const key = `${from}->${to}`;
+    if (profile === undefined) {
+      this.profiles.delete(key);
+    } else {
+      this.profiles.set(key, profile);
+    }
+    for (const link of this.links.values()) {
+      if (link.from === from && link.to === to) link.shaper = this.shaper(from, to);
+    }
+  }
+
+  /**
+   * Cut the hosts off from each other in both directions. Data sent in the
+   * meantime is held back, like TCP retransmitting, and delivered on repair;
+   * data already on the wire still arrives.
+   */
+  partition(a: string, b: string): void {
+    this.partitions.add(pairKey(a, b));
+  }
+
+  /** Heal a partition, delivering what was held back and resuming connection attempts */
+  repair(a: string, b: string): void {
+    this.partitions.delete(pairKey(a, b));
+    this.resume();
+  }
+
+  /** Heal every partition */
+  repairAll(): void {
+    this.partitions.clear();
+    this.resume();
+  }
+
+  /** Whether the hosts are currently partitioned */
+  isPartitioned(a: string, b: string): boolean {
+    return this.partitions.has(pairKey(a, b));
+  }
+
+  /**
+   * Take a host down: its servers stop listening and its connections vanish
+   * without a close reaching their peers, which only notice once they write
+   * (ECONNRESET) or time out
+   */
+  crash(host: string): void {
+    for (const server of [...this.servers.values()]) {
+      if (server.host === host) server.close();
+    }
+    this.crashing = host;
+    try {
+      for (const [socket, link] of [...this.links]) {
+        if (link.from === host) socket.destroy();
+      }
+    } finally {
+      this.crashing = undefined;
+    }
+  }
+
+  private profile(from: string, to: string): LinkProfile {
+    return this.profiles.get(`${from}->${to}`) ?? this.defaultLink;
+  }
+
+  private shaper(from: string, to: string): LinkShaper {
+    const profile = this.profile(from, to);
+    return new LinkShaper({ ...profile, seed: profile.seed ?? this.seed + this.connections++ });
+  }
+
+  private open(from: string, host: string, port: number): [SimSocket, SimSocket] {
+    const localPort = this.nextPorts.get(from) ?? FIRST_EPHEMERAL_PORT;
+    this.nextPorts.set(from, localPort + 1);
+
+    const outgoing: SimLink[] = [];
+    const socket = (self: string, selfPort: number, peer: string, peerPort: number, index: number) => {
+      const created: SimSocket = new SimSocket(
+        self,
+        selfPort,
+        peer,
+        peerPort,
+        (chunk, callback) => this.transmit(outgoing[index]!, chunk, callback),
+        () => this.closed(created)
+      );
+      return created;
+    };
+    const client = socket(from, localPort, host, port, 0);
+    const accepted = socket(host, port, from, localPort, 1);
+
+    const now = this.clock.now();
+    const link = (self: string, peer: string, target: SimSocket): SimLink => ({
+      from: self,
+      to: peer,
+      peer: target,
+      shaper: this.shaper(self, peer),
+      started: now,
+      lastArrival: 0,
+      held: [],
+      finished: false,
+    });
+    outgoing.push(link(from, host, accepted), link(host, from, client));
+    this.links.set(client, outgoing[0]!);
+    this.links.set(accepted, outgoing[1]!);
+    return [client, accepted];
+  }
+
+  private transmit(link: SimLink, chunk: Buffer | null, callback: (error?: Error | null) => void): void {
+    if (link.peer.destroyed && chunk !== null) {
+      callback(networkError("write ECONNRESET", "ECONNRESET"));
+      return;
+    }
+    if (chunk === null) link.finished = true;
+    if (this.isPartitioned(link.from, link.to) || link.held.length > 0) {
+      link.held.push(chunk);
+      callback();
+      return;
+    }
+    this.deliver(link, chunk, callback);
+  }
+
+  private deliver(link: SimLink, chunk: Buffer | null, callback: (error?: Error | null) => void): void {
+    const now = this.clock.now() - link.started;
+    if (chunk === null) {
+      this.clock.setTimer(() => {
+        if (!link.peer.destroyed) link.peer.push(null);
+      }, Math.max(0, link.lastArrival - now));
+      callback();
+      return;
+    }
+    const { departAt, arriveAt } = link.shaper.schedule(now, chunk.length);
+    // A new shaper after setLink() must not let chunks overtake older ones
+    link.lastArrival = Math.max(arriveAt, link.lastArrival);
+    this.clock.setTimer(() => {
+      if (!link.peer.destroyed) link.peer.push(chunk);
+    }, link.lastArrival - now);
+    // Release the writer once the chunk has left, so bandwidth limits apply backpressure
+    if (departAt <= now) {
+      callback();
+    } else {
+      this.clock.setTimer(() => callback(), departAt - now);
+    }
+  }
+
+  /** A socket was destroyed: its peer reads to the end, unless the host crashed */
+  private closed(socket: SimSocket): void {
+    const link = this.links.get(socket);
+    if (!link) return;
+    this.links.delete(socket);
+    if (this.crashing === link.from || link.finished) return;
+    link.finished = true;
+    if (this.isPartitioned(link.from, link.to) || link.held.length > 0) {
+      link.held.push(null);
+      this.links.set(socket, link);
+      return;
+    }
+    this.deliver(link, null, () => {});
+  }
+
+  private resume(): void {
+    for (const link of this.links.values()) {
+      if (link.held.length === 0 || this.isPartitioned(link.from, link.to)) continue;
+      for (const chunk of link.held.splice(0)) {
+        this.deliver(link, chunk, () => {});
+      }
+    }
+    for (const attempt of this.stalled.splice(0)) attempt();
+  }
+}
+
+function pairKey(a: string, b: string): string {
+  return a < b ? `${a}\n${b}` : `${b}\n${a}`;
+}
@@ -0,0 +1,339 @@
+/**
+ * Storage
+ * One key-value interface for every feature that keeps state outside a
+ * connection (replay cache, packet journal), so a backend is written once
+ *
+ * Values are opaque bytes with an optional expiry. `setIfAbsent` must be
+ * atomic across everything sharing the store; it is what makes single-use
+ * tickets and exactly-once bookkeeping safe. Two backends ship with clavis:
+ * `MemoryStorage` for a single process and `FileStorage` for a directory on
+ * local disk. sled, SQLite or Redis backends only need these five methods.
+ */
+
+import { link, mkdir, open, readFile, readdir, rename, stat, unlink, type FileHandle } from "fs/promises";
+import { basename, join } from "path";
+import { randomBytes } from "crypto";
+import { ClavisError, StreamError } from "./error.js";
+import { wallClock, type Clock } from "./clock.js";
+
+/** A lock file this old belongs to a process that died while clearing an entry */
+const LOCK_STALE_MS = 10_000;
+
+/**
+ * Options for a single write
+ */
+export interface StorageWriteOptions {
+  /** Treat the entry as absent from this time on (ms since the epoch; default: never) */
+  expiresAt?: number | undefined;
+}
+
+/**
+ * Key-value store used by clavis' durable features
+ */
+export interface Storage {
+  /** Value stored under `key`, or undefined if absent or expired */
+  get(key: string): Promise<Uint8Array | undefined>;
+  /** Store `value`, replacing any previous one */
+  set(key: string, value: Uint8Array, options?: StorageWriteOptions): Promise<void>;
+  /** Store `value` only if `key` is absent or expired; resolves whether it was stored */
+  setIfAbsent(key: string, value: Uint8Array, options?: StorageWriteOptions): Promise<boolean>;
+  /** Remove `key`; resolves whether it was there */
+  delete(key: string): Promise<boolean>;
+  /** Live keys starting with `prefix`, sorted */
+  keys(prefix?: string): Promise<string[]>;
+}
+
+interface MemoryEntry {
+  value: Uint8Array;
+  expiresAt: number | undefined;
+}
+
+/**
+ * Storage in a Map, for tests and single-process servers
+ */
+export class MemoryStorage implements Storage {
+  private readonly entries = new Map<string, MemoryEntry>();
+  private readonly clock: Clock;
+
+  constructor(options?: { clock?: Clock | undefined }) {
+    this.clock = options?.clock ?? wallClock;
+  }
+
+  async get(key: string): Promise<Uint8Array | undefined> {
+    return this.live(key)?.value;
+  }
+
+  async set(key: string, value: Uint8Array, options?: StorageWriteOptions): Promise<void> {
+    this.entries.set(key, { value: value.slice(), expiresAt: options?.expiresAt });
+  }
+
+  async setIfAbsent(key: string, value: Uint8Array, options?: StorageWriteOptions): Promise<boolean> {
+    if (this.live(key)) return false;
+    this.entries.set(key, { value: value.slice(), expiresAt: options?.expiresAt });
+    return true;
+  }
+
+  async delete(key: string): Promise<boolean> {
+    return this.entries.delete(key);
+  }
+
+  async keys(prefix: string = ""): Promise<string[]> {
+    return [...this.entries.keys()].filter((key) => key.startsWith(prefix) && this.live(key)).sort();
+  }
+
+  private live(key: string): MemoryEntry | undefined {
+    const entry = this.entries.get(key);
+    if (entry?.expiresAt !== undefined && entry.expiresAt <= this.clock.now()) {
+      this.entries.delete(key);
+      return undefined;
+    }
+    return entry;
+  }
+}
+
+/**
+ * Options for file-backed storage
+ */
+export interface FileStorageOptions {
+  /**
+   * fsync every write before it resolves (default: true). Turn off only for
+   * data that may be lost in a crash.
+   */
+  durable?: boolean | undefined;
+  /** Wall-clock time source for expiries (default: `wallClock`) */
+  clock?: Clock | undefined;
+}
+
+/**
+ * Storage in a directory, one file per key.
+ * Files start with the expiry as a u64 (0 = never) followed by the value.
+ * Writes go to a temporary file that is renamed into place, so a crash never
+ * leaves a half-written value; `setIfAbsent` links the temporary file into
+ * place, which fails atomically if the key exists. Expired files are removed
+ * under a per-key lock file, so of two writers racing for an expired key
+ * only one can free it and win.
+ */
+export class FileStorage implements Storage {
+  private readonly durable: boolean;
+  private readonly clock: Clock;
+  private ready: Promise<void> | undefined;
+
+  constructor(readonly directory: string, options?: FileStorageOptions) {
+    this.durable = options?.durable ?? true;
+    this.clock = options?.clock ?? wallClock;
+  }
+
+  async get(key: string): Promise<Uint8Array | undefined> {
+    checkKey(key);
+    await this.init();
+    const path = this.path(key);
+    let data: Uint8Array;
+    try {
+      data = new Uint8Array(await readFile(path));
+    } catch (error) {
+      if (isNotFound(error)) return undefined;
+      throw toStorageError(error);
+    }
+    if (this.expired(data)) {
+      await this.clearExpired(path);
+      return undefined;
+    }
+    return data.subarray(8);
+  }
+
+  async set(key: string, value: Uint8Array, options?: StorageWriteOptions): Promise<void> {
+    checkKey(key);
+    await this.init();
+    const tmp = this.tmpPath();
+    try {
+      await this.write(tmp, value, options);
+      await rename(tmp, this.path(key));
+      await this.syncDirectory();
+    } catch (error) {
+      throw toStorageError(error);
+    }
+  }
+
+  async setIfAbsent(key: string, value: Uint8Array, options?: StorageWriteOptions): Promise<boolean> {
+    checkKey(key);
+    await this.init();
+    const path = this.path(key);
+    const tmp = this.tmpPath();
+    try {
+      await this.write(tmp, value, options);
+      for (let attempt = 0; attempt < 2; attempt++) {
+        try {
+          // link() fails if the name exists, so exactly one writer wins
+          await link(tmp, path);
+          await this.syncDirectory();
+          return true;
+        } catch (error) {
+          if ((error as { code?: string }).code !== "EEXIST") throw error;
+        }
+        // Taken; free the slot only if what holds it has expired
+        if ((await this.get(key)) !== undefined) return false;
+      }
+      return false;
+    } catch (error) {
+      throw toStorageError(error);
+    } finally {
+      await this.unlinkQuietly(tmp);
+    }
+  }
+
+  async delete(key: string): Promise<boolean> {
+    checkKey(key);
+    await this.init();
+    try {
+      await unlink(this.path(key));
+      await this.syncDirectory();
+      return true;
+    } catch (error) {
+      if (isNotFound(error)) return false;
+      throw toStorageError(error);
+    }
+  }
+
+  async keys(prefix: string = ""): Promise<string[]> {
+    await this.init();
+    let names: string[];
+    try {
+      names = await readdir(this.directory);
+    } catch (error) {
+      throw toStorageError(error);
+    }
+    const keys: string[] = [];
+    for (const name of names) {
+      if (name.startsWith(".")) continue;
+      const key = decodeURIComponent(name);
+      if (key.startsWith(prefix) && (await this.get(key)) !== undefined) keys.push(key);
+    }
+    return keys.sort();
+  }
+
+  private init(): Promise<void> {
+    this.ready ??= mkdir(this.directory, { recursive: true }).then(() => undefined, (error) => {
+      this.ready = undefined;
+      throw toStorageError(error);
+    });
+    return this.ready;
+  }
+
+  /** Key as a file name; `encodeURIComponent` leaves no path separators */
+  private path(key: string): string {
+    const name = encodeURIComponent(key).replace(/^\./, "%2E");
+    return join(this.directory, name);
+  }
+
+  private expired(data: Uint8Array): boolean {
+    if (data.length < 8) return true;
+    const expiresAt = Number(new DataView(data.buffer, data.byteOffset, 8).getBigUint64(0, true));
+    return expiresAt !== 0 && expiresAt <= this.clock.now();
+  }
+
+  /**
+   * Remove the file at `path` if it is still an expired entry. Without the
+   * lock, a writer that read the stale file could delete the fresh one
+   * another writer linked in after removing it, and both would win. A key
+   * someone else is clearing is left to them.
+   */
+  private async clearExpired(path: string): Promise<void> {
+    const lock = join(this.directory, `.lock-${basename(path)}`);
+    let held: FileHandle;
+    try {
+      held = await open(lock, "wx");
+    } catch (error) {
+      if ((error as { code?: string }).code !== "EEXIST" || !(await this.breakStaleLock(lock))) return;
+      try {
+        held = await open(lock, "wx");
+      } catch {
+        return;
+      }
+    }
+    try {
+      let file: FileHandle;
+      try {
+        file = await open(path, "r");
+      } catch (error) {
+        if (isNotFound(error)) return;
+        throw toStorageError(error);
+      }
+      try {
+        if (!this.expired(new Uint8Array(await file.readFile()))) return;
+        // Only the file that was read goes; a value set() renamed in since stays
+        const { ino } = await file.stat();
+        if ((await stat(path)).ino === ino) await unlink(path);
+      } finally {
+        await file.close();
+      }
+    } catch (error) {
+      if (!isNotFound(error)) throw toStorageError(error);
+    } finally {
+      await held.close();
+      await this.unlinkQuietly(lock);
+    }
+  }
+
+  /** Remove a lock left behind by a crashed process; resolves whether it did */
+  private async breakStaleLock(lock: string): Promise<boolean> {
+    try {
+      if (this.clock.now() - (await stat(lock)).mtimeMs < LOCK_STALE_MS) return false;
+      await unlink(lock);
+      return true;
+    } catch {
+      return false;
+    }
+  }
+
+  /** A fresh temporary name, unique across processes and instances sharing the directory */
+  private tmpPath(): string {
+    return join(this.directory, `.tmp-${randomBytes(8).toString("hex")}`);
+  }
+
+  private async write(path: string, value: Uint8Array, options?: StorageWriteOptions): Promise<void> {
+    const header = new Uint8Array(8);
+    new DataView(header.buffer).setBigUint64(0, BigInt(Math.ceil(options?.expiresAt ?? 0)), true);
+    const file = await open(path, "w");
+    try {
+      await file.write(header);
+      await file.write(value);
+      if (this.durable) await file.sync();
+    } finally {
+      await file.close();
+    }
+  }
+
+  private async syncDirectory(): Promise<void> {
+    if (!this.durable) return;
+    const dir = await open(this.directory, "r");
+    try {
+      await dir.sync();
+    } catch {
+      // Not every platform can fsync a directory; the rename is still atomic
+    } finally {
+      await dir.close();
+    }
+  }
+
+  private async unlinkQuietly(path: string): Promise<void> {
+    try {
+      await unlink(path);
+    } catch {
+      // Someone else removed or replaced it first
+    }
+  }
+}
+
+/** The empty key would name the directory itself */
+function checkKey(key: string): void {
+  if (key.length === 0) throw ClavisError.invalidOperation("Storage keys can't be empty");
+}
+
+function isNotFound(error: unknown): boolean {
+  return (error as { code?: string }).code === "ENOENT";
+}
+
+function toStorageError(error: unknown): ClavisError {
+  if (error instanceof ClavisError) return error;
+  return ClavisError.stream(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+}
@@ -0,0 +1,263 @@
+/**
+ * Stream options
+ * Checking and normalizing the options of an encrypted stream
+ *
+ * `checkStreamOptions()` reports every problem with a set of options at
+ * once; `normalizeOptions()` then fills in the defaults the stream runs with.
+ */
+
+import { ClavisError, type ConfigConflict } from "./error.js";
+import type { PskSelection } from "./handshake.js";
+import { HANDSHAKE_MESSAGE_LENGTHS } from "./handshake-messages.js";
+import { systemClock, type Clock } from "./clock.js";
+import { redact } from "./audit.js";
+import type { AeadSuite } from "./suites.js";
+import { isKeyLogAllowed } from "./keylog.js";
+import { isNullCipherAllowed } from "./null-cipher.js";
+import type { CorruptionMonitor } from "./corruption.js";
+import type { CorpusCapture } from "./corpus.js";
+import type { KeyExchangeProvider } from "./key-provider.js";
+import type { WatchdogOptions } from "./watchdog.js";
+import { FRAME_TAG_LENGTH, MAX_CIPHERTEXT_LENGTH } from "./frame.js";
+import type { CloseLinger, EncryptedStreamOptions, StreamTracer } from "./stream.js";
+
+/** Largest serialized packet a stream sends or accepts without `maxPacketSize` */
+export const DEFAULT_MAX_PACKET_SIZE = 65536;
+
+const DEFAULT_MAX_BUFFERED_BYTES = 64 * 1024;
+
+/** Largest packet whose ciphertext fits in a frame header next to the control flag */
+export const MAX_FRAME_LENGTH = MAX_CIPHERTEXT_LENGTH - FRAME_TAG_LENGTH;
+
+/** Internal options with normalized PSK */
+export interface NormalizedOptions {
+  maxPacketSize: number;
+  psk: Uint8Array | PskSelection | undefined;
+  keyExchange: KeyExchangeProvider | undefined;
+  maxNegotiablePacketSize: number;
+  connectionId: string;
+  clock: Clock;
+  rekeyAfterBytes: number | undefined;
+  rekeyAfterMs: number | undefined;
+  keepAliveMs: number | undefined;
+  idleTimeoutMs: number | undefined;
+  readTimeoutMs: number | undefined;
+  writeTimeoutMs: number | undefined;
+  closeLinger: CloseLinger;
+  decodeBudgetMs: number | undefined;
+  corruptionMonitor: CorruptionMonitor | undefined;
+  corpusCapture: CorpusCapture | undefined;
+  tracer: StreamTracer | undefined;
+  ultraLowLatency: boolean;
+  maxBufferedBytes: number;
+  watchdog: WatchdogOptions | undefined;
+  sessionHandoff: boolean;
+}
+
+/**
+ * Normalize PSK from string or Uint8Array to Uint8Array.
+ * For strings, attempts base64 decode first, then falls back to UTF-8.
+ */
+function normalizePsk(psk: string | Uint8Array | undefined): Uint8Array | undefined {
+  if (!psk) return undefined;
+  if (psk instanceof Uint8Array) return redact(psk, "psk");
+  
+  // Try base64 decode first
+  try {
+    const decoded = Buffer.from(psk, 'base64');
+    // Verify it's valid base64 by re-encoding and comparing
+    if (decoded.toString('base64') === psk) {
+      return redact(new Uint8Array(decoded), "psk");
+    }
+  } catch {
+    // Not valid base64, fall through
+  }
+  
+  // Fall back to UTF-8 encoding
+  return redact(new Uint8Array(Buffer.from(psk, 'utf-8')), "psk");
+}
+
+/**
+ * The handshake's view of `psk`, `pskIdentity` and `psks`
+ */
+function normalizePskOptions(options: EncryptedStreamOptions | undefined): Uint8Array | PskSelection | undefined {
+  const psk = normalizePsk(options?.psk);
+  const keyring = options?.psks;
+  if (keyring !== undefined) {
+    const lookup = typeof keyring === "function"
+      ? keyring
+      : keyring instanceof Map
+        ? (identity: string) => (keyring as ReadonlyMap<string, string | Uint8Array>).get(identity)
+        : (identity: string) => Object.hasOwn(keyring, identity)
+          ? (keyring as Readonly<Record<string, string | Uint8Array>>)[identity]
+          : undefined;
+    return { identity: "", keyFor: (identity) => normalizePsk(lookup(identity)) };
+  }
+  if (options?.pskIdentity !== undefined && psk) {
+    return { identity: options.pskIdentity, keyFor: () => psk };
+  }
+  return psk;
+}
+
+/**
+ * Fill in the defaults of `options`, which `checkStreamOptions()` passed
+ */
+export function normalizeOptions(options: EncryptedStreamOptions | undefined, connectionId: string): NormalizedOptions {
+  const maxPacketSize = checkPacketSize("maxPacketSize", options?.maxPacketSize ?? DEFAULT_MAX_PACKET_SIZE);
+  return {
+    maxPacketSize,
+    psk: normalizePskOptions(options),
+    keyExchange: options?.keyExchange,
+    maxNegotiablePacketSize: checkPacketSize(
+      "maxNegotiablePacketSize",
+      options?.maxNegotiablePacketSize ?? maxPacketSize
+    ),
+    connectionId,
+    clock: options?.clock ?? systemClock,
+    rekeyAfterBytes: options?.rekeyAfterBytes,
+    rekeyAfterMs: options?.rekeyAfterMs,
+    keepAliveMs: options?.keepAliveMs,
+    idleTimeoutMs: options?.idleTimeoutMs ?? (options?.keepAliveMs !== undefined ? options.keepAliveMs * 3 : undefined),
+    readTimeoutMs: options?.readTimeoutMs,
+    decodeBudgetMs: options?.decodeBudgetMs,
+    writeTimeoutMs: options?.writeTimeoutMs,
+    closeLinger: options?.closeLinger ?? "half-close",
+    corruptionMonitor: options?.corruptionMonitor,
+    corpusCapture: options?.corpusCapture,
+    tracer: options?.tracer,
+    ultraLowLatency: options?.ultraLowLatency ?? false,
+    maxBufferedBytes: options?.maxBufferedBytes ?? DEFAULT_MAX_BUFFERED_BYTES,
+    watchdog: options?.watchdog,
+    sessionHandoff: options?.dangerousSessionHandoff ?? false,
+  };
+}
+
+export function checkPacketSize(name: string, size: number): number {
+  if (!Number.isInteger(size) || size < 1 || size > MAX_FRAME_LENGTH) {
+    throw ClavisError.config(`${name} must be an integer from 1 to ${MAX_FRAME_LENGTH}`);
+  }
+  return size;
+}
+
+/**
+ * Every problem with a set of stream options, out-of-range values and
+ * options that can't be combined alike. Resolves to nothing when they're
+ * fine; otherwise throws one configuration error whose `configConflicts`
+ * lists them all. `EncryptedStream.new` runs it before touching the
+ * transport, so a bad configuration never fails partway through a handshake.
+ */
+export function checkStreamOptions(options: EncryptedStreamOptions | undefined): void {
+  const conflicts: ConfigConflict[] = [];
+  const conflict = (names: string[], message: string) => conflicts.push({ options: names, message });
+  const o: EncryptedStreamOptions = options ?? {};
+
+  for (const name of ["maxPacketSize", "maxNegotiablePacketSize"] as const) {
+    const size = o[name];
+    if (size !== undefined && !(Number.isInteger(size) && size >= 1 && size <= MAX_FRAME_LENGTH)) {
+      conflict([name], `${name} must be an integer from 1 to ${MAX_FRAME_LENGTH}`);
+    }
+  }
+  const positive = [
+    "rekeyAfterBytes", "rekeyAfterMs", "keepAliveMs", "idleTimeoutMs", "handshakeTimeoutMs",
+    "readTimeoutMs", "writeTimeoutMs", "decodeBudgetMs", "maxPacketsPerSecond", "maxBufferedBytes",
+  ] as const;
+  for (const name of positive) {
+    const value = o[name];
+    if (!(value === undefined || value > 0)) conflict([name], `${name} must be positive`);
+  }
+  if (o.watchdog !== undefined && !(o.watchdog.thresholdMs > 0)) {
+    conflict(["watchdog"], "watchdog.thresholdMs must be positive");
+  }
+  if (typeof o.closeLinger === "object" && !(o.closeLinger.waitForPeerMs > 0)) {
+    conflict(["closeLinger"], "closeLinger.waitForPeerMs must be positive");
+  }
+  if (o.packetBurst !== undefined && !(o.packetBurst >= 1)) {
+    conflict(["packetBurst"], "packetBurst must be at least 1");
+  }
+  if (o.decodeLimits) {
+    for (const [name, value] of Object.entries(o.decodeLimits)) {
+      if (name !== "budget" && value !== undefined && !(Number.isInteger(value) && (value as number) >= 0)) {
+        conflict(["decodeLimits"], `decodeLimits.${name} must be a non-negative integer`);
+      }
+    }
+  }
+
+  if (o.cipherSuites !== undefined && o.cipherSuites.length === 0) {
+    conflict(["cipherSuites"], "cipherSuites must list at least one suite");
+  }
+  if (o.cipherSuites !== undefined && o.dangerousNullCipher) {
+    conflict(["cipherSuites", "dangerousNullCipher"], "cipherSuites and dangerousNullCipher can't be combined");
+  }
+  const customSuites = (o.cipherSuites ?? []).filter((suite): suite is AeadSuite => typeof suite !== "string");
+  for (const suite of customSuites) {
+    if (!(Number.isInteger(suite.id) && suite.id >= 128 && suite.id <= 255)) {
+      conflict(["cipherSuites"], `Cipher suite ${suite.name} needs an id from 128 to 255`);
+    }
+    if (!(Number.isInteger(suite.keyLength) && suite.keyLength >= 16 && suite.keyLength <= 64)) {
+      conflict(["cipherSuites"], `Cipher suite ${suite.name} needs a key length from 16 to 64 bytes`);
+    }
+    if (suite.nonces === "sequence" && !o.sequenceNumbers) {
+      conflict(["cipherSuites", "sequenceNumbers"], `Cipher suite ${suite.name} takes sequence nonces, so it needs sequenceNumbers`);
+    }
+  }
+  const customNames = customSuites.map((suite) => suite.name);
+  const customIds = new Set(customSuites.map((suite) => suite.id));
+  if (
+    customIds.size !== customSuites.length ||
+    new Set(customNames).size !== customNames.length ||
+    customNames.some((name) => name === "xchacha20-poly1305" || name === "aes-256-gcm")
+  ) {
+    conflict(["cipherSuites"], "Custom cipher suites need their own ids and names");
+  }
+  if (o.keyLog && !isKeyLogAllowed()) {
+    conflict(["keyLog"], "Key logging is disabled; set CLAVIS_ALLOW_KEYLOG=1 or call allowKeyLog(true)");
+  }
+  if (o.dangerousNullCipher && !isNullCipherAllowed()) {
+    conflict(["dangerousNullCipher"], "The null cipher is disabled; set CLAVIS_ALLOW_NULL_CIPHER=1 or call allowNullCipher(true)");
+  }
+  if (o.postQuantum && o.dangerousNullCipher) {
+    conflict(["postQuantum", "dangerousNullCipher"], "postQuantum and dangerousNullCipher can't be combined");
+  }
+  if (o.dangerousNullCipher && (o.rekeyAfterBytes !== undefined || o.rekeyAfterMs !== undefined)) {
+    conflict(
+      ["dangerousNullCipher", o.rekeyAfterBytes !== undefined ? "rekeyAfterBytes" : "rekeyAfterMs"],
+      "dangerousNullCipher has no keys to roll, so rekeyAfterBytes and rekeyAfterMs can't be set"
+    );
+  }
+  if (o.keepAliveMs !== undefined && o.idleTimeoutMs !== undefined && o.idleTimeoutMs <= o.keepAliveMs) {
+    conflict(["keepAliveMs", "idleTimeoutMs"], "idleTimeoutMs must be longer than keepAliveMs, or a quiet peer times out before it is pinged");
+  }
+  if (o.psks !== undefined && (o.psk || o.pskIdentity !== undefined)) {
+    conflict(["psks", o.psk ? "psk" : "pskIdentity"], "psks picks the key by the peer's identity, so it can't be combined with psk or pskIdentity");
+  }
+  if (o.pskIdentity !== undefined && !o.psk) {
+    conflict(["pskIdentity"], "pskIdentity names a key, so it needs psk");
+  }
+  if (o.pskIdentity !== undefined && (o.pskIdentity.includes("\0")
+    || new TextEncoder().encode(o.pskIdentity).length > HANDSHAKE_MESSAGE_LENGTHS.psk_identity)) {
+    conflict(["pskIdentity"], `pskIdentity must be at most ${HANDSHAKE_MESSAGE_LENGTHS.psk_identity} bytes of UTF-8 without NUL characters`);
+  }
+  const verifies = o.trustedSigners !== undefined || o.trustedRoots !== undefined || o.verifyPeer !== undefined;
+  if (o.isRevoked && !verifies) {
+    conflict(["isRevoked"], "isRevoked needs trustedSigners, trustedRoots or verifyPeer, or no peer identity is ever checked");
+  }
+  if (o.ultraLowLatency) {
+    for (const name of ["compression", "padding", "writeTimeoutMs"] as const) {
+      if (o[name] !== undefined) {
+        conflict(["ultraLowLatency", name], `ultraLowLatency can't be combined with ${name}, which its direct write path leaves out`);
+      }
+    }
+  }
+  if (o.dangerousSessionHandoff) {
+    for (const name of ["compression", "padding", "journal", "dangerousNullCipher"] as const) {
+      if (o[name] !== undefined && o[name] !== false) {
+        conflict(["dangerousSessionHandoff", name], `dangerousSessionHandoff can't be combined with ${name}, whose state doesn't move to another process`);
+      }
+    }
+  }
+  if (o.clockSkewMs !== undefined && o.trustedSigners === undefined && o.trustedRoots === undefined) {
+    conflict(["clockSkewMs"], "clockSkewMs only applies to certificates, so it needs trustedSigners or trustedRoots");
+  }
+
+  if (conflicts.length > 0) throw ClavisError.configConflicts(conflicts);
+}
@@ -3,12 +3,79 @@
  * Provides encrypted packet-based communication over Node.js streams
  */
 
-import { XChaCha20Poly1305Cipher } from "./crypto.js";
-import { ClavisError, MessageError } from "./error.js";
+import { XChaCha20Poly1305Cipher, type FrameCipher } from "./crypto.js";
+import {
+  ClavisError,
+  CryptoError,
+  CryptoOperation,
+  MessageError,
+  type ConfigConflict,
+  StreamError,
+  StreamErrorCode,
+  type ErrorDirection,
+} from "./error.js";
 import { performHandshake } from "./handshake.js";
-import type { HandshakeResult } from "./handshake.js";
-import type { PacketTrait } from "./protocol.js";
+import type { HandshakeResult, HandshakeTimings } from "./handshake.js";
+import { deriveExporterSecret, deriveSessionId, exportKeyingMaterial } from "./keying-material.js";
+import { RawPacket, serializedSize, type PacketTrait, type ProtocolCodec } from "./protocol.js";
 import { Readable, Writable } from "stream";
+import { Socket } from "net";
+import { DecodeBudget, type DecodeLimits } from "./bincode.js";
+import { systemClock, wallClock, type Clock, type TimerHandle } from "./clock.js";
+import { Mutex } from "./mutex.js";
+// Optional features are only imported once a stream enables them, so
+// programs using the core entry point never load them
+import type { IdentityCredentials, IdentityTrust, PeerIdentity, StaticIdentityKey } from "./identity.js";
+import type { X509Credentials } from "./x509.js";
+import type { TicketKeyring } from "./tickets.js";
+import type { JournalEntry, PacketJournal } from "./journal.js";
+import { PrefetchReader, type PrefetchOptions } from "./prefetch.js";
+import type { ResumptionExchange, SessionTicket } from "./resumption.js";
+import type { CompressionAlgorithm, CompressionOptions, PacketCompressor } from "./compression.js";
+import type { PacketPadder, PaddingOptions, PaddingPolicy } from "./padding.js";
+import type { AeadSuite, CipherSuite } from "./suites.js";
+import { KeyLogger, type KeyLogSink } from "./keylog.js";
+import { DangerousNullCipher } from "./null-cipher.js";
+import type { ClockOffsetEstimate, ClockSample, TimeSync } from "./time-sync.js";
+import type { RekeyMachine } from "./rekey.js";
+import type { CorruptionMonitor } from "./corruption.js";
+import type { CorpusCapture } from "./corpus.js";
+import type { PostQuantumMode } from "./hybrid.js";
+import type { KeyExchangeProvider } from "./key-provider.js";
+import type { ProtocolVersionOptions } from "./versioning.js";
+import type { PayloadFormat, WireFormat } from "./formats.js";
+import type { PacketDirection, ProtocolStats } from "./protocol-stats.js";
+import { BandwidthEstimator, type BandwidthEstimate } from "./bandwidth.js";
+import { StallWatchdog, type WatchdogOptions } from "./watchdog.js";
+import { decodeSessionState, encodeSessionState, type SessionState } from "./handoff.js";
+import { FrameInvariants, areDebugAssertionsEnabled, checkBuffered, invariant } from "./invariants.js";
+import {
+  FRAME_HEADER_LENGTH,
+  FRAME_OVERHEAD,
+  FRAME_NONCE_LENGTH,
+  FRAME_TAG_LENGTH,
+  decodeFrameHeader,
+  nonceSequence,
+  openFrame,
+  sealFrame,
+  sealFrameInto,
+  sequenceNonce,
+} from "./frame.js";
+import {
+  ControlFrameKind,
+  decodeControlFrame,
+  decodeControlU32,
+  encodeControlFrame,
+  encodeControlU32,
+  type ControlChannel,
+} from "./control.js";
+import { PacketLimits } from "./resize.js";
+import {
+  MAX_FRAME_LENGTH,
+  checkStreamOptions,
+  normalizeOptions,
+  type NormalizedOptions,
+} from "./stream-options.js";
 
 /**
  * Options for configuring an encrypted stream
@@ -23,35 +90,637 @@ export interface EncryptedStreamOptions {
    * - string: Auto-detected as base64 or UTF-8
    */
   psk?: string | Uint8Array | undefined;
+  /**
+   * Identity announced with `psk`, so a peer holding several keys in `psks`
+   * knows which one to check. Both peers must use PSK identities.
+   */
+  pskIdentity?: string | undefined;
+  /**
+   * Pre-shared keys by identity, or a function looking one up, for peers
+   * that announce a `pskIdentity`. Listing the old and the new key lets
+   * both authenticate while clients move over during a rotation.
+   */
+  psks?: PskKeyring | undefined;
+  /**
+   * Where the handshake's ephemeral X25519 key is generated and used
+   * (default: process memory). Pass a provider backed by an HSM, a TPM or
+   * an OS keystore to keep private keys out of the process; see
+   * `KeyExchangeProvider`.
+   */
+  keyExchange?: KeyExchangeProvider | undefined;
+  /**
+   * Maximum packets read per second regardless of their size (default: unlimited).
+   * Tiny packets cost a full AEAD open each, so a flood of them is a CPU
+   * attack that byte limits don't catch. Exceeding the rate closes the
+   * stream with an Overloaded error.
+   */
+  maxPacketsPerSecond?: number | undefined;
+  /** Packets that may arrive back to back above the rate (default: maxPacketsPerSecond) */
+  packetBurst?: number | undefined;
+  /** Time source for the packet rate guard and bandwidth estimate (default: `systemClock`) */
+  clock?: Clock | undefined;
+  /**
+   * Compress packets with a shared dictionary (default: off).
+   * Both peers must enable it; the dictionary is negotiated right after the handshake.
+   */
+  compression?: CompressionOptions | undefined;
+  /**
+   * Pad packets inside the ciphertext so their lengths don't give away
+   * which variant is sent, and optionally send cover frames (default: off).
+   * Both peers must enable it; each pads by its own policy. See `PaddingOptions`.
+   */
+  padding?: PaddingOptions | undefined;
+  /**
+   * Largest packet size the peer may switch this connection to with
+   * `requestMaxPacketSize()` (default: maxPacketSize, so only lowering is accepted)
+   */
+  maxNegotiablePacketSize?: number | undefined;
+  /**
+   * Exchange receive limits during setup (default: off): each side
+   * advertises its `maxPacketSize`, and writes are checked against the
+   * peer's, so a packet the peer would refuse fails on this side before it
+   * is sent. Both peers must set it; see `peerMaxPacketSize`.
+   */
+  negotiatePacketSize?: boolean | undefined;
+  /**
+   * Number every frame and refuse frames out of order (default: off). Each
+   * side numbers its frames from 0 in the frame's nonce, which the cipher
+   * authenticates, so a replayed, dropped or reordered record fails the
+   * read with a `ReplayError` instead of passing or failing as garbage.
+   * Both peers must set it; see `lastSentSequence` and `lastReceivedSequence`.
+   */
+  sequenceNumbers?: boolean | undefined;
+  /**
+   * Id attached to errors from this stream's reads and writes, for correlating
+   * logs across connections (default: a process-wide counter)
+   */
+  connectionId?: string | undefined;
+  /**
+   * Enable TCP keepalive probes after this much idle time in milliseconds, when
+   * the stream is a socket (default: off). A peer that vanished without closing
+   * then fails reads with PeerUnresponsive instead of hanging forever.
+   */
+  tcpKeepAliveMs?: number | undefined;
+  /**
+   * Send an encrypted ping whenever nothing has been received for this many
+   * milliseconds (default: off). The peer answers it as part of its reads, so
+   * a quiet connection keeps NAT mappings alive and a dead one is noticed.
+   * Works on any transport, unlike `tcpKeepAliveMs`. Pings are skipped while
+   * this side is writing packets, and put off ever longer while the
+   * transport hasn't taken earlier writes, so they never add to a backlog.
+   */
+  keepAliveMs?: number | undefined;
+  /**
+   * Fail reads with an IdleTimeout error and close the stream once nothing
+   * has been received for this many milliseconds (default: three times
+   * `keepAliveMs` when that is set, otherwise off)
+   */
+  idleTimeoutMs?: number | undefined;
+  /**
+   * Fail `new()` with a HandshakeTimeout error and close the connection when
+   * the key exchange and setup exchanges take longer than this many
+   * milliseconds (default: off). Without it, a peer that connects and never
+   * finishes the handshake holds the connection open forever.
+   */
+  handshakeTimeoutMs?: number | undefined;
+  /**
+   * Fail a read with a Timeout error and close the stream when no packet
+   * arrives within this many milliseconds of the call (default: off). The
+   * stream can't be read again, since the timeout may cut a frame in half.
+   */
+  readTimeoutMs?: number | undefined;
+  /**
+   * Fail a write with a Timeout error and close the stream when the
+   * transport doesn't accept it within this many milliseconds (default: off)
+   */
+  writeTimeoutMs?: number | undefined;
+  /**
+   * What `close()` does once its close frame is sent (default: "half-close").
+   * See `CloseLinger`.
+   */
+  closeLinger?: CloseLinger | undefined;
+  /**
+   * Certificate or X.509 chain to present to the peer, with its key, or a bare
+   * Ed25519 key as `{ secretKey }` (default: none). Identities are exchanged
+   * right after the handshake when this, `trustedSigners`, `trustedRoots` or
+   * `verifyPeer` is set, so both peers must enable one.
+   */
+  identity?: IdentityCredentials | X509Credentials | StaticIdentityKey | undefined;
+  /**
+   * Signer public keys whose certificates this side accepts (default: none).
+   * When set, a peer without a valid, unexpired certificate fails the handshake
+   * and the verified identity is available as `peerIdentity`.
+   */
+  trustedSigners?: readonly Uint8Array[] | undefined;
+  /**
+   * DER root certificates whose X.509 chains this side accepts (default: none).
+   * The leaf's first subject alternative name becomes `peerIdentity.identity`.
+   */
+  trustedRoots?: readonly Uint8Array[] | undefined;
+  /**
+   * Accept peer certificates this far outside their validity period, for
+   * fleets whose clocks drift (default: 0). Refusals carry a
+   * `validityFailure` telling expired, not-yet-valid and suspected skew apart.
+   */
+  clockSkewMs?: number | undefined;
+  /**
+   * Called with every verified peer identity before the stream is handed out
+   * (default: none). Resolving true refuses the peer, so compromised
+   * credentials can be cut off without a restart; a check that throws refuses
+   * it too.
+   */
+  isRevoked?: ((identity: PeerIdentity) => boolean | Promise<boolean>) | undefined;
+  /**
+   * Decide whether to accept the peer (default: none). Also accepts bare
+   * Ed25519 keys, whose `identity` and `credentialId` are the key's
+   * fingerprint; without it they are refused. Called after `isRevoked` with
+   * every verified peer identity; resolving false refuses the peer, and so
+   * does a check that throws.
+   */
+  verifyPeer?: ((identity: PeerIdentity) => boolean | Promise<boolean>) | undefined;
+  /**
+   * Keyring to seal session tickets for the peer and open the ones it
+   * presents (default: none). Servers sharing the keyring's keys can resume
+   * each other's sessions. The peer must set `resumption`.
+   */
+  tickets?: TicketKeyring | undefined;
+  /**
+   * Take part in session resumption (default: off): true to receive a
+   * ticket, or the `sessionTicket` of an earlier connection to resume it.
+   * The peer must set `tickets`.
+   */
+  resumption?: boolean | SessionTicket | undefined;
+  /**
+   * Write-ahead journal for received packets (default: none). Packets are
+   * then read with `readJournaled()`, which appends each one before returning
+   * it and acknowledges it to the peer only once the journal has stored it.
+   */
+  journal?: PacketJournal | undefined;
+  /**
+   * Send frames in the clear (default: off). The handshake and framing are
+   * unchanged, but payloads are neither encrypted nor authenticated: only for
+   * profiling, or for links already protected by IPsec or WireGuard. Both
+   * peers must set it, and the process must allow it with
+   * `CLAVIS_ALLOW_NULL_CIPHER=1` or `allowNullCipher(true)`.
+   */
+  dangerousNullCipher?: boolean | undefined;
+  /**
+   * Hand every traffic key of this stream to a sink, as SSLKEYLOGFILE-style
+   * lines for decrypting captures (default: off). Only for debugging: the
+   * process must allow it with `CLAVIS_ALLOW_KEYLOG=1` or `allowKeyLog(true)`.
+   * See `keyLogFile()`.
+   */
+  keyLog?: KeyLogSink | undefined;
+  /**
+   * Allow `exportState()` and `importState()`, which move an established
+   * connection to another process with its live keys (default: off). Only
+   * for zero-downtime upgrades: whoever reads the exported state can
+   * decrypt and forge the connection's traffic. Can't be combined with
+   * compression, padding, a journal or the null cipher.
+   */
+  dangerousSessionHandoff?: boolean | undefined;
+  /**
+   * AEADs this side accepts for frames, most preferred first (default:
+   * XChaCha20-Poly1305 without negotiating). Both peers must set it; they
+   * use the suite both lists rank best together. Custom AEADs are listed as
+   * an `AeadSuite`. See `cipherSuite`.
+   */
+  cipherSuites?: readonly (CipherSuite | AeadSuite)[] | undefined;
+  /**
+   * Answer and send clock probes (default: off), so `measureClockOffset()`
+   * can estimate the peer's clock offset and the one-way delay. Both peers
+   * must enable it; a probe to a peer without it fails the connection.
+   */
+  timeSync?: boolean | undefined;
+  /**
+   * Roll the session keys once this many bytes have been sent and received
+   * under them (default: never). Checked as frames go out and come in;
+   * packets keep flowing during the rekey. Either peer may set it, and both
+   * answer rekeys whether they set it or not.
+   */
+  rekeyAfterBytes?: number | undefined;
+  /** Roll the session keys once they have been in use this long, in milliseconds (default: never) */
+  rekeyAfterMs?: number | undefined;
+  /**
+   * Called with the time each connection setup stage took once the stream
+   * is ready (default: none), for tracing; see `handshakeTimings`
+   */
+  onHandshakeTimings?: ((timings: HandshakeTimings) => void) | undefined;
+  /**
+   * Report frames that fail authentication or break the framing to this
+   * monitor (default: none). Share one monitor across a server's streams
+   * to count corruption per connection and alarm on bursts of it.
+   */
+  corruptionMonitor?: CorruptionMonitor | undefined;
+  /**
+   * Write anonymized copies of the application packets this stream reads
+   * into a fuzz corpus (default: none); see `CorpusCapture`
+   */
+  corpusCapture?: CorpusCapture | undefined;
+  /**
+   * Count the application packets this stream reads and writes by variant,
+   * under the peer's identity (default: none); see `ProtocolStats`
+   */
+  protocolStats?: ProtocolStats | undefined;
+  /**
+   * Hooks run on every application packet this stream reads and writes
+   * (default: none); more can be added with `intercept()`. See
+   * `PacketInterceptor`.
+   */
+  interceptors?: readonly PacketInterceptor[] | undefined;
+  /**
+   * Mix an ML-KEM-768 exchange into the session keys (default: off).
+   * "prefer" falls back to X25519 alone when the peer can't do it,
+   * "require" refuses such peers. Both peers must set it; it needs the
+   * `@noble/post-quantum` package.
+   */
+  postQuantum?: PostQuantumMode | undefined;
+  /**
+   * Exchange the application protocol's version during setup and refuse
+   * peers outside the accepted range (default: off). Takes a version, a
+   * codec declared with one, or `{ version, accept }` to accept a range or
+   * a predicate. Both peers must set it; see `peerProtocolVersion` and
+   * `upgradeProtocol()`.
+   */
+  protocolVersion?: number | ProtocolVersionOptions | Pick<ProtocolCodec<string>, "version"> | undefined;
+  /**
+   * Serialization used by `writeValue()` and `readValue()` (default: none).
+   * A single format, or a codec declared with one, fixes it and both peers
+   * must set the same; a list is negotiated during setup, most preferred
+   * first, and both peers must pass one. See `payloadFormat`.
+   */
+  format?: WireFormat | readonly WireFormat[] | Pick<ProtocolCodec<string>, "format"> | undefined;
+  /**
+   * Bounds on sequence lengths, nesting depth and string lengths when
+   * `readValue()` decodes a payload (default: 64 levels of nesting, lengths
+   * bounded only by the packet). Pass the same limits to the codec with
+   * `createProtocolCodec(variants, { limits })` for bincode messages.
+   */
+  decodeLimits?: DecodeLimits | undefined;
+  /**
+   * Milliseconds one packet may spend being decompressed, and again being
+   * decoded by `readValue()` (default: no budget). Decoding checks the budget
+   * as it goes and fails the read once it's spent, so a packet that is slow
+   * to walk even within `decodeLimits` can't monopolize the event loop.
+   */
+  decodeBudgetMs?: number | undefined;
+  /**
+   * Open a span around the handshake and every packet read and write
+   * (default: none). Nothing is traced, and no spans are created, without it.
+   */
+  tracer?: StreamTracer | undefined;
+  /**
+   * Latency preset for control loops and trading-style traffic (default:
+   * off). Sets TCP_NODELAY on socket transports, and `writePacket()` seals
+   * each packet (into a reused buffer, on sockets) and hands it over within
+   * the call, resolving as soon as the transport took it rather than once
+   * it was flushed; only a full socket buffer makes it wait, for "drain".
+   * Can't be combined with compression, padding or `writeTimeoutMs`; while
+   * interceptors or a tracer are set, writes take the usual path.
+   */
+  ultraLowLatency?: boolean | undefined;
+  /**
+   * Bytes of sealed frames `writePacketBuffered()` and `feed()` hold before
+   * they flush on their own and wait for the transport (default: 64 KiB)
+   */
+  maxBufferedBytes?: number | undefined;
+  /**
+   * Report reads and writes that stop making progress (default: off):
+   * bytes from the peer nobody reads, or a write the transport doesn't
+   * finish, for `thresholdMs`. Only reports; see `WatchdogOptions`.
+   */
+  watchdog?: WatchdogOptions | undefined;
+}
+
+/**
+ * What `close()` does after sending its close frame:
+ * - "half-close": finish writing and keep reading until the peer closes too
+ * - "abort": destroy the transport as soon as the close frame is flushed,
+ *   reclaiming the socket at once; packets still in flight from the peer are lost
+ * - `{ waitForPeerMs }`: read and discard packets until the peer's own close
+ *   frame (or the end of its transport) acknowledges ours, then destroy the
+ *   transport. If that takes longer than `waitForPeerMs`, the transport is
+ *   destroyed anyway and `close()` fails with a Timeout error.
+ */
+export type CloseLinger = "half-close" | "abort" | { waitForPeerMs: number };
+
+/**
+ * Pre-shared keys by PSK identity: a record, a map, or a lookup returning
+ * undefined for identities it doesn't know
+ */
+export type PskKeyring =
+  | Readonly<Record<string, string | Uint8Array>>
+  | ReadonlyMap<string, string | Uint8Array>
+  | ((identity: string) => string | Uint8Array | undefined);
+
+/**
+ * Token bucket over incoming packets
+ */
+class PacketRateGuard {
+  private tokens: number;
+  private last: number;
+
+  constructor(
+    private rate: number,
+    private burst: number,
+    private readonly clock: Clock
+  ) {
+    this.tokens = burst;
+    this.last = clock.now();
+  }
+
+  /** Take one packet from the bucket; false once the peer is over the limit */
+  admit(): boolean {
+    this.refill();
+    if (this.tokens < 1) return false;
+    this.tokens -= 1;
+    return true;
+  }
+
+  /** Current rate and burst */
+  get limit(): { rate: number; burst: number } {
+    return { rate: this.rate, burst: this.burst };
+  }
+
+  /** Switch to a new limit; tokens saved up so far are kept, up to the new burst */
+  configure(rate: number, burst: number): void {
+    this.refill();
+    this.rate = rate;
+    this.burst = burst;
+    this.tokens = Math.min(burst, this.tokens);
+  }
+
+  private refill(): void {
+    const now = this.clock.now();
+    this.tokens = Math.min(this.burst, this.tokens + ((now - this.last) * this.rate) / 1000);
+    this.last = now;
+  }
+
+  /** Throw an Overloaded error and close the stream if the peer is over the limit */
+  check(adapter: StreamAdapter): void {
+    if (this.admit()) return;
+    adapter.close();
+    throw ClavisError.stream(
+      StreamError.overloaded(`Peer exceeded ${this.rate} packets per second`)
+    );
+  }
+}
+
+function createRateGuard(options: EncryptedStreamOptions | undefined): PacketRateGuard | undefined {
+  const rate = options?.maxPacketsPerSecond;
+  if (rate === undefined) return undefined;
+  const burst = options?.packetBurst ?? rate;
+  if (!(rate > 0) || !(burst >= 1)) {
+    throw ClavisError.config("maxPacketsPerSecond must be positive and packetBurst at least 1");
+  }
+  return new PacketRateGuard(rate, burst, options?.clock ?? systemClock);
+}
+
+/**
+ * Settle like `operation`, unless `timeoutMs` passes first: then reject
+ * with what `onTimeout` returns. `operation` keeps running either way.
+ */
+function withDeadline<T>(
+  clock: Clock,
+  timeoutMs: number | undefined,
+  operation: Promise<T>,
+  onTimeout: () => ClavisError
+): Promise<T> {
+  if (timeoutMs === undefined) return operation;
+  return new Promise<T>((resolve, reject) => {
+    const timer = clock.setTimer(() => reject(onTimeout()), timeoutMs);
+    operation.then(
+      (value) => {
+        timer.cancel();
+        resolve(value);
+      },
+      (error: unknown) => {
+        timer.cancel();
+        reject(error);
+      }
+    );
+  });
+}
+
+/**
+ * Time a connection has spent on its hot paths, in milliseconds of wall
+ * time around each operation. Single-threaded work runs start to finish,
+ * so this approximates CPU time closely enough to find the connection that
+ * is burning a server's cores.
+ */
+export interface CpuTime {
+  /** Serializing outgoing packets */
+  serializeMs: number;
+  /** Compressing and decompressing packets */
+  compressionMs: number;
+  /** Encrypting and decrypting frames */
+  cryptoMs: number;
+}
+
+/**
+ * Soft limits to change on a live connection with `reconfigure()`. Keys
+ * left out keep their current value and `null` turns a limit off. None of
+ * them is negotiated, so the peer isn't involved and doesn't notice.
+ */
+export interface StreamReconfiguration {
+  /** See `EncryptedStreamOptions.maxPacketsPerSecond` */
+  maxPacketsPerSecond?: number | null | undefined;
+  /**
+   * See `EncryptedStreamOptions.packetBurst`. Changing the rate without it
+   * resets the burst to the new rate.
+   */
+  packetBurst?: number | null | undefined;
+  /** See `EncryptedStreamOptions.keepAliveMs` */
+  keepAliveMs?: number | null | undefined;
+  /** See `EncryptedStreamOptions.idleTimeoutMs`; a new value counts from the call */
+  idleTimeoutMs?: number | null | undefined;
+  /** See `EncryptedStreamOptions.readTimeoutMs`; applies to reads started afterwards */
+  readTimeoutMs?: number | null | undefined;
+  /** See `EncryptedStreamOptions.writeTimeoutMs`; applies to writes started afterwards */
+  writeTimeoutMs?: number | null | undefined;
+  /** See `EncryptedStreamOptions.decodeBudgetMs` */
+  decodeBudgetMs?: number | null | undefined;
+  /** See `EncryptedStreamOptions.rekeyAfterBytes` */
+  rekeyAfterBytes?: number | null | undefined;
+  /** See `EncryptedStreamOptions.rekeyAfterMs` */
+  rekeyAfterMs?: number | null | undefined;
+}
+
+/** Current value of every soft limit; undefined where it is off */
+type SoftLimits = { [K in keyof StreamReconfiguration]-?: number | undefined };
+
+/**
+ * What a connection has done so far, from `stats()`
+ */
+export interface ConnectionStats {
+  /** Application packets written; setup exchanges and control frames aren't counted */
+  packetsSent: number;
+  /** Application packets read */
+  packetsReceived: number;
+  /** Bytes handed to the transport, handshake and control frames included */
+  bytesSent: number;
+  /** Bytes received from the transport, whether read yet or not */
+  bytesReceived: number;
+  /** Time the handshake and setup exchanges took, in milliseconds */
+  handshakeMs: number;
+  /** Completed rekeys, whichever side started them */
+  rekeys: number;
+  /** Frames that failed authentication */
+  decryptionFailures: number;
+  /** Pings sent by `keepAliveMs` */
+  keepAlivesSent: number;
+  /** Pings `keepAliveMs` skipped because packets were going out or the transport was backed up */
+  keepAlivesSuppressed: number;
+}
+
+/** Operations a `StreamTracer` sees */
+export type TraceSpanName = "clavis.handshake" | "clavis.read" | "clavis.write";
+
+/**
+ * A span opened by a `StreamTracer`
+ */
+export interface TraceSpan {
+  /** Called once when the operation finishes, with its error if it failed */
+  end(error?: unknown): void;
+}
+
+/**
+ * Receives spans around a stream's handshake and packet I/O. The shape
+ * fits OpenTelemetry's tracers with a thin wrapper.
+ *
+ * @example
+ * ```typescript
+ * const otel = trace.getTracer("clavis");
+ * const tracer: StreamTracer = {
+ *   startSpan(name, attributes) {
+ *     const span = otel.startSpan(name, { attributes });
+ *     return {
+ *       end(error) {
+ *         if (error) span.recordException(error as Error);
+ *         span.end();
+ *       },
+ *     };
+ *   },
+ * };
+ * ```
+ */
+export interface StreamTracer {
+  startSpan(name: TraceSpanName, attributes: { connectionId: string }): TraceSpan;
+  /**
+   * Called once the stream is set up, before the handshake span ends, so
+   * the tracer can tag later spans with the peer and the negotiated options
+   */
+  established?(stream: EncryptedStream): void;
+  /** Called once the stream's transport has closed */
+  closed?(connectionId: string): void;
+}
+
+/**
+ * One tracer that hands every span and hook to each of `tracers`, such as
+ * a `ConnectionLogger` next to a `PrometheusExporter`
+ */
+export function combineTracers(...tracers: StreamTracer[]): StreamTracer {
+  return {
+    startSpan(name, attributes) {
+      const spans = tracers.map((tracer) => tracer.startSpan(name, attributes));
+      return {
+        end(error) {
+          for (const span of spans) span.end(error);
+        },
+      };
+    },
+    established(stream) {
+      for (const tracer of tracers) tracer.established?.(stream);
+    },
+    closed(connectionId) {
+      for (const tracer of tracers) tracer.closed?.(connectionId);
+    },
+  };
+}
+
+/** Run `operation` inside a span when there is a tracer */
+function traced<T>(
+  tracer: StreamTracer | undefined,
+  name: TraceSpanName,
+  connectionId: string,
+  operation: () => Promise<T>
+): Promise<T> {
+  if (!tracer) return operation();
+  const span = tracer.startSpan(name, { connectionId });
+  return operation().then(
+    (value) => {
+      span.end();
+      return value;
+    },
+    (error: unknown) => {
+      span.end(error);
+      throw error;
+    }
+  );
 }
 
-/** Internal options with normalized PSK */
-interface NormalizedOptions {
-  maxPacketSize: number;
-  psk: Uint8Array | undefined;
+/**
+ * What an interceptor does with a packet: `false` drops it, anything else
+ * passes it on
+ */
+export type InterceptResult = boolean | void;
+
+/**
+ * Hooks that see each application packet as serialized bytes, e.g. for an
+ * audit log, per-variant rate limits or tagging metrics. Either hook may be
+ * async and may return `false` to drop the packet; an error it throws
+ * fails that read or write. Setup exchanges and control frames never reach
+ * interceptors.
+ *
+ * @example
+ * ```typescript
+ * stream.intercept({
+ *   read(packet, stream) {
+ *     const variant = Codec.variantName(Codec.peekIndex(packet) ?? -1);
+ *     if (variant === "Chat" && !chatLimiter.take(stream.connectionId)) return false;
+ *   },
+ *   async write(packet) {
+ *     await audit.append(packet);
+ *   },
+ * });
+ * ```
+ */
+export interface PacketInterceptor {
+  /** Called with each packet read, after the accept filter and before it is returned */
+  read?(packet: Uint8Array, stream: EncryptedStream): InterceptResult | Promise<InterceptResult>;
+  /** Called with each packet written, once serialized and before it is compressed and encrypted */
+  write?(packet: Uint8Array, stream: EncryptedStream): InterceptResult | Promise<InterceptResult>;
 }
 
+type InterceptHook = (direction: PacketDirection, packet: Uint8Array) => InterceptResult | Promise<InterceptResult>;
+
+/**
+ * Why a health check failed
+ * - "timeout": the peer didn't answer in time
+ * - "closed": the connection has ended
+ * - "error": anything else, such as a protocol violation
+ */
+export type HealthCheckFailure = "timeout" | "closed" | "error";
+
 /**
- * Normalize PSK from string or Uint8Array to Uint8Array.
- * For strings, attempts base64 decode first, then falls back to UTF-8.
+ * Outcome of `healthCheck()`
  */
-function normalizePsk(psk: string | Uint8Array | undefined): Uint8Array | undefined {
-  if (!psk) return undefined;
-  if (psk instanceof Uint8Array) return psk;
-  
-  // Try base64 decode first
-  try {
-    const decoded = Buffer.from(psk, 'base64');
-    // Verify it's valid base64 by re-encoding and comparing
-    if (decoded.toString('base64') === psk) {
-      return new Uint8Array(decoded);
-    }
-  } catch {
-    // Not valid base64, fall through
-  }
-  
-  // Fall back to UTF-8 encoding
-  return new Uint8Array(Buffer.from(psk, 'utf-8'));
+export type HealthCheckResult =
+  | { healthy: true; rttMs: number }
+  | { healthy: false; failure: HealthCheckFailure; error: ClavisError };
+
+/**
+ * Classify the error of a failed ping as a health check result
+ */
+export function healthCheckFailure(error: unknown): HealthCheckResult {
+  const failure = toClavisError(error);
+  const cause = failure.cause;
+  const kind: HealthCheckFailure = !(cause instanceof StreamError)
+    ? "error"
+    : cause.code === StreamErrorCode.Timeout
+      ? "timeout"
+      : cause.isConnectionClosed() ? "closed" : "error";
+  return { healthy: false, failure: kind, error: failure };
 }
 
 /**
@@ -62,49 +731,165 @@ export interface SplitResult {
   writer: EncryptedWriter;
 }
 
-const DEFAULT_MAX_PACKET_SIZE = 65536;
+/** Most a congested transport stretches the keepalive interval, as a multiple of `keepAliveMs` */
+const MAX_KEEPALIVE_BACKOFF = 8;
+
+let nextConnectionId = 1;
+
+function toClavisError(error: unknown): ClavisError {
+  if (error instanceof ClavisError) return error;
+  if (error instanceof StreamError) return ClavisError.stream(error);
+  return ClavisError.stream(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+}
+
+/**
+ * Bytes a packet occupies on the wire once encrypted and framed
+ */
+export function wireSize(packet: PacketTrait): number {
+  return serializedSize(packet) + FRAME_OVERHEAD;
+}
 
 /**
  * Stream adapter for reading/writing
  */
 interface StreamAdapter {
+  /** The next `length` bytes; may be a view into received data, so never modify it */
   read(length: number): Promise<Uint8Array>;
   write(data: Uint8Array): Promise<void>;
+  /** Finish writing; resolves once everything written has been flushed */
+  end(): Promise<void>;
   readU32LE(): Promise<number>;
   writeU32LE(value: number): Promise<void>;
+  /** Tear down the underlying stream */
+  close(): void;
+  /** Number of received bytes not yet read */
+  buffered(): number;
+  /** Copy of the next `length` buffered bytes, or undefined if fewer are buffered */
+  peek(length: number): Uint8Array | undefined;
+  /** Consume `length` bytes that are already buffered; may be a view, like `read()` */
+  take(length: number): Uint8Array;
+  /** Bytes received so far, read or not */
+  receivedBytes(): number;
+  /** Bytes handed to the stream so far */
+  sentBytes(): number;
+  /** End the stream with `error`: pending and later reads fail with it */
+  fail(error: StreamError): void;
+  /** Run `callback` once no more data will arrive */
+  onClose(callback: () => void): void;
+  /**
+   * Hand `data` to the transport now, without waiting for the write to
+   * finish; false when its buffer is full. The transport may keep a
+   * reference to `data` until `flushed()`. Optional, for `ultraLowLatency`.
+   */
+  writeNow?(data: Uint8Array): boolean;
+  /** Whether everything written has left the transport's buffer */
+  flushed?(): boolean;
+  /** Resolves once a full buffer has room again, or the transport closed */
+  drained?(): Promise<void>;
+  /**
+   * Stop reading and writing without closing the stream, so another process
+   * can take it over; later reads and writes fail. Returns the received
+   * bytes no read took, including those the stream buffered but hasn't
+   * emitted yet.
+   */
+  detach(): Uint8Array;
+}
+
+// Frames used to be defined here; keep the old import path working
+export { encodeFrame, FRAME_OVERHEAD } from "./frame.js";
+// As did option checks
+export { checkStreamOptions, DEFAULT_MAX_PACKET_SIZE } from "./stream-options.js";
+
+/** Default cap on packets returned by a single `readPackets()` call */
+const DEFAULT_READ_BATCH = 64;
+
+/** Apply the socket-level options, on TCP transports */
+function tuneSocket(stream: Readable & Writable, options: EncryptedStreamOptions | undefined): void {
+  if (!(stream instanceof Socket)) return;
+  if (options?.ultraLowLatency) stream.setNoDelay(true);
+  if (options?.tcpKeepAliveMs !== undefined) stream.setKeepAlive(true, options.tcpKeepAliveMs);
 }
 
 /**
- * Create a stream adapter from a Node.js stream
+ * Create a stream adapter from a Node.js stream, with `unread` bytes
+ * received before it, if any, ahead of the stream's own
  */
-function createStreamAdapter(stream: Readable & Writable): StreamAdapter {
-  const readBuffer: Uint8Array[] = [];
+function createStreamAdapter(stream: Readable & Writable, unread?: Uint8Array): StreamAdapter {
+  const readBuffer: Uint8Array[] = unread && unread.length > 0 ? [unread] : [];
+  /** Total length of `readBuffer` */
+  let bufferedBytes = unread?.length ?? 0;
+  /** Whether to check `bufferedBytes` against `readBuffer` as it changes */
+  const assertions = areDebugAssertionsEnabled();
+  /** Set once `detach()` let go of the stream */
+  let detached = false;
   let readResolver: ((value: Uint8Array) => void) | null = null;
   let readRejecter: ((error: Error) => void) | null = null;
   let readLength: number | null = null;
+  /** Set once no more data will arrive; builds the error for reads that come up short */
+  let terminal: (() => StreamError) | null = null;
+  let received = 0;
+  let sent = 0;
+  const closeCallbacks: Array<() => void> = [];
+
+  const terminate = (error: () => StreamError) => {
+    if (terminal) return;
+    terminal = error;
+    for (const callback of closeCallbacks.splice(0)) callback();
+    if (readRejecter) {
+      const rejecter = readRejecter;
+      readResolver = null;
+      readRejecter = null;
+      readLength = null;
+      rejecter(error());
+    }
+  };
+
+  /**
+   * Consume `length` buffered bytes: a view into the received chunk when
+   * they all sit in one, which is the common case for frames, and a copy
+   * only when they span several
+   */
+  const consume = (length: number): Uint8Array => {
+    const first = readBuffer[0];
+    if (first && first.length >= length) {
+      if (first.length === length) {
+        readBuffer.shift();
+      } else {
+        readBuffer[0] = first.subarray(length);
+      }
+      bufferedBytes -= length;
+      if (assertions) checkBuffered(bufferedBytes, readBuffer, "Read buffer");
+      return first.subarray(0, length);
+    }
+    const result = new Uint8Array(length);
+    let offset = 0;
+    while (offset < length && readBuffer.length > 0) {
+      const buf = readBuffer[0]!;
+      const toTake = Math.min(buf.length, length - offset);
+      result.set(buf.subarray(0, toTake), offset);
+      offset += toTake;
+      if (toTake === buf.length) {
+        readBuffer.shift();
+      } else {
+        readBuffer[0] = buf.subarray(toTake);
+      }
+    }
+    bufferedBytes -= offset;
+    if (assertions) {
+      invariant(offset === length, `Read buffer gave ${offset} of ${length} bytes`);
+      checkBuffered(bufferedBytes, readBuffer, "Read buffer");
+    }
+    return result;
+  };
 
   const adapter: StreamAdapter = {
     async read(length: number): Promise<Uint8Array> {
-      // Check if we have enough data in buffer
-      let totalBuffered = readBuffer.reduce((sum, buf) => sum + buf.length, 0);
-      
-      if (totalBuffered >= length) {
+      if (bufferedBytes >= length) {
         // We have enough data, extract it
-        const result = new Uint8Array(length);
-        let offset = 0;
-        while (offset < length && readBuffer.length > 0) {
-          const buf = readBuffer[0]!;
-          const toTake = Math.min(buf.length, length - offset);
-          result.set(buf.slice(0, toTake), offset);
-          offset += toTake;
-          
-          if (toTake === buf.length) {
-            readBuffer.shift();
-          } else {
-            readBuffer[0] = buf.slice(toTake);
-          }
-        }
-        return result;
+        return adapter.take(length);
+      }
+      if (terminal) {
+        throw terminal();
       }
       
       // Need to wait for more data
@@ -114,8 +899,7 @@ function createStreamAdapter(stream: Readable & Writable): StreamAdapter {
         readRejecter = reject;
         
         // Check again in case data arrived between check and setting resolver
-        totalBuffered = readBuffer.reduce((sum, buf) => sum + buf.length, 0);
-        if (totalBuffered >= length) {
+        if (bufferedBytes >= length) {
           // Data arrived, process it
           readLength = null;
           const resolver = readResolver;
@@ -127,6 +911,10 @@ function createStreamAdapter(stream: Readable & Writable): StreamAdapter {
     },
 
     async write(data: Uint8Array): Promise<void> {
+      if (stream.destroyed || detached) {
+        throw terminal?.() ?? StreamError.connectionClosed("Stream closed");
+      }
+      sent += data.length;
       return new Promise((resolve, reject) => {
         stream.write(Buffer.from(data), (err) => {
           if (err) reject(err);
@@ -135,6 +923,11 @@ function createStreamAdapter(stream: Readable & Writable): StreamAdapter {
       });
     },
 
+    end(): Promise<void> {
+      if (detached) return Promise.resolve();
+      return new Promise((resolve) => stream.end(resolve));
+    },
+
     async readU32LE(): Promise<number> {
       const bytes = await adapter.read(4);
       // Ensure unsigned 32-bit integer
@@ -150,155 +943,2813 @@ function createStreamAdapter(stream: Readable & Writable): StreamAdapter {
       bytes[3] = (value >> 24) & 0xff;
       await adapter.write(bytes);
     },
+
+    close(): void {
+      if (!detached) stream.destroy();
+    },
+
+    buffered(): number {
+      return bufferedBytes;
+    },
+
+    peek(length: number): Uint8Array | undefined {
+      if (adapter.buffered() < length) return undefined;
+      const result = new Uint8Array(length);
+      let offset = 0;
+      for (const buf of readBuffer) {
+        if (offset >= length) break;
+        const toTake = Math.min(buf.length, length - offset);
+        result.set(buf.subarray(0, toTake), offset);
+        offset += toTake;
+      }
+      return result;
+    },
+
+    take(length: number): Uint8Array {
+      return consume(length);
+    },
+
+    receivedBytes(): number {
+      return received;
+    },
+
+    sentBytes(): number {
+      return sent;
+    },
+
+    fail(error: StreamError): void {
+      terminate(() => error);
+      if (!detached) stream.destroy();
+    },
+
+    onClose(callback: () => void): void {
+      if (terminal) callback();
+      else closeCallbacks.push(callback);
+    },
+
+    writeNow(data: Uint8Array): boolean {
+      if (stream.destroyed || detached) {
+        throw terminal?.() ?? StreamError.connectionClosed("Stream closed");
+      }
+      sent += data.length;
+      return stream.write(data);
+    },
+
+    flushed(): boolean {
+      // Sockets copy what they write into the kernel; other streams, such as
+      // in-memory pairs, may pass the chunk itself on and still report it written
+      return stream instanceof Socket && stream.writableLength === 0;
+    },
+
+    drained(): Promise<void> {
+      return new Promise((resolve) => {
+        const done = () => {
+          stream.off("drain", done);
+          stream.off("close", done);
+          resolve();
+        };
+        stream.once("drain", done);
+        stream.once("close", done);
+      });
+    },
+
+    detach(): Uint8Array {
+      if (stream.writableLength > 0) {
+        throw ClavisError.invalidOperation("Can't export a stream while its writes are still buffered");
+      }
+      stream.off("data", receive);
+      stream.pause();
+      // Chunks the stream read off the socket but hasn't emitted; the rest stays in the kernel
+      for (let chunk = stream.read() as Buffer | null; chunk !== null; chunk = stream.read() as Buffer | null) {
+        receive(chunk);
+      }
+      const unread = consume(bufferedBytes).slice();
+      detached = true;
+      terminate(() => StreamError.connectionClosed("Stream was handed off"));
+      return unread;
+    },
   };
 
   // Handle incoming data
-  stream.on("data", (chunk: Buffer) => {
-    const data = new Uint8Array(chunk);
+  const receive = (chunk: Buffer) => {
+    // A view, not a copy: the stream hands each chunk over and never touches it again
+    const data = new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.length);
+    received += data.length;
+    readBuffer.push(data);
+    bufferedBytes += data.length;
+    if (assertions) checkBuffered(bufferedBytes, readBuffer, "Read buffer");
     if (readResolver && readLength !== null) {
-      readBuffer.push(data);
-      const totalBuffered = readBuffer.reduce((sum, buf) => sum + buf.length, 0);
-      
-      if (totalBuffered >= readLength) {
-        // We have enough data
-        const result = new Uint8Array(readLength);
-        let offset = 0;
-        while (offset < readLength && readBuffer.length > 0) {
-          const buf = readBuffer[0]!;
-          const toTake = Math.min(buf.length, readLength - offset);
-          result.set(buf.slice(0, toTake), offset);
-          offset += toTake;
-          
-          if (toTake === buf.length) {
-            readBuffer.shift();
-          } else {
-            readBuffer[0] = buf.slice(toTake);
-          }
-        }
-        
+      if (bufferedBytes >= readLength) {
+        const result = consume(readLength);
         const resolver = readResolver;
         readResolver = null;
         readRejecter = null;
         readLength = null;
         resolver(result);
       }
-    } else {
-      readBuffer.push(data);
     }
+  };
+  stream.on("data", receive);
+
+  // Tell a clean close (FIN at a frame boundary), a reset and a dead peer apart
+  stream.on("end", () => {
+    terminate(() => adapter.buffered() === 0 ? StreamError.eof() : StreamError.unexpectedClose());
   });
 
-  stream.on("error", (err) => {
-    if (readRejecter) {
-      const rejecter = readRejecter;
-      readResolver = null;
-      readRejecter = null;
-      readLength = null;
-      rejecter(err);
-    }
+  stream.on("error", (err: Error & { code?: string }) => {
+    const error = err.code === "ECONNRESET" || err.code === "EPIPE"
+      ? StreamError.connectionReset(err)
+      : err.code === "ETIMEDOUT"
+        ? StreamError.peerUnresponsive("no response to keepalive probes", err)
+        : StreamError.io(err);
+    terminate(() => error);
   });
 
-  return adapter;
-}
+  stream.on("close", () => {
+    terminate(() => StreamError.connectionClosed("Stream closed"));
+  });
+
+  // Sockets may have been paused by pre-handshake processing (e.g. PROXY headers)
+  stream.resume();
+
+  return adapter;
+}
+
+/**
+ * Build a predicate over serialized packets that passes only the given variants
+ */
+function variantFilter<T extends string>(
+  codec: ProtocolCodec<T>,
+  types: Iterable<T>
+): (plaintext: Uint8Array) => boolean {
+  const accepted = new Set<number>();
+  for (const type of types) {
+    accepted.add(codec.variantIndex(type));
+  }
+  return (plaintext) => {
+    const index = codec.peekIndex(plaintext);
+    return index !== undefined && accepted.has(index);
+  };
+}
+
+interface AckWaiter {
+  count: number;
+  resolve: () => void;
+  reject: (error: unknown) => void;
+}
+
+interface PendingPing {
+  sent: number;
+  resolve: (rttMs: number) => void;
+  reject: (error: unknown) => void;
+}
+
+/** Protocol versions each direction uses under `protocolVersion`, and how the peer's upgrades are answered */
+interface ProtocolVersions {
+  read: number;
+  write: number;
+  accepts: (version: number) => boolean;
+  onUpgrade: ((version: number) => void) | undefined;
+}
+
+interface PendingUpgrade {
+  version: number;
+  resolve: () => void;
+  reject: (error: ClavisError) => void;
+}
+
+/** Leads the `negotiatePacketSize` advertisement, followed by a u32 limit */
+const PACKET_LIMIT_MAGIC = [0x43, 0x4c, 0x56, 0x4c]; // "CLVL"
+
+/** Announces `sequenceNumbers`; frames after it carry their sequence number */
+const SEQUENCE_MAGIC = [0x43, 0x4c, 0x56, 0x53]; // "CLVS"
+
+/**
+ * Concatenate sealed frames into one buffer so they go out in a single write
+ */
+function concatFrames(frames: readonly Uint8Array[]): Uint8Array {
+  let total = 0;
+  for (const frame of frames) total += frame.length;
+  const batch = new Uint8Array(total);
+  let offset = 0;
+  for (const frame of frames) {
+    batch.set(frame, offset);
+    offset += frame.length;
+  }
+  return batch;
+}
+
+/** Control frames that answer the peer rather than ask it something */
+const ANSWER_KINDS: ReadonlySet<ControlFrameKind> = new Set([
+  ControlFrameKind.ResizeAccept,
+  ControlFrameKind.ResizeReject,
+  ControlFrameKind.Ack,
+  ControlFrameKind.TimeReply,
+  ControlFrameKind.RekeyResponse,
+  ControlFrameKind.RekeyConfirm,
+  ControlFrameKind.Pong,
+  ControlFrameKind.UpgradeAccept,
+  ControlFrameKind.UpgradeReject,
+  ControlFrameKind.UpgradeConfirm,
+]);
+
+/** The parts of an exported session that `FrameSession` keeps */
+type FramingState = Omit<
+  SessionState,
+  "suite" | "exporterSecret" | "sessionId" | "pskIdentity" | "postQuantum" | "resumed" | "peer" | "format" | "timings"
+>;
+
+/**
+ * Framing state shared by a stream and its split halves: ciphers, the
+ * current packet size limits and whatever was negotiated after the handshake
+ */
+class FrameSession {
+  /** Packet size limits both ways, and the resize exchange that changes them */
+  readonly limits: PacketLimits;
+  compressor: PacketCompressor | undefined;
+  padder: PacketPadder | undefined;
+  /** Cipher for keys from rekeys, of the negotiated suite */
+  cipherFor: (key: Uint8Array) => FrameCipher = (key) => new XChaCha20Poly1305Cipher(key);
+  private coverTimer: TimerHandle | undefined;
+  /** Set once the transport closed under the cover traffic */
+  private coverStopped = false;
+  private deferredError: unknown;
+  /** Set once `protocolVersion` was negotiated */
+  private versions: ProtocolVersions | undefined;
+  private pendingUpgrade: PendingUpgrade | undefined;
+  /** The peer's upgrade we accepted; our reads switch at its confirmation */
+  private acceptedUpgrade: number | undefined;
+  private acceptFilter: ((plaintext: Uint8Array) => boolean) | undefined;
+  /** Packets dropped by the accept filter */
+  droppedPackets = 0;
+  /** Set once the post-handshake exchanges are done; reads then go through it */
+  private journal: PacketJournal | undefined;
+  /** Packets written that the peer has journaled, counting setup exchanges */
+  private acknowledgedCount = 0;
+  private ackWaiters: AckWaiter[] = [];
+  private setupPackets = 0;
+  /** Packets read during setup, left out of `stats()` */
+  private setupReads = 0;
+  private decryptionFailures = 0;
+  /** Set by `EncryptedStream.new()` once setup is done */
+  handshakeMs = 0;
+  /** Set once setup is over, so setup exchanges stay out of the corpus */
+  private corpusCapture: CorpusCapture | undefined;
+  /** Set by `EncryptedStream.new()` once setup is done, when `protocolStats` is given */
+  countVariant: ((direction: PacketDirection, packet: Uint8Array) => void) | undefined;
+  /** Run in order on every application packet; see `EncryptedStream.intercept()` */
+  readonly interceptors: InterceptHook[] = [];
+  /** Packets of `readPacketView()` are decrypted into this; grows as needed */
+  private scratch: Uint8Array | undefined;
+  /** Frames of `ultraLowLatency` writes are sealed into this while the transport lets go of it */
+  private writeScratch: Uint8Array | undefined;
+  /** Frames sealed by `writePacketBuffered()`, sent ahead of the next write of any kind */
+  private pendingFrames: Uint8Array[] = [];
+  private pendingBytes = 0;
+  /** Set while `readPacketView()` reads */
+  private intoScratch = false;
+  private readSequence = 0;
+  private writeSequence = 0;
+  /** Set once both peers agreed on `sequenceNumbers`: frames then carry their number and must arrive in order */
+  sequenceNumbers = false;
+  /** Frames sealed and opened since the handshake, control frames included */
+  private recordsSent = 0;
+  private recordsReceived = 0;
+  /** Checks on outgoing frames, with debug assertions on */
+  private invariants = areDebugAssertionsEnabled() ? new FrameInvariants(0) : undefined;
+  /** Reads await several times per frame, so concurrent readers take turns */
+  private readonly readLock = new Mutex();
+  /** Set when time sync is enabled */
+  timeSync: TimeSync | undefined;
+  private pings = new Map<number, PendingPing>();
+  private nextPingId = 0;
+  readonly bandwidth: BandwidthEstimator;
+  private readonly cpu: CpuTime = { serializeMs: 0, compressionMs: 0, cryptoMs: 0 };
+  /** Unset where rekeying isn't possible */
+  rekeying: RekeyMachine | undefined;
+  /** Where new keys are logged, with `keyLog` */
+  keyLogger: KeyLogger | undefined;
+  /** Keys behind `cipher` and `decipher`, for `exportState()`; unset under the null cipher */
+  sendingKey: Uint8Array | undefined;
+  receivingKey: Uint8Array | undefined;
+  /** Set once setup is done; automatic rekeys and the idle checks wait for it */
+  private setupDone = false;
+  /** When data last arrived, as far as the idle check has noticed */
+  private lastReceivedAt = 0;
+  private lastReceivedBytes = 0;
+  private lastKeepAliveAt = 0;
+  /** Packets written as of the last idle check, to tell whether traffic is flowing */
+  private lastWrittenPackets = 0;
+  /** Multiple of `keepAliveMs` to wait before the next ping; doubles while the transport is backed up */
+  private keepAliveBackoff = 1;
+  private keepAlivesSent = 0;
+  private keepAlivesSuppressed = 0;
+  private idleTimer: TimerHandle | undefined;
+  /** Set once the idle checks have started watching the connection */
+  private idleWatched = false;
+  /** Set once the transport closed under the idle checks */
+  private idleStopped = false;
+  /** Set once `close()` was called; only answers to the peer may follow */
+  private writeClosed = false;
+  /** Set once the peer's close frame arrived */
+  private peerClosed = false;
+  /** Set once the transport ended or failed; reads then fail without waiting */
+  private transportEnded = false;
+  /** When each write still on the transport started, oldest first, for the watchdog */
+  private writesInFlight: number[] = [];
+  /** How the control exchanges send their frames */
+  private readonly control: ControlChannel = {
+    request: async (kind, payload) => {
+      try {
+        await this.sendControl(kind, payload);
+      } catch (error) {
+        throw this.withContext(error, "write", this.writeSequence);
+      }
+    },
+    answer: (kind, payload) => this.sendControl(kind, payload),
+  };
+
+  constructor(
+    readonly connectionId: string,
+    readonly adapter: StreamAdapter,
+    private cipher: FrameCipher,
+    private decipher: FrameCipher,
+    private readonly options: NormalizedOptions,
+    private readGuard: PacketRateGuard | undefined
+  ) {
+    this.limits = new PacketLimits(options.maxPacketSize, options.maxNegotiablePacketSize, this.control);
+    this.bandwidth = new BandwidthEstimator(options.clock);
+    adapter.onClose(() => {
+      this.transportEnded = true;
+    });
+  }
+
+  /**
+   * Read the next application packet.
+   * Control frames in between are handled here and never returned.
+   */
+  readPacket(): Promise<Uint8Array> {
+    return this.inSpan("clavis.read", () => this.timeBound("read", this.readLock.runExclusive(() => {
+      this.checkUnjournaled();
+      return this.readNext();
+    })));
+  }
+
+  /**
+   * Read the next application packet if a whole frame is already buffered,
+   * without waiting for the transport. Undefined when none is, or while
+   * another read holds the stream; errors are thrown as `readPacket()`
+   * would.
+   */
+  async tryReadPacket(): Promise<Uint8Array | undefined> {
+    if (this.readLock.locked) return undefined;
+    return this.readLock.runExclusive(async () => {
+      this.checkUnjournaled();
+      for (;;) {
+        const packet = await this.readBufferedAccepted();
+        if (packet === undefined || this.interceptors.length === 0) return packet;
+        try {
+          if (await this.intercepted("read", packet)) return packet;
+        } catch (error) {
+          throw this.withContext(error, "read", this.readSequence);
+        }
+      }
+    });
+  }
+
+  /**
+   * Read the next application packet, decrypted into a buffer the next
+   * call reuses where the cipher allows it
+   */
+  readPacketView(): Promise<Uint8Array> {
+    return this.inSpan("clavis.read", () => this.timeBound("read", this.readLock.runExclusive(async () => {
+      this.checkUnjournaled();
+      this.intoScratch = true;
+      try {
+        return await this.readNext();
+      } finally {
+        this.intoScratch = false;
+      }
+    })));
+  }
+
+  /**
+   * Read the next application packet and append it to the journal, then
+   * acknowledge everything read so far to the peer
+   */
+  readJournaled(): Promise<JournalEntry> {
+    return this.inSpan("clavis.read", () => this.timeBound("read", this.readLock.runExclusive(async () => {
+      const journal = this.journal;
+      if (!journal) {
+        throw ClavisError.invalidOperation("No packet journal configured");
+      }
+      const packet = await this.readNext();
+      try {
+        const entry = await journal.append(packet);
+        await this.sendControl(ControlFrameKind.Ack, encodeControlU32(this.readSequence));
+        return entry;
+      } catch (error) {
+        throw this.withContext(error, "read", this.readSequence);
+      }
+    })));
+  }
+
+  /**
+   * The post-handshake exchanges are connection setup, not application
+   * packets: they are never journaled and need no acknowledgment
+   */
+  finishSetup(journal: PacketJournal | undefined): void {
+    this.journal = journal;
+    this.acknowledgedCount = this.writeSequence;
+    this.setupPackets = this.writeSequence;
+    this.setupReads = this.readSequence;
+    this.corpusCapture = this.options.corpusCapture;
+    this.setupDone = true;
+    this.rekeying?.arm();
+    this.startIdleChecks();
+    this.startCoverTraffic();
+    this.startWatchdog();
+  }
+
+  /**
+   * Let go of the transport and return the framing half of the session
+   * state, for `EncryptedStream.exportState()`. Throws, still attached,
+   * while a read, a write or an exchange with the peer is under way.
+   */
+  handOff(): FramingState {
+    if (!this.options.sessionHandoff) {
+      throw ClavisError.config("exportState needs the dangerousSessionHandoff option");
+    }
+    if (this.writeClosed || this.peerClosed || this.transportEnded) {
+      throw ClavisError.invalidOperation("Can't export a closed stream");
+    }
+    if (this.readLock.locked || this.writesInFlight.length > 0 || this.pendingFrames.length > 0) {
+      throw ClavisError.invalidOperation("Can't export a stream while a read or write is under way");
+    }
+    const exchanging = this.rekeying?.busy || this.limits.busy || this.pendingUpgrade
+      || this.acceptedUpgrade !== undefined || this.timeSync?.busy || this.pings.size > 0;
+    if (exchanging) {
+      throw ClavisError.invalidOperation("Can't export a stream while an exchange with the peer is under way");
+    }
+    const { sendingKey, receivingKey } = this;
+    if (!sendingKey || !receivingKey) {
+      throw ClavisError.invalidOperation("Can't export a stream without keys");
+    }
+    const unread = this.adapter.detach();
+    return {
+      connectionId: this.connectionId,
+      sendingKey,
+      receivingKey,
+      rekeyChain: this.rekeying?.chainSecret,
+      rekeys: this.rekeys,
+      readLimit: this.limits.read,
+      writeLimit: this.limits.write,
+      writeLimitFromPeer: this.limits.writeFromPeer,
+      sequenceNumbers: this.sequenceNumbers,
+      recordsSent: this.recordsSent,
+      recordsReceived: this.recordsReceived,
+      packetsSent: this.writeSequence,
+      packetsReceived: this.readSequence,
+      setupPacketsSent: this.setupPackets,
+      setupPacketsReceived: this.setupReads,
+      readProtocolVersion: this.versions?.read,
+      writeProtocolVersion: this.versions?.write,
+      timeSync: this.timeSync !== undefined,
+      unread,
+    };
+  }
+
+  /**
+   * Carry on from the framing state another process handed off, in place
+   * of the setup exchanges; see `EncryptedStream.importState()`. Rekeys and
+   * time sync are enabled before this, as during setup.
+   */
+  resume(state: FramingState, versions: ProtocolVersions | undefined): void {
+    this.sendingKey = state.sendingKey;
+    this.receivingKey = state.receivingKey;
+    this.limits.read = state.readLimit;
+    this.limits.write = state.writeLimit;
+    this.limits.writeFromPeer = state.writeLimitFromPeer;
+    this.sequenceNumbers = state.sequenceNumbers;
+    this.recordsSent = state.recordsSent;
+    this.recordsReceived = state.recordsReceived;
+    if (this.invariants) {
+      this.invariants = new FrameInvariants(state.recordsSent, state.sequenceNumbers ? state.recordsSent : undefined);
+    }
+    this.writeSequence = state.packetsSent;
+    this.readSequence = state.packetsReceived;
+    this.versions = versions;
+    this.finishSetup(undefined);
+    // Stats keep leaving out the setup exchanges of the original connection
+    this.setupPackets = state.setupPacketsSent;
+    this.setupReads = state.setupPacketsReceived;
+  }
+
+  /** Report stalled reads and writes while the connection is open, with `watchdog` */
+  private startWatchdog(): void {
+    const options = this.options.watchdog;
+    if (!options) return;
+    const watchdog = new StallWatchdog(this.connectionId, options, this.options.clock, {
+      readWaiting: () => !this.readLock.locked && this.adapter.buffered() > 0,
+      oldestWriteAt: () => this.writesInFlight[0],
+      state: () => ({
+        packetsSent: this.writeSequence - this.setupPackets,
+        packetsReceived: this.readSequence - this.setupReads,
+        unreadBytes: this.adapter.buffered(),
+        reading: this.readLock.locked,
+        queuedReads: this.readLock.waiting,
+        pendingWrites: this.writesInFlight.length,
+        bufferedWriteBytes: this.pendingBytes,
+        lastSentSequence: this.lastSentSequence,
+        lastReceivedSequence: this.lastReceivedSequence,
+        writeClosed: this.writeClosed,
+        peerClosed: this.peerClosed,
+      }),
+    });
+    watchdog.start();
+    // Runs at once on a transport that is already closed, stopping the checks
+    this.adapter.onClose(() => watchdog.stop());
+  }
+
+  /** Count `write` as in flight until it settles */
+  private inFlight<R>(write: Promise<R>): Promise<R> {
+    const started = this.options.clock.now();
+    this.writesInFlight.push(started);
+    return write.finally(() => {
+      this.writesInFlight.splice(this.writesInFlight.indexOf(started), 1);
+    });
+  }
+
+  /** Send cover frames at random intervals while the connection is open */
+  private startCoverTraffic(): void {
+    if (this.padder?.nextCoverDelay() === undefined) return;
+    this.scheduleCover();
+    // Runs at once on a transport that is already closed, cancelling the timer
+    this.adapter.onClose(() => {
+      this.coverStopped = true;
+      this.coverTimer?.cancel();
+      this.coverTimer = undefined;
+    });
+  }
+
+  private scheduleCover(): void {
+    const padder = this.padder!;
+    this.coverTimer = this.options.clock.setTimer(() => {
+      this.coverTimer = undefined;
+      if (this.writeClosed || this.coverStopped) return;
+      const frame = this.timed("cryptoMs", () => sealFrame(this.cipher, padder.cover(this.limits.write - FRAME_TAG_LENGTH), false, this.nextNonce()));
+      // A failed write fails the stream's own reads and writes too
+      this.send(frame).catch(() => undefined);
+      this.scheduleCover();
+    }, padder.nextCoverDelay()!);
+  }
+
+  /**
+   * Watch for a silent peer: ping it after `keepAliveMs` without data and
+   * fail the stream after `idleTimeoutMs`. Arrivals are counted by the
+   * adapter, so frames waiting for a reader still count as activity.
+   */
+  private startIdleChecks(): void {
+    const { keepAliveMs, idleTimeoutMs } = this.options;
+    if (keepAliveMs === undefined && idleTimeoutMs === undefined) return;
+    this.lastReceivedAt = this.options.clock.now();
+    this.lastReceivedBytes = this.adapter.receivedBytes();
+    this.lastKeepAliveAt = this.lastReceivedAt;
+    this.lastWrittenPackets = this.writeSequence;
+    this.idleWatched = true;
+    this.scheduleIdleCheck();
+    // Runs at once on a transport that is already closed, cancelling the timer
+    this.adapter.onClose(() => {
+      this.idleStopped = true;
+      this.idleTimer?.cancel();
+      this.idleTimer = undefined;
+    });
+  }
+
+  /** Current soft limits, as `reconfigure()` changes them */
+  private softLimits(): SoftLimits {
+    const { keepAliveMs, idleTimeoutMs, readTimeoutMs, writeTimeoutMs, decodeBudgetMs, rekeyAfterBytes, rekeyAfterMs } =
+      this.options;
+    return {
+      maxPacketsPerSecond: this.readGuard?.limit.rate,
+      packetBurst: this.readGuard?.limit.burst,
+      keepAliveMs,
+      idleTimeoutMs,
+      readTimeoutMs,
+      writeTimeoutMs,
+      decodeBudgetMs,
+      rekeyAfterBytes,
+      rekeyAfterMs,
+    };
+  }
+
+  /**
+   * Change soft limits on the live connection. The result is checked as a
+   * whole first, so a patch with any problem changes nothing.
+   */
+  reconfigure(patch: StreamReconfiguration): void {
+    const next = this.softLimits();
+    for (const [name, value] of Object.entries(patch) as Array<[keyof SoftLimits, number | null | undefined]>) {
+      if (value !== undefined) next[name] = value ?? undefined;
+    }
+    if (patch.maxPacketsPerSecond !== undefined && patch.packetBurst === undefined) {
+      next.packetBurst = undefined;
+    }
+
+    const conflicts: ConfigConflict[] = [];
+    const conflict = (names: string[], message: string) => conflicts.push({ options: names, message });
+    for (const [name, value] of Object.entries(next)) {
+      if (name !== "packetBurst" && !(value === undefined || value > 0)) conflict([name], `${name} must be positive`);
+    }
+    if (next.packetBurst !== undefined && !(next.packetBurst >= 1)) {
+      conflict(["packetBurst"], "packetBurst must be at least 1");
+    }
+    if (next.packetBurst !== undefined && next.maxPacketsPerSecond === undefined) {
+      conflict(["packetBurst"], "packetBurst needs maxPacketsPerSecond");
+    }
+    if (next.keepAliveMs !== undefined && next.idleTimeoutMs !== undefined && next.idleTimeoutMs <= next.keepAliveMs) {
+      conflict(["keepAliveMs", "idleTimeoutMs"], "idleTimeoutMs must be longer than keepAliveMs, or a quiet peer times out before it is pinged");
+    }
+    if (!this.rekeying && (next.rekeyAfterBytes !== undefined || next.rekeyAfterMs !== undefined)) {
+      conflict(
+        [next.rekeyAfterBytes !== undefined ? "rekeyAfterBytes" : "rekeyAfterMs"],
+        "This connection has no keys to roll, so rekeyAfterBytes and rekeyAfterMs can't be set"
+      );
+    }
+    if (conflicts.length > 0) throw ClavisError.configConflicts(conflicts);
+
+    Object.assign(this.options, {
+      keepAliveMs: next.keepAliveMs,
+      idleTimeoutMs: next.idleTimeoutMs,
+      readTimeoutMs: next.readTimeoutMs,
+      writeTimeoutMs: next.writeTimeoutMs,
+      decodeBudgetMs: next.decodeBudgetMs,
+      rekeyAfterBytes: next.rekeyAfterBytes,
+      rekeyAfterMs: next.rekeyAfterMs,
+    });
+    const rate = next.maxPacketsPerSecond;
+    const burst = next.packetBurst ?? rate;
+    if (rate === undefined || burst === undefined) {
+      this.readGuard = undefined;
+    } else if (this.readGuard) {
+      this.readGuard.configure(rate, burst);
+    } else {
+      this.readGuard = new PacketRateGuard(rate, burst, this.options.clock);
+    }
+
+    // Before setup is done there is nothing to reschedule; finishSetup() starts the checks
+    if (!this.setupDone || this.idleStopped) return;
+    this.idleTimer?.cancel();
+    this.idleTimer = undefined;
+    if (!this.idleWatched) {
+      this.startIdleChecks();
+    } else if (next.keepAliveMs !== undefined || next.idleTimeoutMs !== undefined) {
+      // A new idle timeout counts from now, not from data that arrived under the old one
+      if (patch.idleTimeoutMs !== undefined) {
+        this.lastReceivedAt = Math.max(this.lastReceivedAt, this.options.clock.now());
+        this.lastReceivedBytes = this.adapter.receivedBytes();
+      }
+      this.scheduleIdleCheck();
+    }
+  }
+
+  private scheduleIdleCheck(): void {
+    const { keepAliveMs, idleTimeoutMs } = this.options;
+    let due = Infinity;
+    if (idleTimeoutMs !== undefined) due = this.lastReceivedAt + idleTimeoutMs;
+    if (keepAliveMs !== undefined) {
+      due = Math.min(due, Math.max(this.lastReceivedAt, this.lastKeepAliveAt) + keepAliveMs * this.keepAliveBackoff);
+    }
+    this.idleTimer = this.options.clock.setTimer(
+      () => this.checkIdle(),
+      Math.max(0, due - this.options.clock.now())
+    );
+  }
+
+  private checkIdle(): void {
+    this.idleTimer = undefined;
+    const { keepAliveMs, idleTimeoutMs } = this.options;
+    const now = this.options.clock.now();
+    const received = this.adapter.receivedBytes();
+    if (received !== this.lastReceivedBytes) {
+      this.lastReceivedBytes = received;
+      this.lastReceivedAt = now;
+    }
+
+    if (idleTimeoutMs !== undefined && now - this.lastReceivedAt >= idleTimeoutMs) {
+      this.adapter.fail(StreamError.idleTimeout(idleTimeoutMs));
+      return;
+    }
+    const written = this.writeSequence;
+    const flowing = written !== this.lastWrittenPackets;
+    this.lastWrittenPackets = written;
+    const quietMs = now - Math.max(this.lastReceivedAt, this.lastKeepAliveAt);
+    if (keepAliveMs !== undefined && quietMs >= keepAliveMs * this.keepAliveBackoff) {
+      this.lastKeepAliveAt = now;
+      const congested = !flowing && this.bandwidth.estimate().queuedWrites > 0;
+      const backoff = congested ? Math.min(this.keepAliveBackoff * 2, MAX_KEEPALIVE_BACKOFF) : 1;
+      // Only the peer's answer holds off the idle timeout, so skip a ping
+      // only if another one is due before it strikes
+      const deadline = idleTimeoutMs === undefined ? Infinity : this.lastReceivedAt + idleTimeoutMs;
+      if ((flowing || congested) && now + keepAliveMs * backoff < deadline) {
+        // Outgoing packets already keep NAT mappings open, and a ping would
+        // queue behind writes the transport can't take yet
+        this.keepAlivesSuppressed++;
+        this.keepAliveBackoff = backoff;
+      } else {
+        this.keepAlivesSent++;
+        this.keepAliveBackoff = 1;
+        // An unanswered ping is settled by the idle timeout or a failed read
+        this.ping().catch(() => undefined);
+      }
+    }
+    this.scheduleIdleCheck();
+  }
+
+  /** Application packets written that the peer has journaled */
+  get acknowledged(): number {
+    return this.acknowledgedCount - this.setupPackets;
+  }
+
+  private checkUnjournaled(): void {
+    if (this.journal) {
+      throw ClavisError.invalidOperation("Packets of a journaled stream must be read with readJournaled()");
+    }
+  }
+
+  /** Read the next packet that passes the accept filter and the read interceptors */
+  private async readNext(): Promise<Uint8Array> {
+    for (;;) {
+      const packet = await this.readAccepted();
+      if (this.interceptors.length === 0) return packet;
+      try {
+        if (await this.intercepted("read", packet)) return packet;
+      } catch (error) {
+        throw this.withContext(error, "read", this.readSequence);
+      }
+    }
+  }
+
+  private async readAccepted(): Promise<Uint8Array> {
+    if (this.deferredError !== undefined) {
+      const error = this.deferredError;
+      this.deferredError = undefined;
+      throw error;
+    }
+    if (this.peerClosed) {
+      throw this.withContext(StreamError.closed(), "read", this.readSequence);
+    }
+
+    try {
+      for (;;) {
+        const packet = await this.readFrame();
+        if (packet && this.accepts(packet)) return packet;
+      }
+    } catch (error) {
+      throw this.failReads(error);
+    }
+  }
+
+  /**
+   * `readAccepted()` over what is already buffered: frames that need no
+   * more data from the transport. Undefined once none is left.
+   */
+  private async readBufferedAccepted(): Promise<Uint8Array | undefined> {
+    if (this.deferredError !== undefined) {
+      const error = this.deferredError;
+      this.deferredError = undefined;
+      throw error;
+    }
+    if (this.peerClosed) {
+      throw this.withContext(StreamError.closed(), "read", this.readSequence);
+    }
+
+    try {
+      while (this.frameReady()) {
+        const packet = await this.readFrame();
+        if (packet && this.accepts(packet)) return packet;
+      }
+      return undefined;
+    } catch (error) {
+      throw this.failReads(error);
+    }
+  }
+
+  /**
+   * Whether `readFrame()` would finish without waiting: a whole frame is
+   * buffered, its header already fails the length check, or the transport
+   * has ended
+   */
+  private frameReady(): boolean {
+    if (this.transportEnded) return true;
+    const header = this.adapter.peek(FRAME_HEADER_LENGTH);
+    if (!header) return false;
+    const { length } = decodeFrameHeader(header);
+    if (length <= 0 || length > this.maxCiphertextLength()) return true;
+    return this.adapter.buffered() >= FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH + length;
+  }
+
+  /** Wrap a read error, and fail what waited on reads, since nothing more can arrive */
+  private failReads(error: unknown): ClavisError {
+    const failure = this.readFailure(error);
+    for (const waiter of this.ackWaiters.splice(0)) waiter.reject(failure);
+    this.timeSync?.fail(failure);
+    for (const ping of this.pings.values()) ping.reject(failure);
+    this.pings.clear();
+    this.rekeying?.fail(failure);
+    return failure;
+  }
+
+  /** Read the rest of a frame whose header arrived; running out of data now means it was cut short */
+  private async readRest(length: number): Promise<Uint8Array> {
+    try {
+      return await this.adapter.read(length);
+    } catch (error) {
+      if (error instanceof StreamError && error.code === StreamErrorCode.EOF) {
+        throw StreamError.unexpectedClose();
+      }
+      throw error;
+    }
+  }
+
+  /** Read one frame; undefined for control frames */
+  private async readFrame(): Promise<Uint8Array | undefined> {
+    // Read length (u32 little-endian); the top bit marks control frames
+    const { length, control } = decodeFrameHeader(await this.adapter.read(FRAME_HEADER_LENGTH));
+    // Count the frame before spending any work on it
+    this.readGuard?.check(this.adapter);
+    this.checkLength(length);
+
+    const nonce = await this.readRest(FRAME_NONCE_LENGTH);
+    const ciphertext = await this.readRest(length);
+    if (!control) {
+      return this.open(nonce, ciphertext);
+    }
+    this.rekeying?.countKeyed(length);
+    const plaintext = this.timed("cryptoMs", () => openFrame(this.decipher, { length, control, nonce, ciphertext }));
+    this.opened(nonce);
+    await this.handleControl(plaintext);
+    return undefined;
+  }
+
+  /**
+   * Read at least one packet, plus any further packets whose frames are
+   * already fully buffered, up to `max`.
+   * An error hit after the first packet is thrown by the next read instead.
+   */
+  readPackets(max: number): Promise<Uint8Array[]> {
+    return this.inSpan("clavis.read", () => this.timeBound("read", this.readLock.runExclusive(async () => {
+      this.checkUnjournaled();
+      const packets = [await this.readNext()];
+      try {
+        if (this.interceptors.length === 0) {
+          this.openBuffered(packets, max);
+        } else {
+          await this.openBufferedIntercepted(packets, max);
+        }
+      } catch (error) {
+        this.deferredError = this.readFailure(error);
+      }
+      return packets;
+    })));
+  }
+
+  /** Encrypt and write one packet; resolves with the bytes written */
+  async writePacket(packet: PacketTrait): Promise<number> {
+    if (this.options.ultraLowLatency && this.canWriteNow()) return this.writeNow(packet);
+    const sequence = this.writeSequence;
+    try {
+      const plaintext = this.serialize(packet);
+      if (this.interceptors.length > 0 && !(await this.intercepted("write", plaintext))) return 0;
+      const frame = this.seal(plaintext);
+      this.writeSequence++;
+      return await this.inSpan("clavis.write", () => this.timeBound("write", this.write(frame)));
+    } catch (error) {
+      throw this.withContext(error, "write", sequence);
+    }
+  }
+
+  /**
+   * Encrypt several packets and send them in a single write.
+   * Nothing is written if any packet is too large, and nothing is
+   * encrypted if one reports a size over the limit.
+   */
+  async writePackets(packets: Iterable<PacketTrait>): Promise<number> {
+    const first = this.writeSequence;
+    let sequence = first;
+    try {
+      const batch = [...packets];
+      for (const packet of batch) {
+        this.checkSize(packet);
+        sequence++;
+      }
+      sequence = first;
+      const plaintexts: Uint8Array[] = [];
+      for (const packet of batch) {
+        plaintexts.push(this.serialize(packet));
+        sequence++;
+      }
+      let kept = plaintexts;
+      if (this.interceptors.length > 0) {
+        kept = [];
+        for (const plaintext of plaintexts) {
+          if (await this.intercepted("write", plaintext)) kept.push(plaintext);
+        }
+      }
+      if (kept.length === 0) return 0;
+      sequence = this.writeSequence;
+      const start = sequence;
+      const frames: Uint8Array[] = [];
+      for (const plaintext of kept) {
+        frames.push(this.seal(plaintext));
+        sequence++;
+      }
+      this.writeSequence = sequence;
+      sequence = start;
+      return await this.inSpan("clavis.write", () => this.timeBound("write", this.write(concatFrames(frames))));
+    } catch (error) {
+      throw this.withContext(error, "write", sequence);
+    }
+  }
+
+  /**
+   * Encrypt a packet and hold its frame until the next flush. Resolves at
+   * once, unless the held frames reach `maxBufferedBytes`: then they are
+   * flushed and it resolves once the transport took them.
+   */
+  async writePacketBuffered(packet: PacketTrait): Promise<void> {
+    const sequence = this.writeSequence;
+    try {
+      const plaintext = this.serialize(packet);
+      if (this.interceptors.length > 0 && !(await this.intercepted("write", plaintext))) return;
+      const frame = this.seal(plaintext);
+      this.writeSequence++;
+      this.pendingFrames.push(frame);
+      this.pendingBytes += frame.length;
+    } catch (error) {
+      throw this.withContext(error, "write", sequence);
+    }
+    if (this.pendingBytes >= this.options.maxBufferedBytes) await this.flush();
+  }
+
+  /** `writePacketBuffered()` for each packet in turn, waiting whenever the buffer fills */
+  async feed(packets: Iterable<PacketTrait> | AsyncIterable<PacketTrait>): Promise<void> {
+    for await (const packet of packets) await this.writePacketBuffered(packet);
+  }
+
+  /** Send every held frame in one write; resolves with the bytes written */
+  async flush(): Promise<number> {
+    if (this.pendingFrames.length === 0) return 0;
+    const sequence = this.writeSequence - this.pendingFrames.length;
+    try {
+      return await this.inSpan("clavis.write", () => this.timeBound("write", this.write(new Uint8Array(0))));
+    } catch (error) {
+      throw this.withContext(error, "write", sequence);
+    }
+  }
+
+  /** Bytes of sealed frames waiting for a flush */
+  get bufferedWriteBytes(): number {
+    return this.pendingBytes;
+  }
+
+  /** Whether nothing on the write path needs the usual, asynchronous route */
+  private canWriteNow(): boolean {
+    return this.adapter.writeNow !== undefined && this.interceptors.length === 0
+      && !this.compressor && !this.padder && !this.options.tracer && this.pendingFrames.length === 0;
+  }
+
+  /**
+   * The `ultraLowLatency` write: seal into the reused buffer and hand the
+   * frame to the transport before returning
+   */
+  private writeNow(packet: PacketTrait): Promise<number> {
+    const sequence = this.writeSequence;
+    try {
+      const plaintext = this.serialize(packet);
+      this.countVariant?.("write", plaintext);
+      const frame = this.timed("cryptoMs", () => sealFrameInto(this.cipher, plaintext, this.frameBuffer(plaintext.length), this.nextNonce()));
+      this.writeSequence++;
+      const drained = this.bandwidth.queued(frame.length);
+      this.rekeying?.countKeyed(frame.length);
+      this.invariants?.writing(frame);
+      if (this.adapter.writeNow!(frame)) {
+        drained();
+        return Promise.resolve(frame.length);
+      }
+      return this.inFlight(this.adapter.drained!()).then(() => {
+        drained();
+        return frame.length;
+      });
+    } catch (error) {
+      return Promise.reject(this.withContext(error, "write", sequence));
+    }
+  }
+
+  /**
+   * Buffer for the next frame: the last one when the transport has let go
+   * of it, else a new one, so a frame still queued is never overwritten
+   */
+  private frameBuffer(plaintextLength: number): Uint8Array {
+    const size = FRAME_OVERHEAD + plaintextLength;
+    const buffer = this.writeScratch;
+    if (buffer && buffer.length >= size && this.adapter.flushed!()) return buffer;
+    this.writeScratch = new Uint8Array(Math.max(size, FRAME_OVERHEAD + this.limits.write));
+    return this.writeScratch;
+  }
+
+  /**
+   * Refuse a packet over the write limit, by the size it reports when it
+   * can report one so nothing is serialized or encrypted
+   */
+  private checkSize(packet: PacketTrait, size: number | undefined = packet.serializedSize?.()): void {
+    if (size !== undefined && size > this.limits.write) {
+      throw ClavisError.message(
+        MessageError.packetTooLarge(size, this.limits.write, packet.variantName, this.limits.writeFromPeer)
+      );
+    }
+  }
+
+  /** Serialize and check one packet */
+  private serialize(packet: PacketTrait): Uint8Array {
+    this.checkWritable();
+    this.checkSize(packet);
+    const plaintext = this.timed("serializeMs", () => packet.serialize());
+    this.checkSize(packet, plaintext.length);
+    return plaintext;
+  }
+
+  /** Count, compress and encrypt one serialized packet into a frame */
+  private seal(plaintext: Uint8Array): Uint8Array {
+    // Checked again, since the stream may have closed while interceptors ran
+    this.checkWritable();
+    this.countVariant?.("write", plaintext);
+
+    const compressor = this.compressor;
+    const body = compressor ? this.timed("compressionMs", () => compressor.compress(plaintext)) : plaintext;
+    const padded = this.padder ? this.padder.pad(body, this.limits.write - FRAME_TAG_LENGTH) : body;
+    return this.timed("cryptoMs", () => sealFrame(this.cipher, padded, false, this.nextNonce()));
+  }
+
+  private checkWritable(): void {
+    if (this.writeClosed) {
+      throw ClavisError.invalidOperation("Cannot write to a closed stream");
+    }
+  }
+
+  /**
+   * Run the interceptors on a packet going `direction`; false once one
+   * drops it. Dropped reads count towards `droppedPackets`.
+   */
+  private async intercepted(direction: PacketDirection, packet: Uint8Array): Promise<boolean> {
+    for (const hook of [...this.interceptors]) {
+      if ((await hook(direction, packet)) === false) {
+        if (direction === "read") this.droppedPackets++;
+        return false;
+      }
+    }
+    return true;
+  }
+
+  /** Run `work`, adding the time it took to one of the CPU time counters */
+  private timed<R>(counter: keyof CpuTime, work: () => R): R {
+    const start = this.options.clock.now();
+    try {
+      return work();
+    } finally {
+      this.cpu[counter] += this.options.clock.now() - start;
+    }
+  }
+
+  /** Time spent serializing, compressing and encrypting so far */
+  get cpuTime(): CpuTime {
+    return { ...this.cpu };
+  }
+
+  stats(): ConnectionStats {
+    return {
+      packetsSent: this.writeSequence - this.setupPackets,
+      packetsReceived: this.readSequence - this.setupReads,
+      bytesSent: this.adapter.sentBytes(),
+      bytesReceived: this.adapter.receivedBytes(),
+      handshakeMs: this.handshakeMs,
+      rekeys: this.rekeys,
+      decryptionFailures: this.decryptionFailures,
+      keepAlivesSent: this.keepAlivesSent,
+      keepAlivesSuppressed: this.keepAlivesSuppressed,
+    };
+  }
+
+  /** Write sealed frames; resolves with the bytes written */
+  private async write(frames: Uint8Array): Promise<number> {
+    // Length, nonce and ciphertext go out in a single write so concurrent
+    // writers can't interleave their frames
+    const total = this.pendingBytes + frames.length;
+    await this.send(frames);
+    return total;
+  }
+
+  /**
+   * Hand bytes to the transport, after any buffered frames so frames keep
+   * the order they were sealed in, keeping the bandwidth estimate up to date
+   */
+  private async send(bytes: Uint8Array): Promise<void> {
+    if (this.pendingFrames.length > 0) {
+      if (this.invariants) checkBuffered(this.pendingBytes, this.pendingFrames, "Held frames");
+      bytes = concatFrames([...this.pendingFrames.splice(0), bytes]);
+      this.pendingBytes = 0;
+    }
+    this.invariants?.writing(bytes);
+    const drained = this.bandwidth.queued(bytes.length);
+    this.rekeying?.countKeyed(bytes.length);
+    try {
+      await this.inFlight(this.adapter.write(bytes));
+    } finally {
+      drained();
+    }
+  }
+
+  /** Allow rekeys, chained to `chain`; `count` carries over the rekeys of a handed-off session */
+  async enableRekeys(chain: Uint8Array, count = 0): Promise<void> {
+    const { RekeyMachine } = await import("./rekey.js");
+    this.rekeying = new RekeyMachine(chain, count, this.options, this.options.clock, {
+      ...this.control,
+      useSendingKey: (key) => {
+        this.cipher = this.cipherFor(key);
+        this.sendingKey = key;
+      },
+      useReceivingKey: (key) => {
+        this.decipher = this.cipherFor(key);
+        this.receivingKey = key;
+      },
+      derived: (sendingKey, receivingKey) => {
+        this.keyLogger?.sending(sendingKey);
+        this.keyLogger?.receiving(receivingKey);
+      },
+    });
+  }
+
+  /** Answer the peer's clock probes and allow `measureClockOffset()` */
+  async enableTimeSync(): Promise<void> {
+    const { TimeSync } = await import("./time-sync.js");
+    this.timeSync = new TimeSync(this.control);
+  }
+
+  /** Completed rekeys, whichever side started them */
+  get rekeys(): number {
+    return this.rekeying?.count ?? 0;
+  }
+
+  /**
+   * Roll the session keys with a fresh key exchange over control frames.
+   * Resolves once both directions use the new keys. Needs someone to be
+   * reading from the stream, since the answer arrives as a control frame.
+   */
+  async rekey(): Promise<void> {
+    if (!this.rekeying) {
+      throw ClavisError.invalidOperation("Rekeying is not available with the null cipher");
+    }
+    return this.rekeying.start();
+  }
+
+  /** Switch both directions to new keys; only while setting up, before anything else is sent or read */
+  switchKeys(sendingKey: Uint8Array, receivingKey: Uint8Array): void {
+    this.cipher = this.cipherFor(sendingKey);
+    this.decipher = this.cipherFor(receivingKey);
+    this.sendingKey = sendingKey;
+    this.receivingKey = receivingKey;
+    this.keyLogger?.sending(sendingKey);
+    this.keyLogger?.receiving(receivingKey);
+  }
+
+  /**
+   * Ask the peer to switch both directions to a new max packet size.
+   * Resolves once the peer agreed; rejects if it refused. Needs someone to be
+   * reading from the stream, since the answer arrives as a control frame.
+   */
+  requestMaxPacketSize(size: number): Promise<number> {
+    return this.limits.request(size);
+  }
+
+  /** Track protocol versions from the ones negotiated during setup, allowing upgrades */
+  enableProtocolUpgrades(versions: ProtocolVersions): void {
+    this.versions = versions;
+  }
+
+  /** Protocol version of the packets being read, or undefined without `protocolVersion` */
+  get readProtocolVersion(): number | undefined {
+    return this.versions?.read;
+  }
+
+  /** Protocol version packets are written in, or undefined without `protocolVersion` */
+  get writeProtocolVersion(): number | undefined {
+    return this.versions?.write;
+  }
+
+  /**
+   * Ask the peer to move both directions to protocol version `version`.
+   * Our packets stay in the old version until the peer accepts, and its
+   * packets until its acceptance. Resolves once both directions switched;
+   * rejects if the peer refused. Needs someone to be reading from the
+   * stream, since the answer arrives as a control frame.
+   */
+  async upgradeProtocol(version: number): Promise<void> {
+    const versions = this.versions;
+    if (!versions) {
+      throw ClavisError.invalidOperation("Protocol upgrades need the protocolVersion option");
+    }
+    if (!Number.isInteger(version) || version < 0 || version > 0xffffffff) {
+      throw ClavisError.invalidOperation(`Protocol version must be a u32, got ${version}`);
+    }
+    if (this.pendingUpgrade || this.acceptedUpgrade !== undefined) {
+      throw ClavisError.invalidOperation("A protocol upgrade is already pending");
+    }
+    if (versions.read === version && versions.write === version) return;
+
+    const result = new Promise<void>((resolve, reject) => {
+      this.pendingUpgrade = { version, resolve, reject };
+    });
+    try {
+      await this.sendControl(ControlFrameKind.UpgradeRequest, encodeControlU32(version));
+    } catch (error) {
+      this.pendingUpgrade = undefined;
+      throw this.withContext(error, "write", this.writeSequence);
+    }
+    return result;
+  }
+
+  /**
+   * Resolve once the peer has journaled every packet written so far.
+   * Needs someone to be reading from the stream, since acks arrive as
+   * control frames.
+   */
+  waitForAcknowledgment(): Promise<void> {
+    const count = this.writeSequence;
+    if (this.acknowledgedCount >= count) return Promise.resolve();
+    return new Promise((resolve, reject) => {
+      this.ackWaiters.push({ count, resolve, reject });
+    });
+  }
+
+  /**
+   * Send a clock probe and resolve with the sample once the peer answers.
+   * Needs someone to be reading from the stream, since the answer arrives
+   * as a control frame.
+   */
+  async measureClockOffset(): Promise<ClockSample> {
+    if (!this.timeSync) {
+      throw ClavisError.invalidOperation("Time sync is not enabled");
+    }
+    return this.timeSync.measure();
+  }
+
+  /**
+   * Send a ping and resolve with the round trip in milliseconds once the
+   * peer answers. Needs someone to be reading from the stream, since the
+   * answer arrives as a control frame.
+   */
+  async ping(timeoutMs?: number): Promise<number> {
+    const id = this.nextPingId;
+    this.nextPingId = (this.nextPingId + 1) >>> 0;
+    let timer: TimerHandle | undefined;
+    const result = new Promise<number>((resolve, reject) => {
+      const sent = this.options.clock.now();
+      this.pings.set(id, { sent, resolve, reject });
+      if (timeoutMs !== undefined) {
+        timer = this.options.clock.setTimer(() => {
+          // A late answer is still a valid one, so keep expecting it
+          this.pings.set(id, { sent, resolve: () => undefined, reject: () => undefined });
+          reject(this.withContext(StreamError.timeout(timeoutMs), "read", this.readSequence));
+        }, timeoutMs);
+      }
+    });
+
+    try {
+      await this.sendControl(ControlFrameKind.Ping, encodeControlU32(id));
+    } catch (error) {
+      this.pings.delete(id);
+      timer?.cancel();
+      result.catch(() => undefined);
+      throw this.withContext(error, "write", this.writeSequence);
+    }
+    try {
+      return await result;
+    } finally {
+      timer?.cancel();
+    }
+  }
+
+  /** Ping with a deadline, reporting failures as a result instead of rejecting */
+  async healthCheck(timeoutMs: number): Promise<HealthCheckResult> {
+    try {
+      return { healthy: true, rttMs: await this.ping(timeoutMs) };
+    } catch (error) {
+      return healthCheckFailure(error);
+    }
+  }
+
+  /** Only pass packets the predicate accepts; undefined passes everything */
+  setAcceptFilter(accept: ((plaintext: Uint8Array) => boolean) | undefined): void {
+    this.acceptFilter = accept;
+  }
+
+  private accepts(plaintext: Uint8Array): boolean {
+    if (!this.acceptFilter || this.acceptFilter(plaintext)) return true;
+    this.droppedPackets++;
+    return false;
+  }
+
+  private inSpan<T>(name: TraceSpanName, operation: () => Promise<T>): Promise<T> {
+    return traced(this.options.tracer, name, this.connectionId, operation);
+  }
+
+  /**
+   * Apply the read or write timeout to `operation`. Running out fails the
+   * whole stream, since the frame in progress can't be picked up again.
+   */
+  private timeBound<T>(direction: ErrorDirection, operation: Promise<T>): Promise<T> {
+    const timeoutMs = direction === "read" ? this.options.readTimeoutMs : this.options.writeTimeoutMs;
+    return withDeadline(this.options.clock, timeoutMs, operation, () => {
+      const error = StreamError.timeout(timeoutMs!);
+      this.adapter.fail(error);
+      // Writes attach their own context with the sequence of the packet that stalled
+      return direction === "read" ? this.withContext(error, "read", this.readSequence) : ClavisError.stream(error);
+    });
+  }
+
+  private withContext(error: unknown, direction: ErrorDirection, sequence: number): ClavisError {
+    return toClavisError(error).withContext({ connectionId: this.connectionId, direction, sequence });
+  }
+
+  /** Wrap a read error and report it to the corruption monitor */
+  private readFailure(error: unknown): ClavisError {
+    const failure = this.withContext(error, "read", this.readSequence);
+    if (failure.cause instanceof CryptoError && failure.cause.operation === CryptoOperation.Decryption) {
+      this.decryptionFailures++;
+    }
+    this.options.corruptionMonitor?.observe(failure, this.connectionId);
+    return failure;
+  }
+
+  /** A fresh budget for one packet, or undefined without `decodeBudgetMs` */
+  decodeBudget(): DecodeBudget | undefined {
+    const budgetMs = this.options.decodeBudgetMs;
+    return budgetMs === undefined ? undefined : new DecodeBudget(budgetMs, this.options.clock);
+  }
+
+  /**
+   * Largest ciphertext a packet within the read limit seals to: the packet,
+   * what compression and padding add, and the tag
+   */
+  private maxCiphertextLength(): number {
+    return this.limits.read + FRAME_TAG_LENGTH + (this.compressor?.overhead ?? 0) + (this.padder?.overhead ?? 0);
+  }
+
+  private checkLength(length: number): void {
+    const max = this.maxCiphertextLength();
+    if (length <= 0 || length > max) {
+      throw ClavisError.message(
+        MessageError.messageTooLarge(Math.max(length - (max - this.limits.read), 0), this.limits.read)
+      );
+    }
+  }
+
+  /** Decrypt an application frame; undefined for a cover frame */
+  private open(nonce: Uint8Array, ciphertext: Uint8Array): Uint8Array | undefined {
+    this.rekeying?.countKeyed(ciphertext.length);
+    const decrypted = this.timed("cryptoMs", () => this.decrypt(nonce, ciphertext));
+    this.opened(nonce);
+    const plaintext = this.padder ? this.padder.unpad(decrypted) : decrypted;
+    if (!plaintext) return undefined;
+    const compressor = this.compressor;
+    const packet = compressor
+      ? this.timed("compressionMs", () => compressor.decompress(plaintext, this.limits.read, this.decodeBudget()))
+      : plaintext;
+    this.readSequence++;
+    this.corpusCapture?.capture(packet);
+    this.countVariant?.("read", packet);
+    return packet;
+  }
+
+  /** Number the next frame to seal: a nonce carrying it with `sequenceNumbers`, otherwise undefined for a random one */
+  private nextNonce(): Uint8Array | undefined {
+    const sequence = this.recordsSent++;
+    const nonce = this.sequenceNumbers ? sequenceNonce(sequence) : undefined;
+    this.invariants?.sealing(sequence, nonce);
+    return nonce;
+  }
+
+  /** Count a frame that opened; with `sequenceNumbers` it must carry the number expected next */
+  private opened(nonce: Uint8Array): void {
+    const expected = this.recordsReceived;
+    if (this.sequenceNumbers) {
+      const received = nonceSequence(nonce);
+      if (received !== expected) throw ClavisError.replay(expected, received);
+    }
+    this.recordsReceived++;
+  }
+
+  /** Sequence number of the last frame sealed, or undefined before the first */
+  get lastSentSequence(): number | undefined {
+    return this.recordsSent > 0 ? this.recordsSent - 1 : undefined;
+  }
+
+  /** Sequence number of the last frame opened, or undefined before the first */
+  get lastReceivedSequence(): number | undefined {
+    return this.recordsReceived > 0 ? this.recordsReceived - 1 : undefined;
+  }
+
+  /** Decrypt a frame's ciphertext, into the scratch buffer during `readPacketView()` */
+  private decrypt(nonce: Uint8Array, ciphertext: Uint8Array): Uint8Array {
+    const decipher = this.decipher;
+    const length = ciphertext.length - FRAME_TAG_LENGTH;
+    if (!this.intoScratch || !decipher.decryptInto || length < 0) {
+      return decipher.decrypt(nonce, ciphertext);
+    }
+    if (!this.scratch || this.scratch.length < length) {
+      this.scratch = new Uint8Array(Math.min(Math.max(length, (this.scratch?.length ?? 0) * 2), this.maxCiphertextLength()));
+    }
+    return decipher.decryptInto(nonce, ciphertext, this.scratch.subarray(0, length));
+  }
+
+  /**
+   * Decrypt frames that are already fully buffered, without waiting for more data.
+   * Stops at the first incomplete, invalid or control frame; the next read handles it.
+   */
+  private openBuffered(into: Uint8Array[], max: number): void {
+    while (into.length < max) {
+      const header = this.adapter.peek(FRAME_HEADER_LENGTH);
+      if (!header) return;
+      const { length, control } = decodeFrameHeader(header);
+      if (control || length <= 0 || length > this.maxCiphertextLength()) return;
+      if (this.adapter.buffered() < FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH + length) return;
+
+      this.adapter.take(FRAME_HEADER_LENGTH);
+      this.readGuard?.check(this.adapter);
+      const nonce = this.adapter.take(FRAME_NONCE_LENGTH);
+      const ciphertext = this.adapter.take(length);
+      const packet = this.open(nonce, ciphertext);
+      if (packet && this.accepts(packet)) into.push(packet);
+    }
+  }
+
+  /**
+   * `openBuffered()` one packet at a time, so each is intercepted before
+   * the next is decrypted
+   */
+  private async openBufferedIntercepted(into: Uint8Array[], max: number): Promise<void> {
+    while (into.length < max) {
+      const next: Uint8Array[] = [];
+      this.openBuffered(next, 1);
+      const packet = next[0];
+      if (!packet) return;
+      let kept: boolean;
+      try {
+        kept = await this.intercepted("read", packet);
+      } catch (error) {
+        // An interceptor's error is not a broken frame, so it skips readFailure()
+        this.deferredError = this.withContext(error, "read", this.readSequence);
+        return;
+      }
+      if (kept) into.push(packet);
+    }
+  }
+
+  /**
+   * Send an authenticated close frame and finish writing. The peer's reads
+   * then fail with Closed instead of EOF, which anyone on the path could
+   * fake by cutting the connection at a frame boundary. `linger` decides
+   * what happens next; a second call only applies its linger.
+   */
+  async close(linger: CloseLinger = this.options.closeLinger): Promise<void> {
+    if (!this.writeClosed) {
+      try {
+        await this.sendControl(ControlFrameKind.Close, new Uint8Array(0));
+      } catch (error) {
+        throw this.withContext(error, "write", this.writeSequence);
+      }
+      this.writeClosed = true;
+      this.idleTimer?.cancel();
+      this.idleTimer = undefined;
+      this.coverTimer?.cancel();
+      this.coverTimer = undefined;
+      await this.adapter.end();
+    }
+    if (linger === "abort") {
+      this.adapter.close();
+    } else if (typeof linger === "object") {
+      await this.awaitPeerClose(linger.waitForPeerMs);
+    }
+  }
+
+  /**
+   * Destroy the transport without a close frame. Unlike `close()` this
+   * doesn't queue behind writes the transport hasn't taken; those and any
+   * waiting read fail with `ConnectionClosed`.
+   */
+  destroy(): void {
+    this.writeClosed = true;
+    this.idleTimer?.cancel();
+    this.idleTimer = undefined;
+    this.coverTimer?.cancel();
+    this.coverTimer = undefined;
+    this.adapter.fail(StreamError.connectionClosed("Stream was destroyed"));
+  }
+
+  /**
+   * Discard packets until the peer's close frame or the end of its
+   * transport, then destroy the transport. A read already waiting keeps
+   * the packets it gets; the peer's close then ends it as usual.
+   */
+  private async awaitPeerClose(timeoutMs: number): Promise<void> {
+    let timedOut = false;
+    const timer = this.options.clock.setTimer(() => {
+      timedOut = true;
+      this.adapter.fail(StreamError.timeout(timeoutMs));
+    }, timeoutMs);
+    let failure: unknown;
+    try {
+      await this.readLock.runExclusive(async () => {
+        while (!this.peerClosed) await this.readFrame();
+      });
+    } catch (error) {
+      if (!isEndOfStream(error)) failure = error;
+    } finally {
+      timer.cancel();
+    }
+    this.adapter.close();
+    if (timedOut) throw ClavisError.stream(StreamError.timeout(timeoutMs));
+    if (failure !== undefined) throw this.withContext(failure, "read", this.readSequence);
+  }
+
+  private sendControl(kind: ControlFrameKind, payload: Uint8Array): Promise<void> {
+    if (this.writeClosed) {
+      // Nothing may follow our close frame; answers are dropped and requests refused
+      return ANSWER_KINDS.has(kind)
+        ? Promise.resolve()
+        : Promise.reject(ClavisError.invalidOperation("Cannot write to a closed stream"));
+    }
+    return this.send(this.timed("cryptoMs", () => sealFrame(this.cipher, encodeControlFrame(kind, payload), true, this.nextNonce())));
+  }
+
+  private async handleControl(data: Uint8Array): Promise<void> {
+    const frame = decodeControlFrame(data);
+    switch (frame.kind) {
+      case ControlFrameKind.Close:
+        this.peerClosed = true;
+        throw StreamError.closed();
+
+      case ControlFrameKind.TimeProbe:
+      case ControlFrameKind.TimeReply: {
+        const received = wallClock.now();
+        if (!this.timeSync) {
+          throw ClavisError.message(MessageError.invalidFormat("Clock probe without time sync enabled"));
+        }
+        return this.timeSync.handle(frame.kind, frame.payload, received);
+      }
+
+      case ControlFrameKind.RekeyRequest:
+      case ControlFrameKind.RekeyResponse:
+      case ControlFrameKind.RekeyConfirm:
+        if (!this.rekeying) {
+          throw ClavisError.message(MessageError.invalidFormat("Rekey on a stream that can't rekey"));
+        }
+        return this.rekeying.handle(frame.kind, frame.payload);
+
+      case ControlFrameKind.ResizeRequest:
+      case ControlFrameKind.ResizeAccept:
+      case ControlFrameKind.ResizeReject:
+        return this.limits.handle(frame.kind, frame.payload);
+    }
+
+    const value = decodeControlU32(frame.payload);
+    switch (frame.kind) {
+      case ControlFrameKind.UpgradeRequest: {
+        // The payload is a protocol version. Crossing requests are both
+        // refused, as for max packet sizes.
+        const versions = this.versions;
+        if (!versions || this.pendingUpgrade || this.acceptedUpgrade !== undefined || !versions.accepts(value)) {
+          await this.sendControl(ControlFrameKind.UpgradeReject, encodeControlU32(versions?.write ?? 0));
+          return;
+        }
+        // Switch before the acceptance is queued: everything we sent earlier
+        // was in the old version, everything after is in the new one. The
+        // peer's packets switch at its confirmation.
+        this.acceptedUpgrade = value;
+        versions.write = value;
+        versions.onUpgrade?.(value);
+        await this.sendControl(ControlFrameKind.UpgradeAccept, encodeControlU32(value));
+        return;
+      }
+
+      case ControlFrameKind.UpgradeAccept: {
+        const upgrade = this.pendingUpgrade;
+        if (!upgrade || upgrade.version !== value) {
+          throw ClavisError.message(MessageError.invalidFormat("Unexpected protocol upgrade acceptance"));
+        }
+        this.pendingUpgrade = undefined;
+        this.versions!.read = value;
+        this.versions!.write = value;
+        await this.sendControl(ControlFrameKind.UpgradeConfirm, encodeControlU32(value));
+        upgrade.resolve();
+        return;
+      }
+
+      case ControlFrameKind.UpgradeReject: {
+        const upgrade = this.pendingUpgrade;
+        if (!upgrade) {
+          throw ClavisError.message(MessageError.invalidFormat("Unexpected protocol upgrade rejection"));
+        }
+        this.pendingUpgrade = undefined;
+        upgrade.reject(ClavisError.stream(StreamError.versionMismatch(upgrade.version, value, "peer")));
+        return;
+      }
+
+      case ControlFrameKind.UpgradeConfirm:
+        if (this.acceptedUpgrade !== value) {
+          throw ClavisError.message(MessageError.invalidFormat("Unexpected protocol upgrade confirmation"));
+        }
+        this.acceptedUpgrade = undefined;
+        this.versions!.read = value;
+        return;
+
+      case ControlFrameKind.Ping:
+        // The payload is an id
+        await this.sendControl(ControlFrameKind.Pong, encodeControlU32(value));
+        return;
+
+      case ControlFrameKind.Pong: {
+        const ping = this.pings.get(value);
+        if (!ping) {
+          throw ClavisError.message(MessageError.invalidFormat("Answer to a ping never sent"));
+        }
+        this.pings.delete(value);
+        ping.resolve(this.options.clock.now() - ping.sent);
+        return;
+      }
+
+      case ControlFrameKind.Ack:
+        // The payload is a count
+        if (value < this.acknowledgedCount || value > this.writeSequence) {
+          throw ClavisError.message(MessageError.invalidFormat("Acknowledgment for packets never sent"));
+        }
+        this.acknowledgedCount = value;
+        this.ackWaiters = this.ackWaiters.filter((waiter) => {
+          if (waiter.count > value) return true;
+          waiter.resolve();
+          return false;
+        });
+        return;
+    }
+  }
+}
+
+/**
+ * Encrypted stream for reading and writing encrypted packets
+ */
+export class EncryptedStream {
+  protected adapter: StreamAdapter;
+  private session: FrameSession;
+  private verifiedPeer: PeerIdentity | undefined;
+  private grantedTicket: SessionTicket | undefined;
+  private wasResumed = false;
+  private peerPskIdentity: string | undefined;
+  /** Set by `new()` once the session keys are final */
+  private exporterSecret!: Uint8Array;
+  /** Set by `new()` from the handshake transcript */
+  private sessionIdentifier!: string;
+  private hybridKeys = false;
+  private suite = "xchacha20-poly1305";
+  private valueFormat: PayloadFormat | undefined;
+  private decodeLimits: DecodeLimits | undefined;
+  /** Set by `new()` before the stream is handed out */
+  private timings!: HandshakeTimings;
+
+  protected constructor(
+    options: NormalizedOptions,
+    adapter: StreamAdapter,
+    cipher: FrameCipher,
+    decipher: FrameCipher,
+    readGuard?: PacketRateGuard
+  ) {
+    this.adapter = adapter;
+    this.session = new FrameSession(options.connectionId, adapter, cipher, decipher, options, readGuard);
+  }
+
+  /**
+   * Create a new encrypted stream by performing handshake
+   * 
+   * @param stream - Node.js duplex stream (e.g., TCP socket)
+   * @param options - Configuration options including optional PSK
+   */
+  static async new(
+    stream: Readable & Writable,
+    options?: EncryptedStreamOptions
+  ): Promise<EncryptedStream> {
+    checkStreamOptions(options);
+    tuneSocket(stream, options);
+    const normalizedOpts = normalizeOptions(options, options?.connectionId ?? String(nextConnectionId++));
+    const handshakeTimeoutMs = options?.handshakeTimeoutMs;
+    const readGuard = createRateGuard(options);
+
+    // Create adapter and perform handshake
+    const adapter = createStreamAdapter(stream);
+    return traced(normalizedOpts.tracer, "clavis.handshake", normalizedOpts.connectionId, () => withDeadline(
+      normalizedOpts.clock,
+      handshakeTimeoutMs,
+      EncryptedStream.establish(adapter, normalizedOpts, readGuard, options),
+      () => {
+        adapter.fail(StreamError.handshakeTimeout(handshakeTimeoutMs!));
+        return ClavisError.stream(StreamError.handshakeTimeout(handshakeTimeoutMs!));
+      }
+    ));
+  }
+
+  /** Handshake and setup exchanges of `new()` */
+  private static async establish(
+    adapter: StreamAdapter,
+    normalizedOpts: NormalizedOptions,
+    readGuard: PacketRateGuard | undefined,
+    options: EncryptedStreamOptions | undefined
+  ): Promise<EncryptedStream> {
+    let handshakeResult: HandshakeResult;
+    let handshakeTimings: HandshakeTimings;
+    let resumption: ResumptionExchange | undefined;
+    if (options?.tickets !== undefined || (options?.resumption ?? false) !== false) {
+      const { ResumptionExchange } = await import("./resumption.js");
+      resumption = new ResumptionExchange(
+        typeof options?.resumption === "object" ? options.resumption : undefined,
+        options?.tickets
+      );
+    }
+    try {
+      // With resumption on, the hellos ride in front of the handshake's first messages
+      const timed = await performHandshake(
+        resumption ? resumption.wrap(adapter) : adapter,
+        normalizedOpts.psk,
+        normalizedOpts.clock,
+        normalizedOpts.keyExchange
+      );
+      handshakeResult = timed;
+      handshakeTimings = timed.timings;
+    } catch (error) {
+      // Socket errors surface here as plain Errors; callers only ever see ClavisError
+      if (error instanceof ClavisError) throw error;
+      throw ClavisError.stream(StreamError.handshakeFailed(
+        error instanceof Error ? error.message : String(error),
+        error instanceof Error ? error : undefined
+      ));
+    }
+
+    const setupStart = normalizedOpts.clock.now();
+
+    let cipher: FrameCipher = new XChaCha20Poly1305Cipher(handshakeResult.encKey);
+    let decipher: FrameCipher = new XChaCha20Poly1305Cipher(handshakeResult.decKey);
+    if (options?.dangerousNullCipher) {
+      cipher = new DangerousNullCipher();
+      decipher = new DangerousNullCipher();
+    }
+    const encryptedStream = new EncryptedStream(normalizedOpts, adapter, cipher, decipher, readGuard);
+    if (!options?.dangerousNullCipher) {
+      encryptedStream.session.sendingKey = handshakeResult.encKey;
+      encryptedStream.session.receivingKey = handshakeResult.decKey;
+    }
+    encryptedStream.peerPskIdentity = handshakeResult.pskIdentity;
+    encryptedStream.sessionIdentifier = deriveSessionId(handshakeResult);
+    if (options?.keyLog) {
+      const keyLogger = new KeyLogger(options.keyLog, encryptedStream.sessionIdentifier, handshakeResult.initiator);
+      keyLogger.sending(handshakeResult.encKey);
+      keyLogger.receiving(handshakeResult.decKey);
+      encryptedStream.session.keyLogger = keyLogger;
+    }
+    if (options?.postQuantum) {
+      handshakeResult = await encryptedStream.negotiatePostQuantum(handshakeResult, options.postQuantum);
+    }
+    encryptedStream.exporterSecret = deriveExporterSecret(handshakeResult);
+    if (options?.cipherSuites) {
+      await encryptedStream.negotiateCipherSuite(handshakeResult, options.cipherSuites);
+    }
+    if (options?.negotiatePacketSize) {
+      await encryptedStream.exchangePacketLimits(normalizedOpts.maxPacketSize);
+    }
+    if (options?.sequenceNumbers) {
+      await encryptedStream.enableSequenceNumbers();
+    }
+    if (options?.protocolVersion !== undefined) {
+      await encryptedStream.negotiateVersion(options.protocolVersion);
+    }
+    if (options?.format !== undefined) {
+      await encryptedStream.selectFormat(options.format, handshakeResult.initiator);
+    }
+    encryptedStream.decodeLimits = options?.decodeLimits;
+    // Enabled before the setup exchanges, since the peer may probe as soon as it is done
+    if (options?.timeSync) {
+      await encryptedStream.session.enableTimeSync();
+    }
+
+    let presentation: Uint8Array | undefined;
+    if (options?.identity || options?.trustedSigners || options?.trustedRoots || options?.verifyPeer) {
+      const { encodePresentation } = await import("./identity.js");
+      presentation = encodePresentation(options.identity, handshakeResult.transcriptHash, handshakeResult.initiator);
+    }
+    if (options && resumption) {
+      handshakeResult = await encryptedStream.finishResumption(resumption, handshakeResult, presentation, options);
+      encryptedStream.exporterSecret = deriveExporterSecret(handshakeResult);
+    } else if (options && presentation) {
+      await encryptedStream.exchangeIdentities(handshakeResult, presentation, options, undefined);
+    }
+    // The null cipher has no keys to roll; the chain starts after resumption mixed in any ticket's secret
+    if (!options?.dangerousNullCipher) {
+      await encryptedStream.session.enableRekeys(handshakeResult.resumptionSecret);
+    }
+    if (options?.compression) {
+      await encryptedStream.negotiateCompression(options.compression);
+    }
+    if (options?.padding) {
+      await encryptedStream.negotiatePadding(options.padding);
+    }
+    encryptedStream.installHooks(options);
+    encryptedStream.session.finishSetup(options?.journal);
+
+    const setupMs = normalizedOpts.clock.now() - setupStart;
+    encryptedStream.timings = { ...handshakeTimings, setupMs, totalMs: handshakeTimings.totalMs + setupMs };
+    encryptedStream.session.handshakeMs = encryptedStream.timings.totalMs;
+    options?.onHandshakeTimings?.(encryptedStream.timings);
+    encryptedStream.traceLifetime(normalizedOpts);
+
+    return encryptedStream;
+  }
+
+  /** Count packet variants and run interceptors, as `options` ask */
+  private installHooks(options: EncryptedStreamOptions | undefined): void {
+    if (options?.protocolStats) {
+      const stats = options.protocolStats;
+      const identity = stats.identityOf(this);
+      this.session.countVariant = (direction, packet) => stats.observe(identity, direction, packet);
+    }
+    for (const interceptor of options?.interceptors ?? []) {
+      this.intercept(interceptor);
+    }
+  }
+
+  /** Tell the tracer the stream is ready, and later that it closed */
+  private traceLifetime(options: NormalizedOptions): void {
+    const tracer = options.tracer;
+    if (tracer?.established || tracer?.closed) {
+      tracer.established?.(this);
+      this.adapter.onClose(() => tracer.closed?.(options.connectionId));
+    }
+  }
+
+  /**
+   * Detach this connection from its transport and return its session
+   * state, for `importState()` in another process during a zero-downtime
+   * upgrade. The transport is left open for the new process; this stream
+   * can't be used afterwards. Needs `dangerousSessionHandoff`, and throws,
+   * still attached, while a read, a write, a rekey or another exchange with
+   * the peer is under way.
+   *
+   * The state holds the live session keys: hand it over in the same tick
+   * as the socket, over the same channel, so no data arrives in between
+   * and no one else reads it.
+   *
+   * @example
+   * ```typescript
+   * const state = stream.exportState();
+   * worker.send({ state: Buffer.from(state).toString("base64") }, socket);
+   * ```
+   */
+  exportState(): Uint8Array {
+    const framing = this.session.handOff();
+    return encodeSessionState({
+      ...framing,
+      suite: this.suite,
+      exporterSecret: this.exporterSecret,
+      sessionId: this.sessionIdentifier,
+      pskIdentity: this.peerPskIdentity,
+      postQuantum: this.hybridKeys,
+      resumed: this.wasResumed,
+      peer: this.verifiedPeer,
+      format: this.valueFormat?.name,
+      timings: this.timings,
+    });
+  }
+
+  /**
+   * Carry on a connection another process exported with `exportState()`,
+   * over `stream`, the same transport handed over. Nothing is exchanged
+   * with the peer. Keys, limits and whatever setup agreed come from the
+   * state; `options` configure the rest as for `new()` (timeouts, hooks,
+   * `protocolVersion.accept` for later upgrades) and need
+   * `dangerousSessionHandoff`. The peer identity keeps its name, expiry
+   * and key, but not the certificate it was proven with.
+   *
+   * @example
+   * ```typescript
+   * process.on("message", async ({ state }, socket) => {
+   *   const stream = await EncryptedStream.importState(socket, Buffer.from(state, "base64"), options);
+   * });
+   * ```
+   */
+  static async importState(
+    stream: Readable & Writable,
+    state: Uint8Array,
+    options: EncryptedStreamOptions
+  ): Promise<EncryptedStream> {
+    checkStreamOptions(options);
+    if (!options.dangerousSessionHandoff) {
+      throw ClavisError.config("importState needs the dangerousSessionHandoff option");
+    }
+    const session = decodeSessionState(state);
+    const { findSuite, suiteCipher } = await import("./suites.js");
+    const suite = findSuite(session.suite, options.cipherSuites);
+    tuneSocket(stream, options);
+    const normalizedOpts = normalizeOptions(options, options.connectionId ?? session.connectionId);
+    const adapter = createStreamAdapter(stream, session.unread);
+    const imported = new EncryptedStream(
+      normalizedOpts,
+      adapter,
+      suiteCipher(suite, session.sendingKey),
+      suiteCipher(suite, session.receivingKey),
+      createRateGuard(options)
+    );
+    imported.suite = suite.name;
+    imported.session.cipherFor = (key) => suiteCipher(suite, key);
+    imported.exporterSecret = session.exporterSecret;
+    imported.sessionIdentifier = session.sessionId;
+    imported.peerPskIdentity = session.pskIdentity;
+    imported.hybridKeys = session.postQuantum;
+    imported.wasResumed = session.resumed;
+    imported.verifiedPeer = session.peer;
+    imported.decodeLimits = options.decodeLimits;
+    imported.timings = session.timings;
+    imported.session.handshakeMs = session.timings.totalMs;
+    if (session.format !== undefined) {
+      const { payloadFormat } = await import("./formats.js");
+      imported.valueFormat = payloadFormat(session.format);
+    }
+    if (session.rekeyChain) {
+      await imported.session.enableRekeys(session.rekeyChain, session.rekeys);
+    }
+    if (session.timeSync) {
+      await imported.session.enableTimeSync();
+    }
+    let versions: ProtocolVersions | undefined;
+    if (session.readProtocolVersion !== undefined && session.writeProtocolVersion !== undefined) {
+      const { acceptsVersion, normalizeVersionOptions } = await import("./versioning.js");
+      const option = options.protocolVersion;
+      // Without protocolVersion this side refuses upgrades
+      const accept = option !== undefined ? normalizeVersionOptions(option).accept : undefined;
+      versions = {
+        read: session.readProtocolVersion,
+        write: session.writeProtocolVersion,
+        accepts: (upgrade) => accept !== undefined && acceptsVersion(accept, upgrade),
+        onUpgrade: typeof option === "object" && "onUpgrade" in option ? option.onUpgrade : undefined,
+      };
+    }
+    imported.installHooks(options);
+    imported.session.resume(session, versions);
+    imported.traceLifetime(normalizedOpts);
+    return imported;
+  }
+
+  /**
+   * Exchange post-quantum offers and, when both sides can, run ML-KEM-768
+   * and switch to keys mixed from both exchanges. Resolves with the
+   * handshake result the later setup exchanges should bind to.
+   */
+  private async negotiatePostQuantum(handshake: HandshakeResult, mode: PostQuantumMode): Promise<HandshakeResult> {
+    const { decodeHybridOffer, deriveHybridKeys, encodeHybridOffer, loadMlKem, ML_KEM_768_CIPHERTEXT_LENGTH } =
+      await import("./hybrid.js");
+    const kem = await loadMlKem();
+    if (!kem && mode === "require") {
+      throw ClavisError.config("postQuantum \"require\" needs the @noble/post-quantum package");
+    }
+    // Only the responder's offer carries a key; the initiator encapsulates to it
+    const keyPair = kem && !handshake.initiator ? kem.keygen() : undefined;
+    const [, peerData] = await Promise.all([
+      this.session.writePacket(new RawPacket(encodeHybridOffer(kem ? mode : undefined, keyPair?.publicKey))),
+      this.session.readPacket(),
+    ]);
+    const peer = decodeHybridOffer(peerData);
+
+    if (!kem || !peer.mode) {
+      if (mode === "require" || peer.mode === "require") {
+        throw ClavisError.stream(StreamError.handshakeFailed("Peer can't do the post-quantum key exchange"));
+      }
+      return handshake;
+    }
+
+    let hybrid: HandshakeResult;
+    if (handshake.initiator) {
+      if (!peer.encapsulationKey) {
+        throw ClavisError.message(MessageError.invalidFormat("Responder's post-quantum offer has no key"));
+      }
+      const { cipherText, sharedSecret } = kem.encapsulate(peer.encapsulationKey);
+      await this.session.writePacket(new RawPacket(cipherText));
+      hybrid = deriveHybridKeys(handshake, sharedSecret, peer.encapsulationKey, cipherText);
+    } else {
+      const cipherText = await this.session.readPacket();
+      if (cipherText.length !== ML_KEM_768_CIPHERTEXT_LENGTH) {
+        throw ClavisError.message(MessageError.invalidFormat("Malformed ML-KEM ciphertext"));
+      }
+      const sharedSecret = kem.decapsulate(cipherText, keyPair!.secretKey);
+      hybrid = deriveHybridKeys(handshake, sharedSecret, keyPair!.publicKey, cipherText);
+    }
+    this.session.switchKeys(hybrid.encKey, hybrid.decKey);
+    this.hybridKeys = true;
+    return hybrid;
+  }
+
+  /**
+   * Exchange cipher suite offers and switch both directions to the chosen
+   * suite; later setup exchanges and rekeys use it too
+   */
+  private async negotiateCipherSuite(
+    handshake: HandshakeResult,
+    suites: readonly (CipherSuite | AeadSuite)[]
+  ): Promise<void> {
+    const {
+      XCHACHA20_POLY1305, chooseSuite, decodeSuiteOffer, deriveSuiteKeys, encodeSuiteOffer, resolveSuite, suiteCipher,
+    } = await import("./suites.js");
+    const [, offer] = await Promise.all([
+      this.session.writePacket(new RawPacket(encodeSuiteOffer(suites))),
+      this.session.readPacket(),
+    ]);
+    const suite = resolveSuite(chooseSuite(suites, decodeSuiteOffer(offer, suites), handshake.initiator));
+    this.suite = suite.name;
+    this.session.cipherFor = (key) => suiteCipher(suite, key);
+    if (suite.id !== XCHACHA20_POLY1305.id) {
+      const keys = deriveSuiteKeys(handshake, suite);
+      this.session.switchKeys(keys.encKey, keys.decKey);
+    }
+  }
+
+  /** AEAD sealing this stream's frames: a `CipherSuite`, or the name of a custom `AeadSuite` */
+  get cipherSuite(): string {
+    return this.suite;
+  }
+
+  /** Whether the session keys include an ML-KEM-768 exchange */
+  get postQuantum(): boolean {
+    return this.hybridKeys;
+  }
+
+  /**
+   * Send our certificate (or an empty presentation) unless it already went
+   * out ahead of the resumption grant, and check the peer's. Like
+   * compression offers, both are sent before either side waits.
+   */
+  private async exchangeIdentities(
+    handshake: HandshakeResult,
+    presentation: Uint8Array | undefined,
+    options: EncryptedStreamOptions,
+    restored: PeerIdentity | undefined
+  ): Promise<void> {
+    const { verifyPresentation } = await import("./identity.js");
+    const { isRevoked, verifyPeer } = options;
+    const trust: IdentityTrust = {
+      signers: options.trustedSigners,
+      x509Roots: options.trustedRoots,
+      clockSkewMs: options.clockSkewMs,
+      staticKeys: verifyPeer !== undefined,
+    };
+    const [, peer] = await Promise.all([
+      presentation && this.session.writePacket(new RawPacket(presentation)),
+      this.session.readPacket(),
+    ]);
+    // A resumed peer's identity comes from its ticket; whatever it presents is only checked for form
+    const identity = restored
+      ?? verifyPresentation(peer, trust, handshake.transcriptHash, !handshake.initiator);
+    if (restored) {
+      verifyPresentation(peer, {}, handshake.transcriptHash, !handshake.initiator);
+    }
+    if (identity && isRevoked) {
+      let revoked: boolean;
+      try {
+        revoked = await isRevoked(identity);
+      } catch (error) {
+        throw ClavisError.stream(StreamError.handshakeFailed(
+          `Revocation check failed for ${identity.identity}`,
+          error instanceof Error ? error : undefined
+        ));
+      }
+      if (revoked) {
+        throw ClavisError.crypto(CryptoError.authenticationFailure(`Credential for ${identity.identity} has been revoked`));
+      }
+    }
+    if (identity && verifyPeer) {
+      let accepted: boolean;
+      try {
+        accepted = await verifyPeer(identity);
+      } catch (error) {
+        // A changed host key is the answer itself, not a failure to get one
+        if (error instanceof ClavisError && error.peerKeyChanged) throw error;
+        throw ClavisError.stream(StreamError.handshakeFailed(
+          `Peer verification failed for ${identity.identity}`,
+          error instanceof Error ? error : undefined
+        ));
+      }
+      if (!accepted) {
+        throw ClavisError.crypto(CryptoError.authenticationFailure(`Peer ${identity.identity} was refused`));
+      }
+    }
+    this.verifiedPeer = identity;
+  }
+
+  /**
+   * Settle resumption once the handshake is done. Our grant goes out as soon
+   * as we know who it is sealed for: right away when the peer resumed or
+   * nobody checks identities, otherwise once its presentation is verified.
+   * Resolves with the handshake the later keys derive from, and switches the
+   * session keys if a ticket was accepted.
+   */
+  private async finishResumption(
+    resumption: ResumptionExchange,
+    handshake: HandshakeResult,
+    presentation: Uint8Array | undefined,
+    options: EncryptedStreamOptions
+  ): Promise<HandshakeResult> {
+    const resumed = await resumption.accepted();
+    const early = !options.tickets || !presentation || resumed?.identity !== undefined;
+    if (early) {
+      if (presentation) await this.session.writePacket(new RawPacket(presentation));
+      await this.session.writePacket(new RawPacket(resumption.encodeGrant(handshake, resumed?.identity)));
+    }
+    if (presentation) {
+      await this.exchangeIdentities(handshake, early ? undefined : presentation, options, resumed?.identity);
+    } else {
+      this.verifiedPeer = resumed?.identity;
+    }
+    const [, grant] = await Promise.all([
+      !early && this.session.writePacket(new RawPacket(resumption.encodeGrant(handshake, this.verifiedPeer))),
+      this.session.readPacket(),
+    ]);
+
+    const outcome = resumption.decodeGrant(grant, handshake, this.session);
+    this.grantedTicket = outcome.ticket;
+    this.wasResumed = outcome.resumed;
+    if (outcome.keys) {
+      this.session.switchKeys(outcome.keys.sendingKey, outcome.keys.receivingKey);
+    }
+    return outcome.handshake;
+  }
+
+  /**
+   * Ticket the peer issued for resuming this session later, when
+   * `resumption` is on and the peer has a keyring
+   */
+  get sessionTicket(): SessionTicket | undefined {
+    return this.grantedTicket;
+  }
+
+  /** Whether this connection resumed an earlier session */
+  get resumed(): boolean {
+    return this.wasResumed;
+  }
+
+  /**
+   * Derive `length` bytes (default: 32) that both peers, and only they, get
+   * for the same `label` and `context`, TLS-exporter style. Bind
+   * application-layer tokens to this connection with them, so a token
+   * captured here can't be replayed on another one. The output stays the
+   * same across rekeys.
+   *
+   * @example
+   * ```typescript
+   * // Client: prove the login token was meant for this very channel
+   * const binding = stream.exportKeyingMaterial("myapp login", tokenId);
+   * await stream.writeValue({ token, proof: hmacSha256(binding, token) });
+   * ```
+   */
+  exportKeyingMaterial(label: string, context?: Uint8Array, length = 32): Uint8Array {
+    return exportKeyingMaterial(this.exporterSecret, label, context, length);
+  }
+
+  /**
+   * Identity the peer announced with its pre-shared key, which names the
+   * key it authenticated with; undefined without PSK identities or when
+   * the peer announced none
+   */
+  get pskIdentity(): string | undefined {
+    return this.peerPskIdentity;
+  }
+
+  /**
+   * The peer's identity, checked against `trustedSigners` or `trustedRoots`
+   * or restored from its session ticket; undefined when neither is configured
+   */
+  get peerIdentity(): PeerIdentity | undefined {
+    return this.verifiedPeer;
+  }
+
+  /**
+   * Advertise our receive limit and adopt the peer's as our write limit.
+   * Each direction keeps its own limit, so the peers may differ.
+   */
+  private async exchangePacketLimits(maxPacketSize: number): Promise<void> {
+    const advert = new Uint8Array(8);
+    advert.set(PACKET_LIMIT_MAGIC, 0);
+    new DataView(advert.buffer).setUint32(4, maxPacketSize, true);
+    const [, packet] = await Promise.all([
+      this.session.writePacket(new RawPacket(advert)),
+      this.session.readPacket(),
+    ]);
+    if (!PACKET_LIMIT_MAGIC.every((b, i) => packet[i] === b)) {
+      throw ClavisError.stream(StreamError.handshakeFailed("Peer did not advertise a max packet size"));
+    }
+    const limit = packet.length === 8 ? new DataView(packet.buffer, packet.byteOffset, 8).getUint32(4, true) : 0;
+    if (limit < 1 || limit > MAX_FRAME_LENGTH) {
+      throw ClavisError.stream(StreamError.handshakeFailed("Malformed max packet size advertisement"));
+    }
+    this.session.limits.write = limit;
+    this.session.limits.writeFromPeer = true;
+  }
+
+  /**
+   * Announce `sequenceNumbers` and check the peer did too; both sides
+   * number their frames from the next one on
+   */
+  private async enableSequenceNumbers(): Promise<void> {
+    const [, packet] = await Promise.all([
+      this.session.writePacket(new RawPacket(new Uint8Array(SEQUENCE_MAGIC))),
+      this.session.readPacket(),
+    ]);
+    if (packet.length !== SEQUENCE_MAGIC.length || !SEQUENCE_MAGIC.every((b, i) => packet[i] === b)) {
+      throw ClavisError.stream(StreamError.handshakeFailed("Peer did not enable sequence numbers"));
+    }
+    this.session.sequenceNumbers = true;
+  }
+
+  /**
+   * Sequence number of the last frame this side sent, control frames and
+   * setup exchanges included, or undefined before the first. Counted with
+   * or without `sequenceNumbers`; only with it does the peer check them.
+   */
+  get lastSentSequence(): number | undefined {
+    return this.session.lastSentSequence;
+  }
+
+  /**
+   * Sequence number of the last frame received and opened, counted like
+   * `lastSentSequence`. After a `ReplayError` it is the last frame that
+   * arrived in order, which is where the stream desynchronized.
+   */
+  get lastReceivedSequence(): number | undefined {
+    return this.session.lastReceivedSequence;
+  }
+
+  /**
+   * The peer's receive limit, as advertised during setup or agreed since
+   * with `requestMaxPacketSize()`; undefined without `negotiatePacketSize`.
+   * Writes larger than it fail before they are sent.
+   */
+  get peerMaxPacketSize(): number | undefined {
+    return this.session.limits.writeFromPeer ? this.session.limits.write : undefined;
+  }
+
+  /**
+   * Exchange protocol versions, then verdicts on them. Both are sent before
+   * either side waits; the stream fails unless both peers accepted.
+   */
+  private async negotiateVersion(option: NonNullable<EncryptedStreamOptions["protocolVersion"]>): Promise<void> {
+    const {
+      acceptsVersion,
+      decodeVersionOffer,
+      decodeVersionVerdict,
+      encodeVersionOffer,
+      encodeVersionVerdict,
+      normalizeVersionOptions,
+    } = await import("./versioning.js");
+    const { version, accept } = normalizeVersionOptions(option);
+    const [, offer] = await Promise.all([
+      this.session.writePacket(new RawPacket(encodeVersionOffer(version))),
+      this.session.readPacket(),
+    ]);
+    const peerVersion = decodeVersionOffer(offer);
+    const accepted = acceptsVersion(accept, peerVersion);
+    const [, verdict] = await Promise.all([
+      this.session.writePacket(new RawPacket(encodeVersionVerdict(accepted, version))),
+      this.session.readPacket(),
+    ]);
+    if (!accepted) {
+      throw ClavisError.stream(StreamError.versionMismatch(version, peerVersion, "local"));
+    }
+    if (!decodeVersionVerdict(verdict)) {
+      throw ClavisError.stream(StreamError.versionMismatch(version, peerVersion, "peer"));
+    }
+    this.session.enableProtocolUpgrades({
+      read: peerVersion,
+      write: version,
+      accepts: (upgrade) => acceptsVersion(accept, upgrade),
+      onUpgrade: typeof option === "object" && "onUpgrade" in option ? option.onUpgrade : undefined,
+    });
+  }
+
+  /**
+   * Protocol version the peer writes in: the one it announced, or the one
+   * an upgrade moved it to. Undefined without `protocolVersion`.
+   */
+  get peerProtocolVersion(): number | undefined {
+    return this.session.readProtocolVersion;
+  }
+
+  /**
+   * Protocol version this side writes in: its own `protocolVersion`, or
+   * the one an upgrade moved it to. Undefined without `protocolVersion`.
+   */
+  get protocolVersion(): number | undefined {
+    return this.session.writeProtocolVersion;
+  }
+
+  /**
+   * Move a live connection to protocol version `version` in both
+   * directions, without reconnecting. Packets already in flight are read
+   * in the version they were written in; `peerProtocolVersion` and
+   * `protocolVersion` tell which applies. The peer accepts versions its
+   * `protocolVersion.accept` allows and must be reading. Rejects with a
+   * VersionMismatch error if the peer refuses; both sides then stay where
+   * they were.
+   */
+  upgradeProtocol(version: number): Promise<void> {
+    return this.session.upgradeProtocol(version);
+  }
+
+  /**
+   * Load the fixed payload format, or exchange offers and pick the first
+   * format on the initiator's list that the responder offered
+   */
+  private async selectFormat(option: NonNullable<EncryptedStreamOptions["format"]>, initiator: boolean): Promise<void> {
+    const { chooseFormat, decodeFormatOffer, encodeFormatOffer, payloadFormat } = await import("./formats.js");
+    if (typeof option === "object" && "format" in option) {
+      if (option.format === undefined) {
+        throw ClavisError.config("format needs a codec declared with a `format`");
+      }
+      this.valueFormat = payloadFormat(option.format);
+      return;
+    }
+    if (typeof option === "string") {
+      this.valueFormat = payloadFormat(option);
+      return;
+    }
+    const [, offer] = await Promise.all([
+      this.session.writePacket(new RawPacket(encodeFormatOffer(option))),
+      this.session.readPacket(),
+    ]);
+    this.valueFormat = payloadFormat(chooseFormat(option, decodeFormatOffer(offer), initiator));
+  }
+
+  /** Payload format of `writeValue()` and `readValue()`, or undefined without `format` */
+  get payloadFormat(): WireFormat | undefined {
+    return this.valueFormat?.name;
+  }
+
+  /**
+   * Serialize `value` with the stream's payload format and send it.
+   * Resolves with the bytes written, frame overhead included.
+   */
+  async writeValue(value: unknown): Promise<number> {
+    return this.session.writePacket(new RawPacket(this.requireFormat().encode(value)));
+  }
+
+  /**
+   * Read a packet and decode it with the stream's payload format, within
+   * `decodeLimits` and `decodeBudgetMs`. Bincode isn't self-describing, so
+   * under it this resolves with the raw bytes.
+   */
+  async readValue<T = unknown>(): Promise<T> {
+    const format = this.requireFormat();
+    const packet = await this.session.readPacket();
+    const budget = this.session.decodeBudget();
+    return format.decode(packet, budget ? { ...this.decodeLimits, budget } : this.decodeLimits) as T;
+  }
+
+  private requireFormat(): PayloadFormat {
+    if (!this.valueFormat) {
+      throw ClavisError.invalidOperation("No payload format configured; set the `format` stream option");
+    }
+    return this.valueFormat;
+  }
+
+  /**
+   * Exchange compression offers over the fresh encrypted channel.
+   * Offers are sent before either side waits, so neither can deadlock.
+   */
+  private async negotiateCompression(compression: CompressionOptions): Promise<void> {
+    const {
+      PacketCompressor,
+      decodeCompressionAlgorithms,
+      decodeCompressionOffer,
+      encodeCompressionOffer,
+    } = await import("./compression.js");
+    const [, offer] = await Promise.all([
+      this.session.writePacket(new RawPacket(encodeCompressionOffer(compression))),
+      this.session.readPacket(),
+    ]);
+    this.session.compressor = PacketCompressor.negotiate(
+      compression,
+      decodeCompressionOffer(offer),
+      decodeCompressionAlgorithms(offer)
+    );
+  }
+
+  /**
+   * Exchange padding offers; packets sent after it are padded. The options
+   * are checked before anything is sent.
+   */
+  private async negotiatePadding(padding: PaddingOptions): Promise<void> {
+    const { PacketPadder, decodePaddingOffer, encodePaddingOffer } = await import("./padding.js");
+    const padder = new PacketPadder(padding);
+    const [, offer] = await Promise.all([
+      this.session.writePacket(new RawPacket(encodePaddingOffer())),
+      this.session.readPacket(),
+    ]);
+    decodePaddingOffer(offer);
+    this.session.padder = padder;
+  }
+
+  /** Policy this side pads its packets by, or undefined without padding */
+  get paddingPolicy(): PaddingPolicy | undefined {
+    return this.session.padder?.policy;
+  }
+
+  /** Dictionary id agreed with the peer, or undefined without compression */
+  get compressionDictionary(): string | undefined {
+    return this.session.compressor?.dictionary?.id;
+  }
+
+  /**
+   * Algorithm this side compresses with when the peers share no dictionary,
+   * or undefined when none was agreed
+   */
+  get compressionAlgorithm(): CompressionAlgorithm | undefined {
+    return this.session.compressor?.algorithm;
+  }
+
+  /**
+   * Read an encrypted packet from the stream
+   *
+   * A frame is only taken off the transport by the read that returns its
+   * packet, so a read never leaves the record layer out of step. A read
+   * can't be cancelled, though: a promise you stop waiting on (say, the
+   * loser of a `Promise.race`) still resolves with the packet it read.
+   * Keep it and race it again, rather than starting another read, or that
+   * packet is lost. `tryReadPacket()` polls without leaving a read pending.
+   */
+  async readPacket<P extends PacketTrait>(): Promise<P> {
+    // Deserialize packet (this will be handled by the protocol)
+    // For now, return as unknown - actual deserialization needs protocol definition
+    return (await this.session.readPacket()) as unknown as P;
+  }
+
+  /**
+   * Read the next packet if its whole frame has already arrived, without
+   * waiting for more data. Resolves with undefined when none has, or while
+   * another read is pending; errors, including the end of the stream, are
+   * thrown as `readPacket()` would.
+   *
+   * @example
+   * ```typescript
+   * // In a game loop: handle whatever arrived since the last tick
+   * let packet;
+   * while ((packet = await stream.tryReadPacket()) !== undefined) handle(packet);
+   * ```
+   */
+  async tryReadPacket<P extends PacketTrait>(): Promise<P | undefined> {
+    return (await this.session.tryReadPacket()) as unknown as P | undefined;
+  }
+
+  /**
+   * Read the next packet's bytes, decrypted into a buffer the stream reuses:
+   * the view is only valid until the next `readPacketView()`, so decode it
+   * (or copy what must outlive it) before reading again. Saves an
+   * allocation per packet with XChaCha20-Poly1305; other suites and
+   * compressed packets allocate as `readPacket()` does.
+   */
+  async readPacketView(): Promise<Uint8Array> {
+    return this.session.readPacketView();
+  }
 
-/**
- * Encrypted stream for reading and writing encrypted packets
- */
-export class EncryptedStream {
-  private cipher: XChaCha20Poly1305Cipher;
-  private decipher: XChaCha20Poly1305Cipher;
-  protected adapter: StreamAdapter;
-  private options: NormalizedOptions;
+  /**
+   * Read the next packet and append it to the stream's `journal`. The peer
+   * is acknowledged once the journal has stored it; call
+   * `journal.complete(entry.sequence)` when the packet has been handled.
+   */
+  async readJournaled(): Promise<JournalEntry> {
+    return this.session.readJournaled();
+  }
 
-  protected constructor(
-    handshakeResult: HandshakeResult,
-    options: NormalizedOptions
-  ) {
-    // Adapter will be set by the new() method
-    this.adapter = null as unknown as StreamAdapter; // Temporary, will be set
-    this.cipher = new XChaCha20Poly1305Cipher(handshakeResult.encKey);
-    this.decipher = new XChaCha20Poly1305Cipher(handshakeResult.decKey);
-    this.options = options;
+  /**
+   * Write an encrypted packet to the stream.
+   * Resolves with the number of bytes written, frame overhead included.
+   */
+  async writePacket(packet: PacketTrait): Promise<number> {
+    return this.session.writePacket(packet);
   }
 
   /**
-   * Create a new encrypted stream by performing handshake
-   * 
-   * @param stream - Node.js duplex stream (e.g., TCP socket)
-   * @param options - Configuration options including optional PSK
+   * Read at least one packet, plus any further packets whose frames are
+   * already fully buffered, up to `max`. Decrypting a backlog of small
+   * frames in one call avoids an await per packet.
+   * An error hit after the first packet is thrown by the next read instead.
    */
-  static async new(
-    stream: Readable & Writable,
-    options?: EncryptedStreamOptions
-  ): Promise<EncryptedStream> {
-    // Normalize options
-    const normalizedOpts: NormalizedOptions = {
-      maxPacketSize: options?.maxPacketSize ?? DEFAULT_MAX_PACKET_SIZE,
-      psk: normalizePsk(options?.psk),
-    };
+  async readPackets<P extends PacketTrait>(max: number = DEFAULT_READ_BATCH): Promise<P[]> {
+    return (await this.session.readPackets(max)) as unknown as P[];
+  }
 
-    // Create adapter and perform handshake
-    const adapter = createStreamAdapter(stream);
-    const handshakeResult = await performHandshake(adapter, normalizedOpts.psk);
+  /**
+   * Read ahead: decrypt and decode up to `depth` packets while the caller
+   * is still handling the last one. The prefetcher owns the reads until
+   * `stop()`; see `PrefetchReader`.
+   *
+   * @example
+   * ```typescript
+   * const messages = stream.prefetch({ depth: 8, decode: (bytes) => codec.decode(bytes) });
+   * for await (const message of messages) await handle(message);
+   * ```
+   */
+  prefetch<T = Uint8Array>(options?: PrefetchOptions<T>): PrefetchReader<T> {
+    return new PrefetchReader(this, options);
+  }
 
-    // Create the encrypted stream with the same adapter
-    const encryptedStream = new EncryptedStream(handshakeResult, normalizedOpts);
-    encryptedStream.adapter = adapter; // Use the same adapter
+  /**
+   * Encrypt several packets and send them in a single write.
+   * Nothing is written if any packet exceeds the maximum size.
+   * Resolves with the total number of bytes written.
+   */
+  async writePackets(packets: Iterable<PacketTrait>): Promise<number> {
+    return this.session.writePackets(packets);
+  }
 
-    return encryptedStream;
+  /**
+   * Encrypt a packet without sending it yet. Its frame waits in a buffer
+   * until `flush()`, the next unbuffered write or close, or until the
+   * buffer reaches `maxBufferedBytes`, when this flushes and waits for the
+   * transport. Many small packets then cost one transport write.
+   */
+  async writePacketBuffered(packet: PacketTrait): Promise<void> {
+    return this.session.writePacketBuffered(packet);
   }
 
   /**
-   * Read an encrypted packet from the stream
+   * Buffer every packet of `packets` with `writePacketBuffered()`, without
+   * flushing at the end
    */
-  async readPacket<P extends PacketTrait>(): Promise<P> {
-    // Read length (u32 little-endian)
-    const length = await this.adapter.readU32LE();
-    
-    if (length <= 0 || length > this.options.maxPacketSize) {
-      throw ClavisError.message(
-        MessageError.messageTooLarge(length, this.options.maxPacketSize)
-      );
-    }
+  async feed(packets: Iterable<PacketTrait> | AsyncIterable<PacketTrait>): Promise<void> {
+    return this.session.feed(packets);
+  }
 
-    // Read nonce (24 bytes)
-    const nonce = await this.adapter.read(24);
+  /** Send the buffered packets in one write; resolves with the bytes written */
+  async flush(): Promise<number> {
+    return this.session.flush();
+  }
 
-    // Read ciphertext
-    const ciphertext = await this.adapter.read(length);
+  /** Bytes of encrypted packets waiting for a flush */
+  get bufferedWriteBytes(): number {
+    return this.session.bufferedWriteBytes;
+  }
 
-    // Decrypt
-    const plaintext = this.decipher.decrypt(nonce, ciphertext);
+  /**
+   * Silently drop incoming packets whose variant is not in `types`, before
+   * they reach deserialization. Useful when a connection phase only allows
+   * part of the protocol, e.g. nothing but `Join` before authentication.
+   * Pass undefined to accept everything again.
+   */
+  setAcceptFilter<T extends string>(codec: ProtocolCodec<T>, types: Iterable<T> | undefined): void {
+    this.session.setAcceptFilter(types === undefined ? undefined : variantFilter(codec, types));
+  }
 
-    // Deserialize packet (this will be handled by the protocol)
-    // For now, return as unknown - actual deserialization needs protocol definition
-    return plaintext as unknown as P;
+  /** Number of packets dropped by the accept filter or a read interceptor */
+  get droppedPackets(): number {
+    return this.session.droppedPackets;
   }
 
   /**
-   * Write an encrypted packet to the stream
+   * Run `interceptor` on every packet read and written from now on, after
+   * those already added. Covers the halves from `split()` too.
+   * Returns a function that removes it again.
    */
-  async writePacket(packet: PacketTrait): Promise<void> {
-    // Serialize packet
-    const plaintext = packet.serialize();
+  intercept(interceptor: PacketInterceptor): () => void {
+    const interceptors = this.session.interceptors;
+    const hook: InterceptHook = (direction, packet) => interceptor[direction]?.(packet, this);
+    interceptors.push(hook);
+    return () => {
+      const index = interceptors.indexOf(hook);
+      if (index >= 0) interceptors.splice(index, 1);
+    };
+  }
 
-    if (plaintext.length > this.options.maxPacketSize) {
-      throw ClavisError.message(
-        MessageError.messageTooLarge(plaintext.length, this.options.maxPacketSize)
-      );
+  /**
+   * Iterate over incoming packets, so the reader works with `for await`,
+   * `Readable.from()` and async iterator helpers. Iteration ends when the
+   * peer closes cleanly (a close frame or EOF); any other read error is thrown.
+   */
+  async *[Symbol.asyncIterator](): AsyncGenerator<Uint8Array, void, undefined> {
+    while (true) {
+      let packet: Uint8Array;
+      try {
+        packet = await this.session.readPacket();
+      } catch (error) {
+        if (isEndOfStream(error)) return;
+        throw error;
+      }
+      yield packet;
     }
+  }
+
+  /**
+   * Incoming packets as a web `ReadableStream`, for `pipeTo()` and
+   * `pipeThrough()`. It closes on EOF and errors on any other read error.
+   */
+  toReadableStream(): ReadableStream<Uint8Array> {
+    const iterator = this[Symbol.asyncIterator]();
+    return new ReadableStream<Uint8Array>({
+      async pull(controller) {
+        const next = await iterator.next();
+        if (next.done) {
+          controller.close();
+        } else {
+          controller.enqueue(next.value);
+        }
+      },
+    });
+  }
+
+  /**
+   * Switch the connection to a new max packet size, in both directions.
+   * The peer must agree (see `maxNegotiablePacketSize`) and must be reading.
+   * Rejects if the peer refuses; the old limit then stays in place.
+   */
+  requestMaxPacketSize(size: number): Promise<number> {
+    return this.session.requestMaxPacketSize(size);
+  }
+
+  /**
+   * Resolve once a journaling peer has stored every packet written so far.
+   * Needs someone to be reading from the stream, since acks arrive as
+   * control frames; rejects if reading fails first.
+   */
+  waitForAcknowledgment(): Promise<void> {
+    return this.session.waitForAcknowledgment();
+  }
+
+  /** Packets written so far that a journaling peer has stored */
+  get acknowledgedPackets(): number {
+    return this.session.acknowledged;
+  }
+
+  /**
+   * Probe the peer's wall clock (needs `timeSync` on both peers). Resolves
+   * with this probe's sample; `clockOffset` is updated as well. Needs someone
+   * to be reading from the stream, since the answer arrives as a control frame.
+   */
+  measureClockOffset(): Promise<ClockSample> {
+    return this.session.measureClockOffset();
+  }
+
+  /**
+   * Best estimate of the peer's clock offset and one-way delay from recent
+   * probes; undefined until a probe has been answered
+   */
+  get clockOffset(): ClockOffsetEstimate | undefined {
+    return this.session.timeSync?.estimator.estimate;
+  }
+
+  /**
+   * Bytes and writes queued for the transport, and the rate the queue has
+   * been draining at over the last second. Adaptive senders can lower their
+   * rate when the queue grows instead of letting it pile up.
+   */
+  bandwidthEstimate(): BandwidthEstimate {
+    return this.session.bandwidth.estimate();
+  }
+
+  /**
+   * Time this connection has spent serializing, compressing and encrypting
+   * (or decrypting) packets, in milliseconds. Compare connections to find
+   * the tenant burning the server's cores.
+   */
+  get cpuTime(): CpuTime {
+    return this.session.cpuTime;
+  }
+
+  /**
+   * Counters for this connection: packets and bytes each way, handshake
+   * time, rekeys and frames that failed authentication. Cheap enough to
+   * poll for metrics.
+   */
+  stats(): ConnectionStats {
+    return this.session.stats();
+  }
+
+  /**
+   * How long each stage of setting this connection up took, in
+   * milliseconds. Round trips show up in the stages but not in `cryptoMs`,
+   * which separates network latency from key exchange cost.
+   */
+  get handshakeTimings(): HandshakeTimings {
+    return { ...this.timings };
+  }
+
+  /**
+   * Check the peer is alive with a transport-level ping; resolves with the
+   * round trip in milliseconds. Needs someone to be reading from the stream,
+   * since the answer arrives as a control frame.
+   */
+  ping(): Promise<number> {
+    return this.session.ping();
+  }
+
+  /**
+   * Ping the peer with a deadline of `timeoutMs`. Resolves with the round
+   * trip, or with why the check failed (`timeout`, `closed` or `error`);
+   * it never rejects, so supervisors and pools can branch on the result
+   * without catching. Needs someone to be reading from the stream.
+   *
+   * @example
+   * ```typescript
+   * const health = await stream.healthCheck(1000);
+   * if (!health.healthy && health.failure !== "timeout") socket.destroy();
+   * ```
+   */
+  healthCheck(timeoutMs: number): Promise<HealthCheckResult> {
+    return this.session.healthCheck(timeoutMs);
+  }
+
+  /**
+   * Loosen or tighten soft limits (rate limit, keep-alive, timeouts, decode
+   * budget and rekey thresholds) on the live connection. Nothing is sent to
+   * the peer. Keys left out keep their value and `null` turns a limit off.
+   * The whole patch is checked first: one with a problem throws a
+   * `ConfigError` listing them all and changes nothing.
+   *
+   * @example
+   * ```typescript
+   * // Under load: slow each peer down and drop quiet ones sooner
+   * stream.reconfigure({ maxPacketsPerSecond: 50, idleTimeoutMs: 30_000 });
+   * ```
+   */
+  reconfigure(patch: StreamReconfiguration): void {
+    this.session.reconfigure(patch);
+  }
+
+  /**
+   * Close gracefully: send an authenticated close frame and finish writing.
+   * The peer's reads then fail with a `Closed` stream error (and its async
+   * iteration ends), so it can tell a deliberate shutdown from a dropped
+   * connection. By default reading continues until the peer closes too;
+   * `linger` (default: the `closeLinger` option) can instead destroy the
+   * transport at once or wait for the peer's close. Writing after
+   * `close()` throws.
+   */
+  close(linger?: CloseLinger): Promise<void> {
+    return this.session.close(linger);
+  }
+
+  /**
+   * Drop the connection at once, without a close frame: writes still
+   * waiting on the transport and any waiting read fail with a
+   * `ConnectionClosed` stream error, and the peer sees the connection cut.
+   * For peers that stopped reading, whose writes a `close()` would queue
+   * behind.
+   */
+  destroy(): void {
+    this.session.destroy();
+  }
+
+  /**
+   * Roll the session keys now with a fresh key exchange, without waiting for
+   * `rekeyAfterBytes` or `rekeyAfterMs`. Packets keep flowing meanwhile.
+   * Resolves once both directions use the new keys; needs someone to be
+   * reading from the stream, since the answer arrives as a control frame.
+   */
+  rekey(): Promise<void> {
+    return this.session.rekey();
+  }
 
-    // Encrypt
-    const nonce = XChaCha20Poly1305Cipher.generateNonce();
-    const ciphertext = this.cipher.encrypt(nonce, plaintext);
+  /** Rekeys completed on this connection, whichever side started them */
+  get rekeyCount(): number {
+    return this.session.rekeys;
+  }
 
-    // Write length (u32 little-endian)
-    await this.adapter.writeU32LE(ciphertext.length);
+  /** Id attached to errors from this stream */
+  get connectionId(): string {
+    return this.session.connectionId;
+  }
 
-    // Write nonce
-    await this.adapter.write(nonce);
+  /**
+   * Id of this session that both peers derive from the handshake
+   * transcript, 32 hex digits. Unlike `connectionId` it is the same on
+   * both sides, so their logs for one session can be joined; it reveals
+   * nothing about the keys.
+   */
+  get sessionId(): string {
+    return this.sessionIdentifier;
+  }
 
-    // Write ciphertext
-    await this.adapter.write(ciphertext);
+  /** Largest serialized packet this stream currently sends */
+  get maxPacketSize(): number {
+    return this.session.limits.write;
   }
 
   /**
@@ -316,41 +3767,136 @@ export class EncryptedStream {
     // For Node.js streams, we can't truly split like Rust's ReadHalf/WriteHalf
     // Instead, we'll create reader/writer that share the same underlying stream
     // but enforce read-only/write-only semantics
-    return {
-      reader: new EncryptedReader(this.adapter, this.decipher, this.options),
-      writer: new EncryptedWriter(this.adapter, this.cipher, this.options),
-    };
+    const reader = new EncryptedReader(this.session);
+    const writer = new EncryptedWriter(this.session);
+    splitOwners.set(reader, this);
+    splitOwners.set(writer, this);
+    return { reader, writer };
+  }
+
+  /**
+   * Get back the full-duplex stream a reader and writer were split from,
+   * e.g. to return it to a pool or wrap it again.
+   * Throws InvalidOperation if the halves came from different streams.
+   * The halves keep working and share state with the returned stream, so
+   * stop using them once it's handed on.
+   *
+   * @example
+   * ```typescript
+   * const { reader, writer } = stream.split();
+   * // ...
+   * pool.release(EncryptedStream.unsplit(reader, writer));
+   * ```
+   */
+  static unsplit(reader: EncryptedReader, writer: EncryptedWriter): EncryptedStream {
+    const owner = splitOwners.get(reader);
+    if (!owner || owner !== splitOwners.get(writer)) {
+      throw ClavisError.invalidOperation("Reader and writer were not split from the same stream");
+    }
+    return owner;
   }
 }
 
+/** The stream each split half came from, for `unsplit()` */
+const splitOwners = new WeakMap<EncryptedReader | EncryptedWriter, EncryptedStream>();
+
+/** Whether a read failed because the peer closed cleanly, with a close frame or between frames */
+function isEndOfStream(error: unknown): boolean {
+  const cause = error instanceof ClavisError ? error.cause : error;
+  return cause instanceof StreamError && (cause.code === StreamErrorCode.EOF || cause.code === StreamErrorCode.Closed);
+}
+
 /**
  * Encrypted reader (read-only half of a split stream)
  */
 export class EncryptedReader {
-  constructor(
-    private adapter: StreamAdapter,
-    private decipher: XChaCha20Poly1305Cipher,
-    private options: NormalizedOptions
-  ) {}
+  constructor(private session: FrameSession) {}
 
   /**
    * Read and decrypt the next packet from the stream.
    * Returns the decrypted packet data.
    */
   async readPacket<P extends PacketTrait>(): Promise<P> {
-    const length = await this.adapter.readU32LE();
-    
-    if (length <= 0 || length > this.options.maxPacketSize) {
-      throw ClavisError.message(
-        MessageError.messageTooLarge(length, this.options.maxPacketSize)
-      );
-    }
+    return (await this.session.readPacket()) as unknown as P;
+  }
+
+  /**
+   * Read the next packet if its whole frame has already arrived; see
+   * `EncryptedStream.tryReadPacket()`
+   */
+  async tryReadPacket<P extends PacketTrait>(): Promise<P | undefined> {
+    return (await this.session.tryReadPacket()) as unknown as P | undefined;
+  }
+
+  /**
+   * Read the next packet without allocating for it; see
+   * `EncryptedStream.readPacketView()`
+   */
+  async readPacketView(): Promise<Uint8Array> {
+    return this.session.readPacketView();
+  }
 
-    const nonce = await this.adapter.read(24);
-    const ciphertext = await this.adapter.read(length);
-    const plaintext = this.decipher.decrypt(nonce, ciphertext);
+  /**
+   * Read at least one packet, plus any further packets whose frames are
+   * already fully buffered, up to `max`. Decrypting a backlog of small
+   * frames in one call avoids an await per packet.
+   * An error hit after the first packet is thrown by the next read instead.
+   */
+  async readPackets<P extends PacketTrait>(max: number = DEFAULT_READ_BATCH): Promise<P[]> {
+    return (await this.session.readPackets(max)) as unknown as P[];
+  }
+
+  /** Read ahead into a bounded buffer of decoded values; see `EncryptedStream.prefetch()` */
+  prefetch<T = Uint8Array>(options?: PrefetchOptions<T>): PrefetchReader<T> {
+    return new PrefetchReader(this, options);
+  }
+
+  /**
+   * Read the next packet and append it to the stream's journal; see
+   * `EncryptedStream.readJournaled()`
+   */
+  async readJournaled(): Promise<JournalEntry> {
+    return this.session.readJournaled();
+  }
+
+  /** Sequence number of the last frame received; see `EncryptedStream.lastReceivedSequence` */
+  get lastReceivedSequence(): number | undefined {
+    return this.session.lastReceivedSequence;
+  }
+
+  /** Protocol version the peer writes in; see `EncryptedStream.peerProtocolVersion` */
+  get peerProtocolVersion(): number | undefined {
+    return this.session.readProtocolVersion;
+  }
+
+  /**
+   * Silently drop incoming packets whose variant is not in `types`, before
+   * they reach deserialization. Useful when a connection phase only allows
+   * part of the protocol, e.g. nothing but `Join` before authentication.
+   * Pass undefined to accept everything again.
+   */
+  setAcceptFilter<T extends string>(codec: ProtocolCodec<T>, types: Iterable<T> | undefined): void {
+    this.session.setAcceptFilter(types === undefined ? undefined : variantFilter(codec, types));
+  }
+
+  /** Number of packets dropped by the accept filter or a read interceptor */
+  get droppedPackets(): number {
+    return this.session.droppedPackets;
+  }
+
+  /** Time spent on this connection's hot paths; see `EncryptedStream.cpuTime` */
+  get cpuTime(): CpuTime {
+    return this.session.cpuTime;
+  }
+
+  /** Connection counters, shared with the other half; see `EncryptedStream.stats()` */
+  stats(): ConnectionStats {
+    return this.session.stats();
+  }
 
-    return plaintext as unknown as P;
+  /** Id attached to errors from this reader */
+  get connectionId(): string {
+    return this.session.connectionId;
   }
 }
 
@@ -358,30 +3904,178 @@ export class EncryptedReader {
  * Encrypted writer (write-only half of a split stream)
  */
 export class EncryptedWriter {
-  constructor(
-    private adapter: StreamAdapter,
-    private cipher: XChaCha20Poly1305Cipher,
-    private options: NormalizedOptions
-  ) {}
+  constructor(private session: FrameSession) {}
 
   /**
    * Encrypt and write a packet to the stream.
    * @param packet - Object implementing PacketTrait with a serialize() method
+   * @returns Number of bytes written, frame overhead included
    */
-  async writePacket(packet: PacketTrait): Promise<void> {
-    const plaintext = packet.serialize();
+  async writePacket(packet: PacketTrait): Promise<number> {
+    return this.session.writePacket(packet);
+  }
 
-    if (plaintext.length > this.options.maxPacketSize) {
-      throw ClavisError.message(
-        MessageError.messageTooLarge(plaintext.length, this.options.maxPacketSize)
-      );
-    }
+  /**
+   * Encrypt several packets and send them in a single write.
+   * Nothing is written if any packet exceeds the maximum size.
+   * Resolves with the total number of bytes written.
+   */
+  async writePackets(packets: Iterable<PacketTrait>): Promise<number> {
+    return this.session.writePackets(packets);
+  }
+
+  /**
+   * Encrypt a packet without sending it yet. Its frame waits in a buffer
+   * until `flush()`, the next unbuffered write or close, or until the
+   * buffer reaches `maxBufferedBytes`, when this flushes and waits for the
+   * transport. Many small packets then cost one transport write.
+   */
+  async writePacketBuffered(packet: PacketTrait): Promise<void> {
+    return this.session.writePacketBuffered(packet);
+  }
+
+  /**
+   * Buffer every packet of `packets` with `writePacketBuffered()`, without
+   * flushing at the end
+   */
+  async feed(packets: Iterable<PacketTrait> | AsyncIterable<PacketTrait>): Promise<void> {
+    return this.session.feed(packets);
+  }
+
+  /** Send the buffered packets in one write; resolves with the bytes written */
+  async flush(): Promise<number> {
+    return this.session.flush();
+  }
+
+  /** Bytes of encrypted packets waiting for a flush */
+  get bufferedWriteBytes(): number {
+    return this.session.bufferedWriteBytes;
+  }
+
+  /** Sequence number of the last frame sent; see `EncryptedStream.lastSentSequence` */
+  get lastSentSequence(): number | undefined {
+    return this.session.lastSentSequence;
+  }
+
+  /**
+   * A web `WritableStream` that writes every chunk as a packet, so a
+   * `ReadableStream` of packets can be piped into the connection. Each write
+   * resolves once the packet went out. Closing the stream leaves the
+   * connection open.
+   */
+  toWritableStream(): WritableStream<PacketTrait> {
+    return new WritableStream<PacketTrait>({
+      write: async (packet) => {
+        await this.session.writePacket(packet);
+      },
+    });
+  }
+
+  /**
+   * Switch the connection to a new max packet size, in both directions.
+   * The answer is picked up by the reader half, which must be reading.
+   */
+  requestMaxPacketSize(size: number): Promise<number> {
+    return this.session.requestMaxPacketSize(size);
+  }
+
+  /** Protocol version this half writes in; see `EncryptedStream.protocolVersion` */
+  get protocolVersion(): number | undefined {
+    return this.session.writeProtocolVersion;
+  }
+
+  /**
+   * Move the connection to protocol version `version` in both directions.
+   * The answer is picked up by the reader half, which must be reading.
+   */
+  upgradeProtocol(version: number): Promise<void> {
+    return this.session.upgradeProtocol(version);
+  }
+
+  /**
+   * Resolve once a journaling peer has stored every packet written so far.
+   * The acks are picked up by the reader half, which must be reading.
+   */
+  waitForAcknowledgment(): Promise<void> {
+    return this.session.waitForAcknowledgment();
+  }
+
+  /** Packets written so far that a journaling peer has stored */
+  get acknowledgedPackets(): number {
+    return this.session.acknowledged;
+  }
+
+  /**
+   * Probe the peer's wall clock (needs `timeSync` on both peers).
+   * The answer is picked up by the reader half, which must be reading.
+   */
+  measureClockOffset(): Promise<ClockSample> {
+    return this.session.measureClockOffset();
+  }
+
+  /** Best estimate of the peer's clock offset; see `EncryptedStream.clockOffset` */
+  get clockOffset(): ClockOffsetEstimate | undefined {
+    return this.session.timeSync?.estimator.estimate;
+  }
+
+  /** Time spent on this connection's hot paths; see `EncryptedStream.cpuTime` */
+  get cpuTime(): CpuTime {
+    return this.session.cpuTime;
+  }
+
+  /** Connection counters, shared with the other half; see `EncryptedStream.stats()` */
+  stats(): ConnectionStats {
+    return this.session.stats();
+  }
+
+  /** Send queue depth and drain rate; see `EncryptedStream.bandwidthEstimate()` */
+  bandwidthEstimate(): BandwidthEstimate {
+    return this.session.bandwidth.estimate();
+  }
+
+  /**
+   * Ping the peer; see `EncryptedStream.ping()`.
+   * The answer is picked up by the reader half, which must be reading.
+   */
+  ping(): Promise<number> {
+    return this.session.ping();
+  }
+
+  /**
+   * Ping the peer with a deadline; see `EncryptedStream.healthCheck()`.
+   * The answer is picked up by the reader half, which must be reading.
+   */
+  healthCheck(timeoutMs: number): Promise<HealthCheckResult> {
+    return this.session.healthCheck(timeoutMs);
+  }
+
+  /**
+   * Send a close frame and finish writing; see `EncryptedStream.close()`
+   */
+  close(linger?: CloseLinger): Promise<void> {
+    return this.session.close(linger);
+  }
+
+  /**
+   * Roll the session keys now; see `EncryptedStream.rekey()`.
+   * The answer is picked up by the reader half, which must be reading.
+   */
+  rekey(): Promise<void> {
+    return this.session.rekey();
+  }
+
+  /** Rekeys completed on this connection */
+  get rekeyCount(): number {
+    return this.session.rekeys;
+  }
 
-    const nonce = XChaCha20Poly1305Cipher.generateNonce();
-    const ciphertext = this.cipher.encrypt(nonce, plaintext);
+  /** Id attached to errors from this writer */
+  get connectionId(): string {
+    return this.session.connectionId;
+  }
 
-    await this.adapter.writeU32LE(ciphertext.length);
-    await this.adapter.write(nonce);
-    await this.adapter.write(ciphertext);
+  /** Largest serialized packet this writer currently sends */
+  get maxPacketSize(): number {
+    return this.session.limits.write;
   }
 }
@@ -0,0 +1,269 @@
+/**
+ * Cipher suites
+ * The AEAD that seals a stream's frames, negotiated after the handshake
+ *
+ * Streams use XChaCha20-Poly1305 unless both peers set `cipherSuites`.
+ * They then exchange offers under the handshake keys and switch to the
+ * suite both lists rank best together (the lowest sum of positions), ties
+ * going to the initiator's order. AES-256-GCM keys are derived from the
+ * handshake keys with HKDF, so no key is ever used with two algorithms.
+ *
+ * AES-256-GCM runs on the runtime's native crypto, so it uses AES-NI where
+ * the CPU has it; XChaCha20-Poly1305 is faster on CPUs without. GCM takes
+ * the frame's whole 24-byte random nonce as its IV. Random IVs bound a key
+ * to about 2^32 frames, so long-lived AES connections should set
+ * `rekeyAfterBytes` or `rekeyAfterMs`.
+ *
+ * Both are `AeadSuite`s, and other AEADs plug into the negotiation the same
+ * way: list an `AeadSuite` in `cipherSuites` on both peers.
+ */
+
+import { createCipheriv, createDecipheriv } from "crypto";
+import { sha256 } from "@noble/hashes/sha2.js";
+import { hkdf } from "@noble/hashes/hkdf.js";
+import { hkdfExpand, XChaCha20Poly1305Cipher, type FrameCipher } from "./crypto.js";
+import { ClavisError, CryptoError, CryptoOperation, StreamError } from "./error.js";
+import { redact } from "./audit.js";
+import type { HandshakeResult } from "./handshake.js";
+
+/**
+ * AEAD for the record layer
+ */
+export type CipherSuite = "xchacha20-poly1305" | "aes-256-gcm";
+
+/** Every suite this implementation knows, in its default order of preference */
+export const CIPHER_SUITES: readonly CipherSuite[] = ["xchacha20-poly1305", "aes-256-gcm"];
+
+/**
+ * An AEAD the record layer can seal frames with
+ *
+ * Frames carry a 24-byte nonce and end in a 16-byte tag, so the cipher must
+ * take the 24-byte nonce (an AEAD with shorter nonces uses its first bytes)
+ * and append a 16-byte tag. Throw a `ClavisError` crypto failure when a
+ * frame fails to open.
+ */
+export interface AeadSuite {
+  /** Name `cipherSuite` reports; distinct from the built-in names */
+  readonly name: string;
+  /** Number naming the suite in offers, from 128 to 255 for custom suites; both peers must agree on it */
+  readonly id: number;
+  /** Key length in bytes, from 16 to 64; keys of other lengths than 32 are expanded from the traffic keys with HKDF */
+  readonly keyLength: number;
+  /**
+   * Nonces the AEAD is safe with. "random" takes the frame's 24 random
+   * bytes. "sequence" is for short nonces that must never repeat: it needs
+   * `sequenceNumbers`, which puts the frame counter in the first 8 bytes.
+   * The few setup frames sent before the counters start still use random
+   * nonces.
+   */
+  readonly nonces: "random" | "sequence";
+  /** A cipher sealing and opening frames under `key` */
+  cipher(key: Uint8Array): FrameCipher;
+}
+
+const OFFER_MAGIC = [0x43, 0x4c, 0x56, 0x43]; // "CLVC"
+const NONCE_LENGTH = 24;
+const TAG_LENGTH = 16;
+
+/**
+ * AES-256-GCM cipher instance, over the runtime's native implementation
+ */
+export class Aes256GcmCipher implements FrameCipher {
+  private key: Uint8Array;
+
+  constructor(key: Uint8Array) {
+    if (key.length !== 32) {
+      throw ClavisError.crypto(
+        CryptoError.invalidKeyMaterial("Key must be 32 bytes for AES-256-GCM")
+      );
+    }
+    this.key = redact(key, "key");
+  }
+
+  /**
+   * Encrypt plaintext with a 24-byte nonce, optionally binding associated data
+   */
+  encrypt(nonce: Uint8Array, plaintext: Uint8Array, aad?: Uint8Array): Uint8Array {
+    if (nonce.length !== NONCE_LENGTH) {
+      throw ClavisError.cryptoFailure(CryptoOperation.Encryption, "Nonce must be 24 bytes");
+    }
+    try {
+      const cipher = createCipheriv("aes-256-gcm", this.key, nonce, { authTagLength: TAG_LENGTH });
+      if (aad) cipher.setAAD(aad);
+      const head = cipher.update(plaintext);
+      const tail = cipher.final();
+      const out = new Uint8Array(head.length + tail.length + TAG_LENGTH);
+      out.set(head, 0);
+      out.set(tail, head.length);
+      out.set(cipher.getAuthTag(), head.length + tail.length);
+      return out;
+    } catch (error: unknown) {
+      const message = error instanceof Error ? error.message : String(error);
+      throw ClavisError.cryptoFailure(CryptoOperation.Encryption, `Encryption failed: ${message}`);
+    }
+  }
+
+  /**
+   * Decrypt ciphertext with a nonce and the associated data it was sealed with
+   */
+  decrypt(nonce: Uint8Array, ciphertext: Uint8Array, aad?: Uint8Array): Uint8Array {
+    if (nonce.length !== NONCE_LENGTH) {
+      throw ClavisError.cryptoFailure(CryptoOperation.Decryption, "Nonce must be 24 bytes");
+    }
+    if (ciphertext.length < TAG_LENGTH) {
+      throw ClavisError.cryptoFailure(CryptoOperation.Decryption, "Decryption failed: ciphertext shorter than its tag");
+    }
+    try {
+      const decipher = createDecipheriv("aes-256-gcm", this.key, nonce, { authTagLength: TAG_LENGTH });
+      decipher.setAuthTag(ciphertext.subarray(ciphertext.length - TAG_LENGTH));
+      if (aad) decipher.setAAD(aad);
+      const head = decipher.update(ciphertext.subarray(0, ciphertext.length - TAG_LENGTH));
+      const tail = decipher.final();
+      const out = new Uint8Array(head.length + tail.length);
+      out.set(head, 0);
+      out.set(tail, head.length);
+      return redact(out, "payload");
+    } catch (error: unknown) {
+      const message = error instanceof Error ? error.message : String(error);
+      throw ClavisError.cryptoFailure(CryptoOperation.Decryption, `Decryption failed: ${message}`);
+    }
+  }
+}
+
+/** XChaCha20-Poly1305, the default suite */
+export const XCHACHA20_POLY1305: AeadSuite = {
+  name: "xchacha20-poly1305",
+  id: 1,
+  keyLength: 32,
+  nonces: "random",
+  cipher: (key) => new XChaCha20Poly1305Cipher(key),
+};
+
+/** AES-256-GCM over the runtime's native crypto */
+export const AES_256_GCM: AeadSuite = {
+  name: "aes-256-gcm",
+  id: 2,
+  keyLength: 32,
+  nonces: "random",
+  cipher: (key) => new Aes256GcmCipher(key),
+};
+
+const BUILT_IN_SUITES: Record<CipherSuite, AeadSuite> = {
+  "xchacha20-poly1305": XCHACHA20_POLY1305,
+  "aes-256-gcm": AES_256_GCM,
+};
+
+/** The suite a `cipherSuites` entry names */
+export function resolveSuite(suite: CipherSuite | AeadSuite): AeadSuite {
+  if (typeof suite !== "string") return suite;
+  const builtIn = BUILT_IN_SUITES[suite];
+  if (!builtIn) throw ClavisError.config(`Unknown cipher suite ${suite}`);
+  return builtIn;
+}
+
+/** Name of a `cipherSuites` entry */
+export function suiteName(suite: CipherSuite | AeadSuite): string {
+  return typeof suite === "string" ? suite : suite.name;
+}
+
+/**
+ * The suite named `name`: one of `listed`, or a built-in suite. Throws a
+ * configuration error for a custom suite that isn't listed.
+ */
+export function findSuite(name: string, listed: readonly (CipherSuite | AeadSuite)[] = []): AeadSuite {
+  const suite = listed.find((entry) => suiteName(entry) === name) ?? BUILT_IN_SUITES[name as CipherSuite];
+  if (!suite) throw ClavisError.config(`Unknown cipher suite ${name}; list it in cipherSuites`);
+  return resolveSuite(suite);
+}
+
+/**
+ * A frame cipher of `suite` under the 32-byte traffic key `key`
+ */
+export function suiteCipher(suite: CipherSuite | AeadSuite, key: Uint8Array): FrameCipher {
+  const aead = resolveSuite(suite);
+  if (aead.keyLength === key.length) return aead.cipher(key);
+  const info = new TextEncoder().encode(`clavis-suite-key ${aead.name}`);
+  return aead.cipher(redact(hkdf(sha256, key, undefined, info, aead.keyLength), "key"));
+}
+
+/**
+ * Keys of `suite` for a session set up with `handshake`. XChaCha20-Poly1305
+ * keeps the handshake keys; other suites get their own.
+ */
+export function deriveSuiteKeys(
+  handshake: HandshakeResult,
+  suite: CipherSuite | AeadSuite
+): { encKey: Uint8Array; decKey: Uint8Array } {
+  const aead = resolveSuite(suite);
+  if (aead.id === XCHACHA20_POLY1305.id) {
+    return { encKey: handshake.encKey, decKey: handshake.decKey };
+  }
+  const info = `clavis-suite ${aead.name}`;
+  return {
+    encKey: hkdfExpand(handshake.encKey, handshake.transcriptHash, info),
+    decKey: hkdfExpand(handshake.decKey, handshake.transcriptHash, info),
+  };
+}
+
+/**
+ * This side's suite offer, most preferred first
+ */
+export function encodeSuiteOffer(suites: readonly (CipherSuite | AeadSuite)[]): Uint8Array {
+  if (suites.length === 0 || suites.length > 255) {
+    throw ClavisError.config("Offer between 1 and 255 cipher suites");
+  }
+  return new Uint8Array([...OFFER_MAGIC, suites.length, ...suites.map((suite) => resolveSuite(suite).id)]);
+}
+
+/**
+ * Decode the peer's suite offer, skipping suites this side doesn't know.
+ * Built-in suites come back by name, custom ones as the `AeadSuite` from
+ * `listed` with the same id.
+ */
+export function decodeSuiteOffer(
+  offer: Uint8Array,
+  listed: readonly (CipherSuite | AeadSuite)[] = []
+): (CipherSuite | AeadSuite)[] {
+  const magic = OFFER_MAGIC.every((b, i) => offer[i] === b);
+  if (!magic) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Peer did not negotiate a cipher suite"));
+  }
+  const count = offer[4];
+  if (count === undefined || offer.length !== 5 + count) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Malformed cipher suite offer"));
+  }
+  const byId = new Map<number, CipherSuite | AeadSuite>();
+  for (const suite of listed) {
+    if (typeof suite !== "string") byId.set(suite.id, suite);
+  }
+  for (const name of CIPHER_SUITES) byId.set(BUILT_IN_SUITES[name].id, name);
+  return [...offer.subarray(5)].flatMap((id) => byId.get(id) ?? []);
+}
+
+/**
+ * Pick the suite both lists rank best together, ties going to the
+ * initiator's order. Both peers compute the same answer.
+ */
+export function chooseSuite<S extends CipherSuite | AeadSuite>(
+  ours: readonly S[],
+  theirs: readonly S[],
+  initiator: boolean
+): S {
+  const [first, second] = initiator ? [ours, theirs] : [theirs, ours];
+  const secondIds = second.map((suite) => resolveSuite(suite).id);
+  let chosen: S | undefined;
+  let best = Infinity;
+  first.forEach((suite, rank) => {
+    const other = secondIds.indexOf(resolveSuite(suite).id);
+    if (other !== -1 && rank + other < best) {
+      chosen = suite;
+      best = rank + other;
+    }
+  });
+  if (chosen === undefined) {
+    throw ClavisError.stream(StreamError.handshakeFailed(
+      `No cipher suite in common: this side offers ${ours.map(suiteName).join(", ")}, the peer ${theirs.map(suiteName).join(", ") || "none this side knows"}`
+    ));
+  }
+  return chosen;
+}
@@ -0,0 +1,691 @@
+/**
+ * Testing utilities
+ * In-memory transports with latency, jitter and bandwidth shaping for
+ * exercising clavis under slow or lossy-looking links, a plain in-memory
+ * pair that can corrupt frames and split reads, and a battery of read-path
+ * denial-of-service attacks to run against a server's settings
+ */
+
+import { Duplex } from "stream";
+import { ManualClock, systemClock, type Clock, type TimerHandle } from "./clock.js";
+import { ClavisError } from "./error.js";
+import { RawPacket } from "./protocol.js";
+import { generateRandomBytes } from "./crypto.js";
+import { HANDSHAKE_MESSAGE_LENGTHS } from "./handshake-messages.js";
+import { FRAME_HEADER_LENGTH, FRAME_NONCE_LENGTH, decodeFrameHeader } from "./frame.js";
+import type { CorruptionKind } from "./corruption.js";
+import { EncryptedStream, type EncryptedStreamOptions } from "./stream.js";
+
+/**
+ * Distribution of extra per-chunk latency on top of the base latency
+ */
+export type JitterDistribution =
+  | { kind: "none" }
+  /** Uniformly distributed in [0, maxMs] */
+  | { kind: "uniform"; maxMs: number }
+  /** Normally distributed around 0 with `stddevMs`, clamped at 0 */
+  | { kind: "normal"; stddevMs: number };
+
+/**
+ * Shape of one direction of a link
+ */
+export interface LinkProfile {
+  /** One-way base latency in milliseconds (default: 0) */
+  latencyMs?: number | undefined;
+  /** Extra latency per chunk (default: none) */
+  jitter?: JitterDistribution | undefined;
+  /** Token bucket refill rate in bytes per second (default: unlimited) */
+  bandwidthBytesPerSec?: number | undefined;
+  /** Token bucket size in bytes (default: 16384) */
+  burstBytes?: number | undefined;
+  /** PRNG seed, so jitter is reproducible across runs (default: 1) */
+  seed?: number | undefined;
+}
+
+/**
+ * Ready-made link profiles
+ */
+export const LinkProfiles = {
+  /** Local network: sub-millisecond latency, no shaping */
+  lan: { latencyMs: 0.2 },
+  /** Mobile 3G: ~100ms latency with jitter, ~750 kbit/s */
+  threeG: {
+    latencyMs: 100,
+    jitter: { kind: "normal", stddevMs: 20 },
+    bandwidthBytesPerSec: 96_000,
+    burstBytes: 8_192,
+  },
+  /** Geostationary satellite: ~600ms latency, ~10 Mbit/s */
+  satellite: {
+    latencyMs: 600,
+    jitter: { kind: "uniform", maxMs: 50 },
+    bandwidthBytesPerSec: 1_250_000,
+    burstBytes: 65_536,
+  },
+} satisfies Record<string, LinkProfile>;
+
+/**
+ * Small deterministic PRNG (mulberry32)
+ */
+export function seededRandom(seed: number): () => number {
+  let state = seed >>> 0;
+  return () => {
+    state = (state + 0x6d2b79f5) >>> 0;
+    let t = state;
+    t = Math.imul(t ^ (t >>> 15), t | 1);
+    t ^= t + Math.imul(t ^ (t >>> 7), t | 61);
+    return ((t ^ (t >>> 14)) >>> 0) / 4294967296;
+  };
+}
+
+/**
+ * Computes when each chunk leaves the sender and reaches the receiver.
+ * Pure timing logic, independent of any real transport.
+ */
+export class LinkShaper {
+  private readonly latencyMs: number;
+  private readonly jitter: JitterDistribution;
+  private readonly rate: number | undefined;
+  private readonly burst: number;
+  private readonly random: () => number;
+  private tokens: number;
+  private cursor = 0;
+  private lastArrival = 0;
+
+  constructor(profile: LinkProfile = {}) {
+    this.latencyMs = profile.latencyMs ?? 0;
+    this.jitter = profile.jitter ?? { kind: "none" };
+    this.rate = profile.bandwidthBytesPerSec;
+    this.burst = profile.burstBytes ?? 16_384;
+    this.random = seededRandom(profile.seed ?? 1);
+    this.tokens = this.burst;
+  }
+
+  /**
+   * Schedule `bytes` written at time `now` (ms).
+   * Chunks never overtake each other, like on a TCP connection.
+   */
+  schedule(now: number, bytes: number): { departAt: number; arriveAt: number } {
+    const departAt = this.depart(now, bytes);
+    const arriveAt = Math.max(departAt + this.latencyMs + this.sampleJitter(), this.lastArrival);
+    this.lastArrival = arriveAt;
+    return { departAt, arriveAt };
+  }
+
+  private depart(now: number, bytes: number): number {
+    if (this.rate === undefined) return now;
+
+    const start = Math.max(now, this.cursor);
+    this.tokens = Math.min(this.burst, this.tokens + ((start - this.cursor) * this.rate) / 1000);
+    this.cursor = start;
+
+    if (this.tokens >= bytes) {
+      this.tokens -= bytes;
+      return start;
+    }
+    // Wait for the deficit to refill; the bucket is empty at departure
+    const departAt = start + ((bytes - this.tokens) * 1000) / this.rate;
+    this.tokens = 0;
+    this.cursor = departAt;
+    return departAt;
+  }
+
+  private sampleJitter(): number {
+    switch (this.jitter.kind) {
+      case "none":
+        return 0;
+      case "uniform":
+        return this.random() * this.jitter.maxMs;
+      case "normal": {
+        // Box-Muller transform
+        const u = Math.max(this.random(), Number.EPSILON);
+        const v = this.random();
+        const z = Math.sqrt(-2 * Math.log(u)) * Math.cos(2 * Math.PI * v);
+        return Math.max(0, z * this.jitter.stddevMs);
+      }
+    }
+  }
+}
+
+/**
+ * Create a connected in-memory duplex pair whose links are shaped by the
+ * given profiles. `aToB` shapes data written to the first stream.
+ * Pass a `ManualClock` to deliver data without waiting in real time.
+ *
+ * @example
+ * ```typescript
+ * const [client, server] = createChaosPair(LinkProfiles.satellite);
+ * const [a, b] = await Promise.all([
+ *   EncryptedStream.new(client),
+ *   EncryptedStream.new(server),
+ * ]);
+ * ```
+ */
+export function createChaosPair(
+  aToB: LinkProfile = {},
+  bToA: LinkProfile = aToB,
+  clock: Clock = systemClock
+): [Duplex, Duplex] {
+  let a: Duplex;
+  let b: Duplex;
+
+  const link = (profile: LinkProfile, target: () => Duplex) => {
+    const shaper = new LinkShaper(profile);
+    const started = clock.now();
+    let lastArrival = 0;
+
+    return {
+      write(chunk: Buffer, callback: (error?: Error | null) => void): void {
+        const now = clock.now() - started;
+        const { departAt, arriveAt } = shaper.schedule(now, chunk.length);
+        lastArrival = arriveAt;
+        clock.setTimer(() => target().push(chunk), arriveAt - now);
+        // Release the writer once the chunk has left, so bandwidth limits apply backpressure
+        clock.setTimer(() => callback(), departAt - now);
+      },
+      final(callback: (error?: Error | null) => void): void {
+        const now = clock.now() - started;
+        clock.setTimer(() => target().push(null), Math.max(0, lastArrival - now));
+        callback();
+      },
+    };
+  };
+
+  const aLink = link(aToB, () => b);
+  const bLink = link(bToA, () => a);
+
+  a = new Duplex({
+    read() {},
+    write: (chunk: Buffer, _encoding, callback) => aLink.write(chunk, callback),
+    final: (callback) => aLink.final(callback),
+  });
+  b = new Duplex({
+    read() {},
+    write: (chunk: Buffer, _encoding, callback) => bLink.write(chunk, callback),
+    final: (callback) => bLink.final(callback),
+  });
+
+  return [a, b];
+}
+
+/**
+ * Options for `createMemoryPair`
+ */
+export interface MemoryPairOptions {
+  /**
+   * Deliver what each side writes in reads of at most this many bytes, each
+   * on its own turn of the event loop, so frames arrive in pieces
+   * (default: as written, synchronously)
+   */
+  maxReadBytes?: number | undefined;
+}
+
+/**
+ * One end of an in-memory connection from `createMemoryPair`
+ *
+ * Besides carrying bytes, it can tamper with the frames it writes. Arm it
+ * once the stream on top has finished setup, when everything written is a
+ * sequence of frames.
+ */
+export class MemoryDuplex extends Duplex {
+  /** Largest read the peer gets; see `MemoryPairOptions` */
+  maxReadBytes: number | undefined;
+  private peer: MemoryDuplex | undefined;
+  private readonly corruptions: CorruptionKind[] = [];
+  /** Outbound bytes of a frame that isn't complete yet, held while corruptions are armed */
+  private held = new Uint8Array(0);
+  private readonly queue: Array<Uint8Array | null> = [];
+  private flushing = false;
+  private cut = false;
+
+  constructor(maxReadBytes?: number) {
+    super();
+    if (maxReadBytes !== undefined && !(Number.isInteger(maxReadBytes) && maxReadBytes > 0)) {
+      throw ClavisError.config(`maxReadBytes must be a positive integer, got ${maxReadBytes}`);
+    }
+    this.maxReadBytes = maxReadBytes;
+  }
+
+  /** Connect two ends; `createMemoryPair` does this */
+  static link(a: MemoryDuplex, b: MemoryDuplex): void {
+    a.peer = b;
+    b.peer = a;
+  }
+
+  /**
+   * Corrupt the next frame this side writes:
+   * - macFailure: flip a bit of the Poly1305 tag
+   * - framing: rewrite the length prefix to one no reader accepts
+   * - truncated: deliver all but the frame's last byte, then end the connection
+   *
+   * Calls queue up, one frame each.
+   */
+  corruptNextFrame(kind: CorruptionKind = "macFailure"): void {
+    this.corruptions.push(kind);
+  }
+
+  /** Deliver raw bytes to the peer as if this side had written them */
+  inject(bytes: Uint8Array): void {
+    this.deliver(bytes);
+  }
+
+  override _read(): void {}
+
+  override _write(chunk: Buffer, _encoding: BufferEncoding, callback: (error?: Error | null) => void): void {
+    if (this.corruptions.length === 0 && this.held.length === 0) {
+      this.deliver(chunk);
+    } else {
+      this.tamper(chunk);
+    }
+    callback();
+  }
+
+  override _final(callback: (error?: Error | null) => void): void {
+    this.deliver(null);
+    callback();
+  }
+
+  /** Split held and new bytes into frames, corrupting one per armed corruption */
+  private tamper(chunk: Uint8Array): void {
+    const bytes = new Uint8Array(this.held.length + chunk.length);
+    bytes.set(this.held);
+    bytes.set(chunk, this.held.length);
+    let offset = 0;
+    while (this.corruptions.length > 0 && bytes.length - offset >= FRAME_HEADER_LENGTH) {
+      const { length } = decodeFrameHeader(bytes.subarray(offset));
+      const end = offset + FRAME_HEADER_LENGTH + FRAME_NONCE_LENGTH + length;
+      if (end > bytes.length) break;
+      const frame = bytes.slice(offset, end);
+      offset = end;
+      switch (this.corruptions.shift()!) {
+        case "macFailure":
+          frame[frame.length - 1] = frame[frame.length - 1]! ^ 0x01;
+          this.deliver(frame);
+          break;
+        case "framing":
+          // The largest length that isn't a control frame, beyond any maxPacketSize
+          frame.set([0xff, 0xff, 0xff, 0x7f]);
+          this.deliver(frame);
+          break;
+        case "truncated":
+          this.deliver(frame.subarray(0, frame.length - 1));
+          this.deliver(null);
+          this.cut = true;
+          break;
+      }
+    }
+    if (this.corruptions.length > 0) {
+      this.held = bytes.slice(offset);
+    } else {
+      this.held = new Uint8Array(0);
+      if (offset < bytes.length) this.deliver(bytes.subarray(offset));
+    }
+  }
+
+  private deliver(bytes: Uint8Array | null): void {
+    if (this.cut) return;
+    const max = this.maxReadBytes;
+    if (max === undefined || bytes === null) {
+      this.queue.push(bytes);
+    } else {
+      for (let offset = 0; offset < bytes.length; offset += max) {
+        this.queue.push(bytes.subarray(offset, offset + max));
+      }
+    }
+    if (max === undefined && !this.flushing) {
+      while (this.queue.length > 0) this.peer?.push(this.queue.shift());
+    } else if (!this.flushing) {
+      this.flushing = true;
+      setImmediate(() => this.flush());
+    }
+  }
+
+  /** Hand queued pieces to the peer one per turn of the event loop */
+  private flush(): void {
+    if (this.queue.length > 0) this.peer?.push(this.queue.shift());
+    if (this.queue.length > 0) {
+      setImmediate(() => this.flush());
+    } else {
+      this.flushing = false;
+    }
+  }
+}
+
+/**
+ * Create a connected in-memory duplex pair. Unlike `createChaosPair` it
+ * delivers data synchronously, and each end can corrupt frames it writes
+ * or split them into partial reads.
+ *
+ * @example
+ * ```typescript
+ * const [a, b] = createMemoryPair({ maxReadBytes: 1 });
+ * const [client, server] = await Promise.all([EncryptedStream.new(a), EncryptedStream.new(b)]);
+ * a.corruptNextFrame("macFailure");
+ * await client.writePacket(packet);
+ * await server.readPacket(); // throws, like a MAC failure on the wire
+ * ```
+ */
+export function createMemoryPair(options: MemoryPairOptions = {}): [MemoryDuplex, MemoryDuplex] {
+  const a = new MemoryDuplex(options.maxReadBytes);
+  const b = new MemoryDuplex(options.maxReadBytes);
+  MemoryDuplex.link(a, b);
+  return [a, b];
+}
+
+/**
+ * Two streams set up with each other over `createMemoryPair`
+ */
+export interface EncryptedPair {
+  a: EncryptedStream;
+  b: EncryptedStream;
+  /** Transport under `a`; frames corrupted here reach `b` */
+  aSocket: MemoryDuplex;
+  /** Transport under `b`; frames corrupted here reach `a` */
+  bSocket: MemoryDuplex;
+}
+
+/**
+ * Set up two streams with each other in memory, with no listener or port
+ *
+ * @example
+ * ```typescript
+ * const { a, b, aSocket } = await createEncryptedPair({ psk }, { psk });
+ * await a.writePacket(packet);
+ * expect(await b.readPacket()).toEqual(packet);
+ * ```
+ */
+export async function createEncryptedPair(
+  optionsA?: EncryptedStreamOptions,
+  optionsB?: EncryptedStreamOptions,
+  memory: MemoryPairOptions = {}
+): Promise<EncryptedPair> {
+  const [aSocket, bSocket] = createMemoryPair(memory);
+  try {
+    const [a, b] = await Promise.all([EncryptedStream.new(aSocket, optionsA), EncryptedStream.new(bSocket, optionsB)]);
+    return { a, b, aSocket, bSocket };
+  } catch (error) {
+    aSocket.destroy();
+    bSocket.destroy();
+    throw error;
+  }
+}
+
+/**
+ * Read-path attacks run by `runReadPathBattery`:
+ * - slowlorisHandshake: sends the opening handshake message a byte at a
+ *   time, slower than any sane deadline; passes if the server gives up on
+ *   the connection within `deadlineMs`
+ * - dripFeed: an honest peer whose every byte arrives on its own; passes if
+ *   its packet comes through intact within `deadlineMs`
+ * - hugeLength: after setup, announces a frame of almost 2 GiB and sends
+ *   nothing more; passes if the read fails without waiting for the body
+ * - zipBomb: after setup, sends a packet of zeros that compresses far below
+ *   the server's maxPacketSize and inflates far beyond it; passes if the
+ *   read fails. Skipped when the server doesn't enable compression.
+ */
+export type ReadPathAttack = "slowlorisHandshake" | "dripFeed" | "hugeLength" | "zipBomb";
+
+/**
+ * Options for `runReadPathBattery`
+ */
+export interface ReadPathBatteryOptions {
+  /** Stream options of the server under test (default: none) */
+  streamOptions?: EncryptedStreamOptions | undefined;
+  /**
+   * Set up the server side of a connection, as the server's accept path
+   * does, including any deadline it puts on handshakes
+   * (default: `EncryptedStream.new(socket, streamOptions)`)
+   */
+  serve?: ((socket: Duplex) => Promise<EncryptedStream>) | undefined;
+  /**
+   * Options of the attacker's honest-looking side, for attacks that get
+   * through setup (default: streamOptions). Set it when setup needs
+   * client-side credentials.
+   */
+  peerOptions?: EncryptedStreamOptions | undefined;
+  /** Attacks to run, in order (default: all) */
+  attacks?: readonly ReadPathAttack[] | undefined;
+  /** How long the server may take to deal with each attack, in milliseconds (default: 5000) */
+  deadlineMs?: number | undefined;
+  /** Gap between the slowloris' bytes, in milliseconds (default: 1000) */
+  slowlorisIntervalMs?: number | undefined;
+  /** Gap between drip-fed bytes, in milliseconds (default: 1) */
+  dripIntervalMs?: number | undefined;
+  /** Inflated size of the zip bomb (default: 16 MiB) */
+  zipBombBytes?: number | undefined;
+  /**
+   * Clock the server under test runs on (default: `systemClock`). A
+   * `ManualClock` is advanced by the battery itself, so the whole run
+   * takes no real time.
+   */
+  clock?: Clock | undefined;
+}
+
+/**
+ * Outcome of one attack
+ */
+export interface ReadPathResult {
+  attack: ReadPathAttack;
+  /** Whether the server held up; false for skipped attacks too */
+  passed: boolean;
+  /** Whether the attack couldn't run against these settings */
+  skipped: boolean;
+  /** Time until the server dealt with the attack, or the deadline if it didn't */
+  elapsedMs: number;
+  /** What happened, for test output */
+  detail: string;
+  /** Error the server failed the connection with, if any */
+  error?: unknown;
+}
+
+const READ_PATH_ATTACKS: readonly ReadPathAttack[] = ["slowlorisHandshake", "dripFeed", "hugeLength", "zipBomb"];
+/** Frame header announcing the largest length that isn't a control frame */
+const HUGE_LENGTH_HEADER = new Uint8Array([0xff, 0xff, 0xff, 0x7f]);
+const DRIP_PAYLOAD = new Uint8Array(16).fill(0xd1);
+
+type Settled<T> =
+  | { status: "fulfilled"; value: T; elapsedMs: number }
+  | { status: "rejected"; error: unknown; elapsedMs: number }
+  | { status: "pending"; elapsedMs: number };
+
+/** Give `work` up to `ms` of the clock's time to settle */
+async function settleWithin<T>(clock: Clock, work: Promise<T>, ms: number): Promise<Settled<T>> {
+  const start = clock.now();
+  let settled: Settled<T> | undefined;
+  const done = work.then(
+    (value) => void (settled ??= { status: "fulfilled", value, elapsedMs: clock.now() - start }),
+    (error: unknown) => void (settled ??= { status: "rejected", error, elapsedMs: clock.now() - start })
+  );
+  if (clock instanceof ManualClock) {
+    await clock.advance(ms);
+  } else {
+    let timer: TimerHandle | undefined;
+    await Promise.race([done, new Promise<void>((resolve) => (timer = clock.setTimer(resolve, ms)))]);
+    timer?.cancel();
+  }
+  return settled ?? { status: "pending", elapsedMs: ms };
+}
+
+/** Write `bytes` to `socket` one byte every `intervalMs`, stopping if it goes away */
+function drip(socket: Duplex, bytes: Uint8Array, clock: Clock, intervalMs: number, done: () => void = () => {}): void {
+  let sent = 0;
+  const next = () => {
+    if (socket.destroyed) return;
+    if (sent === bytes.length) {
+      done();
+      return;
+    }
+    socket.write(bytes.subarray(sent, ++sent));
+    clock.setTimer(next, intervalMs);
+  };
+  next();
+}
+
+/** Wrap `socket` so everything written to the wrapper reaches the peer a byte at a time */
+function drippingSocket(socket: Duplex, clock: Clock, intervalMs: number): Duplex {
+  const wrapper = new Duplex({
+    read() {},
+    write: (chunk: Buffer, _encoding, callback) => drip(socket, new Uint8Array(chunk), clock, intervalMs, callback),
+    final: (callback) => {
+      socket.end();
+      callback();
+    },
+    destroy: (error, callback) => {
+      socket.destroy();
+      callback(error);
+    },
+  });
+  socket.on("data", (chunk: Buffer) => wrapper.push(chunk));
+  socket.on("end", () => wrapper.push(null));
+  return wrapper;
+}
+
+function serverFailed(attack: ReadPathAttack, outcome: Settled<unknown>, what: string): ReadPathResult {
+  if (outcome.status === "rejected" && outcome.error instanceof ClavisError) {
+    const detail = `Refused ${what}: ${outcome.error.message}`;
+    return { attack, passed: true, skipped: false, elapsedMs: outcome.elapsedMs, detail, error: outcome.error };
+  }
+  const detail = outcome.status === "pending"
+    ? `Still waiting on ${what} after ${outcome.elapsedMs}ms`
+    : outcome.status === "fulfilled"
+      ? `Accepted ${what}`
+      : `Failed on ${what} with a non-clavis error: ${String(outcome.error)}`;
+  return {
+    attack,
+    passed: false,
+    skipped: false,
+    elapsedMs: outcome.elapsedMs,
+    detail,
+    ...(outcome.status === "rejected" ? { error: outcome.error } : {}),
+  };
+}
+
+/**
+ * Run adversarial clients against a server's read path over in-memory
+ * connections and report how it held up. Servers can run it in their own
+ * test suites to check that their settings and accept path bound the
+ * time and memory an attacker can tie up.
+ *
+ * Only the slowloris attack depends on the server bounding handshakes,
+ * with `handshakeTimeoutMs` or a deadline of its own in `serve`.
+ *
+ * @example
+ * ```typescript
+ * const clock = new ManualClock();
+ * const results = await runReadPathBattery({
+ *   // Drop handshakes that take over 2 seconds
+ *   streamOptions: { clock, handshakeTimeoutMs: 2000, compression: { algorithms: ["deflate"] } },
+ *   clock,
+ * });
+ * for (const result of results) expect(result.passed || result.skipped).toBe(true);
+ * ```
+ */
+export async function runReadPathBattery(options: ReadPathBatteryOptions = {}): Promise<ReadPathResult[]> {
+  const streamOptions = options.streamOptions ?? {};
+  const battery: Battery = {
+    clock: options.clock ?? systemClock,
+    streamOptions,
+    peerOptions: options.peerOptions ?? streamOptions,
+    serve: options.serve ?? ((socket) => EncryptedStream.new(socket, streamOptions)),
+    deadlineMs: options.deadlineMs ?? 5000,
+    slowlorisIntervalMs: options.slowlorisIntervalMs ?? 1000,
+    dripIntervalMs: options.dripIntervalMs ?? 1,
+    zipBombBytes: options.zipBombBytes ?? 16 * 1024 * 1024,
+  };
+  if (!(battery.deadlineMs > 0) || !(battery.slowlorisIntervalMs > 0) || !(battery.dripIntervalMs > 0)) {
+    throw ClavisError.config("deadlineMs, slowlorisIntervalMs and dripIntervalMs must be positive");
+  }
+
+  const results: ReadPathResult[] = [];
+  for (const attack of options.attacks ?? READ_PATH_ATTACKS) {
+    const [attacker, victim] = createChaosPair({}, {}, battery.clock);
+    try {
+      results.push(await runAttack(attack, attacker, victim, battery));
+    } finally {
+      attacker.destroy();
+      victim.destroy();
+    }
+  }
+  return results;
+}
+
+/** `ReadPathBatteryOptions` with defaults filled in */
+interface Battery {
+  clock: Clock;
+  streamOptions: EncryptedStreamOptions;
+  peerOptions: EncryptedStreamOptions;
+  serve: (socket: Duplex) => Promise<EncryptedStream>;
+  deadlineMs: number;
+  slowlorisIntervalMs: number;
+  dripIntervalMs: number;
+  zipBombBytes: number;
+}
+
+async function runAttack(attack: ReadPathAttack, attacker: Duplex, victim: Duplex, battery: Battery): Promise<ReadPathResult> {
+  const { clock, deadlineMs, peerOptions, serve } = battery;
+  switch (attack) {
+    case "slowlorisHandshake": {
+      drip(attacker, generateRandomBytes(HANDSHAKE_MESSAGE_LENGTHS.nonce), clock, battery.slowlorisIntervalMs);
+      const outcome = await settleWithin(clock, serve(victim), deadlineMs);
+      if (outcome.status === "pending" && victim.destroyed) {
+        return { attack, passed: true, skipped: false, elapsedMs: outcome.elapsedMs, detail: "Closed the connection" };
+      }
+      return serverFailed(attack, outcome, "a handshake that never finishes");
+    }
+    case "dripFeed": {
+      const peer = drippingSocket(attacker, clock, battery.dripIntervalMs);
+      const sending = EncryptedStream.new(peer, peerOptions).then((stream) => stream.writePacket(new RawPacket(DRIP_PAYLOAD)));
+      const receiving = serve(victim).then((stream) => stream.readPacket());
+      const outcome = await settleWithin(clock, Promise.all([receiving, sending]), deadlineMs);
+      peer.destroy();
+      if (outcome.status !== "fulfilled") {
+        const detail = outcome.status === "pending"
+          ? `Still waiting on a drip-fed packet after ${outcome.elapsedMs}ms`
+          : `Failed a drip-fed connection: ${String(outcome.error)}`;
+        return {
+          attack,
+          passed: false,
+          skipped: false,
+          elapsedMs: outcome.elapsedMs,
+          detail,
+          ...(outcome.status === "rejected" ? { error: outcome.error } : {}),
+        };
+      }
+      const received = outcome.value[0] as unknown as Uint8Array;
+      const intact = received.length === DRIP_PAYLOAD.length && received.every((byte, i) => byte === DRIP_PAYLOAD[i]);
+      return {
+        attack,
+        passed: intact,
+        skipped: false,
+        elapsedMs: outcome.elapsedMs,
+        detail: intact ? "Reassembled the drip-fed packet" : "Delivered a corrupted packet",
+      };
+    }
+    case "hugeLength": {
+      const sending = EncryptedStream.new(attacker, peerOptions).then(() => {
+        attacker.write(HUGE_LENGTH_HEADER);
+      });
+      void sending.catch(() => {});
+      const receiving = serve(victim).then((stream) => stream.readPacket());
+      return serverFailed(attack, await settleWithin(clock, receiving, deadlineMs), "a frame of almost 2 GiB");
+    }
+    case "zipBomb": {
+      const compression = battery.streamOptions.compression;
+      if (!compression) {
+        return { attack, passed: false, skipped: true, elapsedMs: 0, detail: "Compression is off" };
+      }
+      // 65536 is the streams' default maxPacketSize
+      if (battery.zipBombBytes <= (battery.streamOptions.maxPacketSize ?? 65536)) {
+        return { attack, passed: false, skipped: true, elapsedMs: 0, detail: "zipBombBytes must exceed maxPacketSize" };
+      }
+      const sending = EncryptedStream.new(attacker, {
+        ...peerOptions,
+        maxPacketSize: battery.zipBombBytes,
+        compression: { ...(peerOptions.compression ?? compression), minSize: 0 },
+      }).then((stream) => stream.writePacket(new RawPacket(new Uint8Array(battery.zipBombBytes))));
+      void sending.catch(() => {});
+      const receiving = serve(victim).then((stream) => stream.readPacket());
+      return serverFailed(attack, await settleWithin(clock, receiving, deadlineMs), "a zip bomb");
+    }
+  }
+}
@@ -0,0 +1,311 @@
+/**
+ * Session ticket keys
+ * Keyring that seals and opens resumption tickets, with scheduled key rotation
+ *
+ * Every key has an activation time and an expiry. Tickets are always sealed
+ * with the newest key that is active, and opened with any key that hasn't
+ * expired, so a rotation never strands tickets issued just before it while an
+ * old key stops being accepted on schedule. A ticket also carries its issue
+ * time and is refused once older than the ticket lifetime, whatever its key.
+ *
+ * A fleet of servers shares tickets by sharing keys: generate a schedule on
+ * one machine, distribute it with `encodeTicketKeys()`, and `install()` it
+ * everywhere ahead of the first activation time. Servers then switch keys at
+ * the same moment without talking to each other. A single server can instead
+ * leave `autoRotate` on and generate keys as it goes.
+ *
+ * Ticket layout (integers little-endian):
+ * version (u8) | key id (8) | issued at, ms since the epoch (u64) | nonce (24) |
+ * XChaCha20-Poly1305 ciphertext, with everything before the nonce as associated data
+ */
+
+import { XChaCha20Poly1305Cipher, generateRandomBytes, sha256Hash } from "./crypto.js";
+import { ClavisError, CryptoError, MessageError } from "./error.js";
+import { wallClock, type Clock } from "./clock.js";
+import { redact } from "./audit.js";
+import { checkValidityPeriod } from "./validity.js";
+import { MemoryReplayCache, type ReplayCache } from "./replay.js";
+
+/**
+ * A ticket-encryption key and the window it is used in
+ */
+export interface TicketKey {
+  /** Hex id written into every ticket sealed with this key */
+  readonly id: string;
+  readonly secret: Uint8Array;
+  /** Tickets are sealed with this key from this time on (ms since the epoch) */
+  readonly activatesAt: number;
+  /** Tickets sealed with this key are refused from this time on (ms since the epoch) */
+  readonly expiresAt: number;
+}
+
+/**
+ * Options for a ticket keyring
+ */
+export interface TicketKeyringOptions {
+  /** Seal with a fresh key this often (default: 12 hours) */
+  rotationIntervalMs?: number | undefined;
+  /** Refuse tickets older than this (default: 24 hours) */
+  ticketLifetimeMs?: number | undefined;
+  /**
+   * Generate a key when none is active or the active one is older than the
+   * rotation interval (default: true). Turn off when keys are installed from
+   * a fleet-wide schedule, so servers never seal with a key only they know.
+   */
+  autoRotate?: boolean | undefined;
+  /** Wall-clock time source (default: `wallClock`) */
+  clock?: Clock | undefined;
+  /**
+   * Accept tickets this far outside their lifetime, for servers whose clocks
+   * disagree (default: 5 minutes)
+   */
+  clockSkewMs?: number | undefined;
+  /**
+   * Where redeemed tickets are remembered (default: an in-memory cache).
+   * Servers sharing ticket keys should share a cache too.
+   */
+  replayCache?: ReplayCache | undefined;
+}
+
+const TICKET_VERSION = 1;
+const KEY_ID_LENGTH = 8;
+const HEADER_LENGTH = 1 + KEY_ID_LENGTH + 8;
+const KEYS_MAGIC = [0x43, 0x4c, 0x56, 0x4b]; // "CLVK"
+const KEYS_VERSION = 1;
+const ENCODED_KEY_LENGTH = KEY_ID_LENGTH + 32 + 8 + 8;
+const HOUR = 60 * 60 * 1000;
+
+function toHex(bytes: Uint8Array): string {
+  return Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+}
+
+function fromHex(hex: string): Uint8Array {
+  const bytes = new Uint8Array(hex.length / 2);
+  for (let i = 0; i < bytes.length; i++) {
+    bytes[i] = parseInt(hex.slice(i * 2, i * 2 + 2), 16);
+  }
+  return bytes;
+}
+
+function issuedAtOf(ticket: Uint8Array): number {
+  return Number(new DataView(ticket.buffer, ticket.byteOffset, HEADER_LENGTH).getBigUint64(1 + KEY_ID_LENGTH, true));
+}
+
+/**
+ * Generate a key used from `activatesAt` until `expiresAt`
+ */
+export function generateTicketKey(activatesAt: number, expiresAt: number): TicketKey {
+  if (!(expiresAt > activatesAt)) {
+    throw ClavisError.config("Ticket keys must expire after they activate");
+  }
+  return {
+    id: toHex(generateRandomBytes(KEY_ID_LENGTH)),
+    secret: redact(generateRandomBytes(32), "key"),
+    activatesAt,
+    expiresAt,
+  };
+}
+
+/**
+ * Generate `count` back-to-back keys for a fleet: key i activates at
+ * `start + i * rotationIntervalMs` and stays accepted for a ticket lifetime
+ * after the next one takes over
+ */
+export function createTicketKeySchedule(options: {
+  start: number;
+  count: number;
+  rotationIntervalMs: number;
+  ticketLifetimeMs: number;
+}): TicketKey[] {
+  const { start, count, rotationIntervalMs, ticketLifetimeMs } = options;
+  if (!(count >= 1) || !(rotationIntervalMs > 0) || !(ticketLifetimeMs > 0)) {
+    throw ClavisError.config("Key schedules need a positive count, interval and lifetime");
+  }
+  return Array.from({ length: count }, (_, i) => {
+    const activatesAt = start + i * rotationIntervalMs;
+    return generateTicketKey(activatesAt, activatesAt + rotationIntervalMs + ticketLifetimeMs);
+  });
+}
+
+/**
+ * Serialize keys for distribution to other servers. The output contains the
+ * secrets; send it only over a channel you'd trust with them.
+ */
+export function encodeTicketKeys(keys: readonly TicketKey[]): Uint8Array {
+  if (keys.length > 0xffff) {
+    throw ClavisError.config("At most 65535 ticket keys can be encoded");
+  }
+  const out = new Uint8Array(KEYS_MAGIC.length + 3 + keys.length * ENCODED_KEY_LENGTH);
+  const view = new DataView(out.buffer);
+  out.set(KEYS_MAGIC, 0);
+  out[4] = KEYS_VERSION;
+  view.setUint16(5, keys.length, true);
+  keys.forEach((key, i) => {
+    const offset = 7 + i * ENCODED_KEY_LENGTH;
+    out.set(fromHex(key.id), offset);
+    out.set(key.secret, offset + KEY_ID_LENGTH);
+    view.setBigUint64(offset + KEY_ID_LENGTH + 32, BigInt(key.activatesAt), true);
+    view.setBigUint64(offset + KEY_ID_LENGTH + 40, BigInt(key.expiresAt), true);
+  });
+  return redact(out, "key");
+}
+
+/**
+ * Undo `encodeTicketKeys`
+ */
+export function decodeTicketKeys(data: Uint8Array): TicketKey[] {
+  const magic = KEYS_MAGIC.every((b, i) => data[i] === b);
+  if (!magic || data[4] !== KEYS_VERSION || data.length < 7) {
+    throw ClavisError.message(MessageError.invalidFormat("Not an encoded ticket key set"));
+  }
+  const view = new DataView(data.buffer, data.byteOffset, data.byteLength);
+  const count = view.getUint16(5, true);
+  if (data.length !== 7 + count * ENCODED_KEY_LENGTH) {
+    throw ClavisError.message(MessageError.invalidFormat("Malformed ticket key set"));
+  }
+  return Array.from({ length: count }, (_, i) => {
+    const offset = 7 + i * ENCODED_KEY_LENGTH;
+    return {
+      id: toHex(data.subarray(offset, offset + KEY_ID_LENGTH)),
+      secret: redact(data.slice(offset + KEY_ID_LENGTH, offset + KEY_ID_LENGTH + 32), "key"),
+      activatesAt: Number(view.getBigUint64(offset + KEY_ID_LENGTH + 32, true)),
+      expiresAt: Number(view.getBigUint64(offset + KEY_ID_LENGTH + 40, true)),
+    };
+  });
+}
+
+/**
+ * Set of ticket keys that seals with the current one and opens with any
+ * that is still accepted
+ */
+export class TicketKeyring {
+  private readonly byId = new Map<string, TicketKey>();
+  private readonly rotationIntervalMs: number;
+  /** Time source tickets are stamped and checked with */
+  readonly clock: Clock;
+  private readonly autoRotate: boolean;
+  readonly ticketLifetimeMs: number;
+  readonly clockSkewMs: number;
+  readonly replayCache: ReplayCache;
+
+  constructor(options?: TicketKeyringOptions, keys: readonly TicketKey[] = []) {
+    this.rotationIntervalMs = options?.rotationIntervalMs ?? 12 * HOUR;
+    this.ticketLifetimeMs = options?.ticketLifetimeMs ?? 24 * HOUR;
+    this.autoRotate = options?.autoRotate ?? true;
+    this.clock = options?.clock ?? wallClock;
+    this.clockSkewMs = options?.clockSkewMs ?? 5 * 60 * 1000;
+    this.replayCache = options?.replayCache ?? new MemoryReplayCache({ clock: this.clock });
+    if (!(this.rotationIntervalMs > 0) || !(this.ticketLifetimeMs > 0)) {
+      throw ClavisError.config("Ticket rotation interval and lifetime must be positive");
+    }
+    if (!(this.clockSkewMs >= 0)) {
+      throw ClavisError.config("clockSkewMs must not be negative");
+    }
+    this.install(keys);
+  }
+
+  /**
+   * Add keys, e.g. a schedule pushed to the fleet; keys with a known id replace the old entry
+   */
+  install(keys: readonly TicketKey[]): void {
+    for (const key of keys) {
+      if (key.secret.length !== 32 || key.id.length !== KEY_ID_LENGTH * 2) {
+        throw ClavisError.crypto(CryptoError.invalidKeyMaterial("Ticket keys need an 8-byte id and a 32-byte secret"));
+      }
+      this.byId.set(key.id, key);
+    }
+  }
+
+  /** Stop accepting tickets sealed with key `id` right away, e.g. after a leak */
+  remove(id: string): boolean {
+    return this.byId.delete(id);
+  }
+
+  /** Keys still accepted, oldest activation first */
+  get keys(): TicketKey[] {
+    this.prune();
+    return [...this.byId.values()].sort((a, b) => a.activatesAt - b.activatesAt);
+  }
+
+  /**
+   * Key new tickets are sealed with: the newest active one, rotated first if
+   * `autoRotate` is on and it is due
+   */
+  get currentKey(): TicketKey {
+    const now = this.clock.now();
+    let current: TicketKey | undefined;
+    for (const key of this.keys) {
+      if (key.activatesAt <= now && key.expiresAt > now) current = key;
+    }
+    if (this.autoRotate && (!current || now - current.activatesAt >= this.rotationIntervalMs)) {
+      current = generateTicketKey(now, now + this.rotationIntervalMs + this.ticketLifetimeMs);
+      this.byId.set(current.id, current);
+    }
+    if (!current) {
+      throw ClavisError.invalidOperation("No ticket key is active; install a schedule that covers now");
+    }
+    return current;
+  }
+
+  /**
+   * Encrypt `state` into a ticket
+   */
+  seal(state: Uint8Array): Uint8Array {
+    const key = this.currentKey;
+    const header = new Uint8Array(HEADER_LENGTH);
+    header[0] = TICKET_VERSION;
+    header.set(fromHex(key.id), 1);
+    new DataView(header.buffer).setBigUint64(1 + KEY_ID_LENGTH, BigInt(Math.floor(this.clock.now())), true);
+
+    const nonce = XChaCha20Poly1305Cipher.generateNonce();
+    const ciphertext = new XChaCha20Poly1305Cipher(key.secret).encrypt(nonce, state, header);
+    const ticket = new Uint8Array(HEADER_LENGTH + nonce.length + ciphertext.length);
+    ticket.set(header, 0);
+    ticket.set(nonce, HEADER_LENGTH);
+    ticket.set(ciphertext, HEADER_LENGTH + nonce.length);
+    return ticket;
+  }
+
+  /**
+   * Decrypt a ticket back into its state. Unknown, expired or tampered
+   * tickets fail with an authentication error; for tickets outside their
+   * lifetime, `validityFailure` says which way.
+   */
+  open(ticket: Uint8Array): Uint8Array {
+    if (ticket.length < HEADER_LENGTH + 24 + 16 || ticket[0] !== TICKET_VERSION) {
+      throw ClavisError.message(MessageError.invalidFormat("Not a session ticket"));
+    }
+    this.prune();
+    const header = ticket.subarray(0, HEADER_LENGTH);
+    const key = this.byId.get(toHex(header.subarray(1, 1 + KEY_ID_LENGTH)));
+    if (!key) {
+      throw ClavisError.crypto(CryptoError.authenticationFailure("Ticket key is unknown or retired"));
+    }
+    const issuedAt = issuedAtOf(ticket);
+    checkValidityPeriod("Ticket", issuedAt, issuedAt + this.ticketLifetimeMs, this.clock.now(), this.clockSkewMs);
+    const nonce = ticket.subarray(HEADER_LENGTH, HEADER_LENGTH + 24);
+    try {
+      return new XChaCha20Poly1305Cipher(key.secret).decrypt(nonce, ticket.subarray(HEADER_LENGTH + 24), header);
+    } catch {
+      throw ClavisError.crypto(CryptoError.authenticationFailure("Ticket failed authentication"));
+    }
+  }
+
+  /**
+   * Mark an opened ticket as used. Resolves false if it had already been
+   * redeemed, in which case it must not be honoured again.
+   */
+  async redeem(ticket: Uint8Array): Promise<boolean> {
+    const id = toHex(sha256Hash(ticket));
+    // Remember it for as long as `open()` would still accept it
+    return this.replayCache.insert(id, issuedAtOf(ticket) + this.ticketLifetimeMs + this.clockSkewMs);
+  }
+
+  /** Drop keys that have expired, allowing for the same skew as tickets */
+  private prune(): void {
+    const now = this.clock.now() - this.clockSkewMs;
+    for (const [id, key] of this.byId) {
+      if (key.expiresAt <= now) this.byId.delete(id);
+    }
+  }
+}
@@ -0,0 +1,183 @@
+/**
+ * Clock offset estimation
+ * NTP-style probes over control frames that estimate how far the peer's
+ * wall clock is from ours and how long a frame takes to cross the link
+ *
+ * A probe records its send time t0; the peer notes when it received it (t1)
+ * and when it answered (t2), and the answer arrives at t3. Then
+ *
+ *   offset = ((t1 - t0) + (t2 - t3)) / 2    (peer clock minus ours)
+ *   delay  = (t3 - t0) - (t2 - t1)           (round trip, minus the peer's turnaround)
+ *
+ * The offset is exact when both directions take equally long, and off by at
+ * most half the delay otherwise, so the estimate keeps the sample with the
+ * lowest delay among the most recent ones, as NTP's clock filter does.
+ */
+
+import { ClavisError, MessageError } from "./error.js";
+import { ControlFrameKind, type ControlChannel } from "./control.js";
+import { wallClock } from "./clock.js";
+
+/**
+ * One completed probe
+ */
+export interface ClockSample {
+  /** Peer wall clock minus ours, in milliseconds */
+  offsetMs: number;
+  /** Round-trip time without the peer's turnaround, in milliseconds */
+  delayMs: number;
+  /** Our wall-clock time when the answer arrived */
+  at: number;
+}
+
+/**
+ * Current best estimate
+ */
+export interface ClockOffsetEstimate {
+  /** Peer wall clock minus ours, in milliseconds */
+  offsetMs: number;
+  /** Estimated one-way delay (half the best round trip), in milliseconds */
+  oneWayDelayMs: number;
+  /** Largest error of `offsetMs` if the link is asymmetric */
+  uncertaintyMs: number;
+  /** Probes the estimate was chosen from */
+  samples: number;
+}
+
+/** Recent samples the estimate is chosen from */
+const DEFAULT_SAMPLE_WINDOW = 8;
+
+/**
+ * Keeps recent probe results and picks the most trustworthy one
+ */
+export class ClockOffsetEstimator {
+  private readonly recent: ClockSample[] = [];
+
+  constructor(private readonly window: number = DEFAULT_SAMPLE_WINDOW) {}
+
+  /** Record a probe from its four timestamps; returns the sample */
+  add(t0: number, t1: number, t2: number, t3: number): ClockSample {
+    const sample: ClockSample = {
+      offsetMs: (t1 - t0 + (t2 - t3)) / 2,
+      // Clocks with coarse resolution can make the turnaround look longer than the round trip
+      delayMs: Math.max(0, t3 - t0 - (t2 - t1)),
+      at: t3,
+    };
+    this.recent.push(sample);
+    if (this.recent.length > this.window) this.recent.shift();
+    return sample;
+  }
+
+  /** Estimate from the lowest-delay recent sample; undefined before the first probe */
+  get estimate(): ClockOffsetEstimate | undefined {
+    let best: ClockSample | undefined;
+    for (const sample of this.recent) {
+      if (!best || sample.delayMs < best.delayMs) best = sample;
+    }
+    if (!best) return undefined;
+    return {
+      offsetMs: best.offsetMs,
+      oneWayDelayMs: best.delayMs / 2,
+      uncertaintyMs: best.delayMs / 2,
+      samples: this.recent.length,
+    };
+  }
+}
+
+/** Probe payload: id (u32) */
+export function encodeTimeProbe(id: number): Uint8Array {
+  const out = new Uint8Array(4);
+  new DataView(out.buffer).setUint32(0, id >>> 0, true);
+  return out;
+}
+
+export function decodeTimeProbe(payload: Uint8Array): number {
+  if (payload.length !== 4) {
+    throw ClavisError.message(MessageError.invalidFormat("Clock probe must be a u32 id"));
+  }
+  return new DataView(payload.buffer, payload.byteOffset, 4).getUint32(0, true);
+}
+
+/**
+ * Answer payload: id (u32), receive time t1 (f64), send time t2 (f64),
+ * both milliseconds since the epoch
+ */
+export function encodeTimeReply(id: number, received: number, sent: number): Uint8Array {
+  const out = new Uint8Array(20);
+  const view = new DataView(out.buffer);
+  view.setUint32(0, id >>> 0, true);
+  view.setFloat64(4, received, true);
+  view.setFloat64(12, sent, true);
+  return out;
+}
+
+export function decodeTimeReply(payload: Uint8Array): { id: number; received: number; sent: number } {
+  if (payload.length !== 20) {
+    throw ClavisError.message(MessageError.invalidFormat("Clock probe answer must be 20 bytes"));
+  }
+  const view = new DataView(payload.buffer, payload.byteOffset, 20);
+  return { id: view.getUint32(0, true), received: view.getFloat64(4, true), sent: view.getFloat64(12, true) };
+}
+
+interface PendingProbe {
+  sent: number;
+  resolve: (sample: ClockSample) => void;
+  reject: (error: unknown) => void;
+}
+
+/**
+ * Time sync of one connection: probes sent and not yet answered, and the
+ * estimate their answers feed
+ */
+export class TimeSync {
+  readonly estimator = new ClockOffsetEstimator();
+  private probes = new Map<number, PendingProbe>();
+  private nextProbeId = 0;
+
+  constructor(private readonly channel: ControlChannel) {}
+
+  /** Whether a probe is waiting for its answer */
+  get busy(): boolean {
+    return this.probes.size > 0;
+  }
+
+  /** Send a probe and resolve with the sample once the peer answers */
+  async measure(): Promise<ClockSample> {
+    const id = this.nextProbeId;
+    this.nextProbeId = (this.nextProbeId + 1) >>> 0;
+    const result = new Promise<ClockSample>((resolve, reject) => {
+      this.probes.set(id, { sent: wallClock.now(), resolve, reject });
+    });
+
+    try {
+      await this.channel.request(ControlFrameKind.TimeProbe, encodeTimeProbe(id));
+    } catch (error) {
+      this.probes.delete(id);
+      throw error;
+    }
+    return result;
+  }
+
+  /** Answer the peer's probe, or complete ours; `received` is when the frame arrived */
+  async handle(kind: ControlFrameKind, payload: Uint8Array, received: number): Promise<void> {
+    if (kind === ControlFrameKind.TimeProbe) {
+      const id = decodeTimeProbe(payload);
+      await this.channel.answer(ControlFrameKind.TimeReply, encodeTimeReply(id, received, wallClock.now()));
+      return;
+    }
+
+    const reply = decodeTimeReply(payload);
+    const probe = this.probes.get(reply.id);
+    if (!probe) {
+      throw ClavisError.message(MessageError.invalidFormat("Answer to a clock probe never sent"));
+    }
+    this.probes.delete(reply.id);
+    probe.resolve(this.estimator.add(probe.sent, reply.received, reply.sent, received));
+  }
+
+  /** Fail the probes waiting for answers, once nothing more can arrive */
+  fail(error: unknown): void {
+    for (const probe of this.probes.values()) probe.reject(error);
+    this.probes.clear();
+  }
+}
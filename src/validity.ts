@@ -0,0 +1,60 @@
+/**
+ * Validity periods
+ * One check for everything with a lifetime (session tickets, identity and
+ * X.509 certificates), with a configurable clock-skew tolerance
+ *
+ * Machines in a fleet rarely agree on the time to the millisecond. A
+ * credential is accepted from `toleranceMs` before its period starts until
+ * `toleranceMs` after it ends. Refusals say which way the check failed, via
+ * `CryptoError.validity`:
+ * - `Expired`: the period ended.
+ * - `SkewSuspected`: the period starts less than a day ahead. Credentials are
+ *   used as soon as they are issued, so this almost always means a clock on
+ *   one end is wrong rather than the credential.
+ * - `NotYetValid`: the period starts further ahead than that.
+ */
+
+import { ClavisError, CryptoError, ValidityFailure } from "./error.js";
+
+/** How far ahead a start time may be before a wrong clock stops being the likely cause */
+const SKEW_SUSPECT_MS = 24 * 60 * 60 * 1000;
+
+/**
+ * Check that `now` falls within `[notBefore, notAfter)` give or take
+ * `toleranceMs` (all in ms since the epoch). `subject` names the credential
+ * in the error.
+ */
+export function checkValidityPeriod(
+  subject: string,
+  notBefore: number | undefined,
+  notAfter: number,
+  now: number,
+  toleranceMs: number = 0
+): void {
+  if (notBefore !== undefined && notBefore > now + toleranceMs) {
+    const ahead = notBefore - now;
+    if (ahead <= SKEW_SUSPECT_MS) {
+      throw ClavisError.crypto(CryptoError.invalidPeriod(
+        ValidityFailure.SkewSuspected,
+        `${subject} only becomes valid in ${formatDuration(ahead)}; check the clocks on both ends`
+      ));
+    }
+    throw ClavisError.crypto(CryptoError.invalidPeriod(
+      ValidityFailure.NotYetValid,
+      `${subject} is not valid until ${new Date(notBefore).toISOString()}`
+    ));
+  }
+  if (now - toleranceMs >= notAfter) {
+    throw ClavisError.crypto(CryptoError.invalidPeriod(
+      ValidityFailure.Expired,
+      `${subject} has expired (at ${new Date(notAfter).toISOString()})`
+    ));
+  }
+}
+
+function formatDuration(ms: number): string {
+  const seconds = Math.ceil(ms / 1000);
+  if (seconds < 120) return `${seconds}s`;
+  const minutes = Math.ceil(seconds / 60);
+  return minutes < 120 ? `${minutes}min` : `${Math.ceil(minutes / 60)}h`;
+}
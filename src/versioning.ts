@@ -0,0 +1,124 @@
+/**
+ * Protocol version negotiation
+ * Exchanges the application protocol's version during connection setup
+ *
+ * Runs over the encrypted channel right after the handshake (and the
+ * post-quantum exchange, when enabled), before the identity, resumption
+ * and compression exchanges, so a peer speaking the wrong version is
+ * turned away before any application packet is read.
+ * Both peers send an offer with their version, check the peer's against
+ * what they accept, and send a verdict; the connection only comes up if both
+ * accepted. Either way each side learns the other's version, so the error
+ * names both.
+ *
+ * Wire layout, both packets:
+ * - magic: "CLVV"
+ * - kind: u8 (0 = offer, 1 = accept, 2 = reject)
+ * - version: u32 little-endian (the sender's own version)
+ *
+ * Peers that predate this exchange don't send an offer, so only enable it
+ * where both sides run a version that has it.
+ *
+ * Once the connection is up, either side can move both directions to
+ * another version with `upgradeProtocol()`, over control frames:
+ *   requester: UpgradeRequest(version)
+ *   peer:      UpgradeAccept(version) or UpgradeReject(its version)
+ *   requester: UpgradeConfirm(version), after an accept
+ * Each side's packets switch right after the frame that announces it, the
+ * peer's at its accept and the requester's at its confirmation, so packets
+ * in flight when the upgrade starts are still read in the old version.
+ */
+
+import { ClavisError, StreamError } from "./error.js";
+
+/**
+ * Which peer versions a side accepts: a predicate, or an inclusive range
+ */
+export type VersionAcceptance = ((peerVersion: number) => boolean) | { min: number; max: number };
+
+/**
+ * Protocol version a stream announces and the versions it accepts
+ */
+export interface ProtocolVersionOptions {
+  /** This side's version */
+  version: number;
+  /** Peer versions to accept, during setup and when the peer asks to upgrade (default: only `version` itself) */
+  accept?: VersionAcceptance | undefined;
+  /**
+   * Called when this side accepts the peer's request to upgrade to
+   * `version`, just before its own packets switch to it
+   */
+  onUpgrade?: ((version: number) => void) | undefined;
+}
+
+const MAGIC = [0x43, 0x4c, 0x56, 0x56]; // "CLVV"
+const PACKET_LENGTH = 9;
+
+enum VersionPacketKind {
+  Offer = 0,
+  Accept = 1,
+  Reject = 2,
+}
+
+function encode(kind: VersionPacketKind, version: number): Uint8Array {
+  const packet = new Uint8Array(PACKET_LENGTH);
+  packet.set(MAGIC, 0);
+  packet[4] = kind;
+  new DataView(packet.buffer).setUint32(5, version, true);
+  return packet;
+}
+
+function decode(packet: Uint8Array, expected: readonly VersionPacketKind[]): { kind: VersionPacketKind; version: number } {
+  const magic = MAGIC.every((b, i) => packet[i] === b);
+  if (!magic) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Peer did not negotiate a protocol version"));
+  }
+  const kind = packet[4] as VersionPacketKind;
+  if (packet.length !== PACKET_LENGTH || !expected.includes(kind)) {
+    throw ClavisError.stream(StreamError.handshakeFailed("Malformed protocol version packet"));
+  }
+  const version = new DataView(packet.buffer, packet.byteOffset, packet.byteLength).getUint32(5, true);
+  return { kind, version };
+}
+
+/**
+ * Check a stream's `protocolVersion` option and fill in the default acceptance
+ */
+export function normalizeVersionOptions(
+  option: number | ProtocolVersionOptions | { version: number | undefined }
+): { version: number; accept: VersionAcceptance } {
+  const options = typeof option === "number" ? { version: option } : option;
+  const version = options.version;
+  if (version === undefined || !Number.isInteger(version) || version < 0 || version > 0xffffffff) {
+    throw ClavisError.config("protocolVersion must be a u32; declare one on the codec with `version`");
+  }
+  const accept = "accept" in options ? options.accept : undefined;
+  return { version, accept: accept ?? ((peer) => peer === version) };
+}
+
+/**
+ * Whether `peerVersion` is acceptable
+ */
+export function acceptsVersion(accept: VersionAcceptance, peerVersion: number): boolean {
+  return typeof accept === "function" ? accept(peerVersion) : peerVersion >= accept.min && peerVersion <= accept.max;
+}
+
+/** Encode this side's version offer */
+export function encodeVersionOffer(version: number): Uint8Array {
+  return encode(VersionPacketKind.Offer, version);
+}
+
+/** Decode the peer's version offer */
+export function decodeVersionOffer(packet: Uint8Array): number {
+  return decode(packet, [VersionPacketKind.Offer]).version;
+}
+
+/** Encode this side's verdict on the peer's version */
+export function encodeVersionVerdict(accepted: boolean, version: number): Uint8Array {
+  return encode(accepted ? VersionPacketKind.Accept : VersionPacketKind.Reject, version);
+}
+
+/** Decode the peer's verdict on this side's version; true if accepted */
+export function decodeVersionVerdict(packet: Uint8Array): boolean {
+  return decode(packet, [VersionPacketKind.Accept, VersionPacketKind.Reject]).kind === VersionPacketKind.Accept;
+}
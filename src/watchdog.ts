@@ -0,0 +1,160 @@
+/**
+ * Stall watchdog
+ * Spots reads and writes that stopped making progress, for debugging hung
+ * servers
+ *
+ * A connection hangs without failing when the application stops reading
+ * from it, because a handler deadlocked or a loop forgot an `await`, or
+ * when a write never completes because the peer stopped reading in turn.
+ * Neither is an error the stream can raise: a timeout would close a
+ * connection that may yet recover. The watchdog only reports. It checks the
+ * stream every `thresholdMs / 2` and calls `onStall` with the connection's
+ * ID and a snapshot of its state, once per stall, so the report can land in
+ * a log next to whatever the handler was doing.
+ */
+
+import type { Clock, TimerHandle } from "./clock.js";
+
+/** Which side of the connection stopped making progress */
+export type StallDirection = "read" | "write";
+
+/**
+ * What the connection looked like when a stall was noticed
+ */
+export interface StallState {
+  /** Application packets sent and received so far */
+  packetsSent: number;
+  packetsReceived: number;
+  /** Bytes received from the transport that no read has taken yet */
+  unreadBytes: number;
+  /** Whether a read is in progress */
+  reading: boolean;
+  /** Reads waiting for the one in progress */
+  queuedReads: number;
+  /** Writes handed to the transport that it hasn't finished */
+  pendingWrites: number;
+  /** Bytes of sealed frames held by `writePacketBuffered()` */
+  bufferedWriteBytes: number;
+  /** Sequence numbers of the last frames sealed and opened; see `EncryptedStream.lastSentSequence` */
+  lastSentSequence: number | undefined;
+  lastReceivedSequence: number | undefined;
+  /** Whether `close()` was called, and whether the peer's close frame arrived */
+  writeClosed: boolean;
+  peerClosed: boolean;
+}
+
+/**
+ * One stall, as passed to `onStall`
+ *
+ * - `read`: bytes from the peer have waited `stalledMs` with no read to take them
+ * - `write`: a write has waited `stalledMs` on the transport
+ */
+export interface StallReport {
+  connectionId: string;
+  direction: StallDirection;
+  /** How long the stall had lasted when it was noticed, at least `thresholdMs` */
+  stalledMs: number;
+  state: StallState;
+}
+
+/**
+ * Options for the `watchdog` stream option
+ */
+export interface WatchdogOptions {
+  /** How long a read or write may go without progress before it is reported, in milliseconds */
+  thresholdMs: number;
+  /**
+   * Called once per stall; a stall that clears and comes back is reported
+   * again. Exceptions are swallowed, so the watchdog can't take the stream down.
+   */
+  onStall: (report: StallReport) => void;
+}
+
+/**
+ * What the watchdog reads off a stream on every check
+ */
+export interface StallProbe {
+  /** Whether bytes from the peer are buffered while no read is in progress */
+  readWaiting(): boolean;
+  /** When the oldest write still on the transport started, if any is */
+  oldestWriteAt(): number | undefined;
+  state(): StallState;
+}
+
+/**
+ * Periodic check of one stream; `EncryptedStream` runs one per connection
+ * when the `watchdog` option is set
+ */
+export class StallWatchdog {
+  private timer: TimerHandle | undefined;
+  private stopped = false;
+  /** When the current read stall was first seen */
+  private readWaitingSince: number | undefined;
+  private readReported = false;
+  private writeReported = false;
+
+  constructor(
+    private readonly connectionId: string,
+    private readonly options: WatchdogOptions,
+    private readonly clock: Clock,
+    private readonly probe: StallProbe
+  ) {}
+
+  /** Start checking; does nothing once stopped */
+  start(): void {
+    if (this.stopped || this.timer) return;
+    this.schedule();
+  }
+
+  /** Stop checking for good */
+  stop(): void {
+    this.stopped = true;
+    this.timer?.cancel();
+    this.timer = undefined;
+  }
+
+  /** Run one check now, as the timer does */
+  check(): void {
+    const now = this.clock.now();
+    const { thresholdMs } = this.options;
+
+    if (this.probe.readWaiting()) {
+      this.readWaitingSince ??= now;
+      const stalledMs = now - this.readWaitingSince;
+      if (stalledMs >= thresholdMs && !this.readReported) {
+        this.readReported = true;
+        this.report("read", stalledMs);
+      }
+    } else {
+      this.readWaitingSince = undefined;
+      this.readReported = false;
+    }
+
+    const oldest = this.probe.oldestWriteAt();
+    if (oldest !== undefined && now - oldest >= thresholdMs) {
+      if (!this.writeReported) {
+        this.writeReported = true;
+        this.report("write", now - oldest);
+      }
+    } else {
+      this.writeReported = false;
+    }
+  }
+
+  private schedule(): void {
+    this.timer = this.clock.setTimer(() => {
+      this.timer = undefined;
+      if (this.stopped) return;
+      this.check();
+      this.schedule();
+    }, this.options.thresholdMs / 2);
+  }
+
+  private report(direction: StallDirection, stalledMs: number): void {
+    try {
+      this.options.onStall({ connectionId: this.connectionId, direction, stalledMs, state: this.probe.state() });
+    } catch {
+      // A broken diagnostic hook must not hurt the connection it watches
+    }
+  }
+}
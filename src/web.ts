@@ -0,0 +1,313 @@
+/**
+ * Web transports
+ * A clavis client for browsers, over a WebSocket or a pair of web streams
+ * (a WebTransport bidirectional stream, for example)
+ *
+ * `EncryptedStream` needs Node's `net` and `stream` modules, which browsers
+ * don't have. `WebClient` runs the same connection on `ClavisConnection`
+ * instead, so this module and everything it loads work without them. The
+ * bytes are those of a stream with default options; the server side wraps
+ * each accepted WebSocket with `transportStream()` and hands it to
+ * `EncryptedStream.new()` as usual.
+ */
+
+import type { Duplex } from "stream";
+import { ClavisConnection, type ClavisConnectionOptions } from "./connection.js";
+import { ClavisError, StreamError } from "./error.js";
+import type { HandshakeTimings } from "./handshake.js";
+import type { PacketTrait } from "./protocol.js";
+import { systemClock } from "./clock.js";
+
+/**
+ * An ordered, reliable byte transport that delivers bytes in chunks of any size
+ */
+export interface ByteTransport {
+  /** Send bytes to the peer */
+  send(bytes: Uint8Array): void | Promise<void>;
+  /** Register the one handler for received bytes */
+  onBytes(handler: (bytes: Uint8Array) => void): void;
+  /** Register the one handler for the end of the transport, with its error if it failed */
+  onClose(handler: (error?: Error) => void): void;
+  /** Close the transport */
+  close(): void;
+}
+
+/**
+ * The parts of a WebSocket the transport uses; browsers', Node's, Bun's
+ * and the `ws` package's sockets all fit
+ */
+export interface WebSocketLike {
+  binaryType: string;
+  readonly readyState: number;
+  send(data: Uint8Array): void;
+  close(): void;
+  // The event is left untyped so every runtime's event classes fit; only `data` of messages is read
+  addEventListener(type: "open" | "message" | "close" | "error", listener: (event: any) => void): void;
+}
+
+const WEBSOCKET_OPEN = 1;
+
+function toClavisError(error: unknown): ClavisError {
+  if (error instanceof ClavisError) return error;
+  return ClavisError.stream(StreamError.io(error instanceof Error ? error : new Error(String(error))));
+}
+
+/**
+ * Carry a connection over a WebSocket, each send as one binary message.
+ * The socket doesn't have to be open yet; sends wait for it.
+ */
+export function webSocketTransport(socket: WebSocketLike): ByteTransport {
+  socket.binaryType = "arraybuffer";
+  const opened = socket.readyState === WEBSOCKET_OPEN
+    ? Promise.resolve()
+    : new Promise<void>((resolve, reject) => {
+      socket.addEventListener("open", () => resolve());
+      socket.addEventListener("close", () => reject(ClavisError.stream(StreamError.connectionClosed("WebSocket closed before it opened"))));
+    });
+  // Nobody may be sending yet; a failed open is reported through onClose
+  opened.catch(() => undefined);
+  return {
+    send: async (bytes) => {
+      await opened;
+      socket.send(bytes);
+    },
+    onBytes(handler) {
+      socket.addEventListener("message", (event: { data: unknown }) => {
+        const data = event.data;
+        if (data instanceof ArrayBuffer) handler(new Uint8Array(data));
+        else if (data instanceof Uint8Array) handler(data);
+      });
+    },
+    onClose(handler) {
+      let failed = false;
+      socket.addEventListener("error", () => {
+        failed = true;
+      });
+      socket.addEventListener("close", () =>
+        handler(failed ? ClavisError.stream(StreamError.connectionReset()) : undefined)
+      );
+    },
+    close: () => socket.close(),
+  };
+}
+
+/**
+ * Carry a connection over a pair of web streams, such as the `readable` and
+ * `writable` of a WebTransport bidirectional stream
+ */
+export function webStreamTransport(stream: {
+  readable: ReadableStream<Uint8Array>;
+  writable: WritableStream<Uint8Array>;
+}): ByteTransport {
+  const writer = stream.writable.getWriter();
+  const reader = stream.readable.getReader();
+  let onBytes: ((bytes: Uint8Array) => void) | undefined;
+  let onClose: ((error?: Error) => void) | undefined;
+  let started = false;
+  const pump = async () => {
+    try {
+      for (;;) {
+        const { value, done } = await reader.read();
+        if (done) break;
+        onBytes?.(value);
+      }
+      onClose?.();
+    } catch (error) {
+      onClose?.(error instanceof Error ? error : new Error(String(error)));
+    }
+  };
+  const start = () => {
+    if (started || !onBytes || !onClose) return;
+    started = true;
+    void pump();
+  };
+  return {
+    send: (bytes) => writer.write(bytes),
+    onBytes(handler) {
+      onBytes = handler;
+      start();
+    },
+    onClose(handler) {
+      onClose = handler;
+      start();
+    },
+    close() {
+      reader.cancel().catch(() => undefined);
+      writer.close().catch(() => undefined);
+    },
+  };
+}
+
+/**
+ * Options for `WebClient.connect`
+ */
+export interface WebClientOptions extends ClavisConnectionOptions {
+  /** Give up on a handshake that hasn't finished within this many milliseconds (default: off) */
+  handshakeTimeoutMs?: number | undefined;
+}
+
+interface Waiter {
+  resolve(packet: Uint8Array): void;
+  reject(error: ClavisError): void;
+}
+
+/**
+ * An encrypted connection over a `ByteTransport`, for runtimes without Node's streams
+ *
+ * @example
+ * ```typescript
+ * const client = await WebClient.connect(webSocketTransport(new WebSocket("wss://example.com/clavis")), { psk });
+ * await client.send(new RawPacket(Codec.encode("Join")));
+ * for await (const packet of client) handle(Codec.decode(packet));
+ * ```
+ */
+export class WebClient {
+  private readonly waiters: Waiter[] = [];
+  private failure: ClavisError | undefined;
+  private established!: () => void;
+  private failed!: (error: ClavisError) => void;
+
+  private constructor(
+    private readonly transport: ByteTransport,
+    private readonly connection: ClavisConnection
+  ) {}
+
+  /**
+   * Run the handshake over `transport`; resolves once packets can be sent
+   */
+  static connect(transport: ByteTransport, options: WebClientOptions = {}): Promise<WebClient> {
+    const client = new WebClient(transport, new ClavisConnection(options));
+    const ready = new Promise<WebClient>((resolve, reject) => {
+      client.established = () => resolve(client);
+      client.failed = reject;
+    });
+    const timeoutMs = options.handshakeTimeoutMs;
+    if (timeoutMs !== undefined) {
+      const timer = (options.clock ?? systemClock).setTimer(
+        () => client.fail(ClavisError.stream(StreamError.handshakeTimeout(timeoutMs))),
+        timeoutMs
+      );
+      ready.then(() => timer.cancel(), () => timer.cancel());
+    }
+    transport.onBytes((bytes) => client.receiveBytes(bytes));
+    transport.onClose((error) =>
+      client.fail(error
+        ? toClavisError(error)
+        : ClavisError.stream(client.connection.established ? StreamError.eof() : StreamError.handshakeFailed("Connection closed during the handshake")))
+    );
+    client.flush().catch((error: unknown) => client.fail(toClavisError(error)));
+    return ready;
+  }
+
+  /** How long each stage of the handshake took */
+  get handshakeTimings(): HandshakeTimings {
+    return this.connection.handshakeResult.timings;
+  }
+
+  /** Encrypt and send one packet */
+  async send(packet: PacketTrait): Promise<void> {
+    if (this.failure) throw this.failure;
+    this.connection.send(packet);
+    await this.flush();
+  }
+
+  /**
+   * Next packet from the server. Rejects with a `Closed` stream error once
+   * the server closed gracefully, or with whatever ended the connection.
+   */
+  receive(): Promise<Uint8Array> {
+    const packet = this.connection.receive();
+    if (packet) return Promise.resolve(packet);
+    if (this.failure) return Promise.reject(this.failure);
+    return new Promise((resolve, reject) => this.waiters.push({ resolve, reject }));
+  }
+
+  /** Packets until the server closes; other failures are thrown */
+  async *[Symbol.asyncIterator](): AsyncIterableIterator<Uint8Array> {
+    for (;;) {
+      try {
+        yield await this.receive();
+      } catch (error) {
+        const cause = (error as ClavisError).cause;
+        if (cause instanceof StreamError && cause.isConnectionClosed()) return;
+        throw error;
+      }
+    }
+  }
+
+  /** Send a close frame, then close the transport */
+  async close(): Promise<void> {
+    if (!this.failure && this.connection.established) {
+      this.connection.close();
+      await this.flush().catch(() => undefined);
+    }
+    this.fail(ClavisError.stream(StreamError.closed()));
+  }
+
+  private receiveBytes(bytes: Uint8Array): void {
+    if (this.failure) return;
+    try {
+      this.connection.feedBytes(bytes);
+    } catch (error) {
+      // The responder's last handshake message may still be queued
+      this.flush().catch(() => undefined);
+      this.fail(toClavisError(error));
+      return;
+    }
+    this.flush().catch((error: unknown) => this.fail(toClavisError(error)));
+    if (this.connection.established) this.established();
+    for (let waiter = this.waiters[0]; waiter; waiter = this.waiters[0]) {
+      const packet = this.connection.receive();
+      if (!packet) break;
+      this.waiters.shift();
+      waiter.resolve(packet);
+    }
+    if (this.connection.closed) this.fail(ClavisError.stream(StreamError.closed()));
+  }
+
+  private async flush(): Promise<void> {
+    const output = this.connection.takeOutput();
+    if (output.length > 0) await this.transport.send(output);
+  }
+
+  /** End the connection: pending and later receives fail with `error` once the queue is empty */
+  private fail(error: ClavisError): void {
+    if (this.failure) return;
+    this.failure = error;
+    this.failed(error);
+    this.transport.close();
+    for (const waiter of this.waiters.splice(0)) waiter.reject(error);
+  }
+}
+
+/**
+ * A Node duplex stream over a `ByteTransport`, so a server can run
+ * `EncryptedStream.new()` on an accepted WebSocket
+ *
+ * @example
+ * ```typescript
+ * wss.on("connection", async (socket) => {
+ *   const stream = await EncryptedStream.new(await transportStream(webSocketTransport(socket)), { psk });
+ * });
+ * ```
+ */
+export async function transportStream(transport: ByteTransport): Promise<Duplex> {
+  const { Duplex } = await import("stream");
+  const duplex = new Duplex({
+    read() {},
+    write(chunk: Uint8Array, _encoding: BufferEncoding, callback: (error?: Error | null) => void) {
+      Promise.resolve(transport.send(new Uint8Array(chunk))).then(() => callback(), callback);
+    },
+    final(callback: () => void) {
+      transport.close();
+      callback();
+    },
+    destroy(error: Error | null, callback: (error: Error | null) => void) {
+      transport.close();
+      callback(error);
+    },
+  });
+  transport.onBytes((bytes) => duplex.push(bytes));
+  transport.onClose((error) => (error ? duplex.destroy(error) : duplex.push(null)));
+  return duplex;
+}
@@ -0,0 +1,243 @@
+/**
+ * Wire format specification
+ * Derives a byte-level description of the handshake and frame formats by
+ * running the real implementation and recording what it puts on the wire
+ */
+
+import { performHandshake } from "./handshake.js";
+import { XChaCha20Poly1305Cipher } from "./crypto.js";
+import { encodeFrame } from "./frame.js";
+
+/**
+ * A contiguous field on the wire
+ */
+export interface WireField {
+  name: string;
+  /** Offset from the start of the message, in bytes */
+  offset: number;
+  /** Length in bytes, or undefined for variable-length fields */
+  length: number | undefined;
+  encoding: string;
+  description: string;
+}
+
+/**
+ * One handshake message, in the order it is written
+ */
+export interface HandshakeStep {
+  /** Who sends the message */
+  sender: "both" | "initiator" | "responder";
+  name: string;
+  length: number;
+  description: string;
+  /** Only sent when a pre-shared key is configured */
+  pskOnly: boolean;
+}
+
+/**
+ * Complete wire specification
+ */
+export interface WireSpec {
+  handshake: {
+    steps: HandshakeStep[];
+    roleRule: string;
+    transcript: string;
+    keyDerivation: string;
+  };
+  frame: {
+    fields: WireField[];
+    /** Bytes added to each packet on the wire */
+    overhead: number;
+    /** Bytes the AEAD tag adds to the plaintext */
+    tagLength: number;
+  };
+}
+
+interface RecordedWrite {
+  side: 0 | 1;
+  bytes: Uint8Array;
+}
+
+/**
+ * In-memory duplex pair that records every write
+ */
+function recordingPair(log: RecordedWrite[]) {
+  const queues: [Uint8Array[], Uint8Array[]] = [[], []];
+  const waiters: [(() => void) | undefined, (() => void) | undefined] = [undefined, undefined];
+
+  const side = (self: 0 | 1) => {
+    const peer = (1 - self) as 0 | 1;
+    return {
+      async write(data: Uint8Array): Promise<void> {
+        log.push({ side: self, bytes: data.slice() });
+        queues[peer].push(data.slice());
+        const wake = waiters[peer];
+        waiters[peer] = undefined;
+        wake?.();
+      },
+      async read(length: number): Promise<Uint8Array> {
+        const out = new Uint8Array(length);
+        let filled = 0;
+        while (filled < length) {
+          const chunk = queues[self][0];
+          if (!chunk) {
+            await new Promise<void>((resolve) => {
+              waiters[self] = resolve;
+            });
+            continue;
+          }
+          const take = Math.min(chunk.length, length - filled);
+          out.set(chunk.subarray(0, take), filled);
+          filled += take;
+          if (take === chunk.length) {
+            queues[self].shift();
+          } else {
+            queues[self][0] = chunk.subarray(take);
+          }
+        }
+        return out;
+      },
+    };
+  };
+
+  return [side(0), side(1)] as const;
+}
+
+function greater(a: Uint8Array, b: Uint8Array): boolean {
+  for (let i = 0; i < a.length; i++) {
+    if (a[i]! !== b[i]!) return a[i]! > b[i]!;
+  }
+  return false;
+}
+
+const STEP_NAMES = ["nonce", "public_key", "mac"] as const;
+
+const STEP_DESCRIPTIONS: Record<(typeof STEP_NAMES)[number], string> = {
+  nonce: "Random bytes used only to pick roles",
+  public_key: "Ephemeral X25519 public key",
+  mac: "HMAC-SHA256(psk, transcript)",
+};
+
+/**
+ * Run a handshake over a recording pair and describe its messages
+ */
+async function traceHandshake(psk: Uint8Array | undefined): Promise<Array<Omit<HandshakeStep, "pskOnly">>> {
+  const log: RecordedWrite[] = [];
+  const [a, b] = recordingPair(log);
+  await Promise.all([performHandshake(a, psk), performHandshake(b, psk)]);
+
+  // Each side's first write is its role nonce
+  const nonces = [0, 1].map((side) => log.find((w) => w.side === side)!.bytes);
+  const initiator: 0 | 1 = greater(nonces[0]!, nonces[1]!) ? 0 : 1;
+
+  const counts = [0, 0];
+  const steps: Array<Omit<HandshakeStep, "pskOnly">> = [];
+  for (const write of log) {
+    const index = counts[write.side]!;
+    counts[write.side] = index + 1;
+    const name = STEP_NAMES[index]!;
+    const sender = write.side === initiator ? "initiator" : "responder";
+    const previous = steps.find((s) => s.name === name);
+    if (previous && name === "nonce") {
+      previous.sender = "both";
+      continue;
+    }
+    steps.push({ sender, name, length: write.bytes.length, description: STEP_DESCRIPTIONS[name] });
+  }
+  return steps;
+}
+
+/**
+ * Generate the wire specification from the running implementation
+ */
+export async function generateWireSpec(): Promise<WireSpec> {
+  const plain = await traceHandshake(undefined);
+  const withPsk = await traceHandshake(new Uint8Array(32).fill(7));
+  const steps: HandshakeStep[] = withPsk.map((step) => ({
+    ...step,
+    pskOnly: !plain.some((s) => s.name === step.name && s.sender === step.sender),
+  }));
+
+  // Measure a real frame to get nonce, tag and header sizes
+  const plaintext = new Uint8Array(10);
+  const nonce = XChaCha20Poly1305Cipher.generateNonce();
+  const ciphertext = new XChaCha20Poly1305Cipher(new Uint8Array(32)).encrypt(nonce, plaintext);
+  const frame = encodeFrame(nonce, ciphertext);
+  const headerLength = frame.length - nonce.length - ciphertext.length;
+  const tagLength = ciphertext.length - plaintext.length;
+
+  return {
+    handshake: {
+      steps,
+      roleRule: "The side whose nonce is lexicographically greater is the initiator",
+      transcript: "initiator public key || responder public key (64 bytes)",
+      keyDerivation:
+        "HKDF-SHA256(ikm = X25519 shared secret, salt = SHA-256(transcript)); " +
+        'initiator encrypts with info "enc" and decrypts with info "dec", the responder the reverse',
+    },
+    frame: {
+      fields: [
+        {
+          name: "length",
+          offset: 0,
+          length: headerLength,
+          encoding: `u${headerLength * 8} little-endian`,
+          description: "Length of the ciphertext field (excludes the nonce); the top bit marks a control frame",
+        },
+        {
+          name: "nonce",
+          offset: headerLength,
+          length: nonce.length,
+          encoding: "random bytes",
+          description: "XChaCha20-Poly1305 nonce, fresh per frame",
+        },
+        {
+          name: "ciphertext",
+          offset: headerLength + nonce.length,
+          length: undefined,
+          encoding: "XChaCha20-Poly1305",
+          description: `Encrypted packet followed by a ${tagLength}-byte Poly1305 tag`,
+        },
+      ],
+      overhead: frame.length - plaintext.length,
+      tagLength,
+    },
+  };
+}
+
+/**
+ * Render a wire specification as Markdown
+ */
+export function formatWireSpec(spec: WireSpec): string {
+  const lines: string[] = [
+    "# Clavis wire format",
+    "",
+    "## Handshake",
+    "",
+    "| # | Sender | Message | Length | PSK only | Description |",
+    "|---|--------|---------|--------|----------|-------------|",
+  ];
+  spec.handshake.steps.forEach((step, i) => {
+    lines.push(
+      `| ${i + 1} | ${step.sender} | ${step.name} | ${step.length} | ${step.pskOnly ? "yes" : "no"} | ${step.description} |`
+    );
+  });
+  lines.push(
+    "",
+    `- Roles: ${spec.handshake.roleRule}`,
+    `- Transcript: ${spec.handshake.transcript}`,
+    `- Keys: ${spec.handshake.keyDerivation}`,
+    "",
+    "## Frame",
+    "",
+    "| Offset | Length | Field | Encoding | Description |",
+    "|--------|--------|-------|----------|-------------|"
+  );
+  for (const field of spec.frame.fields) {
+    lines.push(
+      `| ${field.offset} | ${field.length ?? "length"} | ${field.name} | ${field.encoding} | ${field.description} |`
+    );
+  }
+  lines.push("", `Per-packet overhead: ${spec.frame.overhead} bytes (${spec.frame.tagLength}-byte tag).`, "");
+  return lines.join("\n");
+}
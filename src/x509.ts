@@ -0,0 +1,151 @@
+/**
+ * X.509 identities
+ * Lets organizations with an existing PKI authenticate peers with DER
+ * certificate chains instead of clavis identity certificates
+ *
+ * Chains are checked with the runtime's own X.509 parser: every certificate
+ * must be within its validity period and signed by the next one, every issuer
+ * must be a CA, and the chain must end at (or be issued by) one of the
+ * configured roots. The leaf's first subject alternative name becomes the
+ * peer's identity. Name constraints, policies and revocation are not checked.
+ */
+
+import { X509Certificate, createPrivateKey, sign, verify, type KeyObject } from "crypto";
+import { ClavisError, CryptoError } from "./error.js";
+import { checkValidityPeriod } from "./validity.js";
+import type { IdentitySigner } from "./key-provider.js";
+
+/**
+ * A certificate chain and the private key of its leaf
+ */
+export interface X509Credentials {
+  /** DER certificates, leaf first; the root may be left out */
+  chain: readonly Uint8Array[];
+  /**
+   * Leaf private key, as a KeyObject or PEM/DER-encoded PKCS#8, or a signer
+   * keeping it in a keystore. A signer returns what `crypto.sign()` would:
+   * SHA-256 with the key's default scheme, or pure Ed25519/Ed448.
+   */
+  privateKey: KeyObject | string | Uint8Array | IdentitySigner;
+}
+
+/** Longest chain accepted from a peer, leaf included */
+export const MAX_X509_CHAIN_LENGTH = 8;
+
+/**
+ * Parse a DER certificate
+ */
+export function parseX509Certificate(der: Uint8Array): X509Certificate {
+  try {
+    return new X509Certificate(der);
+  } catch {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("Malformed X.509 certificate"));
+  }
+}
+
+function checkValidity(certificate: X509Certificate, now: number, clockSkewMs: number): void {
+  checkValidityPeriod(
+    `Certificate ${certificate.subject}`,
+    new Date(certificate.validFrom).getTime(),
+    new Date(certificate.validTo).getTime(),
+    now,
+    clockSkewMs
+  );
+}
+
+function issuedBy(certificate: X509Certificate, issuer: X509Certificate): boolean {
+  return certificate.checkIssued(issuer) && certificate.verify(issuer.publicKey);
+}
+
+/**
+ * Check a DER chain (leaf first) against trusted DER roots at `now`
+ * (milliseconds since the epoch), give or take `clockSkewMs`, and return the leaf
+ */
+export function verifyX509Chain(
+  chain: readonly Uint8Array[],
+  roots: readonly Uint8Array[],
+  now: number = Date.now(),
+  clockSkewMs: number = 0
+): X509Certificate {
+  if (chain.length === 0 || chain.length > MAX_X509_CHAIN_LENGTH) {
+    throw ClavisError.crypto(
+      CryptoError.authenticationFailure(`Certificate chains must hold 1 to ${MAX_X509_CHAIN_LENGTH} certificates`)
+    );
+  }
+  const certificates = chain.map(parseX509Certificate);
+  const anchors = roots.map(parseX509Certificate);
+
+  for (let i = 0; i < certificates.length; i++) {
+    const certificate = certificates[i]!;
+    checkValidity(certificate, now, clockSkewMs);
+    if (i > 0 && !certificate.ca) {
+      throw ClavisError.crypto(CryptoError.authenticationFailure(`${certificate.subject} is not a CA`));
+    }
+    const anchor = anchors.find((root) =>
+      root.fingerprint256 === certificate.fingerprint256 || issuedBy(certificate, root)
+    );
+    if (anchor) {
+      checkValidity(anchor, now, clockSkewMs);
+      return certificates[0]!;
+    }
+    const issuer = certificates[i + 1];
+    if (!issuer || !issuedBy(certificate, issuer)) {
+      throw ClavisError.crypto(
+        CryptoError.authenticationFailure(`${certificate.subject} does not chain to a trusted root`)
+      );
+    }
+  }
+  // Unreachable: the last certificate either meets an anchor or has no issuer
+  throw ClavisError.crypto(CryptoError.authenticationFailure("Certificate chain is not trusted"));
+}
+
+/**
+ * Identity named by a certificate: the value of its first subject
+ * alternative name, e.g. a SPIFFE URI or a DNS name
+ */
+export function x509Identity(certificate: X509Certificate): string {
+  const first = certificate.subjectAltName?.split(",")[0]?.trim();
+  const separator = first?.indexOf(":") ?? -1;
+  if (!first || separator < 0) {
+    throw ClavisError.crypto(
+      CryptoError.authenticationFailure(`Certificate ${certificate.subject} has no subject alternative name`)
+    );
+  }
+  return first.slice(separator + 1);
+}
+
+/** Ed25519 and Ed448 keys sign the message itself; other key types hash it with SHA-256 */
+function digestFor(key: KeyObject): string | null {
+  const type = key.asymmetricKeyType;
+  return type === "ed25519" || type === "ed448" ? null : "sha256";
+}
+
+/**
+ * Sign `message` with a leaf private key
+ */
+export function x509Sign(privateKey: X509Credentials["privateKey"], message: Uint8Array): Uint8Array {
+  if (typeof privateKey === "object" && "sign" in privateKey) {
+    return privateKey.sign(message);
+  }
+  try {
+    const key = typeof privateKey === "string"
+      ? createPrivateKey(privateKey)
+      : privateKey instanceof Uint8Array
+        ? createPrivateKey({ key: Buffer.from(privateKey), format: "der", type: "pkcs8" })
+        : privateKey;
+    return new Uint8Array(sign(digestFor(key), message, key));
+  } catch {
+    throw ClavisError.crypto(CryptoError.invalidKeyMaterial("Malformed or unsupported X.509 private key"));
+  }
+}
+
+/**
+ * Check a signature made with `x509Sign` against a certificate's public key
+ */
+export function x509Verify(certificate: X509Certificate, message: Uint8Array, signature: Uint8Array): boolean {
+  try {
+    return verify(digestFor(certificate.publicKey), message, certificate.publicKey, signature);
+  } catch {
+    return false;
+  }
+}
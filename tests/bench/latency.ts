@@ -0,0 +1,86 @@
+/**
+ * Latency benchmark - write and round-trip latency percentiles
+ *
+ * Bounces small packets between two encrypted streams over TCP loopback,
+ * one at a time, and reports how long `writePacket()` took to resolve and
+ * how long the echo took to come back, at p50, p99 and p99.9, for the
+ * default write path and for `ultraLowLatency`.
+ *
+ *   bun tests/bench/latency.ts [round trips] [size]
+ *
+ * Defaults: 50000 round trips of 32 bytes, after 5000 warm-up round trips.
+ */
+
+import { connect, createServer, type Socket } from "net";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+
+const roundTrips = Number(process.argv[2] ?? 50_000);
+const size = Number(process.argv[3] ?? 32);
+const WARM_UP = 5_000;
+
+async function tcpPair(options: EncryptedStreamOptions): Promise<[EncryptedStream, EncryptedStream, () => void]> {
+  const server = createServer();
+  await new Promise<void>((resolve) => server.listen(0, "127.0.0.1", resolve));
+  const address = server.address();
+  if (!address || typeof address === "string") throw new Error("server has no TCP address");
+
+  const accepted = new Promise<Socket>((resolve) => server.once("connection", resolve));
+  const client = await new Promise<Socket>((resolve, reject) => {
+    const socket = connect(address.port, "127.0.0.1", () => resolve(socket));
+    socket.once("error", reject);
+  });
+  const serverSocket = await accepted;
+
+  const [left, right] = await Promise.all([
+    EncryptedStream.new(client, options),
+    EncryptedStream.new(serverSocket, options),
+  ]);
+  return [left, right, () => {
+    client.destroy();
+    serverSocket.destroy();
+    server.close();
+  }];
+}
+
+function percentile(sorted: Float64Array, p: number): string {
+  const value = sorted[Math.min(sorted.length - 1, Math.floor(sorted.length * p))]!;
+  return `${(value * 1000).toFixed(1)}us`.padStart(10);
+}
+
+async function run(name: string, options: EncryptedStreamOptions): Promise<void> {
+  const [sender, echoer, close] = await tcpPair(options);
+  const packet = new RawPacket(new Uint8Array(size).fill(0x5a));
+  const total = WARM_UP + roundTrips;
+
+  void (async () => {
+    for (let i = 0; i < total; i++) {
+      await echoer.writePacket(new RawPacket(await echoer.readPacketView()));
+    }
+  })();
+
+  const writes = new Float64Array(roundTrips);
+  const trips = new Float64Array(roundTrips);
+  for (let i = 0; i < total; i++) {
+    const started = performance.now();
+    await sender.writePacket(packet);
+    const written = performance.now();
+    await sender.readPacketView();
+    if (i >= WARM_UP) {
+      writes[i - WARM_UP] = written - started;
+      trips[i - WARM_UP] = performance.now() - started;
+    }
+  }
+  close();
+
+  writes.sort();
+  trips.sort();
+  console.log(
+    `${name.padEnd(18)} write ${percentile(writes, 0.5)}${percentile(writes, 0.99)}${percentile(writes, 0.999)}` +
+      `   round trip ${percentile(trips, 0.5)}${percentile(trips, 0.99)}${percentile(trips, 0.999)}`
+  );
+}
+
+console.log(`${roundTrips} round trips of ${size} bytes over TCP loopback (p50, p99, p99.9)`);
+await run("default", {});
+await run("ultraLowLatency", { ultraLowLatency: true });
@@ -0,0 +1,91 @@
+/**
+ * Throughput benchmark - packets per second through the frame path
+ *
+ * Sends fixed-size packets from one encrypted stream to another and reports
+ * packets and bytes per second, for single writes and batched writes, over
+ * an in-memory pair (pure crypto and framing cost) and over TCP loopback
+ * (adds the runtime's socket layer).
+ *
+ *   bun tests/bench/throughput.ts [packets] [size]
+ *
+ * Defaults: 200000 packets of 64 bytes.
+ */
+
+import { connect, createServer, type Socket } from "net";
+import { EncryptedStream } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createStreamPair } from "../helpers/test-utils.js";
+
+const packets = Number(process.argv[2] ?? 200_000);
+const size = Number(process.argv[3] ?? 64);
+const BATCH = 64;
+
+type Pair = [EncryptedStream, EncryptedStream, () => void];
+
+async function memoryPair(): Promise<Pair> {
+  const [a, b] = await createStreamPair();
+  const [left, right] = await Promise.all([EncryptedStream.new(a), EncryptedStream.new(b)]);
+  return [left, right, () => {}];
+}
+
+async function tcpPair(): Promise<Pair> {
+  const server = createServer();
+  await new Promise<void>((resolve) => server.listen(0, "127.0.0.1", resolve));
+  const address = server.address();
+  if (!address || typeof address === "string") throw new Error("server has no TCP address");
+
+  const accepted = new Promise<Socket>((resolve) => server.once("connection", resolve));
+  const client = await new Promise<Socket>((resolve, reject) => {
+    const socket = connect(address.port, "127.0.0.1", () => resolve(socket));
+    socket.once("error", reject);
+  });
+  const serverSocket = await accepted;
+  client.setNoDelay(true);
+  serverSocket.setNoDelay(true);
+
+  const [left, right] = await Promise.all([EncryptedStream.new(client), EncryptedStream.new(serverSocket)]);
+  return [left, right, () => {
+    client.destroy();
+    serverSocket.destroy();
+    server.close();
+  }];
+}
+
+async function run(name: string, makePair: () => Promise<Pair>, batched: boolean): Promise<void> {
+  const [sender, receiver, close] = await makePair();
+  const packet = new RawPacket(new Uint8Array(size).fill(0x5a));
+
+  const started = performance.now();
+  const sending = (async () => {
+    for (let sent = 0; sent < packets; ) {
+      if (batched) {
+        const count = Math.min(BATCH, packets - sent);
+        await sender.writePackets(Array.from({ length: count }, () => packet));
+        sent += count;
+      } else {
+        await sender.writePacket(packet);
+        sent++;
+      }
+    }
+  })();
+
+  let received = 0;
+  while (received < packets) {
+    received += (await receiver.readPackets()).length;
+  }
+  await sending;
+  const seconds = (performance.now() - started) / 1000;
+  close();
+
+  const rate = packets / seconds;
+  console.log(
+    `${name.padEnd(24)} ${Math.round(rate).toLocaleString().padStart(12)} packets/s ` +
+      `${((rate * size) / 1024 / 1024).toFixed(1).padStart(8)} MiB/s`
+  );
+}
+
+console.log(`${packets} packets of ${size} bytes`);
+await run("memory, single writes", memoryPair, false);
+await run("memory, batched writes", memoryPair, true);
+await run("tcp, single writes", tcpPair, false);
+await run("tcp, batched writes", tcpPair, true);
@@ -0,0 +1,93 @@
+/**
+ * Version compatibility matrix
+ *
+ * Builds the Rust interop peer against every clavis version listed in
+ * versions.json, then runs the interop harness with all of them plus the
+ * current Rust and JS peers. Every peer meets every other peer as both
+ * client and server, so old-client/new-server and new-client/old-server
+ * handshakes and packet exchange are checked for each pair.
+ *
+ * Each entry names a clavis source: `version` (crates.io), or `git` with an
+ * optional `tag`, `branch` or `rev`. Add an entry whenever a release is cut.
+ *
+ *   bun tests/compat/matrix.ts
+ */
+
+import { mkdirSync, readFileSync, writeFileSync } from "fs";
+import { join } from "path";
+
+interface VersionEntry {
+  name: string;
+  version?: string;
+  git?: string;
+  tag?: string;
+  branch?: string;
+  rev?: string;
+}
+
+const root = join(import.meta.dir, "..", "..");
+const binaries = join(root, "tests", "rust-binaries");
+const buildRoot = join(binaries, "target", "compat");
+
+function dependency(entry: VersionEntry): string {
+  if (entry.version) {
+    return `clavis = "${entry.version}"`;
+  }
+  if (!entry.git) {
+    throw new Error(`Version ${entry.name} needs either "version" or "git"`);
+  }
+  const pin = entry.tag ? `, tag = "${entry.tag}"` : entry.rev ? `, rev = "${entry.rev}"` : entry.branch ? `, branch = "${entry.branch}"` : "";
+  return `clavis = { git = "${entry.git}"${pin} }`;
+}
+
+function manifest(entry: VersionEntry): string {
+  return `[package]
+name = "clavis-compat-${entry.name}"
+version = "0.1.0"
+edition = "2021"
+
+[[bin]]
+name = "interop-peer"
+path = "${join(binaries, "interop-peer", "src", "main.rs")}"
+
+[dependencies]
+${dependency(entry)}
+tokio = { version = "1.0", features = ["full"] }
+serde = { version = "1.0", features = ["derive"] }
+serde_json = "1.0"
+
+[workspace]
+`;
+}
+
+async function run(command: string[], cwd: string, env: Record<string, string> = {}): Promise<void> {
+  const proc = Bun.spawn(command, {
+    cwd,
+    env: { ...process.env, ...env },
+    stdout: "inherit",
+    stderr: "inherit",
+  });
+  const code = await proc.exited;
+  if (code !== 0) {
+    throw new Error(`${command.join(" ")} exited with ${code}`);
+  }
+}
+
+const versions: VersionEntry[] = JSON.parse(readFileSync(join(import.meta.dir, "versions.json"), "utf8"));
+const peers: string[] = [];
+
+for (const entry of versions) {
+  const dir = join(buildRoot, entry.name);
+  mkdirSync(dir, { recursive: true });
+  writeFileSync(join(dir, "Cargo.toml"), manifest(entry));
+
+  console.log(`Building interop peer against ${entry.name}`);
+  await run(["cargo", "build", "--release", "--quiet"], dir);
+  peers.push(`${entry.name}=${join(dir, "target", "release", "interop-peer")}`);
+}
+
+console.log("Building current binaries");
+await run(["cargo", "build", "--release", "--quiet"], binaries);
+await run(["cargo", "run", "--release", "--quiet", "--bin", "interop"], binaries, {
+  INTEROP_RUST_PEERS: peers.join(","),
+});
@@ -0,0 +1,136 @@
+/**
+ * Hostile peer helpers for negative testing
+ * Speaks just enough of the clavis handshake and framing to misbehave on purpose
+ */
+
+import type { Duplex } from "stream";
+import { performHandshake, type HandshakeResult } from "../../src/handshake.js";
+import { XChaCha20Poly1305Cipher } from "../../src/crypto.js";
+import { encodeFrame, sequenceNonce } from "../../src/frame.js";
+
+/**
+ * Raw byte access to one end of a duplex stream
+ */
+export class RawPeer {
+  private buffered: Uint8Array[] = [];
+  private waiter: (() => void) | undefined;
+  private ended = false;
+
+  constructor(public readonly stream: Duplex) {
+    stream.on("data", (chunk: Buffer) => {
+      this.buffered.push(new Uint8Array(chunk));
+      this.wake();
+    });
+    stream.on("end", () => {
+      this.ended = true;
+      this.wake();
+    });
+  }
+
+  write(data: Uint8Array): Promise<void> {
+    return new Promise((resolve, reject) => {
+      this.stream.write(Buffer.from(data), (error) => (error ? reject(error) : resolve()));
+    });
+  }
+
+  async read(length: number): Promise<Uint8Array> {
+    const out = new Uint8Array(length);
+    let filled = 0;
+    while (filled < length) {
+      const chunk = this.buffered[0];
+      if (!chunk) {
+        if (this.ended) throw new Error("peer closed the stream");
+        await new Promise<void>((resolve) => {
+          this.waiter = resolve;
+        });
+        continue;
+      }
+      const take = Math.min(chunk.length, length - filled);
+      out.set(chunk.subarray(0, take), filled);
+      filled += take;
+      if (take === chunk.length) {
+        this.buffered.shift();
+      } else {
+        this.buffered[0] = chunk.subarray(take);
+      }
+    }
+    return out;
+  }
+
+  private wake(): void {
+    const waiter = this.waiter;
+    this.waiter = undefined;
+    waiter?.();
+  }
+}
+
+/**
+ * A peer that completed a real handshake and can then craft frames by hand
+ */
+export class HostilePeer extends RawPeer {
+  private cipher: XChaCha20Poly1305Cipher | undefined;
+  private lastFrame: Uint8Array | undefined;
+  /** Frames built so far, which is the next frame's sequence number */
+  private sequence = 0;
+  private numbered = false;
+
+  /**
+   * Run an honest handshake so later frames reach the decryption path
+   */
+  async handshake(psk?: Uint8Array): Promise<HandshakeResult> {
+    const keys = await performHandshake(this, psk);
+    this.cipher = new XChaCha20Poly1305Cipher(keys.encKey);
+    return keys;
+  }
+
+  /** Answer the victim's `sequenceNumbers` announcement and number frames from then on */
+  async enableSequenceNumbers(): Promise<void> {
+    await this.sendFrame(new TextEncoder().encode("CLVS"));
+    this.numbered = true;
+  }
+
+  /** Leave a sequence number out, as if a frame had been dropped */
+  skipFrame(): void {
+    this.sequence++;
+  }
+
+  /** Build a correctly encrypted frame */
+  frame(plaintext: Uint8Array): Uint8Array {
+    if (!this.cipher) throw new Error("handshake first");
+    const sequence = this.sequence++;
+    const nonce = this.numbered ? sequenceNonce(sequence) : XChaCha20Poly1305Cipher.generateNonce();
+    return encodeFrame(nonce, this.cipher.encrypt(nonce, plaintext));
+  }
+
+  /** Send a valid frame, remembering it for replay */
+  async sendFrame(plaintext: Uint8Array): Promise<void> {
+    this.lastFrame = this.frame(plaintext);
+    await this.write(this.lastFrame);
+  }
+
+  /** Send the previous frame again, byte for byte */
+  async replayFrame(): Promise<void> {
+    if (!this.lastFrame) throw new Error("no frame to replay");
+    await this.write(this.lastFrame);
+  }
+
+  /** Send a valid frame with one ciphertext bit flipped */
+  async sendTamperedFrame(plaintext: Uint8Array): Promise<void> {
+    const frame = this.frame(plaintext);
+    frame[frame.length - 1] = frame[frame.length - 1]! ^ 0x01;
+    await this.write(frame);
+  }
+
+  /** Send only a frame header announcing `length` bytes */
+  async sendLength(length: number): Promise<void> {
+    const header = new Uint8Array(4);
+    new DataView(header.buffer).setUint32(0, length >>> 0, true);
+    await this.write(header);
+  }
+
+  /** Send the first `bytes` bytes of a valid frame, then close */
+  async sendTruncatedFrame(plaintext: Uint8Array, bytes: number): Promise<void> {
+    await this.write(this.frame(plaintext).subarray(0, bytes));
+    this.stream.end();
+  }
+}
@@ -0,0 +1,17 @@
+/**
+ * Listener shard used by the sharding tests: answers each connection's first
+ * packet with the shard's id
+ */
+
+import { bindShard, shardContext } from "../../src/shards.js";
+import { RawPacket } from "../../src/protocol.js";
+
+const { shardId } = shardContext()!;
+const listener = await bindShard();
+
+for await (const { stream } of listener) {
+  void (async () => {
+    await stream.readPacket();
+    await stream.writePacket(new RawPacket(new Uint8Array([shardId])));
+  })().catch(() => {});
+}
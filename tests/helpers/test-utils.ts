@@ -144,6 +144,10 @@ export async function createStreamPair(): Promise<[import("stream").Duplex, impo
       stream2.push(chunk);
       callback();
     },
+    final(callback: () => void) {
+      stream2.push(null);
+      callback();
+    },
   });
 
   stream2 = new Duplex({
@@ -152,8 +156,27 @@ export async function createStreamPair(): Promise<[import("stream").Duplex, impo
       stream1.push(chunk);
       callback();
     },
+    final(callback: () => void) {
+      stream1.push(null);
+      callback();
+    },
   });
 
   return [stream1, stream2];
 }
 
+
+/**
+ * Create two EncryptedStreams connected over an in-memory duplex pair
+ */
+export async function createEncryptedStreamPair(
+  optionsA?: import("../../src/stream.js").EncryptedStreamOptions,
+  optionsB?: import("../../src/stream.js").EncryptedStreamOptions
+): Promise<[import("../../src/stream.js").EncryptedStream, import("../../src/stream.js").EncryptedStream]> {
+  const { EncryptedStream } = await import("../../src/stream.js");
+  const [a, b] = await createStreamPair();
+  return Promise.all([
+    EncryptedStream.new(a, optionsA),
+    EncryptedStream.new(b, optionsB),
+  ]);
+}
@@ -0,0 +1,124 @@
+/**
+ * Interop peer - JS side of the differential interop harness
+ *
+ * Driven by the `interop` binary in tests/rust-binaries, which runs it against
+ * the Rust `interop-peer` with the same arguments and output format:
+ *
+ *   bun tests/interop/js-peer.ts <server|client> <port> <size> [psk]
+ *
+ * The server prints `ready` once listening. Both roles finish by printing one
+ * JSON line describing what they saw.
+ */
+
+import { createServer, connect, type Socket } from "net";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { TestProtocol } from "../helpers/test-protocol.js";
+
+const codec = createProtocolCodec([
+  "Heartbeat",
+  "Join",
+  "Leave",
+  "Message",
+  "Status",
+  "Ping",
+  "Pong",
+  "Shutdown",
+] as const);
+
+const PINGS = 3;
+const ALPHABET = "abcdefghijklmnopqrstuvwxyz0123456789";
+
+function payload(size: number, round: number): string {
+  let text = "";
+  for (let j = 0; j < size; j++) {
+    text += ALPHABET[(round * 7 + j) % ALPHABET.length];
+  }
+  return text;
+}
+
+/** FNV-1a 64, matching the Rust peer */
+function fnv1a(text: string): string {
+  let hash = 0xcbf29ce484222325n;
+  for (const byte of new TextEncoder().encode(text)) {
+    hash ^= BigInt(byte);
+    hash = (hash * 0x100000001b3n) & 0xffffffffffffffffn;
+  }
+  return hash.toString(16).padStart(16, "0");
+}
+
+async function readPacket(stream: EncryptedStream) {
+  const data = (await stream.readPacket()) as unknown as Uint8Array;
+  return codec.decode(data);
+}
+
+async function runServer(port: number, options: EncryptedStreamOptions): Promise<object> {
+  const socket = await new Promise<Socket>((resolve, reject) => {
+    const server = createServer((socket) => {
+      server.close();
+      resolve(socket);
+    });
+    server.on("error", reject);
+    server.listen(port, "127.0.0.1", () => console.log("ready"));
+  });
+
+  const stream = await EncryptedStream.new(socket, options);
+  const received: string[] = [];
+  try {
+    for (;;) {
+      const packet = await readPacket(stream);
+      if (packet.type === "Shutdown") break;
+      if (packet.type !== "Ping") {
+        throw new Error(`unexpected packet ${packet.type}`);
+      }
+      const message = packet.reader.readString();
+      received.push(fnv1a(message));
+      await stream.writePacket(TestProtocol.Pong({ message: `pong-${message}` }));
+    }
+  } finally {
+    socket.destroy();
+  }
+  return { ok: true, received };
+}
+
+async function runClient(port: number, size: number, options: EncryptedStreamOptions): Promise<object> {
+  const socket = await new Promise<Socket>((resolve, reject) => {
+    const socket = connect(port, "127.0.0.1", () => resolve(socket));
+    socket.once("error", reject);
+  });
+
+  const stream = await EncryptedStream.new(socket, options);
+  const replies: Array<{ len: number; hash: string }> = [];
+  try {
+    for (let round = 0; round < PINGS; round++) {
+      await stream.writePacket(TestProtocol.Ping({ message: payload(size, round) }));
+      const reply = await readPacket(stream);
+      if (reply.type !== "Pong") {
+        throw new Error(`expected Pong, got ${reply.type}`);
+      }
+      const message = reply.reader.readString();
+      replies.push({ len: new TextEncoder().encode(message).length, hash: fnv1a(message) });
+    }
+    await stream.writePacket(TestProtocol.Shutdown());
+  } finally {
+    socket.end();
+  }
+  return { ok: true, replies };
+}
+
+const [role, portArg, sizeArg, psk] = process.argv.slice(2);
+const port = Number(portArg ?? 9000);
+const size = Number(sizeArg ?? 16);
+const options: EncryptedStreamOptions = {
+  maxPacketSize: 65536,
+  ...(psk !== undefined ? { psk: new TextEncoder().encode(psk) } : {}),
+};
+
+try {
+  const report = role === "server" ? await runServer(port, options) : await runClient(port, size, options);
+  console.log(JSON.stringify(report));
+  process.exit(0);
+} catch (error) {
+  console.log(JSON.stringify({ ok: false, error: error instanceof Error ? error.message : String(error) }));
+  process.exit(1);
+}
@@ -0,0 +1,245 @@
+//! Adversarial clavis client for negative testing.
+//!
+//! Implements just enough of the handshake and framing to misbehave on
+//! purpose, then checks that the target server drops the connection. Works
+//! against any clavis server that echoes `TestProtocol` packets, such as
+//! `test-server` or the JS interop peer:
+//!
+//!     hostile-peer <port> [psk] [scenario...]
+//!
+//! With no scenarios, all of them run. Prints one JSON line per scenario and
+//! exits non-zero if any attack was not rejected. `wrong-mac` is skipped
+//! without a PSK. Frames carry no sequence number, so `replayed-frame` is
+//! currently answered by every clavis implementation.
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+const SCENARIOS: &[&str] = &[
+    "short-handshake",
+    "wrong-mac",
+    "oversized-length",
+    "zero-length",
+    "truncated-frame",
+    "tampered-frame",
+    "replayed-frame",
+];
+
+/// How long to wait for the server to react to an attack
+const REACTION_TIMEOUT: Duration = Duration::from_secs(3);
+
+struct Keys {
+    enc: [u8; 32],
+}
+
+#[derive(PartialEq)]
+enum Outcome {
+    /// The server closed the connection
+    Closed,
+    /// The server kept the connection open without answering
+    Open,
+    /// The server processed the forged input and answered
+    Answered,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Closed => "closed",
+            Outcome::Open => "open",
+            Outcome::Answered => "answered",
+        }
+    }
+}
+
+/// Honest handshake, optionally replacing our MAC with garbage
+async fn handshake(stream: &mut TcpStream, psk: Option<&[u8]>, forge_mac: bool) -> std::io::Result<Keys> {
+    let mut nonce = [0u8; 32];
+    OsRng.fill_bytes(&mut nonce);
+    stream.write_all(&nonce).await?;
+    let mut peer_nonce = [0u8; 32];
+    stream.read_exact(&mut peer_nonce).await?;
+    let initiator = nonce > peer_nonce;
+
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    let mut peer_public = [0u8; 32];
+    if initiator {
+        stream.write_all(public.as_bytes()).await?;
+        stream.read_exact(&mut peer_public).await?;
+    } else {
+        stream.read_exact(&mut peer_public).await?;
+        stream.write_all(public.as_bytes()).await?;
+    }
+    let shared = secret.diffie_hellman(&PublicKey::from(peer_public));
+
+    let mut transcript = [0u8; 64];
+    let (first, second) = if initiator {
+        (*public.as_bytes(), peer_public)
+    } else {
+        (peer_public, *public.as_bytes())
+    };
+    transcript[..32].copy_from_slice(&first);
+    transcript[32..].copy_from_slice(&second);
+    let transcript_hash = Sha256::digest(transcript);
+
+    if let Some(psk) = psk {
+        let mut mac = [0u8; 32];
+        if forge_mac {
+            OsRng.fill_bytes(&mut mac);
+        } else {
+            let mut hmac = <Hmac<Sha256> as Mac>::new_from_slice(psk).expect("HMAC accepts any key length");
+            hmac.update(&transcript);
+            mac.copy_from_slice(&hmac.finalize().into_bytes());
+        }
+        let mut peer_mac = [0u8; 32];
+        if initiator {
+            stream.write_all(&mac).await?;
+            stream.read_exact(&mut peer_mac).await?;
+        } else {
+            stream.read_exact(&mut peer_mac).await?;
+            stream.write_all(&mac).await?;
+        }
+    }
+
+    let hkdf = Hkdf::<Sha256>::new(Some(&transcript_hash), shared.as_bytes());
+    let mut enc = [0u8; 32];
+    let info: &[u8] = if initiator { b"enc" } else { b"dec" };
+    hkdf.expand(info, &mut enc).expect("32 bytes is a valid HKDF length");
+    Ok(Keys { enc })
+}
+
+/// `TestProtocol::Ping(PingPongData { message })` in bincode
+fn ping(message: &str) -> Vec<u8> {
+    let mut packet = 5u32.to_le_bytes().to_vec();
+    packet.extend_from_slice(&(message.len() as u64).to_le_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet
+}
+
+fn frame(keys: &Keys, plaintext: &[u8]) -> Vec<u8> {
+    let cipher = XChaCha20Poly1305::new((&keys.enc).into());
+    let mut nonce = [0u8; 24];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), plaintext)
+        .expect("encryption cannot fail");
+
+    let mut out = (ciphertext.len() as u32).to_le_bytes().to_vec();
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    out
+}
+
+/// Wait for the server to react: EOF or reset means closed, data means answered
+async fn observe(stream: &mut TcpStream) -> Outcome {
+    let mut buf = [0u8; 4096];
+    match timeout(REACTION_TIMEOUT, stream.read(&mut buf)).await {
+        Ok(Ok(0)) | Ok(Err(_)) => Outcome::Closed,
+        Ok(Ok(_)) => Outcome::Answered,
+        Err(_) => Outcome::Open,
+    }
+}
+
+async fn run(scenario: &str, port: u16, psk: Option<&[u8]>) -> std::io::Result<Outcome> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await?;
+
+    match scenario {
+        "short-handshake" => {
+            stream.write_all(&[0u8; 16]).await?;
+            stream.shutdown().await?;
+        }
+        "wrong-mac" => {
+            if handshake(&mut stream, psk, true).await.is_err() {
+                return Ok(Outcome::Closed);
+            }
+        }
+        _ => {
+            let keys = handshake(&mut stream, psk, false).await?;
+            match scenario {
+                "oversized-length" => stream.write_all(&u32::MAX.to_le_bytes()).await?,
+                "zero-length" => stream.write_all(&0u32.to_le_bytes()).await?,
+                "truncated-frame" => {
+                    let full = frame(&keys, &ping("truncated"));
+                    stream.write_all(&full[..full.len() / 2]).await?;
+                    stream.shutdown().await?;
+                }
+                "tampered-frame" => {
+                    let mut forged = frame(&keys, &ping("tampered"));
+                    *forged.last_mut().expect("frame is not empty") ^= 0x01;
+                    stream.write_all(&forged).await?;
+                }
+                "replayed-frame" => {
+                    let original = frame(&keys, &ping("replayed"));
+                    stream.write_all(&original).await?;
+                    // The original is legitimate and should be answered
+                    if observe(&mut stream).await != Outcome::Answered {
+                        return Ok(Outcome::Closed);
+                    }
+                    stream.write_all(&original).await?;
+                }
+                other => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("unknown scenario {}", other),
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(observe(&mut stream).await)
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let port: u16 = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(9000);
+    let psk = args.get(2).filter(|s| !s.is_empty()).map(|s| s.as_bytes().to_vec());
+    let selected: Vec<&str> = if args.len() > 3 {
+        args[3..].iter().map(|s| s.as_str()).collect()
+    } else {
+        SCENARIOS.to_vec()
+    };
+
+    let mut failures = 0;
+    for scenario in selected {
+        if scenario == "wrong-mac" && psk.is_none() {
+            println!("{}", json!({ "scenario": scenario, "outcome": "skipped" }));
+            continue;
+        }
+        let (outcome, error) = match run(scenario, port, psk.as_deref()).await {
+            Ok(outcome) => (outcome, None),
+            // A reset while attacking also means the server dropped us
+            Err(e) => (Outcome::Closed, Some(e.to_string())),
+        };
+        let rejected = outcome == Outcome::Closed;
+        if !rejected {
+            failures += 1;
+        }
+        println!(
+            "{}",
+            json!({
+                "scenario": scenario,
+                "outcome": outcome.as_str(),
+                "rejected": rejected,
+                "error": error,
+            })
+        );
+    }
+
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
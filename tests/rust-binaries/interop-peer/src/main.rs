@@ -0,0 +1,144 @@
+//! Rust side of the differential interop harness.
+//!
+//! Driven by the `interop` binary, which runs it against the JS peer
+//! (tests/interop/js-peer.ts) with the same arguments and output format:
+//!
+//!     interop-peer <server|client> <port> <size> [psk]
+//!
+//! The server prints `ready` once listening. Both roles finish by printing one
+//! JSON line describing what they saw.
+
+use clavis::{EncryptedPacket, EncryptedStream, EncryptedStreamOptions};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PingPongData {
+    message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChatMessage {
+    username: String,
+    content: String,
+    timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Status {
+    users_online: u32,
+    server_uptime: u64,
+}
+
+clavis::protocol! {
+    enum TestProtocol {
+        Heartbeat,
+        Join(String),
+        Leave(String),
+        Message(ChatMessage),
+        Status(Status),
+        Ping(PingPongData),
+        Pong(PingPongData),
+        Shutdown,
+    }
+}
+
+type BoxError = Box<dyn std::error::Error>;
+
+const PINGS: usize = 3;
+const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+fn payload(size: usize, round: usize) -> String {
+    (0..size)
+        .map(|j| ALPHABET[(round * 7 + j) % ALPHABET.len()] as char)
+        .collect()
+}
+
+/// FNV-1a 64, matching the JS peer
+fn fnv1a(text: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in text.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let role = args.get(1).map(|s| s.as_str()).unwrap_or("client");
+    let port: u16 = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(9000);
+    let size: usize = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(16);
+    let psk = args.get(4).map(|s| s.as_bytes().to_vec());
+
+    let options = EncryptedStreamOptions {
+        max_packet_size: 65536,
+        psk: psk.map(|p| p.into()),
+    };
+
+    let result = match role {
+        "server" => run_server(port, options).await,
+        _ => run_client(port, size, options).await,
+    };
+
+    match result {
+        Ok(report) => println!("{}", report),
+        Err(e) => {
+            println!("{}", json!({ "ok": false, "error": e.to_string() }));
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_server(port: u16, options: EncryptedStreamOptions) -> Result<Value, BoxError> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    println!("ready");
+
+    let (stream, _addr) = listener.accept().await?;
+    let encrypted = EncryptedStream::new(stream, Some(options)).await?;
+    let (mut reader, mut writer) = encrypted.split();
+
+    let mut received = Vec::new();
+    loop {
+        match reader.read_packet::<TestProtocol>().await? {
+            TestProtocol::Ping(data) => {
+                received.push(fnv1a(&data.message));
+                let pong = TestProtocol::Pong(PingPongData {
+                    message: format!("pong-{}", data.message),
+                });
+                writer.write_packet(&pong).await?;
+            }
+            TestProtocol::Shutdown => break,
+            _ => return Err("unexpected packet".into()),
+        }
+    }
+
+    Ok(json!({ "ok": true, "received": received }))
+}
+
+async fn run_client(port: u16, size: usize, options: EncryptedStreamOptions) -> Result<Value, BoxError> {
+    let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+    let encrypted = EncryptedStream::new(stream, Some(options)).await?;
+    let (mut reader, mut writer) = encrypted.split();
+
+    let mut replies = Vec::new();
+    for round in 0..PINGS {
+        let ping = TestProtocol::Ping(PingPongData {
+            message: payload(size, round),
+        });
+        writer.write_packet(&ping).await?;
+
+        match reader.read_packet::<TestProtocol>().await? {
+            TestProtocol::Pong(data) => replies.push(json!({
+                "len": data.message.len(),
+                "hash": fnv1a(&data.message),
+            })),
+            _ => return Err("expected Pong".into()),
+        }
+    }
+    writer.write_packet(&TestProtocol::Shutdown).await?;
+
+    Ok(json!({ "ok": true, "replies": replies }))
+}
@@ -0,0 +1,230 @@
+//! Differential interop harness.
+//!
+//! Runs every server/client pairing of the Rust `interop-peer` and the JS peer
+//! (tests/interop/js-peer.ts) over a matrix of options and checks that all
+//! pairings behave identically: the same replies on success, and failure on
+//! the same side when the handshake is expected to fail.
+//!
+//! The matrix covers PSK modes and packet sizes. Compression is not part of the
+//! clavis wire format, so it has no axis yet.
+//!
+//! Build the release binaries first, then run from this directory:
+//!
+//!     cargo run --release --bin interop
+//!
+//! `BUN` overrides the bun executable used to run the JS peer.
+//! `INTEROP_RUST_PEERS` adds Rust peers built against other clavis versions,
+//! as comma-separated `name=path` entries (see tests/compat/matrix.ts); every
+//! peer is paired with every other peer in both roles.
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PROCESS_TIMEOUT: Duration = Duration::from_secs(15);
+const SIZES: &[usize] = &[0, 1, 1024, 60000];
+
+enum PeerKind {
+    Rust(PathBuf),
+    Js,
+}
+
+struct Peer {
+    name: String,
+    kind: PeerKind,
+}
+
+impl Peer {
+    fn command(&self, args: &[String]) -> Command {
+        let mut command = match &self.kind {
+            PeerKind::Rust(path) => Command::new(path),
+            PeerKind::Js => {
+                let bun = std::env::var("BUN").unwrap_or_else(|_| "bun".to_string());
+                let mut command = Command::new(bun);
+                command.arg(repo_root().join("tests/interop/js-peer.ts"));
+                command
+            }
+        };
+        command
+            .args(args)
+            .current_dir(repo_root())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        command
+    }
+}
+
+struct PskMode {
+    name: &'static str,
+    server: Option<&'static str>,
+    client: Option<&'static str>,
+    /// Whether the pairing is expected to exchange packets
+    succeeds: bool,
+}
+
+const PSK_MODES: &[PskMode] = &[
+    PskMode { name: "no-psk", server: None, client: None, succeeds: true },
+    PskMode { name: "psk", server: Some("interop-pre-shared-key-0123456789"), client: Some("interop-pre-shared-key-0123456789"), succeeds: true },
+    PskMode { name: "psk-mismatch", server: Some("interop-pre-shared-key-0123456789"), client: Some("a-different-pre-shared-key-98765"), succeeds: false },
+];
+
+/// The peers under test: this build's Rust peer, the JS peer, and any extra
+/// Rust builds from `INTEROP_RUST_PEERS`
+fn peers() -> Vec<Peer> {
+    let exe = std::env::current_exe().expect("current executable path");
+    let mut peers = vec![
+        Peer { name: "rust".to_string(), kind: PeerKind::Rust(exe.with_file_name("interop-peer")) },
+        Peer { name: "js".to_string(), kind: PeerKind::Js },
+    ];
+
+    if let Ok(extra) = std::env::var("INTEROP_RUST_PEERS") {
+        for entry in extra.split(',').filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                Some((name, path)) => peers.push(Peer {
+                    name: name.to_string(),
+                    kind: PeerKind::Rust(PathBuf::from(path)),
+                }),
+                None => panic!("INTEROP_RUST_PEERS entry {:?} is not name=path", entry),
+            }
+        }
+    }
+    peers
+}
+
+fn repo_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../..")
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("failed to reserve a port")
+}
+
+/// Wait for a child to exit, killing it after the timeout
+fn wait_with_timeout(child: &mut Child) -> bool {
+    let deadline = Instant::now() + PROCESS_TIMEOUT;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    false
+}
+
+/// Parse the final JSON report, reducing failures to `{"ok": false}` since
+/// error messages differ between implementations
+fn parse_report(output: &str, finished: bool) -> Value {
+    if !finished {
+        return json!({ "ok": false });
+    }
+    let report = output
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str::<Value>(line).ok())
+        .unwrap_or_else(|| json!({ "ok": false }));
+    if report.get("ok") == Some(&Value::Bool(true)) {
+        report
+    } else {
+        json!({ "ok": false })
+    }
+}
+
+fn peer_args(role: &str, port: u16, size: usize, psk: Option<&str>) -> Vec<String> {
+    let mut args = vec![role.to_string(), port.to_string(), size.to_string()];
+    if let Some(psk) = psk {
+        args.push(psk.to_string());
+    }
+    args
+}
+
+/// Run one server/client pairing and return (client report, server report)
+fn run_pairing(server: &Peer, client: &Peer, mode: &PskMode, size: usize) -> (Value, Value) {
+    let port = free_port();
+
+    let mut server_child = server
+        .command(&peer_args("server", port, size, mode.server))
+        .spawn()
+        .expect("failed to spawn server peer");
+    let mut server_stdout = BufReader::new(server_child.stdout.take().expect("server stdout"));
+
+    // The server announces readiness before accepting its single connection
+    let mut line = String::new();
+    if server_stdout.read_line(&mut line).unwrap_or(0) == 0 || line.trim() != "ready" {
+        let _ = server_child.kill();
+        let _ = server_child.wait();
+        return (json!({ "ok": false }), json!({ "ok": false, "startup": false }));
+    }
+
+    let mut client_child = client
+        .command(&peer_args("client", port, size, mode.client))
+        .spawn()
+        .expect("failed to spawn client peer");
+    let client_stdout = client_child.stdout.take().expect("client stdout");
+    let client_output = thread::spawn(move || read_all(client_stdout));
+    let client_finished = wait_with_timeout(&mut client_child);
+
+    let server_finished = wait_with_timeout(&mut server_child);
+    let mut server_output = String::new();
+    let _ = server_stdout.read_to_string(&mut server_output);
+
+    let client_output = client_output.join().unwrap_or_default();
+    (
+        parse_report(&client_output, client_finished && client_child.wait().map(|s| s.success()).unwrap_or(false)),
+        parse_report(&server_output, server_finished),
+    )
+}
+
+fn read_all(mut stdout: ChildStdout) -> String {
+    let mut output = String::new();
+    let _ = stdout.read_to_string(&mut output);
+    output
+}
+
+fn main() {
+    let peers = peers();
+    let pairings: Vec<(&Peer, &Peer)> = peers
+        .iter()
+        .flat_map(|server| peers.iter().map(move |client| (server, client)))
+        .collect();
+
+    let mut failures = 0;
+    for mode in PSK_MODES {
+        for &size in SIZES {
+            let outcomes: Vec<(String, (Value, Value))> = pairings
+                .iter()
+                .map(|&(server, client)| {
+                    let label = format!("{}->{}", client.name, server.name);
+                    (label, run_pairing(server, client, mode, size))
+                })
+                .collect();
+
+            let (_, reference) = &outcomes[0];
+            let identical = outcomes.iter().all(|(_, outcome)| outcome == reference);
+            let expected = (reference.0.get("ok") == Some(&Value::Bool(true))) == mode.succeeds;
+
+            let status = if identical && expected { "ok" } else { "FAIL" };
+            println!("{:<5} {:<13} size={:<6}", status, mode.name, size);
+            if !(identical && expected) {
+                failures += 1;
+                for (label, (client, server)) in &outcomes {
+                    println!("      {:<20} client={} server={}", label, client, server);
+                }
+            }
+        }
+    }
+
+    if failures > 0 {
+        println!("{} case(s) differed", failures);
+        std::process::exit(1);
+    }
+    println!("all pairings behaved identically");
+}
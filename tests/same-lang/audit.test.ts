@@ -0,0 +1,50 @@
+/**
+ * Audit mode tests - secrets and payloads must not show up in debug output
+ */
+
+import { describe, test, expect, beforeEach, afterEach } from "bun:test";
+import { inspect } from "util";
+import { isRedacted, redact, setAuditMode } from "../../src/audit.js";
+import { XChaCha20Poly1305Cipher, generateX25519KeyPair } from "../../src/crypto.js";
+import { RawPacket, createProtocolCodec } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const secret = new TextEncoder().encode("top secret payload");
+
+describe("Audit mode", () => {
+  beforeEach(() => setAuditMode(true));
+  afterEach(() => setAuditMode(false));
+
+  test("should redact keys", () => {
+    const key = new Uint8Array(32).fill(0xab);
+    const cipher = new XChaCha20Poly1305Cipher(key);
+    const output = inspect(cipher) + JSON.stringify({ key }) + `${key}`;
+
+    expect(output).not.toContain("171");
+    expect(output).toContain("<redacted key: 32 bytes>");
+    expect(isRedacted(generateX25519KeyPair().secret)).toBe(true);
+  });
+
+  test("should redact PSKs and decrypted payloads", async () => {
+    const psk = new TextEncoder().encode("audit-test-pre-shared-key");
+    const codec = createProtocolCodec(["Secret"] as const);
+    const [a, b] = await createEncryptedStreamPair({ psk }, { psk });
+    expect(isRedacted(psk)).toBe(true);
+
+    await a.writePacket(new RawPacket(codec.encode("Secret", secret)));
+    const received = (await b.readPacket()) as unknown as Uint8Array;
+    const message = codec.decode(received);
+
+    expect(inspect(received)).toContain("<redacted payload");
+    expect(inspect(message.data)).not.toContain(String(secret[0]));
+    // The bytes themselves are unchanged
+    expect(new TextDecoder().decode(message.data)).toBe("top secret payload");
+  });
+
+  test("should leave buffers alone when off", () => {
+    setAuditMode(false);
+    const bytes = redact(new Uint8Array([1, 2, 3]), "key");
+    expect(isRedacted(bytes)).toBe(false);
+    expect(inspect(bytes)).toContain("1, 2, 3");
+  });
+});
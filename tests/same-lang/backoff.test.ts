@@ -0,0 +1,75 @@
+/**
+ * Backoff tests - delay growth, jitter modes and reset after success
+ */
+
+import { describe, test, expect } from "bun:test";
+import { Backoff, backoffDelay } from "../../src/backoff.js";
+import { ManualClock } from "../../src/clock.js";
+import { ClavisError } from "../../src/error.js";
+
+const delays = (backoff: Backoff) => {
+  const out: Array<number | undefined> = [];
+  for (let i = 0; i < 5; i++) out.push(backoff.next());
+  return out;
+};
+
+describe("backoffDelay", () => {
+  test("should grow exponentially or linearly up to the cap", () => {
+    const exponential = { initialDelayMs: 100, maxDelayMs: 500 };
+    expect([1, 2, 3, 4].map((retry) => backoffDelay(exponential, retry))).toEqual([100, 200, 400, 500]);
+    const linear = { initialDelayMs: 100, backoff: "linear" as const };
+    expect([1, 2, 3].map((retry) => backoffDelay(linear, retry))).toEqual([100, 200, 300]);
+  });
+});
+
+describe("Backoff", () => {
+  test("should stop after maxAttempts", () => {
+    const backoff = new Backoff({ maxAttempts: 4, initialDelayMs: 10 });
+    expect(delays(backoff)).toEqual([10, 20, 40, undefined, undefined]);
+    expect(backoff.retries).toBe(3);
+  });
+
+  test("should apply full and equal jitter", () => {
+    const full = new Backoff({ maxAttempts: 3, initialDelayMs: 100, jitter: "full" }, new ManualClock(), () => 0.25);
+    expect(delays(full)).toEqual([25, 50, undefined, undefined, undefined]);
+    const equal = new Backoff({ maxAttempts: 3, initialDelayMs: 100, jitter: "equal" }, new ManualClock(), () => 0.5);
+    expect(delays(equal)).toEqual([75, 150, undefined, undefined, undefined]);
+  });
+
+  test("should spread decorrelated delays from the previous one and cap them", () => {
+    const backoff = new Backoff(
+      { maxAttempts: 5, initialDelayMs: 100, maxDelayMs: 1_000, jitter: "decorrelated" },
+      new ManualClock(),
+      () => 1
+    );
+    expect(delays(backoff)).toEqual([300, 900, 1_000, 1_000, undefined]);
+  });
+
+  test("should add or subtract a fraction of the delay", () => {
+    const low = new Backoff({ initialDelayMs: 100, jitter: 0.1 }, new ManualClock(), () => 0);
+    const high = new Backoff({ initialDelayMs: 100, jitter: 0.1 }, new ManualClock(), () => 1);
+    expect(low.next()).toBeCloseTo(90);
+    expect(high.next()).toBeCloseTo(110);
+  });
+
+  test("should start over only once a success lasted resetAfterMs", () => {
+    const clock = new ManualClock();
+    const backoff = new Backoff({ maxAttempts: 5, initialDelayMs: 10, resetAfterMs: 1_000 }, clock);
+    backoff.next();
+    backoff.next();
+
+    backoff.succeeded();
+    clock.advance(500);
+    expect(backoff.next()).toBe(40);
+
+    backoff.succeeded();
+    clock.advance(1_000);
+    expect(backoff.next()).toBe(10);
+    expect(backoff.retries).toBe(1);
+  });
+
+  test("should reject invalid policies", () => {
+    expect(() => new Backoff({ maxAttempts: 0 })).toThrow(ClavisError);
+    expect(() => new Backoff({ jitter: 2 })).toThrow(ClavisError);
+  });
+});
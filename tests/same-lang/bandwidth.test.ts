@@ -0,0 +1,70 @@
+/**
+ * Bandwidth estimation tests - send queue depth and drain rate
+ */
+
+import { describe, test, expect } from "bun:test";
+import { BandwidthEstimator } from "../../src/bandwidth.js";
+import { ManualClock } from "../../src/clock.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createChaosPair } from "../../src/testing.js";
+
+describe("BandwidthEstimator", () => {
+  test("should count only the time the link was busy", async () => {
+    const clock = new ManualClock();
+    const estimator = new BandwidthEstimator(clock);
+    expect(estimator.estimate()).toEqual({ bytesPerSecond: undefined, queuedBytes: 0, queuedWrites: 0 });
+
+    const first = estimator.queued(1_000);
+    const second = estimator.queued(1_000);
+    expect(estimator.estimate().queuedBytes).toBe(2_000);
+
+    await clock.advance(100);
+    first();
+    await clock.advance(100);
+    second();
+    // Idle time between writes doesn't lower the rate
+    await clock.advance(300);
+    const drained = estimator.queued(1_000);
+    await clock.advance(100);
+    drained();
+
+    expect(estimator.estimate()).toEqual({ bytesPerSecond: 10_000, queuedBytes: 0, queuedWrites: 0 });
+  });
+
+  test("should forget samples outside the window", async () => {
+    const clock = new ManualClock();
+    const estimator = new BandwidthEstimator(clock, 500);
+    estimator.queued(100)();
+    await clock.advance(600);
+    expect(estimator.estimate().bytesPerSecond).toBeUndefined();
+  });
+});
+
+describe("bandwidthEstimate", () => {
+  test("should report writes queued behind a slow link", async () => {
+    const clock = new ManualClock();
+    const [a, b] = createChaosPair({ bandwidthBytesPerSec: 50_000, burstBytes: 1_000 }, undefined, clock);
+    let streams: [EncryptedStream, EncryptedStream] | undefined;
+    void Promise.all([EncryptedStream.new(a, { clock }), EncryptedStream.new(b, { clock })]).then((pair) => (streams = pair));
+    for (let i = 0; i < 100 && !streams; i++) await clock.advance(10);
+    const [client, server] = streams!;
+
+    // Each 5 kB frame leaves 100 ms after the one before it
+    const writes = [1, 2, 3].map(() => client.writePacket(new RawPacket(new Uint8Array(5_000))));
+    const reads = [1, 2, 3].map(() => server.readPacket());
+    expect(client.bandwidthEstimate().queuedWrites).toBe(3);
+    await clock.advance(150);
+    expect(client.bandwidthEstimate().queuedWrites).toBe(2);
+    await clock.advance(100);
+    expect(client.bandwidthEstimate().queuedWrites).toBe(1);
+    await clock.advance(100);
+    await Promise.all([...writes, ...reads]);
+
+    const estimate = client.bandwidthEstimate();
+    expect(estimate.queuedBytes).toBe(0);
+    // The full bucket lets the first kilobyte out at once, so the rate is a little above the link's
+    expect(estimate.bytesPerSecond!).toBeGreaterThanOrEqual(50_000);
+    expect(estimate.bytesPerSecond!).toBeLessThan(55_000);
+  });
+});
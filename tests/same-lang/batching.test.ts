@@ -0,0 +1,180 @@
+/**
+ * Batching writer tests - immediate, fixed and adaptive flush policies, queue stats, cancellation and supersede keys, and shared writer handles
+ */
+
+import { describe, test, expect } from "bun:test";
+import { BatchingWriter, type BatchTarget } from "../../src/batching.js";
+import { SharedWriter } from "../../src/shared-writer.js";
+import { ClavisError, StreamError, StreamErrorCode } from "../../src/error.js";
+import { ManualClock } from "../../src/clock.js";
+import { RawPacket, type PacketTrait } from "../../src/protocol.js";
+
+/** Target whose writes complete only when released */
+class SlowTarget implements BatchTarget {
+  batches: number[][] = [];
+  queuedBytes = 0;
+  private releases: Array<() => void> = [];
+
+  writePackets(packets: Iterable<PacketTrait>): Promise<number> {
+    const batch = [...packets].map((packet) => packet.serialize()[0]!);
+    this.batches.push(batch);
+    return new Promise((resolve) => this.releases.push(() => resolve(batch.length)));
+  }
+
+  bandwidthEstimate() {
+    return { bytesPerSecond: undefined, queuedBytes: this.queuedBytes, queuedWrites: this.releases.length };
+  }
+
+  release(): void {
+    this.releases.shift()?.();
+  }
+}
+
+const packet = (n: number) => new RawPacket(new Uint8Array([n]));
+const settle = () => new Promise((resolve) => setImmediate(resolve));
+
+describe("BatchingWriter", () => {
+  test("should write each packet at once when immediate", async () => {
+    const target = new SlowTarget();
+    const writer = new BatchingWriter(target, { kind: "immediate" });
+    void writer.write(packet(1));
+    void writer.write(packet(2));
+    target.release();
+    await settle();
+    target.release();
+    await settle();
+    expect(target.batches).toEqual([[1], [2]]);
+  });
+
+  test("should hold packets for the delay or until the batch is full", async () => {
+    const clock = new ManualClock();
+    const target = new SlowTarget();
+    const writer = new BatchingWriter(target, { kind: "batch", maxPackets: 3, maxDelayMs: 10 }, clock);
+
+    void writer.write(packet(1));
+    void writer.write(packet(2));
+    expect(target.batches).toEqual([]);
+    await clock.advance(10);
+    expect(target.batches).toEqual([[1, 2]]);
+    target.release();
+
+    for (const n of [3, 4, 5]) void writer.write(packet(n));
+    await settle();
+    expect(target.batches).toEqual([[1, 2], [3, 4, 5]]);
+  });
+
+  test("should flush at once when idle and batch up behind a write in flight", async () => {
+    const clock = new ManualClock();
+    const target = new SlowTarget();
+    const writer = new BatchingWriter(target, { kind: "adaptive", maxDelayMs: 1_000 }, clock);
+
+    const first = writer.write(packet(1));
+    expect(target.batches).toEqual([[1]]);
+    for (const n of [2, 3, 4]) void writer.write(packet(n));
+    expect(writer.pending).toBe(3);
+
+    target.release();
+    await first;
+    await settle();
+    expect(target.batches).toEqual([[1], [2, 3, 4]]);
+  });
+
+  test("should treat a backed-up transport as congested", async () => {
+    const clock = new ManualClock();
+    const target = new SlowTarget();
+    target.queuedBytes = 1 << 20;
+    const writer = new BatchingWriter(target, { kind: "adaptive", maxDelayMs: 20 }, clock);
+
+    void writer.write(packet(1));
+    void writer.write(packet(2));
+    expect(target.batches).toEqual([]);
+    await clock.advance(20);
+    expect(target.batches).toEqual([[1, 2]]);
+  });
+
+  test("should report the queue and drop cancelled packets before they are sent", async () => {
+    const clock = new ManualClock();
+    const target = new SlowTarget();
+    const writer = new BatchingWriter(target, { kind: "batch", maxPackets: 10, maxDelayMs: 10 }, clock);
+
+    const writes = [1, 2, 3].map((n) => writer.writeCancellable(packet(n)));
+    await clock.advance(4);
+    expect(writer.queueStats()).toEqual({ packets: 3, bytes: 3, oldestAgeMs: 4 });
+
+    expect(writes[1]!.cancel()).toBe(true);
+    expect(writes[1]!.cancel()).toBe(false);
+    expect(writer.queueStats().packets).toBe(2);
+    await clock.advance(6);
+    expect(target.batches).toEqual([[1, 3]]);
+    expect(writes[0]!.cancel()).toBe(false);
+
+    target.release();
+    expect(await Promise.all(writes.map((write) => write.written))).toEqual([true, false, true]);
+    expect(writer.queueStats()).toEqual({ packets: 0, bytes: 0, oldestAgeMs: 0 });
+  });
+
+  test("should drop a queued packet when a newer one shares its supersede key", async () => {
+    const clock = new ManualClock();
+    const target = new SlowTarget();
+    const writer = new BatchingWriter(target, { kind: "batch", maxPackets: 10, maxDelayMs: 10 }, clock);
+
+    const first = writer.writeCancellable(packet(1), { supersede: "status" });
+    void writer.write(packet(2));
+    const second = writer.write(packet(3), { supersede: "status" });
+    const third = writer.writeCancellable(packet(4), { supersede: "status" });
+    expect(await first.written).toBe(false);
+    await second;
+    expect(writer.superseded).toBe(2);
+
+    await clock.advance(10);
+    expect(target.batches).toEqual([[2, 4]]);
+    // The key is free again once its packet left the queue
+    void writer.write(packet(5), { supersede: "status" });
+    expect(writer.pending).toBe(1);
+    target.release();
+    expect(await third.written).toBe(true);
+    expect(writer.superseded).toBe(2);
+  });
+});
+
+describe("SharedWriter", () => {
+  test("should keep every clone's packets in send order behind one bounded queue", async () => {
+    const target = new SlowTarget();
+    const a = SharedWriter.over(target, { policy: { kind: "immediate" }, maxQueued: 2 });
+    const b = a.clone();
+    expect(a.handles).toBe(2);
+
+    const first = a.send(packet(1));
+    expect(b.post(packet(2))).toBe(true);
+    expect(b.post(packet(3))).toBe(false);
+    const error = await a.send(packet(3)).catch((e: unknown) => e);
+    expect(((error as ClavisError).cause as StreamError).code).toBe(StreamErrorCode.Overloaded);
+
+    target.release();
+    await first;
+    await settle();
+    target.release();
+    await settle();
+    expect(target.batches).toEqual([[1], [2]]);
+    expect(a.queued).toBe(0);
+  });
+
+  test("should fail every handle once a write fails", async () => {
+    const broken: BatchTarget = {
+      writePackets: () => Promise.reject(StreamError.connectionReset(new Error("reset"))),
+      bandwidthEstimate: () => ({ bytesPerSecond: undefined, queuedBytes: 0, queuedWrites: 0 }),
+    };
+    const a = SharedWriter.over(broken, { policy: { kind: "immediate" } });
+    const b = a.clone();
+
+    await expect(a.send(packet(1))).rejects.toThrow(ClavisError);
+    expect(b.failure).toBeInstanceOf(ClavisError);
+    expect(b.post(packet(2))).toBe(false);
+
+    await b.release();
+    await expect(b.send(packet(3))).rejects.toThrow(ClavisError);
+    expect(() => b.clone()).toThrow(ClavisError);
+    expect(a.handles).toBe(1);
+    expect(() => SharedWriter.over(broken, { maxQueued: 0 })).toThrow(ClavisError);
+  });
+});
@@ -29,7 +29,11 @@ import {
   readDateTime,
   readChronoString,
   BincodeReader,
+  DecodeBudget,
 } from "../../src/bincode.js";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { payloadFormat } from "../../src/formats.js";
+import { ClavisError } from "../../src/error.js";
 
 describe("Bincode Write Functions", () => {
   test("writeU8 should write a single byte", () => {
@@ -254,6 +258,20 @@ describe("BincodeReader", () => {
     expect(reader.offset).toBe(3);
   });
 
+  test("should read bytes as a view without copying", () => {
+    const data = new Uint8Array([3, 0, 0, 0, 0, 0, 0, 0, 7, 8, 9, 1]);
+
+    const copy = new BincodeReader(data).readBytes();
+    const view = new BincodeReader(data).readBytesView();
+    expect(view).toEqual(new Uint8Array([7, 8, 9]));
+    expect(view.buffer).toBe(data.buffer);
+    expect(view.byteOffset).toBe(8);
+
+    data[8] = 0;
+    expect(view[0]).toBe(0);
+    expect(copy[0]).toBe(7);
+  });
+
   test("should handle complex nested data", () => {
     const buffer: number[] = [];
     writeU32(buffer, 1); // variant index
@@ -273,6 +291,79 @@ describe("BincodeReader", () => {
     expect(reader.readU32()).toBe(10);
     expect(reader.hasMore).toBe(false);
   });
+
+  test("should tolerate missing trailing fields", () => {
+    const oldPacket: number[] = [];
+    writeU32(oldPacket, 7);
+
+    const newPacket: number[] = [];
+    writeU32(newPacket, 7);
+    writeOptionString(newPacket, "eu-west");
+
+    const oldReader = new BincodeReader(new Uint8Array(oldPacket));
+    expect(oldReader.readU32()).toBe(7);
+    expect(oldReader.readTrailing((r) => r.readOptionString())).toBeUndefined();
+
+    const newReader = new BincodeReader(new Uint8Array(newPacket));
+    expect(newReader.readU32()).toBe(7);
+    expect(newReader.readTrailing((r) => r.readOptionString())).toBe("eu-west");
+  });
+});
+
+describe("Decode limits", () => {
+  test("should refuse long sequences and strings before reading them", () => {
+    const names: number[] = [];
+    writeStringVec(names, ["ada", "grace", "linus"]);
+    const limits = { maxSequenceLength: 2, maxStringLength: 4 };
+    expect(() => new BincodeReader(new Uint8Array(names), limits).readStringVec()).toThrow("maxSequenceLength 2");
+    expect(() => new BincodeReader(new Uint8Array(names), { maxStringLength: 4 }).readStringVec()).toThrow("maxStringLength 4");
+    expect(new BincodeReader(new Uint8Array(names)).readStringVec()).toEqual(["ada", "grace", "linus"]);
+
+    // A count the data can't hold fails without allocating
+    const huge: number[] = [];
+    writeU64(huge, 2n ** 40n);
+    expect(() => new BincodeReader(new Uint8Array(huge)).readStringVec()).toThrow(ClavisError);
+
+    const codec = createProtocolCodec(["Chat"] as const, { limits });
+    const chat: number[] = [];
+    writeU32(chat, 0);
+    writeString(chat, "hello");
+    expect(() => codec.decode(new Uint8Array(chat)).reader.readString()).toThrow(ClavisError);
+    expect(() => createProtocolCodec(["Chat"] as const, { limits: { maxDepth: -1 } })).toThrow(ClavisError);
+  });
+
+  test("should bound nesting in readers and self-describing formats", () => {
+    const reader = new BincodeReader(new Uint8Array(0), { maxDepth: 2 });
+    expect(reader.nested((r) => r.nested(() => "ok"))).toBe("ok");
+    expect(() => reader.nested((r) => r.nested((r2) => r2.nested(() => "deep")))).toThrow("nested too deeply");
+
+    const deep = [[[1]]];
+    for (const format of ["msgpack", "cbor", "json"] as const) {
+      const codec = payloadFormat(format);
+      expect(codec.decode(codec.encode(deep), { maxDepth: 3 })).toEqual(deep);
+      expect(() => codec.decode(codec.encode(deep), { maxDepth: 2 })).toThrow("nested too deeply");
+      expect(() => codec.decode(codec.encode({ tags: [1, 2, 3] }), { maxSequenceLength: 2 })).toThrow("maxSequenceLength");
+      expect(() => codec.decode(codec.encode(["x".repeat(10)]), { maxStringLength: 8 })).toThrow("maxStringLength");
+    }
+    expect(() => payloadFormat("json").decode(new TextEncoder().encode('["[[[["]'), { maxDepth: 1 })).not.toThrow();
+  });
+
+  test("should give up on a payload once its decode budget is spent", () => {
+    // Every clock reading moves time on by 1ms, and the budget reads it every 64th item
+    let now = 0;
+    const clock = { now: () => now++, setTimer: () => ({ cancel: () => {} }) };
+    const msgpack = payloadFormat("msgpack");
+    const small = msgpack.encode(Array.from({ length: 50 }, (_, i) => i));
+    const large = msgpack.encode(Array.from({ length: 200 }, (_, i) => i));
+    expect(msgpack.decode(small, { budget: new DecodeBudget(1, clock) })).toHaveLength(50);
+    expect(() => msgpack.decode(large, { budget: new DecodeBudget(1, clock) })).toThrow("1ms budget");
+    expect(msgpack.decode(large, { budget: new DecodeBudget(1_000, clock) })).toHaveLength(200);
+
+    const codec = createProtocolCodec(["Chat", "Roster"] as const, { decodeBudgetMs: { Roster: 20 } });
+    expect(codec.decode(new Uint8Array([0, 0, 0, 0])).reader.limits?.budget).toBeUndefined();
+    expect(codec.decode(new Uint8Array([1, 0, 0, 0])).reader.limits?.budget?.budgetMs).toBe(20);
+    expect(() => createProtocolCodec(["Chat"] as const, { decodeBudgetMs: 0 })).toThrow(ClavisError);
+  });
 });
 
 describe("Round-trip serialization", () => {
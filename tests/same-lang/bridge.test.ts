@@ -0,0 +1,97 @@
+/**
+ * Plaintext bridge tests - length-prefixed packets in, clavis packets out, both modes
+ */
+
+import { describe, test, expect } from "bun:test";
+import { connect, createServer, type AddressInfo, type Server, type Socket } from "net";
+import { startBridge, type Bridge } from "../../src/bridge.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+
+const psk = new Uint8Array(32).fill(5);
+
+function listening(server: Server): Promise<number> {
+  return new Promise((resolve) => server.listen(0, "127.0.0.1", () => resolve((server.address() as AddressInfo).port)));
+}
+
+function connected(port: number): Promise<Socket> {
+  return new Promise((resolve, reject) => {
+    const socket = connect(port, "127.0.0.1", () => resolve(socket));
+    socket.once("error", reject);
+  });
+}
+
+/** `length (u32 LE) | bytes` */
+function framed(bytes: number[]): Uint8Array {
+  const out = new Uint8Array(4 + bytes.length);
+  new DataView(out.buffer).setUint32(0, bytes.length, true);
+  out.set(bytes, 4);
+  return out;
+}
+
+/** Read exactly `length` bytes from a plain socket */
+async function readExactly(socket: Socket, length: number): Promise<Uint8Array> {
+  const chunks: Uint8Array[] = [];
+  let total = 0;
+  for await (const chunk of socket) {
+    chunks.push(chunk as Uint8Array);
+    total += (chunk as Uint8Array).length;
+    if (total >= length) break;
+  }
+  return new Uint8Array(Buffer.concat(chunks)).subarray(0, length);
+}
+
+describe("startBridge", () => {
+  test("should carry a plaintext client's packets to a clavis server", async () => {
+    // A clavis server answering each packet reversed
+    const server = createServer(async (socket) => {
+      const stream = await EncryptedStream.new(socket, { psk });
+      for await (const packet of stream) await stream.writePacket(new RawPacket(packet.slice().reverse()));
+    });
+    const serverPort = await listening(server);
+    const bridge: Bridge = await startBridge({
+      mode: "connect",
+      listen: { port: 0 },
+      remote: { host: "127.0.0.1", port: serverPort },
+      streamOptions: { psk },
+    });
+
+    const client = await connected(bridge.port);
+    client.write(framed([1, 2, 3]));
+
+    // A packet split across writes, then two packets in one
+    const split = framed([4, 5, 6, 7, 8]);
+    for (let i = 0; i < split.length; i += 2) {
+      client.write(split.subarray(i, i + 2));
+      await new Promise((resolve) => setImmediate(resolve));
+    }
+    client.write(new Uint8Array([...framed([9]), ...framed([])]));
+    expect(await readExactly(client, 25)).toEqual(
+      new Uint8Array([...framed([3, 2, 1]), ...framed([8, 7, 6, 5, 4]), ...framed([9]), ...framed([])])
+    );
+
+    client.destroy();
+    await bridge.close();
+    server.close();
+  });
+
+  test("should carry a clavis client's packets to a plaintext service", async () => {
+    // Echoing the framed bytes echoes every packet
+    const service = createServer((socket) => socket.pipe(socket));
+    const servicePort = await listening(service);
+    const bridge = await startBridge({
+      mode: "accept",
+      listen: { host: "127.0.0.1", port: 0 },
+      local: { host: "127.0.0.1", port: servicePort },
+      listenerOptions: { streamOptions: { psk } },
+    });
+
+    const stream = await EncryptedStream.new(await connected(bridge.port), { psk });
+    await stream.writePacket(new RawPacket(new Uint8Array([9, 8])));
+    expect((await stream.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([9, 8]));
+
+    await stream.close();
+    await bridge.close();
+    service.close();
+  });
+});
@@ -0,0 +1,79 @@
+/**
+ * Broadcast tests - fan-out with per-connection results
+ */
+
+import { describe, test, expect } from "bun:test";
+import { broadcast, type BroadcastTarget } from "../../src/broadcast.js";
+import { ManualClock } from "../../src/clock.js";
+import { RawPacket, type PacketTrait } from "../../src/protocol.js";
+import { ClavisError, StreamError, StreamErrorCode } from "../../src/error.js";
+import { wireSize } from "../../src/stream.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const packet = new RawPacket(new TextEncoder().encode("status"));
+
+describe("broadcast", () => {
+  test("should deliver to every connection", async () => {
+    const pairs = await Promise.all(Array.from({ length: 5 }, () => createEncryptedStreamPair()));
+    const summary = await broadcast(packet, pairs.map(([sender]) => sender));
+
+    expect(summary.delivered).toBe(5);
+    expect(summary.failed).toBe(0);
+    expect(summary.bytes).toBe(5 * wireSize(packet));
+    for (const [, receiver] of pairs) {
+      expect((await receiver.readPacket()) as unknown as Uint8Array).toEqual(packet.bytes);
+    }
+  });
+
+  test("should report failures per connection without affecting others", async () => {
+    const [good] = await createEncryptedStreamPair();
+    const broken: BroadcastTarget = {
+      writePacket: () => Promise.reject(StreamError.connectionClosed("gone")),
+    };
+
+    const summary = await broadcast(packet, [good, broken]);
+    expect(summary.delivered).toBe(1);
+    expect(summary.results[0]!.ok).toBe(true);
+    const failure = summary.results[1]!;
+    expect(failure.ok).toBe(false);
+    if (!failure.ok) {
+      expect(failure.target).toBe(broken);
+      expect(failure.error).toBeInstanceOf(ClavisError);
+    }
+  });
+
+  test("should bound the number of writes in flight", async () => {
+    let inFlight = 0;
+    let peak = 0;
+    const targets: BroadcastTarget[] = Array.from({ length: 20 }, () => ({
+      async writePacket(p: PacketTrait) {
+        inFlight++;
+        peak = Math.max(peak, inFlight);
+        await new Promise((resolve) => setTimeout(resolve, 1));
+        inFlight--;
+        return p.serialize().length;
+      },
+    }));
+
+    const summary = await broadcast(packet, targets, { concurrency: 4 });
+    expect(summary.delivered).toBe(20);
+    expect(peak).toBe(4);
+  });
+
+  test("should time out slow connections", async () => {
+    const clock = new ManualClock();
+    const stuck: BroadcastTarget = { writePacket: () => new Promise(() => {}) };
+    const fast: BroadcastTarget = { writePacket: async () => 1 };
+
+    const pending = broadcast(packet, [stuck, fast], { timeoutMs: 500, clock });
+    await clock.advance(500);
+    const summary = await pending;
+
+    expect(summary.delivered).toBe(1);
+    const failure = summary.results[0]!;
+    expect(failure.ok).toBe(false);
+    if (!failure.ok) {
+      expect((failure.error.cause as StreamError).code).toBe(StreamErrorCode.Timeout);
+    }
+  });
+});
@@ -0,0 +1,77 @@
+/**
+ * Chunked payload tests - reassembly, aborts and cancelling mid-transfer
+ */
+
+import { describe, test, expect } from "bun:test";
+import { Readable } from "stream";
+import { readStream, writeStream } from "../../src/chunked.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+async function collect(stream: ReadableStream<Uint8Array>): Promise<Uint8Array> {
+  const parts: Uint8Array[] = [];
+  const reader = stream.getReader();
+  for (;;) {
+    const { done, value } = await reader.read();
+    if (done) break;
+    parts.push(value);
+  }
+  const out = new Uint8Array(parts.reduce((total, part) => total + part.length, 0));
+  let offset = 0;
+  for (const part of parts) {
+    out.set(part, offset);
+    offset += part.length;
+  }
+  return out;
+}
+
+describe("writeStream and readStream", () => {
+  test("should carry a payload many times the max packet size", async () => {
+    const [a, b] = await createEncryptedStreamPair({ maxPacketSize: 4096 }, { maxPacketSize: 4096 });
+    const payload = new Uint8Array(1024 * 1024).map((_, i) => (i * 31) & 0xff);
+    // Uneven source reads exercise gathering and splitting
+    const pieces = [payload.subarray(0, 10), payload.subarray(10, 50_000), payload.subarray(50_000)];
+
+    const [sent, received] = await Promise.all([
+      writeStream(a, Readable.from(pieces)),
+      collect(readStream(b)),
+    ]);
+    expect(sent).toBe(payload.length);
+    expect(received).toEqual(payload);
+
+    // The connection carries on after the transfer
+    await a.writePacket(new RawPacket(new Uint8Array([1])));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+  });
+
+  test("should abort the receiver when the source fails", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    async function* failing() {
+      yield new Uint8Array(100);
+      throw new Error("disk gone");
+    }
+
+    const [writeError, readError] = await Promise.all([
+      writeStream(a, failing(), { chunkSize: 10 }).then(() => undefined, (error) => error as Error),
+      collect(readStream(b)).then(() => undefined, (error) => error as ClavisError),
+    ]);
+    expect(writeError?.message).toBe("disk gone");
+    expect(readError).toBeInstanceOf(ClavisError);
+    expect(readError?.message).toContain("disk gone");
+    await expect(writeStream(a, [], { chunkSize: a.maxPacketSize })).rejects.toThrow(ClavisError);
+  });
+
+  test("should discard the rest of a cancelled transfer", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const writing = writeStream(a, [new Uint8Array(5000)], { chunkSize: 100 });
+
+    const reader = readStream(b).getReader();
+    expect((await reader.read()).value).toHaveLength(100);
+    await reader.cancel();
+    await writing;
+
+    await a.writePacket(new RawPacket(new Uint8Array([2])));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([2]));
+  });
+});
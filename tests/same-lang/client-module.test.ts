@@ -0,0 +1,93 @@
+/**
+ * Client module tests - generated source, and a generated module talking over a live stream
+ */
+
+import { describe, test, expect, afterAll } from "bun:test";
+import { mkdtempSync, rmSync, writeFileSync } from "fs";
+import { tmpdir } from "os";
+import { join, resolve } from "path";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { defineSchema } from "../../src/schema.js";
+import { describeProtocol } from "../../src/protocol-doc.js";
+import { generateClientModule } from "../../src/client-module.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const root = mkdtempSync(join(tmpdir(), "clavis-client-module-"));
+afterAll(() => rmSync(root, { recursive: true, force: true }));
+
+const codec = createProtocolCodec(["join_room", "Leave", "Blob", "Failure"] as const, { errorVariant: "Failure" });
+const doc = describeProtocol(codec, {
+  title: "Chat",
+  variants: {
+    join_room: {
+      doc: "Sent once after connecting",
+      schema: defineSchema("join_room", [
+        { name: "room", type: "string" },
+        { name: "since", type: "u64", optional: true },
+      ]),
+    },
+    Leave: { schema: defineSchema("Leave", []) },
+    Failure: { schema: defineSchema("Failure", [{ name: "reason", type: "string" }]) },
+  },
+});
+
+describe("generateClientModule", () => {
+  test("should emit typed interfaces and helpers", () => {
+    const source = generateClientModule(doc);
+    expect(source).toContain('import { createProtocolCodec, RawPacket, defineSchema, encodeStruct, decodeStruct, type PhaseTransport } from "clavis-js";');
+    expect(source).toContain('{ errorVariant: "Failure" }');
+    expect(source).toContain("/** Sent once after connecting */\nexport interface JoinRoom {\n  room: string;\n  since?: bigint | undefined;\n}");
+    expect(source).toContain("export function sendJoinRoom(transport: PhaseTransport, value: JoinRoom): Promise<number>");
+    expect(source).toContain("export function sendLeave(transport: PhaseTransport): Promise<number>");
+    expect(source).toContain("export function encodeBlob(data?: Uint8Array): Uint8Array");
+    expect(source).toContain('  | { type: "Blob"; data: Uint8Array }');
+  });
+
+  test("should round-trip packets through the generated module", async () => {
+    const path = join(root, "chat.ts");
+    writeFileSync(path, generateClientModule(doc, { importFrom: resolve("src/index.ts") }));
+    const chat = await import(path);
+
+    const [client, server] = await createEncryptedStreamPair();
+    await chat.sendJoinRoom(client, { room: "lobby", since: 42n });
+    await chat.sendLeave(client);
+    await chat.sendBlob(client, new Uint8Array([7, 8]));
+
+    expect(await chat.receiveMessage(server)).toEqual({ type: "join_room", value: { room: "lobby", since: 42n } });
+    expect(await chat.receiveMessage(server)).toEqual({ type: "Leave" });
+    const blob = await chat.receiveMessage(server);
+    expect(blob.type).toBe("Blob");
+    expect(Array.from(blob.data)).toEqual([7, 8]);
+    expect(chat.decodeMessage(chat.encodeJoinRoom({ room: "x" }))).toEqual({
+      type: "join_room",
+      value: { room: "x", since: undefined },
+    });
+    await client.close();
+  });
+
+  test("should pin indices that don't follow from the previous variant", () => {
+    const codec = createProtocolCodec(["Hello", "Emote", "Move"] as const, { ids: { Emote: 10, Move: 1 }, deprecated: ["Move"] });
+    const source = generateClientModule(describeProtocol(codec, { title: "Game" }));
+    expect(source).toContain('{ ids: { "Emote": 10, "Move": 1 }, deprecated: ["Move"] }');
+    expect(source).toContain("/**\n * Send a Move packet\n * @deprecated\n */");
+  });
+
+  test("should accept a reference loaded from JSON", () => {
+    const fromJson = JSON.parse(JSON.stringify(doc));
+    expect(generateClientModule(fromJson, { typeName: "Command" })).toBe(generateClientModule(doc, { typeName: "Command" }));
+  });
+
+  test("should reject names that can't become distinct identifiers", () => {
+    const clashing = describeProtocol(createProtocolCodec(["join_room", "JoinRoom"] as const), { title: "x" });
+    expect(() => generateClientModule(clashing)).toThrow(ClavisError);
+    expect(() => generateClientModule(doc, { typeName: "Leave" })).toThrow(ClavisError);
+    expect(() => generateClientModule(doc, { typeName: "not valid" })).toThrow(ClavisError);
+  });
+
+  test("should reject fields that don't match their schema hash", () => {
+    const tampered = JSON.parse(JSON.stringify(doc));
+    tampered.variants[0].fields[0].type = "bytes";
+    expect(() => generateClientModule(tampered)).toThrow(ClavisError);
+  });
+});
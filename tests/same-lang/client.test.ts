@@ -0,0 +1,65 @@
+/**
+ * ClavisClient tests - trying several pre-shared keys during a rotation
+ */
+
+import { describe, test, expect } from "bun:test";
+import { ClavisClient, type Connector } from "../../src/client.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { ClavisError } from "../../src/error.js";
+import { createStreamPair } from "../helpers/test-utils.js";
+
+const oldKey = new TextEncoder().encode("old-pre-shared-key-2026");
+const newKey = new TextEncoder().encode("new-pre-shared-key-2026");
+
+/** A connector to an in-memory server holding `key`; counts the connections opened */
+function serverWith(key: Uint8Array): { connector: Connector; connections: () => number } {
+  let connections = 0;
+  const connector: Connector = async () => {
+    connections++;
+    const [left, right] = await createStreamPair();
+    EncryptedStream.new(right, { psk: key }).catch(() => undefined);
+    return left;
+  };
+  return { connector, connections: () => connections };
+}
+
+describe("ClavisClient PSK rollover", () => {
+  test("should try the new key first and fall back to the old one", async () => {
+    for (const [key, index, connections] of [[newKey, 0, 1], [oldKey, 1, 2]] as const) {
+      const server = serverWith(key);
+      const client = new ClavisClient({
+        host: "server",
+        port: 7000,
+        psks: [newKey, oldKey],
+        connector: server.connector,
+        reconnect: { enabled: false },
+      });
+      await client.connect();
+      expect(client.isConnected).toBe(true);
+      expect(client.pskIndex).toBe(index);
+      expect(server.connections()).toBe(connections);
+      await client.disconnect();
+    }
+  });
+
+  test("should fail once every key is refused, and reject ambiguous options", async () => {
+    const server = serverWith(new TextEncoder().encode("someone-elses-key-2026"));
+    const client = new ClavisClient({
+      host: "server",
+      port: 7000,
+      psks: [newKey, oldKey],
+      connector: server.connector,
+      reconnect: { enabled: false },
+    });
+    const errors: ClavisError[] = [];
+    client.on("error", (error) => errors.push(error));
+    await expect(client.connect()).rejects.toThrow(ClavisError);
+    expect(errors).toHaveLength(1);
+    expect(errors[0]!.isCryptoError()).toBe(true);
+    expect(server.connections()).toBe(2);
+    expect(client.pskIndex).toBeUndefined();
+
+    expect(() => new ClavisClient({ host: "server", port: 7000, psk: newKey, psks: [oldKey] })).toThrow(ClavisError);
+    expect(() => new ClavisClient({ host: "server", port: 7000, psks: [] })).toThrow(ClavisError);
+  });
+});
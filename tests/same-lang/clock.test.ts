@@ -0,0 +1,110 @@
+/**
+ * Clock tests - manual time drives timeouts and backoff without waiting
+ */
+
+import { describe, test, expect } from "bun:test";
+import { ManualClock, sleepOn } from "../../src/clock.js";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { RpcConnection, callWithRetry } from "../../src/rpc.js";
+import { StreamError, StreamErrorCode, ClavisError } from "../../src/error.js";
+import { createChaosPair } from "../../src/testing.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+describe("ManualClock", () => {
+  test("should fire timers in due order only when advanced", async () => {
+    const clock = new ManualClock();
+    const fired: string[] = [];
+    clock.setTimer(() => fired.push("b"), 20);
+    clock.setTimer(() => fired.push("a"), 10);
+    clock.setTimer(() => fired.push("c"), 20);
+
+    await clock.advance(5);
+    expect(fired).toEqual([]);
+
+    await clock.advance(15);
+    expect(fired).toEqual(["a", "b", "c"]);
+    expect(clock.now()).toBe(20);
+    expect(clock.pendingTimers).toBe(0);
+  });
+
+  test("should not fire cancelled timers", async () => {
+    const clock = new ManualClock();
+    let fired = false;
+    clock.setTimer(() => (fired = true), 10).cancel();
+
+    await clock.advance(100);
+    expect(fired).toBe(false);
+  });
+
+  test("should fire timers scheduled by earlier timers in the same advance", async () => {
+    const clock = new ManualClock();
+    const times: number[] = [];
+    const tick = async () => {
+      for (let i = 0; i < 3; i++) {
+        await sleepOn(clock, 100);
+        times.push(clock.now());
+      }
+    };
+    const done = tick();
+
+    await clock.advance(1_000);
+    await done;
+    expect(times).toEqual([100, 200, 300]);
+  });
+});
+
+describe("Clock threading", () => {
+  test("should time out RPC calls on a manual clock", async () => {
+    const codec = createProtocolCodec(["GetStatus", "Status"] as const);
+    const clock = new ManualClock();
+    const [a, b] = await createEncryptedStreamPair();
+    const { reader, writer } = a.split();
+    b.split();
+    const rpc = new RpcConnection(reader, writer, { codec, clock }).start();
+
+    const call = rpc.call("GetStatus", undefined, { timeoutMs: 60_000 });
+    const outcome = call.catch((error: unknown) => error);
+
+    await clock.advance(59_999);
+    expect(rpc.pendingCalls).toBe(1);
+
+    await clock.advance(1);
+    const error = await outcome;
+    expect(error).toBeInstanceOf(ClavisError);
+    expect(((error as ClavisError).cause as StreamError).code).toBe(StreamErrorCode.Timeout);
+  });
+
+  test("should wait for retry backoff on the given clock", async () => {
+    const clock = new ManualClock();
+    let attempts = 0;
+    const result = callWithRetry(
+      async () => {
+        attempts++;
+        if (attempts < 3) throw ClavisError.stream(StreamError.timeout(1));
+        return "ok";
+      },
+      { idempotent: true, retry: { maxAttempts: 3, initialDelayMs: 10_000 } },
+      clock
+    );
+
+    await clock.advance(9_999);
+    expect(attempts).toBe(1);
+    await clock.advance(1 + 20_000);
+    expect(await result).toBe("ok");
+    expect(attempts).toBe(3);
+  });
+
+  test("should deliver chaos link data on a manual clock", async () => {
+    const clock = new ManualClock();
+    const [a, b] = createChaosPair({ latencyMs: 600 }, {}, clock);
+    const chunks: string[] = [];
+    b.on("data", (chunk: Buffer) => chunks.push(chunk.toString()));
+
+    a.write(Buffer.from("hello"));
+    await clock.advance(599);
+    expect(chunks).toEqual([]);
+
+    await clock.advance(1);
+    expect(chunks).toEqual(["hello"]);
+  });
+});
@@ -0,0 +1,69 @@
+/**
+ * Record layer codec tests - codecs on hand-made keys, and a web stream pipeline against a stream
+ */
+
+import { describe, test, expect } from "bun:test";
+import { ClavisCodec, clavisFramed } from "../../src/codec.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { transportStream, webStreamTransport } from "../../src/web.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+
+const psk = new Uint8Array(32).fill(6);
+
+function codecPair(maxPacketSize?: number): [ClavisCodec, ClavisCodec] {
+  const [k1, k2] = [new Uint8Array(32).fill(1), new Uint8Array(32).fill(2)];
+  return [
+    new ClavisCodec({ encKey: k1, decKey: k2 }, { maxPacketSize }),
+    new ClavisCodec({ encKey: k2, decKey: k1 }, { maxPacketSize }),
+  ];
+}
+
+describe("ClavisCodec", () => {
+  test("should decode frames fed a byte at a time and stop at the close frame", () => {
+    const [a, b] = codecPair();
+    const frames = [a.encode(new RawPacket(new Uint8Array([1, 2]))), a.encode(new Uint8Array([3])), a.encodeClose()];
+    const packets: Uint8Array[] = [];
+    for (const frame of frames) {
+      for (const byte of frame) {
+        b.push(new Uint8Array([byte]));
+        for (let packet = b.decode(); packet; packet = b.decode()) packets.push(packet);
+      }
+    }
+    expect(packets).toEqual([new Uint8Array([1, 2]), new Uint8Array([3])]);
+    expect(b.closed).toBe(true);
+    expect(() => a.encode(new Uint8Array([4]))).toThrow(ClavisError);
+    expect(() => b.push(new Uint8Array([0]))).toThrow(ClavisError);
+  });
+
+  test("should refuse oversized packets and tampered frames", () => {
+    const [a, b] = codecPair(4);
+    expect(() => a.encode(new Uint8Array(5))).toThrow(ClavisError);
+    const frame = a.encode(new Uint8Array([1, 2, 3, 4]));
+    frame[frame.length - 1] = frame[frame.length - 1]! ^ 1;
+    b.push(frame);
+    expect(() => b.decode()).toThrow(ClavisError);
+  });
+
+  test("should run in a web stream pipeline against an EncryptedStream", async () => {
+    const there = new TransformStream<Uint8Array, Uint8Array>();
+    const back = new TransformStream<Uint8Array, Uint8Array>();
+    const [framed, server] = await Promise.all([
+      clavisFramed({ readable: back.readable, writable: there.writable }, { psk }),
+      transportStream(webStreamTransport({ readable: there.readable, writable: back.writable }))
+        .then((duplex) => EncryptedStream.new(duplex, { psk })),
+    ]);
+
+    const writer = framed.writable.getWriter();
+    await writer.write(new RawPacket(new TextEncoder().encode("ping")));
+    expect(new TextDecoder().decode((await server.readPacket()) as unknown as Uint8Array)).toBe("ping");
+
+    // Layered under another codec
+    await server.writePacket(new RawPacket(new TextEncoder().encode("po")));
+    await server.writePacket(new RawPacket(new TextEncoder().encode("ng")));
+    await server.close();
+    let text = "";
+    for await (const chunk of framed.readable.pipeThrough(new TextDecoderStream())) text += chunk;
+    expect(text).toBe("pong");
+  });
+});
@@ -0,0 +1,151 @@
+/**
+ * Compression tests - shared dictionaries and algorithms negotiated after the handshake
+ */
+
+import { describe, test, expect } from "bun:test";
+import {
+  PacketCompressor,
+  buildDictionary,
+  createDictionary,
+  decodeCompressionAlgorithms,
+  decodeCompressionOffer,
+  encodeCompressionOffer,
+  supportedCompressionAlgorithms,
+} from "../../src/compression.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError, DecompressionLimit } from "../../src/error.js";
+import { wireSize } from "../../src/stream.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const encoder = new TextEncoder();
+const samples = [
+  '{"type":"chat","room":"general","user":"alice","text":"hello everyone"}',
+  '{"type":"chat","room":"general","user":"bob","text":"hi alice"}',
+  '{"type":"presence","room":"general","user":"carol","status":"online"}',
+].map((s) => encoder.encode(s));
+const dictionary = buildDictionary(samples);
+const message = encoder.encode('{"type":"chat","room":"general","user":"dave","text":"hello"}');
+
+describe("Compression dictionaries", () => {
+  test("should identify dictionaries by content hash", () => {
+    expect(createDictionary(dictionary.bytes).id).toBe(dictionary.id);
+    expect(dictionary.id).toHaveLength(16);
+    expect(buildDictionary([encoder.encode("other")]).id).not.toBe(dictionary.id);
+    expect(() => createDictionary(new Uint8Array(0))).toThrow(ClavisError);
+  });
+
+  test("should round-trip compression offers", () => {
+    const other = createDictionary(encoder.encode("another dictionary"));
+    const offer = encodeCompressionOffer({ dictionaries: [dictionary, other] });
+    expect(decodeCompressionOffer(offer)).toEqual([dictionary.id, other.id]);
+    expect(() => decodeCompressionOffer(message)).toThrow(ClavisError);
+  });
+
+  test("should agree on the same dictionary from either side", () => {
+    const a = createDictionary(encoder.encode("dictionary a"));
+    const b = createDictionary(encoder.encode("dictionary b"));
+    const left = PacketCompressor.negotiate({ dictionaries: [b, a] }, [a.id, b.id]);
+    const right = PacketCompressor.negotiate({ dictionaries: [a, b] }, [b.id, a.id]);
+    expect(left.dictionary?.id).toBe(right.dictionary!.id);
+  });
+
+  test("should refuse to inflate past the maximum packet size", () => {
+    const compressor = PacketCompressor.negotiate({ dictionaries: [dictionary] }, [dictionary.id]);
+    const compressed = compressor.compress(new Uint8Array(10_000));
+
+    expect(compressed.length).toBeLessThan(100);
+    expect(compressor.decompress(compressed, 10_000)).toEqual(new Uint8Array(10_000));
+    expect(() => compressor.decompress(compressed, 100)).toThrow(ClavisError);
+  });
+
+  test("should refuse compression bombs below the maximum packet size", () => {
+    const compression = { algorithms: ["deflate"] as const, maxDecompressedSize: 1024 * 1024 };
+    const compressor = PacketCompressor.negotiate({ ...compression, maxRatio: 10_000 }, [], ["deflate"]);
+    const bomb = compressor.compress(new Uint8Array(4 * 1024 * 1024));
+    const limitOf = (run: () => unknown) => {
+      try {
+        run();
+      } catch (error) {
+        return (error as ClavisError).decompressionLimit;
+      }
+    };
+    expect(limitOf(() => compressor.decompress(bomb, 64 * 1024 * 1024))).toBe(DecompressionLimit.Size);
+
+    const strict = PacketCompressor.negotiate({ ...compression, maxRatio: 10 }, [], ["deflate"]);
+    const packet = strict.compress(new Uint8Array(512 * 1024));
+    expect(limitOf(() => strict.decompress(packet, 64 * 1024 * 1024))).toBe(DecompressionLimit.Ratio);
+    // Small packets are exempt from the ratio
+    expect(strict.decompress(strict.compress(new Uint8Array(10_000)), 64 * 1024)).toEqual(new Uint8Array(10_000));
+    // The packet size limit still fails as before
+    expect(limitOf(() => strict.decompress(packet, 100))).toBeUndefined();
+  });
+});
+
+describe("Compressed streams", () => {
+  test("should compress packets with the negotiated dictionary", async () => {
+    const compression = { dictionaries: [dictionary] };
+    const [a, b] = await createEncryptedStreamPair({ compression }, { compression });
+    expect(a.compressionDictionary).toBe(dictionary.id);
+    expect(b.compressionDictionary).toBe(dictionary.id);
+
+    const packet = new RawPacket(message);
+    const written = await a.writePacket(packet);
+    expect(written).toBeLessThan(wireSize(packet));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(message);
+  });
+
+  test("should fall back to stored packets without a shared dictionary", async () => {
+    const [a, b] = await createEncryptedStreamPair(
+      { compression: { dictionaries: [dictionary] } },
+      { compression: {} }
+    );
+    expect(a.compressionDictionary).toBeUndefined();
+
+    const packet = new RawPacket(message);
+    expect(await a.writePacket(packet)).toBe(wireSize(packet) + 1);
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(message);
+  });
+
+  test("should compress large packets with an algorithm both sides offer", async () => {
+    const compression = { algorithms: ["zstd", "deflate"] as const, minSize: 64 };
+    const [a, b] = await createEncryptedStreamPair({ compression }, { compression });
+    expect(a.compressionAlgorithm).toBe(supportedCompressionAlgorithms()[0]!);
+    expect(a.compressionDictionary).toBeUndefined();
+
+    const blob = encoder.encode(JSON.stringify(Array.from({ length: 50 }, (_, i) => ({ id: i, status: "ok" }))));
+    const large = new RawPacket(blob);
+    expect(await a.writePacket(large)).toBeLessThan(wireSize(large));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(blob);
+
+    const small = new RawPacket(encoder.encode("tiny"));
+    expect(await a.writePacket(small)).toBe(wireSize(small) + 1);
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(encoder.encode("tiny"));
+  });
+
+  test("should send with its own preference among the peer's algorithms", async () => {
+    const [a, b] = await createEncryptedStreamPair(
+      { compression: { algorithms: ["deflate"] } },
+      { compression: { algorithms: [...supportedCompressionAlgorithms()].reverse() } }
+    );
+    expect(a.compressionAlgorithm).toBe("deflate");
+    expect(b.compressionAlgorithm).toBe("deflate");
+
+    const [c] = await createEncryptedStreamPair({ compression: { algorithms: ["deflate"] } }, { compression: {} });
+    expect(c.compressionAlgorithm).toBeUndefined();
+  });
+});
+
+describe("Compression offers with algorithms", () => {
+  test("should keep the original format without algorithms", () => {
+    const offer = encodeCompressionOffer({ dictionaries: [dictionary] });
+    expect(offer[4]).toBe(1);
+    expect(decodeCompressionAlgorithms(offer)).toEqual([]);
+  });
+
+  test("should round-trip algorithms next to dictionaries", () => {
+    const offer = encodeCompressionOffer({ dictionaries: [dictionary], algorithms: ["deflate", "deflate"] });
+    expect(decodeCompressionOffer(offer)).toEqual([dictionary.id]);
+    expect(decodeCompressionAlgorithms(offer)).toEqual(["deflate"]);
+    expect(() => decodeCompressionOffer(offer.subarray(0, offer.length - 1))).toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,87 @@
+/**
+ * Configuration audit tests - the security posture reported for stream options
+ */
+
+import { describe, test, expect } from "bun:test";
+import { auditStreamOptions } from "../../src/config-audit.js";
+import { DEFAULT_MAX_PACKET_SIZE } from "../../src/stream.js";
+import { setAuditMode } from "../../src/audit.js";
+import { generateEd25519KeyPair } from "../../src/crypto.js";
+import { ClavisError } from "../../src/error.js";
+import { allowNullCipher } from "../../src/null-cipher.js";
+import { allowKeyLog } from "../../src/keylog.js";
+
+describe("auditStreamOptions", () => {
+  test("should flag an unauthenticated default configuration", () => {
+    const report = auditStreamOptions();
+    expect(report.cipher).toEqual({
+      suites: ["xchacha20-poly1305"],
+      negotiated: false,
+      nullCipher: false,
+      postQuantum: "off",
+      keyExchange: "in-memory",
+    });
+    expect(report.authentication.mode).toBe("none");
+    expect(report.limits.maxPacketSize).toBe(DEFAULT_MAX_PACKET_SIZE);
+    expect(report.limits.handshakeTimeoutMs).toBeNull();
+    expect(report.findings.filter((f) => f.severity === "danger").map((f) => f.message)).toEqual([
+      "Peers are not authenticated: set psk, psks or a way to verify the peer's identity",
+    ]);
+    expect(report.dangerous).toEqual([]);
+  });
+
+  test("should describe a hardened configuration without leaking its keys", () => {
+    setAuditMode(true);
+    try {
+      const secretKey = generateEd25519KeyPair().secretKey;
+      const report = auditStreamOptions({
+        psk: "correct horse battery staple",
+        identity: { secretKey },
+        verifyPeer: () => true,
+        cipherSuites: ["aes-256-gcm", "xchacha20-poly1305"],
+        rekeyAfterBytes: 1 << 30,
+        handshakeTimeoutMs: 5000,
+        keepAliveMs: 10_000,
+        padding: { coverIntervalMs: 1000 },
+      });
+      expect(report.authentication).toMatchObject({
+        mode: "psk+identity",
+        psk: "single",
+        identity: "static-key",
+        identityKeyExternal: false,
+        peerVerification: ["callback"],
+      });
+      expect(report.cipher.negotiated).toBe(true);
+      expect(report.limits.idleTimeoutMs).toBe(30_000);
+      expect(report.features).toMatchObject({ padding: true, coverTraffic: true, compression: false });
+      expect(report.redaction).toBe(true);
+      expect(report.findings).toEqual([]);
+
+      const json = JSON.stringify(report);
+      expect(json).not.toContain("correct horse");
+      expect(json).not.toContain("secretKey");
+    } finally {
+      setAuditMode(false);
+    }
+  });
+
+  test("should list dangerous flags and refuse options that can't run", () => {
+    expect(() => auditStreamOptions({ dangerousNullCipher: true })).toThrow(ClavisError);
+    expect(() => auditStreamOptions({ keyLog: () => undefined })).toThrow(ClavisError);
+    allowNullCipher(true);
+    allowKeyLog(true);
+    try {
+      const report = auditStreamOptions({ psk: "k", dangerousNullCipher: true, keyLog: () => undefined });
+      expect(report.dangerous).toEqual(["dangerousNullCipher", "keyLog"]);
+      expect(report.findings.filter((f) => f.severity === "danger").map((f) => f.options)).toEqual([
+        ["dangerousNullCipher"],
+        ["keyLog"],
+      ]);
+    } finally {
+      allowNullCipher(false);
+      allowKeyLog(false);
+    }
+
+    expect(() => auditStreamOptions({ dangerousNullCipher: true, postQuantum: "require" })).toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,127 @@
+/**
+ * Sans-IO connection tests - two machines driven by hand, and one against a stream
+ */
+
+import { describe, test, expect } from "bun:test";
+import { ClavisConnection } from "../../src/connection.js";
+import { HandshakeMachine } from "../../src/handshake.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createStreamPair } from "../helpers/test-utils.js";
+
+const psk = new Uint8Array(32).fill(3);
+
+/** Move each side's output to the other, one byte at a time, until both are quiet */
+function pump(a: ClavisConnection, b: ClavisConnection): void {
+  for (;;) {
+    const fromA = a.takeOutput();
+    const fromB = b.takeOutput();
+    if (fromA.length === 0 && fromB.length === 0) return;
+    for (const byte of fromA) b.feedBytes(new Uint8Array([byte]));
+    for (const byte of fromB) a.feedBytes(new Uint8Array([byte]));
+  }
+}
+
+describe("HandshakeMachine", () => {
+  test("should stop consuming where the handshake ends", () => {
+    const a = new HandshakeMachine();
+    const b = new HandshakeMachine();
+    const nonceA = a.takeOutput();
+    a.feedBytes(b.takeOutput());
+    b.feedBytes(nonceA);
+
+    // Only the initiator has its public key to send yet
+    const fromA = a.takeOutput();
+    const fromB = b.takeOutput();
+    const [initiator, responder, key] = fromA.length > 0 ? [a, b, fromA] : [b, a, fromB];
+    const chunk = new Uint8Array(key.length + 2);
+    chunk.set(key, 0);
+    expect(responder.feedBytes(chunk)).toBe(key.length);
+    expect(responder.done).toBe(true);
+    initiator.feedBytes(responder.takeOutput());
+
+    expect(initiator.result().initiator).toBe(true);
+    expect(initiator.result().encKey).toEqual(responder.result().decKey);
+    expect(initiator.bytesNeeded).toBe(0);
+  });
+
+  test("should fail on a peer with another PSK", () => {
+    const a = new HandshakeMachine(psk);
+    const b = new HandshakeMachine(new Uint8Array(32).fill(4));
+    const failures: unknown[] = [];
+    for (let round = 0; round < 4; round++) {
+      for (const [from, to] of [[a, b], [b, a]] as const) {
+        try {
+          to.feedBytes(from.takeOutput());
+        } catch (error) {
+          failures.push(error);
+        }
+      }
+    }
+    expect(failures.length).toBeGreaterThan(0);
+    expect(failures.every((error) => error instanceof ClavisError)).toBe(true);
+    expect(() => a.result()).toThrow(ClavisError);
+    expect(() => new HandshakeMachine(new Uint8Array(8))).toThrow(ClavisError);
+  });
+});
+
+describe("ClavisConnection", () => {
+  test("should exchange packets, pings and close without any I/O", () => {
+    const a = new ClavisConnection({ psk });
+    const b = new ClavisConnection({ psk });
+    expect(() => a.send(new RawPacket(new Uint8Array([1])))).toThrow("handshake has not finished");
+    pump(a, b);
+    expect(a.established && b.established).toBe(true);
+    expect(a.handshakeResult.transcriptHash).toEqual(b.handshakeResult.transcriptHash);
+
+    a.send(new RawPacket(new Uint8Array([1, 2])));
+    a.send(new RawPacket(new Uint8Array([3])));
+    b.ping(7);
+    pump(a, b);
+    expect(b.receive()).toEqual(new Uint8Array([1, 2]));
+    expect(b.receive()).toEqual(new Uint8Array([3]));
+    expect(b.receive()).toBeUndefined();
+
+    a.close();
+    pump(a, b);
+    expect(b.closed).toBe(true);
+    expect(() => a.send(new RawPacket(new Uint8Array([4])))).toThrow(ClavisError);
+  });
+
+  test("should refuse oversized packets and tampered frames", () => {
+    const a = new ClavisConnection({ maxPacketSize: 4 });
+    const b = new ClavisConnection({ maxPacketSize: 4 });
+    pump(a, b);
+    expect(() => a.send(new RawPacket(new Uint8Array(5)))).toThrow("exceeds the limit of 4");
+
+    a.send(new RawPacket(new Uint8Array([1])));
+    const frame = a.takeOutput();
+    frame[frame.length - 1] = frame[frame.length - 1]! ^ 1;
+    expect(() => b.feedBytes(frame)).toThrow(ClavisError);
+    // The connection stays failed
+    expect(() => b.feedBytes(new Uint8Array(0))).toThrow(ClavisError);
+  });
+
+  test("should talk to an EncryptedStream", async () => {
+    const [left, right] = await createStreamPair();
+    const connection = new ClavisConnection();
+    const flush = () => {
+      const output = connection.takeOutput();
+      if (output.length > 0) left.write(output);
+    };
+    left.on("data", (chunk: Uint8Array) => {
+      connection.feedBytes(chunk);
+      for (let packet = connection.receive(); packet; packet = connection.receive()) {
+        connection.send(new RawPacket(packet.map((byte) => byte + 1)));
+      }
+      flush();
+    });
+    flush();
+
+    const stream = await EncryptedStream.new(right);
+    await stream.writePacket(new RawPacket(new Uint8Array([1, 2, 3])));
+    expect((await stream.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([2, 3, 4]));
+    expect(connection.established).toBe(true);
+  });
+});
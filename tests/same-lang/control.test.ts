@@ -0,0 +1,151 @@
+/**
+ * Control frame tests - renegotiating the max packet size in flight, and
+ * the per-direction limits advertised during setup
+ */
+
+import { describe, test, expect } from "bun:test";
+import {
+  ControlFrameKind,
+  decodeControlFrame,
+  decodeControlU32,
+  encodeControlFrame,
+  encodeControlU32,
+} from "../../src/control.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+describe("Control frames", () => {
+  test("should round-trip control frames", () => {
+    const frame = decodeControlFrame(encodeControlFrame(ControlFrameKind.ResizeRequest, encodeControlU32(1 << 20)));
+    expect(frame.kind).toBe(ControlFrameKind.ResizeRequest);
+    expect(decodeControlU32(frame.payload)).toBe(1 << 20);
+    expect(() => decodeControlFrame(new Uint8Array([0]))).toThrow(ClavisError);
+    expect(() => decodeControlU32(new Uint8Array(3))).toThrow(ClavisError);
+  });
+});
+
+describe("requestMaxPacketSize", () => {
+  test("should raise the limit when the peer allows it", async () => {
+    const [a, b] = await createEncryptedStreamPair({}, { maxNegotiablePacketSize: 1 << 20 });
+    const aRead = a.readPacket();
+    const bRead = b.readPacket();
+
+    expect(await a.requestMaxPacketSize(200_000)).toBe(200_000);
+    expect(a.maxPacketSize).toBe(200_000);
+    expect(b.maxPacketSize).toBe(200_000);
+
+    const big = new Uint8Array(150_000).fill(7);
+    await a.writePacket(new RawPacket(big));
+    expect((await bRead) as unknown as Uint8Array).toEqual(big);
+
+    await b.writePacket(new RawPacket(big));
+    expect((await aRead) as unknown as Uint8Array).toEqual(big);
+  });
+
+  test("should keep the limit when the peer refuses", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const bRead = b.readPacket();
+    const aRead = a.readPacket();
+
+    await expect(a.requestMaxPacketSize(200_000)).rejects.toThrow(ClavisError);
+    expect(a.maxPacketSize).toBe(65536);
+    expect(b.maxPacketSize).toBe(65536);
+
+    const big = new Uint8Array(100_000);
+    await expect(a.writePacket(new RawPacket(big))).rejects.toThrow(ClavisError);
+
+    // The connection stays usable
+    const packet = new RawPacket(new Uint8Array([1, 2, 3]));
+    await a.writePacket(packet);
+    expect((await bRead) as unknown as Uint8Array).toEqual(packet.bytes);
+    await b.writePacket(packet);
+    expect((await aRead) as unknown as Uint8Array).toEqual(packet.bytes);
+  });
+
+  test("should lower the limit for both sides", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const { reader, writer } = a.split();
+    const aRead = reader.readPacket();
+    const bRead = b.readPacket();
+
+    const pending = writer.requestMaxPacketSize(1024);
+    // Our own packets shrink before the peer answers
+    expect(writer.maxPacketSize).toBe(1024);
+    expect(await pending).toBe(1024);
+    expect(b.maxPacketSize).toBe(1024);
+
+    const medium = new RawPacket(new Uint8Array(2000));
+    await expect(writer.writePacket(medium)).rejects.toThrow(ClavisError);
+    await expect(b.writePacket(medium)).rejects.toThrow(ClavisError);
+
+    const small = new RawPacket(new Uint8Array(1000));
+    await writer.writePacket(small);
+    expect((await bRead) as unknown as Uint8Array).toEqual(small.bytes);
+    await b.writePacket(small);
+    expect((await aRead) as unknown as Uint8Array).toEqual(small.bytes);
+  });
+
+  test("should allow one request at a time", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const aRead = a.readPacket();
+    const bRead = b.readPacket();
+
+    const first = a.requestMaxPacketSize(4096);
+    await expect(a.requestMaxPacketSize(2048)).rejects.toThrow(ClavisError);
+    await expect(a.requestMaxPacketSize(0)).rejects.toThrow(ClavisError);
+    expect(await first).toBe(4096);
+
+    const packet = new RawPacket(new Uint8Array([9]));
+    await a.writePacket(packet);
+    await b.writePacket(packet);
+    await Promise.all([aRead, bRead]);
+  });
+});
+
+describe("negotiatePacketSize", () => {
+  test("should hold each side to the receive limit the peer advertised", async () => {
+    const [a, b] = await createEncryptedStreamPair(
+      { maxPacketSize: 4096, negotiatePacketSize: true },
+      { maxPacketSize: 1024, negotiatePacketSize: true }
+    );
+    expect(a.peerMaxPacketSize).toBe(1024);
+    expect(b.peerMaxPacketSize).toBe(4096);
+
+    const medium = new RawPacket(new Uint8Array(2000));
+    const error = await a.writePacket(medium).catch((e: ClavisError) => e);
+    expect(error).toBeInstanceOf(ClavisError);
+    expect((error as ClavisError).packetTooLarge).toEqual({ size: 2000, limit: 1024, variant: undefined, peerLimit: true });
+    expect((error as ClavisError).message).toContain("peer's receive limit of 1024");
+
+    await b.writePacket(medium);
+    expect((await a.readPacket()) as unknown as Uint8Array).toEqual(medium.bytes);
+  });
+
+  test("should fail setup when the peer sends something else", async () => {
+    await expect(
+      createEncryptedStreamPair({ negotiatePacketSize: true, protocolVersion: 1 }, { protocolVersion: 1 })
+    ).rejects.toThrow(ClavisError);
+  });
+});
+
+describe("sequenceNumbers", () => {
+  test("should number frames the same way on both sides", async () => {
+    const [a, b] = await createEncryptedStreamPair({ sequenceNumbers: true }, { sequenceNumbers: true });
+    for (let i = 0; i < 3; i++) await a.writePacket(new RawPacket(new Uint8Array([i])));
+    for (let i = 0; i < 3; i++) expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([i]));
+    await b.writePacket(new RawPacket(new Uint8Array([9])));
+    await a.readPacket();
+
+    expect(b.lastReceivedSequence).toBe(a.lastSentSequence);
+    expect(a.lastReceivedSequence).toBe(b.lastSentSequence);
+    // The setup announcement is frame 0
+    expect(a.lastSentSequence).toBe(3);
+  });
+
+  test("should fail setup when only one side numbers frames", async () => {
+    await expect(
+      createEncryptedStreamPair({ sequenceNumbers: true, protocolVersion: 1 }, { protocolVersion: 1 })
+    ).rejects.toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,46 @@
+/**
+ * Core entry point tests - the minimal build stays free of optional features
+ */
+
+import { describe, test, expect } from "bun:test";
+import { readFileSync } from "fs";
+import { join } from "path";
+
+const src = join(import.meta.dir, "../../src");
+
+/** Optional features; none may load through "clavis-js/core" */
+const OPTIONAL = [
+  "compression", "identity", "x509", "tickets", "resumption", "replay", "storage", "journal",
+  "router", "rpc", "pool", "service", "phases", "schema", "client", "listener", "broadcast",
+];
+
+/** Modules loaded at import time: static value imports only, not `import type` or `import()` */
+function staticImports(module: string): string[] {
+  const source = readFileSync(join(src, `${module}.ts`), "utf8");
+  const pattern = /^(?:import|export)\s+(?!type\s)[^;]*?from\s+"\.\/([\w-]+)\.js";/gms;
+  return [...source.matchAll(pattern)].map((match) => match[1]!);
+}
+
+describe("clavis-js/core", () => {
+  test("should not load optional features", () => {
+    const loaded = new Set<string>();
+    const queue = ["core"];
+    while (queue.length > 0) {
+      const module = queue.pop()!;
+      if (loaded.has(module)) continue;
+      loaded.add(module);
+      queue.push(...staticImports(module));
+    }
+    expect(loaded.has("stream")).toBe(true);
+    expect(OPTIONAL.filter((feature) => loaded.has(feature))).toEqual([]);
+  });
+
+  test("should still connect", async () => {
+    const { EncryptedStream, RawPacket } = await import("../../src/core.js");
+    const { createStreamPair } = await import("../helpers/test-utils.js");
+    const [a, b] = await createStreamPair();
+    const [client, server] = await Promise.all([EncryptedStream.new(a), EncryptedStream.new(b)]);
+    await client.writePacket(new RawPacket(new Uint8Array([5])));
+    expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([5]));
+  });
+});
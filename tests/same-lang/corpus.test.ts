@@ -0,0 +1,81 @@
+/**
+ * Fuzz corpus capture tests - anonymization, corpus layout and capture from a live stream
+ */
+
+import { describe, test, expect, afterAll } from "bun:test";
+import { mkdtempSync, readFileSync, readdirSync, rmSync } from "fs";
+import { tmpdir } from "os";
+import { join } from "path";
+import { CorpusCapture, FUZZ_FRAME_KEY, corpusEntryName, scrubStrings } from "../../src/corpus.js";
+import { decodeFrame, openFrame } from "../../src/frame.js";
+import { XChaCha20Poly1305Cipher } from "../../src/crypto.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const root = mkdtempSync(join(tmpdir(), "clavis-corpus-"));
+afterAll(() => rmSync(root, { recursive: true, force: true }));
+
+let dirs = 0;
+const corpusDir = () => join(root, String(dirs++));
+const text = (s: string) => new TextEncoder().encode(s);
+
+describe("scrubStrings", () => {
+  test("should overwrite text runs and keep every other byte", () => {
+    const packet = new Uint8Array([1, 0, 0, 0, 5, 0, ...text("alice"), 2, ...text("ok"), 0xff]);
+    expect(scrubStrings(packet)).toEqual(new Uint8Array([1, 0, 0, 0, 5, 0, ...text("xxxxx"), 2, ...text("ok"), 0xff]));
+    expect(packet[6]).toBe(0x61);
+  });
+});
+
+describe("CorpusCapture", () => {
+  test("should write each target once per distinct input, named by SHA-1", async () => {
+    const directory = corpusDir();
+    const capture = new CorpusCapture({ directory });
+    capture.capture(text("secret-token"));
+    capture.capture(text("secret-value"));
+    await capture.flush();
+
+    const packets = readdirSync(join(directory, "packet"));
+    expect(packets).toEqual([corpusEntryName(text("xxxxxxxxxxxx"))]);
+    expect(readFileSync(join(directory, "packet", packets[0]!))).toEqual(Buffer.from("xxxxxxxxxxxx"));
+
+    const [frame] = readdirSync(join(directory, "frame"));
+    const sealed = decodeFrame(new Uint8Array(readFileSync(join(directory, "frame", frame!))));
+    expect(openFrame(new XChaCha20Poly1305Cipher(FUZZ_FRAME_KEY), sealed)).toEqual(text("xxxxxxxxxxxx"));
+    expect(capture.counts()).toEqual({ written: 2, duplicates: 2, skipped: 0, failed: 0 });
+  });
+
+  test("should honour sampling, size limits, entry limits and existing entries", async () => {
+    const directory = corpusDir();
+    const first = new CorpusCapture({ directory, targets: { packet: "serde" }, maxInputSize: 4, maxEntries: 2 });
+    first.capture(new Uint8Array([1]));
+    first.capture(new Uint8Array(5));
+    await first.flush();
+    expect(first.counts()).toMatchObject({ written: 1, skipped: 1 });
+
+    const second = new CorpusCapture({ directory, targets: { packet: "serde" }, maxEntries: 2 });
+    for (const byte of [1, 2, 3]) {
+      second.capture(new Uint8Array([byte]));
+      await second.flush();
+    }
+    expect(second.counts()).toEqual({ written: 1, duplicates: 1, skipped: 1, failed: 0 });
+    expect(readdirSync(directory)).toEqual(["serde"]);
+
+    const sampled = new CorpusCapture({ directory: corpusDir(), sampleRate: 0.5, random: () => 0.7 });
+    sampled.capture(new Uint8Array([1]));
+    expect(sampled.counts().skipped).toBe(1);
+    expect(() => new CorpusCapture({ directory, sampleRate: 2 })).toThrow(ClavisError);
+  });
+
+  test("should capture application packets read by a stream but not setup", async () => {
+    const directory = corpusDir();
+    const capture = new CorpusCapture({ directory, targets: { packet: "packet" }, anonymize: (p) => p.slice() });
+    const [a, b] = await createEncryptedStreamPair({ protocolVersion: 1 }, { protocolVersion: 1, corpusCapture: capture });
+    await a.writePacket(new RawPacket(new Uint8Array([7, 7])));
+    await b.readPacket();
+    await capture.flush();
+
+    expect(readdirSync(join(directory, "packet"))).toEqual([corpusEntryName(new Uint8Array([7, 7]))]);
+  });
+});
@@ -0,0 +1,76 @@
+/**
+ * Corruption monitoring tests - classification, counts per connection and alarm windows
+ */
+
+import { describe, test, expect } from "bun:test";
+import { CorruptionMonitor, corruptionKind, type CorruptionEvent } from "../../src/corruption.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { ClavisError, CryptoOperation, MessageError, StreamError } from "../../src/error.js";
+import { ManualClock } from "../../src/clock.js";
+import { HostilePeer } from "../helpers/hostile-peer.js";
+import { createStreamPair, sleep } from "../helpers/test-utils.js";
+
+const macFailure = () => ClavisError.cryptoFailure(CryptoOperation.Decryption, "tag mismatch");
+
+describe("corruptionKind", () => {
+  test("should sort read failures into kinds", () => {
+    expect(corruptionKind(macFailure())).toBe("macFailure");
+    expect(corruptionKind(ClavisError.message(MessageError.messageTooLarge(1 << 30, 65536)))).toBe("framing");
+    expect(corruptionKind(ClavisError.stream(StreamError.unexpectedClose()))).toBe("truncated");
+    expect(corruptionKind(ClavisError.stream(StreamError.connectionClosed()))).toBeUndefined();
+    expect(corruptionKind(ClavisError.cryptoFailure(CryptoOperation.Handshake, "bad key"))).toBeUndefined();
+  });
+});
+
+describe("CorruptionMonitor", () => {
+  test("should fire an alarm once more than count events land in the window", () => {
+    const clock = new ManualClock();
+    const alarms: CorruptionEvent[][] = [];
+    const monitor = new CorruptionMonitor({
+      clock,
+      alarms: [{ kind: "macFailure", count: 2, windowMs: 1000, onAlarm: (events) => alarms.push(events) }],
+    });
+
+    monitor.observe(macFailure(), "a");
+    clock.advance(600);
+    monitor.observe(macFailure(), "b");
+    clock.advance(600);
+    // The first failure has left the window
+    monitor.observe(macFailure(), "a");
+    expect(alarms).toHaveLength(0);
+
+    monitor.observe(ClavisError.stream(StreamError.unexpectedClose()), "a");
+    expect(alarms).toHaveLength(0);
+    monitor.observe(macFailure(), "c");
+    expect(alarms).toHaveLength(1);
+    expect(alarms[0]!.map((event) => event.connectionId)).toEqual(["b", "a", "c"]);
+
+    expect(monitor.counts()).toEqual({ macFailures: 4, framingErrors: 0, truncatedFrames: 1 });
+    expect(monitor.countsFor("a")).toEqual({ macFailures: 2, framingErrors: 0, truncatedFrames: 1 });
+    expect(monitor.countsFor("d")).toEqual({ macFailures: 0, framingErrors: 0, truncatedFrames: 0 });
+    expect(() => new CorruptionMonitor({ alarms: [{ count: 1, windowMs: 0, onAlarm: () => {} }] })).toThrow(ClavisError);
+  });
+
+  test("should count tampered frames a stream reads", async () => {
+    const monitor = new CorruptionMonitor();
+    const [a, b] = await createStreamPair();
+    const attacker = new HostilePeer(a);
+    const [victim] = await Promise.all([
+      EncryptedStream.new(b, { corruptionMonitor: monitor }),
+      attacker.handshake(),
+    ]);
+
+    await attacker.sendTamperedFrame(new Uint8Array([1]));
+    await expect(victim.readPacket()).rejects.toThrow(ClavisError);
+    // The bad frame was consumed, so reading carries on behind it
+    await attacker.sendFrame(new Uint8Array([2]));
+    expect((await victim.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([2]));
+
+    await attacker.sendTruncatedFrame(new Uint8Array([3]), 10);
+    await sleep(10);
+    await expect(victim.readPacket()).rejects.toThrow(ClavisError);
+
+    expect(monitor.countsFor(victim.connectionId)).toEqual({ macFailures: 1, framingErrors: 0, truncatedFrames: 1 });
+    expect(monitor.connections()).toEqual([victim.connectionId]);
+  });
+});
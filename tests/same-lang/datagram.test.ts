@@ -0,0 +1,116 @@
+/**
+ * Encrypted datagram tests - lossy handshakes, replay window and tampering
+ */
+
+import { describe, test, expect } from "bun:test";
+import { EncryptedDatagram, ReplayWindow, DATAGRAM_OVERHEAD, type DatagramTransport } from "../../src/datagram.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { ManualClock } from "../../src/clock.js";
+
+interface Link {
+  a: DatagramTransport;
+  b: DatagramTransport;
+  /** Everything a sent, dropped or not */
+  sentByA: Uint8Array[];
+  /** Hand a datagram to b as if the network delivered it */
+  deliverToB(datagram: Uint8Array): void;
+}
+
+/** Two in-memory datagram endpoints; `drop` decides which datagrams get lost */
+function datagramLink(drop: (datagram: Uint8Array, from: "a" | "b") => boolean = () => false): Link {
+  const handlers: { a?: (datagram: Uint8Array) => void; b?: (datagram: Uint8Array) => void } = {};
+  const sentByA: Uint8Array[] = [];
+  const end = (self: "a" | "b", peer: "a" | "b"): DatagramTransport => ({
+    send(datagram) {
+      if (self === "a") sentByA.push(datagram.slice());
+      if (!drop(datagram, self)) setTimeout(() => handlers[peer]?.(datagram.slice()), 0);
+    },
+    onDatagram(handler) {
+      handlers[self] = handler;
+    },
+  });
+  return { a: end("a", "b"), b: end("b", "a"), sentByA, deliverToB: (datagram) => handlers.b?.(datagram) };
+}
+
+const psk = new Uint8Array(32).fill(7);
+
+describe("ReplayWindow", () => {
+  test("should accept each recent sequence once, in any order", () => {
+    const window = new ReplayWindow(4);
+    for (const sequence of [2, 0, 5]) {
+      expect(window.check(sequence)).toBe(true);
+      window.commit(sequence);
+    }
+    expect(window.check(2)).toBe(false);
+    expect(window.check(5)).toBe(false);
+    expect(window.check(1)).toBe(false); // older than the window
+    expect(window.check(4)).toBe(true);
+    expect(() => new ReplayWindow(0)).toThrow(ClavisError);
+  });
+});
+
+describe("EncryptedDatagram", () => {
+  test("should exchange packets after the key exchange", async () => {
+    const link = datagramLink();
+    const [a, b] = await Promise.all([
+      EncryptedDatagram.new(link.a, { psk }),
+      EncryptedDatagram.new(link.b, { psk }),
+    ]);
+    expect(a.initiator).not.toBe(b.initiator);
+    expect(a.transcriptHash).toEqual(b.transcriptHash);
+
+    await a.send(new RawPacket(new Uint8Array([1, 2, 3])));
+    expect(await b.receive()).toEqual(new Uint8Array([1, 2, 3]));
+    await b.send(new RawPacket(new Uint8Array([4])));
+    expect(await a.receive()).toEqual(new Uint8Array([4]));
+    await expect(a.send(new RawPacket(new Uint8Array(1200 - DATAGRAM_OVERHEAD + 1)))).rejects.toThrow(ClavisError);
+  });
+
+  test("should drop replayed and tampered datagrams", async () => {
+    // Data from a only arrives when the test delivers it
+    const link = datagramLink((datagram, from) => from === "a" && datagram[0] === 1);
+    const [a, b] = await Promise.all([EncryptedDatagram.new(link.a), EncryptedDatagram.new(link.b)]);
+    await a.send(new RawPacket(new Uint8Array([9])));
+    await a.send(new RawPacket(new Uint8Array([8])));
+    const [first, second] = link.sentByA.slice(-2);
+
+    // The second datagram overtakes the first, then arrives again
+    link.deliverToB(second!);
+    link.deliverToB(first!);
+    link.deliverToB(second!);
+    await a.send(new RawPacket(new Uint8Array([7])));
+    const tampered = link.sentByA.at(-1)!.slice();
+    tampered[tampered.length - 1] = tampered[tampered.length - 1]! ^ 1;
+    link.deliverToB(tampered);
+
+    expect(await b.receive()).toEqual(new Uint8Array([8]));
+    expect(await b.receive()).toEqual(new Uint8Array([9]));
+    expect(b.stats()).toEqual({ sent: 0, received: 2, replayed: 1, rejected: 1, overflowed: 0 });
+    expect(a.stats().sent).toBe(3);
+
+    b.close();
+    await expect(b.receive()).rejects.toThrow(ClavisError);
+  });
+
+  test("should finish a handshake that loses datagrams", async () => {
+    // Lose each side's first two handshake datagrams
+    const lost = { a: 0, b: 0 };
+    const link = datagramLink((datagram, from) => datagram[0] === 0 && lost[from]++ < 2);
+    const [a, b] = await Promise.all([
+      EncryptedDatagram.new(link.a, { psk, retransmitMs: 5 }),
+      EncryptedDatagram.new(link.b, { psk, retransmitMs: 5 }),
+    ]);
+    await a.send(new RawPacket(new Uint8Array([1])));
+    expect(await b.receive()).toEqual(new Uint8Array([1]));
+  });
+
+  test("should give up on a peer that never answers", async () => {
+    const clock = new ManualClock();
+    const pending = EncryptedDatagram.new(datagramLink(() => true).a, { clock, handshakeTimeoutMs: 100 }).catch((error) => error);
+    await clock.advance(100);
+    const error = await pending;
+    expect(error).toBeInstanceOf(ClavisError);
+    expect((error as ClavisError).message).toContain("Handshake timed out after 100ms");
+  });
+});
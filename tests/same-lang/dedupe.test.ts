@@ -0,0 +1,45 @@
+/**
+ * Duplicate filter tests - dropping resent packets by message id
+ */
+
+import { describe, test, expect } from "bun:test";
+import { DuplicateFilter } from "../../src/dedupe.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const idOf = (packet: Uint8Array) => (packet[0] === 0 ? undefined : packet[0]);
+
+describe("DuplicateFilter", () => {
+  test("should forget the oldest ids beyond the window", () => {
+    const filter = new DuplicateFilter({ idOf, windowSize: 2 });
+    expect(filter.admit(1)).toBe(true);
+    expect(filter.admit(2)).toBe(true);
+    expect(filter.admit(1)).toBe(false);
+    expect(filter.admit(3)).toBe(true);
+    expect(filter.has(1)).toBe(false);
+    expect(filter.admit(1)).toBe(true);
+    expect(filter.size).toBe(2);
+    expect(filter.duplicates).toBe(1);
+    expect(() => new DuplicateFilter({ idOf, windowSize: 0 })).toThrow(ClavisError);
+  });
+
+  test("should drop resent packets across connections", async () => {
+    const filter = new DuplicateFilter({ idOf });
+    const [a1, b1] = await createEncryptedStreamPair({}, { interceptors: [filter.interceptor] });
+    const [a2, b2] = await createEncryptedStreamPair({}, { interceptors: [filter.interceptor] });
+
+    await a1.writePacket(new RawPacket(new Uint8Array([1])));
+    await a1.writePacket(new RawPacket(new Uint8Array([2])));
+    expect(Array.from((await b1.readPacket()) as unknown as Uint8Array)).toEqual([1]);
+    expect(Array.from((await b1.readPacket()) as unknown as Uint8Array)).toEqual([2]);
+
+    // 2 is resent on the next connection; packets without an id always pass
+    for (const id of [2, 0, 0, 3]) await a2.writePacket(new RawPacket(new Uint8Array([id])));
+    const received: number[] = [];
+    for (let i = 0; i < 3; i++) received.push(((await b2.readPacket()) as unknown as Uint8Array)[0]!);
+    expect(received).toEqual([0, 0, 3]);
+    expect(filter.duplicates).toBe(1);
+    expect(b2.droppedPackets).toBe(1);
+  });
+});
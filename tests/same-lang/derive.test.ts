@@ -0,0 +1,77 @@
+/**
+ * Derived protocol tests - variant order, serde renaming, docs and externally tagged values
+ */
+
+import { describe, test, expect } from "bun:test";
+import { applyRenameRule, deriveProtocol } from "../../src/derive.js";
+import { describeProtocol } from "../../src/protocol-doc.js";
+import { ClavisError } from "../../src/error.js";
+
+enum Command {
+  Join,
+  SendMessage,
+  Leave,
+}
+
+enum Status {
+  Online = "online",
+  Away = "away",
+}
+
+const text = (bytes: Uint8Array) => new TextDecoder().decode(bytes);
+
+describe("deriveProtocol", () => {
+  test("should number variants in declaration order like a protocol! enum", () => {
+    const Codec = deriveProtocol(Command);
+    expect(Codec.variants()).toEqual(["Join", "SendMessage", "Leave"]);
+    expect(Codec.encode("Leave")).toEqual(new Uint8Array([2, 0, 0, 0]));
+    expect(deriveProtocol(Status).variants()).toEqual(["Online", "Away"]);
+    expect(() => deriveProtocol({})).toThrow(ClavisError);
+  });
+
+  test("should apply serde's rename rules", () => {
+    expect(applyRenameRule("SendMessage", "snake_case")).toBe("send_message");
+    expect(applyRenameRule("SendMessage", "SCREAMING-KEBAB-CASE")).toBe("SEND-MESSAGE");
+    expect(applyRenameRule("SendMessage", "camelCase")).toBe("sendMessage");
+    expect(applyRenameRule("SendMessage", "lowercase")).toBe("sendmessage");
+
+    const Codec = deriveProtocol(Command, { renameAll: "kebab-case", rename: { Join: "enter" } });
+    expect(Codec.wireName("Join")).toBe("enter");
+    expect(Codec.wireName("SendMessage")).toBe("send-message");
+    expect(Codec.fromWireName("send-message")).toBe("SendMessage");
+    expect(deriveProtocol(Status, { renameAll: "UPPERCASE" }).wireName("Away")).toBe("away");
+    expect(() => deriveProtocol(Command, { rename: { Join: "Leave" } })).toThrow("both named Leave");
+  });
+
+  test("should write and read serde's externally tagged representation", () => {
+    const Codec = deriveProtocol<typeof Command, { Join: { room: string }; SendMessage: string }>(Command, {
+      renameAll: "snake_case",
+      format: "json",
+    });
+    const message = Codec.encodeValue("SendMessage", "hi");
+    expect(text(message)).toBe('{"send_message":"hi"}');
+    expect(text(Codec.encodeValue("Leave"))).toBe('"leave"');
+    expect(Codec.decodeValue(message)).toEqual({ type: "SendMessage", value: "hi" });
+    expect(Codec.decodeValue(Codec.encodeValue("Leave"))).toEqual({ type: "Leave", value: undefined });
+    expect(() => Codec.decodeValue(new TextEncoder().encode('{"quit":1}'))).toThrow("Unknown variant quit");
+
+    const Cbor = deriveProtocol<typeof Command, { Join: { room: string } }>(Command, { format: "cbor" });
+    expect(Cbor.decodeValue(Cbor.encodeValue("Join", { room: "lobby" }))).toEqual({ type: "Join", value: { room: "lobby" } });
+  });
+
+  test("should encode bincode values behind the variant index", () => {
+    const Codec = deriveProtocol<typeof Command, { SendMessage: string }>(Command);
+    const encoded = Codec.encodeValue("SendMessage", "hi");
+    const decoded = Codec.decode(encoded);
+    expect(decoded.type).toBe("SendMessage");
+    expect(decoded.reader.readString()).toBe("hi");
+    expect(() => Codec.decodeValue(encoded)).toThrow(ClavisError);
+  });
+
+  test("should carry variant docs into the protocol description", () => {
+    const Codec = deriveProtocol(Command, { docs: { Leave: "Leave the current room" }, errorVariant: "Leave" });
+    expect(Codec.docs).toEqual({ Leave: { doc: "Leave the current room" } });
+    expect(Codec.errorVariant).toBe("Leave");
+    expect(describeProtocol(Codec, { title: "Chat", variants: Codec.docs })).toContain("Leave the current room");
+  });
+});
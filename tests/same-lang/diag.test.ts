@@ -0,0 +1,38 @@
+/**
+ * Diagnostics tests - wire layout, responder behaviour and the measuring client
+ */
+
+import { describe, test, expect } from "bun:test";
+import { Diag, decodeDiag, runDiag, serveDiag } from "../../src/diag.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { createStreamPair } from "../helpers/test-utils.js";
+
+describe("Diag packets", () => {
+  test("should match the clavis::protocol! layout", () => {
+    expect(Diag.Ping(7).serialize()).toEqual(new Uint8Array([0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0]));
+    expect(Diag.Echo(new Uint8Array([9])).serialize()).toEqual(
+      new Uint8Array([3, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 9])
+    );
+    expect(decodeDiag(Diag.Pad(100).serialize())).toEqual({ type: "Pad", size: 100 });
+    expect(decodeDiag(Diag.Pong(7n).serialize())).toEqual({ type: "Pong", sequence: 7n });
+  });
+});
+
+describe("serveDiag and runDiag", () => {
+  test("should time round trips, check the echo and measure throughput", async () => {
+    const [left, right] = await createStreamPair();
+    const [client, server] = await Promise.all([EncryptedStream.new(left), EncryptedStream.new(right)]);
+    const serving = serveDiag(server);
+
+    const report = await runDiag(client, { pings: 3, echoSize: 256, padPackets: 20, padSize: 1024 });
+    expect(report.rtt.samples).toHaveLength(3);
+    expect(report.rtt.min).toBeLessThanOrEqual(report.rtt.max);
+    expect(report.echoOk).toBe(true);
+    expect(report.throughput).toBeGreaterThan(0);
+
+    left.end();
+    const stats = await serving;
+    // 3 pings, 1 echo, 20 pads and the ping behind them
+    expect(stats.packets).toBe(25);
+  });
+});
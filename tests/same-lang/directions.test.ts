@@ -0,0 +1,48 @@
+/**
+ * Direction tests - each side sends and accepts only the variants flowing its way
+ */
+
+import { describe, test, expect } from "bun:test";
+import { defineDirections } from "../../src/directions.js";
+import { RawPacket, createProtocolCodec } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const codec = createProtocolCodec(["Login", "Welcome", "Message"] as const);
+const Chat = defineDirections(codec, { Login: "clientToServer", Welcome: "serverToClient" });
+
+describe("defineDirections", () => {
+  test("should carry each variant in its direction", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const client = Chat.open(a, "client");
+    const server = Chat.open(b, "server");
+
+    await client.send("Login", new Uint8Array([1]));
+    expect((await server.receive()).type).toBe("Login");
+    await server.send("Welcome");
+    expect((await client.receive()).type).toBe("Welcome");
+    await server.send("Message");
+    expect((await client.receive()).type).toBe("Message");
+    expect(Chat.directionOf("Message")).toBe("both");
+  });
+
+  test("should enforce directions at runtime for untyped callers", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const client = Chat.open(a, "client");
+    const server = Chat.open(b, "server");
+
+    // @ts-expect-error only the server sends Welcome
+    await expect(client.send("Welcome")).rejects.toThrow(ClavisError);
+
+    await b.writePacket(new RawPacket(codec.encode("Login")));
+    await expect(client.receive()).rejects.toThrow(ClavisError);
+    await a.writePacket(new RawPacket(codec.encode("Welcome")));
+    await expect(server.receive()).rejects.toThrow(ClavisError);
+  });
+
+  test("should reject unknown variants, directions and roles", () => {
+    expect(() => defineDirections(codec, { ["Nope" as "Login"]: "both" })).toThrow(ClavisError);
+    expect(() => defineDirections(codec, { Login: "sideways" as "both" })).toThrow(ClavisError);
+    expect(() => Chat.open({} as never, "proxy" as "client")).toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,97 @@
+/**
+ * Example app tests - each app under examples/apps run end to end on loopback
+ */
+
+import { describe, test, expect } from "bun:test";
+import { mkdtempSync, readFileSync, rmSync, writeFileSync } from "fs";
+import { tmpdir } from "os";
+import { join } from "path";
+import { ClavisError } from "../../src/error.js";
+import { joinChat, startChatServer } from "../../examples/apps/chat.js";
+import { downloadFile, startFileServer } from "../../examples/apps/file-transfer.js";
+import { connectKeyValue, startKeyValueServer, MAX_VALUE_LENGTH } from "../../examples/apps/rpc-service.js";
+import { bindUdp, startCollector, startSensor, udpPort } from "../../examples/apps/telemetry.js";
+
+describe("example apps", () => {
+  test("chat should broadcast to every member and announce who left", async () => {
+    const server = await startChatServer();
+    try {
+      const ada = await joinChat("ada", server.port);
+      expect(await ada.next()).toEqual({ kind: "said", from: "*", text: "ada joined" });
+      const bob = await joinChat("bob", server.port);
+      expect(await ada.next()).toEqual({ kind: "said", from: "*", text: "bob joined" });
+      expect(await bob.next()).toEqual({ kind: "said", from: "*", text: "bob joined" });
+
+      await bob.say("hello");
+      expect(await ada.next()).toEqual({ kind: "said", from: "bob", text: "hello" });
+      expect(await bob.next()).toEqual({ kind: "said", from: "bob", text: "hello" });
+
+      await bob.close();
+      expect(await ada.next()).toEqual({ kind: "left", name: "bob" });
+      await ada.close();
+    } finally {
+      await server.close();
+    }
+  });
+
+  test("file transfer should deliver a file byte for byte and abort on a missing one", async () => {
+    const root = mkdtempSync(join(tmpdir(), "clavis-files-"));
+    const server = await startFileServer(root);
+    try {
+      const contents = new Uint8Array(3 * 1024 * 1024).map((_, i) => (i * 7) & 0xff);
+      writeFileSync(join(root, "data.bin"), contents);
+
+      const destination = join(root, "copy.bin");
+      expect(await downloadFile("data.bin", destination, server.port)).toBe(contents.length);
+      expect(new Uint8Array(readFileSync(destination))).toEqual(contents);
+
+      await expect(downloadFile("missing.bin", join(root, "none.bin"), server.port)).rejects.toThrow(ClavisError);
+    } finally {
+      await server.close();
+      rmSync(root, { recursive: true, force: true });
+    }
+  });
+
+  test("rpc service should answer calls, stream keys and surface handler errors", async () => {
+    const server = await startKeyValueServer();
+    const { client, close } = await connectKeyValue(server.port);
+    try {
+      expect(await client.get("colour")).toBeUndefined();
+      expect(await client.put(["colour", "teal"])).toBe(1);
+      expect(await client.put(["column", "3"])).toBe(2);
+      expect(await client.put(["size", "L"])).toBe(3);
+      expect(await client.get("colour")).toBe("teal");
+
+      const keys: string[] = [];
+      for await (const key of client.keys("co")) keys.push(key);
+      expect(keys).toEqual(["colour", "column"]);
+
+      await expect(client.put(["big", "x".repeat(MAX_VALUE_LENGTH + 1)])).rejects.toThrow("longer than");
+    } finally {
+      await close();
+      await server.close();
+    }
+  });
+
+  test("telemetry should carry readings from the sensor to the collector", async () => {
+    const [collectorSocket, sensorSocket] = await Promise.all([bindUdp(), bindUdp()]);
+    try {
+      const [collector, sensor] = await Promise.all([
+        startCollector(collectorSocket, udpPort(sensorSocket)),
+        startSensor(sensorSocket, udpPort(collectorSocket), "probe"),
+      ]);
+      const readings = collector.readings()[Symbol.asyncIterator]();
+      await sensor.report("load", 0.25);
+      await sensor.report("temp_c", -4.5);
+
+      expect((await readings.next()).value).toEqual({ sensor: "probe", metric: "load", value: 0.25 });
+      expect((await readings.next()).value).toEqual({ sensor: "probe", metric: "temp_c", value: -4.5 });
+      expect(collector.stats().received).toBe(2);
+      sensor.close();
+      collector.close();
+    } finally {
+      collectorSocket.close();
+      sensorSocket.close();
+    }
+  });
+});
@@ -0,0 +1,92 @@
+/**
+ * Payload format tests - MessagePack, CBOR and JSON encodings, offers and negotiation
+ */
+
+import { describe, test, expect } from "bun:test";
+import { chooseFormat, decodeFormatOffer, encodeFormatOffer, payloadFormat } from "../../src/formats.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair, createStreamPair } from "../helpers/test-utils.js";
+
+const msgpack = payloadFormat("msgpack");
+const cbor = payloadFormat("cbor");
+const json = payloadFormat("json");
+
+describe("Payload formats", () => {
+  test("should encode MessagePack and CBOR like reference implementations", () => {
+    expect(msgpack.encode({ a: 1 })).toEqual(new Uint8Array([0x81, 0xa1, 0x61, 0x01]));
+    expect(msgpack.encode([-1, 300, null, true])).toEqual(new Uint8Array([0x94, 0xff, 0xcd, 0x01, 0x2c, 0xc0, 0xc3]));
+    expect(cbor.encode([1, "a"])).toEqual(new Uint8Array([0x82, 0x01, 0x61, 0x61]));
+    expect(cbor.encode({ n: -500 })).toEqual(new Uint8Array([0xa1, 0x61, 0x6e, 0x39, 0x01, 0xf3]));
+    expect(cbor.encode(1_000_000)).toEqual(new Uint8Array([0x1a, 0x00, 0x0f, 0x42, 0x40]));
+    expect(new TextDecoder().decode(json.encode({ id: 2n ** 60n, raw: new Uint8Array([1, 2]), gone: undefined })))
+      .toBe('{"id":1152921504606846976,"raw":[1,2]}');
+  });
+
+  test("should round-trip values through the self-describing formats", () => {
+    const value = {
+      name: "ada",
+      scores: [1, -70000, 2.5],
+      nested: { ok: false, none: null },
+      at: new Date(0),
+      long: "x".repeat(300),
+    };
+    const expected = { ...value, at: "1970-01-01T00:00:00.000Z" };
+    for (const format of [msgpack, cbor, json]) {
+      expect(format.decode(format.encode(value))).toEqual(expected);
+    }
+    for (const format of [msgpack, cbor]) {
+      expect(format.decode(format.encode({ big: 2n ** 63n, small: -(2n ** 62n), bytes: new Uint8Array([9]) })))
+        .toEqual({ big: 2n ** 63n, small: -(2n ** 62n), bytes: new Uint8Array([9]) });
+    }
+  });
+
+  test("should refuse truncated, padded and oversized payloads", () => {
+    for (const format of [msgpack, cbor]) {
+      const encoded = format.encode({ list: [1, 2, 3] });
+      expect(() => format.decode(encoded.subarray(0, encoded.length - 1))).toThrow(ClavisError);
+      expect(() => format.decode(new Uint8Array([...encoded, 0]))).toThrow(ClavisError);
+    }
+    // An array claiming 2^32 - 1 items in a handful of bytes
+    expect(() => msgpack.decode(new Uint8Array([0xdd, 0xff, 0xff, 0xff, 0xff]))).toThrow(ClavisError);
+    expect(() => cbor.decode(new Uint8Array([0x9a, 0xff, 0xff, 0xff, 0xff]))).toThrow(ClavisError);
+    expect(() => json.decode(new TextEncoder().encode("{"))).toThrow(ClavisError);
+  });
+});
+
+describe("Format negotiation", () => {
+  test("should pick the initiator's first format the responder offered", () => {
+    const offer = encodeFormatOffer(["json", "cbor"]);
+    expect(decodeFormatOffer(new Uint8Array([...offer.subarray(0, 4), 3, 9, 3, 2]))).toEqual(["json", "cbor"]);
+    expect(chooseFormat(["cbor", "msgpack", "json"], ["json", "msgpack"], true)).toBe("msgpack");
+    expect(chooseFormat(["cbor", "msgpack", "json"], ["json", "msgpack"], false)).toBe("json");
+    expect(() => chooseFormat(["cbor"], ["json"], true)).toThrow("No payload format in common");
+  });
+
+  test("should exchange values in the negotiated format", async () => {
+    const [a, b] = await createEncryptedStreamPair({ format: ["cbor", "json"] }, { format: ["json", "cbor"] });
+    expect(a.payloadFormat).toBe(b.payloadFormat);
+
+    await a.writeValue({ move: [3, 4] });
+    expect(await b.readValue()).toEqual({ move: [3, 4] });
+  });
+
+  test("should use a codec's fixed format and fail peers without a common one", async () => {
+    const Codec = createProtocolCodec(["Hello"] as const, { format: "msgpack" });
+    const [a, b] = await createEncryptedStreamPair({ format: Codec }, { format: "msgpack" });
+    expect(a.payloadFormat).toBe("msgpack");
+    await b.writeValue("hi");
+    expect(await a.readValue<string>()).toBe("hi");
+
+    const [left, right] = await createStreamPair();
+    const results = await Promise.allSettled([
+      EncryptedStream.new(left, { format: ["cbor"] }),
+      EncryptedStream.new(right, { format: ["json"] }),
+    ]);
+    expect(results.map((result) => result.status)).toEqual(["rejected", "rejected"]);
+
+    const [plain] = await createEncryptedStreamPair();
+    await expect(plain.writeValue(1)).rejects.toThrow(ClavisError);
+  });
+});
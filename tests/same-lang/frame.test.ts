@@ -0,0 +1,65 @@
+/**
+ * Frame codec tests - the wire unit on its own, without a stream
+ */
+
+import { describe, test, expect } from "bun:test";
+import {
+  FRAME_OVERHEAD,
+  FrameDecoder,
+  decodeFrame,
+  encodeFrame,
+  openFrame,
+  sealFrame,
+} from "../../src/frame.js";
+import { XChaCha20Poly1305Cipher, generateRandomBytes } from "../../src/crypto.js";
+import { ClavisError } from "../../src/error.js";
+
+const cipher = new XChaCha20Poly1305Cipher(generateRandomBytes(32));
+const packet = new TextEncoder().encode("hello frames");
+
+describe("Frame codec", () => {
+  test("should seal and open application and control frames", () => {
+    const frame = sealFrame(cipher, packet);
+    expect(frame.length).toBe(packet.length + FRAME_OVERHEAD);
+    expect(openFrame(cipher, decodeFrame(frame))).toEqual(packet);
+
+    const control = decodeFrame(sealFrame(cipher, packet, true));
+    expect(control.control).toBe(true);
+    expect(openFrame(cipher, control)).toEqual(packet);
+    // Flipping the flag makes the frame fail authentication instead of changing its meaning
+    expect(() => openFrame(cipher, { ...control, control: false })).toThrow(ClavisError);
+  });
+
+  test("should reject frames whose length doesn't match", () => {
+    const frame = sealFrame(cipher, packet);
+    expect(() => decodeFrame(frame.subarray(0, frame.length - 1))).toThrow(ClavisError);
+    expect(() => encodeFrame(new Uint8Array(12), new Uint8Array(16))).toThrow(ClavisError);
+  });
+
+  test("should reassemble frames from arbitrary chunks", () => {
+    const frames = [sealFrame(cipher, packet), sealFrame(cipher, new Uint8Array(0)), sealFrame(cipher, packet, true)];
+    const wire = new Uint8Array(frames.reduce((total, frame) => total + frame.length, 0));
+    let offset = 0;
+    for (const frame of frames) {
+      wire.set(frame, offset);
+      offset += frame.length;
+    }
+
+    const decoder = new FrameDecoder();
+    const opened: Uint8Array[] = [];
+    for (let i = 0; i < wire.length; i += 7) {
+      decoder.push(wire.subarray(i, i + 7));
+      for (let frame = decoder.next(); frame; frame = decoder.next()) {
+        opened.push(openFrame(cipher, frame));
+      }
+    }
+    expect(opened).toEqual([packet, new Uint8Array(0), packet]);
+    expect(decoder.buffered).toBe(0);
+  });
+
+  test("should refuse oversized headers before buffering the body", () => {
+    const decoder = new FrameDecoder(64);
+    decoder.push(encodeFrame(new Uint8Array(24), new Uint8Array(100)).subarray(0, 4));
+    expect(() => decoder.next()).toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,118 @@
+/**
+ * Fuzz tests - the frame, crypto and decoding paths fail only with ClavisError
+ */
+
+import { describe, test, expect } from "bun:test";
+import { readdirSync, readFileSync } from "fs";
+import { join } from "path";
+import { seededRandom } from "../../src/testing.js";
+import { ClavisError } from "../../src/error.js";
+import { XChaCha20Poly1305Cipher, computeSharedSecret, hkdfExpand } from "../../src/crypto.js";
+import { decodeControlFrame, decodeControlU32 } from "../../src/control.js";
+import { PacketCompressor, buildDictionary, decodeCompressionOffer } from "../../src/compression.js";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { BincodeReader } from "../../src/bincode.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { HostilePeer } from "../helpers/hostile-peer.js";
+import { createStreamPair } from "../helpers/test-utils.js";
+
+const ITERATIONS = 2000;
+const random = seededRandom(0xc1a715);
+
+function fillRandom(length: number): Uint8Array {
+  const bytes = new Uint8Array(length);
+  for (let i = 0; i < length; i++) {
+    bytes[i] = Math.floor(random() * 256);
+  }
+  return bytes;
+}
+
+function randomBytes(maxLength: number): Uint8Array {
+  return fillRandom(Math.floor(random() * (maxLength + 1)));
+}
+
+/** Run `fn` on random inputs; it may return or throw ClavisError, nothing else */
+function fuzz(maxLength: number, fn: (input: Uint8Array) => unknown): void {
+  for (let i = 0; i < ITERATIONS; i++) {
+    const input = randomBytes(maxLength);
+    try {
+      fn(input);
+    } catch (error) {
+      if (!(error instanceof ClavisError)) {
+        throw new Error(`${String(error)} on input [${input.join(",")}]`);
+      }
+    }
+  }
+}
+
+describe("Fuzzing", () => {
+  test("control frame decoding", () => {
+    fuzz(16, (input) => decodeControlU32(decodeControlFrame(input).payload));
+  });
+
+  test("compression offers and compressed packets", () => {
+    const dictionary = buildDictionary([new TextEncoder().encode("dictionary sample")]);
+    const compressor = PacketCompressor.negotiate({ dictionaries: [dictionary] }, []);
+    const withDictionary = PacketCompressor.negotiate({ dictionaries: [dictionary] }, [dictionary.id]);
+    fuzz(64, (input) => decodeCompressionOffer(input));
+    fuzz(64, (input) => compressor.decompress(input, 1024));
+    fuzz(64, (input) => withDictionary.decompress(input, 1024));
+  });
+
+  test("protocol and bincode decoding", () => {
+    const codec = createProtocolCodec(["A", "B", "C"] as const);
+    const varint = createProtocolCodec(["A", "B", "C"] as const, { useVarint: true });
+    fuzz(32, (input) => {
+      const { reader } = codec.decode(input);
+      reader.readString();
+      reader.readDateTime();
+    });
+    fuzz(32, (input) => varint.decode(input));
+    fuzz(32, (input) => {
+      const reader = new BincodeReader(input);
+      reader.readStringVec();
+      reader.readOptionString();
+      reader.readBytes();
+    });
+  });
+
+  test("key exchange and decryption", () => {
+    const cipher = new XChaCha20Poly1305Cipher(new Uint8Array(32).fill(1));
+    fuzz(64, (input) => cipher.decrypt(input.subarray(0, 24), input.subarray(24)));
+    fuzz(40, (input) => computeSharedSecret(new Uint8Array(32).fill(2), input));
+    fuzz(40, (input) => hkdfExpand(input, new Uint8Array(32), "enc"));
+    fuzz(40, (input) => new XChaCha20Poly1305Cipher(input));
+  });
+
+  test("incoming frames", async () => {
+    for (let i = 0; i < 50; i++) {
+      const [a, b] = await createStreamPair();
+      const attacker = new HostilePeer(a);
+      const [victim] = await Promise.all([EncryptedStream.new(b), attacker.handshake()]);
+
+      // A valid length so the victim reads the whole frame instead of waiting
+      const length = 16 + Math.floor(random() * 64);
+      const frame = new Uint8Array(4 + 24 + length);
+      new DataView(frame.buffer).setUint32(0, length, true);
+      frame.set(fillRandom(24 + length), 4);
+      if (random() < 0.5) frame[3] = frame[3]! | 0x80;
+      await attacker.write(frame);
+
+      const error = await victim.readPacket().then(() => undefined, (e: unknown) => e);
+      expect(error).toBeInstanceOf(ClavisError);
+    }
+  });
+});
+
+describe("Fallible core", () => {
+  test("core modules throw only ClavisError", () => {
+    const core = ["stream.ts", "frame.ts", "crypto.ts", "handshake.ts", "handshake-messages.ts", "control.ts", "compression.ts", "bincode.ts", "protocol.ts", "identity.ts", "x509.ts"];
+    const src = join(import.meta.dir, "../../src");
+    for (const file of readdirSync(src).filter((f) => core.includes(f))) {
+      const offending = readFileSync(join(src, file), "utf8")
+        .split("\n")
+        .filter((line) => /\bthrow new\b/.test(line));
+      expect({ file, offending }).toEqual({ file, offending: [] });
+    }
+  });
+});
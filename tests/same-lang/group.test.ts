@@ -0,0 +1,220 @@
+/**
+ * Group session tests - end-to-end encrypted groups through a relay
+ */
+
+import { describe, test, expect } from "bun:test";
+import { Duplex } from "stream";
+import { GroupRelay, GroupSession, type GroupEvent, type GroupMembershipEvent, type GroupSessionOptions } from "../../src/group.js";
+import { keyFingerprint } from "../../src/identity.js";
+import { generateX25519KeyPair } from "../../src/crypto.js";
+import { ClavisError } from "../../src/error.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { createEncryptedStreamPair, sleep } from "../helpers/test-utils.js";
+
+const text = (value: string) => new TextEncoder().encode(value);
+
+async function joinVia(relay: GroupRelay, options: GroupSessionOptions): Promise<GroupSession> {
+  const [client, server] = await createEncryptedStreamPair();
+  void relay.serve(server).catch(() => undefined);
+  return GroupSession.join(client, options);
+}
+
+/** Join over a link whose relay side stops taking writes once `stall()` is called, like a member that never reads */
+async function joinStalling(relay: GroupRelay, options: GroupSessionOptions): Promise<[GroupSession, () => void]> {
+  let stalled = false;
+  let member: Duplex;
+  const relaySide = new Duplex({
+    read() {},
+    write(chunk: Buffer, _encoding: BufferEncoding, callback: () => void) {
+      if (stalled) return;
+      member.push(chunk);
+      callback();
+    },
+  });
+  member = new Duplex({
+    read() {},
+    write(chunk: Buffer, _encoding: BufferEncoding, callback: () => void) {
+      relaySide.push(chunk);
+      callback();
+    },
+  });
+  const [client, server] = await Promise.all([EncryptedStream.new(member), EncryptedStream.new(relaySide)]);
+  void relay.serve(server).catch(() => undefined);
+  return [await GroupSession.join(client, options), () => (stalled = true)];
+}
+
+async function next(session: GroupSession): Promise<GroupEvent> {
+  return (await session[Symbol.asyncIterator]().next()).value as GroupEvent;
+}
+
+async function nextMessage(session: GroupSession): Promise<GroupEvent> {
+  for (;;) {
+    const event = await next(session);
+    if (event.type === "message") return event;
+  }
+}
+
+async function until(condition: () => boolean): Promise<void> {
+  for (let i = 0; i < 200 && !condition(); i++) await sleep(5);
+  expect(condition()).toBe(true);
+}
+
+describe("GroupSession", () => {
+  test("should exchange messages and replace the key as members come and go", async () => {
+    const relay = new GroupRelay();
+    const ada = await joinVia(relay, { group: "ops", member: "ada" });
+    const bob = await joinVia(relay, { group: "ops", member: "bob" });
+    expect(await next(ada)).toEqual({ type: "joined", member: "bob" });
+    expect(bob.leader).toBe("ada");
+
+    await bob.send(text("hello"));
+    expect(await next(ada)).toEqual({ type: "message", from: "bob", payload: text("hello") });
+    expect(bob.epoch).toBe(ada.epoch);
+    const epoch = ada.epoch;
+
+    const carol = await joinVia(relay, { group: "ops", member: "carol" });
+    expect(relay.members("ops")).toEqual(["ada", "bob", "carol"]);
+    await carol.send(text("hi all"));
+    expect(await next(ada)).toEqual({ type: "joined", member: "carol" });
+    expect(await next(ada)).toEqual({ type: "message", from: "carol", payload: text("hi all") });
+    expect(await next(bob)).toEqual({ type: "joined", member: "carol" });
+    expect(await next(bob)).toEqual({ type: "message", from: "carol", payload: text("hi all") });
+    expect(ada.epoch).toBe(epoch + 1);
+
+    // The leader leaving hands the key over to the next oldest member
+    await ada.leave();
+    expect(await next(bob)).toEqual({ type: "left", member: "ada" });
+    expect(await next(carol)).toEqual({ type: "left", member: "ada" });
+    await carol.send(text("still here"));
+    expect(await next(bob)).toEqual({ type: "message", from: "carol", payload: text("still here") });
+    expect(bob.leader).toBe("bob");
+    expect(carol.epoch).toBe(epoch + 2);
+  });
+
+  test("should keep members whose keys fail verification out of the group", async () => {
+    const relay = new GroupRelay();
+    const verifyMember = (member: string) => member !== "mallory";
+    const ada = await joinVia(relay, { group: "ops", member: "ada", verifyMember });
+    const bob = await joinVia(relay, { group: "ops", member: "bob", verifyMember });
+    expect(await next(ada)).toEqual({ type: "joined", member: "bob" });
+    const mallory = await joinVia(relay, { group: "ops", member: "mallory" });
+
+    await ada.send(text("secret"));
+    expect(await next(bob)).toEqual({ type: "message", from: "ada", payload: text("secret") });
+    await sleep(10);
+    expect(ada.memberNames).toEqual(["ada", "bob"]);
+    expect(mallory.epoch).toBe(0);
+  });
+
+  test("should settle on one key through concurrent joins and leaves", async () => {
+    const relay = new GroupRelay();
+    const ada = await joinVia(relay, { group: "ops", member: "ada" });
+    const joined = await Promise.all(
+      ["m1", "m2", "m3", "m4", "m5"].map((member) => joinVia(relay, { group: "ops", member }))
+    );
+    const [m1, m2, m3, m4, m5] = joined as [GroupSession, GroupSession, GroupSession, GroupSession, GroupSession];
+    const [m6] = await Promise.all([joinVia(relay, { group: "ops", member: "m6" }), m2.leave(), m4.leave()]);
+
+    const group = [ada, m1, m3, m5, m6];
+    await until(() =>
+      group.every((session) => session.epoch === ada.epoch && session.memberNames.join() === relay.members("ops").join())
+    );
+    expect(relay.members("ops")).toEqual(["ada", "m1", "m3", "m5", "m6"]);
+
+    for (const sender of group) await sender.send(text(`from ${sender.groupInfo().member}`));
+    for (const receiver of group) {
+      const received: string[] = [];
+      for (let i = 0; i < group.length - 1; i++) {
+        const event = await nextMessage(receiver);
+        if (event.type === "message") received.push(new TextDecoder().decode(event.payload));
+      }
+      const others = group.map((session) => session.groupInfo().member).filter((member) => member !== receiver.groupInfo().member);
+      expect(received.sort()).toEqual(others.map((member) => `from ${member}`).sort());
+    }
+  });
+
+  test("should let the leader remove members and rotate the key", async () => {
+    const relay = new GroupRelay();
+    const changes: GroupMembershipEvent[] = [];
+    const views: string[][] = [];
+    const rotations: number[] = [];
+    const [adaKeys, bobKeys] = [generateX25519KeyPair(), generateX25519KeyPair()];
+    const ada = await joinVia(relay, { group: "ops", member: "ada", keyPair: adaKeys });
+    const bob = await joinVia(relay, {
+      group: "ops",
+      member: "bob",
+      keyPair: bobKeys,
+      onMembershipChange: (event, info) => {
+        changes.push(event);
+        views.push(info.members.map(({ member }) => member));
+      },
+      onKeyRotated: (epoch) => rotations.push(epoch),
+    });
+    const carol = await joinVia(relay, { group: "ops", member: "carol" });
+    expect(await next(bob)).toEqual({ type: "joined", member: "carol" });
+
+    await expect(bob.removeMember("carol")).rejects.toThrow(ClavisError);
+    await ada.removeMember("carol");
+    expect(await next(carol)).toEqual({ type: "left", member: "carol" });
+    await expect(carol.send(text("still here?"))).rejects.toThrow(ClavisError);
+    expect(await next(bob)).toEqual({ type: "left", member: "carol" });
+    await until(() => relay.members("ops").length === 2);
+
+    await bob.send(text("just us"));
+    expect(await next(ada)).toEqual({ type: "joined", member: "bob" });
+    expect(await nextMessage(ada)).toEqual({ type: "message", from: "bob", payload: text("just us") });
+    expect(changes).toEqual([{ type: "joined", member: "carol" }, { type: "left", member: "carol" }]);
+    expect(views).toEqual([["ada", "bob", "carol"], ["ada", "bob"]]);
+
+    expect(() => bob.rotateKey()).toThrow(ClavisError);
+    const epoch = ada.rotateKey();
+    await until(() => bob.epoch === epoch);
+    expect(rotations.at(-1)).toBe(epoch);
+    expect(ada.groupInfo()).toEqual({
+      group: "ops",
+      member: "ada",
+      leader: "ada",
+      epoch,
+      members: [
+        { member: "ada", fingerprint: keyFingerprint(adaKeys.publicKey) },
+        { member: "bob", fingerprint: keyFingerprint(bobKeys.publicKey) },
+      ],
+    });
+  });
+
+  test("should only admit invited members on an invite-only relay", async () => {
+    const relay = new GroupRelay({ inviteOnly: true });
+    const ada = await joinVia(relay, { group: "ops", member: "ada" });
+    await expect(joinVia(relay, { group: "ops", member: "bob" })).rejects.toThrow();
+
+    await ada.addMember("bob");
+    await sleep(10);
+    const bob = await joinVia(relay, { group: "ops", member: "bob" });
+    expect(await next(ada)).toEqual({ type: "joined", member: "bob" });
+    expect(relay.members("ops")).toEqual(["ada", "bob"]);
+
+    // The invitation was used up
+    await bob.leave();
+    expect(await next(ada)).toEqual({ type: "left", member: "bob" });
+    await expect(joinVia(relay, { group: "ops", member: "bob" })).rejects.toThrow();
+  });
+
+  test("should drop a member that stops reading once it falls too far behind", async () => {
+    const relay = new GroupRelay({ maxPendingBytes: 4096 });
+    const ada = await joinVia(relay, { group: "ops", member: "ada" });
+    const [bob, stall] = await joinStalling(relay, { group: "ops", member: "bob" });
+    expect(await next(ada)).toEqual({ type: "joined", member: "bob" });
+    await until(() => bob.epoch === ada.epoch);
+
+    stall();
+    for (let i = 0; i < 8; i++) await ada.send(new Uint8Array(1000));
+    expect(await next(ada)).toEqual({ type: "left", member: "bob" });
+    expect(relay.members("ops")).toEqual(["ada"]);
+
+    // The relay keeps serving everyone else
+    const carol = await joinVia(relay, { group: "ops", member: "carol" });
+    expect(await next(ada)).toEqual({ type: "joined", member: "carol" });
+    await carol.send(text("hi"));
+    expect(await nextMessage(ada)).toEqual({ type: "message", from: "carol", payload: text("hi") });
+  });
+});
@@ -0,0 +1,77 @@
+/**
+ * Session handoff tests - exporting a live connection and carrying it on in a new stream
+ */
+
+import { describe, test, expect } from "bun:test";
+import { createEncryptedStreamPair, createStreamPair, sleep } from "../helpers/test-utils.js";
+import { EncryptedStream, checkStreamOptions, type EncryptedStreamOptions } from "../../src/stream.js";
+import { decodeSessionState } from "../../src/handoff.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+
+const packet = (n: number) => new RawPacket(new Uint8Array([n]));
+
+describe("Session handoff", () => {
+  test("should carry a connection on over the same transport, unread data and keys included", async () => {
+    const options: EncryptedStreamOptions = {
+      dangerousSessionHandoff: true,
+      sequenceNumbers: true,
+      cipherSuites: ["aes-256-gcm"],
+      format: "json",
+    };
+    const [left, right] = await createStreamPair();
+    const [client, server] = await Promise.all([EncryptedStream.new(left, options), EncryptedStream.new(right, options)]);
+
+    await client.writePacket(packet(1));
+    await client.writePacket(packet(2));
+    expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+    await sleep(10);
+
+    const state = server.exportState();
+    expect(decodeSessionState(state).unread.length).toBeGreaterThan(0);
+    await expect(server.readPacket()).rejects.toThrow(ClavisError);
+
+    const resumed = await EncryptedStream.importState(right, state, { dangerousSessionHandoff: true });
+    expect(resumed.connectionId).toBe(server.connectionId);
+    expect(resumed.sessionId).toBe(client.sessionId);
+    expect(resumed.cipherSuite).toBe("aes-256-gcm");
+    expect(resumed.exportKeyingMaterial("handoff")).toEqual(client.exportKeyingMaterial("handoff"));
+    expect((await resumed.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([2]));
+    expect(resumed.stats().packetsReceived).toBe(2);
+
+    await resumed.writeValue({ hello: "from the new process" });
+    expect(await client.readValue()).toEqual({ hello: "from the new process" });
+
+    // Rekeys chain on from the exported secret
+    const answer = resumed.readPacket();
+    const clientRead = client.readPacket();
+    await resumed.rekey();
+    await client.writePacket(packet(3));
+    await resumed.writePacket(packet(4));
+    expect((await answer) as unknown as Uint8Array).toEqual(new Uint8Array([3]));
+    expect((await clientRead) as unknown as Uint8Array).toEqual(new Uint8Array([4]));
+    expect(resumed.rekeyCount).toBe(1);
+  });
+
+  test("should refuse to export without the option or in the middle of a read", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    expect(() => b.exportState()).toThrow(ClavisError);
+
+    const [c, d] = await createEncryptedStreamPair({ dangerousSessionHandoff: true }, { dangerousSessionHandoff: true });
+    const pending = d.readPacket();
+    expect(() => d.exportState()).toThrow(ClavisError);
+    await c.writePacket(packet(5));
+    expect((await pending) as unknown as Uint8Array).toEqual(new Uint8Array([5]));
+
+    // Both streams are still attached
+    await a.writePacket(packet(6));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([6]));
+
+    const [, right] = await createStreamPair();
+    const state = d.exportState();
+    await expect(EncryptedStream.importState(right, state, {})).rejects.toThrow(ClavisError);
+    await expect(EncryptedStream.importState(right, new Uint8Array([1, 2, 3]), { dangerousSessionHandoff: true }))
+      .rejects.toThrow(ClavisError);
+    expect(() => checkStreamOptions({ dangerousSessionHandoff: true, dangerousNullCipher: true })).toThrow(ClavisError);
+  });
+});
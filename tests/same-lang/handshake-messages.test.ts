@@ -0,0 +1,93 @@
+/**
+ * Handshake message tests - an independent peer built from the public message API
+ */
+
+import { describe, test, expect } from "bun:test";
+import {
+  HANDSHAKE_MESSAGE_LENGTHS,
+  decodeHandshakeMessage,
+  deriveHandshakeKeys,
+  handshakeMac,
+  handshakeSteps,
+  handshakeTranscript,
+  isHandshakeInitiator,
+  encodePskIdentity,
+  decodePskIdentity,
+} from "../../src/handshake-messages.js";
+import { computeSharedSecret, generateRandomBytes, generateX25519KeyPair, XChaCha20Poly1305Cipher } from "../../src/crypto.js";
+import { decodeFrame, openFrame, sealFrame } from "../../src/frame.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { RawPeer } from "../helpers/hostile-peer.js";
+import { createStreamPair } from "../helpers/test-utils.js";
+
+const psk = new TextEncoder().encode("handshake-messages-psk");
+
+describe("Handshake messages", () => {
+  test("should describe the message order", () => {
+    expect(handshakeSteps(false).map((step) => `${step.sender}:${step.kind}`))
+      .toEqual(["both:nonce", "initiator:public_key", "responder:public_key"]);
+    expect(handshakeSteps(true)).toHaveLength(5);
+    expect(handshakeSteps(true, true).map((step) => step.kind))
+      .toEqual(["nonce", "psk_identity", "public_key", "public_key", "mac", "mac"]);
+  });
+
+  test("should zero-pad PSK identities to a fixed size", () => {
+    const message = encodePskIdentity("2026-10");
+    expect(message).toHaveLength(HANDSHAKE_MESSAGE_LENGTHS.psk_identity);
+    expect(decodePskIdentity(message)).toBe("2026-10");
+    expect(decodePskIdentity(encodePskIdentity(""))).toBe("");
+    expect(() => encodePskIdentity("x".repeat(65))).toThrow(ClavisError);
+    const holed = encodePskIdentity("a");
+    holed[2] = 0x62;
+    expect(() => decodePskIdentity(holed)).toThrow(ClavisError);
+  });
+
+  test("should reject messages of the wrong size", () => {
+    expect(() => decodeHandshakeMessage("public_key", new Uint8Array(31))).toThrow(ClavisError);
+    expect(() => isHandshakeInitiator(new Uint8Array(32), new Uint8Array(16))).toThrow(ClavisError);
+  });
+
+  test("should interoperate with EncryptedStream", async () => {
+    const [a, b] = await createStreamPair();
+    const peer = new RawPeer(b);
+    const streamReady = EncryptedStream.new(a, { psk });
+
+    // The same steps performHandshake takes, spelled out with the public API
+    const nonce = generateRandomBytes(HANDSHAKE_MESSAGE_LENGTHS.nonce);
+    await peer.write(nonce);
+    const initiator = isHandshakeInitiator(nonce, await peer.read(32));
+    const keyPair = generateX25519KeyPair();
+    let peerKey: Uint8Array;
+    if (initiator) {
+      await peer.write(keyPair.publicKey);
+      peerKey = await peer.read(32);
+    } else {
+      peerKey = await peer.read(32);
+      await peer.write(keyPair.publicKey);
+    }
+    const transcript = initiator
+      ? handshakeTranscript(keyPair.publicKey, peerKey)
+      : handshakeTranscript(peerKey, keyPair.publicKey);
+    const mac = handshakeMac(psk, transcript);
+    if (initiator) {
+      await peer.write(mac);
+      expect(await peer.read(32)).toEqual(mac);
+    } else {
+      expect(await peer.read(32)).toEqual(mac);
+      await peer.write(mac);
+    }
+    const keys = deriveHandshakeKeys(computeSharedSecret(keyPair.secret, peerKey), transcript, initiator);
+
+    const stream = await streamReady;
+    await stream.writePacket(new RawPacket(new Uint8Array([1, 2, 3])));
+    const header = await peer.read(4);
+    const length = new DataView(header.buffer).getUint32(0, true);
+    const frame = new Uint8Array([...header, ...(await peer.read(24 + length))]);
+    expect(openFrame(new XChaCha20Poly1305Cipher(keys.decKey), decodeFrame(frame))).toEqual(new Uint8Array([1, 2, 3]));
+
+    await peer.write(sealFrame(new XChaCha20Poly1305Cipher(keys.encKey), new Uint8Array([4])));
+    expect((await stream.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([4]));
+  });
+});
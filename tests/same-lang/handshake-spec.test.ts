@@ -0,0 +1,327 @@
+/**
+ * Handshake state machine tests - the legal message sequences as a table, and
+ * every out-of-place and truncated message generated from it
+ */
+
+import { describe, test, expect } from "bun:test";
+import { HandshakeMachine } from "../../src/handshake.js";
+import {
+  HANDSHAKE_MESSAGE_LENGTHS,
+  deriveHandshakeKeys,
+  encodePskIdentity,
+  handshakeMac,
+  handshakeTranscript,
+  isHandshakeInitiator,
+  type HandshakeMessageKind,
+} from "../../src/handshake-messages.js";
+import { computeSharedSecret, generateRandomBytes, generateX25519KeyPair } from "../../src/crypto.js";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+import { ClavisError, CryptoError, StreamError, StreamErrorCode } from "../../src/error.js";
+import { RawPeer } from "../helpers/hostile-peer.js";
+import { createStreamPair } from "../helpers/test-utils.js";
+
+type Mode = "plain" | "psk" | "psk identities";
+type Role = "initiator" | "responder";
+type Outcome = "completes" | "keys disagree" | "bad MAC" | "bad identity" | "stalls";
+
+const MODES: readonly Mode[] = ["plain", "psk", "psk identities"];
+const ROLES: readonly Role[] = ["initiator", "responder"];
+const PSK = new TextEncoder().encode("handshake-spec-pre-shared-key");
+const IDENTITY = "current";
+
+/** One step of a conforming machine: the message it waits for, then what it sends */
+interface Transition {
+  receive: HandshakeMessageKind;
+  send: HandshakeMessageKind[];
+}
+
+interface MachineSpec {
+  /** Sent before anything arrives */
+  opening: HandshakeMessageKind[];
+  transitions: Transition[];
+}
+
+/** The legal sequences, as seen by the machine under test */
+const SPEC: Record<Mode, Record<Role, MachineSpec>> = {
+  plain: {
+    initiator: {
+      opening: ["nonce"],
+      transitions: [
+        { receive: "nonce", send: ["public_key"] },
+        { receive: "public_key", send: [] },
+      ],
+    },
+    responder: {
+      opening: ["nonce"],
+      transitions: [
+        { receive: "nonce", send: [] },
+        { receive: "public_key", send: ["public_key"] },
+      ],
+    },
+  },
+  psk: {
+    initiator: {
+      opening: ["nonce"],
+      transitions: [
+        { receive: "nonce", send: ["public_key"] },
+        { receive: "public_key", send: ["mac"] },
+        { receive: "mac", send: [] },
+      ],
+    },
+    responder: {
+      opening: ["nonce"],
+      transitions: [
+        { receive: "nonce", send: [] },
+        { receive: "public_key", send: ["public_key"] },
+        { receive: "mac", send: ["mac"] },
+      ],
+    },
+  },
+  "psk identities": {
+    initiator: {
+      opening: ["nonce", "psk_identity"],
+      transitions: [
+        { receive: "nonce", send: ["public_key"] },
+        { receive: "psk_identity", send: [] },
+        { receive: "public_key", send: ["mac"] },
+        { receive: "mac", send: [] },
+      ],
+    },
+    responder: {
+      opening: ["nonce", "psk_identity"],
+      transitions: [
+        { receive: "nonce", send: [] },
+        { receive: "psk_identity", send: [] },
+        { receive: "public_key", send: ["public_key"] },
+        { receive: "mac", send: ["mac"] },
+      ],
+    },
+  },
+};
+
+/**
+ * What a message of the wrong kind leads to. Messages carry no type, so the
+ * machine takes whatever arrives for the message it expects. Only the PSK
+ * MACs authenticate the exchange; without a PSK the sides end up with
+ * different keys, which the first record exposes. An identity that isn't
+ * zero-padded UTF-8 is refused before that.
+ */
+function expectedOutcome(mode: Mode, expected: HandshakeMessageKind, injected: HandshakeMessageKind): Outcome {
+  if (expected === "psk_identity" || (expected === "nonce" && injected === "psk_identity")) return "bad identity";
+  return mode === "plain" ? "keys disagree" : "bad MAC";
+}
+
+/** Kinds a peer sends in `mode` */
+function kindsOf(mode: Mode): HandshakeMessageKind[] {
+  const kinds = new Set(SPEC[mode].initiator.transitions.map((t) => t.receive));
+  return [...kinds];
+}
+
+function lengthOf(kinds: HandshakeMessageKind[]): number {
+  return kinds.reduce((total, kind) => total + HANDSHAKE_MESSAGE_LENGTHS[kind], 0);
+}
+
+function equalBytes(a: Uint8Array, b: Uint8Array): boolean {
+  return a.length === b.length && a.every((byte, i) => byte === b[i]);
+}
+
+function classify(error: unknown): Outcome {
+  if (error instanceof ClavisError && error.cause instanceof CryptoError) {
+    if (error.cause.message.includes("MAC verification failed")) return "bad MAC";
+    if (error.cause.message.includes("zero-padded UTF-8")) return "bad identity";
+  }
+  throw error;
+}
+
+interface Run {
+  mode: Mode;
+  /** Role the machine under test takes */
+  role: Role;
+  /** Role the scripted peer takes (default: the other one) */
+  peerRole?: Role | undefined;
+  /** Send a message of this kind in place of transition `at` */
+  inject?: { at: number; kind: HandshakeMessageKind } | undefined;
+  /** Output lengths each transition produced, for checking a legal run against the spec */
+  sent?: number[] | undefined;
+}
+
+/**
+ * Run a `HandshakeMachine` against a peer scripted from the spec. The peer
+ * sends each message once it has what the message depends on, and stops
+ * when it is waiting for the machine.
+ */
+function run(options: Run): Outcome {
+  const { mode, role, inject, sent } = options;
+  const spec = SPEC[mode][role];
+  const peerInitiator = (options.peerRole ?? (role === "initiator" ? "responder" : "initiator")) === "initiator";
+  const psk = mode === "plain"
+    ? undefined
+    : mode === "psk"
+      ? PSK
+      : { identity: IDENTITY, keyFor: (identity: string) => (identity === IDENTITY ? PSK : undefined) };
+  const machine = new HandshakeMachine(psk);
+  let output = machine.takeOutput();
+  sent?.push(output.length);
+
+  const machineNonce = output.subarray(0, HANDSHAKE_MESSAGE_LENGTHS.nonce);
+  let peerNonce = generateRandomBytes(HANDSHAKE_MESSAGE_LENGTHS.nonce);
+  while (isHandshakeInitiator(machineNonce, peerNonce) !== (role === "initiator")) {
+    peerNonce = generateRandomBytes(HANDSHAKE_MESSAGE_LENGTHS.nonce);
+  }
+  const peer = generateX25519KeyPair();
+  const opening = lengthOf(spec.opening);
+  const machineKey = () => output.length >= opening + HANDSHAKE_MESSAGE_LENGTHS.public_key
+    ? output.subarray(opening, opening + HANDSHAKE_MESSAGE_LENGTHS.public_key)
+    : undefined;
+  const transcript = (key: Uint8Array) => peerInitiator
+    ? handshakeTranscript(peer.publicKey, key)
+    : handshakeTranscript(key, peer.publicKey);
+
+  /** The peer's message of `kind`, or undefined while it waits for the machine */
+  const message = (kind: HandshakeMessageKind, forced: boolean): Uint8Array | undefined => {
+    const key = machineKey();
+    switch (kind) {
+      case "nonce":
+        return peerNonce;
+      case "psk_identity":
+        return encodePskIdentity(IDENTITY);
+      case "public_key":
+        return peerInitiator || key || forced ? peer.publicKey : undefined;
+      case "mac": {
+        const macSent = output.length >= opening + HANDSHAKE_MESSAGE_LENGTHS.public_key + HANDSHAKE_MESSAGE_LENGTHS.mac;
+        if (key && (peerInitiator || macSent)) return handshakeMac(PSK, transcript(key));
+        // Sent early, before the peer could compute it
+        return forced ? generateRandomBytes(HANDSHAKE_MESSAGE_LENGTHS.mac) : undefined;
+      }
+    }
+  };
+
+  for (const [i, transition] of spec.transitions.entries()) {
+    const injected = inject?.at === i ? inject.kind : undefined;
+    const bytes = message(injected ?? transition.receive, injected !== undefined);
+    if (!bytes) break;
+    if (sent) {
+      expect(machine.bytesNeeded).toBe(HANDSHAKE_MESSAGE_LENGTHS[transition.receive]);
+      expect(() => machine.result()).toThrow(ClavisError);
+    }
+    try {
+      machine.feedBytes(bytes);
+    } catch (error) {
+      const outcome = classify(error);
+      // A failed machine stays failed
+      expect(() => machine.feedBytes(bytes)).toThrow(error as Error);
+      return outcome;
+    }
+    const more = machine.takeOutput();
+    sent?.push(more.length);
+    output = new Uint8Array([...output, ...more]);
+  }
+  if (!machine.done) return "stalls";
+
+  const key = machineKey()!;
+  const keys = deriveHandshakeKeys(computeSharedSecret(peer.secret, key), transcript(key), peerInitiator);
+  const result = machine.result();
+  return equalBytes(result.encKey, keys.decKey) && equalBytes(result.decKey, keys.encKey) ? "completes" : "keys disagree";
+}
+
+describe("Handshake state machine", () => {
+  test("should follow every legal sequence in the spec", () => {
+    for (const mode of MODES) {
+      for (const role of ROLES) {
+        const spec = SPEC[mode][role];
+        const sent: number[] = [];
+        expect(`${mode} ${role}: ${run({ mode, role, sent })}`).toBe(`${mode} ${role}: completes`);
+        const expected = [spec.opening, ...spec.transitions.map((t) => t.send)].map(lengthOf);
+        expect(`${mode} ${role}: sent ${sent}`).toBe(`${mode} ${role}: sent ${expected}`);
+      }
+    }
+  });
+
+  test("should leave bytes past the last message for the record layer", () => {
+    const [a, b] = [new HandshakeMachine(), new HandshakeMachine()];
+    b.feedBytes(a.takeOutput());
+    a.feedBytes(b.takeOutput());
+    b.feedBytes(a.takeOutput());
+    a.feedBytes(b.takeOutput());
+    expect(a.done && b.done).toBe(true);
+    expect(a.bytesNeeded).toBe(0);
+    expect(a.feedBytes(new Uint8Array(HANDSHAKE_MESSAGE_LENGTHS.public_key))).toBe(0);
+  });
+
+  test("should fail every message of the wrong kind the way the spec says", () => {
+    let cases = 0;
+    for (const mode of MODES) {
+      for (const role of ROLES) {
+        for (const [at, transition] of SPEC[mode][role].transitions.entries()) {
+          for (const kind of kindsOf(mode)) {
+            if (kind === transition.receive) continue;
+            // Any 32 bytes are a nonce; a wrong one only picks the roles, covered below
+            if (transition.receive === "nonce" && HANDSHAKE_MESSAGE_LENGTHS[kind] === HANDSHAKE_MESSAGE_LENGTHS.nonce) {
+              continue;
+            }
+            const label = `${mode} ${role}: ${kind} instead of ${transition.receive}`;
+            const outcome = run({ mode, role, inject: { at, kind } });
+            expect(`${label}: ${outcome}`).toBe(`${label}: ${expectedOutcome(mode, transition.receive, kind)}`);
+            cases++;
+          }
+        }
+      }
+    }
+    expect(cases).toBe(30);
+  });
+
+  test("should stall or fail when both sides take the same role", () => {
+    for (const mode of MODES) {
+      // Both wait for the other's public key
+      expect(`${mode}: ${run({ mode, role: "responder", peerRole: "responder" })}`).toBe(`${mode}: stalls`);
+      // Each puts its own key first in the transcript
+      const clash = mode === "plain" ? "keys disagree" : "bad MAC";
+      expect(`${mode}: ${run({ mode, role: "initiator", peerRole: "initiator" })}`).toBe(`${mode}: ${clash}`);
+    }
+  });
+});
+
+describe("Truncated handshake messages", () => {
+  /** Stream options that put an `EncryptedStream` in `mode` */
+  const optionsFor = (mode: Mode): EncryptedStreamOptions =>
+    mode === "plain" ? {} : mode === "psk" ? { psk: PSK } : { psks: new Map([[IDENTITY, PSK]]) };
+
+  test("should fail the handshake with a closed connection for each message cut short, and EOF between messages", async () => {
+    for (const mode of MODES) {
+      for (const role of ROLES) {
+        const spec = SPEC[mode][role];
+        for (const [at, transition] of spec.transitions.entries()) {
+          for (const keep of [HANDSHAKE_MESSAGE_LENGTHS[transition.receive] - 1, 0]) {
+            const [left, right] = await createStreamPair();
+            const peer = new RawPeer(right);
+            const establishing = EncryptedStream.new(left, optionsFor(mode)).catch((error: unknown) => error);
+
+            const machineNonce = (await peer.read(lengthOf(spec.opening))).subarray(0, HANDSHAKE_MESSAGE_LENGTHS.nonce);
+            let peerNonce = generateRandomBytes(HANDSHAKE_MESSAGE_LENGTHS.nonce);
+            while (isHandshakeInitiator(machineNonce, peerNonce) !== (role === "initiator")) {
+              peerNonce = generateRandomBytes(HANDSHAKE_MESSAGE_LENGTHS.nonce);
+            }
+            // Everything before the cut is legal; no MAC comes before it
+            const before: Record<HandshakeMessageKind, Uint8Array> = {
+              nonce: peerNonce,
+              psk_identity: encodePskIdentity(IDENTITY),
+              public_key: generateX25519KeyPair().publicKey,
+              mac: new Uint8Array(HANDSHAKE_MESSAGE_LENGTHS.mac),
+            };
+            for (const { receive } of spec.transitions.slice(0, at)) await peer.write(before[receive]);
+            if (keep > 0) await peer.write(generateRandomBytes(keep));
+            right.end();
+
+            const error = await establishing;
+            const label = `${mode} ${role}: ${keep} bytes of ${transition.receive}`;
+            expect(error).toBeInstanceOf(ClavisError);
+            const cause = (error as ClavisError).cause as StreamError;
+            expect(`${label}: ${cause.code}`).toBe(`${label}: ${StreamErrorCode.HandshakeFailed}`);
+            const ending = keep > 0 ? StreamErrorCode.ConnectionClosed : StreamErrorCode.EOF;
+            expect(`${label}: ${(cause.cause as StreamError).code}`).toBe(`${label}: ${ending}`);
+          }
+        }
+      }
+    }
+  });
+});
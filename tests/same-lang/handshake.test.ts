@@ -5,7 +5,12 @@
 import { describe, test, expect, beforeEach, afterEach } from "bun:test";
 import { createTestServer } from "../helpers/test-server.js";
 import { createTestClient } from "../helpers/test-client.js";
-import { findAvailablePort } from "../helpers/test-utils.js";
+import { createEncryptedStreamPair, findAvailablePort } from "../helpers/test-utils.js";
+import { EncryptedStream, checkStreamOptions } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { HandshakeMachine, type HandshakeTimings, type TimedHandshakeResult } from "../../src/handshake.js";
+import { ManualClock } from "../../src/clock.js";
 import { Server } from "net";
 
 describe("Handshake", () => {
@@ -118,3 +123,67 @@ describe("Handshake", () => {
     client.close();
   });
 });
+
+describe("Handshake timings", () => {
+  /** Run two machines against each other, every flight taking `latencyMs` */
+  async function timedHandshake(psk: Uint8Array | undefined, latencyMs: number): Promise<[TimedHandshakeResult, TimedHandshakeResult]> {
+    const clock = new ManualClock(0);
+    const [a, b] = [new HandshakeMachine(psk, clock), new HandshakeMachine(psk, clock)];
+    let [toA, toB] = [b.takeOutput(), a.takeOutput()];
+    while (!a.done || !b.done) {
+      await clock.advance(latencyMs);
+      a.feedBytes(toA);
+      b.feedBytes(toB);
+      [toA, toB] = [b.takeOutput(), a.takeOutput()];
+    }
+    return a.result().initiator ? [a.result(), b.result()] : [b.result(), a.result()];
+  }
+
+  test("should attribute each flight to its stage", async () => {
+    const psk = new TextEncoder().encode("a-pre-shared-key-of-32-bytes!!!!");
+    // Nonces cross, the initiator's key and the responder's answer, then the MACs in turn
+    const [initiator, responder] = await timedHandshake(psk, 20);
+    expect(initiator.timings).toEqual({ firstFlightMs: 20, keyExchangeMs: 40, confirmationMs: 40, cryptoMs: 0, setupMs: 0, totalMs: 100 });
+    expect(responder.timings).toEqual({ firstFlightMs: 20, keyExchangeMs: 20, confirmationMs: 40, cryptoMs: 0, setupMs: 0, totalMs: 80 });
+
+    const [plainInitiator, plainResponder] = await timedHandshake(undefined, 20);
+    expect(plainInitiator.timings).toEqual({ firstFlightMs: 20, keyExchangeMs: 40, confirmationMs: 0, cryptoMs: 0, setupMs: 0, totalMs: 60 });
+    expect(plainResponder.timings).toEqual({ firstFlightMs: 20, keyExchangeMs: 20, confirmationMs: 0, cryptoMs: 0, setupMs: 0, totalMs: 40 });
+  });
+
+  test("should report a stream's timings to onHandshakeTimings", async () => {
+    let traced: HandshakeTimings | undefined;
+    const [client] = await createEncryptedStreamPair({ onHandshakeTimings: (timings) => (traced = timings) });
+    const timings = client.handshakeTimings;
+    expect(traced).toEqual(timings);
+    expect(timings.totalMs).toBeCloseTo(timings.firstFlightMs + timings.keyExchangeMs + timings.confirmationMs + timings.setupMs);
+  });
+});
+
+describe("PSK identities", () => {
+  const oldKey = new TextEncoder().encode("old-pre-shared-key-2026");
+  const newKey = new TextEncoder().encode("new-pre-shared-key-2026");
+
+  test("should accept clients on either key during a rotation", async () => {
+    const psks = new Map([["2026-09", oldKey], ["2026-10", newKey]]);
+    for (const [identity, psk] of [["2026-09", oldKey], ["2026-10", newKey]] as const) {
+      const [client, server] = await createEncryptedStreamPair({ psk, pskIdentity: identity }, { psks });
+      expect(server.pskIdentity).toBe(identity);
+      expect(client.pskIdentity).toBeUndefined();
+      await client.writePacket(new RawPacket(new Uint8Array([1])));
+      expect(await server.readPacket()).toEqual(new Uint8Array([1]));
+    }
+  });
+
+  test("should refuse unknown identities and keys that don't match their identity", async () => {
+    const psks = (identity: string) => (identity === "2026-10" ? newKey : undefined);
+    await expect(createEncryptedStreamPair({ psk: newKey, pskIdentity: "2025-01" }, { psks })).rejects.toThrow(ClavisError);
+    await expect(createEncryptedStreamPair({ psk: oldKey, pskIdentity: "2026-10" }, { psks })).rejects.toThrow(ClavisError);
+  });
+
+  test("should reject conflicting options before the handshake", () => {
+    expect(() => checkStreamOptions({ psk: newKey, psks: { a: newKey } })).toThrow(ClavisError);
+    expect(() => checkStreamOptions({ pskIdentity: "2026-10" })).toThrow(ClavisError);
+    expect(() => checkStreamOptions({ psk: newKey, pskIdentity: "x".repeat(65) })).toThrow(ClavisError);
+  });
+});
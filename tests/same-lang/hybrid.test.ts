@@ -0,0 +1,67 @@
+/**
+ * Hybrid key exchange tests - ML-KEM-768 mixed into the session keys
+ */
+
+import { describe, test, expect } from "bun:test";
+import { decodeHybridOffer, deriveHybridKeys, encodeHybridOffer, loadMlKem } from "../../src/hybrid.js";
+import type { HandshakeResult } from "../../src/handshake.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+function handshake(initiator: boolean): HandshakeResult {
+  const enc = new Uint8Array(32).fill(1);
+  const dec = new Uint8Array(32).fill(2);
+  return {
+    encKey: initiator ? enc : dec,
+    decKey: initiator ? dec : enc,
+    transcriptHash: new Uint8Array(32).fill(3),
+    initiator,
+    resumptionSecret: new Uint8Array(32).fill(4),
+  };
+}
+
+describe("Hybrid offers", () => {
+  test("should round-trip offers with and without a key", () => {
+    const key = new Uint8Array(1184).fill(9);
+    expect(decodeHybridOffer(encodeHybridOffer("require", key))).toEqual({ mode: "require", encapsulationKey: key });
+    expect(decodeHybridOffer(encodeHybridOffer(undefined))).toEqual({ mode: undefined, encapsulationKey: undefined });
+  });
+
+  test("should reject malformed offers", () => {
+    expect(() => decodeHybridOffer(new Uint8Array([3]))).toThrow(ClavisError);
+    expect(() => decodeHybridOffer(new Uint8Array([1, 2, 3]))).toThrow(ClavisError);
+    expect(() => decodeHybridOffer(new Uint8Array(0))).toThrow(ClavisError);
+  });
+
+  test("should derive matching keys on both sides", () => {
+    const secret = new Uint8Array(32).fill(5);
+    const key = new Uint8Array(1184);
+    const cipherText = new Uint8Array(1088);
+    const initiator = deriveHybridKeys(handshake(true), secret, key, cipherText);
+    const responder = deriveHybridKeys(handshake(false), secret, key, cipherText);
+    expect(initiator.encKey).toEqual(responder.decKey);
+    expect(initiator.decKey).toEqual(responder.encKey);
+    expect(initiator.resumptionSecret).toEqual(responder.resumptionSecret);
+    expect(initiator.encKey).not.toEqual(handshake(true).encKey);
+  });
+});
+
+describe("postQuantum streams", () => {
+  test("should upgrade both peers to hybrid keys", async () => {
+    if (!(await loadMlKem())) return;
+    const [a, b] = await createEncryptedStreamPair({ postQuantum: "prefer" }, { postQuantum: "require" });
+    expect(a.postQuantum).toBe(true);
+    expect(b.postQuantum).toBe(true);
+
+    await a.writePacket(new RawPacket(new Uint8Array([1, 2, 3])));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1, 2, 3]));
+    await b.writePacket(new RawPacket(new Uint8Array([4])));
+    expect((await a.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([4]));
+  });
+
+  test("should stay classical without the option", async () => {
+    const [a] = await createEncryptedStreamPair();
+    expect(a.postQuantum).toBe(false);
+  });
+});
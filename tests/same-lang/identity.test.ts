@@ -0,0 +1,140 @@
+/**
+ * Identity certificate tests - signer-issued identities checked during connection setup
+ */
+
+import { describe, test, expect } from "bun:test";
+import {
+  decodeCertificate,
+  encodeCertificate,
+  issueCertificate,
+  keyFingerprint,
+  verifyCertificate,
+  type IdentityCredentials,
+  type PeerIdentity,
+} from "../../src/identity.js";
+import { generateEd25519KeyPair } from "../../src/crypto.js";
+import { ClavisError } from "../../src/error.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const signer = generateEd25519KeyPair();
+const hour = 60 * 60 * 1000;
+
+function credentials(identity: string, expiresAt = new Date(Date.now() + hour), issuer = signer): IdentityCredentials {
+  const key = generateEd25519KeyPair();
+  return {
+    certificate: issueCertificate(issuer.secretKey, { identity, publicKey: key.publicKey, expiresAt }),
+    secretKey: key.secretKey,
+  };
+}
+
+describe("Identity certificates", () => {
+  test("should round-trip and verify", () => {
+    const { certificate } = credentials("device-42");
+    const decoded = decodeCertificate(encodeCertificate(certificate));
+    expect(decoded).toEqual(certificate);
+    expect(() => verifyCertificate(decoded, [signer.publicKey])).not.toThrow();
+  });
+
+  test("should reject untrusted, tampered and expired certificates", () => {
+    const { certificate } = credentials("alice");
+    expect(() => verifyCertificate(certificate, [generateEd25519KeyPair().publicKey])).toThrow(ClavisError);
+    expect(() => verifyCertificate({ ...certificate, identity: "mallory" }, [signer.publicKey])).toThrow(ClavisError);
+    expect(() => verifyCertificate(certificate, [signer.publicKey], Date.now() + 2 * hour)).toThrow(ClavisError);
+
+    const encoded = encodeCertificate(certificate);
+    expect(() => decodeCertificate(encoded.subarray(0, encoded.length - 1))).toThrow(ClavisError);
+  });
+});
+
+describe("Identity exchange", () => {
+  test("should expose the verified client identity to the server", async () => {
+    const [client, server] = await createEncryptedStreamPair(
+      { identity: credentials("alice") },
+      { trustedSigners: [signer.publicKey] }
+    );
+    expect(server.peerIdentity?.identity).toBe("alice");
+    expect(client.peerIdentity).toBeUndefined();
+
+    await client.writePacket(new RawPacket(new Uint8Array([1])));
+    expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+  });
+
+  test("should refuse clients without a trusted certificate", async () => {
+    const attempts = [
+      { identity: credentials("bob", new Date(Date.now() + hour), generateEd25519KeyPair()) },
+      { identity: credentials("carol", new Date(Date.now() - 1)) },
+      { trustedSigners: [] },
+    ];
+    for (const client of attempts) {
+      const error = await createEncryptedStreamPair(client, { trustedSigners: [signer.publicKey] })
+        .catch((error) => error);
+      expect(error).toBeInstanceOf(ClavisError);
+    }
+  });
+
+  test("should consult the revocation check with a stable credential id", async () => {
+    const alice = credentials("alice");
+    const revoked = new Set<string>();
+    const server = {
+      trustedSigners: [signer.publicKey],
+      isRevoked: async (identity: { credentialId: string }) => revoked.has(identity.credentialId),
+    };
+
+    const [, first] = await createEncryptedStreamPair({ identity: alice }, server);
+    revoked.add(first.peerIdentity!.credentialId);
+    const error = await createEncryptedStreamPair({ identity: alice }, server).catch((error) => error);
+    expect((error as ClavisError).message).toContain("revoked");
+
+    const failing = { ...server, isRevoked: () => Promise.reject(new Error("store down")) };
+    expect(await createEncryptedStreamPair({ identity: credentials("bob") }, failing).catch((error) => error))
+      .toBeInstanceOf(ClavisError);
+  });
+
+  test("should reject a certificate presented without its secret key", async () => {
+    const stolen = credentials("alice").certificate;
+    const error = await createEncryptedStreamPair(
+      { identity: { certificate: stolen, secretKey: generateEd25519KeyPair().secretKey } },
+      { trustedSigners: [signer.publicKey] }
+    ).catch((error) => error);
+    expect((error as ClavisError).message).toContain("certificate's key");
+  });
+});
+
+describe("Static identity keys", () => {
+  test("should let verifyPeer accept both sides' bare keys by fingerprint", async () => {
+    const clientKey = generateEd25519KeyPair();
+    const serverKey = generateEd25519KeyPair();
+    const pinned = (key: Uint8Array) => (peer: PeerIdentity) => peer.credentialId === keyFingerprint(key);
+    const [client, server] = await createEncryptedStreamPair(
+      { identity: { secretKey: clientKey.secretKey }, verifyPeer: pinned(serverKey.publicKey) },
+      { identity: { secretKey: serverKey.secretKey }, verifyPeer: pinned(clientKey.publicKey) }
+    );
+    expect(server.peerIdentity?.publicKey).toEqual(clientKey.publicKey);
+    expect(client.peerIdentity?.identity).toBe(keyFingerprint(serverKey.publicKey));
+  });
+
+  test("should refuse keys verifyPeer rejects and keys nobody checks", async () => {
+    const key = { secretKey: generateEd25519KeyPair().secretKey };
+    const refused = await createEncryptedStreamPair({ identity: key }, { verifyPeer: () => false })
+      .catch((error) => error);
+    expect((refused as ClavisError).message).toContain("refused");
+
+    const unchecked = await createEncryptedStreamPair({ identity: key }, { trustedSigners: [signer.publicKey] })
+      .catch((error) => error);
+    expect(unchecked).toBeInstanceOf(ClavisError);
+
+    const anyone = { verifyPeer: () => true };
+    const anonymous = await createEncryptedStreamPair(anyone, anyone).catch((error) => error);
+    expect(anonymous).toBeInstanceOf(ClavisError);
+  });
+
+  test("should pass certificate identities to verifyPeer too", async () => {
+    const seen: string[] = [];
+    await createEncryptedStreamPair(
+      { identity: credentials("alice") },
+      { trustedSigners: [signer.publicKey], verifyPeer: (peer) => (seen.push(peer.identity), true) }
+    );
+    expect(seen).toEqual(["alice"]);
+  });
+});
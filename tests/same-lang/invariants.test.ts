@@ -0,0 +1,81 @@
+/**
+ * Debug assertion tests - frame layer invariants checked under test and caught when broken
+ */
+
+import { describe, test, expect, afterEach } from "bun:test";
+import {
+  FrameInvariants,
+  InvariantViolation,
+  areDebugAssertionsEnabled,
+  checkBuffered,
+  setDebugAssertions,
+} from "../../src/invariants.js";
+import { sealFrame, sequenceNonce } from "../../src/frame.js";
+import { XChaCha20Poly1305Cipher, generateRandomBytes } from "../../src/crypto.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const enabledByDefault = areDebugAssertionsEnabled();
+afterEach(() => setDebugAssertions(enabledByDefault));
+
+const cipher = new XChaCha20Poly1305Cipher(generateRandomBytes(32));
+const frame = (sequence: number) => sealFrame(cipher, new Uint8Array([sequence]), false, sequenceNonce(sequence));
+const packet = (n: number) => new RawPacket(new Uint8Array([n]));
+
+/** The frame session behind a stream, to corrupt its counters */
+type Internals = { session: { recordsSent: number; pendingBytes: number } };
+
+describe("Debug assertions", () => {
+  test("should be on under bun test", () => {
+    expect(enabledByDefault).toBe(true);
+  });
+
+  test("should accept frames numbered and written in order and refuse anything else", () => {
+    const invariants = new FrameInvariants(0);
+    invariants.sealing(0, undefined);
+    invariants.sealing(1, sequenceNonce(1));
+    invariants.sealing(2, sequenceNonce(2));
+    invariants.writing(sealFrame(cipher, new Uint8Array([0])));
+    invariants.writing(new Uint8Array([...frame(1), ...frame(2)]));
+    invariants.writing(new Uint8Array(0));
+
+    // A number handed out twice, a nonce that doesn't carry it, a random nonce after numbered ones
+    expect(() => invariants.sealing(2, sequenceNonce(2))).toThrow(InvariantViolation);
+    expect(() => new FrameInvariants(3).sealing(3, sequenceNonce(4))).toThrow(InvariantViolation);
+    expect(() => new FrameInvariants(3, 0).sealing(3, undefined)).toThrow(InvariantViolation);
+
+    // Frames written out of order, cut short, or never sealed
+    const swapped = new FrameInvariants(0);
+    swapped.sealing(0, sequenceNonce(0));
+    swapped.sealing(1, sequenceNonce(1));
+    expect(() => swapped.writing(frame(1))).toThrow(InvariantViolation);
+    const short = new FrameInvariants(0);
+    short.sealing(0, sequenceNonce(0));
+    expect(() => short.writing(frame(0).subarray(0, 20))).toThrow(InvariantViolation);
+    expect(() => new FrameInvariants(0).writing(frame(0))).toThrow(InvariantViolation);
+
+    checkBuffered(3, [new Uint8Array(1), new Uint8Array(2)], "Read buffer");
+    expect(() => checkBuffered(4, [new Uint8Array(1), new Uint8Array(2)], "Read buffer")).toThrow(/counted as 4 bytes, holds 3/);
+  });
+
+  test("should pass ordinary traffic and stop a stream whose counters were corrupted", async () => {
+    setDebugAssertions(true);
+    const [client, server] = await createEncryptedStreamPair({ sequenceNumbers: true }, { sequenceNumbers: true });
+    await client.writePacket(packet(1));
+    await client.writePacketBuffered(packet(2));
+    await client.writePacketBuffered(packet(3));
+    await client.flush();
+    for (const n of [1, 2, 3]) {
+      expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([n]));
+    }
+
+    const session = (client as unknown as Internals).session;
+    await client.writePacketBuffered(packet(4));
+    session.pendingBytes++;
+    await expect(client.flush()).rejects.toThrow(/invariant violated: Held frames/);
+
+    const [other] = await createEncryptedStreamPair({ sequenceNumbers: true }, { sequenceNumbers: true });
+    (other as unknown as Internals).session.recordsSent--;
+    await expect(other.writePacket(packet(5))).rejects.toThrow(/invariant violated: sealing frame/);
+  });
+});
@@ -0,0 +1,51 @@
+/**
+ * Packet journal tests - journal before handling, acknowledge after storing
+ */
+
+import { describe, test, expect } from "bun:test";
+import { PacketJournal } from "../../src/journal.js";
+import { MemoryStorage } from "../../src/storage.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+describe("PacketJournal", () => {
+  test("should acknowledge packets once they are journaled", async () => {
+    const journal = new PacketJournal(new MemoryStorage());
+    const [client, server] = await createEncryptedStreamPair({}, { journal });
+    // Acks arrive as control frames, so the client must be reading
+    const reply = client.readPacket();
+
+    await client.writePackets([new RawPacket(new Uint8Array([1])), new RawPacket(new Uint8Array([2]))]);
+    const first = await server.readJournaled();
+    const second = await server.readJournaled();
+    expect([first.sequence, second.sequence]).toEqual([0, 1]);
+    expect(second.packet).toEqual(new Uint8Array([2]));
+
+    await client.waitForAcknowledgment();
+    expect(client.acknowledgedPackets).toBe(2);
+    expect((await journal.pending()).map((entry) => entry.sequence)).toEqual([0, 1]);
+
+    await server.writePacket(new RawPacket(new Uint8Array([3])));
+    expect((await reply) as unknown as Uint8Array).toEqual(new Uint8Array([3]));
+  });
+
+  test("should hand back unfinished entries after a restart", async () => {
+    const storage = new MemoryStorage();
+    const journal = new PacketJournal(storage);
+    await journal.append(new Uint8Array([1]));
+    await journal.append(new Uint8Array([2]));
+    await journal.complete(0);
+
+    const restarted = new PacketJournal(storage);
+    expect(await restarted.pending()).toEqual([{ sequence: 1, packet: new Uint8Array([2]) }]);
+    expect((await restarted.append(new Uint8Array([3]))).sequence).toBe(2);
+  });
+
+  test("should refuse reads that bypass the journal", async () => {
+    const journal = new PacketJournal(new MemoryStorage());
+    const [, server] = await createEncryptedStreamPair({ compression: {} }, { journal, compression: {} });
+    await expect(server.readPacket()).rejects.toThrow(ClavisError);
+    await expect(server.readPackets()).rejects.toThrow(ClavisError);
+  });
+});
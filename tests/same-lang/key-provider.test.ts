@@ -0,0 +1,71 @@
+/**
+ * Key provider tests - handshake and identity keys held outside the stream
+ */
+
+import { describe, test, expect } from "bun:test";
+import type { KeyExchangeProvider, IdentitySigner } from "../../src/key-provider.js";
+import { inMemoryKeyExchange } from "../../src/key-provider.js";
+import { keyFingerprint } from "../../src/identity.js";
+import { ed25519PublicKey, ed25519Sign, generateEd25519KeyPair } from "../../src/crypto.js";
+import { ClavisError } from "../../src/error.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+/** Stands in for an HSM: keys stay in the closure, and calls are counted */
+function countingKeystore() {
+  const calls = { generated: 0, agreed: 0, destroyed: 0 };
+  const provider: KeyExchangeProvider = {
+    generateEphemeral() {
+      calls.generated++;
+      const key = inMemoryKeyExchange.generateEphemeral();
+      return {
+        publicKey: key.publicKey,
+        agree: (peer) => (calls.agreed++, key.agree(peer)),
+        destroy: () => (calls.destroyed++, key.destroy?.()),
+      };
+    },
+  };
+  return { provider, calls };
+}
+
+describe("KeyExchangeProvider", () => {
+  test("should run the handshake's key exchange through the provider", async () => {
+    const { provider, calls } = countingKeystore();
+    const [a, b] = await createEncryptedStreamPair({ keyExchange: provider }, {});
+    expect(calls).toEqual({ generated: 1, agreed: 1, destroyed: 1 });
+
+    await a.writePacket(new RawPacket(new Uint8Array([7])));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([7]));
+  });
+
+  test("should refuse a provider returning a bad key or secret", async () => {
+    const shortKey: KeyExchangeProvider = {
+      generateEphemeral: () => ({ publicKey: new Uint8Array(16), agree: () => new Uint8Array(32) }),
+    };
+    await expect(createEncryptedStreamPair({ keyExchange: shortKey }, {})).rejects.toThrow(ClavisError);
+
+    const zeroSecret: KeyExchangeProvider = {
+      generateEphemeral: () => ({
+        publicKey: inMemoryKeyExchange.generateEphemeral().publicKey,
+        agree: () => new Uint8Array(32),
+      }),
+    };
+    await expect(createEncryptedStreamPair({ keyExchange: zeroSecret }, {})).rejects.toThrow(ClavisError);
+  });
+});
+
+describe("IdentitySigner", () => {
+  test("should prove a static identity without its secret key in the options", async () => {
+    const held = generateEd25519KeyPair().secretKey;
+    const publicKey = ed25519PublicKey(held);
+    let signatures = 0;
+    const signer: IdentitySigner = { publicKey, sign: (message) => (signatures++, ed25519Sign(held, message)) };
+
+    const [, server] = await createEncryptedStreamPair(
+      { identity: { signer }, verifyPeer: () => true },
+      { identity: { secretKey: generateEd25519KeyPair().secretKey }, verifyPeer: () => true }
+    );
+    expect(signatures).toBe(1);
+    expect(server.peerIdentity?.identity).toBe(keyFingerprint(publicKey));
+  });
+});
@@ -0,0 +1,56 @@
+/**
+ * Keying material exporter tests - agreement between peers, separation by label, context and connection, and session ids
+ */
+
+import { describe, test, expect } from "bun:test";
+import { exportKeyingMaterial, MAX_EXPORTED_LENGTH } from "../../src/keying-material.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+describe("exportKeyingMaterial", () => {
+  test("should give both peers the same bytes, distinct per label, context and connection", async () => {
+    const [client, server] = await createEncryptedStreamPair();
+    const context = new Uint8Array([1, 2, 3]);
+
+    const binding = client.exportKeyingMaterial("login", context);
+    expect(binding).toHaveLength(32);
+    expect(server.exportKeyingMaterial("login", context)).toEqual(binding);
+    expect(client.exportKeyingMaterial("login", new Uint8Array([1, 2, 4]))).not.toEqual(binding);
+    expect(client.exportKeyingMaterial("other", context)).not.toEqual(binding);
+    expect(client.exportKeyingMaterial("login")).toEqual(client.exportKeyingMaterial("login", new Uint8Array(0)));
+    expect(client.exportKeyingMaterial("login", context, 64)).toHaveLength(64);
+
+    const [another] = await createEncryptedStreamPair();
+    expect(another.exportKeyingMaterial("login", context)).not.toEqual(binding);
+  });
+
+  test("should not change on rekey", async () => {
+    const [client, server] = await createEncryptedStreamPair({ postQuantum: "require" }, { postQuantum: "require" });
+    const before = client.exportKeyingMaterial("login");
+    void client.readPacket().catch(() => {});
+    void server.readPacket().catch(() => {});
+    await client.rekey();
+    expect(client.rekeyCount).toBe(1);
+    expect(client.exportKeyingMaterial("login")).toEqual(before);
+    expect(server.exportKeyingMaterial("login")).toEqual(before);
+  });
+
+  test("should reject empty labels and out-of-range lengths", () => {
+    const secret = new Uint8Array(32);
+    expect(() => exportKeyingMaterial(secret, "")).toThrow(ClavisError);
+    expect(() => exportKeyingMaterial(secret, "x", undefined, 0)).toThrow(ClavisError);
+    expect(() => exportKeyingMaterial(secret, "x", undefined, MAX_EXPORTED_LENGTH + 1)).toThrow(ClavisError);
+    expect(exportKeyingMaterial(secret, "x", undefined, MAX_EXPORTED_LENGTH)).toHaveLength(MAX_EXPORTED_LENGTH);
+  });
+});
+
+describe("sessionId", () => {
+  test("should be the same on both sides and distinct per session", async () => {
+    const [client, server] = await createEncryptedStreamPair({ connectionId: "client-1" }, { connectionId: "server-9" });
+    expect(client.sessionId).toMatch(/^[0-9a-f]{32}$/);
+    expect(server.sessionId).toBe(client.sessionId);
+
+    const [another] = await createEncryptedStreamPair();
+    expect(another.sessionId).not.toBe(client.sessionId);
+  });
+});
@@ -0,0 +1,58 @@
+/**
+ * Key log tests - SSLKEYLOGFILE-style traffic keys behind an explicit opt-in
+ */
+
+import { describe, test, expect, afterEach } from "bun:test";
+import { KeyLogger, allowKeyLog, formatKeyLogLine } from "../../src/keylog.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair, createStreamPair } from "../helpers/test-utils.js";
+import { EncryptedStream, checkStreamOptions } from "../../src/stream.js";
+
+afterEach(() => allowKeyLog(false));
+
+describe("keyLog", () => {
+  test("should refuse to log unless the process allows it", async () => {
+    expect(() => new KeyLogger(() => {}, "00", true)).toThrow(ClavisError);
+    expect(() => checkStreamOptions({ keyLog: () => {} })).toThrow(ClavisError);
+
+    // Refused up front: not a byte reaches the peer
+    const [left, right] = await createStreamPair();
+    let sent = 0;
+    right.on("data", (chunk: Buffer) => (sent += chunk.length));
+    const error = (await EncryptedStream.new(left, { keyLog: () => {} }).catch((e) => e)) as ClavisError;
+    expect(error.configConflicts?.map((conflict) => conflict.options)).toEqual([["keyLog"]]);
+    expect(sent).toBe(0);
+
+    expect(formatKeyLogLine("server", 2, "ab", new Uint8Array([1, 255])))
+      .toBe("CLAVIS_SERVER_TRAFFIC_KEY_2 ab 01ff");
+  });
+
+  test("should log the same keys on both peers, rekeys included", async () => {
+    allowKeyLog(true);
+    const linesA: string[] = [];
+    const linesB: string[] = [];
+    const [a, b] = await createEncryptedStreamPair(
+      { keyLog: (line) => linesA.push(line) },
+      { keyLog: (line) => linesB.push(line) }
+    );
+    void a.readPacket().catch(() => {});
+    const read = b.readPacket();
+    await a.rekey();
+    await a.writePacket(new RawPacket(new Uint8Array([1])));
+    await read;
+
+    expect(linesA.map((line) => line.split(" ")[0]).sort()).toEqual([
+      "CLAVIS_CLIENT_TRAFFIC_KEY_0",
+      "CLAVIS_CLIENT_TRAFFIC_KEY_1",
+      "CLAVIS_SERVER_TRAFFIC_KEY_0",
+      "CLAVIS_SERVER_TRAFFIC_KEY_1",
+    ]);
+    expect([...linesA].sort()).toEqual([...linesB].sort());
+    for (const line of linesA) {
+      const [, sessionId, key] = line.split(" ");
+      expect(sessionId).toBe(a.sessionId);
+      expect(key).toMatch(/^[0-9a-f]{64}$/);
+    }
+  });
+});
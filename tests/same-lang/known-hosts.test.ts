@@ -0,0 +1,76 @@
+/**
+ * Known hosts tests - host keys remembered on first use and changes refused or reported
+ */
+
+import { describe, test, expect } from "bun:test";
+import { createStreamPair } from "../helpers/test-utils.js";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+import { KnownHosts, type PeerKeyChange } from "../../src/known-hosts.js";
+import { MemoryStorage } from "../../src/storage.js";
+import { keyFingerprint } from "../../src/identity.js";
+import { generateEd25519KeyPair } from "../../src/crypto.js";
+import { ClavisError } from "../../src/error.js";
+
+/** Connect a client with `client` options to a server presenting `serverKey`; resolves the client's stream or error */
+async function connect(serverKey: Uint8Array, client: EncryptedStreamOptions): Promise<EncryptedStream | unknown> {
+  const [left, right] = await createStreamPair();
+  const server = EncryptedStream.new(right, { identity: { secretKey: serverKey } }).catch(() => undefined);
+  const result = await EncryptedStream.new(left, client).catch((error: unknown) => error);
+  if (!(result instanceof EncryptedStream)) left.destroy();
+  await server;
+  return result;
+}
+
+describe("KnownHosts", () => {
+  test("should trust a host's first key and refuse another one", async () => {
+    const first = generateEd25519KeyPair();
+    const second = generateEd25519KeyPair();
+    const changes: PeerKeyChange[] = [];
+    const knownHosts = new KnownHosts({ storage: new MemoryStorage(), onKeyChanged: (change) => changes.push(change) });
+    const client = { verifyPeer: knownHosts.verifier("db-1") };
+
+    expect(await connect(first.secretKey, client)).toBeInstanceOf(EncryptedStream);
+    expect(await knownHosts.get("db-1")).toBe(keyFingerprint(first.publicKey));
+    expect(await connect(first.secretKey, client)).toBeInstanceOf(EncryptedStream);
+
+    const error = await connect(second.secretKey, client);
+    expect(error).toBeInstanceOf(ClavisError);
+    expect((error as ClavisError).peerKeyChanged).toMatchObject({
+      host: "db-1",
+      previous: keyFingerprint(first.publicKey),
+      current: keyFingerprint(second.publicKey),
+    });
+    expect(changes).toEqual([{
+      host: "db-1",
+      previous: keyFingerprint(first.publicKey),
+      current: keyFingerprint(second.publicKey),
+      accepted: false,
+    }]);
+    expect(await knownHosts.get("db-1")).toBe(keyFingerprint(first.publicKey));
+
+    // Forgetting the host trusts whatever key it presents next
+    expect(await knownHosts.forget("db-1")).toBe(true);
+    expect(await connect(second.secretKey, client)).toBeInstanceOf(EncryptedStream);
+  });
+
+  test("should accept and remember a changed key in warn mode, and honor pinned keys", async () => {
+    const first = generateEd25519KeyPair();
+    const second = generateEd25519KeyPair();
+    const changes: PeerKeyChange[] = [];
+    const knownHosts = new KnownHosts({
+      storage: new MemoryStorage(),
+      onChange: "warn",
+      onKeyChanged: (change) => changes.push(change),
+    });
+    await knownHosts.trust("db-2", keyFingerprint(first.publicKey));
+
+    expect(await connect(second.secretKey, { verifyPeer: knownHosts.verifier("db-2") })).toBeInstanceOf(EncryptedStream);
+    expect(changes.map((c) => c.accepted)).toEqual([true]);
+    expect(await knownHosts.get("db-2")).toBe(keyFingerprint(second.publicKey));
+
+    const strict = new KnownHosts({ storage: new MemoryStorage(), trustOnFirstUse: false });
+    const refused = await connect(first.secretKey, { verifyPeer: strict.verifier("db-3") });
+    expect(refused).toBeInstanceOf(ClavisError);
+    expect((refused as ClavisError).peerKeyChanged).toBeUndefined();
+  });
+});
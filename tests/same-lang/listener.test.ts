@@ -0,0 +1,432 @@
+/**
+ * Listener tests - accept helper, handshake limiting and flood protection
+ */
+
+import { describe, test, expect, afterEach } from "bun:test";
+import { EncryptedListener, HandshakeLimiter, HandshakeRateLimiter, detectForeignProtocol } from "../../src/listener.js";
+import { ManualClock } from "../../src/clock.js";
+import { generateRandomBytes } from "../../src/crypto.js";
+import { createTestClient } from "../helpers/test-client.js";
+import { findAvailablePort } from "../helpers/test-utils.js";
+import { ClavisError, StreamError, StreamErrorCode } from "../../src/error.js";
+import { parseProxyHeader } from "../../src/proxy-protocol.js";
+import { spawnShards, bindShard } from "../../src/shards.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createConnection, type AddressInfo, type Socket } from "net";
+import { EncryptedStream } from "../../src/stream.js";
+
+describe("HandshakeLimiter", () => {
+  test("should grant slots up to the concurrency limit", async () => {
+    const limiter = new HandshakeLimiter(2, 1);
+    await limiter.acquire();
+    await limiter.acquire();
+    expect(limiter.active).toBe(2);
+    expect(limiter.queued).toBe(0);
+  });
+
+  test("should queue and hand over slots in order", async () => {
+    const limiter = new HandshakeLimiter(1, 2);
+    const release = await limiter.acquire();
+
+    const order: number[] = [];
+    const second = limiter.acquire().then((r) => { order.push(2); return r; });
+    const third = limiter.acquire().then((r) => { order.push(3); return r; });
+    expect(limiter.queued).toBe(2);
+
+    release();
+    (await second)();
+    (await third)();
+
+    expect(order).toEqual([2, 3]);
+    expect(limiter.active).toBe(0);
+  });
+
+  test("should reject with Overloaded when the queue is full", async () => {
+    const limiter = new HandshakeLimiter(1, 0);
+    await limiter.acquire();
+
+    try {
+      await limiter.acquire();
+      throw new Error("expected overload");
+    } catch (error) {
+      expect(error).toBeInstanceOf(ClavisError);
+      const cause = (error as ClavisError).cause;
+      expect(cause).toBeInstanceOf(StreamError);
+      expect((cause as StreamError).code).toBe(StreamErrorCode.Overloaded);
+    }
+  });
+
+  test("should drop aborted waiters from the queue", async () => {
+    const limiter = new HandshakeLimiter(1, 1);
+    await limiter.acquire();
+
+    const abort = new AbortController();
+    const waiting = limiter.acquire(abort.signal);
+    abort.abort();
+
+    await expect(waiting).rejects.toThrow();
+    expect(limiter.queued).toBe(0);
+  });
+});
+
+describe("EncryptedListener", () => {
+  let listener: EncryptedListener | undefined;
+
+  afterEach(async () => {
+    await listener?.close();
+    listener = undefined;
+  });
+
+  test("should accept handshaken streams", async () => {
+    const port = await findAvailablePort();
+    listener = await EncryptedListener.bind(port);
+
+    const [accepted, client] = await Promise.all([
+      listener.accept(),
+      createTestClient({ host: "127.0.0.1", port }),
+    ]);
+
+    expect(accepted.stream).toBeDefined();
+    expect(listener.activeHandshakes).toBe(0);
+    client.close();
+  });
+
+  test("should reject pending accepts when closed", async () => {
+    const port = await findAvailablePort();
+    listener = await EncryptedListener.bind(port);
+
+    const pending = listener.accept();
+    await listener.close();
+    await expect(pending).rejects.toThrow();
+  });
+
+  test("should bind a host:port address and accept split halves", async () => {
+    const port = await findAvailablePort();
+    listener = await EncryptedListener.bind(`127.0.0.1:${port}`);
+
+    const [accepted, client] = await Promise.all([
+      listener.acceptSplit(),
+      createTestClient({ host: "127.0.0.1", port }),
+    ]);
+    await client.stream.writePacket(new RawPacket(new Uint8Array([9])));
+    expect(Array.from((await accepted.reader.readPacket()) as unknown as Uint8Array)).toEqual([9]);
+    await accepted.writer.writePacket(new RawPacket(new Uint8Array([10])));
+    expect(Array.from((await client.stream.readPacket()) as unknown as Uint8Array)).toEqual([10]);
+    expect(accepted.peer.remoteAddress).toContain("127.0.0.1");
+    client.close();
+
+    await expect(EncryptedListener.bind("7000")).rejects.toThrow(ClavisError);
+
+    // Like bind(port), an address without a host stays on loopback
+    await listener.close();
+    listener = await EncryptedListener.bind(`:${port}`);
+    expect((listener.address() as AddressInfo).address).toBe("127.0.0.1");
+  });
+
+  test("should time out handshakes that never finish", async () => {
+    const port = await findAvailablePort();
+    // Options that leave the timeout explicitly undefined keep the listener's
+    listener = await EncryptedListener.bind(port, "127.0.0.1", {
+      handshakeTimeoutMs: 50,
+      streamOptions: { handshakeTimeoutMs: undefined },
+      streamOptionsFor: () => ({ handshakeTimeoutMs: undefined }),
+    });
+
+    const failed = new Promise<ClavisError>((resolve) => listener!.once("handshakeError", resolve));
+    const socket = createConnection({ host: "127.0.0.1", port });
+    socket.on("error", () => {});
+    const error = await failed;
+    expect((error.cause as StreamError).code).toBe(StreamErrorCode.HandshakeTimeout);
+    expect(listener.activeHandshakes).toBe(0);
+    socket.destroy();
+  });
+});
+
+describe("Sharded listeners", () => {
+  test("should let listeners share a port with reusePort", async () => {
+    const port = await findAvailablePort();
+    const first = await EncryptedListener.bind(port, "127.0.0.1", { reusePort: true });
+    const second = await EncryptedListener.bind(port, "127.0.0.1", { reusePort: true });
+    await Promise.all([first.close(), second.close()]);
+  });
+
+  test("should serve connections from worker shards", async () => {
+    const port = await findAvailablePort();
+    const shards = await spawnShards(new URL("../helpers/shard-worker.ts", import.meta.url), { port, shards: 2 });
+
+    try {
+      const replies = await Promise.all(Array.from({ length: 8 }, async () => {
+        const client = await createTestClient({ host: "127.0.0.1", port });
+        await client.stream.writePacket(new RawPacket(new Uint8Array([1])));
+        const reply = (await client.stream.readPacket()) as unknown as Uint8Array;
+        client.close();
+        return reply[0]!;
+      }));
+      for (const shardId of replies) {
+        expect([0, 1]).toContain(shardId);
+      }
+    } finally {
+      await shards.close();
+    }
+  });
+
+  test("should refuse an ephemeral port and binding outside a shard", async () => {
+    await expect(spawnShards("./unused.ts", { port: 0 })).rejects.toThrow(ClavisError);
+    await expect(bindShard()).rejects.toThrow(ClavisError);
+  });
+});
+
+describe("Accept filtering", () => {
+  let listener: EncryptedListener | undefined;
+
+  afterEach(async () => {
+    await listener?.close();
+    listener = undefined;
+  });
+
+  test("should drop filtered peers before the handshake", async () => {
+    const port = await findAvailablePort();
+    const seen: Array<string | undefined> = [];
+    listener = await EncryptedListener.bind(port, "127.0.0.1", {
+      filter: (peer) => {
+        seen.push(peer.remoteAddress);
+        return false;
+      },
+    });
+
+    const rejected = new Promise<void>((resolve) => listener!.once("rejected", () => resolve()));
+    const socket = createConnection({ host: "127.0.0.1", port });
+    socket.on("error", () => {});
+    await rejected;
+    socket.destroy();
+
+    expect(seen.length).toBe(1);
+    expect(listener.activeHandshakes).toBe(0);
+  });
+
+  test("should expose the PROXY-derived address to the filter", async () => {
+    const port = await findAvailablePort();
+    let proxiedAddress: string | undefined;
+    listener = await EncryptedListener.bind(port, "127.0.0.1", {
+      proxyProtocol: true,
+      filter: (peer) => {
+        proxiedAddress = peer.proxied?.sourceAddress;
+        return false;
+      },
+    });
+
+    const rejected = new Promise<void>((resolve) => listener!.once("rejected", () => resolve()));
+    const socket = createConnection({ host: "127.0.0.1", port }, () => {
+      socket.write("PROXY TCP4 203.0.113.7 127.0.0.1 51234 7272\r\n");
+    });
+    socket.on("error", () => {});
+    await rejected;
+    socket.destroy();
+
+    expect(proxiedAddress).toBe("203.0.113.7");
+  });
+});
+
+describe("Handshake flood protection", () => {
+  let listener: EncryptedListener | undefined;
+
+  afterEach(async () => {
+    await listener?.close();
+    listener = undefined;
+  });
+
+  test("should refill each address's bucket at the configured rate", async () => {
+    const clock = new ManualClock();
+    const limiter = new HandshakeRateLimiter({ ratePerSec: 1, burst: 2, maxTracked: 2, clock });
+
+    expect([limiter.tryAcquire("a"), limiter.tryAcquire("a"), limiter.tryAcquire("a")]).toEqual([true, true, false]);
+    expect(limiter.tryAcquire("b")).toBe(true);
+    await clock.advance(1000);
+    expect([limiter.tryAcquire("a"), limiter.tryAcquire("a")]).toEqual([true, false]);
+
+    // "b" is the least recently seen, so a third address pushes it out
+    limiter.tryAcquire("c");
+    expect(limiter.tracked).toBe(2);
+    expect(limiter.keyFor({ remoteAddress: "10.0.0.1", remotePort: 1, proxied: { version: 1, family: "TCP4", sourceAddress: "203.0.113.7" } }))
+      .toBe("203.0.113.7");
+    expect(() => new HandshakeRateLimiter({ ratePerSec: 0 })).toThrow(ClavisError);
+  });
+
+  test("should recognize protocols scanners open with", () => {
+    const ascii = (text: string) => new TextEncoder().encode(text);
+    expect(detectForeignProtocol(new Uint8Array([0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01]))).toBe("TLS");
+    expect(detectForeignProtocol(ascii("GET / HTTP/1.1\r\n"))).toBe("HTTP");
+    expect(detectForeignProtocol(ascii("PRI * HTTP/2.0\r\n"))).toBe("HTTP/2");
+    expect(detectForeignProtocol(ascii("SSH-2.0-OpenSSH_9.6\r\n"))).toBe("SSH");
+    expect(detectForeignProtocol(ascii("PROXY TCP4 1.2.3.4"))).toBe("PROXY");
+    expect(detectForeignProtocol(new Uint8Array(32).fill(0x47))).toBeUndefined();
+    for (let i = 0; i < 100; i++) {
+      expect(detectForeignProtocol(generateRandomBytes(32))).toBeUndefined();
+    }
+  });
+
+  test("should drop addresses starting handshakes too fast", async () => {
+    const port = await findAvailablePort();
+    listener = await EncryptedListener.bind(port, "127.0.0.1", {
+      handshakeRateLimit: { ratePerSec: 0.001, burst: 1 },
+    });
+
+    const limited = new Promise<void>((resolve) => listener!.once("rateLimited", () => resolve()));
+    const first = createConnection({ host: "127.0.0.1", port });
+    const second = createConnection({ host: "127.0.0.1", port });
+    first.on("error", () => {});
+    second.on("error", () => {});
+    await limited;
+    first.destroy();
+    second.destroy();
+  });
+
+  test("should drop other protocols before taking a handshake slot", async () => {
+    const port = await findAvailablePort();
+    listener = await EncryptedListener.bind(port, "127.0.0.1", { screenFirstBytes: true });
+
+    const failed = new Promise<ClavisError>((resolve) => listener!.once("handshakeError", (error) => resolve(error)));
+    const scanner = createConnection({ host: "127.0.0.1", port }, () => {
+      scanner.write("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    });
+    scanner.on("error", () => {});
+    expect((await failed).message).toContain("HTTP");
+    expect(listener.activeHandshakes).toBe(0);
+    scanner.destroy();
+
+    const [accepted, client] = await Promise.all([
+      listener.accept(),
+      createTestClient({ host: "127.0.0.1", port }),
+    ]);
+    expect(accepted.stream).toBeDefined();
+    client.close();
+  });
+});
+
+/** A PROXY v2 header for 203.0.113.7 carrying `authority` */
+function proxyV2WithAuthority(authority: string): Uint8Array {
+  const name = new TextEncoder().encode(authority);
+  const length = 12 + 3 + name.length;
+  return new Uint8Array([
+    0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a,
+    0x21, 0x11, length >> 8, length & 0xff,
+    203, 0, 113, 7,
+    127, 0, 0, 1,
+    0xc8, 0x02,
+    0x1c, 0x68,
+    0x02, name.length >> 8, name.length & 0xff, ...name,
+  ]);
+}
+
+describe("Per-connection stream options", () => {
+  let listener: EncryptedListener | undefined;
+
+  afterEach(async () => {
+    await listener?.close();
+    listener = undefined;
+  });
+
+  test("should pick a tenant's credentials by the name it connected to", async () => {
+    const port = await findAvailablePort();
+    const tenantPsk = new Uint8Array(32).fill(8);
+    listener = await EncryptedListener.bind(port, "127.0.0.1", {
+      proxyProtocol: true,
+      streamOptions: { psk: new Uint8Array(32).fill(7), maxPacketSize: 4096 },
+      streamOptionsFor: (peer) => (peer.serverName === "b.example" ? { psk: tenantPsk } : undefined),
+    });
+
+    const socket = await new Promise<Socket>((resolve) => {
+      const conn = createConnection({ host: "127.0.0.1", port }, () => resolve(conn));
+    });
+    socket.write(proxyV2WithAuthority("b.example"));
+    const [accepted, client] = await Promise.all([
+      listener.accept(),
+      EncryptedStream.new(socket, { psk: tenantPsk }),
+    ]);
+
+    expect(accepted.peer.serverName).toBe("b.example");
+    // Fields the override leaves out keep their defaults
+    expect(accepted.stream.maxPacketSize).toBe(4096);
+    await client.writePacket(new RawPacket(new Uint8Array([1])));
+    expect((await accepted.stream.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+    socket.destroy();
+  });
+
+  test("should drop connections whose options can't be chosen", async () => {
+    const port = await findAvailablePort();
+    listener = await EncryptedListener.bind(port, "127.0.0.1", {
+      streamOptionsFor: () => Promise.reject(new Error("unknown tenant")),
+    });
+
+    const failed = new Promise<ClavisError>((resolve) => listener!.once("handshakeError", (error) => resolve(error)));
+    const socket = createConnection({ host: "127.0.0.1", port });
+    socket.on("error", () => {});
+    const error = await failed;
+    socket.destroy();
+
+    expect(error.message).toContain("unknown tenant");
+    expect(listener.activeHandshakes).toBe(0);
+  });
+});
+
+describe("PROXY protocol parsing", () => {
+  test("should parse a v1 header", () => {
+    const data = new TextEncoder().encode("PROXY TCP4 192.0.2.1 198.51.100.2 40000 443\r\nrest");
+    const result = parseProxyHeader(data);
+    expect(result?.header).toEqual({
+      version: 1,
+      family: "TCP4",
+      sourceAddress: "192.0.2.1",
+      destinationAddress: "198.51.100.2",
+      sourcePort: 40000,
+      destinationPort: 443,
+    });
+    expect(result?.bytesRead).toBe(data.length - 4);
+
+    // Addresses must be addresses of the stated family
+    const header = (line: string) => new TextEncoder().encode(`${line}\r\n`);
+    expect(parseProxyHeader(header("PROXY TCP6 2001:db8::1 2001:db8::2 40000 443"))?.header.sourceAddress).toBe("2001:db8::1");
+    for (const line of [
+      "PROXY TCP4 not-an-address 198.51.100.2 40000 443",
+      "PROXY TCP4 192.0.2.1 2001:db8::2 40000 443",
+      "PROXY TCP6 192.0.2.1 198.51.100.2 40000 443",
+      "PROXY TCP4 192.0.2.999 198.51.100.2 40000 443",
+    ]) {
+      expect(() => parseProxyHeader(header(line))).toThrow(ClavisError);
+    }
+  });
+
+  test("should report incomplete headers", () => {
+    expect(parseProxyHeader(new TextEncoder().encode("PROXY TCP4 192.0.2.1"))).toBeUndefined();
+  });
+
+  test("should parse a v2 IPv4 header", () => {
+    const data = new Uint8Array([
+      0x0d, 0x0a, 0x0d, 0x0a, 0x00, 0x0d, 0x0a, 0x51, 0x55, 0x49, 0x54, 0x0a,
+      0x21, 0x11, 0x00, 0x0c,
+      10, 0, 0, 1,
+      10, 0, 0, 2,
+      0x1f, 0x90,
+      0x01, 0xbb,
+    ]);
+    const result = parseProxyHeader(data);
+    expect(result?.header.family).toBe("TCP4");
+    expect(result?.header.sourceAddress).toBe("10.0.0.1");
+    expect(result?.header.sourcePort).toBe(8080);
+    expect(result?.header.destinationPort).toBe(443);
+    expect(result?.bytesRead).toBe(28);
+  });
+
+  test("should read the authority TLV of a v2 header", () => {
+    const data = proxyV2WithAuthority("api.example.com");
+    const result = parseProxyHeader(data);
+    expect(result?.header.authority).toBe("api.example.com");
+    expect(result?.header.sourceAddress).toBe("203.0.113.7");
+    expect(result?.bytesRead).toBe(data.length);
+    expect(() => parseProxyHeader(data.subarray(0, data.length - 1).map((byte, i) => (i === 15 ? byte - 1 : byte)))).toThrow("truncated v2 TLV");
+  });
+
+  test("should reject data without a PROXY signature", () => {
+    expect(() => parseProxyHeader(new Uint8Array([1, 2, 3, 4, 5, 6]))).toThrow();
+  });
+});
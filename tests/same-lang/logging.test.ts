@@ -0,0 +1,78 @@
+/**
+ * Connection logging tests - records before and after the handshake, and cleanup on close
+ */
+
+import { describe, test, expect } from "bun:test";
+import { ConnectionLogger, type ConnectionLogRecord } from "../../src/logging.js";
+import { issueCertificate } from "../../src/identity.js";
+import { generateEd25519KeyPair } from "../../src/crypto.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const signer = generateEd25519KeyPair();
+
+function credentials(identity: string) {
+  const key = generateEd25519KeyPair();
+  return {
+    certificate: issueCertificate(signer.secretKey, {
+      identity,
+      publicKey: key.publicKey,
+      expiresAt: new Date(Date.now() + 60 * 60 * 1000),
+    }),
+    secretKey: key.secretKey,
+  };
+}
+
+describe("ConnectionLogger", () => {
+  test("should tag records with the peer identity and negotiated parameters", async () => {
+    const records: ConnectionLogRecord[] = [];
+    const logger = new ConnectionLogger({
+      log: (record) => records.push(record),
+      fields: (stream) => ({ tenant: stream.peerIdentity?.identity === "alice" ? "acme" : undefined }),
+    });
+    const [client, server] = await createEncryptedStreamPair(
+      { identity: credentials("alice"), protocolVersion: 3 },
+      { trustedSigners: [signer.publicKey], protocolVersion: 3, tracer: logger, connectionId: "conn-1" }
+    );
+
+    await client.writePacket(new RawPacket(new Uint8Array([1])));
+    await server.readPacket();
+
+    expect(records.map((record) => record.event)).toEqual(["clavis.handshake", "clavis.read"]);
+    for (const record of records) {
+      expect(record).toMatchObject({
+        connectionId: "conn-1",
+        peerIdentity: "alice",
+        tenant: "acme",
+        protocolVersion: 3,
+        resumed: false,
+        ok: true,
+      });
+    }
+    expect(logger.context("conn-1").peerIdentity).toBe("alice");
+
+    await client.close();
+    await expect(server.readPacket()).rejects.toThrow();
+    expect(records.at(-1)).toMatchObject({ event: "clavis.read", ok: false, peerIdentity: "alice" });
+    // The transport's end follows the close frame
+    await new Promise((resolve) => setTimeout(resolve, 10));
+    expect(logger.size).toBe(0);
+    expect(logger.context("conn-1")).toEqual({ connectionId: "conn-1" });
+  });
+
+  test("should log only the chosen events, and failed handshakes by id alone", async () => {
+    const records: ConnectionLogRecord[] = [];
+    const logger = new ConnectionLogger({ log: (record) => records.push(record), events: ["clavis.handshake"] });
+    const error = await createEncryptedStreamPair(
+      { psk: new Uint8Array(32).fill(1), tracer: logger, connectionId: "conn-2" },
+      { psk: new Uint8Array(32).fill(2) }
+    ).catch((error) => error);
+    expect(error).toBeInstanceOf(Error);
+
+    expect(records).toHaveLength(1);
+    expect(records[0]).toMatchObject({ event: "clavis.handshake", ok: false, connectionId: "conn-2" });
+    expect(records[0]!.error).toBeString();
+    expect(records[0]!.peerIdentity).toBeUndefined();
+    expect(logger.size).toBe(0);
+  });
+});
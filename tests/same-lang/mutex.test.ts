@@ -0,0 +1,96 @@
+/**
+ * Mutex tests - exclusion and ordering under many interleavings
+ */
+
+import { describe, test, expect } from "bun:test";
+import { Mutex } from "../../src/mutex.js";
+import { seededRandom } from "../../src/testing.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+/** Yield to other tasks a random number of times, mixing microtasks and macrotasks */
+async function yieldRandomly(random: () => number): Promise<void> {
+  const steps = Math.floor(random() * 4);
+  for (let i = 0; i < steps; i++) {
+    if (random() < 0.5) {
+      await Promise.resolve();
+    } else {
+      await new Promise((resolve) => setImmediate(resolve));
+    }
+  }
+}
+
+describe("Mutex", () => {
+  test("should keep sections exclusive and FIFO across schedules", async () => {
+    for (let seed = 1; seed <= 100; seed++) {
+      const random = seededRandom(seed);
+      const mutex = new Mutex();
+      let inside = 0;
+      const entered: number[] = [];
+
+      await Promise.all(Array.from({ length: 6 }, (_, task) => mutex.runExclusive(async () => {
+        inside++;
+        expect(inside).toBe(1);
+        entered.push(task);
+        await yieldRandomly(random);
+        inside--;
+      })));
+
+      expect(entered).toEqual([0, 1, 2, 3, 4, 5]);
+      expect(mutex.locked).toBe(false);
+    }
+  });
+
+  test("should release when the section throws", async () => {
+    const mutex = new Mutex();
+    await expect(mutex.runExclusive(() => {
+      throw new Error("boom");
+    })).rejects.toThrow("boom");
+    expect(mutex.locked).toBe(false);
+    expect(await mutex.runExclusive(() => 1)).toBe(1);
+  });
+
+  test("should ignore a second release", async () => {
+    const mutex = new Mutex();
+    const release = await mutex.lock();
+    const waiting = mutex.lock();
+    expect(mutex.waiting).toBe(1);
+
+    release();
+    release();
+    const second = await waiting;
+    // The double release must not have unlocked the mutex under the second holder
+    expect(mutex.locked).toBe(true);
+    second();
+    expect(mutex.locked).toBe(false);
+  });
+});
+
+describe("Shared stream state", () => {
+  test("should hand concurrent readers whole packets in order", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const { reader } = b.split();
+    const reads = [b.readPacket(), reader.readPacket(), b.readPackets(1), reader.readPacket()];
+
+    const packets = Array.from({ length: 4 }, (_, i) => new RawPacket(new Uint8Array(100).fill(i)));
+    for (const packet of packets) {
+      await a.writePacket(packet);
+    }
+
+    const [first, second, [third], fourth] = (await Promise.all(reads)) as unknown as
+      [Uint8Array, Uint8Array, Uint8Array[], Uint8Array];
+    expect([first, second, third, fourth]).toEqual(packets.map((p) => p.bytes));
+  });
+
+  test("should not interleave frames from concurrent writers", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const { writer } = a.split();
+    const packets = Array.from({ length: 50 }, (_, i) => new RawPacket(new Uint8Array(1000).fill(i)));
+
+    await Promise.all(packets.map((packet, i) => (i % 2 ? writer : a).writePacket(packet)));
+
+    for (const packet of packets) {
+      expect((await b.readPacket()) as unknown as Uint8Array).toEqual(packet.bytes);
+    }
+  });
+});
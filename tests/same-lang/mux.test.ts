@@ -0,0 +1,255 @@
+/**
+ * Channel multiplexing tests - logical channels, per-channel flow control and idle reaping
+ */
+
+import { describe, test, expect } from "bun:test";
+import { ChannelMux, MuxFrameKind, encodeMuxEnvelope, decodeMuxEnvelope, type ChannelMuxOptions } from "../../src/mux.js";
+import { FairQueue } from "../../src/flow-control.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import type { EncryptedStreamOptions } from "../../src/stream.js";
+import { ManualClock } from "../../src/clock.js";
+import { createEncryptedStreamPair, sleep } from "../helpers/test-utils.js";
+
+const text = (s: string) => new RawPacket(new TextEncoder().encode(s));
+const decode = (bytes: Uint8Array) => new TextDecoder().decode(bytes);
+
+async function createMuxPair(window?: number, options: ChannelMuxOptions = {}, streamOptions?: EncryptedStreamOptions) {
+  const [a, b] = await createEncryptedStreamPair(streamOptions);
+  return [ChannelMux.over(a, { window, ...options }).start(), ChannelMux.over(b, { window }).start()] as const;
+}
+
+describe("Mux envelopes", () => {
+  test("should round-trip an envelope", () => {
+    const frame = encodeMuxEnvelope(MuxFrameKind.Data, 0xfffffffe, new Uint8Array([1, 2]));
+    const envelope = decodeMuxEnvelope(frame);
+    expect(envelope.kind).toBe(MuxFrameKind.Data);
+    expect(envelope.channel).toBe(0xfffffffe);
+    expect([...envelope.payload]).toEqual([1, 2]);
+  });
+
+  test("should reject unknown kinds and short frames", () => {
+    expect(() => decodeMuxEnvelope(new Uint8Array([9, 0, 0, 0, 0]))).toThrow(ClavisError);
+    expect(() => decodeMuxEnvelope(new Uint8Array([1, 0]))).toThrow(ClavisError);
+  });
+});
+
+describe("ChannelMux", () => {
+  test("should carry independent channels over one stream", async () => {
+    const [client, server] = await createMuxPair();
+    const first = await client.openChannel(1);
+    const second = await client.openChannel(2);
+    await second.writer.writePacket(text("two"));
+    await first.writer.writePacket(text("one"));
+
+    const accepted = [await server.accept(), await server.accept()];
+    expect(accepted.map((channel) => channel.id)).toEqual([1, 2]);
+    expect(decode(await accepted[1]!.reader.readPacket())).toBe("two");
+    expect(decode(await accepted[0]!.reader.readPacket())).toBe("one");
+
+    await accepted[0]!.writer.writePacket(text("reply"));
+    expect(decode(await first.reader.readPacket())).toBe("reply");
+  });
+
+  test("should stall only the channel whose reader is behind", async () => {
+    const [client, server] = await createMuxPair(2);
+    const slow = await client.openChannel(1);
+    const fast = await client.openChannel(2);
+    const slowPeer = await server.accept();
+    const fastPeer = await server.accept();
+
+    await slow.writer.writePacket(text("a"));
+    await slow.writer.writePacket(text("b"));
+    let third = false;
+    const blocked = slow.writer.writePacket(text("c")).then(() => (third = true));
+
+    for (let i = 0; i < 5; i++) {
+      await fast.writer.writePacket(text(`fast ${i}`));
+      expect(decode(await fastPeer.reader.readPacket())).toBe(`fast ${i}`);
+    }
+    await sleep(10);
+    expect(third).toBe(false);
+
+    expect(decode(await slowPeer.reader.readPacket())).toBe("a");
+    await blocked;
+    expect(third).toBe(true);
+  });
+
+  test("should end the peer's reader on close and free the ID", async () => {
+    const [client, server] = await createMuxPair();
+    const channel = await client.openChannel(5);
+    const peer = await server.accept();
+    await channel.writer.writePacket(text("last"));
+    await channel.writer.close();
+
+    const received: string[] = [];
+    for await (const bytes of peer.reader) received.push(decode(bytes));
+    expect(received).toEqual(["last"]);
+
+    await peer.writer.close();
+    await expect(channel.reader.readPacket()).rejects.toThrow(ClavisError);
+    expect(client.openChannels).toBe(0);
+    await client.openChannel(5);
+  });
+
+  test("should agree on a channel both sides open at once", async () => {
+    const [client, server] = await createMuxPair();
+    const [a, b] = await Promise.all([client.openChannel(3), server.openChannel(3)]);
+    await a.writer.writePacket(text("from a"));
+    await b.writer.writePacket(text("from b"));
+    expect(decode(await b.reader.readPacket())).toBe("from a");
+    expect(decode(await a.reader.readPacket())).toBe("from b");
+  });
+
+  test("should refuse duplicate and out-of-range IDs", async () => {
+    const [client] = await createMuxPair();
+    await client.openChannel(1);
+    await expect(client.openChannel(1)).rejects.toThrow(ClavisError);
+    await expect(client.openChannel(-1)).rejects.toThrow(ClavisError);
+    await expect(client.openChannel(2 ** 32)).rejects.toThrow(ClavisError);
+  });
+
+  test("should fail every channel when closed", async () => {
+    const [client, server] = await createMuxPair();
+    const channel = await client.openChannel(1);
+    await server.accept();
+    client.close();
+    await expect(channel.reader.readPacket()).rejects.toThrow(ClavisError);
+    await expect(channel.writer.writePacket(text("x"))).rejects.toThrow(ClavisError);
+    await expect(client.accept()).rejects.toThrow(ClavisError);
+  });
+});
+
+describe("FairQueue", () => {
+  test("should share turns by weight", () => {
+    const queue = new FairQueue<string>(100, (flow) => (flow === 2 ? 3 : 1));
+    for (let i = 0; i < 8; i++) {
+      queue.push(1, "a", 100);
+      queue.push(2, "b", 100);
+    }
+    const order = Array.from({ length: 8 }, () => queue.shift());
+    expect(order.join("")).toBe("abbbabbb");
+    expect(queue.stats().get(2)).toEqual({ weight: 3, queuedItems: 2, queuedBytes: 200, sentItems: 6, sentBytes: 600 });
+  });
+
+  test("should let an item larger than the quantum out once turns add up", () => {
+    const queue = new FairQueue<string>(100);
+    queue.push(1, "large", 250);
+    for (let i = 0; i < 6; i++) queue.push(2, "small", 50);
+    const order = Array.from({ length: 5 }, () => queue.shift());
+    expect(order).toEqual(["small", "small", "small", "small", "large"]);
+    expect(queue.size).toBe(2);
+  });
+});
+
+describe("ChannelMux scheduler", () => {
+  test("should not let a bulk channel starve another", async () => {
+    // Channels of the data frames, in the order they reach the wire
+    const wire: number[] = [];
+    const [client, server] = await createMuxPair(64, { scheduler: { quantum: 1024 } }, {
+      interceptors: [{
+        write(packet) {
+          const envelope = decodeMuxEnvelope(packet);
+          if (envelope.kind === MuxFrameKind.Data) wire.push(envelope.channel);
+        },
+      }],
+    });
+    const bulk = await client.openChannel(1);
+    const small = await client.openChannel(2);
+    await server.accept();
+    await server.accept();
+
+    await Promise.all([
+      ...Array.from({ length: 32 }, () => bulk.writer.writePacket(new RawPacket(new Uint8Array(4096)))),
+      small.writer.writePacket(text("ping")),
+    ]);
+
+    // A 4101-byte bulk frame needs five 1024-byte turns; the ping fits in one,
+    // so it goes out right after the first bulk frame instead of behind all 32
+    expect(wire).toEqual([1, 2, ...Array<number>(31).fill(1)]);
+
+    const stats = new Map(client.queueStats().map((entry) => [entry.channel, entry]));
+    expect(stats.get(1)).toMatchObject({ weight: 1, queuedItems: 0, sentItems: 32, sentBytes: 32 * (4096 + 5) });
+    expect(stats.get(2)?.sentItems).toBe(1);
+  });
+
+  test("should check weights and the quantum", async () => {
+    const [client, server] = await createMuxPair(8, { scheduler: {} });
+    expect(() => client.setChannelWeight(1, 0)).toThrow(ClavisError);
+    client.setChannelWeight(1, 2.5);
+    expect(client.queueStats()).toEqual([
+      { channel: 1, weight: 2.5, queuedItems: 0, queuedBytes: 0, sentItems: 0, sentBytes: 0 },
+    ]);
+    expect(() => server.setChannelWeight(1, 2)).toThrow(ClavisError);
+    expect(server.queueStats()).toEqual([]);
+    const [stream] = await createEncryptedStreamPair();
+    expect(() => ChannelMux.over(stream, { scheduler: { quantum: 0 } })).toThrow(ClavisError);
+  });
+});
+
+describe("ChannelMux windows", () => {
+  test("should advertise a window per channel", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const client = ChannelMux.over(a, { window: (id) => (id === 1 ? 2 : 16) }).start();
+    const server = ChannelMux.over(b, { window: (id) => (id === 1 ? 1 : 8) }).start();
+
+    const small = await client.openChannel(1);
+    const wide = await client.openChannel(2, { window: 32 });
+    const smallPeer = await server.accept();
+    const widePeer = await server.accept();
+    expect([small.reader.window, wide.reader.window]).toEqual([2, 32]);
+    expect([smallPeer.reader.window, widePeer.reader.window]).toEqual([1, 8]);
+
+    // The server's window of 1 holds back the second packet until the first is read
+    await small.writer.writePacket(text("a"));
+    let second = false;
+    const blocked = small.writer.writePacket(text("b")).then(() => (second = true));
+    for (let i = 0; i < 8; i++) await wide.writer.writePacket(text(`wide ${i}`));
+    await sleep(10);
+    expect(widePeer.reader.buffered).toBe(8);
+    expect(second).toBe(false);
+
+    expect(decode(await smallPeer.reader.readPacket())).toBe("a");
+    await blocked;
+    expect(second).toBe(true);
+    await expect(client.openChannel(3, { window: 0 })).rejects.toThrow(ClavisError);
+  });
+});
+
+describe("ChannelMux idle reaping", () => {
+  test("should close channels that stay idle and spare busy ones", async () => {
+    const clock = new ManualClock();
+    const [a, b] = await createEncryptedStreamPair();
+    const client = ChannelMux.over(a, { clock, idleTimeoutMs: (id) => (id === 0 ? undefined : 1000) }).start();
+    const server = ChannelMux.over(b).start();
+    const reaped: number[] = [];
+    client.on("reaped", (channel) => reaped.push(channel.id));
+
+    const control = await client.openChannel(0);
+    const idle = await client.openChannel(1);
+    const busy = await client.openChannel(2);
+    const [, idlePeer, busyPeer] = [await server.accept(), await server.accept(), await server.accept()];
+
+    await clock.advance(600);
+    await busy.writer.writePacket(text("still here"));
+    await clock.advance(600);
+
+    expect(reaped).toEqual([1]);
+    await expect(idle.reader.readPacket()).rejects.toThrow(ClavisError);
+    await expect(idle.writer.writePacket(text("late"))).rejects.toThrow(ClavisError);
+    // The peer's reader ends with the close; once it closes too, the ID is free on both sides
+    await expect(idlePeer.reader.readPacket()).rejects.toThrow(ClavisError);
+    await idlePeer.writer.close();
+    await sleep(10);
+    expect(client.hasChannel(1)).toBe(false);
+    expect(server.hasChannel(1)).toBe(false);
+
+    expect(decode(await busyPeer.reader.readPacket())).toBe("still here");
+    await clock.advance(5000);
+    expect(reaped).toEqual([1, 2]);
+    expect(client.hasChannel(0)).toBe(true);
+    expect(control.reader.buffered).toBe(0);
+    const [spare] = await createEncryptedStreamPair();
+    expect(() => ChannelMux.over(spare, { idleTimeoutMs: 0 })).toThrow(ClavisError);
+  });
+});
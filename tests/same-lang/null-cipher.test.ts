@@ -0,0 +1,60 @@
+/**
+ * Null cipher tests - cleartext frames behind an explicit opt-in
+ */
+
+import { describe, test, expect, afterEach } from "bun:test";
+import { DangerousNullCipher, allowNullCipher } from "../../src/null-cipher.js";
+import { decodeFrame, openFrame, sealFrame, FRAME_OVERHEAD } from "../../src/frame.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair, createStreamPair } from "../helpers/test-utils.js";
+import { EncryptedStream } from "../../src/stream.js";
+
+afterEach(() => allowNullCipher(false));
+
+describe("DangerousNullCipher", () => {
+  test("should refuse to run unless the process allows it", async () => {
+    expect(() => new DangerousNullCipher()).toThrow(ClavisError);
+
+    // Refused up front: not a byte reaches the peer
+    const [left, right] = await createStreamPair();
+    let sent = 0;
+    right.on("data", (chunk: Buffer) => (sent += chunk.length));
+    const error = (await EncryptedStream.new(left, { dangerousNullCipher: true }).catch((e) => e)) as ClavisError;
+    expect(error.configConflicts?.map((conflict) => conflict.options)).toEqual([["dangerousNullCipher"]]);
+    expect(sent).toBe(0);
+  });
+
+  test("should keep the frame layout but send the payload in the clear", () => {
+    allowNullCipher(true);
+    const cipher = new DangerousNullCipher();
+    const plaintext = new TextEncoder().encode("visible");
+
+    const frame = sealFrame(cipher, plaintext);
+    expect(frame).toHaveLength(plaintext.length + FRAME_OVERHEAD);
+    expect(decodeFrame(frame).ciphertext.subarray(0, plaintext.length)).toEqual(plaintext);
+    expect(openFrame(cipher, decodeFrame(frame))).toEqual(plaintext);
+
+    // A flipped control flag still fails
+    expect(() => openFrame(cipher, { ...decodeFrame(frame), control: true })).toThrow(ClavisError);
+  });
+
+  test("should carry packets between peers that both enable it", async () => {
+    allowNullCipher(true);
+    const [client, server] = await createEncryptedStreamPair(
+      { dangerousNullCipher: true },
+      { dangerousNullCipher: true }
+    );
+
+    await client.writePacket(new RawPacket(new Uint8Array([1, 2, 3])));
+    expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1, 2, 3]));
+  });
+
+  test("should fail reads when only one peer enables it", async () => {
+    allowNullCipher(true);
+    const [client, server] = await createEncryptedStreamPair({ dangerousNullCipher: true }, {});
+
+    await client.writePacket(new RawPacket(new Uint8Array([1, 2, 3])));
+    await expect(server.readPacket()).rejects.toThrow(ClavisError);
+  });
+});
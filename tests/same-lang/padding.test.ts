@@ -0,0 +1,100 @@
+/**
+ * Padding tests - policies, the padded layout, and padded streams with cover traffic
+ */
+
+import { describe, test, expect } from "bun:test";
+import { PacketPadder, padmeLength, paddedLength, unpadPacket } from "../../src/padding.js";
+import { FRAME_OVERHEAD } from "../../src/frame.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { ManualClock } from "../../src/clock.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+describe("Padding policies", () => {
+  test("should round lengths with Padmé", () => {
+    expect(padmeLength(1)).toBe(1);
+    expect(padmeLength(9)).toBe(10);
+    expect(padmeLength(100)).toBe(104);
+    expect(padmeLength(1000)).toBe(1024);
+    expect(padmeLength(1024)).toBe(1024);
+  });
+
+  test("should pad to buckets and multiples of the largest", () => {
+    const policy = { buckets: [64, 256] };
+    expect(paddedLength(5, policy)).toBe(64);
+    expect(paddedLength(64, policy)).toBe(64);
+    expect(paddedLength(65, policy)).toBe(256);
+    expect(paddedLength(600, policy)).toBe(768);
+  });
+
+  test("should refuse malformed options", () => {
+    expect(() => new PacketPadder({ policy: { buckets: [] } })).toThrow(ClavisError);
+    expect(() => new PacketPadder({ policy: { buckets: [256, 64] } })).toThrow("increasing order");
+    expect(() => new PacketPadder({ coverIntervalMs: 0 })).toThrow(ClavisError);
+  });
+});
+
+describe("PacketPadder", () => {
+  test("should pad within the limit and strip the padding again", () => {
+    const padder = new PacketPadder({ policy: { buckets: [32, 1024] } });
+    const packet = new Uint8Array([1, 2, 3]);
+    expect(padder.pad(packet, 1000)).toHaveLength(32);
+    expect(unpadPacket(padder.pad(packet, 1000))).toEqual(packet);
+    // The limit caps the padding but never cuts the packet
+    expect(padder.pad(new Uint8Array(100), 500)).toHaveLength(500);
+    expect(padder.pad(new Uint8Array(100), 50)).toHaveLength(104);
+  });
+
+  test("should make cover frames that unpad to nothing", () => {
+    const padder = new PacketPadder({ policy: { buckets: [512] }, coverIntervalMs: 100 });
+    expect(padder.cover(1000)).toHaveLength(512);
+    expect(unpadPacket(padder.cover(1000))).toBeUndefined();
+    expect(padder.nextCoverDelay()).toBeGreaterThanOrEqual(0);
+    expect(new PacketPadder().nextCoverDelay()).toBeUndefined();
+  });
+
+  test("should reject a length beyond the padded packet", () => {
+    const padded = new PacketPadder().pad(new Uint8Array(8), 100);
+    padded[0] = 200;
+    expect(() => unpadPacket(padded)).toThrow(ClavisError);
+    expect(() => unpadPacket(new Uint8Array(2))).toThrow(ClavisError);
+  });
+});
+
+describe("Padded streams", () => {
+  test("should send packets of different sizes as frames of one size", async () => {
+    const padding = { policy: { buckets: [128] } };
+    const [client, server] = await createEncryptedStreamPair({ padding }, { padding });
+    expect(client.paddingPolicy).toEqual({ buckets: [128] });
+
+    const sizes: number[] = [];
+    for (const length of [1, 9, 100]) {
+      const before = client.stats().bytesSent;
+      await client.writePacket(new RawPacket(new Uint8Array(length).fill(length)));
+      sizes.push(client.stats().bytesSent - before);
+      expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array(length).fill(length));
+    }
+    expect(sizes).toEqual([FRAME_OVERHEAD + 128, FRAME_OVERHEAD + 128, FRAME_OVERHEAD + 128]);
+  });
+
+  test("should send cover frames the reader never sees", async () => {
+    const clock = new ManualClock();
+    const [client, server] = await createEncryptedStreamPair(
+      { clock, padding: { coverIntervalMs: 10 } },
+      { clock, padding: {} }
+    );
+    const before = client.stats().bytesSent;
+    await clock.advance(500);
+    expect(client.stats().bytesSent).toBeGreaterThan(before);
+    expect(client.stats().packetsSent).toBe(0);
+
+    await client.writePacket(new RawPacket(new Uint8Array([7])));
+    expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([7]));
+    expect(server.stats().packetsReceived).toBe(1);
+
+    await client.close();
+    const sent = client.stats().bytesSent;
+    await clock.advance(500);
+    expect(client.stats().bytesSent).toBe(sent);
+  });
+});
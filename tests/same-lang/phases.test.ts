@@ -0,0 +1,117 @@
+/**
+ * Phase tests - only the current phase's variants may be sent or received
+ */
+
+import { describe, test, expect } from "bun:test";
+import { definePhases } from "../../src/phases.js";
+import { RawPacket, createProtocolCodec } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { PacketRouter } from "../../src/router.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const codec = createProtocolCodec(["Login", "LoginOk", "Message", "Leave"] as const);
+const ClientPhases = definePhases(codec, {
+  Unauthenticated: { send: ["Login"], receive: ["LoginOk"], next: ["Active"] },
+  Active: { send: ["Message", "Leave"], receive: ["Message"], next: [] },
+});
+const ServerPhases = definePhases(codec, {
+  Unauthenticated: { send: ["LoginOk"], receive: ["Login"], next: ["Active"] },
+  Active: { send: ["Message"], receive: ["Message", "Leave"] },
+});
+
+describe("definePhases", () => {
+  test("should walk both sides through their phases", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const client = ClientPhases.open(a, "Unauthenticated");
+    const server = ServerPhases.open(b, "Unauthenticated");
+
+    await client.send("Login", new Uint8Array([1]));
+    expect((await server.receive()).type).toBe("Login");
+    await server.send("LoginOk");
+    expect((await client.receive()).type).toBe("LoginOk");
+
+    const activeClient = client.advance("Active");
+    const activeServer = server.advance("Active");
+    expect(activeClient.phase).toBe("Active");
+    await activeClient.send("Message", new Uint8Array([2]));
+    const message = await activeServer.receive();
+    expect(message.type).toBe("Message");
+    expect(message.data).toEqual(new Uint8Array([2]));
+  });
+
+  test("should stop using a phase once it has advanced", async () => {
+    const [a] = await createEncryptedStreamPair();
+    const login = ClientPhases.open(a, "Unauthenticated");
+    login.advance("Active");
+
+    await expect(login.send("Login")).rejects.toThrow(ClavisError);
+    expect(() => login.advance("Active")).toThrow(ClavisError);
+  });
+
+  test("should enforce phases at runtime for untyped callers", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const client = ClientPhases.open(a, "Unauthenticated");
+    const server = ServerPhases.open(b, "Unauthenticated");
+
+    // @ts-expect-error Message is not legal before login
+    await expect(client.send("Message")).rejects.toThrow(ClavisError);
+
+    await a.writePacket(new RawPacket(codec.encode("Message")));
+    await expect(server.receive()).rejects.toThrow(ClavisError);
+
+    // @ts-expect-error Active is the last phase
+    expect(() => client.advance("Active").advance("Unauthenticated")).toThrow(ClavisError);
+  });
+
+  test("should reject unknown variants and phases", () => {
+    expect(() => definePhases(codec, {
+      Start: { send: ["Nope" as "Login"], receive: [] },
+    })).toThrow(ClavisError);
+    expect(() => definePhases(codec, {
+      Start: { send: [], receive: [], next: ["Missing"] },
+    })).toThrow(ClavisError);
+  });
+
+  test("should keep a reader's filter and a router's handlers in step with the phase", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const { reader } = b.split();
+    const router = new PacketRouter(codec).on("Message", () => undefined).on("Login", () => undefined);
+    const server = ServerPhases.open(b, "Unauthenticated", { reader, router });
+
+    expect(router.routes()).toEqual(["Login"]);
+    expect(await router.dispatch(codec.decode(codec.encode("Message"))).catch((error) => error))
+      .toBeInstanceOf(ClavisError);
+
+    await a.writePacket(new RawPacket(codec.encode("Message")));
+    await a.writePacket(new RawPacket(codec.encode("Login")));
+    expect((await server.receive()).type).toBe("Login");
+    expect(reader.droppedPackets).toBe(1);
+
+    server.advance("Active");
+    expect(router.routes()).toEqual(["Message"]);
+    expect(await router.dispatch(codec.decode(codec.encode("Message")))).toBeUndefined();
+  });
+
+  test("should surface variants from a newer peer when asked to", async () => {
+    const newer = createProtocolCodec(["Login", "LoginOk", "Message", "Leave", "Emote"] as const);
+    const [a, b] = await createEncryptedStreamPair();
+    const server = ServerPhases.open(b, "Unauthenticated");
+
+    await a.writePacket(new RawPacket(newer.encode("Emote", new Uint8Array([9]))));
+    await a.writePacket(new RawPacket(newer.encode("Login")));
+    const unknown = await server.receiveVariant();
+    expect(unknown.type).toBeUndefined();
+    expect(unknown.index).toBe(4);
+    expect(Array.from(unknown.data)).toEqual([9]);
+    expect((await server.receiveVariant()).type).toBe("Login");
+
+    await a.writePacket(new RawPacket(newer.encode("Emote")));
+    await expect(server.receive()).rejects.toThrow(ClavisError);
+
+    const skipped: number[] = [];
+    const router = new PacketRouter(codec, { onUnknown: ({ index }) => void skipped.push(index) });
+    expect(await router.dispatchBytes(newer.encode("Emote"))).toBeUndefined();
+    expect(skipped).toEqual([4]);
+    await expect(new PacketRouter(codec).dispatchBytes(newer.encode("Emote"))).rejects.toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,98 @@
+/**
+ * Prefetch tests - the read-ahead bound, cancellation, stopping, and errors in arrival order
+ */
+
+import { describe, test, expect } from "bun:test";
+import { PrefetchReader, type PrefetchSource } from "../../src/prefetch.js";
+import { ClavisError, MessageError } from "../../src/error.js";
+import { RawPacket, type PacketTrait } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+/** Source whose reads complete only when the test delivers a packet */
+class ScriptedSource implements PrefetchSource {
+  reads = 0;
+  private pending: Array<(packet: Uint8Array) => void> = [];
+
+  readPacket<P extends PacketTrait>(): Promise<P> {
+    this.reads++;
+    return new Promise((resolve) => this.pending.push((packet) => resolve(packet as unknown as P)));
+  }
+
+  deliver(n: number): void {
+    this.pending.shift()!(new Uint8Array([n]));
+  }
+}
+
+const settle = () => new Promise((resolve) => setImmediate(resolve));
+
+describe("PrefetchReader", () => {
+  test("should read ahead up to its depth and no further", async () => {
+    const source = new ScriptedSource();
+    const prefetch = new PrefetchReader(source, { depth: 2, decode: (bytes) => bytes[0]! * 10 });
+    expect(source.reads).toBe(1);
+
+    source.deliver(1);
+    await settle();
+    source.deliver(2);
+    await settle();
+    expect(prefetch.buffered).toBe(2);
+    expect(source.reads).toBe(2);
+    expect(prefetch.readingAhead).toBe(false);
+
+    // Taking one makes room for one more read
+    expect(await prefetch.next()).toBe(10);
+    expect(source.reads).toBe(3);
+    expect(prefetch.tryNext()).toBe(20);
+    expect(prefetch.tryNext()).toBeUndefined();
+
+    expect(() => new PrefetchReader(source, { depth: 0 })).toThrow(ClavisError);
+  });
+
+  test("should keep a packet for the next call when a wait is aborted, and drain after stop()", async () => {
+    const source = new ScriptedSource();
+    const prefetch = new PrefetchReader(source, { depth: 4 });
+    const controller = new AbortController();
+    const abandoned = prefetch.next(controller.signal);
+    controller.abort();
+    await expect(abandoned).rejects.toThrow(ClavisError);
+
+    source.deliver(7);
+    await settle();
+    expect(prefetch.buffered).toBe(1);
+    expect(await prefetch.next()).toEqual(new Uint8Array([7]));
+
+    // The read in progress completes into the buffer; nothing is read after it
+    prefetch.stop();
+    source.deliver(8);
+    await settle();
+    expect(source.reads).toBe(2);
+    expect(await prefetch.next()).toEqual(new Uint8Array([8]));
+    await expect(prefetch.next()).rejects.toThrow(ClavisError);
+  });
+
+  test("should throw decode and read errors in the order their packets arrived", async () => {
+    const [client, server] = await createEncryptedStreamPair();
+    for (const n of [1, 255, 2]) await client.writePacket(new RawPacket(new Uint8Array([n])));
+    await client.close();
+
+    const prefetch = server.prefetch({
+      decode: (bytes) => {
+        if (bytes[0] === 255) throw ClavisError.message(MessageError.invalidFormat("Unknown variant"));
+        return bytes[0]!;
+      },
+    });
+    expect(await prefetch.next()).toBe(1);
+    await expect(prefetch.next()).rejects.toThrow(/Unknown variant/);
+    expect(await prefetch.next()).toBe(2);
+    await expect(prefetch.next()).rejects.toThrow(ClavisError);
+    await expect(prefetch.next()).rejects.toThrow(ClavisError);
+
+    // Iteration stops quietly at a clean close
+    const [a, b] = await createEncryptedStreamPair();
+    for (const n of [3, 4]) await a.writePacket(new RawPacket(new Uint8Array([n])));
+    await a.close();
+    const received: number[] = [];
+    for await (const packet of b.prefetch()) received.push(packet[0]!);
+    expect(received).toEqual([3, 4]);
+  });
+});
@@ -0,0 +1,78 @@
+/**
+ * Prometheus exporter tests - live and retired counters, handshake metrics and combined tracers
+ */
+
+import { describe, test, expect } from "bun:test";
+import { PrometheusExporter } from "../../src/prometheus.js";
+import { ConnectionLogger, type ConnectionLogRecord } from "../../src/logging.js";
+import { combineTracers } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+function sample(text: string, name: string): number {
+  const line = text.split("\n").find((line) => line.startsWith(`${name} `));
+  if (line === undefined) throw new Error(`No sample ${name}`);
+  return Number(line.slice(name.length + 1));
+}
+
+describe("PrometheusExporter", () => {
+  test("should count open connections and keep totals after they close", async () => {
+    const exporter = new PrometheusExporter();
+    const [client, server] = await createEncryptedStreamPair({}, { tracer: exporter });
+
+    await client.writePacket(new RawPacket(new Uint8Array([1])));
+    await client.writePacket(new RawPacket(new Uint8Array([2])));
+    await server.readPacket();
+    await server.readPacket();
+    await server.writePacket(new RawPacket(new Uint8Array([3])));
+
+    let text = exporter.render();
+    expect(sample(text, "clavis_connections_open")).toBe(1);
+    expect(sample(text, "clavis_connections_total")).toBe(1);
+    expect(sample(text, "clavis_packets_received_total")).toBe(2);
+    expect(sample(text, "clavis_packets_sent_total")).toBe(1);
+    expect(sample(text, "clavis_handshake_duration_seconds_count")).toBe(1);
+    expect(text).toContain('clavis_handshake_duration_seconds_bucket{le="+Inf"} 1');
+    expect(text).toContain("# TYPE clavis_packets_sent_total counter");
+
+    await client.close();
+    await expect(server.readPacket()).rejects.toThrow();
+    // The transport's end follows the close frame
+    await new Promise((resolve) => setTimeout(resolve, 10));
+
+    text = exporter.render();
+    expect(sample(text, "clavis_connections_open")).toBe(0);
+    expect(sample(text, "clavis_packets_received_total")).toBe(2);
+    expect(sample(text, "clavis_packets_sent_total")).toBe(1);
+    expect(sample(text, "clavis_bytes_received_total")).toBeGreaterThan(0);
+  });
+
+  test("should count failed handshakes and apply the prefix and labels", async () => {
+    const exporter = new PrometheusExporter({ prefix: "edge", labels: { instance: 'eu-"1"' } });
+    const error = await createEncryptedStreamPair(
+      { psk: new Uint8Array(32).fill(1), tracer: exporter },
+      { psk: new Uint8Array(32).fill(2) }
+    ).catch((error) => error);
+    expect(error).toBeInstanceOf(Error);
+
+    const text = exporter.render();
+    expect(text).toContain('edge_handshake_failures_total{instance="eu-\\"1\\""} 1');
+    expect(text).toContain('edge_connections_total{instance="eu-\\"1\\""} 0');
+    expect(text).toContain('edge_handshake_duration_seconds_bucket{instance="eu-\\"1\\"",le="0.005"} 0');
+  });
+
+  test("should share a stream with another tracer through combineTracers", async () => {
+    const exporter = new PrometheusExporter();
+    const records: ConnectionLogRecord[] = [];
+    const logger = new ConnectionLogger({ log: (record) => records.push(record) });
+    const [client, server] = await createEncryptedStreamPair({}, { tracer: combineTracers(logger, exporter) });
+
+    await client.writePacket(new RawPacket(new Uint8Array([1])));
+    await server.readPacket();
+
+    expect(records.map((record) => record.event)).toEqual(["clavis.handshake", "clavis.read"]);
+    expect(logger.size).toBe(1);
+    expect(sample(exporter.render(), "clavis_connections_open")).toBe(1);
+    await client.close();
+  });
+});
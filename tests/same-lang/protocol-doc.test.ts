@@ -0,0 +1,57 @@
+/**
+ * Protocol reference tests - variant tables, field sizes and rendering
+ */
+
+import { describe, test, expect } from "bun:test";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { defineSchema } from "../../src/schema.js";
+import { describeProtocol, formatProtocolDoc, formatProtocolDocHtml } from "../../src/protocol-doc.js";
+import { ClavisError } from "../../src/error.js";
+
+const codec = createProtocolCodec(["Join", "Move", "Failure"] as const, { errorVariant: "Failure" });
+const Join = defineSchema("Join", [{ name: "name", type: "string" }]);
+const Move = defineSchema("Move", [
+  { name: "x", type: "i32" },
+  { name: "y", type: "i32" },
+]);
+
+const doc = describeProtocol(codec, {
+  title: "Game protocol",
+  maxPacketSize: 1024,
+  variants: {
+    Join: { doc: "Sent once | first", schema: Join },
+    Move: { doc: "Moves the player", schema: Move },
+  },
+});
+
+describe("describeProtocol", () => {
+  test("should list every variant with its index and sizes", () => {
+    expect(doc.indexEncoding).toBe("u32");
+    expect(doc.variants.map((v) => [v.name, v.index, v.minSize, v.maxSize])).toEqual([
+      ["Join", 0, 12, 1024],
+      ["Move", 1, 12, 12],
+      ["Failure", 2, undefined, 1024],
+    ]);
+    expect(doc.variants[2]!.error).toBe(true);
+    expect(doc.variants[0]!.schemaHash).toBe(Join.hash);
+  });
+
+  test("should reject docs for unknown variants", () => {
+    expect(() => describeProtocol(codec, { title: "x", variants: { Jump: {} } as never })).toThrow(ClavisError);
+  });
+});
+
+describe("formatProtocolDoc", () => {
+  test("should render Markdown tables", () => {
+    const markdown = formatProtocolDoc(doc);
+    expect(markdown).toContain("| 0 | Join | 12–1024 | Sent once \\| first |");
+    expect(markdown).toContain("| 2 | Failure (error) | ≤ 1024 |  |");
+    expect(markdown).toContain("| x | i32 | 4 |");
+  });
+
+  test("should escape HTML", () => {
+    const html = formatProtocolDocHtml(describeProtocol(codec, { title: "<Game>" }));
+    expect(html).toContain("<h1>&lt;Game&gt;</h1>");
+    expect(html).toContain('<h2 id="Move">Move</h2>');
+  });
+});
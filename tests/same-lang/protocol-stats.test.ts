@@ -0,0 +1,62 @@
+/**
+ * Protocol statistics tests - per-identity variant counts, eviction and Prometheus output
+ */
+
+import { describe, test, expect } from "bun:test";
+import { ProtocolStats, OTHER_IDENTITY, UNKNOWN_VARIANT } from "../../src/protocol-stats.js";
+import { PrometheusExporter } from "../../src/prometheus.js";
+import { createProtocolCodec, RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const codec = createProtocolCodec(["Join", "Chat"] as const);
+const psk = new TextEncoder().encode("protocol-stats-psk-0001");
+
+describe("ProtocolStats", () => {
+  test("should count packets by identity, direction and variant", async () => {
+    const protocolStats = new ProtocolStats({ codec });
+    const [client, server] = await createEncryptedStreamPair(
+      { psk, pskIdentity: "alice" },
+      { psks: { alice: psk }, protocolStats }
+    );
+
+    await client.writePacket(new RawPacket(codec.encode("Join")));
+    await client.writePacket(new RawPacket(codec.encode("Join")));
+    await client.writePacket(new RawPacket(codec.encode("Chat", new Uint8Array([1, 2]))));
+    for (let i = 0; i < 3; i++) await server.readPacket();
+    await server.writePacket(new RawPacket(codec.encode("Chat")));
+
+    expect(protocolStats.entries()).toEqual([
+      { identity: "alice", direction: "read", variant: "Join", packets: 2, bytes: 8 },
+      { identity: "alice", direction: "read", variant: "Chat", packets: 1, bytes: 6 },
+      { identity: "alice", direction: "write", variant: "Chat", packets: 1, bytes: 4 },
+    ]);
+    expect(protocolStats.top(1, { variant: "Join", direction: "read" }))
+      .toEqual([{ identity: "alice", direction: "read", variant: "Join", packets: 2, bytes: 8 }]);
+
+    const text = new PrometheusExporter({ protocolStats }).render();
+    expect(text).toContain('clavis_variant_packets_total{identity="alice",direction="read",variant="Join"} 2');
+    expect(text).toContain('clavis_variant_bytes_total{identity="alice",direction="write",variant="Chat"} 4');
+    await client.close();
+  });
+
+  test("should fold the least recently active identity into (other)", () => {
+    const protocolStats = new ProtocolStats({ maxIdentities: 2 });
+    const packet = new Uint8Array([1, 0, 0, 0]);
+    protocolStats.observe("a", "read", packet);
+    protocolStats.observe("b", "read", packet);
+    protocolStats.observe("a", "read", packet);
+    protocolStats.observe("c", "read", packet);
+    protocolStats.observe("d", "read", new Uint8Array([1]));
+
+    expect(protocolStats.size).toBe(2);
+    const byIdentity = Object.fromEntries(protocolStats.top(10).map((entry) => [entry.identity, entry.packets]));
+    expect(byIdentity).toEqual({ c: 1, d: 1, [OTHER_IDENTITY]: 3 });
+    expect(protocolStats.entries().find((entry) => entry.identity === "d")?.variant).toBe(UNKNOWN_VARIANT);
+    expect(protocolStats.entries().find((entry) => entry.identity === "c")?.variant).toBe("1");
+  });
+
+  test("should reject a non-positive identity limit", () => {
+    expect(() => new ProtocolStats({ maxIdentities: 0 })).toThrow(ClavisError);
+  });
+});
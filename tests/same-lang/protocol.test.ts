@@ -4,8 +4,10 @@
 
 import { describe, test, expect } from "bun:test";
 import { TestProtocol, type ChatMessage, type PingPongData, type Status } from "../helpers/test-protocol.js";
-import { createProtocolCodec } from "../../src/protocol.js";
+import { createProtocolCodec, matchKnown, PreparedPacket } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
 import { writeU32, writeString } from "../../src/bincode.js";
+import { ClavisError } from "../../src/error.js";
 
 describe("TestProtocol", () => {
   test("should create Heartbeat packet", () => {
@@ -197,6 +199,33 @@ describe("createProtocolCodec", () => {
     expect(() => codec.decode(data)).toThrow();
   });
 
+  test("should hand unknown variants to matchKnown's fallback", () => {
+    const buffer: number[] = [];
+    writeU32(buffer, 999);
+    writeString(buffer, "from the future");
+    const unknown = codec.decodeVariant(new Uint8Array(buffer));
+    expect(unknown.type).toBeUndefined();
+    expect(unknown.index).toBe(999);
+
+    const handlers = {
+      AgentHello: () => "hello",
+      ControllerAck: ({ reader }: { reader: { readString(): string } }) => reader.readString(),
+      Heartbeat: () => "beat",
+      TaskOffer: () => "offer",
+      TaskResult: () => "result",
+    };
+    expect(matchKnown(unknown, handlers, ({ index, reader }) => `${index}: ${reader.readString()}`))
+      .toBe("999: from the future");
+
+    const ack: number[] = [];
+    writeU32(ack, 1);
+    writeString(ack, "session-1");
+    expect(matchKnown(codec.decodeVariant(new Uint8Array(ack)), handlers, () => "unknown")).toBe("session-1");
+
+    // @ts-expect-error every known variant needs a handler
+    matchKnown(unknown, { AgentHello: () => "hello" }, () => "unknown");
+  });
+
   test("should work with varint encoding option", () => {
     const varintCodec = createProtocolCodec<TestMessage>(
       ["AgentHello", "ControllerAck", "Heartbeat", "TaskOffer", "TaskResult"],
@@ -208,5 +237,61 @@ describe("createProtocolCodec", () => {
     expect(encoded.length).toBe(1);
     expect(encoded[0]).toBe(2);
   });
+
+  test("should keep pinned indices when variants are inserted", () => {
+    const v1 = createProtocolCodec(["Hello", "Move", "Chat"] as const, { ids: { Hello: 0, Move: 1, Chat: 2 } });
+    const v2 = createProtocolCodec(["Hello", "Emote", "Move", "Chat", "Whisper"] as const, {
+      ids: { Hello: 0, Emote: 10, Move: 1, Chat: 2 },
+      deprecated: ["Chat"],
+    });
+
+    for (const name of v1.variants()) {
+      expect(v2.variantIndex(name)).toBe(v1.variantIndex(name));
+      expect(v2.decode(v1.encode(name)).type).toBe(name);
+    }
+    expect(v2.variantIndex("Emote")).toBe(10);
+    expect(v2.variantIndex("Whisper")).toBe(3);
+    expect(v2.isDeprecated("Chat")).toBe(true);
+    expect(v2.isDeprecated("Move")).toBe(false);
+  });
+
+  test("should reject clashing or unknown ids", () => {
+    expect(() => createProtocolCodec(["A", "B"] as const, { ids: { B: 0 } })).toThrow(ClavisError);
+    expect(() => createProtocolCodec(["A", "B"] as const, { ids: { A: -1 } })).toThrow(ClavisError);
+    expect(() => createProtocolCodec(["A"] as const, { ids: { C: 1 } as Record<string, number> })).toThrow(ClavisError);
+    expect(() => createProtocolCodec(["A"] as const, { deprecated: ["C" as "A"] })).toThrow(ClavisError);
+  });
 });
 
+describe("PreparedPacket", () => {
+  test("should serialize once and match the original packet", () => {
+    const ping = TestProtocol.Ping({ message: "broadcast" });
+    let calls = 0;
+    const counting = {
+      serialize: () => {
+        calls++;
+        return ping.serialize();
+      },
+      deserialize: () => counting,
+    };
+
+    const prepared = PreparedPacket.new(counting);
+    prepared.serialize();
+    prepared.serialize();
+    expect(calls).toBe(1);
+    expect(prepared.serialize()).toEqual(ping.serialize());
+    expect(prepared.serializedSize()).toBe(ping.serialize().length);
+  });
+
+  test("should be written to several connections with per-connection encryption", async () => {
+    const prepared = PreparedPacket.new(TestProtocol.Ping({ message: "to everyone" }));
+    const pairs = await Promise.all([createEncryptedStreamPair(), createEncryptedStreamPair()]);
+
+    for (const [sender] of pairs) {
+      await sender.writePacket(prepared);
+    }
+    for (const [, receiver] of pairs) {
+      expect((await receiver.readPacket()) as unknown as Uint8Array).toEqual(prepared.bytes);
+    }
+  });
+});
@@ -0,0 +1,76 @@
+/**
+ * QUIC transport tests - sessions and channels over an in-memory stand-in for a QUIC connection
+ */
+
+import { describe, test, expect } from "bun:test";
+import { QuicSession, quicStream, type QuicBidirectionalStream, type QuicConnectionLike } from "../../src/quic.js";
+import { keyFingerprint } from "../../src/identity.js";
+import { ed25519PublicKey, generateEd25519KeyPair } from "../../src/crypto.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+
+/** Two ends of a connection; each stream one side opens arrives at the other's incoming streams */
+function quicPair(): [QuicConnectionLike, QuicConnectionLike] {
+  const incoming: Array<ReadableStreamDefaultController<QuicBidirectionalStream>> = [];
+  const end = (self: 0 | 1): QuicConnectionLike => ({
+    incomingBidirectionalStreams: new ReadableStream<QuicBidirectionalStream>({
+      start: (controller) => {
+        incoming[self] = controller;
+      },
+    }),
+    async createBidirectionalStream() {
+      const there = new TransformStream<Uint8Array, Uint8Array>();
+      const back = new TransformStream<Uint8Array, Uint8Array>();
+      incoming[1 - self]!.enqueue({ readable: there.readable, writable: back.writable });
+      return { readable: back.readable, writable: there.writable };
+    },
+    close: () => incoming[self]!.close(),
+  });
+  return [end(0), end(1)];
+}
+
+const psk = new Uint8Array(32).fill(4);
+
+describe("QuicSession", () => {
+  test("should carry channels that share the control stream's identity", async () => {
+    const [clientEnd, serverEnd] = quicPair();
+    const clientKey = generateEd25519KeyPair().secretKey;
+    const [client, server] = await Promise.all([
+      QuicSession.connect(clientEnd, { psk, identity: { secretKey: clientKey }, verifyPeer: () => true }),
+      QuicSession.accept(serverEnd, {
+        psk,
+        identity: { secretKey: generateEd25519KeyPair().secretKey },
+        verifyPeer: () => true,
+      }),
+    ]);
+    expect(server.peerIdentity?.identity).toBe(keyFingerprint(ed25519PublicKey(clientKey)));
+
+    const [uploads, accepted] = await Promise.all([client.openChannel(2), server.acceptChannel()]);
+    const [events, acceptedEvents] = await Promise.all([client.openChannel(4), server.acceptChannel()]);
+    expect([accepted?.id, acceptedEvents?.id]).toEqual([2, 4]);
+
+    await uploads.stream.writePacket(new RawPacket(new Uint8Array([1])));
+    await events.stream.writePacket(new RawPacket(new Uint8Array([2])));
+    expect((await acceptedEvents!.stream.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([2]));
+    expect((await accepted!.stream.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+
+    await accepted!.stream.writePacket(new RawPacket(new Uint8Array([3])));
+    expect((await uploads.stream.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([3]));
+    await expect(client.openChannel(0)).rejects.toThrow(ClavisError);
+  });
+
+  test("should refuse a channel not keyed from the control stream", async () => {
+    const [clientEnd, serverEnd] = quicPair();
+    const [, server] = await Promise.all([QuicSession.connect(clientEnd, { psk }), QuicSession.accept(serverEnd, { psk })]);
+
+    // A stream that knows the session's PSK but not its exported channel key
+    const bidi = await clientEnd.createBidirectionalStream();
+    const writer = bidi.writable.getWriter();
+    await writer.write(new Uint8Array([2, 0, 0, 0]));
+    writer.releaseLock();
+    const forged = quicStream(bidi, { psk }).catch((error) => error);
+
+    await expect(server.acceptChannel()).rejects.toThrow(ClavisError);
+    expect(await forged).toBeInstanceOf(ClavisError);
+  });
+});
@@ -0,0 +1,147 @@
+/**
+ * Reconnecting stream tests - redialing after drops, buffered writes and giving up
+ */
+
+import { describe, test, expect } from "bun:test";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+import { TicketKeyring } from "../../src/tickets.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError, StreamError } from "../../src/error.js";
+import { ReconnectingEncryptedStream, isConnectionLost, type ReconnectInfo } from "../../src/reconnecting.js";
+import { createMemoryPair } from "../../src/testing.js";
+
+/** A connect factory over in-memory pairs, keeping the server end of each */
+function dialer(serverOptions?: EncryptedStreamOptions) {
+  const servers: Array<Promise<EncryptedStream>> = [];
+  let refusals = 0;
+  return {
+    servers,
+    refuse(count: number) {
+      refusals = count;
+    },
+    connect: async () => {
+      if (refusals > 0) {
+        refusals--;
+        throw ClavisError.stream(StreamError.connectionClosed("refused"));
+      }
+      const [client, server] = createMemoryPair();
+      servers.push(EncryptedStream.new(server, serverOptions));
+      return client;
+    },
+  };
+}
+
+async function serverAt(servers: Array<Promise<EncryptedStream>>, index: number): Promise<EncryptedStream> {
+  while (servers.length <= index) await new Promise((resolve) => setTimeout(resolve, 1));
+  return servers[index]!;
+}
+
+const bytes = (packet: unknown) => Array.from(packet as Uint8Array);
+const fastRetry = { maxAttempts: 3, initialDelayMs: 5 };
+
+describe("ReconnectingEncryptedStream", () => {
+  test("should redial and keep reading after the peer closes", async () => {
+    const net = dialer();
+    const stream = await ReconnectingEncryptedStream.connect({ connect: net.connect, retry: fastRetry });
+    const events: string[] = [];
+    stream.on("disconnect", () => events.push("disconnect"));
+    stream.on("reconnecting", (attempt) => events.push(`reconnecting ${attempt}`));
+    const infos: ReconnectInfo[] = [];
+    stream.on("reconnect", (info) => {
+      events.push("reconnect");
+      infos.push(info);
+    });
+
+    const first = await serverAt(net.servers, 0);
+    await stream.writePacket(new RawPacket(new Uint8Array([1])));
+    expect(bytes(await first.readPacket())).toEqual([1]);
+
+    const read = stream.readPacket();
+    await first.close();
+    const second = await serverAt(net.servers, 1);
+    await second.writePacket(new RawPacket(new Uint8Array([2])));
+
+    expect(bytes(await read)).toEqual([2]);
+    expect(events).toEqual(["disconnect", "reconnecting 1", "reconnect"]);
+    expect(infos[0]).toMatchObject({ resumed: false, attempts: 1, flushedPackets: 0 });
+    expect(infos[0]!.downtimeMs).toBeGreaterThanOrEqual(0);
+    expect(infos[0]!.stream).toBe(stream.stream!);
+    expect(stream.reconnects).toBe(1);
+    expect(stream.resumedReconnects).toBe(0);
+    expect(stream.isConnected).toBe(true);
+    await stream.close();
+  });
+
+  test("should buffer writes while reconnecting and send them first", async () => {
+    const net = dialer();
+    const stream = await ReconnectingEncryptedStream.connect({
+      connect: net.connect,
+      retry: { maxAttempts: 5, initialDelayMs: 5 },
+      bufferPackets: 2,
+    });
+    const disconnected = new Promise((resolve) => stream.once("disconnect", resolve));
+    const reconnected = new Promise<ReconnectInfo>((resolve) => stream.once("reconnect", resolve));
+    net.refuse(1);
+    const read = stream.readPacket();
+    await (await serverAt(net.servers, 0)).close();
+    await disconnected;
+
+    const writes = [
+      stream.writePacket(new RawPacket(new Uint8Array([1]))),
+      stream.writePacket(new RawPacket(new Uint8Array([2]))),
+    ];
+    expect(stream.bufferedPackets).toBe(2);
+    const overflow = await stream.writePacket(new RawPacket(new Uint8Array([3]))).catch((e: unknown) => e);
+    expect(isConnectionLost(overflow)).toBe(true);
+
+    const second = await serverAt(net.servers, 1);
+    await Promise.all(writes);
+    await stream.writePacket(new RawPacket(new Uint8Array([4])));
+    expect(bytes(await second.readPacket())).toEqual([1]);
+    expect(bytes(await second.readPacket())).toEqual([2]);
+    expect(bytes(await second.readPacket())).toEqual([4]);
+
+    await second.writePacket(new RawPacket(new Uint8Array([5])));
+    expect(bytes(await read)).toEqual([5]);
+    expect(await reconnected).toMatchObject({ attempts: 2, flushedPackets: 2 });
+    await stream.close();
+  });
+
+  test("should report reconnects that resumed the session", async () => {
+    const net = dialer({ tickets: new TicketKeyring() });
+    const stream = await ReconnectingEncryptedStream.connect({ connect: net.connect, retry: fastRetry, resumeSessions: true });
+    const reconnected = new Promise<ReconnectInfo>((resolve) => stream.once("reconnect", resolve));
+
+    const read = stream.readPacket().catch((e: unknown) => e);
+    await (await serverAt(net.servers, 0)).close();
+    expect(await reconnected).toMatchObject({ resumed: true, attempts: 1 });
+    expect(stream.resumedReconnects).toBe(1);
+    await stream.close();
+    await read;
+  });
+
+  test("should give up once the redials run out", async () => {
+    const net = dialer();
+    const stream = await ReconnectingEncryptedStream.connect({ connect: net.connect, retry: fastRetry });
+    const closed = new Promise<ClavisError>((resolve) => stream.once("close", resolve));
+    net.refuse(Infinity);
+
+    const read = stream.readPacket().catch((e: unknown) => e);
+    await (await serverAt(net.servers, 0)).close();
+
+    const reason = await closed;
+    expect(await read).toBe(reason);
+    expect(stream.isClosed).toBe(true);
+    expect(await stream.writePacket(new RawPacket(new Uint8Array([1]))).catch((e: unknown) => e)).toBe(reason);
+  });
+
+  test("should stop for good on close and validate its options", async () => {
+    const net = dialer();
+    await expect(ReconnectingEncryptedStream.connect({ connect: net.connect, bufferPackets: -1 })).rejects.toThrow(ClavisError);
+
+    const stream = await ReconnectingEncryptedStream.connect({ connect: net.connect, retry: fastRetry });
+    await stream.close();
+    await expect(stream.readPacket()).rejects.toThrow(ClavisError);
+    expect(net.servers.length).toBe(1);
+  });
+});
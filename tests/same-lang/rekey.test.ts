@@ -0,0 +1,154 @@
+/**
+ * Rekeying tests - in-band key rolls under traffic
+ */
+
+import { describe, test, expect } from "bun:test";
+import { RekeyMachine, deriveRekeyKeys, winsRekeyTie, type RekeyPolicy } from "../../src/rekey.js";
+import { ControlFrameKind } from "../../src/control.js";
+import { ManualClock } from "../../src/clock.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { allowNullCipher } from "../../src/null-cipher.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const packet = (n: number) => new RawPacket(new Uint8Array([n, n, n]));
+
+/**
+ * Two machines whose control frames wait in `wire` until delivered, and
+ * the keys each side switched to
+ */
+function createMachinePair(policy: RekeyPolicy = { rekeyAfterBytes: undefined, rekeyAfterMs: undefined }) {
+  const wire: Array<{ to: "a" | "b"; kind: ControlFrameKind; payload: Uint8Array }> = [];
+  const keys = { a: { sending: "", receiving: "" }, b: { sending: "", receiving: "" } };
+  const hex = (key: Uint8Array) => Buffer.from(key).toString("hex");
+  const machine = (side: "a" | "b", peer: "a" | "b") => {
+    const send = async (kind: ControlFrameKind, payload: Uint8Array) => {
+      wire.push({ to: peer, kind, payload });
+    };
+    return new RekeyMachine(new Uint8Array(32), 0, policy, new ManualClock(), {
+      request: send,
+      answer: send,
+      useSendingKey: (key) => {
+        keys[side].sending = hex(key);
+      },
+      useReceivingKey: (key) => {
+        keys[side].receiving = hex(key);
+      },
+      derived: () => undefined,
+    });
+  };
+  const machines = { a: machine("a", "b"), b: machine("b", "a") };
+  const deliver = async () => {
+    const kinds: ControlFrameKind[] = [];
+    for (let frame = wire.shift(); frame; frame = wire.shift()) {
+      kinds.push(frame.kind);
+      await machines[frame.to].handle(frame.kind, frame.payload);
+    }
+    return kinds;
+  };
+  return { ...machines, keys, deliver };
+}
+
+describe("deriveRekeyKeys", () => {
+  test("should give distinct keys per direction and chain on the previous secret", () => {
+    const shared = new Uint8Array(32).fill(7);
+    const a = new Uint8Array(32).fill(1);
+    const b = new Uint8Array(32).fill(2);
+    const first = deriveRekeyKeys(new Uint8Array(32), shared, a, b);
+    const second = deriveRekeyKeys(first.chain, shared, a, b);
+    expect(first.initiatorKey).not.toEqual(first.responderKey);
+    expect(second.initiatorKey).not.toEqual(first.initiatorKey);
+  });
+
+  test("should break ties on the lower key", () => {
+    expect(winsRekeyTie(new Uint8Array([1, 9]), new Uint8Array([2, 0]))).toBe(true);
+    expect(winsRekeyTie(new Uint8Array([2, 0]), new Uint8Array([1, 9]))).toBe(false);
+  });
+});
+
+describe("RekeyMachine", () => {
+  test("should leave both sides on matching keys after three frames", async () => {
+    const { a, b, keys, deliver } = createMachinePair();
+    const done = a.start();
+    expect(a.busy).toBe(true);
+    expect(await deliver()).toEqual([
+      ControlFrameKind.RekeyRequest,
+      ControlFrameKind.RekeyResponse,
+      ControlFrameKind.RekeyConfirm,
+    ]);
+    await done;
+    expect(keys.a.sending).toBe(keys.b.receiving);
+    expect(keys.b.sending).toBe(keys.a.receiving);
+    expect(keys.a.sending).not.toBe(keys.a.receiving);
+    expect([a.count, b.count, a.busy, b.busy]).toEqual([1, 1, false, false]);
+    expect(a.chainSecret).toEqual(b.chainSecret);
+  });
+
+  test("should start on its own once armed and over the byte threshold", async () => {
+    const { a, deliver } = createMachinePair({ rekeyAfterBytes: 100, rekeyAfterMs: undefined });
+    a.countKeyed(200);
+    expect(a.busy).toBe(false);
+    a.arm();
+    a.countKeyed(1);
+    expect(a.busy).toBe(true);
+    await deliver();
+    expect(a.count).toBe(1);
+  });
+});
+
+describe("rekey", () => {
+  test("should keep packets flowing across a manual rekey", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const received: number[] = [];
+    const reading = (async () => {
+      while (received.length < 20) received.push(((await b.readPacket()) as unknown as Uint8Array)[0]!);
+    })();
+    const answer = a.readPacket();
+
+    for (let i = 0; i < 10; i++) await a.writePacket(packet(i));
+    const rolled = a.rekey();
+    for (let i = 10; i < 20; i++) await a.writePacket(packet(i));
+    await rolled;
+    await reading;
+
+    expect(received).toEqual(Array.from({ length: 20 }, (_, i) => i));
+    expect(a.rekeyCount).toBe(1);
+    expect(b.rekeyCount).toBe(1);
+
+    // Both directions work under the new keys
+    await b.writePacket(packet(99));
+    expect((await answer) as unknown as Uint8Array).toEqual(new Uint8Array([99, 99, 99]));
+  });
+
+  test("should rekey automatically after the byte threshold", async () => {
+    const [a, b] = await createEncryptedStreamPair({ rekeyAfterBytes: 1_000 });
+    void a.readPacket().catch(() => {});
+    for (let i = 0; i < 50; i++) {
+      await a.writePacket(new RawPacket(new Uint8Array(100)));
+      await b.readPacket();
+    }
+    expect(a.rekeyCount).toBeGreaterThanOrEqual(3);
+    // The last confirmation may still be on its way
+    expect(b.rekeyCount).toBeGreaterThanOrEqual(a.rekeyCount - 1);
+  });
+
+  test("should settle rekeys both sides start at once", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    void a.readPacket().catch(() => {});
+    void b.readPacket().catch(() => {});
+    await Promise.all([a.rekey(), b.rekey()]);
+    expect(a.rekeyCount).toBe(1);
+    expect(b.rekeyCount).toBe(1);
+  });
+
+  test("should reject bad thresholds and the null cipher", async () => {
+    await expect(createEncryptedStreamPair({ rekeyAfterBytes: 0 })).rejects.toThrow(ClavisError);
+    allowNullCipher(true);
+    try {
+      const [a] = await createEncryptedStreamPair({ dangerousNullCipher: true }, { dangerousNullCipher: true });
+      await expect(a.rekey()).rejects.toThrow(ClavisError);
+    } finally {
+      allowNullCipher(false);
+    }
+  });
+});
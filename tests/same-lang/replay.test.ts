@@ -0,0 +1,51 @@
+/**
+ * Replay cache tests - in-memory default and the single-use ticket check
+ */
+
+import { describe, test, expect } from "bun:test";
+import { MemoryReplayCache, type ReplayCache } from "../../src/replay.js";
+import { TicketKeyring } from "../../src/tickets.js";
+import { ManualClock } from "../../src/clock.js";
+
+describe("MemoryReplayCache", () => {
+  test("should refuse a key until it expires", async () => {
+    const clock = new ManualClock(1_000);
+    const cache = new MemoryReplayCache({ clock });
+    expect(cache.insert("a", 2_000)).toBe(true);
+    expect(cache.insert("a", 2_000)).toBe(false);
+
+    await clock.advance(1_000);
+    expect(cache.insert("a", 3_000)).toBe(true);
+  });
+
+  test("should refuse new keys when full of live entries", async () => {
+    const clock = new ManualClock(0);
+    const cache = new MemoryReplayCache({ clock, maxEntries: 2 });
+    expect(cache.insert("a", 100)).toBe(true);
+    expect(cache.insert("b", 200)).toBe(true);
+    expect(cache.insert("c", 300)).toBe(false);
+
+    await clock.advance(100);
+    expect(cache.insert("c", 300)).toBe(true);
+    expect(cache.size).toBe(2);
+  });
+});
+
+describe("Ticket redemption", () => {
+  test("should redeem a ticket once through a shared cache", async () => {
+    const seen = new Set<string>();
+    const shared: ReplayCache = {
+      insert: async (key) => {
+        if (seen.has(key)) return false;
+        seen.add(key);
+        return true;
+      },
+    };
+    const a = new TicketKeyring({ replayCache: shared });
+    const b = new TicketKeyring({ replayCache: shared }, a.keys);
+
+    const ticket = a.seal(new Uint8Array([1]));
+    expect(await b.redeem(ticket)).toBe(true);
+    expect(await a.redeem(ticket)).toBe(false);
+  });
+});
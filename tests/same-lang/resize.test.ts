@@ -0,0 +1,73 @@
+/**
+ * Packet limit tests - the resize exchange on its own, against a recorded
+ * control channel
+ */
+
+import { describe, test, expect } from "bun:test";
+import { PacketLimits } from "../../src/resize.js";
+import { ControlFrameKind, decodeControlU32, encodeControlU32, type ControlChannel } from "../../src/control.js";
+import { ClavisError } from "../../src/error.js";
+
+/** Limits of `size` that record what they send as [kind, u32] */
+function createLimits(size: number, sent: Array<[ControlFrameKind, number]>, channel?: Partial<ControlChannel>) {
+  const record = async (kind: ControlFrameKind, payload: Uint8Array) => {
+    sent.push([kind, decodeControlU32(payload)]);
+  };
+  return new PacketLimits(size, 1024, { request: record, answer: record, ...channel });
+}
+
+describe("PacketLimits", () => {
+  test("should lower our writes at once and raise them only once the peer accepts", async () => {
+    const sent: Array<[ControlFrameKind, number]> = [];
+    const limits = createLimits(512, sent);
+
+    const lower = limits.request(256);
+    expect(limits.write).toBe(256);
+    expect(limits.read).toBe(512);
+    await limits.handle(ControlFrameKind.ResizeAccept, encodeControlU32(256));
+    expect(await lower).toBe(256);
+    expect(limits.read).toBe(256);
+
+    const higher = limits.request(1024);
+    expect(limits.write).toBe(256);
+    expect(limits.busy).toBe(true);
+    await limits.handle(ControlFrameKind.ResizeAccept, encodeControlU32(1024));
+    expect(await higher).toBe(1024);
+    expect([limits.read, limits.write, limits.busy]).toEqual([1024, 1024, false]);
+    expect(sent).toEqual([[ControlFrameKind.ResizeRequest, 256], [ControlFrameKind.ResizeRequest, 1024]]);
+  });
+
+  test("should refuse crossing requests and sizes over the maximum", async () => {
+    const sent: Array<[ControlFrameKind, number]> = [];
+    const limits = createLimits(512, sent);
+
+    const ours = limits.request(256);
+    await limits.handle(ControlFrameKind.ResizeRequest, encodeControlU32(300));
+    const refused = expect(ours).rejects.toThrow(ClavisError);
+    await limits.handle(ControlFrameKind.ResizeReject, encodeControlU32(512));
+    await refused;
+    expect(limits.write).toBe(512);
+
+    await limits.handle(ControlFrameKind.ResizeRequest, encodeControlU32(2048));
+    await limits.handle(ControlFrameKind.ResizeRequest, encodeControlU32(100));
+    expect([limits.read, limits.write]).toEqual([100, 100]);
+    expect(sent).toEqual([
+      [ControlFrameKind.ResizeRequest, 256],
+      [ControlFrameKind.ResizeReject, 256],
+      [ControlFrameKind.ResizeReject, 512],
+      [ControlFrameKind.ResizeAccept, 100],
+    ]);
+    await expect(limits.handle(ControlFrameKind.ResizeAccept, encodeControlU32(100))).rejects.toThrow(ClavisError);
+  });
+
+  test("should keep the old limit when the request can't be sent", async () => {
+    const limits = createLimits(512, [], {
+      request: async () => {
+        throw ClavisError.invalidOperation("Cannot write to a closed stream");
+      },
+    });
+    await expect(limits.request(256)).rejects.toThrow(ClavisError);
+    expect([limits.write, limits.busy]).toEqual([512, false]);
+    await expect(limits.request(0)).rejects.toThrow(ClavisError);
+  });
+});
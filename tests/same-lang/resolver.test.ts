@@ -0,0 +1,82 @@
+/**
+ * Host resolver tests - TTL caching and address rotation
+ */
+
+import { describe, test, expect } from "bun:test";
+import { HostResolver, type ResolvedRecord } from "../../src/resolver.js";
+import { ManualClock } from "../../src/clock.js";
+import { ClavisError } from "../../src/error.js";
+
+function fakeDns(answers: Array<ResolvedRecord[] | Error>) {
+  let lookups = 0;
+  return {
+    get lookups() {
+      return lookups;
+    },
+    resolve: async (): Promise<ResolvedRecord[]> => {
+      const answer = answers[Math.min(lookups++, answers.length - 1)]!;
+      if (answer instanceof Error) throw answer;
+      return answer;
+    },
+  };
+}
+
+const record = (address: string, ttlMs?: number): ResolvedRecord => ({ address, family: 4, ttlMs });
+
+describe("HostResolver", () => {
+  test("should rotate among the addresses of a name", async () => {
+    const dns = fakeDns([[record("10.0.0.1"), record("10.0.0.2")]]);
+    const resolver = new HostResolver({ resolve: dns.resolve, clock: new ManualClock() });
+
+    const picked = [];
+    for (let i = 0; i < 4; i++) picked.push((await resolver.next("db.internal")).address);
+    expect(picked).toEqual(["10.0.0.1", "10.0.0.2", "10.0.0.1", "10.0.0.2"]);
+    expect(dns.lookups).toBe(1);
+  });
+
+  test("should look the name up again once the shortest TTL ran out", async () => {
+    const clock = new ManualClock();
+    const dns = fakeDns([[record("10.0.0.1", 5_000), record("10.0.0.2", 60_000)], [record("10.0.0.3", 5_000)]]);
+    const resolver = new HostResolver({ resolve: dns.resolve, clock });
+
+    await resolver.next("db.internal");
+    clock.advance(4_999);
+    await resolver.next("db.internal");
+    expect(dns.lookups).toBe(1);
+
+    clock.advance(1);
+    expect((await resolver.next("db.internal")).address).toBe("10.0.0.3");
+    expect(dns.lookups).toBe(2);
+  });
+
+  test("should keep the last addresses while lookups fail", async () => {
+    const clock = new ManualClock();
+    const dns = fakeDns([[record("10.0.0.1", 0)], new Error("SERVFAIL")]);
+    const resolver = new HostResolver({ resolve: dns.resolve, clock, minTtlMs: 100 });
+
+    await resolver.next("db.internal");
+    clock.advance(100);
+    expect((await resolver.next("db.internal")).address).toBe("10.0.0.1");
+    expect(dns.lookups).toBe(2);
+  });
+
+  test("should fail when the first lookup fails", async () => {
+    const resolver = new HostResolver({ resolve: fakeDns([new Error("NXDOMAIN")]).resolve });
+    await expect(resolver.next("missing.internal")).rejects.toThrow(ClavisError);
+  });
+
+  test("should pass IP literals through", async () => {
+    const dns = fakeDns([[]]);
+    const resolver = new HostResolver({ resolve: dns.resolve });
+    expect(await resolver.next("::1")).toEqual({ address: "::1", family: 6 });
+    expect(dns.lookups).toBe(0);
+  });
+
+  test("should look up again after invalidate()", async () => {
+    const dns = fakeDns([[record("10.0.0.1")], [record("10.0.0.9")]]);
+    const resolver = new HostResolver({ resolve: dns.resolve, clock: new ManualClock() });
+    await resolver.next("db.internal");
+    resolver.invalidate("db.internal");
+    expect((await resolver.next("db.internal")).address).toBe("10.0.0.9");
+  });
+});
@@ -0,0 +1,129 @@
+/**
+ * Resumption tests - self-contained tickets resumed on any server sharing the keys
+ */
+
+import { describe, test, expect } from "bun:test";
+import { TicketKeyring } from "../../src/tickets.js";
+import { issueCertificate } from "../../src/identity.js";
+import { generateEd25519KeyPair } from "../../src/crypto.js";
+import { RawPacket } from "../../src/protocol.js";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+import { ManualClock } from "../../src/clock.js";
+import { createChaosPair } from "../../src/testing.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+/** Time until both ends are ready, on a manual clock with `latencyMs` each way */
+async function setupTime(latencyMs: number, client: EncryptedStreamOptions, server: EncryptedStreamOptions): Promise<number> {
+  const clock = new ManualClock();
+  const [a, b] = createChaosPair({ latencyMs }, undefined, clock);
+  let settled = false;
+  const ready = Promise.all([EncryptedStream.new(a, { ...client, clock }), EncryptedStream.new(b, { ...server, clock })]);
+  void ready.then(() => { settled = true; }, () => { settled = true; });
+  for (let trips = 0; trips < 10 && !settled; trips++) {
+    await clock.advance(latencyMs);
+  }
+  const streams = await ready;
+  a.destroy();
+  b.destroy();
+  return Math.max(...streams.map((stream) => stream.handshakeTimings.totalMs));
+}
+
+describe("Session resumption", () => {
+  test("should resume on another server that shares the ticket keys", async () => {
+    const first = new TicketKeyring();
+    const [client, server] = await createEncryptedStreamPair({ resumption: true }, { tickets: first });
+    expect(client.resumed).toBe(false);
+    expect(server.resumed).toBe(false);
+    const ticket = client.sessionTicket!;
+    expect(ticket).toBeDefined();
+
+    const second = new TicketKeyring({}, first.keys);
+    const [again, other] = await createEncryptedStreamPair({ resumption: ticket }, { tickets: second });
+    expect(again.resumed).toBe(true);
+    expect(other.resumed).toBe(true);
+    expect(again.sessionTicket!.ticket).not.toEqual(ticket.ticket);
+
+    await again.writePacket(new RawPacket(new Uint8Array([1])));
+    expect((await other.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+  });
+
+  test("should resume each ticket only once", async () => {
+    const tickets = new TicketKeyring();
+    const [client] = await createEncryptedStreamPair({ resumption: true }, { tickets });
+    const ticket = client.sessionTicket!;
+
+    const [first] = await createEncryptedStreamPair({ resumption: ticket }, { tickets });
+    const [replayed] = await createEncryptedStreamPair({ resumption: ticket }, { tickets });
+    expect(first.resumed).toBe(true);
+    expect(replayed.resumed).toBe(false);
+  });
+
+  test("should fall back to a new session for tickets it can't open", async () => {
+    const [client] = await createEncryptedStreamPair({ resumption: true }, { tickets: new TicketKeyring() });
+    const [again, other] = await createEncryptedStreamPair(
+      { resumption: client.sessionTicket! },
+      { tickets: new TicketKeyring() }
+    );
+    expect(again.resumed).toBe(false);
+    expect(other.resumed).toBe(false);
+    expect(again.sessionTicket).toBeDefined();
+  });
+
+  test("should restore the verified identity from the ticket", async () => {
+    const signer = generateEd25519KeyPair();
+    const key = generateEd25519KeyPair();
+    const certificate = issueCertificate(signer.secretKey, {
+      identity: "alice",
+      publicKey: key.publicKey,
+      expiresAt: new Date(Date.now() + 60 * 60 * 1000),
+    });
+    const tickets = new TicketKeyring();
+    const checked: string[] = [];
+    const server = {
+      tickets,
+      trustedSigners: [signer.publicKey],
+      isRevoked: (identity: { identity: string }) => {
+        checked.push(identity.identity);
+        return false;
+      },
+    };
+    const identity = { certificate, secretKey: key.secretKey };
+
+    const [client, first] = await createEncryptedStreamPair({ resumption: true, identity }, server);
+    expect(first.peerIdentity?.certificate).toBeDefined();
+
+    const [, resumed] = await createEncryptedStreamPair({ resumption: client.sessionTicket!, identity }, server);
+    expect(resumed.resumed).toBe(true);
+    expect(resumed.peerIdentity?.identity).toBe("alice");
+    expect(resumed.peerIdentity?.certificate).toBeUndefined();
+    expect(checked).toEqual(["alice", "alice"]);
+  });
+
+  test("should resume in as many trips as a connection that only exchanges identities", async () => {
+    const signer = generateEd25519KeyPair();
+    const key = generateEd25519KeyPair();
+    const certificate = issueCertificate(signer.secretKey, {
+      identity: "alice",
+      publicKey: key.publicKey,
+      expiresAt: new Date(Date.now() + 60 * 60 * 1000),
+    });
+    const identity = { certificate, secretKey: key.secretKey };
+    const tickets = new TicketKeyring();
+    const server = { tickets, trustedSigners: [signer.publicKey] };
+    const [client] = await createEncryptedStreamPair({ resumption: true, identity }, server);
+
+    const latency = 100;
+    const plain = await setupTime(latency, { identity }, { trustedSigners: [signer.publicKey] });
+    const resumed = await setupTime(latency, { resumption: client.sessionTicket!, identity }, server);
+    // Hellos ride in the first flight and grants behind the presentations, so no trip is added
+    expect(resumed).toBe(plain);
+  });
+
+  test("should fail a ticket presented without its secret", async () => {
+    const tickets = new TicketKeyring();
+    const [client] = await createEncryptedStreamPair({ resumption: true }, { tickets });
+    const stolen = { ticket: client.sessionTicket!.ticket, secret: new Uint8Array(32) };
+
+    await expect(createEncryptedStreamPair({ resumption: stolen }, { tickets })).rejects.toThrow();
+  });
+});
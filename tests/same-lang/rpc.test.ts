@@ -0,0 +1,509 @@
+/**
+ * RPC and router tests - correlation, the error packet convention and calls over a multiplexer
+ */
+
+import { describe, test, expect } from "bun:test";
+import { createProtocolCodec, RawPacket, type DecodedMessage, type ProtocolCodec } from "../../src/protocol.js";
+import { PacketRouter } from "../../src/router.js";
+import { RpcConnection, RpcError, encodeRpcEnvelope, decodeRpcEnvelope, RpcFrameKind } from "../../src/rpc.js";
+import { RpcPool } from "../../src/pool.js";
+import { HedgeRegistry } from "../../src/hedging.js";
+import { ChannelMux } from "../../src/mux.js";
+import { StreamError, StreamErrorCode, ClavisError } from "../../src/error.js";
+import { writeString, writeU32 } from "../../src/bincode.js";
+import { ManualClock } from "../../src/clock.js";
+import { createEncryptedStreamPair, sleep } from "../helpers/test-utils.js";
+
+const variants = ["GetStatus", "Status", "Echo", "Error"] as const;
+type Variant = typeof variants[number];
+
+function encodeU32(n: number): Uint8Array {
+  const buffer: number[] = [];
+  writeU32(buffer, n);
+  return new Uint8Array(buffer);
+}
+
+function encodeText(s: string): Uint8Array {
+  const buffer: number[] = [];
+  writeString(buffer, s);
+  return new Uint8Array(buffer);
+}
+
+async function createRpcPair(errorVariant?: Variant) {
+  const codec = errorVariant
+    ? createProtocolCodec<Variant>(variants, { errorVariant })
+    : createProtocolCodec<Variant>(variants);
+
+  const router = new PacketRouter(codec)
+    .on("GetStatus", () => ({ type: "Status", data: encodeU32(42) }))
+    .on("Echo", (message) => {
+      const text = message.reader.readString();
+      if (text === "fail") {
+        throw new Error("echo refused");
+      }
+      return { type: "Echo", data: encodeText(text) };
+    });
+
+  const [a, b] = await createEncryptedStreamPair();
+  const client = a.split();
+  const server = b.split();
+
+  const caller = new RpcConnection(client.reader, client.writer, { codec }).start();
+  const callee = new RpcConnection(server.reader, server.writer, { codec, router }).start();
+  return { caller, callee, codec };
+}
+
+describe("RPC envelope", () => {
+  test("should round-trip envelopes", () => {
+    const payload = new Uint8Array([1, 2, 3]);
+    const decoded = decodeRpcEnvelope(encodeRpcEnvelope(RpcFrameKind.Request, 77, payload));
+    expect(decoded.kind).toBe(RpcFrameKind.Request);
+    expect(decoded.id).toBe(77);
+    expect(decoded.payload).toEqual(payload);
+  });
+
+  test("should reject truncated envelopes", () => {
+    expect(() => decodeRpcEnvelope(new Uint8Array([1, 0, 0]))).toThrow();
+  });
+});
+
+describe("PacketRouter", () => {
+  test("should map handler failures to the error variant", async () => {
+    const codec = createProtocolCodec<Variant>(variants, { errorVariant: "Error" });
+    const router = new PacketRouter(codec).on("Echo", () => {
+      throw new Error("boom");
+    });
+
+    const reply = await router.dispatch(codec.decode(codec.encode("Echo", encodeText("x"))));
+    expect(reply?.type).toBe("Error");
+  });
+
+  test("should rethrow failures without an error variant", async () => {
+    const codec = createProtocolCodec<Variant>(variants);
+    const router = new PacketRouter(codec).on("Echo", () => {
+      throw new Error("boom");
+    });
+
+    await expect(router.dispatch(codec.decode(codec.encode("Echo")))).rejects.toThrow("boom");
+  });
+});
+
+describe("RpcConnection", () => {
+  test("should correlate replies with calls", async () => {
+    const { caller } = await createRpcPair("Error");
+
+    const [status, echo] = await Promise.all([
+      caller.call("GetStatus"),
+      caller.call("Echo", encodeText("hello")),
+    ]);
+
+    expect(status.type).toBe("Status");
+    expect(status.reader.readU32()).toBe(42);
+    expect(echo.type).toBe("Echo");
+    expect(echo.reader.readString()).toBe("hello");
+    expect(caller.pendingCalls).toBe(0);
+  });
+
+  test("should turn error variant replies into rejections", async () => {
+    const { caller } = await createRpcPair("Error");
+
+    try {
+      await caller.call("Echo", encodeText("fail"));
+      throw new Error("expected rejection");
+    } catch (error) {
+      expect(error).toBeInstanceOf(RpcError);
+      expect((error as RpcError).message).toBe("echo refused");
+      expect((error as RpcError).reply?.type).toBe("Error");
+    }
+  });
+
+  test("should report failures when the protocol has no error variant", async () => {
+    const { caller } = await createRpcPair();
+
+    await expect(caller.call("Echo", encodeText("fail"))).rejects.toThrow("echo refused");
+  });
+
+  test("should reject pending calls on close", async () => {
+    const { caller } = await createRpcPair("Error");
+    const pending = caller.call("Status");
+    caller.close();
+    await expect(pending).rejects.toThrow();
+  });
+
+  test("should route unrelated packets to the inbox, apart from replies", async () => {
+    const { caller, callee } = await createRpcPair("Error");
+    const inbox = caller.inbox()[Symbol.asyncIterator]();
+
+    const reply = caller.call("GetStatus");
+    await callee.notify("Echo", encodeText("notice"));
+    expect((await reply).reader.readU32()).toBe(42);
+
+    const notice = await inbox.next();
+    expect(notice.value?.type).toBe("Echo");
+    expect(notice.value?.reader.readString()).toBe("notice");
+
+    caller.close();
+    expect((await inbox.next()).done).toBe(true);
+  });
+});
+
+/** RPC pair whose nth call is answered after `delays[n]` ms, or once the given promise settles */
+async function createDelayedPair(delays: Array<number | Promise<void>>) {
+  const codec = createProtocolCodec<Variant>(variants, { errorVariant: "Error" });
+  let served = 0;
+  const router = new PacketRouter(codec).on("GetStatus", async () => {
+    const delay = delays[served++] ?? 0;
+    await (typeof delay === "number" ? sleep(delay) : delay);
+    return { type: "Status", data: encodeU32(served) };
+  });
+
+  const [a, b] = await createEncryptedStreamPair();
+  const client = a.split();
+  const server = b.split();
+  new RpcConnection(server.reader, server.writer, { codec, router }).start();
+  return { codec, caller: new RpcConnection(client.reader, client.writer, { codec }).start() };
+}
+
+describe("RPC timeouts and retries", () => {
+  test("should time out calls without a reply", async () => {
+    const { caller } = await createDelayedPair([500]);
+
+    try {
+      await caller.call("GetStatus", undefined, { timeoutMs: 50 });
+      throw new Error("expected timeout");
+    } catch (error) {
+      expect(error).toBeInstanceOf(ClavisError);
+      expect(((error as ClavisError).cause as StreamError).code).toBe(StreamErrorCode.Timeout);
+    }
+    expect(caller.pendingCalls).toBe(0);
+  });
+
+  test("should retry idempotent calls after a timeout", async () => {
+    const { caller } = await createDelayedPair([300, 0]);
+
+    const reply = await caller.call("GetStatus", undefined, {
+      timeoutMs: 100,
+      idempotent: true,
+      retry: { maxAttempts: 2, initialDelayMs: 1 },
+    });
+    expect(reply.type).toBe("Status");
+  });
+
+  test("should not retry calls that are not idempotent", async () => {
+    const { caller } = await createDelayedPair([300, 0]);
+
+    await expect(caller.call("GetStatus", undefined, {
+      timeoutMs: 100,
+      retry: { maxAttempts: 2, initialDelayMs: 1 },
+    })).rejects.toThrow();
+  });
+});
+
+describe("RpcPool", () => {
+  test("should hedge slow calls onto another connection", async () => {
+    let release!: () => void;
+    const slow = await createDelayedPair([new Promise<void>((resolve) => (release = resolve))]);
+    const fast = await createDelayedPair([0]);
+    const connections = [slow.caller, fast.caller];
+    let opened = 0;
+
+    const clock = new ManualClock();
+    const pool = new RpcPool<Variant>({
+      codec: slow.codec,
+      size: 2,
+      clock,
+      connect: async () => connections[opened++]!,
+    });
+
+    // The first connection never answers; the copy goes out once hedgeAfterMs passes
+    let reply: DecodedMessage<Variant> | undefined;
+    const call = pool.call("GetStatus", undefined, { idempotent: true, hedgeAfterMs: 20 }).then((answer) => (reply = answer));
+    await clock.advance(19);
+    expect(reply).toBeUndefined();
+    expect(opened).toBe(1);
+    await clock.advance(1);
+    await call;
+    expect(opened).toBe(2);
+    expect(reply?.type).toBe("Status");
+
+    release();
+    await pool.close();
+  });
+
+  test("should run hedged copies once and drop the losers' replies", async () => {
+    const codec = createProtocolCodec<Variant>(variants);
+    let runs = 0;
+    const router = new PacketRouter(codec).on("GetStatus", async () => {
+      runs++;
+      await sleep(100);
+      return { type: "Status", data: encodeU32(runs) };
+    });
+    const connect = async (hedges: HedgeRegistry) => {
+      const [a, b] = await createEncryptedStreamPair();
+      const client = a.split();
+      const server = b.split();
+      new RpcConnection(server.reader, server.writer, { codec, router, hedges }).start();
+      return new RpcConnection(client.reader, client.writer, { codec }).start();
+    };
+
+    // Both connections reach the same server: the copies share one run
+    const shared = new HedgeRegistry();
+    const connections = [await connect(shared), await connect(shared)];
+    let opened = 0;
+    const pool = new RpcPool<Variant>({ codec, size: 2, connect: async () => connections[opened++]! });
+    const reply = await pool.call("GetStatus", undefined, { idempotent: true, hedgeAfterMs: 20 });
+    expect(reply.reader.readU32()).toBe(1);
+    expect(runs).toBe(1);
+    expect(shared.stats()).toMatchObject({ groups: 1, deduplicated: 1 });
+    await pool.close();
+
+    // Separate servers: the slow copy is cancelled and its reply dropped
+    const slow = new HedgeRegistry();
+    const fast = await createRpcPair();
+    const separate = [await connect(slow), fast.caller];
+    opened = 0;
+    const hedgedPool = new RpcPool<Variant>({ codec, size: 2, connect: async () => separate[opened++]! });
+    expect((await hedgedPool.call("GetStatus", undefined, { idempotent: true, hedgeAfterMs: 20 })).reader.readU32()).toBe(42);
+    await sleep(150);
+    expect(slow.stats()).toEqual({ groups: 1, deduplicated: 0, suppressed: 1 });
+    await hedgedPool.close();
+  });
+
+  test("should replace idle connections that fail the warm-up ping", async () => {
+    const healthy = await createRpcPair();
+    const stalled = await createStalledCaller(healthy.codec);
+    const connections = [stalled, healthy.caller];
+    let opened = 0;
+
+    const pool = new RpcPool<Variant>({
+      codec: healthy.codec,
+      size: 1,
+      warmUp: { afterIdleMs: 0, timeoutMs: 50 },
+      connect: async () => connections[opened++]!,
+    });
+
+    expect((await pool.call("GetStatus")).reader.readU32()).toBe(1);
+    // The first connection's peer stopped reading; the caller never sees it
+    expect((await pool.call("GetStatus")).reader.readU32()).toBe(42);
+    expect(pool.discarded).toBe(1);
+    expect(stalled.isClosed).toBe(true);
+
+    await pool.close();
+  });
+
+  test("should keep idle connections that answer the ping", async () => {
+    const { caller, codec } = await createRpcPair();
+    let opened = 0;
+    const pool = new RpcPool<Variant>({
+      codec,
+      size: 1,
+      warmUp: { afterIdleMs: 0 },
+      connect: async () => {
+        opened++;
+        return caller;
+      },
+    });
+
+    await pool.call("GetStatus");
+    await pool.call("GetStatus");
+    expect(opened).toBe(1);
+    expect(pool.discarded).toBe(0);
+    expect(await caller.ping()).toBeGreaterThanOrEqual(0);
+
+    await pool.close();
+  });
+});
+
+/** A caller whose peer answers one request by hand and then stops reading, like a hung server */
+async function createStalledCaller(codec: ProtocolCodec<Variant>) {
+  const [a, b] = await createEncryptedStreamPair();
+  const { reader, writer } = a.split();
+  const caller = new RpcConnection(reader, writer, { codec }).start();
+  void (async () => {
+    const request = decodeRpcEnvelope((await b.readPacket()) as unknown as Uint8Array);
+    const reply = codec.encode("Status", encodeU32(1));
+    await b.writePacket(new RawPacket(encodeRpcEnvelope(RpcFrameKind.Response, request.id, reply)));
+  })();
+  return caller;
+}
+
+async function createStreamingPair(configure: (router: PacketRouter<Variant>) => void, uploadWindow?: number) {
+  const codec = createProtocolCodec<Variant>(variants, { errorVariant: "Error" });
+  const router = new PacketRouter(codec);
+  configure(router);
+
+  const [a, b] = await createEncryptedStreamPair();
+  const client = a.split();
+  const server = b.split();
+  new RpcConnection(server.reader, server.writer, { codec, router, uploadWindow }).start();
+  return new RpcConnection(client.reader, client.writer, { codec }).start();
+}
+
+describe("Streaming RPC", () => {
+  test("should deliver server-streamed items in order", async () => {
+    const caller = await createStreamingPair((router) => {
+      router.onStream("GetStatus", function* () {
+        for (let i = 0; i < 5; i++) {
+          yield { type: "Status", data: encodeU32(i) };
+        }
+      });
+    });
+
+    const received: number[] = [];
+    for await (const item of caller.callStream("GetStatus")) {
+      received.push(item.reader.readU32());
+    }
+    expect(received).toEqual([0, 1, 2, 3, 4]);
+  });
+
+  test("should not send more items than the window allows", async () => {
+    let produced = 0;
+    const caller = await createStreamingPair((router) => {
+      router.onStream("GetStatus", function* () {
+        for (let i = 0; i < 100; i++) {
+          produced++;
+          yield { type: "Status", data: encodeU32(i) };
+        }
+      });
+    });
+
+    const items = caller.callStream("GetStatus", undefined, { window: 4 });
+    await items.next();
+    await sleep(50);
+    // Four credited items plus the one waiting for credit
+    expect(produced).toBeLessThanOrEqual(5);
+
+    let count = 1;
+    for await (const item of items) {
+      expect(item.type).toBe("Status");
+      count++;
+    }
+    expect(count).toBe(100);
+  });
+
+  test("should cancel the handler when the caller stops early", async () => {
+    let finished = false;
+    let aborted = false;
+    const caller = await createStreamingPair((router) => {
+      router.onStream("GetStatus", async function* (_message, signal) {
+        try {
+          for (let i = 0; ; i++) {
+            yield { type: "Status", data: encodeU32(i) };
+            await sleep(1);
+          }
+        } finally {
+          finished = true;
+          aborted = signal.aborted;
+        }
+      });
+    });
+
+    let count = 0;
+    for await (const item of caller.callStream("GetStatus", undefined, { window: 2 })) {
+      expect(item.type).toBe("Status");
+      if (++count === 3) break;
+    }
+    await sleep(50);
+    expect(finished).toBe(true);
+    expect(aborted).toBe(true);
+  });
+
+  test("should end the stream with the error variant on handler failure", async () => {
+    const caller = await createStreamingPair((router) => {
+      router.onStream("GetStatus", async function* () {
+        yield { type: "Status", data: encodeU32(1) };
+        throw new Error("feed broke");
+      });
+    });
+
+    const received: number[] = [];
+    try {
+      for await (const item of caller.callStream("GetStatus")) {
+        received.push(item.reader.readU32());
+      }
+      throw new Error("expected rejection");
+    } catch (error) {
+      expect(error).toBeInstanceOf(RpcError);
+      expect((error as RpcError).message).toBe("feed broke");
+    }
+    expect(received).toEqual([1]);
+  });
+
+  test("should collect client-streamed items into one reply", async () => {
+    const caller = await createStreamingPair((router) => {
+      router.onUpload("Echo", async (_message, items) => {
+        let sum = 0;
+        for await (const item of items) {
+          sum += item.reader.readU32();
+          await sleep(1);
+        }
+        return { type: "Status", data: encodeU32(sum) };
+      });
+    }, 2);
+
+    function* readings() {
+      for (let i = 1; i <= 10; i++) {
+        yield { type: "Status" as const, data: encodeU32(i) };
+      }
+    }
+
+    const reply = await caller.callUpload("Echo", encodeText("sum"), readings());
+    expect(reply.type).toBe("Status");
+    expect(reply.reader.readU32()).toBe(55);
+  });
+
+  test("should reject uploads without a handler", async () => {
+    const caller = await createStreamingPair(() => {});
+    await expect(caller.callUpload("Echo", encodeText("x"), [])).rejects.toThrow(RpcError);
+  });
+});
+
+describe("RPC over a multiplexer", () => {
+  async function createMuxRpcPair(channelPerCall: boolean) {
+    const codec = createProtocolCodec<Variant>(variants);
+    const router = new PacketRouter(codec)
+      .on("GetStatus", () => ({ type: "Status", data: encodeU32(42) }))
+      .onStream("Echo", function* () {
+        for (let i = 0; i < 1000; i++) yield { type: "Status", data: encodeU32(i) };
+      });
+    const [a, b] = await createEncryptedStreamPair();
+    const clientMux = ChannelMux.over(a);
+    const serverMux = ChannelMux.over(b);
+    const [client, server] = await Promise.all([
+      RpcConnection.overMux(clientMux, { codec, router, role: "client", channelPerCall }),
+      RpcConnection.overMux(serverMux, { codec, router, role: "server", channelPerCall }),
+    ]);
+    return { client, server, clientMux, serverMux };
+  }
+
+  test("should run each call on its own channel and free it afterwards", async () => {
+    const { client, clientMux, serverMux } = await createMuxRpcPair(true);
+
+    // A stream nobody reads holds its channel, but not the calls next to it
+    const stalled = client.callStream("Echo", undefined, { window: 1 });
+    await stalled.next();
+    expect(clientMux.openChannels).toBe(2);
+    const replies = await Promise.all([client.call("GetStatus"), client.call("GetStatus")]);
+    expect(replies.map((reply) => reply.reader.readU32())).toEqual([42, 42]);
+
+    await stalled.return(undefined);
+    await sleep(50);
+    expect(clientMux.openChannels).toBe(1);
+    expect(serverMux.openChannels).toBe(1);
+  });
+
+  test("should let both sides call at once without clashing channels", async () => {
+    const { client, server } = await createMuxRpcPair(true);
+    const calls = Array.from({ length: 10 }, (_, i) => (i % 2 === 0 ? client : server).call("GetStatus"));
+    for (const reply of await Promise.all(calls)) {
+      expect(reply.reader.readU32()).toBe(42);
+    }
+  });
+
+  test("should share channel 0 without channelPerCall", async () => {
+    const { client, clientMux } = await createMuxRpcPair(false);
+    expect((await client.call("GetStatus")).reader.readU32()).toBe(42);
+    expect(clientMux.openChannels).toBe(1);
+    await expect(client.ping()).rejects.toThrow(ClavisError);
+  });
+});
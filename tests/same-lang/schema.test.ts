@@ -0,0 +1,98 @@
+/**
+ * Packet schema tests - hashing and append-only evolution
+ */
+
+import { describe, test, expect } from "bun:test";
+import { BincodeReader } from "../../src/bincode.js";
+import { defineSchema, encodeStruct, decodeStruct, schemaHash } from "../../src/schema.js";
+import { ClavisError } from "../../src/error.js";
+
+const StatusV1 = defineSchema("Status", [
+  { name: "users_online", type: "u32" },
+  { name: "name", type: "string" },
+], { evolution: "append-only" });
+
+const StatusV2 = defineSchema("Status", [
+  { name: "users_online", type: "u32" },
+  { name: "name", type: "string" },
+  { name: "region", type: "string", optional: true },
+  { name: "uptime", type: "u64", optional: true },
+], { evolution: "append-only", previous: StatusV1 });
+
+describe("Schema hashing", () => {
+  test("should be stable for identical descriptions", () => {
+    expect(StatusV1.hash).toBe(schemaHash("Status", StatusV1.fields));
+    expect(StatusV1.hash).toHaveLength(64);
+    expect(StatusV2.hash).not.toBe(StatusV1.hash);
+  });
+
+  test("should record every compatible version", () => {
+    expect(StatusV2.compatibleHashes).toEqual([StatusV1.hash, StatusV2.hash]);
+    expect(StatusV2.requiredFieldCount).toBe(2);
+  });
+});
+
+describe("Append-only evolution", () => {
+  test("should decode packets from older senders", () => {
+    const old = encodeStruct(StatusV1, { users_online: 3, name: "edge" });
+    const decoded = decodeStruct(StatusV2, new BincodeReader(old));
+    expect(decoded).toEqual({ users_online: 3, name: "edge", region: undefined, uptime: undefined });
+  });
+
+  test("should let older readers ignore appended fields", () => {
+    const current = encodeStruct(StatusV2, { users_online: 3, name: "edge", region: "eu", uptime: 10n });
+    const reader = new BincodeReader(current);
+    expect(decodeStruct(StatusV1, reader)).toEqual({ users_online: 3, name: "edge" });
+    expect(reader.hasMore).toBe(true);
+  });
+
+  test("should round-trip the current version", () => {
+    const value = { users_online: 9, name: "core", region: "us", uptime: 1234n };
+    expect(decodeStruct(StatusV2, new BincodeReader(encodeStruct(StatusV2, value)))).toEqual(value);
+  });
+
+  test("should reject appended required fields", () => {
+    expect(() => defineSchema("Status", [
+      ...StatusV1.fields,
+      { name: "region", type: "string" },
+    ], { evolution: "append-only", previous: StatusV1 })).toThrow(ClavisError);
+  });
+
+  test("should reject changed or reordered fields", () => {
+    expect(() => defineSchema("Status", [
+      { name: "name", type: "string" },
+      { name: "users_online", type: "u32" },
+    ], { evolution: "append-only", previous: StatusV1 })).toThrow(ClavisError);
+
+    expect(() => defineSchema("Status", [
+      { name: "users_online", type: "u64" },
+      { name: "name", type: "string" },
+    ], { evolution: "append-only", previous: StatusV1 })).toThrow(ClavisError);
+  });
+
+  test("should reject removed fields", () => {
+    expect(() => defineSchema("Status", [
+      { name: "users_online", type: "u32" },
+    ], { evolution: "append-only", previous: StatusV1 })).toThrow(ClavisError);
+  });
+
+  test("should require append-only mode to declare a previous version", () => {
+    expect(() => defineSchema("Status", [...StatusV1.fields], { previous: StatusV1 })).toThrow(ClavisError);
+  });
+});
+
+describe("Bytes views", () => {
+  const Blob = defineSchema("Blob", [{ name: "body", type: "bytes", view: true }]);
+
+  test("should decode bytes fields as views into the packet", () => {
+    const packet = encodeStruct(Blob, { body: new Uint8Array([1, 2, 3]) });
+    const decoded = decodeStruct(Blob, new BincodeReader(packet));
+    expect(decoded["body"]).toEqual(new Uint8Array([1, 2, 3]));
+    expect((decoded["body"] as Uint8Array).buffer).toBe(packet.buffer);
+  });
+
+  test("should not change the schema hash", () => {
+    const copied = defineSchema("Blob", [{ name: "body", type: "bytes" }]);
+    expect(Blob.hash).toBe(copied.hash);
+  });
+});
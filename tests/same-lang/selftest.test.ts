@@ -0,0 +1,74 @@
+/**
+ * Self-test tests - core checks against a diagnostics responder, extension probes and the report
+ */
+
+import { describe, test, expect } from "bun:test";
+import { createServer, connect, type AddressInfo, type Server, type Socket } from "net";
+import { runSelfTest, formatSelfTestReport } from "../../src/selftest.js";
+import { serveDiag } from "../../src/diag.js";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+
+async function responder(options: EncryptedStreamOptions): Promise<Server> {
+  const server = createServer(async (socket) => {
+    try {
+      await serveDiag(await EncryptedStream.new(socket, options));
+    } catch {
+      // Probes the responder doesn't support end here
+    } finally {
+      socket.destroy();
+    }
+  });
+  await new Promise<void>((resolve) => server.listen(0, "127.0.0.1", resolve));
+  return server;
+}
+
+function connector(server: Server) {
+  const { port } = server.address() as AddressInfo;
+  return () => new Promise<Socket>((resolve, reject) => {
+    const socket = connect(port, "127.0.0.1", () => resolve(socket));
+    socket.once("error", reject);
+  });
+}
+
+describe("runSelfTest", () => {
+  test("should pass the core checks, boundary sizes included, and report unsupported extensions", async () => {
+    const server = await responder({ maxPacketSize: 4096 });
+    try {
+      const report = await runSelfTest({ connect: connector(server), streamOptions: { maxPacketSize: 4096 }, timeoutMs: 2000 });
+      expect(report.checks.map((check) => check.name)).toEqual([
+        "handshake", "ping", "echo 0 B payload", "echo 1 B payload", "echo 4095 B packet", "echo 4096 B packet",
+      ]);
+      expect(report.checks.filter((check) => !check.ok)).toEqual([]);
+      expect(report.ok).toBe(true);
+      expect(report.extensions.map((check) => check.ok)).toEqual([false, false, false, false, false, false]);
+
+      const text = formatSelfTestReport(report);
+      expect(text).toContain("ok    echo 4096 B packet");
+      expect(text).toContain("no    padding");
+      expect(text.split("\n").at(-1)).toBe("compatible");
+    } finally {
+      server.close();
+    }
+  });
+
+  test("should find extensions the peer runs with and fail on a wrong key", async () => {
+    const psk = new TextEncoder().encode("selftest-pre-shared-key");
+    const server = await responder({ psk, padding: {} });
+    try {
+      const padded = await runSelfTest({ connect: connector(server), streamOptions: { psk, padding: {} }, timeoutMs: 2000 });
+      expect(padded.ok).toBe(true);
+      expect(padded.extensions.find((check) => check.name === "padding")).toMatchObject({ ok: true, detail: "padme" });
+
+      const wrongKey = await runSelfTest({
+        connect: connector(server),
+        streamOptions: { psk: new TextEncoder().encode("another-pre-shared-key") },
+        timeoutMs: 2000,
+      });
+      expect(wrongKey.ok).toBe(false);
+      expect(wrongKey.checks).toHaveLength(1);
+      expect(formatSelfTestReport(wrongKey)).toContain("FAIL  handshake");
+    } finally {
+      server.close();
+    }
+  });
+});
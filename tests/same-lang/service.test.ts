@@ -0,0 +1,101 @@
+/**
+ * Service definition tests - typed client stubs and server bindings
+ */
+
+import { describe, test, expect } from "bun:test";
+import { createProtocolCodec } from "../../src/protocol.js";
+import { PacketRouter } from "../../src/router.js";
+import { RpcConnection, RpcError } from "../../src/rpc.js";
+import { defineService, type ValueCodec } from "../../src/service.js";
+import { ClavisError } from "../../src/error.js";
+import { writeString, writeU32 } from "../../src/bincode.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const variants = ["GetStatus", "Status", "Echo", "Count", "Tick", "Error"] as const;
+type Variant = typeof variants[number];
+
+const textCodec: ValueCodec<string> = {
+  encode(value) {
+    const buffer: number[] = [];
+    writeString(buffer, value);
+    return new Uint8Array(buffer);
+  },
+  decode: (reader) => reader.readString(),
+};
+
+const u32Codec: ValueCodec<number> = {
+  encode(value) {
+    const buffer: number[] = [];
+    writeU32(buffer, value);
+    return new Uint8Array(buffer);
+  },
+  decode: (reader) => reader.readU32(),
+};
+
+const codec = createProtocolCodec<Variant>(variants, { errorVariant: "Error" });
+
+const TestService = defineService(codec, {
+  getStatus: { request: "GetStatus", response: "Status", output: u32Codec },
+  echo: { request: "Echo", response: "Echo", input: textCodec, output: textCodec },
+  count: { request: "Count", response: "Tick", input: u32Codec, output: u32Codec, streaming: true },
+});
+
+async function createServicePair() {
+  const router = TestService.serve(new PacketRouter(codec), {
+    getStatus: () => 42,
+    echo: (text) => {
+      if (text === "fail") throw new Error("echo refused");
+      return text.toUpperCase();
+    },
+    *count(n) {
+      for (let i = 1; i <= n; i++) yield i;
+    },
+  });
+
+  const [a, b] = await createEncryptedStreamPair();
+  const client = a.split();
+  const server = b.split();
+  new RpcConnection(server.reader, server.writer, { codec, router }).start();
+  const rpc = new RpcConnection(client.reader, client.writer, { codec }).start();
+  return TestService.client(rpc);
+}
+
+describe("defineService", () => {
+  test("should call unary methods through the stub", async () => {
+    const client = await createServicePair();
+
+    expect(await client.getStatus()).toBe(42);
+    expect(await client.echo("hello")).toBe("HELLO");
+  });
+
+  test("should surface handler failures as RpcError", async () => {
+    const client = await createServicePair();
+    await expect(client.echo("fail")).rejects.toThrow(RpcError);
+  });
+
+  test("should stream values from streaming methods", async () => {
+    const client = await createServicePair();
+
+    const ticks: number[] = [];
+    for await (const tick of client.count(4)) {
+      ticks.push(tick);
+    }
+    expect(ticks).toEqual([1, 2, 3, 4]);
+  });
+
+  test("should reject unknown variants", () => {
+    expect(() =>
+      defineService(codec, {
+        broken: { request: "GetStatus", response: "Missing" as Variant },
+      })
+    ).toThrow(ClavisError);
+  });
+
+  test("should require every method to be implemented", () => {
+    expect(() =>
+      TestService.serve(new PacketRouter(codec), {
+        getStatus: () => 1,
+      } as unknown as Parameters<typeof TestService.serve>[1])
+    ).toThrow(ClavisError);
+  });
+});
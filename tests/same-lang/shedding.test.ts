@@ -0,0 +1,95 @@
+/**
+ * Load shedding tests - eviction order, callbacks and overload signals
+ */
+
+import { describe, test, expect } from "bun:test";
+import { LoadShedder, type LoadSheddingOptions } from "../../src/shedding.js";
+import { ManualClock } from "../../src/clock.js";
+import { ClavisError } from "../../src/error.js";
+
+function shedder(options: LoadSheddingOptions<string>, queued = () => 0) {
+  const evicted: Array<[string, string]> = [];
+  const engine = new LoadShedder<string>(options, (conn, reason) => evicted.push([conn, reason]), queued);
+  return { engine, evicted };
+}
+
+describe("LoadShedder", () => {
+  test("should evict lowest priority first, oldest activity breaking ties", async () => {
+    const clock = new ManualClock();
+    let overloaded = 3;
+    const { engine } = shedder({ clock, isOverloaded: () => (overloaded-- > 0 ? "busy" : undefined) });
+    engine.track("gold", 2);
+    clock.advance(1);
+    engine.track("old", 0);
+    clock.advance(1);
+    const recent = engine.track("recent", 0);
+    engine.track("silver", 1);
+    clock.advance(1);
+    recent.touch();
+
+    expect(await engine.shed()).toEqual(["old", "recent", "silver"]);
+    expect(engine.size).toBe(1);
+  });
+
+  test("should order purely by activity when asked", async () => {
+    const clock = new ManualClock();
+    const { engine } = shedder({ clock, order: "least-recently-active", isOverloaded: () => "busy" });
+    const first = engine.track("first", 9);
+    clock.advance(1);
+    engine.track("second", 0);
+    clock.advance(1);
+    first.touch();
+
+    expect(await engine.shed(1)).toEqual(["second"]);
+  });
+
+  test("should stop once the signals clear", async () => {
+    let used = 300;
+    const { engine, evicted } = shedder({
+      memoryBudgetBytes: 100,
+      memoryUsage: () => used,
+      beforeEvict: () => {
+        // Each eviction frees 100 bytes
+        used -= 100;
+      },
+    });
+    for (const conn of ["a", "b", "c", "d"]) engine.track(conn);
+
+    expect(await engine.shed()).toEqual(["a", "b"]);
+    expect(evicted[0]?.[1]).toBe("300 bytes in use, budget 100");
+    expect(engine.size).toBe(2);
+  });
+
+  test("should spare connections the callback refuses", async () => {
+    const { engine, evicted } = shedder({
+      isOverloaded: () => "busy",
+      beforeEvict: (conn) => conn !== "keep",
+    });
+    engine.track("keep");
+    engine.track("drop");
+
+    expect(await engine.shed()).toEqual(["drop"]);
+    expect(evicted).toEqual([["drop", "busy"]]);
+    expect(engine.size).toBe(1);
+  });
+
+  test("should read the handshake queue depth", async () => {
+    let queued = 5;
+    const { engine } = shedder({ maxQueuedHandshakes: 4 }, () => queued);
+    engine.track("a");
+    expect(engine.overloadReason()).toBe("5 handshakes queued, limit 4");
+    queued = 4;
+    expect(engine.overloadReason()).toBeUndefined();
+    expect(await engine.shed()).toEqual([]);
+  });
+
+  test("should skip untracked connections", async () => {
+    const { engine } = shedder({ isOverloaded: () => "busy" });
+    engine.track("a").untrack();
+    expect(await engine.shed()).toEqual([]);
+  });
+
+  test("should reject a non-positive memory budget", () => {
+    expect(() => shedder({ memoryBudgetBytes: 0 })).toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,105 @@
+/**
+ * Simulated network tests - listeners and clients on simulated time, partitions and crashes
+ */
+
+import { describe, test, expect } from "bun:test";
+import { SimNetwork, type SimSocket } from "../../src/sim.js";
+import { EncryptedListener } from "../../src/listener.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { ClavisClient } from "../../src/client.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+
+async function connected(network: SimNetwork) {
+  const listener = new EncryptedListener(network.bind("server", 7000));
+  const connecting = network.connect("client", "server", 7000).then((socket) => EncryptedStream.new(socket));
+  await network.clock.advance(1000);
+  const client = await connecting;
+  const accepted = await listener.accept();
+  return { listener, client, server: accepted.stream, socket: accepted.socket };
+}
+
+describe("SimNetwork", () => {
+  test("should connect a listener and a stream on simulated time", async () => {
+    const network = new SimNetwork({ link: { latencyMs: 50 } });
+    const { client, server, socket } = await connected(network);
+    expect(socket.remoteAddress).toBe("client");
+    expect(socket.remotePort).toBe(49152);
+
+    const start = network.clock.now();
+    const reading = server.readPacket();
+    await client.writePacket(new RawPacket(new Uint8Array([1])));
+    await network.clock.advance(49);
+    let done = false;
+    void reading.then(() => (done = true));
+    await network.clock.advance(0);
+    expect(done).toBe(false);
+    await network.clock.advance(1);
+    expect((await reading) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+    expect(network.clock.now() - start).toBe(50);
+  });
+
+  test("should hold data back across a partition until it is repaired", async () => {
+    const network = new SimNetwork({ link: { latencyMs: 10 } });
+    const { client, server } = await connected(network);
+
+    network.partition("server", "client");
+    let received: Uint8Array | undefined;
+    void server.readPacket().then((packet) => (received = packet as unknown as Uint8Array));
+    await client.writePacket(new RawPacket(new Uint8Array([2])));
+    await network.clock.advance(60_000);
+    expect(received).toBeUndefined();
+
+    network.repair("client", "server");
+    await network.clock.advance(10);
+    expect(received).toEqual(new Uint8Array([2]));
+  });
+
+  test("should refuse connections nobody listens for and wait out partitioned ones", async () => {
+    const network = new SimNetwork({ link: { latencyMs: 5 } });
+    const refused = network.connect("client", "server", 1).catch((error: NodeJS.ErrnoException) => error.code);
+    await network.clock.advance(10);
+    expect(await refused).toBe("ECONNREFUSED");
+
+    network.bind("server", 1);
+    network.partition("client", "server");
+    let socket: SimSocket | undefined;
+    void network.connect("client", "server", 1).then((s) => (socket = s));
+    await network.clock.advance(1000);
+    expect(socket).toBeUndefined();
+    network.repairAll();
+    await network.clock.advance(10);
+    expect(socket?.remoteAddress).toBe("server");
+    expect(() => network.bind("server", 1)).toThrow(ClavisError);
+  });
+
+  test("should reset writes to a crashed host", async () => {
+    const network = new SimNetwork();
+    const { client } = await connected(network);
+
+    network.crash("server");
+    await network.clock.advance(10);
+    await expect(client.writePacket(new RawPacket(new Uint8Array([3])))).rejects.toThrow(ClavisError);
+  });
+
+  test("should run a ClavisClient through its connector", async () => {
+    const network = new SimNetwork({ link: { latencyMs: 20 } });
+    const listener = new EncryptedListener(network.bind("server", 7000));
+    const client = new ClavisClient({
+      host: "server",
+      port: 7000,
+      connector: network.connector("client"),
+      clock: network.clock,
+      reconnect: { enabled: false },
+    });
+
+    const connecting = client.connect();
+    await network.clock.advance(1000);
+    await connecting;
+    expect(client.isConnected).toBe(true);
+    expect((await listener.accept()).peer.remoteAddress).toBe("client");
+
+    await client.disconnect();
+    await listener.close();
+  });
+});
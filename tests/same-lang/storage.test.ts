@@ -0,0 +1,106 @@
+/**
+ * Storage tests - the same contract for every shipped backend
+ */
+
+import { describe, test, expect, afterAll } from "bun:test";
+import { mkdtempSync, readdirSync, rmSync, utimesSync, writeFileSync } from "fs";
+import { tmpdir } from "os";
+import { join } from "path";
+import { FileStorage, MemoryStorage, type Storage } from "../../src/storage.js";
+import { StorageReplayCache } from "../../src/replay.js";
+import { ManualClock } from "../../src/clock.js";
+import { ClavisError } from "../../src/error.js";
+
+const root = mkdtempSync(join(tmpdir(), "clavis-storage-"));
+afterAll(() => rmSync(root, { recursive: true, force: true }));
+
+let dirs = 0;
+const backends: [string, (clock: ManualClock) => Storage][] = [
+  ["MemoryStorage", (clock) => new MemoryStorage({ clock })],
+  ["FileStorage", (clock) => new FileStorage(join(root, String(dirs++)), { clock })],
+];
+
+for (const [name, create] of backends) {
+  describe(name, () => {
+    test("should get, set and delete values", async () => {
+      const storage = create(new ManualClock(0));
+      expect(await storage.get("a/b")).toBeUndefined();
+      await storage.set("a/b", new Uint8Array([1, 2]));
+      await storage.set("a/c", new Uint8Array([3]));
+      await storage.set("z", new Uint8Array(0));
+      expect(await storage.get("a/b")).toEqual(new Uint8Array([1, 2]));
+      expect(await storage.keys("a/")).toEqual(["a/b", "a/c"]);
+
+      expect(await storage.delete("a/b")).toBe(true);
+      expect(await storage.delete("a/b")).toBe(false);
+      expect(await storage.keys()).toEqual(["a/c", "z"]);
+    });
+
+    test("should set once until the entry expires", async () => {
+      const clock = new ManualClock(1_000);
+      const storage = create(clock);
+      expect(await storage.setIfAbsent("t", new Uint8Array([1]), { expiresAt: 2_000 })).toBe(true);
+      expect(await storage.setIfAbsent("t", new Uint8Array([2]))).toBe(false);
+      expect(await storage.get("t")).toEqual(new Uint8Array([1]));
+
+      await clock.advance(1_000);
+      expect(await storage.get("t")).toBeUndefined();
+      expect(await storage.setIfAbsent("t", new Uint8Array([3]))).toBe(true);
+      expect(await storage.get("t")).toEqual(new Uint8Array([3]));
+    });
+
+    test("should back a replay cache", async () => {
+      const cache = new StorageReplayCache(create(new ManualClock(0)));
+      expect(await cache.insert("ticket", 10)).toBe(true);
+      expect(await cache.insert("ticket", 10)).toBe(false);
+    });
+  });
+}
+
+describe("FileStorage", () => {
+  test("should keep values across instances", async () => {
+    const dir = join(root, "shared");
+    await new FileStorage(dir).set("key", new Uint8Array([9]));
+    expect(await new FileStorage(dir).get("key")).toEqual(new Uint8Array([9]));
+  });
+
+  test("should not collide on temporary files and refuse empty keys", async () => {
+    const dir = join(root, "concurrent");
+    const [first, second] = [new FileStorage(dir), new FileStorage(dir)];
+    const writes = Array.from({ length: 20 }, (_, i) => (i % 2 ? first : second).set(`key-${i % 4}`, new Uint8Array(1000).fill(i)));
+    await Promise.all(writes);
+    for (let key = 0; key < 4; key++) {
+      const value = (await first.get(`key-${key}`))!;
+      expect(value).toHaveLength(1000);
+      expect(value.every((byte) => byte === value[0])).toBe(true);
+    }
+    expect(readdirSync(dir).filter((name) => name.startsWith("."))).toEqual([]);
+
+    await expect(first.set("", new Uint8Array([1]))).rejects.toThrow(ClavisError);
+    await expect(first.get("")).rejects.toThrow(ClavisError);
+    await expect(first.delete("")).rejects.toThrow(ClavisError);
+  });
+
+  test("should let only one of racing writers take an expired key", async () => {
+    const dir = join(root, "expired");
+    const clock = new ManualClock(100_000);
+    const writers = Array.from({ length: 4 }, () => new FileStorage(dir, { clock, durable: false }));
+    const keys = Array.from({ length: 20 }, (_, i) => `ticket-${i}`);
+    for (const key of keys) await writers[0]!.set(key, new Uint8Array([0]), { expiresAt: clock.now() + 1 });
+    await clock.advance(10);
+
+    for (const key of keys) {
+      const won = await Promise.all(writers.map((writer, i) => writer.setIfAbsent(key, new Uint8Array([i + 1]))));
+      expect(won.filter(Boolean)).toHaveLength(1);
+      expect(await writers[0]!.get(key)).toEqual(new Uint8Array([won.indexOf(true) + 1]));
+    }
+    expect(readdirSync(dir).filter((name) => name.startsWith("."))).toEqual([]);
+
+    // An expired key someone else is clearing stays theirs, unless their lock is long dead
+    await writers[0]!.set("held", new Uint8Array([0]), { expiresAt: clock.now() });
+    writeFileSync(join(dir, ".lock-held"), "");
+    expect(await writers[0]!.setIfAbsent("held", new Uint8Array([1]))).toBe(false);
+    utimesSync(join(dir, ".lock-held"), 0, 0);
+    expect(await writers[0]!.setIfAbsent("held", new Uint8Array([1]))).toBe(true);
+  });
+});
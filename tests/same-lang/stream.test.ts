@@ -5,8 +5,13 @@
 import { describe, test, expect, beforeEach, afterEach } from "bun:test";
 import { createTestServer, createEchoServer } from "../helpers/test-server.js";
 import { createTestClient } from "../helpers/test-client.js";
-import { findAvailablePort } from "../helpers/test-utils.js";
+import { findAvailablePort, createEncryptedStreamPair, createStreamPair, sleep } from "../helpers/test-utils.js";
 import { TestProtocol } from "../helpers/test-protocol.js";
+import { HostilePeer } from "../helpers/hostile-peer.js";
+import { EncryptedStream, FRAME_OVERHEAD, checkStreamOptions, wireSize } from "../../src/stream.js";
+import { RawPacket, createProtocolCodec, serializedSize } from "../../src/protocol.js";
+import { ClavisError, StreamError, StreamErrorCode } from "../../src/error.js";
+import { ManualClock, systemClock } from "../../src/clock.js";
 import { Server } from "net";
 
 describe("EncryptedStream", () => {
@@ -102,5 +107,662 @@ describe("EncryptedStream", () => {
 
     client.close();
   });
+
+  test("should read buffered packets in one batch", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const packets = Array.from({ length: 10 }, (_, i) => new RawPacket(new Uint8Array([i])));
+
+    await a.writePackets(packets);
+    await sleep(10);
+
+    const batch = (await b.readPackets(8)) as unknown as Uint8Array[];
+    expect(batch).toEqual(packets.slice(0, 8).map((p) => p.bytes));
+    const rest = (await b.readPackets()) as unknown as Uint8Array[];
+    expect(rest).toEqual(packets.slice(8).map((p) => p.bytes));
+  });
+
+  test("should not write a batch containing an oversized packet", async () => {
+    const [a, b] = await createEncryptedStreamPair({ maxPacketSize: 16 });
+    const { reader } = b.split();
+    const { writer } = a.split();
+
+    await expect(writer.writePackets([
+      new RawPacket(new Uint8Array(4)),
+      new RawPacket(new Uint8Array(32)),
+    ])).rejects.toThrow(ClavisError);
+
+    await writer.writePackets([new RawPacket(new Uint8Array([7]))]);
+    expect((await reader.readPackets()) as unknown as Uint8Array[]).toEqual([new Uint8Array([7])]);
+  });
+
+  test("should name the variant of an oversized packet without encrypting it", async () => {
+    const [a] = await createEncryptedStreamPair({ maxPacketSize: 16 });
+    const ping = TestProtocol.Ping({ message: "x".repeat(32) });
+    const error = (await a.writePacket(ping).catch((error) => error)) as ClavisError;
+    expect(error.packetTooLarge).toEqual({ size: ping.serialize().length, limit: 16, variant: "Ping" });
+    expect(error.message).toContain("Packet Ping of");
+
+    // A packet that reports its size is refused before it is serialized
+    const bulk = {
+      variantName: "Bulk",
+      serializedSize: () => 1_000,
+      serialize: (): Uint8Array => { throw new Error("serialized"); },
+      deserialize() { return this; },
+    };
+    await expect(a.writePackets([new RawPacket(new Uint8Array(1)), bulk])).rejects.toThrow("Packet Bulk of 1000 bytes");
+    expect(a.cpuTime.cryptoMs).toBe(0);
+  });
+
+  test("should report a bad frame after the packets decrypted before it", async () => {
+    const [a, b] = await createStreamPair();
+    const attacker = new HostilePeer(a);
+    const [victim] = await Promise.all([EncryptedStream.new(b), attacker.handshake()]);
+
+    const good = attacker.frame(new Uint8Array([1]));
+    const bad = attacker.frame(new Uint8Array([2]));
+    bad[bad.length - 1] = bad[bad.length - 1]! ^ 0x01;
+    const batch = new Uint8Array(good.length + bad.length);
+    batch.set(good, 0);
+    batch.set(bad, good.length);
+    await attacker.write(batch);
+    await sleep(10);
+
+    expect((await victim.readPackets()) as unknown as Uint8Array[]).toEqual([new Uint8Array([1])]);
+    await expect(victim.readPackets()).rejects.toThrow(ClavisError);
+  });
+
+  test("should report wire sizes and bytes written", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const packet = new RawPacket(new Uint8Array(100));
+
+    expect(serializedSize(packet)).toBe(100);
+    expect(serializedSize(TestProtocol.Ping({ message: "hi" }))).toBe(TestProtocol.Ping({ message: "hi" }).serialize().length);
+    expect(wireSize(packet)).toBe(100 + FRAME_OVERHEAD);
+    expect(a.maxPacketSize).toBe(65536);
+
+    const written = await a.writePacket(packet);
+    expect(written).toBe(wireSize(packet));
+    await b.readPacket();
+
+    const batch = [packet, new RawPacket(new Uint8Array(1))];
+    expect(await a.writePackets(batch)).toBe(wireSize(batch[0]!) + wireSize(batch[1]!));
+  });
+
+  test("should drop packets outside the accept filter", async () => {
+    const codec = createProtocolCodec(["Join", "Message", "Leave"] as const);
+    const [a, b] = await createEncryptedStreamPair();
+    const { reader } = b.split();
+    reader.setAcceptFilter(codec, ["Join"]);
+
+    const message = new RawPacket(codec.encode("Message", new Uint8Array([1])));
+    const join = new RawPacket(codec.encode("Join", new Uint8Array([2])));
+    const leave = new RawPacket(codec.encode("Leave"));
+    await a.writePackets([message, new RawPacket(new Uint8Array([0])), join, leave]);
+
+    expect((await reader.readPacket()) as unknown as Uint8Array).toEqual(join.bytes);
+    expect(reader.droppedPackets).toBe(2);
+
+    reader.setAcceptFilter(codec, undefined);
+    expect((await reader.readPacket()) as unknown as Uint8Array).toEqual(leave.bytes);
+    expect(() => reader.setAcceptFilter(codec, ["Unknown" as "Join"])).toThrow(ClavisError);
+  });
 });
 
+describe("Error context", () => {
+  test("should tag read and write errors with connection and sequence", async () => {
+    const [a, b] = await createStreamPair();
+    const attacker = new HostilePeer(a);
+    const [victim] = await Promise.all([
+      EncryptedStream.new(b, { connectionId: "client-7", maxPacketSize: 64 }),
+      attacker.handshake(),
+    ]);
+    expect(victim.connectionId).toBe("client-7");
+
+    await attacker.sendFrame(new Uint8Array([1]));
+    await victim.readPacket();
+    await attacker.sendTamperedFrame(new Uint8Array([2]));
+    const readError = await victim.readPacket().catch((error) => error as ClavisError);
+    expect(readError.context).toEqual({ connectionId: "client-7", direction: "read", sequence: 1 });
+
+    const writeError = await victim.writePackets([
+      new RawPacket(new Uint8Array(1)),
+      new RawPacket(new Uint8Array(100)),
+    ]).catch((error) => error as ClavisError);
+    expect(writeError.context).toEqual({ connectionId: "client-7", direction: "write", sequence: 1 });
+  });
+
+  test("should number connections by default", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    expect(a.connectionId).not.toBe(b.connectionId);
+    expect(a.split().reader.connectionId).toBe(a.connectionId);
+  });
+
+  test("should unsplit halves back into their stream", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const { reader, writer } = a.split();
+    const whole = EncryptedStream.unsplit(reader, writer);
+    expect(whole).toBe(a);
+
+    await b.writePacket(new RawPacket(new Uint8Array([4])));
+    expect((await whole.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([4]));
+
+    expect(() => EncryptedStream.unsplit(reader, b.split().writer)).toThrow(ClavisError);
+  });
+});
+
+describe("Option checks", () => {
+  test("should report every conflict at once, before the handshake", async () => {
+    const options = {
+      postQuantum: "prefer" as const,
+      dangerousNullCipher: true,
+      rekeyAfterMs: 60_000,
+      keepAliveMs: 5_000,
+      idleTimeoutMs: 5_000,
+      readTimeoutMs: 0,
+    };
+    expect(() => checkStreamOptions(options)).toThrow(ClavisError);
+    expect(checkStreamOptions({ keepAliveMs: 5_000 })).toBeUndefined();
+
+    // The peer never answers, so only a check up front can fail this
+    const [left] = await createStreamPair();
+    const error = (await EncryptedStream.new(left, options).catch((error) => error)) as ClavisError;
+    expect(error.configConflicts?.map((conflict) => conflict.options)).toEqual([
+      ["readTimeoutMs"],
+      ["dangerousNullCipher"],
+      ["postQuantum", "dangerousNullCipher"],
+      ["dangerousNullCipher", "rekeyAfterMs"],
+      ["keepAliveMs", "idleTimeoutMs"],
+    ]);
+    expect(error.message).toContain("postQuantum and dangerousNullCipher can't be combined");
+  });
+});
+
+describe("Reconfiguration", () => {
+  const codeOf = (error: unknown) => ((error as ClavisError).cause as StreamError).code;
+
+  test("should apply a new rate limit to a live connection", async () => {
+    const clock = new ManualClock();
+    const [left, right] = await createStreamPair();
+    const [a, b] = await Promise.all([EncryptedStream.new(left), EncryptedStream.new(right, { clock })]);
+    b.reconfigure({ maxPacketsPerSecond: 1 });
+
+    await a.writePacket(new RawPacket(new Uint8Array([1])));
+    await a.writePacket(new RawPacket(new Uint8Array([2])));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+    expect(codeOf(await b.readPacket().catch((error) => error))).toBe(StreamErrorCode.Overloaded);
+  });
+
+  test("should check the whole patch and change nothing when it fails", async () => {
+    const clock = new ManualClock();
+    const [left, right] = await createStreamPair();
+    const [, b] = await Promise.all([EncryptedStream.new(left), EncryptedStream.new(right, { clock, keepAliveMs: 1_000 })]);
+
+    let error: ClavisError | undefined;
+    try {
+      b.reconfigure({ idleTimeoutMs: 500, readTimeoutMs: 0, packetBurst: 2 });
+    } catch (caught) {
+      error = caught as ClavisError;
+    }
+    expect(error?.configConflicts?.map((conflict) => conflict.options)).toEqual([
+      ["readTimeoutMs"],
+      ["packetBurst"],
+      ["keepAliveMs", "idleTimeoutMs"],
+    ]);
+
+    // Three keep-alives without an answer are allowed; the shorter timeout counts from the change
+    await clock.advance(2_000);
+    b.reconfigure({ keepAliveMs: null, idleTimeoutMs: 100 });
+    const read = b.readPacket().catch((error) => error);
+    await clock.advance(100);
+    expect(codeOf(await read)).toBe(StreamErrorCode.IdleTimeout);
+  });
+});
+
+describe("Connection teardown", () => {
+  async function connected() {
+    const [left, right] = await createStreamPair();
+    const [a, b] = await Promise.all([EncryptedStream.new(left), EncryptedStream.new(right)]);
+    return { left, right, a, b };
+  }
+
+  function codeOf(error: unknown): StreamErrorCode {
+    return ((error as ClavisError).cause as StreamError).code;
+  }
+
+  test("should report a clean close at a frame boundary as EOF", async () => {
+    const { left, a, b } = await connected();
+    await a.writePacket(new RawPacket(new Uint8Array([1])));
+    left.end();
+
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+    expect(codeOf(await b.readPacket().catch((error) => error))).toBe(StreamErrorCode.EOF);
+    expect(codeOf(await b.readPacket().catch((error) => error))).toBe(StreamErrorCode.EOF);
+  });
+
+  test("should report a frame cut short as an unexpected close", async () => {
+    const [a, b] = await createStreamPair();
+    const attacker = new HostilePeer(a);
+    const [victim] = await Promise.all([EncryptedStream.new(b), attacker.handshake()]);
+
+    const pending = victim.readPacket().catch((error) => error);
+    await attacker.sendTruncatedFrame(new Uint8Array([1, 2, 3]), 10);
+    const error = await pending;
+    expect(codeOf(error)).toBe(StreamErrorCode.ConnectionClosed);
+    expect((error as ClavisError).message).toContain("unexpectedly");
+  });
+
+  test("should tell a reset from a peer that stopped answering", async () => {
+    const reset = await connected();
+    const pending = reset.b.readPacket().catch((error) => error);
+    reset.right.destroy(Object.assign(new Error("read ECONNRESET"), { code: "ECONNRESET" }));
+    expect(codeOf(await pending)).toBe(StreamErrorCode.ConnectionReset);
+
+    const dead = await connected();
+    dead.right.destroy(Object.assign(new Error("read ETIMEDOUT"), { code: "ETIMEDOUT" }));
+    const error = await dead.b.readPacket().catch((error) => error);
+    expect(codeOf(error)).toBe(StreamErrorCode.PeerUnresponsive);
+    expect(((error as ClavisError).cause as StreamError).isConnectionClosed()).toBe(true);
+  });
+
+  test("should end reads with Closed after the peer's close frame", async () => {
+    const { a, b } = await connected();
+    await a.writePacket(new RawPacket(new Uint8Array([1])));
+    await a.close();
+    await expect(a.writePacket(new RawPacket(new Uint8Array([2])))).rejects.toThrow(ClavisError);
+
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+    expect(codeOf(await b.readPacket().catch((error) => error))).toBe(StreamErrorCode.Closed);
+    expect(codeOf(await b.readPacket().catch((error) => error))).toBe(StreamErrorCode.Closed);
+
+    // The other direction stays open until b closes as well
+    await b.writePacket(new RawPacket(new Uint8Array([3])));
+    expect((await a.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([3]));
+    await b.close();
+    const packets: Uint8Array[] = [];
+    for await (const packet of a.split().reader) packets.push(packet);
+    expect(packets).toEqual([]);
+  });
+
+  test("should linger on close until the peer closes too, or abort at once", async () => {
+    const { left, a, b } = await connected();
+    await b.writePacket(new RawPacket(new Uint8Array([1])));
+    const closing = a.close({ waitForPeerMs: 5_000 });
+
+    expect(codeOf(await b.readPacket().catch((error) => error))).toBe(StreamErrorCode.Closed);
+    await b.close();
+    await closing;
+    expect(left.destroyed).toBe(true);
+
+    const aborted = await connected();
+    await aborted.a.close("abort");
+    expect(aborted.left.destroyed).toBe(true);
+    expect(codeOf(await aborted.b.readPacket().catch((error) => error))).toBe(StreamErrorCode.Closed);
+  });
+
+  test("should fail a lingering close when the peer never answers", async () => {
+    const clock = new ManualClock();
+    const [left, right] = await createStreamPair();
+    const [a] = await Promise.all([
+      EncryptedStream.new(left, { clock, closeLinger: { waitForPeerMs: 500 } }),
+      EncryptedStream.new(right),
+    ]);
+
+    const closing = a.close().catch((error) => error);
+    await clock.advance(500);
+    expect(codeOf(await closing)).toBe(StreamErrorCode.Timeout);
+    expect(left.destroyed).toBe(true);
+    await expect(EncryptedStream.new(left, { closeLinger: { waitForPeerMs: 0 } })).rejects.toThrow(ClavisError);
+  });
+
+  test("should keep a quiet connection alive with pings the peer answers", async () => {
+    const clock = new ManualClock();
+    const [a, b] = await createEncryptedStreamPair({ clock, keepAliveMs: 1_000 });
+    let failed = false;
+    a.readPacket().catch(() => (failed = true));
+    b.readPacket().catch(() => undefined);
+
+    for (let i = 0; i < 10; i++) await clock.advance(1_000);
+    expect(failed).toBe(false);
+    expect(a.stats().keepAlivesSent).toBeGreaterThan(0);
+    expect(a.stats().keepAlivesSuppressed).toBe(0);
+  });
+
+  test("should skip keepalives while packets go out, but not past the idle timeout", async () => {
+    const clock = new ManualClock();
+    const [a, b] = await createEncryptedStreamPair({ clock, keepAliveMs: 1_000 });
+    let failed = false;
+    a.readPacket().catch(() => (failed = true));
+    (async () => {
+      for (;;) await b.readPacket();
+    })().catch(() => undefined);
+
+    for (let i = 0; i < 10; i++) {
+      await a.writePacket(new RawPacket(new Uint8Array([i])));
+      await clock.advance(1_000);
+    }
+    expect(failed).toBe(false);
+    // The peer hears from us anyway, so only we need pings answered
+    expect(a.stats().keepAlivesSuppressed).toBeGreaterThan(0);
+    expect(a.stats().keepAlivesSent).toBeGreaterThan(0);
+    expect(b.stats()).toMatchObject({ keepAlivesSent: 0, keepAlivesSuppressed: 0 });
+  });
+
+  test("should fail reads with IdleTimeout once the peer goes silent", async () => {
+    const clock = new ManualClock();
+    const [a] = await createEncryptedStreamPair({ clock, keepAliveMs: 1_000 });
+    const pending = a.readPacket().catch((error) => error);
+
+    await clock.advance(3_000);
+    const error = await pending;
+    expect(codeOf(error)).toBe(StreamErrorCode.IdleTimeout);
+    expect(clock.pendingTimers).toBe(0);
+  });
+
+  test("should give up on a handshake the peer never finishes", async () => {
+    const clock = new ManualClock();
+    const [left] = await createStreamPair();
+    const pending = EncryptedStream.new(left, { clock, handshakeTimeoutMs: 5_000 }).catch((error) => error);
+
+    await clock.advance(5_000);
+    const error = await pending;
+    expect(codeOf(error)).toBe(StreamErrorCode.HandshakeTimeout);
+    expect(left.destroyed).toBe(true);
+    await expect(EncryptedStream.new(left, { handshakeTimeoutMs: 0 })).rejects.toThrow(ClavisError);
+  });
+
+  test("should fail a read that waits past the read timeout and close the stream", async () => {
+    const clock = new ManualClock();
+    const [a, b] = await createEncryptedStreamPair({ clock, readTimeoutMs: 1_000 });
+    await b.writePacket(new RawPacket(new Uint8Array([1])));
+    expect((await a.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+
+    const pending = a.readPacket().catch((error) => error);
+    await clock.advance(1_000);
+    const error = await pending;
+    expect(codeOf(error)).toBe(StreamErrorCode.Timeout);
+    expect((error as ClavisError).context).toMatchObject({ direction: "read" });
+    expect(codeOf(await a.readPacket().catch((error) => error))).toBe(StreamErrorCode.Timeout);
+  });
+});
+
+describe("Polling reads", () => {
+  const bytes = (packet: unknown) => Array.from(packet as Uint8Array);
+
+  test("should return only packets that have already arrived", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    expect(await b.tryReadPacket()).toBeUndefined();
+
+    await a.writePacket(new RawPacket(new Uint8Array([1])));
+    await a.writePacket(new RawPacket(new Uint8Array([2])));
+    await sleep(10);
+    expect(bytes(await b.tryReadPacket())).toEqual([1]);
+    expect(bytes(await b.tryReadPacket())).toEqual([2]);
+    expect(await b.tryReadPacket()).toBeUndefined();
+
+    await a.close();
+    await sleep(10);
+    await expect(b.tryReadPacket()).rejects.toThrow(ClavisError);
+  });
+
+  test("should leave packets to a read that is already waiting", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const pending = b.readPacket();
+    expect(await b.tryReadPacket()).toBeUndefined();
+
+    await a.writePacket(new RawPacket(new Uint8Array([3])));
+    expect(bytes(await pending)).toEqual([3]);
+    expect(await b.tryReadPacket()).toBeUndefined();
+  });
+});
+
+describe("Health checks", () => {
+  test("should report the round trip, a timeout or a closed connection", async () => {
+    const [left, right] = await createStreamPair();
+    const [a, b] = await Promise.all([EncryptedStream.new(left), EncryptedStream.new(right)]);
+
+    // Nobody reads on either side yet, so the pong is never picked up
+    const silent = await a.healthCheck(20);
+    expect(silent.healthy === false && silent.failure).toBe("timeout");
+
+    a.readPacket().catch(() => undefined);
+    b.readPacket().catch(() => undefined);
+    const health = await a.healthCheck(1_000);
+    expect(health.healthy && health.rttMs).toBeGreaterThanOrEqual(0);
+
+    left.destroy();
+    const closed = await a.healthCheck(1_000);
+    expect(closed.healthy === false && closed.failure).toBe("closed");
+  });
+});
+
+describe("Stream adapters", () => {
+  test("should iterate over packets until the peer closes cleanly", async () => {
+    const [left, right] = await createStreamPair();
+    const [a, b] = await Promise.all([EncryptedStream.new(left), EncryptedStream.new(right)]);
+    await a.writePackets([new RawPacket(new Uint8Array([1])), new RawPacket(new Uint8Array([2]))]);
+    left.end();
+
+    const received: number[] = [];
+    for await (const packet of b.split().reader) received.push(packet[0]!);
+    expect(received).toEqual([1, 2]);
+  });
+
+  test("should pipe packets between web streams", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const source = new ReadableStream<RawPacket>({
+      start(controller) {
+        controller.enqueue(new RawPacket(new Uint8Array([7])));
+        controller.enqueue(new RawPacket(new Uint8Array([8])));
+        controller.close();
+      },
+    });
+    await source.pipeTo(a.split().writer.toWritableStream());
+
+    const reader = b.split().reader.toReadableStream().getReader();
+    expect((await reader.read()).value).toEqual(new Uint8Array([7]));
+    expect((await reader.read()).value).toEqual(new Uint8Array([8]));
+  });
+});
+
+describe("CPU time", () => {
+  test("should attribute time to serialization and crypto", async () => {
+    // Every reading moves the clock by 1ms, so each timed operation counts once
+    let now = 0;
+    const clock = { now: () => now++, setTimer: systemClock.setTimer };
+    const [a, b] = await createEncryptedStreamPair({ clock }, { clock });
+    expect(a.cpuTime).toEqual({ serializeMs: 0, compressionMs: 0, cryptoMs: 0 });
+
+    await a.writePackets([new RawPacket(new Uint8Array([1])), new RawPacket(new Uint8Array([2]))]);
+    await b.readPacket();
+    expect(a.cpuTime).toEqual({ serializeMs: 2, compressionMs: 0, cryptoMs: 2 });
+    expect(b.split().reader.cpuTime.cryptoMs).toBe(1);
+  });
+});
+
+describe("Connection stats", () => {
+  test("should count application traffic and trace each operation", async () => {
+    const spans: string[] = [];
+    const tracer = {
+      startSpan: (name: string) => ({ end: (error?: unknown) => spans.push(error ? `${name}!` : name) }),
+    };
+    const [a, b] = await createEncryptedStreamPair({ protocolVersion: 1, tracer }, { protocolVersion: 1 });
+    const written = await a.writePacket(new RawPacket(new Uint8Array([1, 2, 3])));
+    await b.readPacket();
+
+    expect(a.stats()).toMatchObject({ packetsSent: 1, packetsReceived: 0, rekeys: 0, decryptionFailures: 0 });
+    expect(a.stats().bytesSent).toBeGreaterThanOrEqual(written);
+    expect(b.split().reader.stats().packetsReceived).toBe(1);
+    expect(spans).toEqual(["clavis.handshake", "clavis.write"]);
+  });
+
+  test("should count frames that fail to decrypt", async () => {
+    const [a, b] = await createStreamPair();
+    const attacker = new HostilePeer(a);
+    const [victim] = await Promise.all([EncryptedStream.new(b), attacker.handshake()]);
+    await attacker.sendTamperedFrame(new Uint8Array([1]));
+
+    await expect(victim.readPacket()).rejects.toThrow(ClavisError);
+    expect(victim.stats().decryptionFailures).toBe(1);
+    expect(victim.stats().handshakeMs).toBeGreaterThanOrEqual(0);
+  });
+});
+
+describe("Packet size limit", () => {
+  test("should carry a packet of exactly maxPacketSize, with compression and padding too", async () => {
+    const variants: Array<Parameters<typeof EncryptedStream.new>[1]> = [
+      { maxPacketSize: 1024 },
+      { maxPacketSize: 1024, compression: { algorithms: ["deflate"] }, padding: {} },
+    ];
+    for (const options of variants) {
+      const [a, b] = await createEncryptedStreamPair(options, options);
+      // Random bytes don't compress, so compression stores them with its method byte
+      const packet = crypto.getRandomValues(new Uint8Array(1024));
+      await a.writePacket(new RawPacket(packet));
+      expect(await b.readPacket()).toEqual(packet);
+      await expect(a.writePacket(new RawPacket(new Uint8Array(1025)))).rejects.toThrow(ClavisError);
+    }
+  });
+});
+
+describe("Packet interceptors", () => {
+  const codec = createProtocolCodec(["Join", "Chat"] as const);
+  const join = new RawPacket(codec.encode("Join"));
+  const chat = new RawPacket(codec.encode("Chat", new Uint8Array([1])));
+
+  test("should see packets both ways and drop what returns false", async () => {
+    const seen: string[] = [];
+    const [a, b] = await createEncryptedStreamPair(
+      {
+        interceptors: [{
+          write: async (packet) => {
+            await sleep(1);
+            seen.push(`write ${codec.variantName(codec.peekIndex(packet)!)}`);
+          },
+        }],
+      },
+      {}
+    );
+    const remove = b.intercept({
+      read(packet, stream) {
+        expect(stream).toBe(b);
+        const variant = codec.variantName(codec.peekIndex(packet)!);
+        seen.push(`read ${variant}`);
+        return variant !== "Chat";
+      },
+    });
+
+    await a.writePackets([chat, join]);
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(join.bytes);
+    expect(b.droppedPackets).toBe(1);
+    expect(seen).toEqual(["write Chat", "write Join", "read Chat", "read Join"]);
+
+    remove();
+    await a.writePacket(chat);
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(chat.bytes);
+  });
+
+  test("should send nothing for a dropped write", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    a.intercept({ write: (packet) => codec.peekIndex(packet) !== codec.variantIndex("Chat") });
+
+    expect(await a.writePacket(chat)).toBe(0);
+    expect(await a.writePackets([chat, join])).toBe(wireSize(join));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(join.bytes);
+    expect(a.stats().packetsSent).toBe(1);
+  });
+
+  test("should intercept buffered packets in a batch read and defer an error", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    let reads = 0;
+    b.intercept({
+      read() {
+        if (++reads === 3) throw new Error("rate limited");
+      },
+    });
+
+    await a.writePackets([join, join, join, join]);
+    await sleep(10);
+    expect(await b.readPackets(10)).toHaveLength(2);
+    await expect(b.readPacket()).rejects.toThrow(ClavisError);
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(join.bytes);
+  });
+});
+
+describe("Packet views", () => {
+  test("should decrypt into a reused buffer", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    const first = new Uint8Array([1, 2, 3]);
+    const second = new Uint8Array([4, 5, 6]);
+    await a.writePackets([new RawPacket(first), new RawPacket(second), new RawPacket(new Uint8Array(200))]);
+
+    const view = await b.readPacketView();
+    expect(view).toEqual(first);
+    const next = await b.readPacketView();
+    expect(next).toEqual(second);
+    expect(view).toEqual(second);
+    expect(next.buffer).toBe(view.buffer);
+    expect(await b.readPacketView()).toEqual(new Uint8Array(200));
+  });
+
+  test("should fall back to allocating for compressed packets", async () => {
+    const options = { compression: { algorithms: ["deflate" as const], minSize: 0 } };
+    const [a, b] = await createEncryptedStreamPair(options, options);
+    const packet = new Uint8Array(512).fill(7);
+    await a.writePacket(new RawPacket(packet));
+    await a.writePacket(new RawPacket(packet));
+    const view = await b.readPacketView();
+    expect(await b.readPacketView()).toEqual(packet);
+    expect(view).toEqual(packet);
+  });
+});
+
+describe("Minimal-latency writes", () => {
+  test("should deliver packets written back to back intact and in order", async () => {
+    const [a, b] = await createEncryptedStreamPair({ ultraLowLatency: true }, { ultraLowLatency: true });
+    const writes = Array.from({ length: 200 }, (_, i) => a.writePacket(new RawPacket(new Uint8Array(i + 1).fill(i))));
+    expect(await Promise.all(writes)).toEqual(writes.map((_, i) => i + 1 + FRAME_OVERHEAD));
+    for (let i = 0; i < 200; i++) {
+      expect(await b.readPacketView()).toEqual(new Uint8Array(i + 1).fill(i));
+    }
+    expect(a.stats().packetsSent).toBe(200);
+  });
+
+  test("should refuse options its write path leaves out", () => {
+    expect(() => checkStreamOptions({ ultraLowLatency: true, writeTimeoutMs: 100 })).toThrow(ClavisError);
+    expect(() => checkStreamOptions({ ultraLowLatency: true, padding: {} })).toThrow(ClavisError);
+  });
+});
+
+describe("Buffered writes", () => {
+  test("should hold packets until flushed, then send them in order", async () => {
+    const [a, b] = await createEncryptedStreamPair();
+    await a.writePacketBuffered(new RawPacket(new Uint8Array([1])));
+    await a.feed([new RawPacket(new Uint8Array([2])), new RawPacket(new Uint8Array([3]))]);
+    expect(a.bufferedWriteBytes).toBe(3 * (1 + FRAME_OVERHEAD));
+    await sleep(10);
+    expect(await b.tryReadPacket()).toBeUndefined();
+
+    expect(await a.flush()).toBe(3 * (1 + FRAME_OVERHEAD));
+    expect(a.bufferedWriteBytes).toBe(0);
+    expect(await a.flush()).toBe(0);
+    for (const expected of [1, 2, 3]) {
+      expect(await b.readPacketView()).toEqual(new Uint8Array([expected]));
+    }
+
+    // An unbuffered write sends what is buffered ahead of its own packet
+    await a.writePacketBuffered(new RawPacket(new Uint8Array([4])));
+    expect(await a.writePacket(new RawPacket(new Uint8Array([5])))).toBe(2 * (1 + FRAME_OVERHEAD));
+    expect(await b.readPacketView()).toEqual(new Uint8Array([4]));
+    expect(await b.readPacketView()).toEqual(new Uint8Array([5]));
+  });
+
+  test("should flush on its own once maxBufferedBytes is reached", async () => {
+    const [a, b] = await createEncryptedStreamPair({ maxBufferedBytes: 4 * (32 + FRAME_OVERHEAD) });
+    await a.feed(Array.from({ length: 10 }, (_, i) => new RawPacket(new Uint8Array(32).fill(i))));
+    expect(a.bufferedWriteBytes).toBe(2 * (32 + FRAME_OVERHEAD));
+    for (let i = 0; i < 8; i++) {
+      expect(await b.readPacketView()).toEqual(new Uint8Array(32).fill(i));
+    }
+    expect(() => checkStreamOptions({ maxBufferedBytes: 0 })).toThrow(ClavisError);
+  });
+});
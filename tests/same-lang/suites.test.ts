@@ -0,0 +1,171 @@
+/**
+ * Cipher suite tests - AES-256-GCM frames, choosing a suite, and negotiated streams
+ */
+
+import { describe, test, expect } from "bun:test";
+import { createCipheriv, createDecipheriv } from "crypto";
+import {
+  AES_256_GCM,
+  Aes256GcmCipher,
+  chooseSuite,
+  decodeSuiteOffer,
+  encodeSuiteOffer,
+  type AeadSuite,
+} from "../../src/suites.js";
+import { XChaCha20Poly1305Cipher, type FrameCipher } from "../../src/crypto.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError, CryptoOperation } from "../../src/error.js";
+import { checkStreamOptions } from "../../src/stream.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const AES_ONLY = ["aes-256-gcm"] as const;
+
+/** AES-128-GCM with 12-byte IVs, standing in for a national-standard AEAD */
+class Aes128GcmCipher implements FrameCipher {
+  constructor(private readonly key: Uint8Array) {}
+
+  encrypt(nonce: Uint8Array, plaintext: Uint8Array, aad?: Uint8Array): Uint8Array {
+    const cipher = createCipheriv("aes-128-gcm", this.key, nonce.subarray(0, 12));
+    if (aad) cipher.setAAD(aad);
+    return new Uint8Array(Buffer.concat([cipher.update(plaintext), cipher.final(), cipher.getAuthTag()]));
+  }
+
+  decrypt(nonce: Uint8Array, ciphertext: Uint8Array, aad?: Uint8Array): Uint8Array {
+    try {
+      const decipher = createDecipheriv("aes-128-gcm", this.key, nonce.subarray(0, 12));
+      decipher.setAuthTag(ciphertext.subarray(ciphertext.length - 16));
+      if (aad) decipher.setAAD(aad);
+      return new Uint8Array(Buffer.concat([decipher.update(ciphertext.subarray(0, ciphertext.length - 16)), decipher.final()]));
+    } catch {
+      throw ClavisError.cryptoFailure(CryptoOperation.Decryption, "Decryption failed");
+    }
+  }
+}
+
+const AES_128_GCM: AeadSuite = {
+  name: "aes-128-gcm",
+  id: 200,
+  keyLength: 16,
+  nonces: "sequence",
+  cipher: (key) => new Aes128GcmCipher(key),
+};
+
+describe("Aes256GcmCipher", () => {
+  test("should round-trip and bind the associated data", () => {
+    const cipher = new Aes256GcmCipher(new Uint8Array(32).fill(1));
+    const nonce = XChaCha20Poly1305Cipher.generateNonce();
+    const aad = new Uint8Array([9]);
+    const sealed = cipher.encrypt(nonce, new Uint8Array([1, 2, 3]), aad);
+    expect(sealed).toHaveLength(3 + 16);
+    expect(cipher.decrypt(nonce, sealed, aad)).toEqual(new Uint8Array([1, 2, 3]));
+    expect(() => cipher.decrypt(nonce, sealed)).toThrow(ClavisError);
+
+    sealed[0] = sealed[0]! ^ 1;
+    expect(() => cipher.decrypt(nonce, sealed, aad)).toThrow(ClavisError);
+    expect(() => new Aes256GcmCipher(new Uint8Array(16))).toThrow(ClavisError);
+  });
+});
+
+describe("chooseSuite", () => {
+  test("should pick the best combined rank, ties going to the initiator", () => {
+    expect(chooseSuite(["aes-256-gcm", "xchacha20-poly1305"], ["xchacha20-poly1305"], true)).toBe("xchacha20-poly1305");
+    expect(chooseSuite(["aes-256-gcm", "xchacha20-poly1305"], ["xchacha20-poly1305", "aes-256-gcm"], true)).toBe("aes-256-gcm");
+    expect(chooseSuite(["aes-256-gcm", "xchacha20-poly1305"], ["xchacha20-poly1305", "aes-256-gcm"], false)).toBe("xchacha20-poly1305");
+    expect(() => chooseSuite(["aes-256-gcm"], ["xchacha20-poly1305"], true)).toThrow("No cipher suite in common");
+  });
+
+  test("should skip unknown suites in an offer", () => {
+    const offer = encodeSuiteOffer(["aes-256-gcm"]);
+    expect(decodeSuiteOffer(new Uint8Array([...offer.subarray(0, 4), 2, 99, 2]))).toEqual(["aes-256-gcm"]);
+    expect(() => decodeSuiteOffer(new Uint8Array([1, 2, 3]))).toThrow(ClavisError);
+  });
+});
+
+describe("Negotiated streams", () => {
+  test("should carry packets and rekey under AES-256-GCM", async () => {
+    const [a, b] = await createEncryptedStreamPair(
+      { cipherSuites: ["aes-256-gcm", "xchacha20-poly1305"] },
+      { cipherSuites: AES_ONLY }
+    );
+    expect(a.cipherSuite).toBe("aes-256-gcm");
+    expect(b.cipherSuite).toBe("aes-256-gcm");
+
+    const answer = a.readPacket();
+    await a.writePacket(new RawPacket(new Uint8Array([1])));
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+
+    const reading = b.readPacket();
+    const rolled = a.rekey();
+    await a.writePacket(new RawPacket(new Uint8Array([2])));
+    expect((await reading) as unknown as Uint8Array).toEqual(new Uint8Array([2]));
+    await rolled;
+    await b.writePacket(new RawPacket(new Uint8Array([3])));
+    expect((await answer) as unknown as Uint8Array).toEqual(new Uint8Array([3]));
+  });
+
+  test("should keep XChaCha20-Poly1305 without cipherSuites and refuse peers with nothing in common", async () => {
+    const [a] = await createEncryptedStreamPair();
+    expect(a.cipherSuite).toBe("xchacha20-poly1305");
+
+    const error = await createEncryptedStreamPair(
+      { cipherSuites: AES_ONLY },
+      { cipherSuites: ["xchacha20-poly1305"] }
+    ).catch((error) => error);
+    expect((error as ClavisError).message).toContain("No cipher suite in common");
+    await expect(createEncryptedStreamPair({ cipherSuites: [] })).rejects.toThrow("at least one suite");
+  });
+});
+
+describe("Custom AEAD suites", () => {
+  test("should negotiate, carry packets and rekey under a custom suite", async () => {
+    const [a, b] = await createEncryptedStreamPair(
+      { cipherSuites: [AES_128_GCM, "xchacha20-poly1305"], sequenceNumbers: true },
+      { cipherSuites: ["aes-256-gcm", AES_128_GCM], sequenceNumbers: true }
+    );
+    expect(a.cipherSuite).toBe("aes-128-gcm");
+    expect(b.cipherSuite).toBe("aes-128-gcm");
+
+    const answer = a.readPacket();
+    const reading = b.readPacket();
+    const rolled = a.rekey();
+    await a.writePacket(new RawPacket(new Uint8Array([1])));
+    expect((await reading) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+    await rolled;
+    await b.writePacket(new RawPacket(new Uint8Array([2])));
+    expect((await answer) as unknown as Uint8Array).toEqual(new Uint8Array([2]));
+
+    // A peer that doesn't list it falls back to a suite both know
+    const [c] = await createEncryptedStreamPair(
+      { cipherSuites: [AES_128_GCM, "xchacha20-poly1305"], sequenceNumbers: true },
+      { cipherSuites: ["xchacha20-poly1305"], sequenceNumbers: true }
+    );
+    expect(c.cipherSuite).toBe("xchacha20-poly1305");
+  });
+
+  test("should run the built-in suites through the same interface", () => {
+    const key = new Uint8Array(32).fill(3);
+    const nonce = XChaCha20Poly1305Cipher.generateNonce();
+    const sealed = AES_256_GCM.cipher(key).encrypt(nonce, new Uint8Array([1, 2]));
+    expect(new Aes256GcmCipher(key).decrypt(nonce, sealed)).toEqual(new Uint8Array([1, 2]));
+    expect(decodeSuiteOffer(encodeSuiteOffer([AES_128_GCM, AES_256_GCM]), [AES_128_GCM])).toEqual([AES_128_GCM, "aes-256-gcm"]);
+    expect(chooseSuite([AES_128_GCM, AES_256_GCM], [AES_256_GCM], true)).toBe(AES_256_GCM);
+  });
+
+  test("should refuse suites that could clash or reuse nonces", () => {
+    const conflicts = (suite: AeadSuite, sequenceNumbers = true) => {
+      try {
+        checkStreamOptions({ cipherSuites: [suite], sequenceNumbers });
+        return [];
+      } catch (error) {
+        return (error as ClavisError).configConflicts?.map((c) => c.message) ?? [];
+      }
+    };
+    expect(conflicts(AES_128_GCM)).toEqual([]);
+    expect(conflicts({ ...AES_128_GCM, id: 2 })).toEqual(["Cipher suite aes-128-gcm needs an id from 128 to 255"]);
+    expect(conflicts({ ...AES_128_GCM, keyLength: 8 })).toEqual(["Cipher suite aes-128-gcm needs a key length from 16 to 64 bytes"]);
+    expect(conflicts(AES_128_GCM, false)).toEqual([
+      "Cipher suite aes-128-gcm takes sequence nonces, so it needs sequenceNumbers",
+    ]);
+    expect(conflicts({ ...AES_128_GCM, name: "aes-256-gcm" })).toEqual(["Custom cipher suites need their own ids and names"]);
+  });
+});
@@ -0,0 +1,201 @@
+/**
+ * Chaos transport tests - latency, jitter and bandwidth shaping, in-memory pairs with frame corruption, and the read-path attack battery
+ */
+
+import { describe, test, expect } from "bun:test";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ManualClock } from "../../src/clock.js";
+import { ClavisError } from "../../src/error.js";
+import { corruptionKind, type CorruptionKind } from "../../src/corruption.js";
+import {
+  LinkShaper,
+  LinkProfiles,
+  createChaosPair,
+  createEncryptedPair,
+  createMemoryPair,
+  runReadPathBattery,
+  seededRandom,
+} from "../../src/testing.js";
+
+function schedules(shaper: LinkShaper, count: number, bytes = 1000) {
+  return Array.from({ length: count }, (_, i) => shaper.schedule(i * 10, bytes));
+}
+
+describe("LinkShaper", () => {
+  test("should be deterministic for a given seed", () => {
+    const a = schedules(new LinkShaper({ ...LinkProfiles.threeG, seed: 42 }), 50);
+    const b = schedules(new LinkShaper({ ...LinkProfiles.threeG, seed: 42 }), 50);
+    const c = schedules(new LinkShaper({ ...LinkProfiles.threeG, seed: 43 }), 50);
+
+    expect(a).toEqual(b);
+    expect(a).not.toEqual(c);
+    expect(seededRandom(7)()).toBe(seededRandom(7)());
+  });
+
+  test("should add base latency and keep chunks in order", () => {
+    const shaper = new LinkShaper({ latencyMs: 100, jitter: { kind: "uniform", maxMs: 80 } });
+    const result = schedules(shaper, 100);
+
+    for (let i = 0; i < result.length; i++) {
+      const { departAt, arriveAt } = result[i]!;
+      expect(arriveAt).toBeGreaterThanOrEqual(departAt + 100);
+      if (i > 0) expect(arriveAt).toBeGreaterThanOrEqual(result[i - 1]!.arriveAt);
+    }
+  });
+
+  test("should limit throughput to the token bucket rate", () => {
+    const shaper = new LinkShaper({ bandwidthBytesPerSec: 10_000, burstBytes: 1_000 });
+
+    // The burst leaves immediately, the rest waits for refills
+    expect(shaper.schedule(0, 1_000).departAt).toBe(0);
+    expect(shaper.schedule(0, 1_000).departAt).toBeCloseTo(100);
+    expect(shaper.schedule(0, 5_000).departAt).toBeCloseTo(600);
+
+    // After idling, the bucket refills up to the burst size only
+    const idle = shaper.schedule(10_000, 2_000);
+    expect(idle.departAt).toBeCloseTo(10_100);
+  });
+
+  test("should not delay anything without a profile", () => {
+    const shaper = new LinkShaper();
+    expect(shaper.schedule(5, 1_000_000)).toEqual({ departAt: 5, arriveAt: 5 });
+  });
+});
+
+describe("createChaosPair", () => {
+  test("should deliver data after the configured latency", async () => {
+    const clock = new ManualClock(0);
+    const [a, b] = createChaosPair({ latencyMs: 50 }, undefined, clock);
+    const arrivals: number[] = [];
+    b.on("data", () => arrivals.push(clock.now()));
+    a.write(Buffer.from("ping"));
+
+    await clock.advance(49);
+    expect(arrivals).toEqual([]);
+    await clock.advance(1);
+    expect(arrivals).toEqual([50]);
+  });
+
+  test("should shape each direction independently", async () => {
+    const clock = new ManualClock(0);
+    const [a, b] = createChaosPair({ latencyMs: 0 }, { latencyMs: 60 }, clock);
+    const arrivals: string[] = [];
+    b.on("data", () => arrivals.push(`a->b at ${clock.now()}`));
+    a.on("data", () => arrivals.push(`b->a at ${clock.now()}`));
+    a.write(Buffer.from("a"));
+    b.write(Buffer.from("b"));
+
+    await clock.advance(59);
+    expect(arrivals).toEqual(["a->b at 0"]);
+    await clock.advance(1);
+    expect(arrivals).toEqual(["a->b at 0", "b->a at 60"]);
+  });
+
+  test("should end the peer after in-flight data arrives", async () => {
+    const [a, b] = createChaosPair({ latencyMs: 20 });
+    const chunks: string[] = [];
+    b.on("data", (chunk: Buffer) => chunks.push(chunk.toString()));
+    const ended = new Promise<void>((resolve) => b.once("end", resolve));
+
+    a.write(Buffer.from("last"));
+    a.end();
+    await ended;
+    expect(chunks).toEqual(["last"]);
+  });
+
+  test("should carry an encrypted session over a slow link", async () => {
+    const [a, b] = createChaosPair({
+      latencyMs: 20,
+      jitter: { kind: "normal", stddevMs: 5 },
+      bandwidthBytesPerSec: 200_000,
+      burstBytes: 4_096,
+      seed: 3,
+    });
+    const [client, server] = await Promise.all([EncryptedStream.new(a), EncryptedStream.new(b)]);
+
+    const data = new Uint8Array(8_192).fill(9);
+    await client.writePacket(new RawPacket(data));
+    const received = (await server.readPacket()) as unknown as Uint8Array;
+    expect(received).toEqual(data);
+  });
+});
+
+describe("createEncryptedPair", () => {
+  test("should carry packets through reads of a few bytes", async () => {
+    const { a, b } = await createEncryptedPair({}, {}, { maxReadBytes: 3 });
+    const data = new Uint8Array(1_000).map((_, i) => i);
+    await a.writePacket(new RawPacket(data));
+    await b.writePacket(new RawPacket(data.subarray(0, 10)));
+
+    expect((await b.readPacket()) as unknown as Uint8Array).toEqual(data);
+    expect((await a.readPacket()) as unknown as Uint8Array).toEqual(data.subarray(0, 10));
+  });
+
+  test("should corrupt frames the way the read path classifies them", async () => {
+    for (const kind of ["macFailure", "framing", "truncated"] as CorruptionKind[]) {
+      const { a, b, aSocket } = await createEncryptedPair();
+      await a.writePacket(new RawPacket(new Uint8Array([1])));
+      aSocket.corruptNextFrame(kind);
+      await a.writePacket(new RawPacket(new Uint8Array([2])));
+
+      expect(Array.from((await b.readPacket()) as unknown as Uint8Array)).toEqual([1]);
+      const error = await b.readPacket().catch((e: unknown) => e);
+      expect(corruptionKind(error)).toBe(kind);
+    }
+  });
+
+  test("should deliver injected bytes and reject bad read sizes", async () => {
+    const [a, b] = createMemoryPair();
+    const received = new Promise<Buffer>((resolve) => b.once("data", resolve));
+    a.inject(new Uint8Array([4, 2]));
+    expect(Array.from(await received)).toEqual([4, 2]);
+    expect(() => createMemoryPair({ maxReadBytes: 0 })).toThrow(ClavisError);
+  });
+});
+
+describe("runReadPathBattery", () => {
+  test("should pass a server that puts a deadline on handshakes", async () => {
+    const clock = new ManualClock();
+    const streamOptions: EncryptedStreamOptions = { clock, compression: { algorithms: ["deflate"] } };
+    const results = await runReadPathBattery({
+      streamOptions,
+      clock,
+      serve: (socket) => {
+        const deadline = clock.setTimer(() => socket.destroy(), 2000);
+        return EncryptedStream.new(socket, streamOptions).finally(() => deadline.cancel());
+      },
+    });
+
+    expect(results.map((result) => [result.attack, result.passed])).toEqual([
+      ["slowlorisHandshake", true],
+      ["dripFeed", true],
+      ["hugeLength", true],
+      ["zipBomb", true],
+    ]);
+    expect(results[0]!.elapsedMs).toBe(2000);
+    expect(results[2]!.detail).toContain("exceeds maximum");
+  });
+
+  test("should pass slowloris against a handshake timeout", async () => {
+    const clock = new ManualClock();
+    const [slowloris] = await runReadPathBattery({
+      streamOptions: { clock, handshakeTimeoutMs: 3000 },
+      clock,
+      attacks: ["slowlorisHandshake"],
+    });
+    expect(slowloris).toMatchObject({ passed: true, elapsedMs: 3000 });
+  });
+
+  test("should flag handshakes without a deadline and skip the zip bomb without compression", async () => {
+    const clock = new ManualClock();
+    const [slowloris, zipBomb] = await runReadPathBattery({
+      streamOptions: { clock },
+      clock,
+      attacks: ["slowlorisHandshake", "zipBomb"],
+    });
+
+    expect(slowloris).toMatchObject({ passed: false, skipped: false, elapsedMs: 5000 });
+    expect(zipBomb).toMatchObject({ passed: false, skipped: true });
+  });
+});
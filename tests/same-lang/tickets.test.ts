@@ -0,0 +1,67 @@
+/**
+ * Ticket key tests - sealing, rotation schedules and fleet distribution
+ */
+
+import { describe, test, expect } from "bun:test";
+import {
+  TicketKeyring,
+  createTicketKeySchedule,
+  decodeTicketKeys,
+  encodeTicketKeys,
+} from "../../src/tickets.js";
+import { ManualClock } from "../../src/clock.js";
+import { ClavisError } from "../../src/error.js";
+
+const HOUR = 60 * 60 * 1000;
+const state = new TextEncoder().encode("session state");
+
+describe("TicketKeyring", () => {
+  test("should open what it sealed and refuse tampering", () => {
+    const keyring = new TicketKeyring({ clock: new ManualClock(Date.UTC(2026, 0, 1)) });
+    const ticket = keyring.seal(state);
+    expect(keyring.open(ticket)).toEqual(state);
+
+    const tampered = ticket.slice();
+    tampered[tampered.length - 1] = tampered[tampered.length - 1]! ^ 1;
+    expect(() => keyring.open(tampered)).toThrow(ClavisError);
+    expect(() => new TicketKeyring().open(ticket)).toThrow(ClavisError);
+  });
+
+  test("should seal with new keys while still opening old tickets", async () => {
+    const clock = new ManualClock(Date.UTC(2026, 0, 1));
+    const keyring = new TicketKeyring({ clock, rotationIntervalMs: HOUR, ticketLifetimeMs: 2 * HOUR, clockSkewMs: 0 });
+    const first = keyring.currentKey.id;
+    const old = keyring.seal(state);
+
+    await clock.advance(HOUR);
+    expect(keyring.currentKey.id).not.toBe(first);
+    expect(keyring.open(old)).toEqual(state);
+
+    await clock.advance(HOUR);
+    expect(() => keyring.open(old)).toThrow(ClavisError);
+    await clock.advance(HOUR);
+    expect(keyring.keys.map((key) => key.id)).not.toContain(first);
+  });
+
+  test("should let a fleet share one schedule", async () => {
+    const start = Date.UTC(2026, 0, 1);
+    const schedule = createTicketKeySchedule({ start, count: 3, rotationIntervalMs: HOUR, ticketLifetimeMs: HOUR });
+    const shipped = decodeTicketKeys(encodeTicketKeys(schedule));
+    expect(shipped).toEqual(schedule);
+
+    const clock = new ManualClock(start);
+    const a = new TicketKeyring({ clock, autoRotate: false }, schedule);
+    const b = new TicketKeyring({ clock, autoRotate: false }, shipped);
+    expect(b.open(a.seal(state))).toEqual(state);
+
+    await clock.advance(HOUR);
+    expect(a.currentKey.id).toBe(schedule[1]!.id);
+    expect(b.currentKey.id).toBe(schedule[1]!.id);
+
+    b.remove(schedule[1]!.id);
+    expect(() => b.open(a.seal(state))).toThrow(ClavisError);
+
+    await clock.advance(3 * HOUR);
+    expect(() => a.currentKey).toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,49 @@
+/**
+ * Clock offset tests - NTP-style probes over control frames
+ */
+
+import { describe, test, expect } from "bun:test";
+import { ClockOffsetEstimator } from "../../src/time-sync.js";
+import { ClavisError } from "../../src/error.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+describe("ClockOffsetEstimator", () => {
+  test("should compute offset and delay from the four timestamps", () => {
+    const estimator = new ClockOffsetEstimator();
+    // Peer is 500ms ahead, 10ms each way, 2ms turnaround
+    const sample = estimator.add(1_000, 1_510, 1_512, 1_022);
+    expect(sample.offsetMs).toBe(500);
+    expect(sample.delayMs).toBe(20);
+    expect(estimator.estimate).toEqual({ offsetMs: 500, oneWayDelayMs: 10, uncertaintyMs: 10, samples: 1 });
+  });
+
+  test("should trust the lowest-delay recent sample", () => {
+    const estimator = new ClockOffsetEstimator(2);
+    estimator.add(0, 505, 505, 10); // 10ms round trip
+    estimator.add(100, 650, 650, 300); // congested: 200ms, skewed
+    expect(estimator.estimate!.offsetMs).toBe(500);
+
+    // The good sample falls out of the window
+    estimator.add(400, 960, 960, 500);
+    expect(estimator.estimate!.offsetMs).toBe(510);
+    expect(estimator.estimate!.samples).toBe(2);
+  });
+});
+
+describe("measureClockOffset", () => {
+  test("should estimate a near-zero offset between local peers", async () => {
+    const [a, b] = await createEncryptedStreamPair({ timeSync: true }, { timeSync: true });
+    void a.readPacket().catch(() => {});
+    void b.readPacket().catch(() => {});
+
+    expect(a.clockOffset).toBeUndefined();
+    const sample = await a.measureClockOffset();
+    expect(Math.abs(sample.offsetMs)).toBeLessThan(50);
+    expect(a.clockOffset!.samples).toBe(1);
+  });
+
+  test("should require time sync to be enabled", async () => {
+    const [a] = await createEncryptedStreamPair();
+    await expect(a.measureClockOffset()).rejects.toThrow(ClavisError);
+  });
+});
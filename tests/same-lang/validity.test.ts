@@ -0,0 +1,51 @@
+/**
+ * Validity period tests - telling expiry, early use and clock skew apart
+ */
+
+import { describe, test, expect } from "bun:test";
+import { checkValidityPeriod } from "../../src/validity.js";
+import { ClavisError, ValidityFailure } from "../../src/error.js";
+import { TicketKeyring } from "../../src/tickets.js";
+import { ManualClock } from "../../src/clock.js";
+
+const MINUTE = 60 * 1000;
+const DAY = 24 * 60 * MINUTE;
+const now = Date.UTC(2026, 0, 1);
+
+function failure(check: () => void): ValidityFailure | undefined {
+  try {
+    check();
+  } catch (error) {
+    expect(error).toBeInstanceOf(ClavisError);
+    return (error as ClavisError).validityFailure;
+  }
+  return undefined;
+}
+
+describe("checkValidityPeriod", () => {
+  test("should say which way a period was missed", () => {
+    expect(failure(() => checkValidityPeriod("Token", now - DAY, now, now))).toBe(ValidityFailure.Expired);
+    expect(failure(() => checkValidityPeriod("Token", now + MINUTE, now + DAY, now))).toBe(ValidityFailure.SkewSuspected);
+    expect(failure(() => checkValidityPeriod("Token", now + 2 * DAY, now + 3 * DAY, now))).toBe(ValidityFailure.NotYetValid);
+    expect(failure(() => checkValidityPeriod("Token", undefined, now + 1, now))).toBeUndefined();
+  });
+
+  test("should allow the configured skew on both ends", () => {
+    expect(failure(() => checkValidityPeriod("Token", now + MINUTE, now + DAY, now, 2 * MINUTE))).toBeUndefined();
+    expect(failure(() => checkValidityPeriod("Token", now - DAY, now - MINUTE, now, 2 * MINUTE))).toBeUndefined();
+    expect(failure(() => checkValidityPeriod("Token", now - DAY, now - 2 * MINUTE, now, 2 * MINUTE)))
+      .toBe(ValidityFailure.Expired);
+  });
+});
+
+describe("Ticket lifetimes", () => {
+  test("should flag tickets from a server whose clock runs ahead", () => {
+    const ahead = new TicketKeyring({ clock: new ManualClock(now + 10 * MINUTE) });
+    const ticket = ahead.seal(new Uint8Array([1]));
+    const behind = new TicketKeyring({ clock: new ManualClock(now) }, ahead.keys);
+    expect(failure(() => behind.open(ticket))).toBe(ValidityFailure.SkewSuspected);
+
+    const tolerant = new TicketKeyring({ clock: new ManualClock(now), clockSkewMs: 15 * MINUTE }, ahead.keys);
+    expect(tolerant.open(ticket)).toEqual(new Uint8Array([1]));
+  });
+});
@@ -0,0 +1,126 @@
+/**
+ * Protocol version tests - packet layout, acceptance and the setup exchange
+ */
+
+import { describe, test, expect } from "bun:test";
+import {
+  acceptsVersion,
+  decodeVersionOffer,
+  decodeVersionVerdict,
+  encodeVersionOffer,
+  encodeVersionVerdict,
+  normalizeVersionOptions,
+} from "../../src/versioning.js";
+import { EncryptedStream, type EncryptedStreamOptions } from "../../src/stream.js";
+import { RawPacket, createProtocolCodec } from "../../src/protocol.js";
+import { ClavisError, StreamError, StreamErrorCode } from "../../src/error.js";
+import { createEncryptedStreamPair, createStreamPair } from "../helpers/test-utils.js";
+
+const V3 = createProtocolCodec(["Hello", "Move"] as const, { version: 3 });
+
+async function connect(a: EncryptedStreamOptions, b: EncryptedStreamOptions) {
+  const [left, right] = await createStreamPair();
+  return Promise.allSettled([EncryptedStream.new(left, a), EncryptedStream.new(right, b)]);
+}
+
+function codeOf(result: PromiseSettledResult<EncryptedStream>): StreamErrorCode | undefined {
+  if (result.status === "fulfilled") return undefined;
+  const cause = (result.reason as ClavisError).cause;
+  return cause instanceof StreamError ? cause.code : undefined;
+}
+
+describe("Version packets", () => {
+  test("should encode offers and verdicts", () => {
+    expect(encodeVersionOffer(3)).toEqual(new Uint8Array([0x43, 0x4c, 0x56, 0x56, 0, 3, 0, 0, 0]));
+    expect(decodeVersionOffer(encodeVersionOffer(70000))).toBe(70000);
+    expect(decodeVersionVerdict(encodeVersionVerdict(true, 3))).toBe(true);
+    expect(decodeVersionVerdict(encodeVersionVerdict(false, 3))).toBe(false);
+    expect(() => decodeVersionOffer(encodeVersionVerdict(true, 3))).toThrow(ClavisError);
+    expect(() => decodeVersionOffer(new Uint8Array([0, 0, 0, 0, 1]))).toThrow("did not negotiate");
+  });
+
+  test("should accept ranges, predicates and the exact version by default", () => {
+    expect(acceptsVersion({ min: 2, max: 3 }, 2)).toBe(true);
+    expect(acceptsVersion({ min: 2, max: 3 }, 4)).toBe(false);
+    expect(acceptsVersion((v) => v % 2 === 1, 3)).toBe(true);
+    const { version, accept } = normalizeVersionOptions(V3);
+    expect(version).toBe(3);
+    expect(acceptsVersion(accept, 2)).toBe(false);
+    expect(() => normalizeVersionOptions(createProtocolCodec(["A"] as const))).toThrow(ClavisError);
+    expect(() => createProtocolCodec(["A"] as const, { version: -1 })).toThrow(ClavisError);
+  });
+});
+
+describe("Version negotiation", () => {
+  test("should connect peers that accept each other and report the peer's version", async () => {
+    const [a, b] = await createEncryptedStreamPair(
+      { protocolVersion: { version: 3, accept: { min: 2, max: 3 } } },
+      { protocolVersion: 2 }
+    );
+    expect(a.peerProtocolVersion).toBe(2);
+    expect(b.peerProtocolVersion).toBe(3);
+  });
+
+  test("should fail both sides with VersionMismatch when one refuses", async () => {
+    const [v2, v3] = await connect({ protocolVersion: 2 }, { protocolVersion: V3 });
+    expect(codeOf(v2)).toBe(StreamErrorCode.VersionMismatch);
+    expect(codeOf(v3)).toBe(StreamErrorCode.VersionMismatch);
+    expect((v2 as PromiseRejectedResult).reason.message).toContain("this side speaks 2, the peer speaks 3");
+  });
+});
+
+describe("Protocol upgrades", () => {
+  const raw = (...bytes: number[]) => new RawPacket(new Uint8Array(bytes));
+  const read = async (stream: EncryptedStream) => Array.from((await stream.readPacket()) as unknown as Uint8Array);
+  const v3to4 = { version: 3, accept: { min: 3, max: 4 } };
+
+  test("should switch both directions after the packets already in flight", async () => {
+    const upgraded: number[] = [];
+    const [a, b] = await createEncryptedStreamPair(
+      { protocolVersion: v3to4 },
+      { protocolVersion: { ...v3to4, onUpgrade: (version) => upgraded.push(version) } }
+    );
+    await b.writePacket(raw(1));
+    await a.writePacket(raw(2));
+    const upgrade = a.upgradeProtocol(4);
+
+    // Each side still reads the old version up to the other's switch
+    expect(await read(b)).toEqual([2]);
+    expect(b.peerProtocolVersion).toBe(3);
+    expect(await read(a)).toEqual([1]);
+    expect(a.peerProtocolVersion).toBe(3);
+
+    const bRead = read(b);
+    const aRead = read(a);
+    await upgrade;
+    expect([a.protocolVersion, a.peerProtocolVersion, b.protocolVersion]).toEqual([4, 4, 4]);
+    expect(upgraded).toEqual([4]);
+
+    await a.writePacket(raw(3));
+    expect(await bRead).toEqual([3]);
+    expect(b.peerProtocolVersion).toBe(4);
+    await b.writePacket(raw(4));
+    expect(await aRead).toEqual([4]);
+  });
+
+  test("should stay on the old version when the peer refuses or requests cross", async () => {
+    const [a, b] = await createEncryptedStreamPair({ protocolVersion: v3to4 }, { protocolVersion: 3 });
+    const pending = b.readPacket();
+    const refused = await a.upgradeProtocol(4).catch((error) => error);
+    expect(refused).toBeInstanceOf(ClavisError);
+    expect(((refused as ClavisError).cause as StreamError).code).toBe(StreamErrorCode.VersionMismatch);
+    expect([a.protocolVersion, b.protocolVersion]).toEqual([3, 3]);
+    await a.writePacket(raw(1));
+    expect(Array.from((await pending) as unknown as Uint8Array)).toEqual([1]);
+
+    const [c, d] = await createEncryptedStreamPair({ protocolVersion: v3to4 }, { protocolVersion: v3to4 });
+    c.readPacket().catch(() => undefined);
+    d.readPacket().catch(() => undefined);
+    const crossed = await Promise.allSettled([c.upgradeProtocol(4), d.upgradeProtocol(4)]);
+    expect(crossed.map((result) => result.status)).toEqual(["rejected", "rejected"]);
+    expect([c.protocolVersion, d.peerProtocolVersion]).toEqual([3, 3]);
+
+    const [plain] = await createEncryptedStreamPair();
+    await expect(plain.upgradeProtocol(2)).rejects.toThrow("need the protocolVersion option");
+  });
+});
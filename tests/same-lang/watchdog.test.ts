@@ -0,0 +1,94 @@
+/**
+ * Stall watchdog tests - reads nobody takes and writes the transport doesn't finish
+ */
+
+import { describe, test, expect } from "bun:test";
+import { createEncryptedStreamPair, createStreamPair, sleep } from "../helpers/test-utils.js";
+import { EncryptedStream, checkStreamOptions } from "../../src/stream.js";
+import { StallWatchdog, type StallReport, type StallState } from "../../src/watchdog.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+import { ManualClock } from "../../src/clock.js";
+
+describe("StallWatchdog", () => {
+  test("should report packets nobody reads once per stall", async () => {
+    const clock = new ManualClock();
+    const reports: StallReport[] = [];
+    const [a, b] = await createEncryptedStreamPair({
+      clock,
+      connectionId: "conn-7",
+      watchdog: { thresholdMs: 1_000, onStall: (report) => reports.push(report) },
+    });
+
+    await b.writePacket(new RawPacket(new Uint8Array([1])));
+    await sleep(20);
+    await clock.advance(1_000);
+    expect(reports).toEqual([]);
+    await clock.advance(1_000);
+    expect(reports).toHaveLength(1);
+    expect(reports[0]).toMatchObject({
+      connectionId: "conn-7",
+      direction: "read",
+      state: { packetsReceived: 0, reading: false, pendingWrites: 0, peerClosed: false },
+    });
+    expect(reports[0]!.stalledMs).toBeGreaterThanOrEqual(1_000);
+    expect(reports[0]!.state.unreadBytes).toBeGreaterThan(0);
+
+    // Still stalled: not reported again
+    await clock.advance(5_000);
+    expect(reports).toHaveLength(1);
+
+    // Reading clears the stall; the next one is reported anew
+    expect((await a.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1]));
+    await clock.advance(1_000);
+    await b.writePacket(new RawPacket(new Uint8Array([2])));
+    await sleep(20);
+    await clock.advance(2_000);
+    expect(reports.map((r) => r.direction)).toEqual(["read", "read"]);
+    expect(reports[1]!.state.packetsReceived).toBe(1);
+
+    // A read waiting for data is not a stall
+    const pending = a.readPacket();
+    await clock.advance(5_000);
+    expect(reports).toHaveLength(2);
+    await b.writePacket(new RawPacket(new Uint8Array([3])));
+    expect((await pending) as unknown as Uint8Array).toEqual(new Uint8Array([3]));
+  });
+
+  test("should report a write the transport doesn't finish and stop with the transport", async () => {
+    const clock = new ManualClock();
+    const reports: StallReport[] = [];
+    const writes: number[] = [];
+    const state = {} as StallState;
+    const watchdog = new StallWatchdog("conn-1", { thresholdMs: 100, onStall: (report) => reports.push(report) }, clock, {
+      readWaiting: () => false,
+      oldestWriteAt: () => writes[0],
+      state: () => state,
+    });
+    watchdog.start();
+
+    writes.push(clock.now());
+    await clock.advance(150);
+    expect(reports).toEqual([{ connectionId: "conn-1", direction: "write", stalledMs: 100, state }]);
+    writes.shift();
+    await clock.advance(100);
+    writes.push(clock.now());
+    await clock.advance(100);
+    expect(reports.map((r) => r.stalledMs)).toEqual([100, 100]);
+
+    watchdog.stop();
+    expect(clock.pendingTimers).toBe(0);
+
+    const [left, right] = await createStreamPair();
+    const [a] = await Promise.all([
+      EncryptedStream.new(left, { clock, watchdog: { thresholdMs: 100, onStall: () => undefined } }),
+      EncryptedStream.new(right),
+    ]);
+    expect(clock.pendingTimers).toBe(1);
+    left.destroy();
+    await sleep(20);
+    expect(clock.pendingTimers).toBe(0);
+    await a.close().catch(() => undefined);
+    expect(() => checkStreamOptions({ watchdog: { thresholdMs: 0, onStall: () => undefined } })).toThrow(ClavisError);
+  });
+});
@@ -0,0 +1,79 @@
+/**
+ * Web transport tests - a WebClient against a stream server and over web streams
+ */
+
+import { describe, test, expect } from "bun:test";
+import { WebClient, transportStream, webStreamTransport, type ByteTransport } from "../../src/web.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+import { ClavisError } from "../../src/error.js";
+
+/** Two connected in-memory transports; everything arrives on a later tick, in order */
+function transportPair(): [ByteTransport, ByteTransport] {
+  const handlers: Array<{ bytes?: (bytes: Uint8Array) => void; close?: (error?: Error) => void }> = [{}, {}];
+  const end = (self: 0 | 1): ByteTransport => {
+    const peer = handlers[1 - self]!;
+    return {
+      send(bytes) {
+        const copy = bytes.slice();
+        setTimeout(() => peer.bytes?.(copy), 0);
+      },
+      onBytes(handler) {
+        handlers[self]!.bytes = handler;
+      },
+      onClose(handler) {
+        handlers[self]!.close = handler;
+      },
+      close() {
+        setTimeout(() => peer.close?.(), 0);
+      },
+    };
+  };
+  return [end(0), end(1)];
+}
+
+const psk = new Uint8Array(32).fill(9);
+
+describe("WebClient", () => {
+  test("should talk to an EncryptedStream server through transportStream", async () => {
+    const [clientSide, serverSide] = transportPair();
+    const [client, server] = await Promise.all([
+      WebClient.connect(clientSide, { psk }),
+      transportStream(serverSide).then((duplex) => EncryptedStream.new(duplex, { psk })),
+    ]);
+    expect(client.handshakeTimings.totalMs).toBeGreaterThanOrEqual(0);
+
+    await client.send(new RawPacket(new Uint8Array([1, 2])));
+    expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([1, 2]));
+    await server.writePacket(new RawPacket(new Uint8Array([3])));
+    await server.writePacket(new RawPacket(new Uint8Array([4])));
+    await server.close();
+
+    const received: Uint8Array[] = [];
+    for await (const packet of client) received.push(packet);
+    expect(received).toEqual([new Uint8Array([3]), new Uint8Array([4])]);
+    await expect(client.send(new RawPacket(new Uint8Array([5])))).rejects.toThrow(ClavisError);
+  });
+
+  test("should connect two clients over a pair of web streams", async () => {
+    const toB = new TransformStream<Uint8Array, Uint8Array>();
+    const toA = new TransformStream<Uint8Array, Uint8Array>();
+    const [a, b] = await Promise.all([
+      WebClient.connect(webStreamTransport({ readable: toA.readable, writable: toB.writable })),
+      WebClient.connect(webStreamTransport({ readable: toB.readable, writable: toA.writable })),
+    ]);
+    await a.send(new RawPacket(new Uint8Array([7])));
+    expect(await b.receive()).toEqual(new Uint8Array([7]));
+    await b.close();
+    await expect(b.receive()).rejects.toThrow(ClavisError);
+  });
+
+  test("should fail a handshake the transport cuts short", async () => {
+    const [clientSide, serverSide] = transportPair();
+    serverSide.onBytes(() => serverSide.close());
+    serverSide.onClose(() => undefined);
+    const error = await WebClient.connect(clientSide).catch((error) => error);
+    expect(error).toBeInstanceOf(ClavisError);
+    expect((error as ClavisError).message).toContain("closed during the handshake");
+  });
+});
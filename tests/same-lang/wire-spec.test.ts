@@ -0,0 +1,40 @@
+/**
+ * Wire specification tests - generated layout matches the documented format
+ */
+
+import { describe, test, expect } from "bun:test";
+import { generateWireSpec, formatWireSpec } from "../../src/wire-spec.js";
+
+describe("Wire specification", () => {
+  test("should describe the handshake messages", async () => {
+    const spec = await generateWireSpec();
+    const steps = spec.handshake.steps.map((s) => [s.sender, s.name, s.length, s.pskOnly]);
+
+    expect(steps).toEqual([
+      ["both", "nonce", 32, false],
+      ["initiator", "public_key", 32, false],
+      ["responder", "public_key", 32, false],
+      ["initiator", "mac", 32, true],
+      ["responder", "mac", 32, true],
+    ]);
+  });
+
+  test("should describe the frame layout", async () => {
+    const spec = await generateWireSpec();
+    const fields = spec.frame.fields.map((f) => [f.name, f.offset, f.length]);
+
+    expect(fields).toEqual([
+      ["length", 0, 4],
+      ["nonce", 4, 24],
+      ["ciphertext", 28, undefined],
+    ]);
+    expect(spec.frame.tagLength).toBe(16);
+    expect(spec.frame.overhead).toBe(44);
+  });
+
+  test("should render Markdown", async () => {
+    const text = formatWireSpec(await generateWireSpec());
+    expect(text).toContain("## Handshake");
+    expect(text).toContain("| 0 | 4 | length | u32 little-endian |");
+  });
+});
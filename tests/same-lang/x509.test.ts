@@ -0,0 +1,63 @@
+/**
+ * X.509 tests - DER chains validated against a root store during connection setup
+ *
+ * Fixtures in tests/fixtures/x509 were made with openssl: an Ed25519 root and
+ * intermediate, a P-256 leaf with URI and DNS subject alternative names, and
+ * an unrelated root. All are valid until 2126.
+ */
+
+import { describe, test, expect } from "bun:test";
+import { X509Certificate } from "crypto";
+import { readFileSync } from "fs";
+import { join } from "path";
+import { verifyX509Chain, x509Identity } from "../../src/x509.js";
+import { ClavisError } from "../../src/error.js";
+import { RawPacket } from "../../src/protocol.js";
+import { createEncryptedStreamPair } from "../helpers/test-utils.js";
+
+const fixtures = join(import.meta.dir, "../fixtures/x509");
+const der = (name: string) => new Uint8Array(new X509Certificate(readFileSync(join(fixtures, name))).raw);
+
+const root = der("root.pem");
+const otherRoot = der("other-root.pem");
+const intermediate = der("intermediate.pem");
+const leaf = der("leaf.pem");
+const privateKey = readFileSync(join(fixtures, "leaf.key"), "utf8");
+
+describe("X.509 chains", () => {
+  test("should accept a chain that ends at a trusted root", () => {
+    const certificate = verifyX509Chain([leaf, intermediate], [root]);
+    expect(x509Identity(certificate)).toBe("spiffe://example.org/device-42");
+    expect(() => verifyX509Chain([leaf, intermediate, root], [root])).not.toThrow();
+  });
+
+  test("should reject broken, untrusted and expired chains", () => {
+    expect(() => verifyX509Chain([leaf], [root])).toThrow(ClavisError);
+    expect(() => verifyX509Chain([leaf, intermediate], [otherRoot])).toThrow(ClavisError);
+    expect(() => verifyX509Chain([leaf, intermediate], [root], Date.UTC(2200, 0, 1))).toThrow(ClavisError);
+    expect(() => verifyX509Chain([leaf, leaf], [root])).toThrow(ClavisError);
+    expect(() => verifyX509Chain([new Uint8Array(10)], [root])).toThrow(ClavisError);
+  });
+});
+
+describe("X.509 identity exchange", () => {
+  test("should map the leaf's SAN to the peer identity", async () => {
+    const [client, server] = await createEncryptedStreamPair(
+      { identity: { chain: [leaf, intermediate], privateKey } },
+      { trustedRoots: [root] }
+    );
+    expect(server.peerIdentity?.identity).toBe("spiffe://example.org/device-42");
+    expect(server.peerIdentity?.x509?.subject).toContain("device-42");
+
+    await client.writePacket(new RawPacket(new Uint8Array([7])));
+    expect((await server.readPacket()) as unknown as Uint8Array).toEqual(new Uint8Array([7]));
+  });
+
+  test("should refuse chains from another root", async () => {
+    const error = await createEncryptedStreamPair(
+      { identity: { chain: [leaf, intermediate], privateKey } },
+      { trustedRoots: [otherRoot] }
+    ).catch((error) => error);
+    expect(error).toBeInstanceOf(ClavisError);
+  });
+});
@@ -0,0 +1,161 @@
+/**
+ * Soak test - connection churn with leak tracking
+ *
+ * Runs thousands of connect/handshake/split/exchange/disconnect cycles
+ * against an in-process EncryptedListener while sampling RSS, open file
+ * descriptors and active handles. Fails if any of them keeps growing
+ * across samples after warm-up.
+ *
+ *   bun tests/soak/soak.ts [cycles] [concurrency]
+ *
+ * Defaults: 5000 cycles, 16 concurrent. Samples are taken every
+ * SOAK_SAMPLE_EVERY cycles (default: 250).
+ */
+
+import { connect, type Socket } from "net";
+import { readdirSync } from "fs";
+import { EncryptedListener } from "../../src/listener.js";
+import { EncryptedStream } from "../../src/stream.js";
+import { RawPacket } from "../../src/protocol.js";
+
+interface Sample {
+  cycle: number;
+  rss: number;
+  fds: number | undefined;
+  handles: number;
+}
+
+const cycles = Number(process.argv[2] ?? 5000);
+const concurrency = Number(process.argv[3] ?? 16);
+const sampleEvery = Number(process.env.SOAK_SAMPLE_EVERY ?? 250);
+const warmupSamples = 2;
+
+/** Allowed growth before a monotonic trend counts as a leak */
+const TOLERANCE = {
+  rss: 32 * 1024 * 1024,
+  fds: 8,
+  handles: 8,
+};
+
+function openFds(): number | undefined {
+  try {
+    return readdirSync("/proc/self/fd").length;
+  } catch {
+    // Not available outside Linux
+    return undefined;
+  }
+}
+
+function activeHandles(): number {
+  return process.getActiveResourcesInfo().length;
+}
+
+function sample(cycle: number): Sample {
+  // Collect garbage first so RSS reflects live memory
+  Bun.gc(true);
+  return { cycle, rss: process.memoryUsage().rss, fds: openFds(), handles: activeHandles() };
+}
+
+/**
+ * True if the values never decrease and grow by more than `tolerance` overall
+ */
+function isMonotonicGrowth(values: number[], tolerance: number): boolean {
+  if (values.length < 2) return false;
+  for (let i = 1; i < values.length; i++) {
+    if (values[i]! < values[i - 1]!) return false;
+  }
+  return values[values.length - 1]! - values[0]! > tolerance;
+}
+
+async function serve(listener: EncryptedListener): Promise<void> {
+  for await (const { stream, socket } of listener) {
+    void (async () => {
+      try {
+        const { reader, writer } = stream.split();
+        const packet = (await reader.readPacket()) as unknown as Uint8Array;
+        await writer.writePacket(new RawPacket(packet));
+      } catch {
+        // Client went away mid-exchange
+      } finally {
+        socket.end();
+      }
+    })();
+  }
+}
+
+async function cycle(port: number, index: number): Promise<void> {
+  const socket = await new Promise<Socket>((resolve, reject) => {
+    const socket = connect(port, "127.0.0.1", () => resolve(socket));
+    socket.once("error", reject);
+  });
+
+  try {
+    const { reader, writer } = (await EncryptedStream.new(socket)).split();
+    const payload = new TextEncoder().encode(`soak-${index}`);
+    await writer.writePacket(new RawPacket(payload));
+    const echoed = (await reader.readPacket()) as unknown as Uint8Array;
+    if (echoed.length !== payload.length) {
+      throw new Error(`cycle ${index}: echo mismatch`);
+    }
+  } finally {
+    socket.destroy();
+  }
+}
+
+const listener = await EncryptedListener.bind(0, "127.0.0.1");
+const address = listener.address();
+if (!address || typeof address === "string") {
+  throw new Error("listener has no TCP address");
+}
+void serve(listener);
+
+const samples: Sample[] = [sample(0)];
+let next = 0;
+let failures = 0;
+
+async function worker(): Promise<void> {
+  while (next < cycles) {
+    const index = next++;
+    try {
+      await cycle(address!.port, index);
+    } catch (error) {
+      failures++;
+      console.error(error);
+    }
+    if ((index + 1) % sampleEvery === 0) {
+      const s = sample(index + 1);
+      samples.push(s);
+      console.log(
+        `cycle ${s.cycle}: rss=${(s.rss / 1024 / 1024).toFixed(1)}MiB fds=${s.fds ?? "n/a"} handles=${s.handles}`
+      );
+    }
+  }
+}
+
+const started = Date.now();
+await Promise.all(Array.from({ length: concurrency }, () => worker()));
+await listener.close();
+
+// Let the last sockets finish closing before the final sample
+await new Promise((resolve) => setTimeout(resolve, 200));
+samples.push(sample(cycles));
+
+const measured = samples.slice(warmupSamples);
+const leaks: string[] = [];
+if (isMonotonicGrowth(measured.map((s) => s.rss), TOLERANCE.rss)) {
+  leaks.push("rss");
+}
+const fds = measured.map((s) => s.fds);
+if (fds.every((n) => n !== undefined) && isMonotonicGrowth(fds as number[], TOLERANCE.fds)) {
+  leaks.push("file descriptors");
+}
+if (isMonotonicGrowth(measured.map((s) => s.handles), TOLERANCE.handles)) {
+  leaks.push("active handles");
+}
+
+console.log(`${cycles} cycles in ${((Date.now() - started) / 1000).toFixed(1)}s, ${failures} failed`);
+if (failures > 0 || leaks.length > 0) {
+  if (leaks.length > 0) console.log(`monotonic growth in: ${leaks.join(", ")}`);
+  process.exit(1);
+}
+console.log("no leaks detected");